@@ -0,0 +1,175 @@
+/*!
+ * Gzipped Turtle chunks for loading into Blazegraph, the triple store of the
+ * Wikidata Query Service, so a self-hosted query service can be loaded with a
+ * filtered subset instead of the whole dump:
+ *
+ * ```text
+ * wikidump-process filter --input latest-all.json.bz2 --blazegraph-chunks ./munged ...
+ * ./loadData.sh -n wdq -d "$(pwd)/munged"
+ * ```
+ *
+ * Chunks are named as the service's munger names them and its `loadData.sh`
+ * expects, `wikidump-000000001.ttl.gz` onwards, and each has a set number of
+ * entities (50000 by default, like the munger) as Turtle (see `crate::rdf`),
+ * after the prefixes, so they can be loaded one at a time.
+ */
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use flate2::write::GzEncoder;
+use log::debug;
+use crate::error::{ProcessError, Result};
+use crate::rdf;
+use crate::sink::Sink;
+
+/// Entities in each chunk by default, as the query service's munger writes them
+pub const DEFAULT_CHUNK_ENTITIES: usize = 50_000;
+
+const CHUNK_PREFIX: &str = "wikidump-";
+const CHUNK_EXTENSION: &str = ".ttl.gz";
+
+/// The path of chunk `number` (from 1) in `directory`, e.g. `wikidump-000000001.ttl.gz`
+pub fn chunk_path(directory: &Path, number: usize) -> PathBuf {
+    directory.join(format!("{}{:09}{}", CHUNK_PREFIX, number, CHUNK_EXTENSION))
+}
+
+// the chunks already in `directory`
+fn existing_chunks(directory: &Path) -> Result<Vec<PathBuf>> {
+    let entries = match fs::read_dir(directory) {
+        Ok(entries) => entries,
+        Err(_) => return Ok(Vec::new()),
+    };
+    let mut chunks = Vec::new();
+    for entry in entries {
+        let path = entry.map_err(|source| ProcessError::CreateOutput { path: directory.to_path_buf(), source })?.path();
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default();
+        if name.starts_with(CHUNK_PREFIX) && name.ends_with(CHUNK_EXTENSION) {
+            chunks.push(path);
+        }
+    }
+    chunks.sort();
+    Ok(chunks)
+}
+
+type Chunk = GzEncoder<BufWriter<File>>;
+
+/// Writes each output, which has to be a whole entity, as Turtle into numbered chunks, see the module documentation
+pub struct BlazegraphChunkSink {
+    directory: PathBuf,
+    chunk_entities: usize,
+    chunk: Option<Chunk>,
+    chunks: usize,
+    // entities in the chunk being written
+    entities: usize,
+    turtle: String,
+}
+
+impl BlazegraphChunkSink {
+    /// Writes chunks into `directory`, creating it if need be. Chunks already there are removed first when
+    /// `overwrite` is set, as `loadData.sh` would load them along with the new ones, otherwise this fails.
+    pub fn create(directory: &Path, chunk_entities: usize, overwrite: bool) -> Result<Self> {
+        let existing = existing_chunks(directory)?;
+        if let Some(first) = existing.first() {
+            if !overwrite {
+                return Err(ProcessError::OutputExists(first.clone()));
+            }
+            for chunk in &existing {
+                fs::remove_file(chunk).map_err(|source| ProcessError::CreateOutput { path: chunk.clone(), source })?;
+            }
+        }
+        fs::create_dir_all(directory).map_err(|source| ProcessError::CreateOutput { path: directory.to_path_buf(), source })?;
+        Ok(BlazegraphChunkSink { directory: directory.to_path_buf(), chunk_entities: chunk_entities.max(1), chunk: None, chunks: 0, entities: 0, turtle: String::new() })
+    }
+
+    /// Number of chunks started so far
+    pub fn chunks(&self) -> usize {
+        self.chunks
+    }
+
+    // the chunk being written, starting the next one if there's none
+    fn chunk(&mut self) -> Result<&mut Chunk> {
+        if self.chunk.is_none() {
+            self.chunks += 1;
+            let path = chunk_path(&self.directory, self.chunks);
+            let file = File::create(&path).map_err(|source| ProcessError::CreateOutput { path: path.clone(), source })?;
+            let mut chunk = GzEncoder::new(BufWriter::new(file), flate2::Compression::default());
+            chunk.write_all(rdf::PREFIXES.as_bytes()).map_err(ProcessError::Write)?;
+            debug!("Writing chunk {:?}", path);
+            self.chunk = Some(chunk);
+            self.entities = 0;
+        }
+        Ok(self.chunk.as_mut().expect("a chunk was just started"))
+    }
+
+    fn finish_chunk(&mut self) -> Result<()> {
+        if let Some(chunk) = self.chunk.take() {
+            chunk.finish().and_then(|mut file| file.flush()).map_err(ProcessError::Write)?;
+        }
+        Ok(())
+    }
+}
+
+impl Sink for BlazegraphChunkSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        self.turtle.clear();
+        rdf::write_output(&mut self.turtle, output)?;
+        let turtle = std::mem::take(&mut self.turtle);
+        let written = self.chunk()?.write_all(turtle.as_bytes()).map_err(ProcessError::Write);
+        self.turtle = turtle;
+        written?;
+        self.entities += 1;
+        if self.entities >= self.chunk_entities {
+            self.finish_chunk()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.chunk {
+            Some(chunk) => chunk.flush().map_err(ProcessError::Write),
+            None => Ok(()),
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.finish_chunk()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use crate::Pipeline;
+
+    #[test]
+    fn test_chunks() {
+        let directory = tempfile::tempdir().unwrap();
+        let sink = BlazegraphChunkSink::create(directory.path(), 3, false).unwrap();
+        let stats = Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .filter(".")
+            .entity_sink(sink)
+            .build().unwrap()
+            .run().unwrap();
+        let chunks = existing_chunks(directory.path()).unwrap();
+        assert_eq!(chunks.len(), (stats.entities_written as usize).div_ceil(3));
+        assert_eq!(chunks[0], chunk_path(directory.path(), 1));
+
+        let mut turtle = String::new();
+        GzDecoder::new(File::open(&chunks[0]).unwrap()).read_to_string(&mut turtle).unwrap();
+        assert!(turtle.starts_with(rdf::PREFIXES));
+        assert!(turtle.contains(" a wikibase:"));
+
+        assert!(matches!(BlazegraphChunkSink::create(directory.path(), 3, false), Err(ProcessError::OutputExists(_))));
+        BlazegraphChunkSink::create(directory.path(), 3, true).unwrap();
+        assert!(existing_chunks(directory.path()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_chunk_path() {
+        assert_eq!(chunk_path(Path::new("munged"), 12), Path::new("munged/wikidump-000000012.ttl.gz"));
+    }
+}
@@ -0,0 +1,270 @@
+/*!
+ * Bloom filters of entity ids, so other services can check whether an id is
+ * in a filtered subset without loading the full list of ids, at the cost of
+ * a chosen rate of false positives (never false negatives).
+ *
+ * The file format is simple enough to read from any language, all integers
+ * being little-endian:
+ *
+ * - the magic bytes `WDBLOOM1`
+ * - the number of bits `m`, as a u64
+ * - the number of hash functions `k`, as a u32
+ * - the number of ids inserted, as a u64
+ * - the bits, as `ceil(m / 64)` u64 words, bit `i` being `1 << (i % 64)` of word `i / 64`
+ *
+ * An id is hashed with 64-bit FNV-1a over its UTF-8 bytes, then mixed with
+ * splitmix64 into `h1`, and `h1` mixed again (with its lowest bit set) into
+ * `h2`. Its bits are `(h1 + i * h2) mod m` for `i` from 0 to `k - 1`, with
+ * wrapping u64 arithmetic.
+ */
+
+use std::fs::File;
+use std::io::{self, BufWriter, Read, Write};
+use std::borrow::Cow;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use crate::error::{ProcessError, Result};
+use crate::filter::EntityFilter;
+use crate::shard::fnv1a;
+use crate::splitter;
+
+const MAGIC: &[u8; 8] = b"WDBLOOM1";
+
+/// The false positive rate filters are sized for by default
+pub const DEFAULT_FALSE_POSITIVE_RATE: f64 = 0.01;
+
+fn splitmix64(value: u64) -> u64 {
+    let mut z = value.wrapping_add(0x9e3779b97f4a7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+}
+
+/// The hash of an id which its bits are worked out from, see the module documentation
+pub fn id_hash(id: &str) -> u64 {
+    fnv1a(id.as_bytes())
+}
+
+/// Parses a false positive rate, which has to be between 0 and 1
+pub fn parse_false_positive_rate(value: &str) -> std::result::Result<f64, String> {
+    match value.parse::<f64>() {
+        Ok(rate) if rate > 0.0 && rate < 1.0 => Ok(rate),
+        _ => Err(format!("Invalid false positive rate '{}', expected a number between 0 and 1, e.g. 0.01", value)),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BloomFilter {
+    words: Vec<u64>,
+    bits: u64,
+    hashes: u32,
+    len: u64,
+}
+
+impl BloomFilter {
+    /// An empty filter sized for `capacity` ids with about `false_positive_rate` false positives once full
+    pub fn new(capacity: u64, false_positive_rate: f64) -> Self {
+        let capacity = capacity.max(1) as f64;
+        let ln2 = std::f64::consts::LN_2;
+        let bits = ((-capacity * false_positive_rate.ln() / (ln2 * ln2)).ceil() as u64).max(64);
+        let hashes = ((bits as f64 / capacity * ln2).round() as u32).clamp(1, 32);
+        BloomFilter { words: vec![0; bits.div_ceil(64) as usize], bits, hashes, len: 0 }
+    }
+
+    // the bits of an id with the hash `hash`
+    fn positions(&self, hash: u64) -> impl Iterator<Item = u64> {
+        let h1 = splitmix64(hash);
+        let h2 = splitmix64(h1) | 1;
+        let bits = self.bits;
+        (0..self.hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % bits)
+    }
+
+    /// Adds the id with the hash `hash`, see `id_hash`
+    pub fn insert_hash(&mut self, hash: u64) {
+        for position in self.positions(hash) {
+            self.words[(position / 64) as usize] |= 1 << (position % 64);
+        }
+        self.len += 1;
+    }
+
+    pub fn insert(&mut self, id: &str) {
+        self.insert_hash(id_hash(id));
+    }
+
+    /// Whether `id` may have been inserted. If not, it definitely wasn't
+    pub fn contains(&self, id: &str) -> bool {
+        self.positions(id_hash(id)).all(|position| self.words[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+
+    /// How many ids were inserted
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Size of the filter in bits
+    pub fn bits(&self) -> u64 {
+        self.bits
+    }
+
+    pub fn write(&self, mut output: impl Write) -> io::Result<()> {
+        output.write_all(MAGIC)?;
+        output.write_all(&self.bits.to_le_bytes())?;
+        output.write_all(&self.hashes.to_le_bytes())?;
+        output.write_all(&self.len.to_le_bytes())?;
+        for word in &self.words {
+            output.write_all(&word.to_le_bytes())?;
+        }
+        output.flush()
+    }
+
+    pub fn read(mut input: impl Read) -> io::Result<Self> {
+        let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a bloom filter written by wikidump-process"));
+        }
+        let mut u64_bytes = [0; 8];
+        let mut u32_bytes = [0; 4];
+        input.read_exact(&mut u64_bytes)?;
+        let bits = u64::from_le_bytes(u64_bytes);
+        input.read_exact(&mut u32_bytes)?;
+        let hashes = u32::from_le_bytes(u32_bytes);
+        input.read_exact(&mut u64_bytes)?;
+        let len = u64::from_le_bytes(u64_bytes);
+        if bits == 0 || hashes == 0 {
+            return Err(invalid("bloom filter without any bits or hash functions"));
+        }
+        let mut words = Vec::with_capacity(bits.div_ceil(64) as usize);
+        for _ in 0..bits.div_ceil(64) {
+            input.read_exact(&mut u64_bytes)?;
+            words.push(u64::from_le_bytes(u64_bytes));
+        }
+        Ok(BloomFilter { words, bits, hashes, len })
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(ProcessError::Read)?;
+        BloomFilter::read(io::BufReader::new(file)).map_err(ProcessError::Read)
+    }
+}
+
+/// The hashes of the ids gathered by every `BloomRecorder` of a run, for a bloom filter sized for exactly those ids
+/// to be written once it's over. Only the 8 byte hash of each id is held until then.
+#[derive(Debug, Default)]
+pub struct IdHashes {
+    hashes: Mutex<Vec<u64>>,
+}
+
+impl IdHashes {
+    /// Writes a bloom filter of the ids gathered so far to `path`, returning how many different ones there were
+    pub fn write(&self, path: &Path, false_positive_rate: f64) -> Result<u64> {
+        let mut hashes = std::mem::take(&mut *self.hashes.lock().expect("Id hashes poisoned"));
+        hashes.sort_unstable();
+        hashes.dedup();
+        let mut filter = BloomFilter::new(hashes.len() as u64, false_positive_rate);
+        for hash in hashes {
+            filter.insert_hash(hash);
+        }
+        let create_error = |source| ProcessError::CreateOutput { path: path.to_path_buf(), source };
+        let file = File::create(path).map_err(create_error)?;
+        filter.write(BufWriter::new(file)).map_err(create_error)?;
+        Ok(filter.len())
+    }
+}
+
+/// Records the id of each raw entity another filter outputs something for, whatever the output is, adding them to
+/// the shared `IdHashes` once dropped, i.e. once the run is over
+pub struct BloomRecorder<F: EntityFilter> {
+    filter: F,
+    hashes: Vec<u64>,
+    total: Arc<IdHashes>,
+}
+
+impl<F: EntityFilter> BloomRecorder<F> {
+    pub fn new(filter: F, total: Arc<IdHashes>) -> Self {
+        BloomRecorder { filter, hashes: Vec::new(), total }
+    }
+}
+
+impl<F: EntityFilter> EntityFilter for BloomRecorder<F> {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let output = self.filter.apply(raw)?;
+        if let (Some(_), Some(id)) = (&output, splitter::entity_id(raw)) {
+            self.hashes.push(id_hash(id));
+        }
+        Ok(output)
+    }
+
+    fn failures(&self) -> usize {
+        self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.filter.take_error()
+    }
+}
+
+impl<F: EntityFilter> Drop for BloomRecorder<F> {
+    fn drop(&mut self) {
+        if let Ok(mut total) = self.total.hashes.lock() {
+            total.append(&mut self.hashes);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter;
+    use crate::Pipeline;
+
+    #[test]
+    fn test_bloom_filter() {
+        let mut filter = BloomFilter::new(10_000, 0.01);
+        for n in 0..10_000 {
+            filter.insert(&format!("Q{}", n * 2));
+        }
+        assert!((0..10_000).all(|n| filter.contains(&format!("Q{}", n * 2))));
+        let false_positives = (0..10_000).filter(|n| filter.contains(&format!("Q{}", n * 2 + 1))).count();
+        assert!(false_positives < 200, "{} false positives", false_positives);
+
+        let mut bytes = Vec::new();
+        filter.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len() as u64, 28 + filter.bits().div_ceil(64) * 8);
+        assert_eq!(BloomFilter::read(bytes.as_slice()).unwrap(), filter);
+        assert!(BloomFilter::read(&b"NOTBLOOM"[..]).is_err());
+        assert!(parse_false_positive_rate("1.5").is_err());
+    }
+
+    #[test]
+    fn test_bloom_recorder() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("ids.bloom");
+        let hashes = Arc::new(IdHashes::default());
+        // outputs without an id of their own still have their entity's recorded
+        for (jq_filter, outputs) in [(".id", 8), (r#"select(.id != "Q2") | .labels"#, 7)] {
+            let stats = Pipeline::builder()
+                .source("./tests/test-data.json.bz2")
+                .entity_filter({
+                    let hashes = Arc::clone(&hashes);
+                    let jq_filter = filter::jq_filter_factory(jq_filter, false, false);
+                    move || Ok(BloomRecorder::new(jq_filter()?, Arc::clone(&hashes)))
+                })
+                .sink(io::sink())
+                .build().unwrap()
+                .run().unwrap();
+            assert_eq!(stats.entities_written, outputs);
+        }
+        assert_eq!(hashes.write(&path, 0.001).unwrap(), 8);
+
+        let filter = BloomFilter::load(&path).unwrap();
+        assert_eq!(filter.len(), 8);
+        assert!(filter.contains("Q1") && filter.contains("Q2") && filter.contains("P1"));
+        assert!(!filter.contains("Q7"));
+    }
+}
@@ -0,0 +1,60 @@
+/*!
+ * Stopping a run early from another thread, e.g. a signal handler or the
+ * application embedding the library.
+ */
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::thread;
+use std::time::Duration;
+
+/// A flag shared between a run and whoever may want to stop it. Clones refer to the same flag.
+#[derive(Debug, Clone, Default)]
+pub struct CancellationToken(Arc<AtomicBool>);
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Asks the run to stop. Output written so far is flushed and partial stats are returned.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    /// Cancels the token once `timeout` has passed, e.g. to fit a run within a cluster's walltime limit
+    pub fn cancel_after(&self, timeout: Duration) {
+        let token = self.clone();
+        thread::spawn(move || {
+            thread::sleep(timeout);
+            token.cancel();
+        });
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cancellation_token() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        assert!(!clone.is_cancelled());
+        token.cancel();
+        assert!(clone.is_cancelled());
+    }
+
+    #[test]
+    fn test_cancel_after() {
+        let token = CancellationToken::new();
+        token.cancel_after(Duration::from_millis(10));
+        assert!(!token.is_cancelled());
+        thread::sleep(Duration::from_millis(200));
+        assert!(token.is_cancelled());
+    }
+}
@@ -0,0 +1,116 @@
+/*!
+ * Canonical JSON, so that outputs of different runs or dump versions diff
+ * cleanly line by line: the same entity is always written the same way,
+ * whatever the order jq or the dump left its keys and statements in.
+ *
+ * Object keys are sorted, at every level, except for `id` which comes first,
+ * where `splitter::entity_id` looks for it. The statements of each
+ * property are sorted by their id (statements without one coming last, in the
+ * order of their JSON), as are those of the forms and senses of lexemes.
+ * Other arrays, e.g. aliases or `qualifiers-order`, are in a meaningful order
+ * and are left as they are. Outputs which aren't JSON are left as they are.
+ */
+
+use serde_json::Value;
+
+// the statements of a property in canonical order, see the module documentation
+fn sort_statements(statements: &mut [Value]) {
+    statements.sort_by_cached_key(|statement| match statement.get("id") {
+        Some(Value::String(id)) => (false, id.clone()),
+        _ => (true, statement.to_string()),
+    });
+}
+
+/// Sorts the statements of `entity`, and of its forms and senses, in place. Keys are sorted as `write_canonical`
+/// writes them.
+pub fn canonicalize(entity: &mut Value) {
+    let entity = match entity {
+        Value::Object(entity) => entity,
+        _ => return,
+    };
+    if let Some(Value::Object(claims)) = entity.get_mut("claims") {
+        for statements in claims.values_mut() {
+            if let Value::Array(statements) = statements {
+                sort_statements(statements);
+            }
+        }
+    }
+    for key in ["forms", "senses"] {
+        if let Some(Value::Array(parts)) = entity.get_mut(key) {
+            parts.iter_mut().for_each(canonicalize);
+        }
+    }
+}
+
+/// Appends `value` to `json` with the keys of its objects in canonical order, `id` first and then the others sorted,
+/// whatever order the map of `serde_json` keeps them in
+pub fn write_canonical(json: &mut String, value: &Value) {
+    match value {
+        Value::Object(object) => {
+            let mut keys: Vec<&String> = object.keys().collect();
+            keys.sort_by_key(|key| (key.as_str() != "id", key.as_str()));
+            json.push('{');
+            for (i, key) in keys.into_iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                json.push_str(&Value::from(key.as_str()).to_string());
+                json.push(':');
+                write_canonical(json, &object[key]);
+            }
+            json.push('}');
+        }
+        Value::Array(values) => {
+            json.push('[');
+            for (i, value) in values.iter().enumerate() {
+                if i > 0 {
+                    json.push(',');
+                }
+                write_canonical(json, value);
+            }
+            json.push(']');
+        }
+        _ => json.push_str(&value.to_string()),
+    }
+}
+
+/// `output` in canonical form, see the module documentation
+pub fn canonicalize_output(output: String) -> String {
+    match serde_json::from_str::<Value>(&output) {
+        Ok(mut value) => {
+            canonicalize(&mut value);
+            let mut json = String::with_capacity(output.len());
+            write_canonical(&mut json, &value);
+            json
+        }
+        Err(_) => output,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::splitter;
+
+    #[test]
+    fn test_canonicalize_output() {
+        let entity = r#"{"type":"item","id":"Q42","claims":{"P31":[
+            {"rank":"normal","id":"Q42$b","mainsnak":{"property":"P31","snaktype":"value"}},
+            {"mainsnak":{"snaktype":"value","property":"P31"}},
+            {"id":"Q42$a","mainsnak":{"property":"P31","snaktype":"value"},"rank":"normal"}
+        ]},"aliases":{"en":[{"value":"b","language":"en"},{"language":"en","value":"a"}]}}"#;
+        let canonical = concat!(
+            r#"{"id":"Q42","aliases":{"en":[{"language":"en","value":"b"},{"language":"en","value":"a"}]},"#,
+            r#""claims":{"P31":[{"id":"Q42$a","mainsnak":{"property":"P31","snaktype":"value"},"rank":"normal"},"#,
+            r#"{"id":"Q42$b","mainsnak":{"property":"P31","snaktype":"value"},"rank":"normal"},"#,
+            r#"{"mainsnak":{"property":"P31","snaktype":"value"}}]},"type":"item"}"#,
+        );
+        assert_eq!(canonicalize_output(entity.to_string()), canonical);
+        assert_eq!(canonicalize_output(canonical.to_string()), canonical);
+        assert_eq!(splitter::entity_id(&canonicalize_output(entity.to_string())), Some("Q42"));
+
+        let lexeme = r#"{"id":"L1","forms":[{"id":"L1-F1","claims":{"P1":[{"id":"L1-F1$2"},{"id":"L1-F1$1"}]}}]}"#;
+        assert_eq!(canonicalize_output(lexeme.to_string()), r#"{"id":"L1","forms":[{"id":"L1-F1","claims":{"P1":[{"id":"L1-F1$1"},{"id":"L1-F1$2"}]}}]}"#);
+        assert_eq!(canonicalize_output(String::from("not json")), "not json");
+    }
+}
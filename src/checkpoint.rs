@@ -0,0 +1,107 @@
+/*!
+ * Checkpoints of long runs, so an interrupted run can carry on from the last
+ * checkpoint rather than from the start of the dump.
+ *
+ * A checkpoint records the bzip2 stream to restart decoding from, where the
+ * next unprocessed entity starts, and how much output had been written, so
+ * anything written after the checkpoint can be truncated before resuming.
+ */
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use crate::decoder::StreamStart;
+use crate::error::{ProcessError, Result};
+use crate::sink::Sink;
+
+/// How often checkpoints are saved during a run
+pub const CHECKPOINT_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Checkpoint {
+    /// The bzip2 stream containing `offset`
+    pub stream: StreamStart,
+    /// Decompressed offset of the first entity not yet processed
+    pub offset: u64,
+    pub entities_read: usize,
+    pub entities_written: usize,
+    pub entities_failed: usize,
+    /// Output written up to this checkpoint, anything past it is from entities after `offset`
+    pub bytes_out: u64,
+    /// Whether the run got through the whole dump
+    pub complete: bool,
+}
+
+impl Checkpoint {
+    pub fn load(path: &Path) -> Result<Checkpoint> {
+        let contents = fs::read_to_string(path).map_err(|source| ProcessError::Checkpoint { path: path.to_path_buf(), source })?;
+        serde_json::from_str(&contents).map_err(|error| ProcessError::InvalidCheckpoint { path: path.to_path_buf(), message: error.to_string() })
+    }
+
+    /// Saves the checkpoint, replacing any previous one at `path` in one go so a crash can't leave half a checkpoint behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let checkpoint_error = |source| ProcessError::Checkpoint { path: path.to_path_buf(), source };
+        let mut partial = path.as_os_str().to_owned();
+        partial.push(".partial");
+        let contents = serde_json::to_string_pretty(self).expect("Checkpoints can always be serialized");
+        fs::write(&partial, contents).map_err(checkpoint_error)?;
+        fs::rename(&partial, path).map_err(checkpoint_error)?;
+        debug!("Saved checkpoint {:?}", self);
+        Ok(())
+    }
+}
+
+// keeps track of the latest point a run could be resumed from, saving it every so often
+pub(crate) struct Checkpointer {
+    path: PathBuf,
+    latest: Checkpoint,
+    saved_at: Instant,
+}
+
+impl Checkpointer {
+    // `start` is the point the run started from
+    pub(crate) fn new(path: PathBuf, start: Checkpoint) -> Self {
+        Checkpointer { path, latest: start, saved_at: Instant::now() }
+    }
+
+    // records the point reached once everything before it has been handed to `sink`, saving it if it's been a while
+    pub(crate) fn update(&mut self, checkpoint: Checkpoint, sink: &mut dyn Sink) -> Result<()> {
+        self.latest = checkpoint;
+        if self.saved_at.elapsed() >= CHECKPOINT_INTERVAL {
+            self.save(sink)?;
+        }
+        Ok(())
+    }
+
+    // saves the latest point reached, making sure the output it describes has been written out first
+    pub(crate) fn save(&mut self, sink: &mut dyn Sink) -> Result<()> {
+        sink.flush()?;
+        self.latest.save(&self.path)?;
+        self.saved_at = Instant::now();
+        Ok(())
+    }
+
+    pub(crate) fn complete(&mut self, sink: &mut dyn Sink) -> Result<()> {
+        self.latest.complete = true;
+        self.save(sink)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_checkpoint() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("run.checkpoint");
+        let checkpoint = Checkpoint { offset: 1234, entities_read: 5, bytes_out: 99, ..Checkpoint::default() };
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path).unwrap(), checkpoint);
+
+        fs::write(&path, "{").unwrap();
+        assert!(matches!(Checkpoint::load(&path), Err(ProcessError::InvalidCheckpoint { .. })));
+    }
+}
@@ -0,0 +1,342 @@
+/*!
+ * The class hierarchy of a dump: the "subclass of" (P279) statements of every
+ * entity, from which the transitive subclasses of a set of classes are found,
+ * e.g. every kind of human settlement rather than only the items stating
+ * they're one directly. Only the P279 statements of each entity are parsed,
+ * and entities without any are skipped unparsed.
+ *
+ * Hierarchies are written as `subclass<TAB>class` TSV, sorted by the subclass
+ * id, with one row per (non-deprecated) statement.
+ *
+ * `ClassFilter` then keeps the entities which are an "instance of" (P31) any
+ * of a set of classes found this way, which per-entity jq filters can't tell
+ * on their own.
+ */
+
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use crate::edges::Statement;
+use crate::error::{ProcessError, Result};
+use crate::filter::{self, EntityFilter};
+use crate::index::parse_id;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+/// The property "subclass of"
+pub const SUBCLASS_OF: &str = "P279";
+
+/// The property "instance of"
+pub const INSTANCE_OF: &str = "P31";
+
+#[derive(Deserialize)]
+struct Outline<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(default, borrow)]
+    claims: Claims<'a>,
+}
+
+// only the statements classes are told by, everything else is skipped over
+#[derive(Deserialize, Default)]
+struct Claims<'a> {
+    #[serde(default, rename = "P279", borrow)]
+    subclass_of: Vec<Statement<'a>>,
+    #[serde(default, rename = "P31", borrow)]
+    instance_of: Vec<Statement<'a>>,
+}
+
+/// The classes each class is a direct subclass of
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ClassHierarchy {
+    parents: HashMap<String, Vec<String>>,
+}
+
+impl ClassHierarchy {
+    /// Records that `subclass` is a subclass of `class`
+    pub fn insert(&mut self, subclass: impl Into<String>, class: impl Into<String>) {
+        self.parents.entry(subclass.into()).or_default().push(class.into());
+    }
+
+    /// Number of classes which are a subclass of another
+    pub fn len(&self) -> usize {
+        self.parents.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.parents.is_empty()
+    }
+
+    /// The classes `class` is a direct subclass of
+    pub fn parents(&self, class: &str) -> &[String] {
+        self.parents.get(class).map(Vec::as_slice).unwrap_or_default()
+    }
+
+    /// `classes` and all of their subclasses, however indirect. Cycles, which the hierarchy has a few of, are
+    /// followed round once.
+    pub fn subclasses_of(&self, classes: &[String]) -> HashSet<String> {
+        let mut children: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (subclass, parents) in &self.parents {
+            for parent in parents {
+                children.entry(parent.as_str()).or_default().push(subclass.as_str());
+            }
+        }
+        let mut found = classes.iter().map(String::as_str).collect::<HashSet<_>>();
+        let mut pending = found.iter().copied().collect::<Vec<_>>();
+        while let Some(class) = pending.pop() {
+            for child in children.get(class).into_iter().flatten() {
+                if found.insert(child) {
+                    pending.push(child);
+                }
+            }
+        }
+        found.into_iter().map(str::to_string).collect()
+    }
+
+    /// Reads a hierarchy written by `write_tsv`
+    pub fn load(path: &Path) -> Result<Self> {
+        let open_error = |source| ProcessError::OpenInput { path: path.to_path_buf(), source };
+        let mut hierarchy = ClassHierarchy::default();
+        for (i, line) in BufReader::new(File::open(path).map_err(open_error)?).lines().enumerate() {
+            let line = line.map_err(ProcessError::Read)?;
+            if line.is_empty() {
+                continue;
+            }
+            match line.split_once('\t') {
+                Some((subclass, class)) => hierarchy.insert(subclass, class),
+                None => {
+                    let message = format!("line {} of {:?} is not a subclass<TAB>class row", i + 1, path);
+                    return Err(ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, message)));
+                }
+            }
+        }
+        Ok(hierarchy)
+    }
+
+    /// Writes every subclass and the classes it's a subclass of, sorted by the subclass id
+    pub fn write_tsv(&self, output: &mut impl Write) -> io::Result<()> {
+        let mut ids = self.parents.keys().collect::<Vec<_>>();
+        ids.sort_unstable_by_key(|id| (parse_id(id), *id));
+        for id in ids {
+            for parent in &self.parents[id] {
+                writeln!(output, "{}\t{}", id, parent)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Writes `ids` one per line, sorted
+pub fn write_ids(ids: &HashSet<String>, output: &mut impl Write) -> io::Result<()> {
+    let mut ids = ids.iter().collect::<Vec<_>>();
+    ids.sort_unstable_by_key(|id| (parse_id(id), *id));
+    for id in ids {
+        writeln!(output, "{}", id)?;
+    }
+    Ok(())
+}
+
+// collects the hierarchy found on one thread, adding it to the total once dropped
+struct HierarchyFinder {
+    hierarchy: ClassHierarchy,
+    total: Arc<Mutex<ClassHierarchy>>,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl EntityFilter for HierarchyFinder {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        // most entities have no P279 statements, and can be told apart without parsing
+        if !raw.contains("\"P279\"") {
+            return Ok(None);
+        }
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        for statement in outline.claims.subclass_of.iter().filter(|statement| !statement.is_deprecated()) {
+            if let Some(class) = statement.mainsnak.item() {
+                self.hierarchy.insert(outline.id.as_ref(), class);
+            }
+        }
+        Ok(None)
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+impl Drop for HierarchyFinder {
+    fn drop(&mut self) {
+        if let Ok(mut total) = self.total.lock() {
+            for (subclass, parents) in std::mem::take(&mut self.hierarchy.parents) {
+                total.parents.entry(subclass).or_default().extend(parents);
+            }
+        }
+    }
+}
+
+/// Applies another filter only to the entities which are an instance of (P31, deprecated statements aside) one of
+/// a set of classes, dropping all others. The classes are usually those found by `ClassHierarchy::subclasses_of`.
+pub struct ClassFilter {
+    classes: Arc<HashSet<String>>,
+    filter: Box<dyn EntityFilter>,
+    continue_on_error: bool,
+    failures: usize,
+    error: Option<String>,
+}
+
+impl ClassFilter {
+    pub fn new(classes: Arc<HashSet<String>>, filter: Box<dyn EntityFilter>, continue_on_error: bool) -> Self {
+        ClassFilter { classes, filter, continue_on_error, failures: 0, error: None }
+    }
+
+    // whether `raw` is an instance of one of the classes
+    fn is_instance(&mut self, raw: &str) -> Result<bool> {
+        // entities without P31 statements can be told apart without parsing
+        if !raw.contains("\"P31\"") {
+            return Ok(false);
+        }
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                self.error = Some(error.to_string());
+                return Ok(false);
+            }
+        };
+        let is_instance = outline.claims.instance_of.iter()
+            .filter(|statement| !statement.is_deprecated())
+            .filter_map(|statement| statement.mainsnak.item())
+            .any(|class| self.classes.contains(class.as_ref()));
+        Ok(is_instance)
+    }
+}
+
+impl EntityFilter for ClassFilter {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        match self.is_instance(raw)? {
+            true => self.filter.apply(raw),
+            false => Ok(None),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.failures + self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take().or_else(|| self.filter.take_error())
+    }
+}
+
+/// Finds the class hierarchy of the entities of `source` on `options.threads` threads
+pub fn find_hierarchy(source: impl Source, options: ProcessOptions) -> Result<(ClassHierarchy, ProcessStats)> {
+    let total = Arc::new(Mutex::new(ClassHierarchy::default()));
+    let continue_on_error = options.continue_on_error;
+    let finders = Arc::clone(&total);
+    let stats = Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(HierarchyFinder { hierarchy: ClassHierarchy::default(), total: Arc::clone(&finders), continue_on_error, failures: 0 }))
+        .sink(io::sink())
+        .options(options)
+        .build()?
+        .run()?;
+    let hierarchy = std::mem::take(&mut *total.lock().expect("Class hierarchy poisoned"));
+    Ok((hierarchy, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::splitter;
+    use crate::source::FileSource;
+
+    fn ids(ids: &[&str]) -> Vec<String> {
+        ids.iter().map(|id| id.to_string()).collect()
+    }
+
+    #[test]
+    fn test_find_hierarchy() {
+        let (hierarchy, stats) = find_hierarchy(FileSource::new("./tests/test-data.json.bz2"), ProcessOptions::default()).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        assert_eq!(stats.entities_written, 0);
+        // none of the test entities have statements
+        assert!(hierarchy.is_empty());
+
+        let mut finder = HierarchyFinder { hierarchy: ClassHierarchy::default(), total: Arc::default(), continue_on_error: false, failures: 0 };
+        let entity = r#"{"id":"Q515","type":"item","claims":{"P279":[
+            {"mainsnak":{"snaktype":"value","property":"P279","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":486972}}},"rank":"normal"},
+            {"mainsnak":{"snaktype":"value","property":"P279","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q5"}}},"rank":"deprecated"}],
+            "P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q1"}}},"rank":"normal"}]}}"#;
+        assert!(finder.apply(entity).unwrap().is_none());
+        assert_eq!(finder.hierarchy.parents("Q515"), ids(&["Q486972"]));
+        assert!(finder.apply(r#"{"id":"Q1","claims":{"P279":"#).is_err());
+    }
+
+    #[test]
+    fn test_class_filter() {
+        struct Ids;
+        impl EntityFilter for Ids {
+            fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+                Ok(splitter::entity_id(raw).map(Cow::Borrowed))
+            }
+        }
+        let instance = |id: &str, class: &str, rank: &str| format!(
+            r#"{{"id":"{}","type":"item","claims":{{"P31":[{{"mainsnak":{{"snaktype":"value","property":"P31","datavalue":{{"type":"wikibase-entityid","value":{{"entity-type":"item","id":"{}"}}}}}},"rank":"{}"}}]}}}}"#,
+            id, class, rank,
+        );
+        let classes = Arc::new(HashSet::from([String::from("Q515"), String::from("Q486972")]));
+        let mut filter = ClassFilter::new(classes, Box::new(Ids), false);
+        assert_eq!(filter.apply(&instance("Q90", "Q515", "normal")).unwrap().as_deref(), Some("Q90"));
+        assert_eq!(filter.apply(&instance("Q90", "Q515", "deprecated")).unwrap(), None);
+        assert_eq!(filter.apply(&instance("Q42", "Q5", "normal")).unwrap(), None);
+        assert_eq!(filter.apply(r#"{"id":"Q1","type":"item","claims":{}}"#).unwrap(), None);
+        assert!(filter.apply(r#"{"id":"Q1","claims":{"P31":"#).is_err());
+
+        let mut filter = ClassFilter::new(Arc::default(), Box::new(Ids), true);
+        assert_eq!(filter.apply(r#"{"id":"Q1","claims":{"P31":"#).unwrap(), None);
+        assert_eq!(filter.failures(), 1);
+    }
+
+    #[test]
+    fn test_subclasses_of() {
+        let mut hierarchy = ClassHierarchy::default();
+        hierarchy.insert("Q515", "Q486972");
+        hierarchy.insert("Q1549591", "Q515");
+        hierarchy.insert("Q532", "Q486972");
+        hierarchy.insert("Q3957", "Q486972");
+        hierarchy.insert("Q3957", "Q5");
+        // a cycle
+        hierarchy.insert("Q1", "Q2");
+        hierarchy.insert("Q2", "Q1");
+
+        let mut settlements = hierarchy.subclasses_of(&ids(&["Q486972"])).into_iter().collect::<Vec<_>>();
+        settlements.sort();
+        assert_eq!(settlements, ids(&["Q1549591", "Q3957", "Q486972", "Q515", "Q532"]));
+        assert_eq!(hierarchy.subclasses_of(&ids(&["Q1"])).len(), 2);
+        assert_eq!(hierarchy.subclasses_of(&ids(&["Q42"])).len(), 1);
+
+        let mut output = Vec::new();
+        write_ids(&hierarchy.subclasses_of(&ids(&["Q515"])), &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "Q515\nQ1549591\n");
+
+        let mut tsv = Vec::new();
+        hierarchy.write_tsv(&mut tsv).unwrap();
+        assert!(String::from_utf8(tsv.clone()).unwrap().starts_with("Q1\tQ2\nQ2\tQ1\nQ515\tQ486972\nQ532\tQ486972\nQ3957\tQ486972\nQ3957\tQ5\n"));
+        let path = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(path.path(), &tsv).unwrap();
+        assert_eq!(ClassHierarchy::load(path.path()).unwrap(), hierarchy);
+    }
+}
@@ -0,0 +1,163 @@
+/*!
+ * Simplified entities in ClickHouse's RowBinary format, for loading a
+ * snapshot into ClickHouse without it parsing JSON, e.g.
+ *
+ * ```text
+ * wikidump-process filter --format clickhouse ... \
+ *     | clickhouse-client --query "INSERT INTO entities FORMAT RowBinary"
+ * ```
+ *
+ * or over HTTP with `curl --data-binary @entities.bin
+ * 'http://localhost:8123/?query=INSERT%20INTO%20entities%20FORMAT%20RowBinary'`.
+ * See https://clickhouse.com/docs/en/interfaces/formats#rowbinary
+ *
+ * Outputs are parsed as entities and simplified (see `model::SimpleEntity`),
+ * and written as rows of the table made by `create_table`, which has the
+ * columns of the `sql` module's table: claim values are strings, ids, times
+ * and amounts as they are and anything else (e.g. coordinates) as JSON.
+ * RowBinary has no header, so the columns of the table have to be in this
+ * order.
+ */
+
+use std::io::{BufWriter, Write};
+use serde_json::Value;
+use crate::error::{ProcessError, Result};
+use crate::model::{Entity, SimpleEntity};
+use crate::sink::Sink;
+
+/// The statement creating a table `name` for the rows written by `ClickHouseSink`, ordered by id
+pub fn create_table(name: &str) -> String {
+    format!("CREATE TABLE {} (
+    id String,
+    type LowCardinality(String),
+    labels Map(String, String),
+    descriptions Map(String, String),
+    aliases Map(String, Array(String)),
+    claims Map(String, Array(String)),
+    sitelinks Map(String, String)
+) ENGINE = MergeTree ORDER BY id", name)
+}
+
+// a simplified claim value as a string: strings as they are, anything else as JSON
+fn value_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+// lengths are unsigned LEB128
+fn write_length(row: &mut Vec<u8>, mut length: usize) {
+    loop {
+        let byte = (length & 0x7f) as u8;
+        length >>= 7;
+        if length == 0 {
+            row.push(byte);
+            return;
+        }
+        row.push(byte | 0x80);
+    }
+}
+
+fn write_string(row: &mut Vec<u8>, value: &str) {
+    write_length(row, value.len());
+    row.extend_from_slice(value.as_bytes());
+}
+
+fn write_strings<'a>(row: &mut Vec<u8>, values: impl ExactSizeIterator<Item = &'a str>) {
+    write_length(row, values.len());
+    for value in values {
+        write_string(row, value);
+    }
+}
+
+/// Appends `entity` to `row` as a RowBinary row of the table made by `create_table`
+pub fn write_row(row: &mut Vec<u8>, entity: &SimpleEntity) {
+    write_string(row, &entity.id);
+    write_string(row, &entity.entity_type);
+    for terms in [&entity.labels, &entity.descriptions] {
+        write_length(row, terms.len());
+        for (language, term) in terms {
+            write_string(row, language);
+            write_string(row, term);
+        }
+    }
+    write_length(row, entity.aliases.len());
+    for (language, aliases) in &entity.aliases {
+        write_string(row, language);
+        write_strings(row, aliases.iter().map(String::as_str));
+    }
+    write_length(row, entity.claims.len());
+    for (property, values) in &entity.claims {
+        write_string(row, property);
+        let values = values.iter().map(value_string).collect::<Vec<_>>();
+        write_strings(row, values.iter().map(String::as_str));
+    }
+    write_length(row, entity.sitelinks.len());
+    for (site, title) in &entity.sitelinks {
+        write_string(row, site);
+        write_string(row, title);
+    }
+}
+
+/// Writes each output, which has to be a whole entity, as a RowBinary row, see the module documentation
+pub struct ClickHouseSink<W: Write> {
+    stream: BufWriter<W>,
+    row: Vec<u8>,
+}
+
+impl<W: Write> ClickHouseSink<W> {
+    /// Rows are accumulated and only handed to `output` once `buffer_size` bytes are ready, like `WriteSink`
+    pub fn new(output: W, buffer_size: usize) -> Self {
+        ClickHouseSink { stream: BufWriter::with_capacity(buffer_size, output), row: Vec::new() }
+    }
+}
+
+impl<W: Write> Sink for ClickHouseSink<W> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let entity = Entity::parse(output)
+            .map_err(|error| ProcessError::ClickHouse(format!("Output isn't an entity ({}), the jq filter has to keep whole entities: {:.100}", error, output)))?;
+        self.row.clear();
+        write_row(&mut self.row, &entity.simplify());
+        self.stream.write_all(&self.row).map_err(ProcessError::Write)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stream.flush().map_err(ProcessError::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_write_row() {
+        let entity = SimpleEntity {
+            id: String::from("Q1"),
+            entity_type: String::from("item"),
+            labels: [(String::from("en"), String::from("universe"))].into(),
+            aliases: [(String::from("en"), vec![String::from("cosmos")])].into(),
+            claims: [(String::from("P31"), vec![Value::from("Q36906466"), Value::from(1)])].into(),
+            ..SimpleEntity::default()
+        };
+        let mut row = Vec::new();
+        write_row(&mut row, &entity);
+        let mut expected = b"\x02Q1\x04item\x01\x02en\x08universe\x00\x01\x02en\x01\x06cosmos\x01\x03P31\x02\x09Q36906466\x011".to_vec();
+        expected.push(0);
+        assert_eq!(row, expected);
+    }
+
+    #[test]
+    fn test_write_length() {
+        let mut row = Vec::new();
+        write_length(&mut row, 300);
+        assert_eq!(row, [0xac, 0x02]);
+    }
+
+    #[test]
+    fn test_not_an_entity() {
+        let mut sink = ClickHouseSink::new(Vec::new(), 64);
+        assert!(matches!(sink.write_entity(r#"{"label":"universe"}"#), Err(ProcessError::ClickHouse(_))));
+    }
+}
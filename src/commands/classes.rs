@@ -0,0 +1,70 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{classes, default_threads, ProcessOptions};
+use wikidump_process::classes::ClassHierarchy;
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct ClassesArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to read subclass of (P279) statements from (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), long = "hierarchy", conflicts_with = "input-file-path", help = "Hierarchy written by an earlier run to use instead of reading a dump")]
+    hierarchy_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the subclass<TAB>class hierarchy, or the subclasses, to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "subclasses-of", help = "Comma separated classes to write the ids of, along with all of their subclasses however indirect, instead of the hierarchy")]
+    subclasses_of: Option<String>,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: ClassesArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let seeds = args.subclasses_of.as_deref().map(|classes| {
+        classes.split(',').map(str::trim).filter(|class| !class.is_empty()).map(str::to_string).collect::<Vec<_>>()
+    });
+    if seeds.as_ref().is_some_and(Vec::is_empty) {
+        return Err("--subclasses-of needs at least one class".into());
+    }
+    let mut output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+
+    let hierarchy = match (args.hierarchy_path, args.input_file_path) {
+        (Some(path), _) => ClassHierarchy::load(&path)?,
+        (None, input_file_path) => {
+            let source: Box<dyn Source> = match input_file_path {
+                Some(path) => Box::new(FileSource::new(path)),
+                None => Box::new(StdinSource),
+            };
+            classes::find_hierarchy(source, options)?.0
+        }
+    };
+    info!("Found {} subclasses", hierarchy.len());
+    match seeds {
+        Some(seeds) => {
+            let subclasses = hierarchy.subclasses_of(&seeds);
+            info!("{} classes are (subclasses of) {}", subclasses.len(), seeds.join(", "));
+            classes::write_ids(&subclasses, &mut output)?;
+        }
+        None => hierarchy.write_tsv(&mut output)?,
+    }
+    output.flush()?;
+    Ok(())
+}
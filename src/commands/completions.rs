@@ -0,0 +1,18 @@
+use std::io;
+use clap::{Args, CommandFactory};
+use clap_complete::Shell;
+use crate::Cli;
+use super::CommandResult;
+
+#[derive(Args, Debug)]
+pub struct CompletionsArgs {
+    #[clap(arg_enum, help = "Shell to generate completions for")]
+    shell: Shell,
+}
+
+pub fn run(args: CompletionsArgs) -> CommandResult {
+    let mut command = Cli::command();
+    let name = command.get_name().to_string();
+    clap_complete::generate(args.shell, &mut command, name, &mut io::stdout());
+    Ok(())
+}
@@ -0,0 +1,33 @@
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::convert::{self, Format};
+use wikidump_process::sink::Compression;
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct ConvertArgs {
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "Dump or output to convert, in any of the formats and compressions written (told apart by its content)")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the converted entities to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "to", possible_values = &["dump", "ndjson", "msgpack", "wbgetentities", "parquet"], help = "Format to write (default is guessed from the output's extension, e.g. dump for .json.bz2, otherwise ndjson). wbgetentities wraps the entities like the Wikibase API's responses, keyed by id. parquet writes them simplified, as rows of the table queried with filter --sql (with the parquet feature)")]
+    format: Option<Format>,
+
+    #[clap(long = "compression", possible_values = &["none", "bzip2", "gzip"], help = "How to compress the output (default is guessed from the output's extension, .bz2 or .gz, otherwise none)")]
+    compression: Option<Compression>,
+}
+
+pub fn run(args: ConvertArgs, context: &Context) -> CommandResult {
+    let output_path = args.output_file_path.as_deref();
+    let format = args.format.or_else(|| output_path.and_then(Format::from_path)).unwrap_or(Format::Ndjson);
+    let compression = args.compression.or_else(|| output_path.map(Compression::from_path)).unwrap_or(Compression::None);
+    // compressed as --compression says, whatever the extension
+    let output = context.create_uncompressed_output(output_path, args.force_overwrite)?;
+    convert::convert(&args.input_file_path, output, format, compression, context.progress)?;
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::{coverage, default_threads, ProcessOptions};
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{instance_classes, CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct CoverageArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to report on (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the CSV report to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "instance-of", help = "Comma separated classes, e.g. Q5,Q811979, to only count the entities which are an instance of (P31) one of, or of any of their subclasses however indirect. The subclasses are found with a first pass over the input, unless given with --class-hierarchy")]
+    instance_of: Option<String>,
+
+    #[clap(parse(from_os_str), long = "class-hierarchy", requires = "instance-of", help = "subclass<TAB>class hierarchy written by the classes subcommand to find the subclasses of --instance-of in, instead of a first pass over the input")]
+    class_hierarchy: Option<PathBuf>,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: CoverageArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let mut output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let classes = match &args.instance_of {
+        Some(instance_of) => Some(instance_classes(instance_of, args.class_hierarchy.as_deref(), args.input_file_path.as_deref(), &options)?),
+        None => None,
+    };
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    let (coverage, _) = coverage::language_coverage(source, classes, options)?;
+    coverage.write_csv(&mut output)?;
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::dedupe::{self, Keep};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct DedupeArgs {
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "Dump or filter output to dedupe (bzip2 compressed dumps, uncompressed dumps and ndjson are all read)")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the remaining entities to, one per line (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "keep", default_value = "first", possible_values = &["first", "last"], help = "Which of the entities with the same id to keep. last takes a second pass over the input")]
+    keep: Keep,
+}
+
+pub fn run(args: DedupeArgs, context: &Context) -> CommandResult {
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let stats = dedupe::dedupe(&args.input_file_path, args.keep, context.progress, output)?;
+    info!("Dropped {} duplicates of {} entities", stats.duplicates, stats.entities);
+    Ok(())
+}
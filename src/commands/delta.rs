@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{delta, ProcessError, ProcessOptions};
+use wikidump_process::sink::{CompressedWriter, Compression, WriteSink};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct DeltaArgs {
+    #[clap(parse(from_os_str), help = "Older filter output, sorted by id (see the sort subcommand)")]
+    old: PathBuf,
+
+    #[clap(parse(from_os_str), help = "Newer filter output, sorted by id")]
+    new: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the delta to, compressed when it ends with .gz or .bz2 (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct ApplyDeltaArgs {
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "Filter output sorted by id, the old version the delta was made from")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'd', long = "delta", help = "Delta written by the delta subcommand")]
+    delta_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the patched entities to, one per line (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+}
+
+pub fn run(args: DeltaArgs, context: &Context) -> CommandResult {
+    let compression = args.output_file_path.as_deref().map(Compression::from_path).unwrap_or(Compression::None);
+    // compressed here, so the end of the compressed data is written (or fails) before the delta is reported done
+    let output = context.create_uncompressed_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let mut output = CompressedWriter::new(output, compression);
+    let stats = delta::delta(&args.old, &args.new, context.progress, &mut output)?;
+    output.finish().map_err(ProcessError::Write)?;
+    info!("{} added, {} updated, {} deleted, {} unchanged", stats.added, stats.updated, stats.deleted, stats.unchanged);
+    Ok(())
+}
+
+pub fn apply(args: ApplyDeltaArgs, context: &Context) -> CommandResult {
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let mut sink = WriteSink::new(output, ProcessOptions::default().write_buffer_size);
+    let stats = delta::apply_delta(&args.input_file_path, &args.delta_file_path, context.progress, &mut sink)?;
+    info!("{} added, {} updated, {} deleted, {} unchanged", stats.added, stats.updated, stats.deleted, stats.unchanged);
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{diff, ProcessError};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct DiffArgs {
+    #[clap(parse(from_os_str), help = "Older dump or filter output (bzip2 compressed dumps, uncompressed dumps and ndjson are all read)")]
+    old: PathBuf,
+
+    #[clap(parse(from_os_str), help = "Newer dump or filter output")]
+    new: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the changes to, one JSON object per line (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "patches", help = "Include a JSON Patch (RFC 6902) of each changed entity, which takes another pass over the old input")]
+    patches: bool,
+}
+
+pub fn run(args: DiffArgs, context: &Context) -> CommandResult {
+    let mut output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let stats = diff::diff(&args.old, &args.new, args.patches, context.progress, |change| {
+        serde_json::to_writer(&mut output, &change).map_err(|error| ProcessError::Write(error.into()))?;
+        writeln!(output).map_err(ProcessError::Write)
+    })?;
+    output.flush()?;
+    info!("{} added, {} removed, {} changed, {} unchanged, {} skipped without an id", stats.added, stats.removed, stats.changed, stats.unchanged, stats.skipped);
+    Ok(())
+}
@@ -0,0 +1,24 @@
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::download;
+use super::{CommandResult, Context, Exit, EXIT_DOWNLOAD_FAILED};
+
+#[derive(Args, Debug)]
+pub struct DownloadArgs {
+    #[clap(long = "dump-version", default_value = "latest", help = "Which dump to download, e.g. latest or 20220404")]
+    dump_version: String,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output-dir", default_value = ".", help = "Directory to download the dump to")]
+    output_dir: PathBuf,
+
+    #[clap(long = "force-redownload", help = "Download the dump again even if it's already in the output directory")]
+    force_redownload: bool,
+}
+
+pub async fn run(args: DownloadArgs, context: &Context) -> CommandResult {
+    let path = download::dump_path(&args.dump_version, &args.output_dir);
+    let overwrite = context.may_overwrite(&path, args.force_redownload)?;
+    download::download(&args.dump_version, &args.output_dir, context.progress, overwrite).await
+        .map_err(|error| Exit::error(EXIT_DOWNLOAD_FAILED, error))?;
+    Ok(())
+}
@@ -0,0 +1,94 @@
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use clap::Args;
+use wikidump_process::{default_threads, edges, ProcessOptions, ProcessStats};
+use wikidump_process::source::{FileSource, Source, StdinSource};
+#[cfg(feature = "parquet")]
+use wikidump_process::parquet_sink::{self, EdgeParquetSink};
+use super::{CommandResult, Context};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum EdgeFormat {
+    Tsv,
+    Parquet,
+}
+
+impl EdgeFormat {
+    // the format of an output from its extension
+    fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(OsStr::to_str) {
+            Some("parquet") => EdgeFormat::Parquet,
+            _ => EdgeFormat::Tsv,
+        }
+    }
+}
+
+impl FromStr for EdgeFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "tsv" => Ok(EdgeFormat::Tsv),
+            "parquet" => Ok(EdgeFormat::Parquet),
+            _ => Err(format!("Invalid edge list format '{}', expected tsv or parquet", value)),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct EdgesArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to read statements from (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the edge list to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "qualifiers", help = "Also write edges for qualifiers whose value is an item, with the property they qualify in a fourth column")]
+    qualifiers: bool,
+
+    #[clap(long = "format", possible_values = &["tsv", "parquet"], help = "Format to write (default is guessed from the output's extension, parquet for .parquet, otherwise tsv). parquet has source, property and target columns, and a qualified one with --qualifiers (with the parquet feature)")]
+    format: Option<EdgeFormat>,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: EdgesArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    let format = args.format.or_else(|| args.output_file_path.as_deref().map(EdgeFormat::from_path)).unwrap_or(EdgeFormat::Tsv);
+    match format {
+        EdgeFormat::Tsv => edges::write_edges(source, args.qualifiers, options, output)?,
+        EdgeFormat::Parquet => write_parquet_edges(source, args.qualifiers, options, output)?,
+    };
+    Ok(())
+}
+
+#[cfg(feature = "parquet")]
+fn write_parquet_edges(source: Box<dyn Source>, qualifiers: bool, options: ProcessOptions, output: Box<dyn Write>) -> Result<ProcessStats, Box<dyn std::error::Error>> {
+    let sink = EdgeParquetSink::new(output, qualifiers, parquet_sink::DEFAULT_ROW_GROUP_SIZE)?;
+    Ok(edges::write_edges_to(source, qualifiers, options, sink)?)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn write_parquet_edges(_source: Box<dyn Source>, _qualifiers: bool, _options: ProcessOptions, _output: Box<dyn Write>) -> Result<ProcessStats, Box<dyn std::error::Error>> {
+    Err("Writing edges as Parquet needs a build with the parquet feature".into())
+}
@@ -0,0 +1,1203 @@
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+use clap::Args;
+use indicatif::{HumanBytes, HumanDuration};
+use log::{error, info, warn};
+use wikidump_process::{decoder, CancellationToken, default_threads, filter, parse_duration, parse_size, sink, validate, EntityReader, ErrorBudget, Pipeline, ProcessError, ProcessOptions, Progress};
+use wikidump_process::blazegraph::{self, BlazegraphChunkSink};
+use wikidump_process::bloom::{self, BloomRecorder, IdHashes};
+use wikidump_process::canonical;
+use wikidump_process::checkpoint::Checkpoint;
+use wikidump_process::decoder::StreamRange;
+use wikidump_process::classes::ClassFilter;
+use wikidump_process::clickhouse::{self, ClickHouseSink};
+use wikidump_process::crosswalk::{self, Crosswalk};
+use wikidump_process::datatypes::{self, Datatypes};
+use wikidump_process::dedupe::DedupeSink;
+use wikidump_process::edges;
+use wikidump_process::estimate;
+use wikidump_process::filter::{CountingFilter, EntityFilter, FilterCounts, FilterFactory};
+use wikidump_process::ids::{IdFilter, IdSet};
+use wikidump_process::geojson::{self, GeoJsonSink};
+use wikidump_process::languages::{self, LanguageSplitSink};
+use wikidump_process::lexemes;
+use wikidump_process::metadata::{self, MetadataFilter};
+use wikidump_process::metrics::{self, Metrics};
+use wikidump_process::model::Entity;
+use wikidump_process::oversize::{OversizeFilter, OversizePolicy};
+use wikidump_process::pipeline::{PipelineBuilder, Transform};
+use wikidump_process::plan::{Manifest, PlannedRange};
+use wikidump_process::presets::{self, Preset};
+use wikidump_process::qlever::QleverSink;
+use wikidump_process::quickstatements::{self, QuickStatementsFormat};
+use wikidump_process::redirects::Redirects;
+use wikidump_process::references::{self, ReferenceRequirement};
+use wikidump_process::revisions::{self, RevisionFilter, Since};
+use wikidump_process::shard::{self, Shard, ShardFilter};
+use wikidump_process::sink::{Compression, CountingSink, FinishingSink, OutputFinish, Preallocation, Sink, WriteSink};
+use wikidump_process::source::{self, FileSource, Source, StdinSource};
+#[cfg(feature = "s3")]
+use wikidump_process::source::S3Source;
+#[cfg(feature = "flight")]
+use wikidump_process::flight::FlightSink;
+#[cfg(feature = "redis")]
+use wikidump_process::redis_sink::{RedisLayout, RedisSink};
+#[cfg(feature = "mongodb")]
+use wikidump_process::mongodb_sink::{MongoDbSink, WriteMode};
+#[cfg(feature = "rocksdb")]
+use wikidump_process::rocksdb_store::{self, RocksDbSink};
+#[cfg(feature = "lmdb")]
+use wikidump_process::lmdb_store::{self, LmdbSink};
+#[cfg(feature = "sqlite")]
+use wikidump_process::sqlite_sink::SqliteSink;
+#[cfg(feature = "kafka")]
+use wikidump_process::kafka_sink::KafkaSink;
+#[cfg(feature = "datafusion")]
+use wikidump_process::sql::{self, SqlSink};
+#[cfg(feature = "parquet")]
+use wikidump_process::parquet_sink::{self, ParquetSink};
+use wikidump_process::style::{self, OutputStyle};
+use wikidump_process::times;
+use wikidump_process::units::UnitTable;
+use super::{instance_classes, CommandResult, Context, Exit, EXIT_INTERRUPTED, EXIT_INVALID_INPUT, EXIT_PARTIAL, EXIT_TIMED_OUT, EXIT_VERIFY_FAILED};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum OutputFormat {
+    Ndjson,
+    Geojson,
+    Clickhouse,
+    Parquet,
+    Edges,
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "ndjson" => Ok(OutputFormat::Ndjson),
+            "geojson" => Ok(OutputFormat::Geojson),
+            "clickhouse" => Ok(OutputFormat::Clickhouse),
+            "parquet" => Ok(OutputFormat::Parquet),
+            "edges" => Ok(OutputFormat::Edges),
+            _ => Err(format!("Invalid output format '{}', expected ndjson, geojson, clickhouse, parquet or edges", value)),
+        }
+    }
+}
+
+#[derive(Args, Debug, Clone)]
+pub struct FilterArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Don't bail on error while filtering")]
+    continue_on_error: bool,
+
+    #[clap(long = "max-errors", requires = "continue-on-error", help = "Fail once more than this many entities (e.g. 100) or this percentage of those read (e.g. 5%) couldn't be filtered, rather than skipping any number of them. Percentages are only checked once 1000 entities have been read")]
+    max_errors: Option<ErrorBudget>,
+
+    #[clap(parse(from_os_str), long = "error-report", requires = "continue-on-error", help = "Write a line of JSON for each entity which couldn't be filtered to this file: its id, position in the dump, decompressed byte offset and length, and the error. Added to when resuming")]
+    error_report: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to filter (default is stdin), or one in an S3 bucket as s3://bucket/key to stream it from (with the s3 feature)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to output filtered entities (default is stdout), a Unix domain socket as unix:///path/to/socket, a Redis server as redis://host:port/db to set each output under its entity's id (with the redis feature), a MongoDB database as mongodb://host:port/db to write them as documents (with the mongodb feature), a Kafka topic as kafka://host:port/topic to produce them to keyed by their entity's id (with the kafka feature), or a new RocksDB, LMDB or SQLite database as rocksdb://path, lmdb://path or sqlite://path to store them under their entity's id (with the rocksdb, lmdb or sqlite feature). Files ending in .gz or .bz2 are compressed")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "preallocate", requires = "output-file-path", conflicts_with_all = &["resume", "count-only", "split-languages", "range-from-manifest"], parse(try_from_str = parse_preallocation), help = "Reserve disk for the output up front, to keep 100+ GB outputs from fragmenting on XFS or ext4: its expected size, e.g. 120G, the estimate of one printed as JSON by --estimate --stats-json, estimate to make that estimate first, or a previous output (e.g. of the last dump) to take the size of. Whatever isn't used is given back once done, or once the run fails. Linux only")]
+    preallocate: Option<PreallocationSize>,
+
+    #[clap(short = 'j', long = "jq-filter", default_value = ".", help = "jq filter, see https://stedolan.github.io/jq/ for usage. NOTE: The filter is applied to EACH ENTITY!")]
+    jq_filter: String,
+
+    #[clap(long = "write-buffer-size", default_value = "8M", parse(try_from_str = parse_size), help = "Amount of filtered output to accumulate before writing, e.g. 64K, 8M. Larger values mean fewer syscalls, which helps on network filesystems")]
+    write_buffer_size: usize,
+
+    #[clap(short = 'p', long = "pass-through", help = "Use the jq filter only to decide which entities to keep, and write those entities out exactly as they appear in the dump")]
+    pass_through: bool,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for filtering (default is the number of available CPUs)")]
+    threads: Option<usize>,
+
+    #[clap(long = "pin-cores", help = "Pin each filtering thread to its own CPU core")]
+    pin_cores: bool,
+
+    #[clap(long = "max-entity-size", parse(try_from_str = parse_size), help = "Largest entity to filter as it is, in bytes of JSON, e.g. 16M, so a single pathological entity can't blow up memory or stall a thread. See --oversize-policy for what happens to larger ones")]
+    max_entity_size: Option<usize>,
+
+    #[clap(long = "oversize-policy", default_value = "error", possible_values = &["skip", "truncate-claims", "error"], requires = "max-entity-size", help = "What to do with entities over --max-entity-size: skip them, recording their ids in the --error-report, drop their statements and filter the rest, or stop with an error")]
+    oversize_policy: OversizePolicy,
+
+    #[clap(long = "max-memory", parse(try_from_str = parse_size), help = "Upper bound on the memory used by entities waiting to be filtered or written, e.g. 512M, 4G. Batches shrink as the limit is approached")]
+    max_memory: Option<usize>,
+
+    #[clap(long = "dry-run", help = "Check the filter, input and output, and print what would be done without processing anything")]
+    dry_run: bool,
+
+    #[clap(parse(from_os_str), long = "checkpoint", help = "Save where the run got to in this file every minute and once it stops, so it can be resumed with --resume")]
+    checkpoint: Option<PathBuf>,
+
+    #[clap(long = "resume", requires_all = &["checkpoint", "input-file-path", "output-file-path"], help = "Carry on from the --checkpoint of an interrupted run, appending to its --output")]
+    resume: bool,
+
+    #[clap(long = "max-runtime", parse(try_from_str = parse_duration), help = "Stop after this long, e.g. 90m, 6h, saving a checkpoint to resume from (--checkpoint, or the output's path with .checkpoint appended) and exiting with code 124")]
+    max_runtime: Option<Duration>,
+
+    #[clap(long = "stats-interval", parse(try_from_str = parse_duration), help = "Print a line to stderr this often, e.g. 60s or 5m, with entities per second, MB/s in and out, matches, errors and the ETA, for batch logs where the progress bar is useless")]
+    stats_interval: Option<Duration>,
+
+    #[clap(long = "dedupe", conflicts_with_all = &["checkpoint", "resume", "max-runtime"], help = "Drop entities whose id (or whole output, without one) was already written, keeping the first. Use the dedupe subcommand to keep the last")]
+    dedupe: bool,
+
+    #[clap(parse(from_os_str), long = "redirects", help = "Replace the ids of redirected entities in statement values of the output with those they redirect to, using a from<TAB>to mapping written by the redirects subcommand")]
+    redirects: Option<PathBuf>,
+
+    #[clap(long = "normalize-units", help = "Add a normalized member to quantity values in common units of length, area, volume, mass, time and speed, with their amount and bounds converted to one canonical unit per dimension (e.g. metres), keeping the original amount and unit")]
+    normalize_units: bool,
+
+    #[clap(parse(from_os_str), long = "unit-table", requires = "normalize-units", help = "Normalize units with the unit<TAB>canonical unit<TAB>factor rows of this file too, as item ids, e.g. Q828224<TAB>Q11573<TAB>1000 for kilometres to metres. Its rows replace built in ones for the same units")]
+    unit_table: Option<PathBuf>,
+
+    #[clap(long = "normalize-times", help = "Add a normalized member to time values, with the time as plain ISO 8601 cut down to its precision (e.g. 1952-03 for a month) in the Gregorian calendar, and the name of the precision (e.g. month), keeping the original value")]
+    normalize_times: bool,
+
+    #[clap(long = "keep-datatypes", help = "Comma separated datatypes to keep the statements of in outputs which are entities, e.g. wikibase-item,time,quantity, dropping the others (and properties left without statements)")]
+    keep_datatypes: Option<Datatypes>,
+
+    #[clap(long = "require-references", possible_values = &["statements", "entities"], help = "Leave out unsourced data from outputs which are entities: statements drops the statements without any reference (and properties left without statements), entities drops the entities without a single referenced statement")]
+    require_references: Option<ReferenceRequirement>,
+
+    #[clap(long = "instance-of", help = "Comma separated classes, e.g. Q5,Q811979, to only filter the entities which are an instance of (P31) one of, or of any of their subclasses however indirect. The subclasses are found with a first pass over the input, unless given with --class-hierarchy")]
+    instance_of: Option<String>,
+
+    #[clap(parse(from_os_str), long = "class-hierarchy", requires = "instance-of", help = "subclass<TAB>class hierarchy written by the classes subcommand to find the subclasses of --instance-of in, instead of a first pass over the input")]
+    class_hierarchy: Option<PathBuf>,
+
+    #[clap(long = "shard", help = "Only filter the entities of one shard out of several by a hash of their id, e.g. 3/8 for shard 3 of 8 counting from 0, so machines can each take a shard of the same dump. Shards are the same as merge --shards makes")]
+    shard: Option<Shard>,
+
+    #[clap(parse(from_os_str), long = "range-from-manifest", requires = "output-file-path", conflicts_with_all = &["checkpoint", "resume", "max-runtime", "split-languages"], help = "Only filter ranges of the dump planned by the plan subcommand, claiming each one no other worker has claimed yet until none are left, or the one given with --range. Each range is written next to --output, e.g. out.3.ndjson for range 3, so merge can put them back together in order. The input defaults to the manifest's dump")]
+    range_from_manifest: Option<PathBuf>,
+
+    #[clap(long = "range", requires = "range-from-manifest", help = "Filter this range of the --range-from-manifest manifest, by its index, without claiming it")]
+    range: Option<usize>,
+
+    #[clap(parse(from_os_str), long = "ids-bloom", conflicts_with = "ids-bitmap", help = "Only keep the entities whose id is in this bloom filter, as written by --bloom-output. A few others get through, at the rate it was made for")]
+    ids_bloom: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), long = "ids-bitmap", help = "Only keep the items whose number is in this roaring bitmap (in the portable format), e.g. 42 for Q42")]
+    ids_bitmap: Option<PathBuf>,
+
+    #[clap(long = "modified-after", parse(try_from_str = revisions::parse_timestamp), help = "Only filter the entities modified after this date or UTC time, e.g. 2024-01-01 or 2024-01-01T12:00:00Z, by their modified field, to extract what changed since an older dump")]
+    modified_after: Option<String>,
+
+    #[clap(long = "revision-after", help = "Only filter the entities whose last revision (their lastrevid field) is after this revision id")]
+    revision_after: Option<u64>,
+
+    #[clap(long = "keep-metadata", help = "Comma separated page metadata fields (pageid, ns, title, lastrevid, modified) or all, to add to outputs which are JSON objects whatever the jq filter keeps, e.g. for provenance")]
+    keep_metadata: Option<String>,
+
+    #[clap(long = "drop-metadata", help = "Comma separated page metadata fields (pageid, ns, title, lastrevid, modified) or all, to remove from outputs which are JSON objects whatever the jq filter keeps, e.g. for size")]
+    drop_metadata: Option<String>,
+
+    #[clap(short = 'l', long = "languages", help = "Comma separated languages to keep the labels, descriptions and aliases of outputs in, dropping the others. Trimmed outputs are re-serialized")]
+    languages: Option<String>,
+
+    #[clap(long = "split-languages", requires_all = &["languages", "output-file-path"], conflicts_with_all = &["count-only", "checkpoint", "resume", "max-runtime"], help = "Write an output per language next to --output, e.g. out.en.ndjson, with only the terms in that language, leaving out entities without any")]
+    split_languages: bool,
+
+    #[clap(long = "preset", possible_values = &["labels-only", "minimal", "truthy-simple", "graph-edges"], conflicts_with_all = &["jq-filter", "pass-through", "split-languages", "flatten-lexemes", "quickstatements", "crosswalk", "format"], help = "Write a simpler record of each entity, made natively without jq: labels-only its labels as plain strings, minimal its terms and sitelinks, truthy-simple those and the plain values of its best ranked statements, graph-edges the items its statements point to")]
+    preset: Option<Preset>,
+
+    #[clap(long = "canonicalize", conflicts_with_all = &["quickstatements", "crosswalk"], help = "Write outputs as canonical JSON, with the id first and the other keys sorted, and the statements of each property sorted by id, so outputs of different runs or dump versions diff cleanly line by line")]
+    canonicalize: bool,
+
+    #[clap(long = "compact", conflicts_with_all = &["pretty", "quickstatements", "crosswalk"], help = "Write JSON outputs compact, each value on a single line without whitespace between tokens, whatever the jq filter produced")]
+    compact: bool,
+
+    #[clap(long = "pretty", conflicts_with_all = &["quickstatements", "crosswalk", "verify-output"], help = "Write JSON outputs pretty-printed, indented with two spaces, whatever the jq filter produced. The output is then no longer ndjson")]
+    pretty: bool,
+
+    #[clap(long = "flatten-lexemes", help = "Replace outputs which are whole lexemes with one record per form and per sense, carrying the lexeme's lemmas, language and lexical category. Other outputs are kept as-is")]
+    flatten_lexemes: bool,
+
+    #[clap(long = "quickstatements", possible_values = &["v1", "csv"], conflicts_with_all = &["resume", "count-only", "split-languages"], help = "Write QuickStatements commands recreating the statements of each output entity instead of the output itself: v1 writes a tab separated command per statement with its qualifiers and references, csv a row per statement under a qid,<property>... header")]
+    quickstatements: Option<QuickStatementsFormat>,
+
+    #[clap(long = "quickstatements-properties", requires = "quickstatements", help = "Comma separated properties to write QuickStatements commands for (default is all of them for v1). csv needs them, as its columns")]
+    quickstatements_properties: Option<String>,
+
+    #[clap(long = "crosswalk", conflicts_with_all = &["resume", "count-only", "split-languages", "quickstatements"], help = "Write a crosswalk of external identifiers instead of the outputs: a TSV row per output entity with any of them, under a qid,<name>... header, e.g. P227=GND,P214=VIAF,P345=IMDb. Several values of a property are separated by |")]
+    crosswalk: Option<Crosswalk>,
+
+    #[clap(long = "format", default_value = "ndjson", possible_values = &["ndjson", "geojson", "clickhouse", "parquet", "edges"], conflicts_with_all = &["resume", "count-only", "split-languages", "quickstatements", "crosswalk"], help = "geojson writes a FeatureCollection with a point feature per output entity with a coordinate location (P625) instead of the outputs, leaving out the others. clickhouse writes each output entity, simplified, as a RowBinary row of the table --dry-run prints the CREATE TABLE statement of, for INSERT INTO entities FORMAT RowBinary. parquet writes each output entity, simplified, as a row of a Parquet file of the table queried with --sql (with the parquet feature). edges writes a <source>\t<property>\t<target> row for each non-deprecated statement of each output entity whose value is an item, as the edges subcommand does")]
+    format: OutputFormat,
+
+    #[clap(long = "edge-qualifiers", help = "With --format edges, also write edges for qualifiers whose value is an item, with the property they qualify in a fourth column")]
+    edge_qualifiers: bool,
+
+    #[clap(long = "geojson-properties", help = "Comma separated dotted paths into the simplified entity to give features as properties, e.g. labels.en,claims.P31 (default is none)")]
+    geojson_properties: Option<String>,
+
+    #[clap(long = "verify-output", requires = "output-file-path", conflicts_with_all = &["resume", "count-only", "split-languages", "quickstatements", "crosswalk"], help = "Once done, read the output back to check that it reads to its end, that each line is valid JSON, and that there are as many as were written, failing if not")]
+    verify_output: bool,
+
+    #[clap(parse(from_os_str), long = "bloom-output", conflicts_with = "resume", help = "Also write a bloom filter of the ids of the entities with an output to this file, whatever the output is (e.g. of -j .labels), for other services to check whether an id is in the subset without the full list of ids")]
+    bloom_output: Option<PathBuf>,
+
+    #[clap(long = "bloom-false-positive-rate", requires = "bloom-output", parse(try_from_str = bloom::parse_false_positive_rate), help = "Rate of ids the bloom filter wrongly reports as in the subset, which it's sized for (default is 0.01)")]
+    bloom_false_positive_rate: Option<f64>,
+
+    #[clap(long = "count-only", conflicts_with_all = &["checkpoint", "resume", "max-runtime"], help = "Apply the filters but write nothing except the number of entities with an output, to estimate the size of a full run")]
+    count_only: bool,
+
+    #[clap(long = "count-stages", requires = "count-only", help = "Write the number of entities left after each stage instead, as <stage><TAB><count> rows: read, shard, ids, modified-after, instance-of, jq-filter, flatten-lexemes and dedupe, for those used")]
+    count_stages: bool,
+
+    #[clap(long = "estimate", requires = "input-file-path", conflicts_with_all = &["dry-run", "checkpoint", "resume", "max-runtime", "count-only", "split-languages", "range-from-manifest"], help = "Filter a few samples of the dump spread over it, as the run would with the rest of the options, writing nothing, and print how many entities, outputs and bytes of them a full run would make and how long it would take, extrapolated to the whole dump")]
+    estimate: bool,
+
+    #[clap(long = "estimate-samples", default_value = "8", requires = "estimate", help = "Samples of the dump --estimate filters")]
+    estimate_samples: usize,
+
+    #[clap(long = "estimate-sample-size", default_value = "16M", requires = "estimate", parse(try_from_str = parse_size), help = "Compressed bytes of the dump in each sample of --estimate, e.g. 64M, rounded up to whole bzip2 streams")]
+    estimate_sample_size: usize,
+
+    #[clap(long = "metrics-listen", help = "Serve live metrics in the Prometheus text format on this address, e.g. 0.0.0.0:9100, while the run goes on")]
+    metrics_listen: Option<String>,
+
+    #[clap(long = "stats-json", help = "Print statistics about the run as JSON to stderr once done")]
+    stats_json: bool,
+
+    #[cfg(feature = "datafusion")]
+    #[clap(long = "sql", conflicts_with_all = &["estimate", "checkpoint", "resume", "max-runtime", "count-only", "split-languages", "preset", "flatten-lexemes", "quickstatements", "crosswalk", "format", "verify-output", "blazegraph-chunks", "qlever"], help = "Write the results of this SQL query over the entities kept by the jq filter as ndjson instead, e.g. \"SELECT id, labels.en FROM entities WHERE type = 'property'\". The entities table has id, type, labels, descriptions, aliases, claims and sitelinks columns of simplified entities, and is queried by DataFusion as the dump streams in")]
+    sql: Option<String>,
+
+    #[cfg(feature = "redis")]
+    #[clap(long = "redis-key-prefix", conflicts_with = "redis-hash", help = "With a redis:// --output, prefix the id each output is set under with this, e.g. entity: for entity:Q42")]
+    redis_key_prefix: Option<String>,
+
+    #[cfg(feature = "redis")]
+    #[clap(long = "redis-hash", help = "With a redis:// --output, set outputs as fields of this hash, by id, with HSET, instead of a key per entity")]
+    redis_hash: Option<String>,
+
+    #[cfg(feature = "mongodb")]
+    #[clap(long = "mongodb-collection", default_value = "entities", help = "With a mongodb:// --output, the collection of its database to write outputs to")]
+    mongodb_collection: String,
+
+    #[cfg(feature = "mongodb")]
+    #[clap(long = "mongodb-mode", default_value = "insert", possible_values = &["insert", "replace"], help = "With a mongodb:// --output, insert outputs with insertMany, failing on ids already in the collection, or replace the document with the same id, inserting those which aren't there yet")]
+    mongodb_mode: WriteMode,
+
+    #[cfg(feature = "flight")]
+    #[clap(long = "flight-listen", conflicts_with_all = &["output-file-path", "estimate", "sql", "checkpoint", "resume", "max-runtime", "count-only", "split-languages", "preset", "flatten-lexemes", "quickstatements", "crosswalk", "format", "verify-output", "blazegraph-chunks", "qlever"], help = "Serve the entities kept by the jq filter over Arrow Flight on this address, e.g. 0.0.0.0:50051, as record batches of simplified entities (the table queried with --sql), instead of writing them out. The run waits for consumers to take them, and ends once they've taken the last")]
+    flight_listen: Option<String>,
+
+    #[clap(parse(from_os_str), long = "blazegraph-chunks", conflicts_with_all = &["output-file-path", "checkpoint", "resume", "max-runtime", "count-only", "split-languages", "preset", "flatten-lexemes", "quickstatements", "crosswalk", "format", "verify-output"], help = "Write the entities kept by the jq filter as Turtle, as the Wikidata Query Service has them, into gzipped chunks in this directory named for its loadData.sh: wikidump-000000001.ttl.gz onwards")]
+    blazegraph_chunks: Option<PathBuf>,
+
+    #[clap(long = "chunk-entities", default_value = "50000", requires = "blazegraph-chunks", help = "Entities in each chunk written by --blazegraph-chunks")]
+    chunk_entities: usize,
+
+    #[clap(parse(from_os_str), long = "qlever", conflicts_with_all = &["output-file-path", "blazegraph-chunks", "checkpoint", "resume", "max-runtime", "count-only", "split-languages", "preset", "flatten-lexemes", "quickstatements", "crosswalk", "format", "verify-output"], help = "Write the entities kept by the jq filter as Turtle for QLever to index into this directory, e.g. ./humans, with the settings and Qleverfile for qlever index: humans.ttl.gz, humans.settings.json and Qleverfile")]
+    qlever: Option<PathBuf>,
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    #[clap(long = "io-uring", help = "Write the output file through io_uring so filtering overlaps with writing. Requires --output")]
+    io_uring: bool,
+}
+
+pub fn run(args: FilterArgs, context: &Context) -> CommandResult {
+    if args.input_file_path.as_deref().and_then(source::s3_url).is_some() {
+        if args.range_from_manifest.is_some() || args.estimate {
+            return Err("--range-from-manifest and --estimate seek around the dump, so need it as a local file rather than on S3".into());
+        }
+        if args.instance_of.is_some() && args.class_hierarchy.is_none() {
+            return Err("--instance-of would read the dump from S3 twice, give the hierarchy written by the classes subcommand with --class-hierarchy".into());
+        }
+    }
+    match args.range_from_manifest.clone() {
+        Some(manifest) => filter_ranges(args, &manifest, context),
+        None => filter_dump(args, None, context),
+    }
+}
+
+// filters the range given with --range of the manifest at `path`, or else each range no other worker has claimed yet
+fn filter_ranges(args: FilterArgs, path: &Path, context: &Context) -> CommandResult {
+    let manifest = Manifest::load(path)?;
+    let input = args.input_file_path.clone().unwrap_or_else(|| manifest.dump.clone());
+    let (_, size) = decoder::open(&input)?;
+    if size != manifest.size {
+        return Err(format!("{:?} was planned for a dump of {} bytes, but {:?} has {}", path, manifest.size, input, size).into());
+    }
+    let output = args.output_file_path.clone().expect("--range-from-manifest requires --output");
+    let filter_range = |range: &PlannedRange| {
+        info!("Filtering range {} of {} ({} streams, about {} entities)", range.index, manifest.ranges.len(), range.streams, range.estimated_entities);
+        let args = FilterArgs {
+            input_file_path: Some(input.clone()),
+            output_file_path: Some(shard::shard_path(&output, range.index)),
+            range_from_manifest: None,
+            ..args.clone()
+        };
+        filter_dump(args, Some(range.stream_range()), context)
+    };
+    match args.range {
+        Some(index) => filter_range(manifest.range(index).ok_or_else(|| format!("{:?} has no range {}", path, index))?),
+        None => {
+            let mut filtered = 0;
+            while let Some(range) = manifest.claim(path)? {
+                filter_range(range)?;
+                filtered += 1;
+            }
+            info!("Filtered {} ranges, none of the {} are left to claim", filtered, manifest.ranges.len());
+            Ok(())
+        }
+    }
+}
+
+// filters the whole dump, or only `range` of its bzip2 streams
+fn filter_dump(args: FilterArgs, range: Option<StreamRange>, context: &Context) -> CommandResult {
+    let mut options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        write_buffer_size: args.write_buffer_size,
+        pass_through: args.pass_through,
+        threads: args.threads.unwrap_or_else(default_threads),
+        pin_cores: args.pin_cores,
+        max_memory: args.max_memory,
+        progress: context.progress,
+        checkpoint: checkpoint_path(&args),
+        max_errors: args.max_errors,
+        error_report: args.error_report.clone(),
+        stats_interval: args.stats_interval,
+        range,
+        ..ProcessOptions::default()
+    };
+
+    if args.verify_output && args.format == OutputFormat::Geojson {
+        return Err("--verify-output reads outputs back as lines of JSON, so can't verify a GeoJSON feature collection".into());
+    }
+    if args.verify_output && args.format == OutputFormat::Edges {
+        return Err("--verify-output reads outputs back as lines of JSON, so can't verify an edge list".into());
+    }
+    if args.edge_qualifiers && args.format != OutputFormat::Edges {
+        return Err("--edge-qualifiers only applies to --format edges".into());
+    }
+    let binary_format = match args.format {
+        OutputFormat::Clickhouse => Some("clickhouse"),
+        OutputFormat::Parquet => Some("parquet"),
+        OutputFormat::Ndjson | OutputFormat::Geojson | OutputFormat::Edges => None,
+    };
+    if let Some(format) = binary_format.filter(|_| args.verify_output || database_output(&args).is_some()) {
+        return Err(format!("--format {} writes binary rows, which can't be read back by --verify-output or written to a database", format).into());
+    }
+    if let Some(socket) = args.output_file_path.as_deref().and_then(sink::unix_socket) {
+        if args.resume || args.verify_output || args.split_languages || options.checkpoint.is_some() || uses_io_uring(&args) {
+            return Err(format!("{:?} is a Unix domain socket, which can't be checkpointed, resumed, read back, split by language or written through io_uring", socket).into());
+        }
+    }
+
+    if let Some(path) = args.output_file_path.as_deref().filter(|path| database_output(&args).is_none() && sink::unix_socket(path).is_none()) {
+        let compressed = sink::output_compression(path)? != Compression::None;
+        if compressed && (args.resume || options.checkpoint.is_some() || uses_io_uring(&args)) {
+            return Err(format!("{:?} is compressed, so can't be checkpointed, resumed or written through io_uring", path).into());
+        }
+    }
+
+    if let Some(url) = database_output(&args) {
+        if args.resume || args.verify_output || args.split_languages || args.count_only || args.preallocate.is_some() || options.checkpoint.is_some() || uses_io_uring(&args) {
+            return Err(format!("{} is a database, which can't be checkpointed, resumed, read back, split by language, counted into, preallocated or written through io_uring", url).into());
+        }
+    }
+
+    if args.estimate {
+        return estimate(&args, &options);
+    }
+    if args.dry_run {
+        return dry_run(&args, &options, args.force_overwrite || context.yes);
+    }
+
+    // compressed outputs are finished once the sink is finalized, failing the run if their end can't be written
+    let (output, finish) = match (&args.checkpoint, &args.output_file_path) {
+        (Some(checkpoint_path), Some(output_path)) if args.resume => {
+            let checkpoint = Checkpoint::load(checkpoint_path)?;
+            if checkpoint.complete {
+                warn!("{:?} is from a run which already finished, nothing to resume", checkpoint_path);
+                return Ok(());
+            }
+            let output = sink::open_resumed_output(output_path, checkpoint.bytes_out)?;
+            options.resume = Some(checkpoint);
+            (output, OutputFinish::default())
+        }
+        // each language has its own output instead
+        _ if args.split_languages => (Box::new(io::sink()) as Box<dyn Write>, OutputFinish::default()),
+        _ => open_output(&args, context)?,
+    };
+    // released once the output is written, or dropped along with the reservation if the run fails
+    let preallocation = match &args.output_file_path {
+        Some(path) if !args.resume && sink::unix_socket(path).is_none() && database_output(&args).is_none() => preallocate(&args, &options, path)?,
+        _ => None,
+    };
+
+    if let Some(address) = &args.metrics_listen {
+        let served = Arc::new(Metrics::default());
+        metrics::serve(address.as_str(), Arc::clone(&served))
+            .map_err(|error| format!("Could not serve metrics on {}: {}", address, error))?;
+        options.metrics = Some(served);
+    }
+
+    let cancel = options.cancel.clone();
+    let checkpoint = options.checkpoint.clone();
+    let mut deduped = None;
+    // counts only go to the output, with the filtered entities going nowhere
+    let (output, mut count_output) = match args.count_only {
+        true => (Box::new(io::sink()) as Box<dyn Write>, Some(output)),
+        false => (output, None),
+    };
+
+    // the ids of the entities with an output, whatever the output is, for --bloom-output
+    let bloom_ids = match &args.bloom_output {
+        Some(path) if !context.may_overwrite(path, args.force_overwrite)? => return Err(ProcessError::OutputExists(path.clone()).into()),
+        Some(_) => Some(Arc::new(IdHashes::default())),
+        None => None,
+    };
+    let counts = StageCounts::default();
+    let stages = Stages::new(&args, &options, &counts, bloom_ids.clone())?;
+    let mut pipeline = stages.apply(Pipeline::builder().options(options));
+    let mut output = output;
+    if args.quickstatements == Some(QuickStatementsFormat::Csv) {
+        writeln!(output, "{}", quickstatements::csv_header(&quickstatements_properties(&args)))?;
+    }
+    if let Some(crosswalk) = &args.crosswalk {
+        writeln!(output, "{}", crosswalk.header())?;
+    }
+    let sink: Box<dyn Sink> = match (languages(&args), &args.output_file_path) {
+        (Some(languages), Some(path)) if args.split_languages => {
+            let sinks = languages.iter()
+                .map(|language| {
+                    let (output, finish) = open_language_output(path, language, &args, context)?;
+                    Ok((language.clone(), FinishingSink::new(WriteSink::new(output, args.write_buffer_size), finish)))
+                })
+                .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+            Box::new(LanguageSplitSink::new(sinks))
+        }
+        _ => Box::new(FinishingSink::new(output_sink(output, &args)?, finish)),
+    };
+    let sink: Box<dyn Sink> = match args.format {
+        OutputFormat::Geojson => Box::new(GeoJsonSink::new(sink)),
+        OutputFormat::Ndjson | OutputFormat::Clickhouse | OutputFormat::Parquet | OutputFormat::Edges => sink,
+    };
+    let records = Arc::new(AtomicU64::new(0));
+    let sink: Box<dyn Sink> = match args.verify_output {
+        true => Box::new(CountingSink::new(sink, Arc::clone(&records))),
+        false => sink,
+    };
+    pipeline = match args.dedupe {
+        true => pipeline.entity_sink(deduped.insert(DedupeSink::new(sink))),
+        false => pipeline.entity_sink(sink),
+    };
+    pipeline = match args.input_file_path {
+        Some(input_file_path) => pipeline.dump_source(input_source(&input_file_path)?),
+        None => pipeline.dump_source(StdinSource),
+    };
+    let pipeline = pipeline.build()?;
+    if let Some(max_runtime) = args.max_runtime {
+        cancel.cancel_after(max_runtime);
+    }
+    let interrupted = CancellationToken::new();
+    handle_interrupts(cancel, interrupted.clone());
+    let stats = pipeline.run()?;
+    let duplicates = deduped.map_or(0, |deduped| deduped.duplicates() as usize);
+    if let Some(preallocation) = preallocation {
+        preallocation.release()?;
+    }
+    if let (Some(ids), Some(path)) = (&bloom_ids, &args.bloom_output) {
+        let written = ids.write(path, args.bloom_false_positive_rate.unwrap_or(bloom::DEFAULT_FALSE_POSITIVE_RATE))?;
+        info!("Wrote a bloom filter of {} ids to {:?}", written, path);
+    }
+    if args.dedupe {
+        info!("Dropped {} duplicate entities", duplicates);
+    }
+    if let Some(output) = &mut count_output {
+        let matched = stats.entities_written - duplicates;
+        match args.count_stages {
+            true => {
+                writeln!(output, "read\t{}", stats.entities_read)?;
+                if args.shard.is_some() {
+                    writeln!(output, "shard\t{}", counts.shard.applied())?;
+                }
+                if args.ids_bloom.is_some() || args.ids_bitmap.is_some() {
+                    writeln!(output, "ids\t{}", counts.listed.applied())?;
+                }
+                if args.modified_after.is_some() || args.revision_after.is_some() {
+                    writeln!(output, "modified-after\t{}", counts.recent.applied())?;
+                }
+                if args.instance_of.is_some() {
+                    writeln!(output, "instance-of\t{}", counts.jq_filter.applied())?;
+                }
+                writeln!(output, "jq-filter\t{}", counts.jq_filter.output())?;
+                if args.flatten_lexemes {
+                    writeln!(output, "flatten-lexemes\t{}", stats.entities_written)?;
+                }
+                if args.dedupe {
+                    writeln!(output, "dedupe\t{}", matched)?;
+                }
+            }
+            false => writeln!(output, "{}", matched)?,
+        }
+        output.flush()?;
+    }
+    if args.stats_json {
+        eprintln!("{}", serde_json::to_string(&stats)?);
+    }
+    if !stats.cancelled {
+        if let (true, Some(path)) = (args.verify_output, &args.output_file_path) {
+            verify_output(path, records.load(Ordering::Relaxed), context)?;
+        }
+        if stats.entities_failed > 0 {
+            let message = format!("Finished, but skipped {} entities which could not be filtered", stats.entities_failed);
+            return Err(Exit { code: EXIT_PARTIAL, message }.into());
+        }
+        return Ok(());
+    }
+    let resume = match &checkpoint {
+        Some(path) => format!(", resume with --checkpoint {:?} --resume", path),
+        None => String::new(),
+    };
+    let exit = match args.max_runtime {
+        _ if interrupted.is_cancelled() => Exit { code: EXIT_INTERRUPTED, message: format!("Interrupted after {} entities{}", stats.entities_read, resume) },
+        Some(max_runtime) => Exit { code: EXIT_TIMED_OUT, message: format!("Stopped after the maximum runtime of {}{}", HumanDuration(max_runtime), resume) },
+        None => return Ok(()),
+    };
+    Err(exit.into())
+}
+
+// entities counted at each stage of the filters, for --count-stages
+#[derive(Default)]
+struct StageCounts {
+    jq_filter: Arc<FilterCounts>,
+    recent: Arc<FilterCounts>,
+    listed: Arc<FilterCounts>,
+    shard: Arc<FilterCounts>,
+}
+
+// the filters and transforms of a run, set up once so they can make several pipelines, e.g. for the samples of
+// --estimate, which then filter just as the run does
+struct Stages {
+    jq_filter: String,
+    // the filters around jq, without which jq is run on its own
+    filter: Option<FilterFactory>,
+    transforms: Vec<Transform>,
+}
+
+impl Stages {
+    fn new(args: &FilterArgs, options: &ProcessOptions, counts: &StageCounts, bloom_ids: Option<Arc<IdHashes>>) -> Result<Self, Box<dyn std::error::Error>> {
+        let classes = match &args.instance_of {
+            Some(instance_of) => Some(instance_classes(instance_of, args.class_hierarchy.as_deref(), args.input_file_path.as_deref(), options)?),
+            None => None,
+        };
+        let ids = match (&args.ids_bloom, &args.ids_bitmap) {
+            (Some(path), _) => Some(IdSet::load_bloom(path)?),
+            (_, Some(path)) => Some(IdSet::load_bitmap(path)?),
+            _ => None,
+        };
+        if let Some(ids) = &ids {
+            info!("Keeping the entities of {} ids", ids.len());
+        }
+        let ids = ids.map(Arc::new);
+        let since = Since { modified_after: args.modified_after.clone(), revision_after: args.revision_after };
+        let keep_metadata = metadata::parse_fields(args.keep_metadata.as_deref().unwrap_or(""))?;
+        let drop_metadata = metadata::parse_fields(args.drop_metadata.as_deref().unwrap_or(""))?;
+        if let Some(field) = keep_metadata.iter().find(|field| drop_metadata.contains(field)) {
+            return Err(format!("The {} metadata field can't be both kept and dropped", field).into());
+        }
+        let retains_metadata = !keep_metadata.is_empty() || !drop_metadata.is_empty();
+        let filter = match classes {
+            None if !args.count_only && since.is_empty() && !retains_metadata && ids.is_none() && args.shard.is_none() && args.max_entity_size.is_none() && bloom_ids.is_none() => None,
+            classes => {
+                let jq_filter = filter::jq_filter_factory(&args.jq_filter, options.continue_on_error, options.pass_through);
+                let continue_on_error = options.continue_on_error;
+                let counted = Arc::clone(&counts.jq_filter);
+                let recent = Arc::clone(&counts.recent);
+                let listed = Arc::clone(&counts.listed);
+                let sharded = Arc::clone(&counts.shard);
+                let shard = args.shard;
+                let oversize = args.max_entity_size.map(|limit| (limit, args.oversize_policy));
+                let recorded = bloom_ids.clone();
+                let filter: FilterFactory = Arc::new(move || {
+                    let jq_filter = match retains_metadata {
+                        true => Box::new(MetadataFilter::new(jq_filter()?, keep_metadata.clone(), drop_metadata.clone(), continue_on_error)),
+                        false => jq_filter()?,
+                    };
+                    let filter = CountingFilter::new(jq_filter, Arc::clone(&counted));
+                    let filter = match &classes {
+                        Some(classes) => Box::new(ClassFilter::new(Arc::clone(classes), Box::new(filter), continue_on_error)) as Box<dyn EntityFilter>,
+                        None => Box::new(filter),
+                    };
+                    let filter = match since.is_empty() {
+                        true => filter,
+                        false => Box::new(RevisionFilter::new(since.clone(), Box::new(CountingFilter::new(filter, Arc::clone(&recent))), continue_on_error)),
+                    };
+                    let filter = match &ids {
+                        Some(ids) => Box::new(IdFilter::new(Arc::clone(ids), Box::new(CountingFilter::new(filter, Arc::clone(&listed))))),
+                        None => filter,
+                    };
+                    let filter = match shard {
+                        Some(shard) => Box::new(ShardFilter::new(shard, Box::new(CountingFilter::new(filter, Arc::clone(&sharded))))),
+                        None => filter,
+                    };
+                    let filter = match oversize {
+                        Some((limit, policy)) => Box::new(OversizeFilter::new(limit, policy, filter)),
+                        None => filter,
+                    };
+                    Ok(match &recorded {
+                        Some(recorded) => Box::new(BloomRecorder::new(filter, Arc::clone(recorded))),
+                        None => filter,
+                    })
+                });
+                Some(filter)
+            }
+        };
+
+        let mut transforms: Vec<Transform> = Vec::new();
+        if let Some(path) = &args.redirects {
+            let redirects = Redirects::load(path)?;
+            info!("Rewriting the ids of {} redirected entities", redirects.len());
+            transforms.push(Arc::new(move |output| Some(redirects.rewrite(output))));
+        }
+        if args.normalize_units {
+            let mut units = UnitTable::bundled();
+            if let Some(path) = &args.unit_table {
+                units.load(path)?;
+            }
+            info!("Normalizing quantities in {} units", units.len());
+            transforms.push(Arc::new(move |output| Some(units.normalize_output(output))));
+        }
+        if args.normalize_times {
+            transforms.push(Arc::new(|output| Some(times::normalize_output(output))));
+        }
+        if let Some(datatypes) = args.keep_datatypes.clone() {
+            transforms.push(Arc::new(move |output| Some(datatypes::keep_datatypes_output(output, &datatypes))));
+        }
+        if let Some(requirement) = args.require_references {
+            transforms.push(Arc::new(move |output| references::require_references_output(output, requirement)));
+        }
+        let quickstatements_properties = quickstatements_properties(args);
+        if args.quickstatements == Some(QuickStatementsFormat::Csv) && quickstatements_properties.is_empty() {
+            return Err("--quickstatements csv needs the properties to write, as its columns, with --quickstatements-properties".into());
+        }
+        // each language has its own output instead when split
+        if let (Some(languages), false) = (languages(args), args.split_languages) {
+            transforms.push(Arc::new(move |output| Some(languages::trim_output(output, &languages))));
+        }
+        if args.flatten_lexemes {
+            transforms.push(Arc::new(lexemes::flatten_output));
+        }
+        if let Some(preset) = args.preset {
+            transforms.push(Arc::new(move |output| presets::preset_output(output, preset)));
+        }
+        if args.canonicalize {
+            transforms.push(Arc::new(|output| Some(canonical::canonicalize_output(output))));
+        }
+        let style = match (args.compact, args.pretty) {
+            (true, _) => Some(OutputStyle::Compact),
+            (_, true) => Some(OutputStyle::Pretty),
+            _ => None,
+        };
+        if let Some(style) = style {
+            transforms.push(Arc::new(move |output| Some(style::format_output(output, style))));
+        }
+        if let Some(format) = args.quickstatements {
+            transforms.push(Arc::new(move |output| quickstatements::quickstatements_output(output, format, &quickstatements_properties)));
+        }
+        if let Some(crosswalk) = args.crosswalk.clone() {
+            transforms.push(Arc::new(move |output| crosswalk::crosswalk_output(output, &crosswalk)));
+        }
+        if args.format == OutputFormat::Geojson {
+            let properties = args.geojson_properties.as_deref().unwrap_or("")
+                .split(',')
+                .map(str::trim)
+                .filter(|path| !path.is_empty())
+                .map(str::to_string)
+                .collect::<Vec<_>>();
+            transforms.push(Arc::new(move |output| geojson::feature_output(output, &properties)));
+        }
+        if args.format == OutputFormat::Edges {
+            let qualifiers = args.edge_qualifiers;
+            transforms.push(Arc::new(move |output| edges::edge_output(output, qualifiers)));
+        }
+        Ok(Stages { jq_filter: args.jq_filter.clone(), filter, transforms })
+    }
+
+    // `pipeline` filtering and transforming entities as the run does
+    fn apply<'a>(&self, pipeline: PipelineBuilder<'a>) -> PipelineBuilder<'a> {
+        let pipeline = match &self.filter {
+            Some(filter) => {
+                let filter = Arc::clone(filter);
+                pipeline.entity_filter(move || filter())
+            }
+            None => pipeline.filter(self.jq_filter.as_str()),
+        };
+        self.transforms.iter().fold(pipeline, |pipeline, transform| {
+            let transform = Arc::clone(transform);
+            pipeline.transform(move |output| transform(output))
+        })
+    }
+}
+
+// the properties given with --quickstatements-properties
+fn quickstatements_properties(args: &FilterArgs) -> Vec<String> {
+    args.quickstatements_properties.as_deref().unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|property| !property.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+// the languages given with --languages
+fn languages(args: &FilterArgs) -> Option<Vec<String>> {
+    args.languages.as_deref().map(|languages| {
+        languages.split(',').map(str::trim).filter(|language| !language.is_empty()).map(str::to_string).collect()
+    })
+}
+
+// reads the output at `path` back, failing unless it has `records` valid records
+fn verify_output(path: &Path, records: u64, context: &Context) -> CommandResult {
+    info!("Verifying {:?}", path);
+    let problems = validate::verify_output(path, records, context.progress)?;
+    for problem in &problems {
+        error!("{:?} line {}: {}: {}", path, problem.line, problem.kind, problem.message);
+    }
+    match problems.is_empty() {
+        true => Ok(()),
+        false => Err(Exit::error(EXIT_VERIFY_FAILED, format!("Output {:?} failed verification with {} problems", path, problems.len())).into()),
+    }
+}
+
+// stops the run on the first Ctrl-C, letting it flush its output and save its checkpoint, and exits right away on the second
+fn handle_interrupts(cancel: CancellationToken, interrupted: CancellationToken) {
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_err() {
+            return;
+        }
+        warn!("Interrupted, finishing up. Press Ctrl-C again to stop right away");
+        interrupted.cancel();
+        cancel.cancel();
+        if tokio::signal::ctrl_c().await.is_ok() {
+            std::process::exit(EXIT_INTERRUPTED);
+        }
+    });
+}
+
+// where to save checkpoints, which time-boxed runs always do when there's an output file to resume
+fn checkpoint_path(args: &FilterArgs) -> Option<PathBuf> {
+    match (&args.checkpoint, &args.output_file_path) {
+        (Some(checkpoint), _) => Some(checkpoint.clone()),
+        (None, Some(output)) if args.max_runtime.is_some() => {
+            let mut checkpoint = output.as_os_str().to_owned();
+            checkpoint.push(".checkpoint");
+            Some(checkpoint.into())
+        }
+        _ => None,
+    }
+}
+
+// opens the output in `language` of a run split by language, asking before overwriting it
+fn open_language_output(path: &Path, language: &str, args: &FilterArgs, context: &Context) -> Result<(Box<dyn Write>, OutputFinish), Box<dyn std::error::Error>> {
+    let path = languages::language_path(path, language);
+    let force_overwrite = context.may_overwrite(&path, args.force_overwrite)?;
+    Ok(sink::open_finishable_output(Some(&path), force_overwrite)?)
+}
+
+// the database outputs are written to, rather than a file: the URL of a server or a Kafka topic, or a RocksDB, LMDB or SQLite output
+fn database_output(args: &FilterArgs) -> Option<&str> {
+    let path = args.output_file_path.as_deref()?;
+    sink::redis_url(path)
+        .or_else(|| sink::mongodb_url(path))
+        .or_else(|| sink::kafka_url(path))
+        .or_else(|| local_database(path).and(path.to_str()))
+}
+
+// the directory (or file) of the RocksDB, LMDB or SQLite database `path` stands for, if it's one
+fn local_database(path: &Path) -> Option<&Path> {
+    sink::rocksdb_path(path).or_else(|| sink::lmdb_path(path)).or_else(|| sink::sqlite_path(path))
+}
+
+// writes outputs to `output`, unless they go to a database, Blazegraph chunks or QLever input, are served with --flight-listen,
+// queried with --sql or written as ClickHouse rows or Parquet
+fn output_sink(output: Box<dyn Write>, args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    if let Some(url) = args.output_file_path.as_deref().and_then(sink::redis_url) {
+        return redis_sink(url, args);
+    }
+    // open_output asked before overwriting the chunks already there
+    if let Some(directory) = &args.blazegraph_chunks {
+        return Ok(Box::new(BlazegraphChunkSink::create(directory, args.chunk_entities, true)?));
+    }
+    if let Some(directory) = &args.qlever {
+        return Ok(Box::new(QleverSink::create(directory, &qlever_name(directory), true)?));
+    }
+    if let Some(url) = args.output_file_path.as_deref().and_then(sink::mongodb_url) {
+        return mongodb_sink(url, args);
+    }
+    if let Some(path) = args.output_file_path.as_deref().and_then(sink::rocksdb_path) {
+        return rocksdb_sink(path);
+    }
+    if let Some(path) = args.output_file_path.as_deref().and_then(sink::lmdb_path) {
+        return lmdb_sink(path, args);
+    }
+    if let Some(path) = args.output_file_path.as_deref().and_then(sink::sqlite_path) {
+        return sqlite_sink(path, args);
+    }
+    if let Some(url) = args.output_file_path.as_deref().and_then(sink::kafka_url) {
+        return kafka_sink(url);
+    }
+    #[cfg(feature = "flight")]
+    if let Some(address) = &args.flight_listen {
+        return Ok(Box::new(FlightSink::new(address.as_str(), sql::DEFAULT_BATCH_SIZE)?));
+    }
+    #[cfg(feature = "datafusion")]
+    if let Some(query) = &args.sql {
+        return Ok(Box::new(SqlSink::new(query, io::BufWriter::with_capacity(args.write_buffer_size, output), sql::DEFAULT_BATCH_SIZE)?));
+    }
+    if args.format == OutputFormat::Clickhouse {
+        return Ok(Box::new(ClickHouseSink::new(output, args.write_buffer_size)));
+    }
+    if args.format == OutputFormat::Parquet {
+        return parquet_sink(output);
+    }
+    Ok(Box::new(WriteSink::new(output, args.write_buffer_size)))
+}
+
+#[cfg(feature = "parquet")]
+fn parquet_sink(output: Box<dyn Write>) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Ok(Box::new(ParquetSink::new(output, parquet_sink::DEFAULT_ROW_GROUP_SIZE)?))
+}
+
+#[cfg(not(feature = "parquet"))]
+fn parquet_sink(_output: Box<dyn Write>) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Err("--format parquet needs a build with the parquet feature".into())
+}
+
+// the name of the index written with --qlever into `directory`, which is named after it
+fn qlever_name(directory: &Path) -> String {
+    match directory.file_name().and_then(|name| name.to_str()) {
+        Some(name) if name != "." && name != ".." => name.to_string(),
+        _ => String::from("wikidata"),
+    }
+}
+
+#[cfg(feature = "redis")]
+fn redis_sink(url: &str, args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    let layout = match &args.redis_hash {
+        Some(key) => RedisLayout::Hash { key: key.clone() },
+        None => RedisLayout::Keys { prefix: args.redis_key_prefix.clone().unwrap_or_default() },
+    };
+    Ok(Box::new(RedisSink::connect(url, layout, args.write_buffer_size)?))
+}
+
+#[cfg(not(feature = "redis"))]
+fn redis_sink(url: &str, _args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Err(format!("{} is a Redis server, which needs a build with the redis feature to write to", url).into())
+}
+
+#[cfg(feature = "mongodb")]
+fn mongodb_sink(url: &str, args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Ok(Box::new(MongoDbSink::connect(url, &args.mongodb_collection, args.mongodb_mode)?))
+}
+
+#[cfg(not(feature = "mongodb"))]
+fn mongodb_sink(url: &str, _args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Err(format!("{} is a MongoDB server, which needs a build with the mongodb feature to write to", url).into())
+}
+
+// open_output asked before overwriting the database already
+#[cfg(feature = "rocksdb")]
+fn rocksdb_sink(path: &Path) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Ok(Box::new(RocksDbSink::create(path, true, rocksdb_store::DEFAULT_SST_SIZE)?))
+}
+
+#[cfg(not(feature = "rocksdb"))]
+fn rocksdb_sink(path: &Path) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Err(format!("{:?} is a RocksDB database, which needs a build with the rocksdb feature to write to", path).into())
+}
+
+// open_output asked before overwriting the database already
+#[cfg(feature = "lmdb")]
+fn lmdb_sink(path: &Path, args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Ok(Box::new(LmdbSink::create(path, true, lmdb_store::DEFAULT_MAP_SIZE, args.write_buffer_size)?))
+}
+
+#[cfg(not(feature = "lmdb"))]
+fn lmdb_sink(path: &Path, _args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Err(format!("{:?} is an LMDB database, which needs a build with the lmdb feature to write to", path).into())
+}
+
+// open_output asked before overwriting the database already
+#[cfg(feature = "sqlite")]
+fn sqlite_sink(path: &Path, args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Ok(Box::new(SqliteSink::create(path, true, args.write_buffer_size)?))
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_sink(path: &Path, _args: &FilterArgs) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Err(format!("{:?} is a SQLite database, which needs a build with the sqlite feature to write to", path).into())
+}
+
+#[cfg(feature = "kafka")]
+fn kafka_sink(url: &str) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Ok(Box::new(KafkaSink::connect(url)?))
+}
+
+#[cfg(not(feature = "kafka"))]
+fn kafka_sink(url: &str) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    Err(format!("{} is a Kafka topic, which needs a build with the kafka feature to write to", url).into())
+}
+
+// the dump at `path`, streamed from S3 for s3:// inputs
+fn input_source(path: &Path) -> Result<Box<dyn Source>, Box<dyn std::error::Error>> {
+    match source::s3_url(path) {
+        Some(url) => s3_source(url),
+        None => Ok(Box::new(FileSource::new(path))),
+    }
+}
+
+#[cfg(feature = "s3")]
+fn s3_source(url: &str) -> Result<Box<dyn Source>, Box<dyn std::error::Error>> {
+    Ok(Box::new(S3Source::new(url)?))
+}
+
+#[cfg(not(feature = "s3"))]
+fn s3_source(url: &str) -> Result<Box<dyn Source>, Box<dyn std::error::Error>> {
+    Err(format!("{} is an S3 object, which needs a build with the s3 feature to read from", url).into())
+}
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+fn uses_io_uring(args: &FilterArgs) -> bool {
+    args.io_uring
+}
+
+#[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+fn uses_io_uring(_args: &FilterArgs) -> bool {
+    false
+}
+
+// opens the output for a fresh run, asking before overwriting it
+fn open_output(args: &FilterArgs, context: &Context) -> Result<(Box<dyn Write>, OutputFinish), Box<dyn std::error::Error>> {
+    // a database is written to by its own sink instead
+    if let Some(path) = args.output_file_path.as_deref().and_then(local_database) {
+        if !context.may_overwrite(path, args.force_overwrite)? {
+            return Err(ProcessError::OutputExists(path.to_path_buf()).into());
+        }
+    }
+    if database_output(args).is_some() {
+        return Ok((Box::new(io::sink()), OutputFinish::default()));
+    }
+    if let Some(directory) = &args.blazegraph_chunks {
+        let first = blazegraph::chunk_path(directory, 1);
+        if !context.may_overwrite(&first, args.force_overwrite)? {
+            return Err(ProcessError::OutputExists(first).into());
+        }
+        return Ok((Box::new(io::sink()), OutputFinish::default()));
+    }
+    if let Some(directory) = &args.qlever {
+        let turtle = QleverSink::turtle_path(directory, &qlever_name(directory));
+        if !context.may_overwrite(&turtle, args.force_overwrite)? {
+            return Err(ProcessError::OutputExists(turtle).into());
+        }
+        return Ok((Box::new(io::sink()), OutputFinish::default()));
+    }
+    let force_overwrite = match &args.output_file_path {
+        Some(path) if sink::unix_socket(path).is_none() => context.may_overwrite(path, args.force_overwrite)?,
+        _ => false,
+    };
+
+    #[cfg(all(target_os = "linux", feature = "io-uring"))]
+    let output = match &args.output_file_path {
+        Some(path) if args.io_uring => (sink::open_uring_output(path, force_overwrite, args.write_buffer_size)?, OutputFinish::default()),
+        path => sink::open_finishable_output(path.as_deref(), force_overwrite)?,
+    };
+    #[cfg(not(all(target_os = "linux", feature = "io-uring")))]
+    let output = sink::open_finishable_output(args.output_file_path.as_deref(), force_overwrite)?;
+    Ok(output)
+}
+
+// how much disk to reserve for the output
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PreallocationSize {
+    Bytes(u64),
+    // as much as --estimate expects the run to write, sampling the dump first
+    Estimate,
+}
+
+// the size to preallocate for the output, given as a size, as an estimate written by --estimate --stats-json, or as a
+// previous output to take the size of
+fn parse_preallocation(value: &str) -> Result<PreallocationSize, String> {
+    if value == "estimate" {
+        return Ok(PreallocationSize::Estimate);
+    }
+    let error = match parse_size(value) {
+        Ok(size) => return Ok(PreallocationSize::Bytes(size as u64)),
+        Err(error) => error,
+    };
+    let metadata = match std::fs::metadata(value) {
+        Ok(metadata) if metadata.is_file() => metadata,
+        _ => return Err(format!("{}, and no estimate or previous output {:?} to take the size of either", error, value)),
+    };
+    let estimate = std::fs::read(value).ok()
+        .and_then(|json| serde_json::from_slice::<serde_json::Value>(&json).ok())
+        .and_then(|estimate| estimate.get("output_size").and_then(serde_json::Value::as_u64));
+    Ok(PreallocationSize::Bytes(estimate.unwrap_or(metadata.len())))
+}
+
+// reserves disk for the output to be written to `path`, the run carrying on without if that isn't possible
+fn preallocate(args: &FilterArgs, options: &ProcessOptions, path: &Path) -> Result<Option<Preallocation>, Box<dyn std::error::Error>> {
+    let size = match args.preallocate {
+        Some(PreallocationSize::Bytes(size)) => size,
+        Some(PreallocationSize::Estimate) => {
+            let estimate = estimate_run(args, options)?;
+            info!("Preallocating the estimated {} of output", HumanBytes(estimate.output_size));
+            estimate.output_size
+        }
+        None => return Ok(None),
+    };
+    match sink::preallocate(path, size) {
+        Ok(preallocation) => Ok(Some(preallocation)),
+        Err(error) => {
+            warn!("Could not preallocate the output, writing it without: {}", error);
+            Ok(None)
+        }
+    }
+}
+
+// checks everything that can be checked up front, printing the plan, so mistakes show up before a long run
+fn dry_run(args: &FilterArgs, options: &ProcessOptions, force_overwrite: bool) -> CommandResult {
+    filter::compile(&args.jq_filter)?;
+    println!("Filter: {} ({})", args.jq_filter, if args.pass_through { "pass-through" } else { "writing jq's output" });
+
+    let (input, (dump, size)) = match &args.input_file_path {
+        Some(path) => (format!("{:?}", path), input_source(path)?.open()?),
+        None => (String::from("stdin"), StdinSource.open()?),
+    };
+    // S3 inputs block on the runtime to download, which this thread is one of the workers of
+    let first = tokio::task::block_in_place(|| EntityReader::new(decoder::decoder(dump))
+        .map_err(|error| Exit::error(EXIT_INVALID_INPUT, format!("{} doesn't look like a bzip2 compressed dump: {}", input, error)))?
+        .next()
+        .ok_or_else(|| Exit::error(EXIT_INVALID_INPUT, format!("{} has no entities", input)))?
+        .map_err(|error| Exit::error(EXIT_INVALID_INPUT, format!("Could not read the first entity of {}: {}", input, error))))?;
+    let first = Entity::parse(&first).map_err(|error| Exit::error(EXIT_INVALID_INPUT, format!("Could not parse the first entity of {}: {}", input, error)))?;
+    match size {
+        Some(size) => println!("Input: {} ({} compressed, first entity {})", input, HumanBytes(size), first.id),
+        None => println!("Input: {} (first entity {})", input, first.id),
+    }
+
+    match (&args.output_file_path, args.output_file_path.as_deref().and_then(sink::unix_socket)) {
+        (_, Some(socket)) if !socket.exists() => return Err(format!("Nothing is listening on {:?}", socket).into()),
+        (_, Some(socket)) => println!("Output: Unix domain socket {:?}", socket),
+        (Some(path), None) if sink::redis_url(path).is_some() => println!("Output: Redis server {}", path.display()),
+        (Some(path), None) if sink::mongodb_url(path).is_some() => println!("Output: MongoDB server {}", path.display()),
+        (Some(path), None) if sink::kafka_url(path).is_some() => println!("Output: Kafka topic {}", path.display()),
+        (Some(path), None) if local_database(path).is_some() => {
+            let database = local_database(path).unwrap_or(path);
+            println!("Output: database {:?} ({})", database, check_output(database, force_overwrite)?)
+        }
+        (Some(path), None) => println!("Output: {:?} ({})", path, check_output(path, force_overwrite)?),
+        (None, None) => match &args.blazegraph_chunks {
+            Some(directory) => println!("Output: Turtle chunks of {} entities in {:?}", args.chunk_entities, directory),
+            None => match &args.qlever {
+                Some(directory) => println!("Output: QLever input {:?}", QleverSink::turtle_path(directory, &qlever_name(directory))),
+                None => println!("Output: stdout"),
+            },
+        },
+    }
+    if args.format == OutputFormat::Clickhouse {
+        println!("ClickHouse table, for the rows written:\n{};", clickhouse::create_table("entities"));
+    }
+
+    println!("Threads: {}{}", options.threads, if options.pin_cores { ", pinned to cores" } else { "" });
+    println!("Write buffer: {}", HumanBytes(options.write_buffer_size as u64));
+    if let Some(max_memory) = options.max_memory {
+        println!("Memory limit: {}", HumanBytes(max_memory as u64));
+    }
+    Ok(())
+}
+
+// filters samples of the dump instead of all of it, printing what a full run is expected to make
+fn estimate(args: &FilterArgs, options: &ProcessOptions) -> CommandResult {
+    filter::compile(&args.jq_filter)?;
+    let estimate = estimate_run(args, options)?;
+    println!("Sampled: {} of {} compressed, {} entities, {} with an output", HumanBytes(estimate.sampled_bytes), HumanBytes(estimate.dump_size), estimate.sampled_entities, estimate.sampled_matches);
+    println!("Entities: about {} ({:.0} per compressed MiB)", estimate.entities, estimate.entities_per_mib());
+    println!("Outputs: about {} ({:.2}% of entities)", estimate.matches, estimate.match_rate() * 100.0);
+    println!("Output size: about {}", HumanBytes(estimate.output_size));
+    println!("Runtime: about {} with {} threads", HumanDuration(estimate.runtime), options.threads);
+    if args.stats_json {
+        eprintln!("{}", serde_json::to_string(&estimate)?);
+    }
+    Ok(())
+}
+
+// extrapolates what the run is expected to make from samples of the dump, see --estimate
+fn estimate_run(args: &FilterArgs, options: &ProcessOptions) -> Result<estimate::Estimate, Box<dyn std::error::Error>> {
+    let input = args.input_file_path.as_deref().ok_or("Estimating a run needs its --input, to sample it")?;
+    info!("Filtering {} samples of {} compressed bytes of {:?}", args.estimate_samples, args.estimate_sample_size, input);
+    // the samples are only counted, not checkpointed or reported on as the run would be
+    let options = ProcessOptions { progress: Progress::Hidden, checkpoint: None, metrics: None, error_report: None, ..options.clone() };
+    let counts = StageCounts::default();
+    let stages = Stages::new(args, &options, &counts, None)?;
+    let estimate = estimate::estimate_with(input, args.estimate_samples, args.estimate_sample_size as u64, |range| {
+        let pipeline = stages.apply(Pipeline::builder().source(input).options(ProcessOptions { range: Some(range), ..options.clone() }));
+        if !args.dedupe {
+            return pipeline.sink(io::sink()).build()?.run();
+        }
+        let mut deduped = DedupeSink::new(WriteSink::new(io::sink(), options.write_buffer_size));
+        let mut stats = pipeline.entity_sink(&mut deduped).build()?.run()?;
+        stats.entities_written -= deduped.duplicates() as usize;
+        Ok(stats)
+    })?;
+    Ok(estimate)
+}
+
+// makes sure the output can be created without touching it, describing the space left for it
+fn check_output(path: &Path, force_overwrite: bool) -> Result<String, Box<dyn std::error::Error>> {
+    if path.exists() && !force_overwrite {
+        return Err(ProcessError::OutputExists(path.to_path_buf()).into());
+    }
+    let directory = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    tempfile::tempfile_in(directory)
+        .map_err(|source| ProcessError::CreateOutput { path: path.to_path_buf(), source })?;
+
+    #[cfg(unix)]
+    return Ok(format!("writable, {} free", HumanBytes(wikidump_process::util::available_space(directory)?)));
+    #[cfg(not(unix))]
+    return Ok(String::from("writable"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::commands::exit_code;
+
+    #[test]
+    fn test_verify_output_exit_code() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("output.ndjson");
+        std::fs::write(&path, "{\"id\":\"Q1\"}\n{\"id\":\"Q2\"}\n").unwrap();
+        let context = Context { progress: Progress::Hidden, yes: false };
+        assert!(verify_output(&path, 2, &context).is_ok());
+
+        // a record fewer than was written
+        let error = verify_output(&path, 3, &context).unwrap_err();
+        assert_eq!(exit_code(error.as_ref()), EXIT_VERIFY_FAILED);
+        std::fs::write(&path, "{\"id\":\"Q1\"}\n{\"id\":").unwrap();
+        let error = verify_output(&path, 2, &context).unwrap_err();
+        assert_eq!(exit_code(error.as_ref()), EXIT_VERIFY_FAILED);
+    }
+}
@@ -0,0 +1,49 @@
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::{default_threads, gazetteer, ProcessOptions};
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct GazetteerArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to read labels and aliases from (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the gazetteer to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 'l', long = "languages", help = "Comma separated languages to write labels and aliases in (default is all of them)")]
+    languages: Option<String>,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: GazetteerArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let languages = args.languages.as_deref().unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|language| !language.is_empty())
+        .map(str::to_string)
+        .collect();
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    gazetteer::write_gazetteer(source, languages, options, output)?;
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::io::{self, Write};
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::index::{self, Index};
+use super::CommandResult;
+
+#[derive(Args, Debug)]
+pub struct GetArgs {
+    #[clap(required = true, help = "Ids of the entities to print, e.g. Q42 P31")]
+    ids: Vec<String>,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump the index was built from")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), long = "index", help = "Index built by the index subcommand (default is the input's with .idx appended)")]
+    index_file_path: Option<PathBuf>,
+}
+
+pub fn run(args: GetArgs) -> CommandResult {
+    let index_file_path = args.index_file_path.unwrap_or_else(|| index::index_path(&args.input_file_path));
+    let mut index = Index::open(&index_file_path)?;
+    let mut dump = index.open_dump(&args.input_file_path)?;
+
+    let mut output = io::stdout().lock();
+    let mut missing = Vec::new();
+    for id in &args.ids {
+        match index.lookup(id)? {
+            Some(entry) => writeln!(output, "{}", index::read_entity(&mut dump, &entry)?)?,
+            None => missing.push(id.as_str()),
+        }
+    }
+    output.flush()?;
+    if !missing.is_empty() {
+        return Err(format!("Could not find {}", missing.join(", ")).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,27 @@
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::{index, sink};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct IndexArgs {
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to index")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the index to (default is the input's with .idx appended)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the index if it exists, without asking")]
+    force_overwrite: bool,
+}
+
+pub fn run(args: IndexArgs, context: &Context) -> CommandResult {
+    let output_file_path = args.output_file_path.unwrap_or_else(|| index::index_path(&args.input_file_path));
+    // check before the scan rather than after, which takes as long as decompressing the whole dump
+    let force_overwrite = context.may_overwrite(&output_file_path, args.force_overwrite)?;
+    let output = sink::open_output(Some(&output_file_path), force_overwrite)?;
+
+    let (mut entries, dump_size) = index::build_index(&args.input_file_path, context.progress)?;
+    index::write_index(&mut entries, dump_size, output)?;
+    Ok(())
+}
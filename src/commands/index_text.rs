@@ -0,0 +1,60 @@
+use std::fs;
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, parse_size, text_index, ProcessError, ProcessOptions};
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct IndexTextArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump (or filter output) to index (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Directory to build the tantivy index in")]
+    output_dir: PathBuf,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Delete an index already in the output directory, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 'l', long = "languages", default_value = "en", help = "Comma separated languages to index the labels, aliases and descriptions in, each in its own fields")]
+    languages: String,
+
+    #[clap(long = "writer-memory", default_value = "1G", parse(try_from_str = parse_size), help = "Memory tantivy indexes documents in before writing them out as a segment, e.g. 256M, 4G. At least 15M per indexing thread")]
+    writer_memory: usize,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: IndexTextArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let languages = args.languages.split(',').map(str::trim).filter(|language| !language.is_empty()).map(str::to_string).collect::<Vec<_>>();
+    if languages.is_empty() {
+        return Err("--languages needs at least one language".into());
+    }
+    // an index is a directory of files, replaced as a whole
+    let occupied = fs::read_dir(&args.output_dir).map(|mut entries| entries.next().is_some()).unwrap_or(false);
+    if occupied {
+        if !context.may_overwrite(&args.output_dir, args.force_overwrite)? {
+            return Err(ProcessError::OutputExists(args.output_dir).into());
+        }
+        fs::remove_dir_all(&args.output_dir)?;
+    }
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    let stats = text_index::write_text_index(source, languages, args.writer_memory, options, &args.output_dir)?;
+    info!("Indexed {} of {} entities in {:?}", stats.entities_written, stats.entities_read, args.output_dir);
+    Ok(())
+}
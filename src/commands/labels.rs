@@ -0,0 +1,92 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, labels, ProcessOptions};
+use wikidump_process::labels::LabelOptions;
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum TableFormat {
+    Tsv,
+    Map,
+}
+
+impl FromStr for TableFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "tsv" => Ok(TableFormat::Tsv),
+            "map" => Ok(TableFormat::Map),
+            _ => Err(format!("Invalid table format '{}', expected tsv or map", value)),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct LabelsArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to read labels from (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the table to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 'l', long = "languages", default_value = "en", help = "Comma separated languages to take labels in. A map has the label in the first of them each entity has one in")]
+    languages: String,
+
+    #[clap(long = "aliases", help = "Also write a row for each alias, like another label (tsv only)")]
+    aliases: bool,
+
+    #[clap(long = "descriptions", help = "Add a column with the description in the row's language (tsv only)")]
+    descriptions: bool,
+
+    #[clap(long = "format", default_value = "tsv", possible_values = &["tsv", "map"], help = "tsv writes id<TAB>label rows, with a language column when there are several. map writes a file sorted by id, searched on disk without loading it")]
+    format: TableFormat,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: LabelsArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    if args.format == TableFormat::Map && (args.aliases || args.descriptions) {
+        return Err("--aliases and --descriptions only apply to --format tsv".into());
+    }
+    let label_options = LabelOptions {
+        languages: args.languages.split(',').map(str::trim).filter(|language| !language.is_empty()).map(str::to_string).collect(),
+        aliases: args.aliases,
+        descriptions: args.descriptions,
+    };
+    if label_options.languages.is_empty() {
+        return Err("--languages needs at least one language".into());
+    }
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    match args.format {
+        TableFormat::Tsv => {
+            labels::write_labels(source, label_options, options, output)?;
+        }
+        TableFormat::Map => {
+            let (entities, _) = labels::write_label_map(source, label_options, options, output)?;
+            info!("Wrote the labels of {} entities", entities);
+        }
+    }
+    Ok(())
+}
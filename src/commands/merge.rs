@@ -0,0 +1,99 @@
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, merge, ProcessOptions};
+use wikidump_process::dedupe::DedupeSink;
+use wikidump_process::shard::{self, ParallelShardedSink};
+use wikidump_process::sink::{self, Sink, WriteSink};
+use super::{CommandResult, Context};
+#[cfg(feature = "zstd")]
+use wikidump_process::dictionary::{self, DictionarySink, ZstdSink};
+#[cfg(feature = "zstd")]
+use wikidump_process::ProcessError;
+
+#[derive(Args, Debug)]
+pub struct MergeArgs {
+    #[clap(parse(from_os_str), required = true, help = "Filter outputs or dumps to merge, e.g. the shards of a run")]
+    inputs: Vec<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the merged entities to, one per line (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output files if they exist, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "sorted", help = "The inputs are each sorted by id (see the sort subcommand), merge them into one sorted output rather than one after the other")]
+    sorted: bool,
+
+    #[clap(long = "dedupe", help = "Drop entities whose id was already written, keeping the first")]
+    dedupe: bool,
+
+    #[clap(long = "shards", requires = "output-file-path", help = "Split the output into this many shards by id, written next to --output as e.g. out.0.ndjson")]
+    shards: Option<usize>,
+
+    #[clap(short = 't', long = "threads", requires = "shards", help = "Number of threads writing (and compressing) the shards, each taking several shards if there are more (default is the number of available CPUs)")]
+    threads: Option<usize>,
+
+    #[cfg(feature = "zstd")]
+    #[clap(long = "zstd-dictionary", requires = "shards", help = "Compress the shards with zstd, using a dictionary trained on the first entities and written next to --output as e.g. out.dict. Shards are written as e.g. out.0.ndjson.zst")]
+    zstd_dictionary: bool,
+
+    #[cfg(feature = "zstd")]
+    #[clap(long = "dictionary-samples", requires = "zstd-dictionary", default_value_t = dictionary::DEFAULT_SAMPLE_SIZE, help = "How many entities to train the zstd dictionary on")]
+    dictionary_samples: usize,
+}
+
+pub fn run(args: MergeArgs, context: &Context) -> CommandResult {
+    let buffer_size = ProcessOptions::default().write_buffer_size;
+    let threads = args.threads.unwrap_or_else(default_threads);
+    let mut sink: Box<dyn Sink> = match (args.shards, &args.output_file_path) {
+        (Some(0), _) => return Err("--shards has to be at least 1".into()),
+        #[cfg(feature = "zstd")]
+        (Some(shards), Some(output)) if args.zstd_dictionary => dictionary_sink(output, shards, threads, args.dictionary_samples, args.force_overwrite, context)?,
+        (Some(shards), Some(output)) => {
+            let sinks = (0..shards)
+                .map(|n| Ok(WriteSink::new(create_shard(&shard::shard_path(output, n), args.force_overwrite, context)?, buffer_size)))
+                .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+            Box::new(ParallelShardedSink::new(sinks, threads))
+        }
+        _ => Box::new(WriteSink::new(context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?, buffer_size)),
+    };
+
+    if args.dedupe {
+        let mut sink = DedupeSink::new(sink);
+        let entities = merge::merge(&args.inputs, args.sorted, context.progress, &mut sink)?;
+        info!("Merged {} entities, dropping {} duplicates", entities, sink.duplicates());
+    } else {
+        let entities = merge::merge(&args.inputs, args.sorted, context.progress, sink.as_mut())?;
+        info!("Merged {} entities", entities);
+    }
+    Ok(())
+}
+
+// opens a shard, asking before overwriting it. Shards are written on threads of their own, so they're always files
+fn create_shard(path: &Path, force: bool, context: &Context) -> Result<File, Box<dyn std::error::Error>> {
+    let force = context.may_overwrite(path, force)?;
+    Ok(sink::create_file(path, force)?)
+}
+
+// shards compressed with a dictionary trained on the first `samples` entities, which is written once trained
+#[cfg(feature = "zstd")]
+fn dictionary_sink(output: &Path, shards: usize, threads: usize, samples: usize, force: bool, context: &Context) -> Result<Box<dyn Sink>, Box<dyn std::error::Error>> {
+    use std::io::Write;
+    let dictionary_path = output.with_extension("dict");
+    let mut dictionary_file = context.create_output(Some(&dictionary_path), force)?;
+    let outputs = (0..shards)
+        .map(|n| {
+            let mut path = shard::shard_path(output, n).into_os_string();
+            path.push(".zst");
+            create_shard(Path::new(&path), force, context)
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Box::new(DictionarySink::new(samples, move |trained: &[u8]| {
+        dictionary_file.write_all(trained).and_then(|_| dictionary_file.flush())
+            .map_err(|source| ProcessError::CreateOutput { path: dictionary_path, source })?;
+        let sinks = outputs.into_iter().map(|output| ZstdSink::new(output, trained)).collect::<Result<Vec<_>, _>>()?;
+        Ok(ParallelShardedSink::new(sinks, threads))
+    })))
+}
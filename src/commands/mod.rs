@@ -0,0 +1,265 @@
+/*!
+ * The CLI's subcommands, each one a thin layer over the library
+ */
+
+mod classes;
+mod completions;
+mod convert;
+mod coverage;
+mod dedupe;
+mod delta;
+mod diff;
+mod download;
+mod edges;
+mod filter;
+mod gazetteer;
+mod get;
+mod index;
+#[cfg(feature = "tantivy")]
+mod index_text;
+mod labels;
+mod merge;
+mod plan;
+mod properties;
+mod quality;
+mod redirects;
+mod reference_urls;
+mod serve;
+mod sitelinks;
+mod sort;
+mod stats;
+mod validate;
+mod watch;
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io::{self, BufRead, IsTerminal, Write};
+use std::path::Path;
+use std::sync::Arc;
+use clap::Subcommand;
+use log::info;
+use wikidump_process::{sink, Progress, ProcessError, ProcessOptions};
+use wikidump_process::classes::{find_hierarchy, ClassHierarchy};
+use wikidump_process::source::FileSource;
+
+pub type CommandResult = Result<(), Box<dyn std::error::Error>>;
+
+// exit codes, so scripts can branch on what went wrong. 2 is left to clap for invalid arguments
+
+/// Any failure without a more specific code
+pub const EXIT_FAILED: i32 = 1;
+
+/// The jq filter doesn't compile
+pub const EXIT_FILTER_COMPILE: i32 = 3;
+
+/// The input isn't a readable bzip2 compressed JSON dump, is truncated, or `validate` found problems in it
+pub const EXIT_INVALID_INPUT: i32 = 4;
+
+/// The run finished, but skipped entities which couldn't be filtered (with --continue-on-error)
+pub const EXIT_PARTIAL: i32 = 5;
+
+/// The dump couldn't be downloaded
+pub const EXIT_DOWNLOAD_FAILED: i32 = 6;
+
+/// The output was written, but --verify-output found problems reading it back
+pub const EXIT_VERIFY_FAILED: i32 = 7;
+
+/// Exit code of a run stopped by --max-runtime, the same as timeout(1)'s
+pub const EXIT_TIMED_OUT: i32 = 124;
+
+/// Exit code of a run stopped with Ctrl-C, the same as shells use for SIGINT
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+/// A run which ended without failing as such, but in a way scripts need to tell apart by its exit code
+#[derive(Debug)]
+pub struct Exit {
+    pub code: i32,
+    pub message: String,
+}
+
+impl Exit {
+    /// An error reported like any other, but with its own exit code
+    pub fn error(code: i32, error: impl fmt::Display) -> Self {
+        Exit { code, message: format!("Error: {}", error) }
+    }
+}
+
+impl fmt::Display for Exit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for Exit {}
+
+/// The code to exit with after a command failed with `error`
+pub fn exit_code(error: &(dyn std::error::Error + 'static)) -> i32 {
+    if let Some(exit) = error.downcast_ref::<Exit>() {
+        return exit.code;
+    }
+    match error.downcast_ref::<ProcessError>() {
+        Some(ProcessError::FilterCompile { .. }) => EXIT_FILTER_COMPILE,
+        Some(ProcessError::Read(_) | ProcessError::InvalidUtf8 { .. } | ProcessError::Truncated { .. }) => EXIT_INVALID_INPUT,
+        _ => EXIT_FAILED,
+    }
+}
+
+/// Options shared by all subcommands
+pub struct Context {
+    pub progress: Progress,
+    /// Answer yes to any question instead of asking
+    pub yes: bool,
+}
+
+impl Context {
+    // whether `path` may be overwritten: when forced or answered with --yes, otherwise by asking
+    // if there is someone at a terminal to ask
+    fn may_overwrite(&self, path: &Path, force: bool) -> io::Result<bool> {
+        if force || self.yes || !path.exists() {
+            return Ok(true);
+        }
+        if !io::stdin().is_terminal() || !io::stderr().is_terminal() {
+            return Ok(false);
+        }
+        eprint!("{:?} already exists, overwrite it? [y/N] ", path);
+        io::stderr().flush()?;
+        let mut answer = String::new();
+        io::stdin().lock().read_line(&mut answer)?;
+        Ok(matches!(answer.trim().to_ascii_lowercase().as_str(), "y" | "yes"))
+    }
+
+    // opens `path` (or stdout without one) for a report or other output, asking before overwriting it. Outputs
+    // named .gz or .bz2 are compressed
+    fn create_output(&self, path: Option<&Path>, force: bool) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
+        let force = match path {
+            Some(path) => self.may_overwrite(path, force)?,
+            None => false,
+        };
+        Ok(sink::open_output(path, force)?)
+    }
+
+    // opens `path` as create_output does, for an output compressed by the command itself
+    fn create_uncompressed_output(&self, path: Option<&Path>, force: bool) -> Result<Box<dyn Write>, Box<dyn std::error::Error>> {
+        let force = match path {
+            Some(path) => self.may_overwrite(path, force)?,
+            None => false,
+        };
+        Ok(sink::open_uncompressed_output(path, force)?)
+    }
+}
+
+// the classes given with --instance-of and all of their subclasses, from the hierarchy given or found in a first pass over `input`
+fn instance_classes(instance_of: &str, class_hierarchy: Option<&Path>, input: Option<&Path>, options: &ProcessOptions) -> Result<Arc<HashSet<String>>, Box<dyn std::error::Error>> {
+    let seeds = instance_of.split(',').map(str::trim).filter(|class| !class.is_empty()).map(str::to_string).collect::<Vec<_>>();
+    if seeds.is_empty() {
+        return Err("--instance-of needs at least one class".into());
+    }
+    let hierarchy = match (class_hierarchy, input) {
+        (Some(path), _) => ClassHierarchy::load(path)?,
+        (None, Some(input)) => {
+            info!("Finding the subclasses of {} in a first pass over the input", seeds.join(", "));
+            // the subclasses are found in the whole dump, even when only a range of it is filtered
+            let first_pass = ProcessOptions { checkpoint: None, resume: None, metrics: None, range: None, ..options.clone() };
+            find_hierarchy(FileSource::new(input), first_pass)?.0
+        }
+        (None, None) => return Err("--instance-of can't read stdin twice, give the hierarchy written by the classes subcommand with --class-hierarchy".into()),
+    };
+    let classes = hierarchy.subclasses_of(&seeds);
+    info!("Keeping instances of {} classes", classes.len());
+    Ok(Arc::new(classes))
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Apply a delta written by the delta subcommand to the sorted output it was made from
+    ApplyDelta(delta::ApplyDeltaArgs),
+    /// Write the subclass of (P279) hierarchy of a dump, or every subclass of some classes
+    Classes(classes::ClassesArgs),
+    /// Re-encode a dump or filter output as a dump, ndjson or MessagePack, compressed or not
+    Convert(convert::ConvertArgs),
+    /// Count the entities with a label, description and aliases in each language, as CSV
+    Coverage(coverage::CoverageArgs),
+    /// Drop entities found more than once in a dump or filter output, keeping the first or last
+    Dedupe(dedupe::DedupeArgs),
+    /// Write a compact patch of the entities added, updated and deleted between two sorted filter outputs
+    Delta(delta::DeltaArgs),
+    /// Compare two dumps or filter outputs, listing the entities added, removed and changed
+    Diff(diff::DiffArgs),
+    /// Download a wikidata dump json file
+    Download(download::DownloadArgs),
+    /// Write a graph edge list of a dump's item-valued statements, without going through jq
+    Edges(edges::EdgesArgs),
+    /// Filter the entities of a dump with jq
+    Filter(Box<filter::FilterArgs>),
+    /// Write every label and alias of a dump's entities with their ids and types, as a dictionary for entity recognizers
+    Gazetteer(gazetteer::GazetteerArgs),
+    /// Print single entities of a dump by id, using an index built by the index subcommand
+    Get(get::GetArgs),
+    /// Build an index of where each entity is in a dump, for reading single entities without a full scan
+    Index(index::IndexArgs),
+    /// Build a tantivy full-text index of the labels, aliases and descriptions of a dump's entities
+    #[cfg(feature = "tantivy")]
+    IndexText(index_text::IndexTextArgs),
+    /// Write a label lookup table of a dump as TSV or a label map, without going through jq
+    Labels(labels::LabelsArgs),
+    /// Merge filter outputs written in parts into one output, or a different number of shards
+    Merge(merge::MergeArgs),
+    /// Split a dump into ranges of bzip2 streams, written to a manifest for filtering them on several machines
+    Plan(plan::PlanArgs),
+    /// Write the datatype, labels and constraints of every property of a dump
+    Properties(properties::PropertiesArgs),
+    /// Report entities missing labels or P31, with deprecated-only statements or breaking constraints, as CSVs of ids
+    Quality(quality::QualityArgs),
+    /// List the redirects of a dump left by merged entities, and the ids they resolve to
+    Redirects(redirects::RedirectsArgs),
+    /// Write the URLs cited by the references of a dump's statements (P854), and how often each domain is cited
+    ReferenceUrls(reference_urls::ReferenceUrlsArgs),
+    /// Serve entities, labels and searches from a dump's index, label map and text index over HTTP
+    Serve(serve::ServeArgs),
+    /// Map the wiki pages of a dump's entities, e.g. Wikipedia articles, to their ids
+    Sitelinks(sitelinks::SitelinksArgs),
+    /// Sort a dump or filter output by entity id, also when it doesn't fit in memory
+    Sort(sort::SortArgs),
+    /// Profile a dump: entity types, property usage, label languages, sitelinks and entity sizes
+    Stats(stats::StatsArgs),
+    /// Check a dump or filter output for truncation, invalid entities and duplicate ids
+    Validate(validate::ValidateArgs),
+    /// Poll for new dumps, and download, verify and filter each one once it's complete
+    Watch(watch::WatchArgs),
+    /// Print shell completions, e.g. `wikidump-process completions bash > /etc/bash_completion.d/wikidump-process`
+    Completions(completions::CompletionsArgs),
+}
+
+pub async fn run(command: Command, context: &Context) -> CommandResult {
+    match command {
+        Command::ApplyDelta(args) => delta::apply(args, context),
+        Command::Classes(args) => classes::run(args, context),
+        Command::Convert(args) => convert::run(args, context),
+        Command::Coverage(args) => coverage::run(args, context),
+        Command::Dedupe(args) => dedupe::run(args, context),
+        Command::Delta(args) => delta::run(args, context),
+        Command::Diff(args) => diff::run(args, context),
+        Command::Download(args) => download::run(args, context).await,
+        Command::Edges(args) => edges::run(args, context),
+        Command::Filter(args) => filter::run(*args, context),
+        Command::Gazetteer(args) => gazetteer::run(args, context),
+        Command::Get(args) => get::run(args),
+        Command::Index(args) => index::run(args, context),
+        #[cfg(feature = "tantivy")]
+        Command::IndexText(args) => index_text::run(args, context),
+        Command::Labels(args) => labels::run(args, context),
+        Command::Merge(args) => merge::run(args, context),
+        Command::Plan(args) => plan::run(args, context),
+        Command::Properties(args) => properties::run(args, context),
+        Command::Quality(args) => quality::run(args, context),
+        Command::Redirects(args) => redirects::run(args, context),
+        Command::ReferenceUrls(args) => reference_urls::run(args, context),
+        Command::Serve(args) => serve::run(args),
+        Command::Sitelinks(args) => sitelinks::run(args, context),
+        Command::Sort(args) => sort::run(args, context),
+        Command::Stats(args) => stats::run(args, context),
+        Command::Validate(args) => validate::run(args, context),
+        Command::Watch(args) => watch::run(args, context).await,
+        Command::Completions(args) => completions::run(args),
+    }
+}
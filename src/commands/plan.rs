@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+use clap::Args;
+use indicatif::HumanBytes;
+use log::info;
+use wikidump_process::{parse_size, plan, ProcessError};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct PlanArgs {
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to plan the filtering of")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the manifest to, for filter --range-from-manifest (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the manifest if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "range-size", parse(try_from_str = parse_size), help = "Compressed size of each range, e.g. 512M, 4G. Ranges end with a whole bzip2 stream, so are a little bigger (default is 1G)")]
+    range_size: Option<usize>,
+}
+
+pub fn run(args: PlanArgs, context: &Context) -> CommandResult {
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let range_size = args.range_size.map_or(plan::DEFAULT_RANGE_SIZE, |size| size as u64);
+    let manifest = plan::plan(&args.input_file_path, range_size, context.progress)?;
+    manifest.write(output).map_err(ProcessError::Write)?;
+    info!("Planned {} ranges of about {} from {} bzip2 streams, with about {} entities", manifest.ranges.len(), HumanBytes(range_size), manifest.streams, manifest.estimated_entities);
+    Ok(())
+}
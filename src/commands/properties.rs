@@ -0,0 +1,53 @@
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::{default_threads, properties, ProcessOptions};
+use wikidump_process::properties::PropertyFormat;
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct PropertiesArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip properties which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to read properties from (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the table to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 'l', long = "languages", help = "Comma separated languages to keep labels in (default is all of them). The tsv label is in the first of them")]
+    languages: Option<String>,
+
+    #[clap(long = "format", default_value = "ndjson", possible_values = &["ndjson", "tsv"], help = "ndjson writes an object per property, tsv id<TAB>datatype<TAB>label<TAB>constraint types rows")]
+    format: PropertyFormat,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: PropertiesArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let languages = args.languages.as_deref().unwrap_or("")
+        .split(',')
+        .map(str::trim)
+        .filter(|language| !language.is_empty())
+        .map(str::to_string)
+        .collect();
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    properties::write_properties(source, languages, args.format, options, output)?;
+    Ok(())
+}
@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, quality, ProcessError, ProcessOptions};
+use wikidump_process::quality::{Check, QualityOptions, QualityReport};
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct QualityArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to check (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output-dir", default_value = ".", help = "Directory to write a <check>.csv file of offending ids to for each check")]
+    output_dir: PathBuf,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the files of an earlier report, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 'l', long = "languages", default_value = "en", help = "Comma separated languages items and properties should have a label in")]
+    languages: String,
+
+    #[clap(long = "checks", help = "Comma separated checks to run, of missing-label, missing-p31, deprecated-only, single-value and self-reference (default is all of them, single-value only with --constraints)")]
+    checks: Option<String>,
+
+    #[clap(parse(from_os_str), long = "constraints", help = "Property table written by properties as ndjson, to read single-value constraints from")]
+    constraints_path: Option<PathBuf>,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: QualityArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let languages = args.languages.split(',').map(str::trim).filter(|language| !language.is_empty()).map(str::to_string).collect();
+    let checks = match &args.checks {
+        Some(checks) => checks.split(',').map(str::trim).filter(|check| !check.is_empty()).map(str::parse).collect::<Result<Vec<Check>, _>>()?,
+        None => Check::ALL.into_iter().filter(|check| *check != Check::SingleValue || args.constraints_path.is_some()).collect(),
+    };
+    if checks.is_empty() {
+        return Err("--checks needs at least one check".into());
+    }
+    let single_value = match &args.constraints_path {
+        Some(path) => quality::single_value_properties(path)?,
+        None if checks.contains(&Check::SingleValue) => return Err("The single-value check needs a property table, give one with --constraints".into()),
+        None => HashSet::new(),
+    };
+
+    // each check has its own file, any of which may be left from an earlier report
+    for check in &checks {
+        let path = check.path(&args.output_dir);
+        if !context.may_overwrite(&path, args.force_overwrite)? {
+            return Err(ProcessError::OutputExists(path).into());
+        }
+    }
+    let mut report = QualityReport::create(&args.output_dir, &checks, true)?;
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    let quality = QualityOptions { languages, checks, single_value };
+    let stats = quality::write_quality_report(source, quality, options, &mut report)?;
+    for (check, count) in report.counts() {
+        info!("{} rows for {} of {} entities", count, check.name(), stats.entities_read);
+    }
+    Ok(())
+}
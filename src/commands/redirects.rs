@@ -0,0 +1,45 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, redirects, ProcessOptions};
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct RedirectsArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip redirects which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to find redirects in (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the from<TAB>to mapping to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: RedirectsArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let mut output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    let (redirects, _) = redirects::find_redirects(source, options)?;
+    info!("Found {} redirects", redirects.len());
+    redirects.write_tsv(&mut output)?;
+    output.flush()?;
+    Ok(())
+}
@@ -0,0 +1,54 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, references, ProcessError, ProcessOptions};
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct ReferenceUrlsArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to harvest reference URLs from (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the id<TAB>url rows to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), long = "domains", help = "Also write how often each domain is cited to this file, as a domain,references,entities CSV, most cited first")]
+    domains_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output files if they exist, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: ReferenceUrlsArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let domains_output = match &args.domains_file_path {
+        Some(path) => Some(context.create_output(Some(path), args.force_overwrite)?),
+        None => None,
+    };
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    let (domains, _) = references::write_reference_urls(source, options, output)?;
+    if let Some(mut output) = domains_output {
+        info!("Reference URLs cite {} domains", domains.domains.len());
+        domains.write_csv(&mut output).map_err(ProcessError::Write)?;
+        output.flush().map_err(ProcessError::Write)?;
+    }
+    Ok(())
+}
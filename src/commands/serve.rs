@@ -0,0 +1,90 @@
+use std::net::TcpListener;
+use std::path::PathBuf;
+use std::sync::Arc;
+use clap::Args;
+use log::info;
+use wikidump_process::index;
+use wikidump_process::serve::{self, Service};
+use super::CommandResult;
+
+#[derive(Args, Debug)]
+pub struct ServeArgs {
+    #[clap(long = "listen", default_value = "127.0.0.1:8080", help = "Address to listen on, e.g. 0.0.0.0:8080 to answer requests from other hosts")]
+    listen: String,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to serve entities from, at /entity/<id>. Needs an index built by the index subcommand")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), long = "index", requires = "input-file-path", help = "Index of the dump (default is the input's with .idx appended)")]
+    index_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), long = "labels", help = "Label map written by labels --format map to serve labels from, at /label/<id>")]
+    label_map_path: Option<PathBuf>,
+
+    #[cfg(feature = "rocksdb")]
+    #[clap(parse(from_os_str), long = "rocksdb", conflicts_with = "input-file-path", help = "RocksDB database written by filter --output rocksdb://<path> to serve entities from instead of a dump, at /entity/<id>")]
+    rocksdb_path: Option<PathBuf>,
+
+    #[cfg(feature = "lmdb")]
+    #[clap(parse(from_os_str), long = "lmdb", conflicts_with = "input-file-path", help = "LMDB database written by filter --output lmdb://<path> to serve entities from instead of a dump, at /entity/<id>")]
+    lmdb_path: Option<PathBuf>,
+
+    #[cfg(feature = "sqlite")]
+    #[clap(parse(from_os_str), long = "sqlite", conflicts_with = "input-file-path", help = "SQLite database written by filter --output sqlite://<path> to serve entities from instead of a dump, at /entity/<id>")]
+    sqlite_path: Option<PathBuf>,
+
+    #[clap(long = "workers", default_value = "16", help = "Connections answered at the same time, any more waiting for one of them to be done")]
+    workers: usize,
+
+    #[cfg(feature = "tantivy")]
+    #[clap(parse(from_os_str), long = "text-index", help = "Text index built by the index-text subcommand to serve searches of, at /search?q=<query>")]
+    text_index_path: Option<PathBuf>,
+}
+
+pub fn run(args: ServeArgs) -> CommandResult {
+    let mut service = Service::default();
+    let mut served = false;
+    // entities come from a single one of the dump and the databases
+    let mut entities = 0;
+    if let Some(input_file_path) = &args.input_file_path {
+        let index_file_path = args.index_file_path.clone().unwrap_or_else(|| index::index_path(input_file_path));
+        service = service.with_dump(input_file_path, &index_file_path)?;
+        entities += 1;
+    }
+    #[cfg(feature = "rocksdb")]
+    if let Some(rocksdb_path) = &args.rocksdb_path {
+        service = service.with_rocksdb(rocksdb_path)?;
+        entities += 1;
+    }
+    #[cfg(feature = "lmdb")]
+    if let Some(lmdb_path) = &args.lmdb_path {
+        service = service.with_lmdb(lmdb_path)?;
+        entities += 1;
+    }
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_path) = &args.sqlite_path {
+        service = service.with_sqlite(sqlite_path)?;
+        entities += 1;
+    }
+    if entities > 1 {
+        return Err("Entities are served from only one of --input, --rocksdb, --lmdb and --sqlite".into());
+    }
+    served |= entities > 0;
+    if let Some(label_map_path) = &args.label_map_path {
+        service = service.with_labels(label_map_path)?;
+        served = true;
+    }
+    #[cfg(feature = "tantivy")]
+    if let Some(text_index_path) = &args.text_index_path {
+        service = service.with_text_index(text_index_path)?;
+        served = true;
+    }
+    if !served {
+        return Err("Nothing to serve, give a dump with --input (or a database with --rocksdb, --lmdb or --sqlite), a label map with --labels or a text index with --text-index".into());
+    }
+
+    let listener = TcpListener::bind(&args.listen)?;
+    info!("Serving on http://{}/", listener.local_addr()?);
+    serve::serve(listener, Arc::new(service), args.workers)?;
+    Ok(())
+}
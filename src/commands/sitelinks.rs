@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::{default_threads, sitelinks, ProcessOptions};
+use wikidump_process::sitelinks::SitelinkOptions;
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct SitelinksArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to read sitelinks from (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the mapping to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(short = 's', long = "sites", help = "Comma separated sites to map, e.g. enwiki,dewiki (default is all of them). Unless there is exactly one, rows start with the site")]
+    sites: Option<String>,
+
+    #[clap(long = "underscores", help = "Write titles with underscores instead of spaces, as in page URLs")]
+    underscores: bool,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: SitelinksArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let sitelink_options = SitelinkOptions {
+        sites: args.sites.as_deref().unwrap_or("")
+            .split(',')
+            .map(str::trim)
+            .filter(|site| !site.is_empty())
+            .map(str::to_string)
+            .collect(),
+        underscores: args.underscores,
+    };
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    sitelinks::write_sitelinks(source, sitelink_options, options, output)?;
+    Ok(())
+}
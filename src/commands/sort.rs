@@ -0,0 +1,30 @@
+use std::path::PathBuf;
+use clap::Args;
+use log::info;
+use wikidump_process::{parse_size, sort};
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct SortArgs {
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "Dump or filter output to sort (bzip2 compressed dumps, uncompressed dumps and ndjson are all read)")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the sorted entities to, one per line (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "chunk-size", default_value = "1G", parse(try_from_str = parse_size), help = "Amount of entities to sort in memory at once, e.g. 512M, 4G. Memory use is a few times this")]
+    chunk_size: usize,
+
+    #[clap(parse(from_os_str), long = "temp-dir", help = "Directory for the sorted chunks, which take as much space as the uncompressed input (default is the system's temporary directory)")]
+    temp_dir: Option<PathBuf>,
+}
+
+pub fn run(args: SortArgs, context: &Context) -> CommandResult {
+    let output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let stats = sort::sort(&args.input_file_path, output, args.chunk_size, args.temp_dir.as_deref(), context.progress)?;
+    info!("Sorted {} entities in {} chunks", stats.entities, stats.chunks.max(1));
+    Ok(())
+}
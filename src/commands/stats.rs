@@ -0,0 +1,89 @@
+use std::io::Write;
+use std::path::PathBuf;
+use std::str::FromStr;
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, profile, ProcessError, ProcessOptions};
+use wikidump_process::profile::ProfileOptions;
+use wikidump_process::source::{FileSource, Source, StdinSource};
+use super::{CommandResult, Context};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ReportFormat {
+    Json,
+    Csv,
+}
+
+impl FromStr for ReportFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "json" => Ok(ReportFormat::Json),
+            "csv" => Ok(ReportFormat::Csv),
+            _ => Err(format!("Invalid report format '{}', expected json or csv", value)),
+        }
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct StatsArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Skip entities which can't be parsed rather than bailing")]
+    continue_on_error: bool,
+
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "bzip2 compressed wikidata dump to profile (default is stdin)")]
+    input_file_path: Option<PathBuf>,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the report to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "format", default_value = "json", possible_values = &["json", "csv"], help = "Format of the report. csv has one section,key,value row per count")]
+    format: ReportFormat,
+
+    #[clap(parse(from_os_str), long = "cooccurrence", help = "Also write how many entities each pair of properties is used together on to this file, as sparse CSV with a property,other_property,entities row per pair used together at least once")]
+    cooccurrence: Option<PathBuf>,
+
+    #[clap(long = "top", help = "Also list this many entities, e.g. 20, with the most statements, sitelinks and references, and the largest ones by the size of their JSON, to show how skewed the dump is and pick buffer sizes which fit its biggest entities")]
+    top: Option<usize>,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for parsing (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub fn run(args: StatsArgs, context: &Context) -> CommandResult {
+    let options = ProcessOptions {
+        continue_on_error: args.continue_on_error,
+        threads: args.threads.unwrap_or_else(default_threads),
+        progress: context.progress,
+        ..ProcessOptions::default()
+    };
+    let mut output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let cooccurrence_output = match &args.cooccurrence {
+        Some(path) => Some(context.create_output(Some(path), args.force_overwrite)?),
+        None => None,
+    };
+
+    let source: Box<dyn Source> = match args.input_file_path {
+        Some(path) => Box::new(FileSource::new(path)),
+        None => Box::new(StdinSource),
+    };
+    let profiling = ProfileOptions { cooccurrence: args.cooccurrence.is_some(), top: args.top };
+    let (profile, _) = profile::profile_with(source, profiling, options)?;
+    match args.format {
+        ReportFormat::Json => {
+            serde_json::to_writer_pretty(&mut output, &profile)?;
+            writeln!(output)?;
+        }
+        ReportFormat::Csv => profile.write_csv(&mut output)?,
+    }
+    output.flush()?;
+    if let (Some(cooccurrence), Some(mut output)) = (&profile.cooccurrence, cooccurrence_output) {
+        info!("{} pairs of properties are used together", cooccurrence.len());
+        cooccurrence.write_csv(&mut output).map_err(ProcessError::Write)?;
+        output.flush().map_err(ProcessError::Write)?;
+    }
+    Ok(())
+}
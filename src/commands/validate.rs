@@ -0,0 +1,42 @@
+use std::io::Write;
+use std::path::PathBuf;
+use clap::Args;
+use wikidump_process::{validate, ProcessError};
+use super::{CommandResult, Context, Exit, EXIT_INVALID_INPUT};
+
+#[derive(Args, Debug)]
+pub struct ValidateArgs {
+    #[clap(parse(from_os_str), short = 'i', long = "input", help = "Dump or filter output to check (bzip2 compressed dumps, uncompressed dumps and ndjson are all read)")]
+    input_file_path: PathBuf,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to write the problems found to (default is stdout)")]
+    output_file_path: Option<PathBuf>,
+
+    #[clap(short = 'f', long = "force-overwrite-output", alias = "force", help = "Overwrite the output file if it exists, without asking")]
+    force_overwrite: bool,
+
+    #[clap(long = "json", help = "Write each problem as a JSON object on its own line")]
+    json: bool,
+
+    #[clap(long = "no-schema", help = "Only check that entities are valid JSON, for outputs of filters which don't keep whole entities")]
+    no_schema: bool,
+}
+
+pub fn run(args: ValidateArgs, context: &Context) -> CommandResult {
+    let mut output = context.create_output(args.output_file_path.as_deref(), args.force_overwrite)?;
+    let stats = validate::validate(&args.input_file_path, !args.no_schema, context.progress, |problem| {
+        if args.json {
+            serde_json::to_writer(&mut output, &problem).map_err(|error| ProcessError::Write(error.into()))?;
+            writeln!(output)
+        } else {
+            writeln!(output, "line {}: {}{}: {}", problem.line, problem.id.map(|id| id + ": ").unwrap_or_default(), problem.kind, problem.message)
+        }
+        .map_err(ProcessError::Write)
+    })?;
+    output.flush()?;
+    if stats.problems > 0 {
+        let message = format!("Found {} problems in {} entities of {:?}", stats.problems, stats.entities, args.input_file_path);
+        return Err(Exit::error(EXIT_INVALID_INPUT, message).into());
+    }
+    Ok(())
+}
@@ -0,0 +1,63 @@
+use std::path::PathBuf;
+use std::time::Duration;
+use clap::Args;
+use log::info;
+use wikidump_process::{default_threads, parse_duration, watch, ProcessOptions};
+use wikidump_process::watch::WatchOptions;
+use super::{CommandResult, Context};
+
+#[derive(Args, Debug)]
+pub struct WatchArgs {
+    #[clap(short = 'c', long = "continue-on-error", help = "Don't bail on error while filtering")]
+    continue_on_error: bool,
+
+    #[clap(short = 'j', long = "jq-filter", default_value = ".", help = "jq filter applied to each entity of every new dump")]
+    jq_filter: String,
+
+    #[clap(parse(from_os_str), short = 'o', long = "output-dir", default_value = ".", help = "Directory to write outputs to, as wikidata-<date>.ndjson")]
+    output_dir: PathBuf,
+
+    #[clap(parse(from_os_str), long = "download-dir", help = "Directory to download dumps to (default is the output directory)")]
+    download_dir: Option<PathBuf>,
+
+    #[clap(long = "keep", default_value = "2", help = "Number of outputs to keep, deleting older ones once a new one is written")]
+    keep: usize,
+
+    #[clap(long = "keep-dumps", help = "Keep dumps once they've been filtered, rather than deleting them")]
+    keep_dumps: bool,
+
+    #[clap(long = "interval", default_value = "6h", parse(try_from_str = parse_duration), help = "How often to check for a new dump, e.g. 30m, 6h")]
+    interval: Duration,
+
+    #[clap(long = "once", help = "Check once and exit, e.g. when run from cron")]
+    once: bool,
+
+    #[clap(short = 't', long = "threads", help = "Number of threads used for filtering (default is the number of available CPUs)")]
+    threads: Option<usize>,
+}
+
+pub async fn run(args: WatchArgs, context: &Context) -> CommandResult {
+    let options = WatchOptions {
+        jq_filter: args.jq_filter,
+        download_dir: args.download_dir.unwrap_or_else(|| args.output_dir.clone()),
+        output_dir: args.output_dir,
+        keep: args.keep.max(1),
+        keep_dumps: args.keep_dumps,
+        process: ProcessOptions {
+            continue_on_error: args.continue_on_error,
+            threads: args.threads.unwrap_or_else(default_threads),
+            progress: context.progress,
+            ..ProcessOptions::default()
+        },
+    };
+    if !args.once {
+        return watch::watch(&options, args.interval).await;
+    }
+    std::fs::create_dir_all(&options.output_dir)?;
+    std::fs::create_dir_all(&options.download_dir)?;
+    match watch::check(&options).await? {
+        Some(output) => info!("Wrote {:?}", output),
+        None => info!("No new dump"),
+    }
+    Ok(())
+}
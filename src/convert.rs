@@ -0,0 +1,241 @@
+/*!
+ * Re-encoding dumps and outputs from one representation to another without
+ * filtering them: a dump (a JSON array with one entity per line), one entity
+ * per line, or MessagePack maps one after the other, each either uncompressed
+ * or compressed with bzip2 or gzip. They can also be written (but not read) as
+ * a response of the Wikibase API's `wbgetentities` action, for clients of the
+ * API to read slices of a dump with, or as a Parquet file of simplified
+ * entities (with the `parquet` feature, see `parquet_sink`).
+ *
+ * Inputs are told apart by their content, see `reader::EntityFile`, so only
+ * what to convert them to has to be given.
+ */
+
+use std::ffi::OsStr;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::str::FromStr;
+use serde_json::Value;
+use crate::decoder::BUFFER_LENGTH;
+use crate::error::{ProcessError, Result};
+use crate::progress::{Progress, Reporter};
+use crate::reader::EntityFile;
+use crate::sink::{CompressedWriter, Compression};
+#[cfg(feature = "parquet")]
+use crate::parquet_sink::{self, ParquetSink};
+#[cfg(feature = "parquet")]
+use crate::sink::Sink;
+use crate::splitter::{self, DUMP_END, DUMP_START, ENTITY_SEPARATOR};
+
+// what wraps the entities of a wbgetentities response, which are keyed by id
+const WBGETENTITIES_START: &str = "{\"entities\":{\n";
+const WBGETENTITIES_END: &str = "\n},\"success\":1}\n";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// A JSON array with one entity per line, like the dumps Wikimedia publishes
+    Dump,
+    /// One entity per line, like `filter` writes
+    Ndjson,
+    /// MessagePack maps one after the other
+    Msgpack,
+    /// `{"entities": {"Q42": {...}, ...}, "success": 1}`, with one entity per line
+    Wbgetentities,
+    /// Simplified entities as rows of a Parquet file, which compresses them itself
+    Parquet,
+}
+
+impl Format {
+    /// The format a file is expected to have from its extension, ignoring any compression's, e.g. a dump for
+    /// `out.json.bz2`
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let path = match Compression::from_path(path) {
+            Compression::None => path,
+            _ => Path::new(path.file_stem()?),
+        };
+        match path.extension().and_then(OsStr::to_str) {
+            Some("json") => Some(Format::Dump),
+            Some("ndjson" | "jsonl") => Some(Format::Ndjson),
+            Some("msgpack" | "mpk") => Some(Format::Msgpack),
+            Some("parquet") => Some(Format::Parquet),
+            _ => None,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "dump" => Ok(Format::Dump),
+            "ndjson" => Ok(Format::Ndjson),
+            "msgpack" => Ok(Format::Msgpack),
+            "wbgetentities" => Ok(Format::Wbgetentities),
+            "parquet" => Ok(Format::Parquet),
+            _ => Err(format!("Invalid format '{}', expected dump, ndjson, msgpack, wbgetentities or parquet", value)),
+        }
+    }
+}
+
+/// Writes the entities of the file at `input` to `output` as `format`, compressed with `compression`, returning
+/// how many there were
+pub fn convert(input: &Path, output: impl Write, format: Format, compression: Compression, progress: Progress) -> Result<u64> {
+    let mut entities = EntityFile::open(input)?;
+    let progress = Reporter::new(progress, Some(entities.size()), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+    progress.set_draw_rate(1);
+    if format == Format::Parquet {
+        if compression != Compression::None {
+            return Err(ProcessError::Parquet(String::from("Parquet compresses its row groups itself, so can't be compressed as a whole")));
+        }
+        let converted = convert_parquet(&mut entities, output, &progress)?;
+        progress.finish(format!("Converted {} entities", converted));
+        return Ok(converted as u64);
+    }
+    let mut output = BufWriter::with_capacity(BUFFER_LENGTH, CompressedWriter::new(output, compression));
+    match format {
+        Format::Dump => output.write_all(DUMP_START.as_bytes()).map_err(ProcessError::Write)?,
+        Format::Wbgetentities => output.write_all(WBGETENTITIES_START.as_bytes()).map_err(ProcessError::Write)?,
+        _ => {}
+    }
+    let mut converted = 0;
+    while let Some(entity) = entities.next() {
+        let entity = entity.map_err(ProcessError::Read)?;
+        match format {
+            Format::Dump => {
+                if converted > 0 {
+                    output.write_all(ENTITY_SEPARATOR.as_bytes()).map_err(ProcessError::Write)?;
+                }
+                output.write_all(entity.as_bytes()).map_err(ProcessError::Write)?;
+            }
+            Format::Ndjson => {
+                output.write_all(entity.as_bytes()).map_err(ProcessError::Write)?;
+                output.write_all(b"\n").map_err(ProcessError::Write)?;
+            }
+            Format::Msgpack => {
+                let value: Value = serde_json::from_str(&entity).map_err(|error| {
+                    let id = splitter::entity_id(&entity).unwrap_or("(unknown id)");
+                    ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, format!("entity {} is not valid JSON: {}", id, error)))
+                })?;
+                rmp_serde::encode::write(&mut output, &value)
+                    .map_err(|error| ProcessError::Write(io::Error::other(error)))?;
+            }
+            Format::Wbgetentities => {
+                let id = splitter::entity_id(&entity).ok_or_else(|| {
+                    ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, format!("entity {} has no id to key it by", converted + 1)))
+                })?;
+                if converted > 0 {
+                    output.write_all(b",\n").map_err(ProcessError::Write)?;
+                }
+                write!(output, "{}:{}", Value::from(id), entity).map_err(ProcessError::Write)?;
+            }
+            Format::Parquet => unreachable!("converted by convert_parquet"),
+        }
+        converted += 1;
+        progress.set_position(entities.position());
+        progress.set_entities(converted, converted);
+    }
+    match format {
+        Format::Dump => output.write_all(DUMP_END.as_bytes()).map_err(ProcessError::Write)?,
+        Format::Wbgetentities => output.write_all(WBGETENTITIES_END.as_bytes()).map_err(ProcessError::Write)?,
+        _ => {}
+    }
+    output.into_inner()
+        .map_err(|error| ProcessError::Write(error.into_error()))?
+        .finish()
+        .map_err(ProcessError::Write)?;
+    progress.finish(format!("Converted {} entities", converted));
+    Ok(converted as u64)
+}
+
+// writes the entities left in `entities` to `output` as a Parquet file, returning how many there were
+#[cfg(feature = "parquet")]
+fn convert_parquet(entities: &mut EntityFile, output: impl Write, progress: &Reporter) -> Result<usize> {
+    let mut sink = ParquetSink::new(output, parquet_sink::DEFAULT_ROW_GROUP_SIZE)?;
+    let mut converted = 0;
+    while let Some(entity) = entities.next() {
+        sink.write_entity(&entity.map_err(ProcessError::Read)?)?;
+        converted += 1;
+        progress.set_position(entities.position());
+        progress.set_entities(converted, converted);
+    }
+    sink.finalize()?;
+    Ok(converted)
+}
+
+#[cfg(not(feature = "parquet"))]
+fn convert_parquet(_entities: &mut EntityFile, _output: impl Write, _progress: &Reporter) -> Result<usize> {
+    Err(ProcessError::Parquet(String::from("it needs a build with the parquet feature")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::io::Read;
+    use crate::decoder;
+
+    #[test]
+    fn test_convert() {
+        let directory = tempfile::tempdir().unwrap();
+        let dump = Path::new("./tests/test-data.json.bz2");
+        let mut original = String::new();
+        decoder::decoder(File::open(dump).unwrap()).read_to_string(&mut original).unwrap();
+
+        // through every format and compression, and back to an uncompressed dump identical to the original
+        let steps = [
+            ("test.msgpack.gz", Format::Msgpack, Compression::Gzip),
+            ("test.ndjson.bz2", Format::Ndjson, Compression::Bzip2),
+            ("test.ndjson", Format::Ndjson, Compression::None),
+            ("test.json.bz2", Format::Dump, Compression::Bzip2),
+        ];
+        let mut input = dump.to_path_buf();
+        for (name, format, compression) in steps {
+            let output = directory.path().join(name);
+            assert_eq!(convert(&input, File::create(&output).unwrap(), format, compression, Progress::Hidden).unwrap(), 8);
+            assert_eq!(Format::from_path(&output), Some(format));
+            input = output;
+        }
+        let values = |path: &Path| EntityFile::open(path).unwrap()
+            .map(|entity| serde_json::from_str::<Value>(&entity.unwrap()).unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(values(&input), values(dump));
+        let ids = |path: &Path| EntityFile::open(path).unwrap()
+            .map(|entity| splitter::entity_id(&entity.unwrap()).map(str::to_string))
+            .collect::<Vec<_>>();
+        assert_eq!(ids(&directory.path().join("test.msgpack.gz")), ids(dump));
+
+        let mut output = Vec::new();
+        convert(dump, &mut output, Format::Dump, Compression::None, Progress::Hidden).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), original);
+    }
+
+    #[test]
+    fn test_convert_wbgetentities() {
+        let dump = Path::new("./tests/test-data.json.bz2");
+        let mut output = Vec::new();
+        assert_eq!(convert(dump, &mut output, Format::Wbgetentities, Compression::None, Progress::Hidden).unwrap(), 8);
+        let response: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(response["success"], 1);
+        let entities = response["entities"].as_object().unwrap();
+        assert_eq!(entities.len(), 8);
+        assert_eq!(entities["Q60"]["labels"]["en"]["value"], "New York City");
+        assert_eq!(entities["P1"]["id"], "P1");
+    }
+
+    #[cfg(feature = "parquet")]
+    #[test]
+    fn test_convert_parquet() {
+        use parquet::file::reader::{FileReader, SerializedFileReader};
+
+        let directory = tempfile::tempdir().unwrap();
+        let dump = Path::new("./tests/test-data.json.bz2");
+        let output = directory.path().join("test.parquet");
+        assert_eq!(Format::from_path(&output), Some(Format::Parquet));
+        assert_eq!(convert(dump, File::create(&output).unwrap(), Format::Parquet, Compression::None, Progress::Hidden).unwrap(), 8);
+        let reader = SerializedFileReader::new(File::open(&output).unwrap()).unwrap();
+        assert_eq!(reader.metadata().file_metadata().num_rows(), 8);
+
+        assert!(matches!(convert(dump, Vec::new(), Format::Parquet, Compression::Gzip, Progress::Hidden), Err(ProcessError::Parquet(_))));
+    }
+}
@@ -0,0 +1,173 @@
+/*!
+ * Language coverage: how many entities have a label, a description and
+ * aliases in each language, optionally only counting the instances of some
+ * classes, e.g. to tell how well a language is served before training models
+ * on the labels of humans in it.
+ *
+ * The report is CSV, with a `language,labels,descriptions,aliases,label_share`
+ * row per language, `label_share` being the share of the entities counted
+ * which have a label in it, from 0 to 1. Entities are counted on all
+ * filtering threads, each thread keeping its own `LanguageCoverage` which are
+ * merged once the run is done.
+ */
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use serde::de::IgnoredAny;
+use serde::{Deserialize, Serialize};
+use crate::classes::ClassFilter;
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+/// Entities with each kind of term in a language
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct TermCoverage {
+    pub labels: u64,
+    pub descriptions: u64,
+    pub aliases: u64,
+}
+
+/// How many entities have terms in each language, see the module documentation
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct LanguageCoverage {
+    /// Entities counted, whatever languages they have terms in
+    pub entities: u64,
+    pub languages: BTreeMap<String, TermCoverage>,
+}
+
+// the terms of an entity, skipping over the rest without allocating
+#[derive(Deserialize)]
+struct Terms {
+    #[serde(default)]
+    labels: BTreeMap<String, IgnoredAny>,
+    #[serde(default)]
+    descriptions: BTreeMap<String, IgnoredAny>,
+    #[serde(default)]
+    aliases: BTreeMap<String, Vec<IgnoredAny>>,
+}
+
+impl LanguageCoverage {
+    /// Counts the terms of a raw entity
+    pub fn add(&mut self, raw: &str) -> serde_json::Result<()> {
+        let terms: Terms = serde_json::from_str(raw)?;
+        self.entities += 1;
+        for language in terms.labels.into_keys() {
+            self.languages.entry(language).or_default().labels += 1;
+        }
+        for language in terms.descriptions.into_keys() {
+            self.languages.entry(language).or_default().descriptions += 1;
+        }
+        for (language, aliases) in terms.aliases {
+            if !aliases.is_empty() {
+                self.languages.entry(language).or_default().aliases += 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds everything in `other`, e.g. the coverage of another part of the same dump
+    pub fn merge(&mut self, other: LanguageCoverage) {
+        self.entities += other.entities;
+        for (language, coverage) in other.languages {
+            let total = self.languages.entry(language).or_default();
+            total.labels += coverage.labels;
+            total.descriptions += coverage.descriptions;
+            total.aliases += coverage.aliases;
+        }
+    }
+
+    /// Writes the coverage as CSV, see the module documentation
+    pub fn write_csv(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "language,labels,descriptions,aliases,label_share")?;
+        for (language, coverage) in &self.languages {
+            let share = coverage.labels as f64 / self.entities.max(1) as f64;
+            writeln!(output, "{},{},{},{},{:.4}", language, coverage.labels, coverage.descriptions, coverage.aliases, share)?;
+        }
+        Ok(())
+    }
+}
+
+// counts the entities it's given on one thread, adding its coverage to the total once dropped
+struct CoverageCounter {
+    coverage: LanguageCoverage,
+    total: Arc<Mutex<LanguageCoverage>>,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl EntityFilter for CoverageCounter {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        if let Err(error) = self.coverage.add(raw) {
+            filter::skip_entity(raw, &error, self.continue_on_error)?;
+            self.failures += 1;
+        }
+        Ok(None)
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+impl Drop for CoverageCounter {
+    fn drop(&mut self) {
+        if let Ok(mut total) = self.total.lock() {
+            total.merge(std::mem::take(&mut self.coverage));
+        }
+    }
+}
+
+/// Counts the terms of every entity of `source` on `options.threads` threads, or with `classes`, only of those
+/// which are an instance of (P31) one of them
+pub fn language_coverage(source: impl Source, classes: Option<Arc<HashSet<String>>>, options: ProcessOptions) -> Result<(LanguageCoverage, ProcessStats)> {
+    let total = Arc::new(Mutex::new(LanguageCoverage::default()));
+    let continue_on_error = options.continue_on_error;
+    let counters = Arc::clone(&total);
+    let stats = Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || {
+            let counter = CoverageCounter { coverage: LanguageCoverage::default(), total: Arc::clone(&counters), continue_on_error, failures: 0 };
+            Ok(match &classes {
+                Some(classes) => Box::new(ClassFilter::new(Arc::clone(classes), Box::new(counter), continue_on_error)) as Box<dyn EntityFilter>,
+                None => Box::new(counter),
+            })
+        })
+        .sink(io::sink())
+        .options(options)
+        .build()?
+        .run()?;
+    let coverage = std::mem::take(&mut *total.lock().expect("Coverage poisoned"));
+    Ok((coverage, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    #[test]
+    fn test_language_coverage() {
+        let options = ProcessOptions { threads: 2, ..ProcessOptions::default() };
+        let (coverage, stats) = language_coverage(FileSource::new("./tests/test-data.json.bz2"), None, options).unwrap();
+        assert_eq!((coverage.entities, stats.entities_read), (8, 8));
+        let french = coverage.languages["fr"];
+        assert!(french.labels > 0 && french.labels <= 8);
+
+        let mut coverage = LanguageCoverage::default();
+        coverage.add(r#"{"labels":{"en":{}},"descriptions":{"en":{},"de":{}},"aliases":{"en":[],"de":[{}]}}"#).unwrap();
+        coverage.add(r#"{"labels":{"de":{}}}"#).unwrap();
+        let mut csv = Vec::new();
+        coverage.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "language,labels,descriptions,aliases,label_share\nde,1,1,1,0.5000\nen,1,1,0,0.5000\n");
+
+        // only the instances of a class, of which there are none in the test data
+        let classes = Arc::new(HashSet::from([String::from("Q5")]));
+        let (coverage, _) = language_coverage(FileSource::new("./tests/test-data.json.bz2"), Some(classes), ProcessOptions::default()).unwrap();
+        assert_eq!(coverage.entities, 0);
+    }
+}
@@ -0,0 +1,143 @@
+/*!
+ * Crosswalks between Wikidata and other authority files: a wide TSV keyed by
+ * QID with a column per external identifier property, e.g. GND (P227), VIAF
+ * (P214) or IMDb (P345), the table libraries and archives rebuild from every
+ * dump to link their records to each other.
+ *
+ * Columns are given as `P227=GND,P214=VIAF`, the name after `=` being the
+ * header of the column (the property itself if left out). An entity with
+ * several values for a property has them all in its cell, separated by `|`,
+ * deprecated statements being left out. Entities without any of the
+ * identifiers have no row. Fields are escaped as in `labels`, see
+ * `escape_tsv`.
+ */
+
+use std::str::FromStr;
+use log::debug;
+use crate::labels::escape_tsv;
+use crate::model::{DataValue, Entity, Rank};
+
+/// Separates the values of a cell for a property with several of them
+pub const VALUE_SEPARATOR: &str = "|";
+
+/// A column of a crosswalk, see the module documentation
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CrosswalkColumn {
+    pub property: String,
+    pub name: String,
+}
+
+/// The columns of a crosswalk, parsed from e.g. `P227=GND,P214=VIAF,P345=IMDb`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Crosswalk {
+    pub columns: Vec<CrosswalkColumn>,
+}
+
+impl FromStr for Crosswalk {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let mut columns: Vec<CrosswalkColumn> = Vec::new();
+        for column in value.split(',').map(str::trim).filter(|column| !column.is_empty()) {
+            let (property, name) = match column.split_once('=') {
+                Some((property, name)) => (property.trim(), name.trim()),
+                None => (column, column),
+            };
+            let valid = property.strip_prefix('P').is_some_and(|number| !number.is_empty() && number.bytes().all(|b| b.is_ascii_digit()));
+            if !valid || name.is_empty() || name.contains(['\t', '\n', '\r']) {
+                return Err(format!("Invalid crosswalk column '{}', expected a property and its column name, e.g. P227=GND", column));
+            }
+            if columns.iter().any(|other| other.name == name) {
+                return Err(format!("Crosswalk column '{}' is given twice", name));
+            }
+            columns.push(CrosswalkColumn { property: property.to_string(), name: name.to_string() });
+        }
+        if columns.is_empty() {
+            return Err(String::from("A crosswalk needs at least one column, e.g. P227=GND"));
+        }
+        Ok(Crosswalk { columns })
+    }
+}
+
+impl Crosswalk {
+    /// The header row, `qid` then the name of each column
+    pub fn header(&self) -> String {
+        let mut header = String::from("qid");
+        for column in &self.columns {
+            header.push('\t');
+            header.push_str(&escape_tsv(&column.name));
+        }
+        header
+    }
+
+    /// The row of `entity`, or `None` if it has none of the identifiers
+    pub fn row(&self, entity: &Entity) -> Option<String> {
+        let mut row = escape_tsv(&entity.id).into_owned();
+        let mut found = false;
+        for column in &self.columns {
+            let mut values: Vec<&str> = Vec::new();
+            for claim in entity.claims.get(&column.property).into_iter().flatten() {
+                if claim.rank == Rank::Deprecated {
+                    continue;
+                }
+                if let Some(DataValue::String(value)) = &claim.mainsnak.datavalue {
+                    if !values.contains(&value.as_str()) {
+                        values.push(value);
+                    }
+                }
+            }
+            found |= !values.is_empty();
+            row.push('\t');
+            row.push_str(&escape_tsv(&values.join(VALUE_SEPARATOR)));
+        }
+        found.then_some(row)
+    }
+}
+
+/// Replaces an output which is a whole entity with its crosswalk row, or drops it if it has none of the
+/// identifiers. Other outputs (e.g. just an id) have no identifiers to write and are dropped too.
+pub fn crosswalk_output(output: String, crosswalk: &Crosswalk) -> Option<String> {
+    match Entity::parse(&output) {
+        Ok(entity) => crosswalk.row(&entity),
+        Err(error) => {
+            debug!("Not an entity, so no crosswalk row ({}): {}", error, output);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item","claims":{
+        "P214":[{"mainsnak":{"snaktype":"value","property":"P214","datavalue":{"type":"string","value":"113230702"}},"type":"statement","rank":"normal"},
+                {"mainsnak":{"snaktype":"value","property":"P214","datavalue":{"type":"string","value":"113230702"}},"type":"statement","rank":"preferred"},
+                {"mainsnak":{"snaktype":"value","property":"P214","datavalue":{"type":"string","value":"1"}},"type":"statement","rank":"deprecated"}],
+        "P345":[{"mainsnak":{"snaktype":"value","property":"P345","datavalue":{"type":"string","value":"nm0010930"}},"type":"statement","rank":"normal"},
+                {"mainsnak":{"snaktype":"value","property":"P345","datavalue":{"type":"string","value":"ch0000001"}},"type":"statement","rank":"normal"},
+                {"mainsnak":{"snaktype":"somevalue","property":"P345"},"type":"statement","rank":"normal"}]
+    }}"#;
+
+    #[test]
+    fn test_parse_crosswalk() {
+        let crosswalk: Crosswalk = "P227=GND, P214=VIAF,P345".parse().unwrap();
+        assert_eq!(crosswalk.columns[1], CrosswalkColumn { property: String::from("P214"), name: String::from("VIAF") });
+        assert_eq!(crosswalk.header(), "qid\tGND\tVIAF\tP345");
+        assert!("".parse::<Crosswalk>().is_err());
+        assert!("Q5=GND".parse::<Crosswalk>().is_err());
+        assert!("P227=".parse::<Crosswalk>().is_err());
+        assert!("P227=ID,P214=ID".parse::<Crosswalk>().is_err());
+    }
+
+    #[test]
+    fn test_crosswalk_output() {
+        let crosswalk: Crosswalk = "P227=GND,P214=VIAF,P345=IMDb".parse().unwrap();
+        let row = crosswalk_output(ENTITY.to_string(), &crosswalk);
+        assert_eq!(row.as_deref(), Some("Q42\t\t113230702\tnm0010930|ch0000001"));
+
+        let crosswalk: Crosswalk = "P227=GND".parse().unwrap();
+        assert_eq!(crosswalk_output(ENTITY.to_string(), &crosswalk), None);
+        assert_eq!(crosswalk_output(String::from("\"Q42\""), &crosswalk), None);
+    }
+}
@@ -0,0 +1,101 @@
+/*!
+ * Polars DataFrames of the outputs of a pipeline (with the `polars` feature),
+ * for embedding applications analysing a subset of a dump without writing it
+ * out and reading it back:
+ *
+ * ```no_run
+ * use wikidump_process::{dataframe, Pipeline};
+ *
+ * let pipeline = Pipeline::builder()
+ *     .source("./example.json.bz2")
+ *     .filter(r#"select(.type == "item") | {id, label: .labels.en.value}"#);
+ * let (frame, _stats) = dataframe::collect_dataframe(pipeline)?;
+ * # Ok::<(), wikidump_process::error::ProcessError>(())
+ * ```
+ *
+ * Each output has to be a JSON object, and becomes a row with a column per
+ * member. The types of the columns are inferred from every output, so members
+ * missing from some of them are null there. The outputs are held as ndjson
+ * until the pipeline has run, so the subset (not the dump) has to fit in
+ * memory, as the DataFrame will anyway.
+ */
+
+use std::cell::RefCell;
+use std::io::Cursor;
+use std::rc::Rc;
+use polars::prelude::{DataFrame, JsonFormat, JsonReader, SerReader};
+use crate::error::{ProcessError, Result};
+use crate::pipeline::PipelineBuilder;
+use crate::process::ProcessStats;
+use crate::sink::Sink;
+
+// gathers the outputs of a run as ndjson, to be taken once the pipeline owning it has run
+struct NdjsonBuffer {
+    ndjson: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Sink for NdjsonBuffer {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let mut ndjson = self.ndjson.borrow_mut();
+        ndjson.extend_from_slice(output.as_bytes());
+        ndjson.push(b'\n');
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Reads `ndjson`, a JSON object per line, into a DataFrame with a column per member of any of them
+pub fn read_ndjson(ndjson: Vec<u8>) -> Result<DataFrame> {
+    if ndjson.is_empty() {
+        return Ok(DataFrame::empty());
+    }
+    JsonReader::new(Cursor::new(ndjson))
+        .with_json_format(JsonFormat::JsonLines)
+        .infer_schema_len(None)
+        .finish()
+        .map_err(|error| ProcessError::DataFrame(error.to_string()))
+}
+
+/// Runs `pipeline`, which mustn't have a sink, into a DataFrame of its outputs, see the module documentation
+pub fn collect_dataframe(pipeline: PipelineBuilder) -> Result<(DataFrame, ProcessStats)> {
+    let ndjson = Rc::default();
+    let stats = pipeline.entity_sink(NdjsonBuffer { ndjson: Rc::clone(&ndjson) }).build()?.run()?;
+    Ok((read_ndjson(ndjson.take())?, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use polars::prelude::DataType;
+    use crate::Pipeline;
+
+    #[test]
+    fn test_collect_dataframe() {
+        let pipeline = Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .filter(r#"select(.type == "item") | {id, label: .labels.en.value, sitelinks: (.sitelinks | length)}"#);
+        let (frame, stats) = collect_dataframe(pipeline).unwrap();
+        assert_eq!(stats.entities_written, 7);
+        assert_eq!(frame.shape(), (7, 3));
+        assert_eq!(frame.column("id").unwrap().str().unwrap().get(6), Some("Q60"));
+        assert_eq!(frame.column("label").unwrap().str().unwrap().get(6), Some("New York City"));
+        assert_eq!(frame.column("label").unwrap().null_count(), 1);
+        assert_eq!(frame.column("sitelinks").unwrap().dtype(), &DataType::Int64);
+    }
+
+    #[test]
+    fn test_collect_nothing() {
+        let pipeline = Pipeline::builder().source("./tests/test-data.json.bz2").filter("empty");
+        let (frame, _) = collect_dataframe(pipeline).unwrap();
+        assert_eq!(frame.height(), 0);
+    }
+
+    #[test]
+    fn test_outputs_which_are_not_objects() {
+        let pipeline = Pipeline::builder().source("./tests/test-data.json.bz2").filter("[.id]");
+        assert!(matches!(collect_dataframe(pipeline), Err(ProcessError::DataFrame(_))));
+    }
+}
@@ -0,0 +1,118 @@
+/*!
+ * Keeps only the statements of some datatypes, e.g. `wikibase-item`, `time`
+ * and `quantity` for graph-only consumers, who have no use for the
+ * `external-id`, `commonsMedia` or `url` statements making up much of a dump.
+ *
+ * The datatype of a statement is the one of its main snak. Statements whose
+ * main snak has none (which only older dumps leave out) are kept, as are
+ * outputs which aren't entities. Properties left without statements are
+ * dropped.
+ */
+
+use std::str::FromStr;
+use serde_json::{Map, Value};
+
+/// The datatypes of Wikidata properties
+pub const DATATYPES: &[&str] = &[
+    "commonsMedia", "entity-schema", "external-id", "geo-shape", "globe-coordinate", "math", "monolingualtext",
+    "musical-notation", "quantity", "string", "tabular-data", "time", "url", "wikibase-form", "wikibase-item",
+    "wikibase-lexeme", "wikibase-property", "wikibase-sense",
+];
+
+/// The datatypes to keep statements of, parsed from e.g. `wikibase-item,time,quantity`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Datatypes(Vec<String>);
+
+impl FromStr for Datatypes {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let mut datatypes = Vec::new();
+        for datatype in value.split(',').map(str::trim).filter(|datatype| !datatype.is_empty()) {
+            if !DATATYPES.contains(&datatype) {
+                return Err(format!("Invalid datatype '{}', expected one of {}", datatype, DATATYPES.join(", ")));
+            }
+            datatypes.push(datatype.to_string());
+        }
+        if datatypes.is_empty() {
+            return Err(String::from("No datatypes to keep, e.g. wikibase-item,time,quantity"));
+        }
+        Ok(Datatypes(datatypes))
+    }
+}
+
+impl Datatypes {
+    pub fn contains(&self, datatype: &str) -> bool {
+        self.0.iter().any(|kept| kept == datatype)
+    }
+
+    // whether `statement` is kept, see the module documentation
+    fn keeps(&self, statement: &Value) -> bool {
+        match statement.pointer("/mainsnak/datatype") {
+            Some(Value::String(datatype)) => self.contains(datatype),
+            _ => true,
+        }
+    }
+}
+
+/// Drops the statements of `entity` of other datatypes than `datatypes`, returning how many were
+pub fn drop_other_datatypes(entity: &mut Map<String, Value>, datatypes: &Datatypes) -> usize {
+    let claims = match entity.get_mut("claims") {
+        Some(Value::Object(claims)) => claims,
+        _ => return 0,
+    };
+    let mut dropped = 0;
+    claims.retain(|_, statements| match statements {
+        Value::Array(statements) => {
+            let before = statements.len();
+            statements.retain(|statement| datatypes.keeps(statement));
+            dropped += before - statements.len();
+            !statements.is_empty()
+        }
+        _ => true,
+    });
+    dropped
+}
+
+/// `output` with only the statements of `datatypes`, re-serialized only if statements were dropped
+pub fn keep_datatypes_output(output: String, datatypes: &Datatypes) -> String {
+    let mut entity = match serde_json::from_str::<Value>(&output) {
+        Ok(Value::Object(entity)) if entity.contains_key("claims") => entity,
+        _ => return output,
+    };
+    match drop_other_datatypes(&mut entity, datatypes) {
+        0 => output,
+        _ => Value::Object(entity).to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_datatypes() {
+        let datatypes: Datatypes = "wikibase-item, time,quantity".parse().unwrap();
+        assert!(datatypes.contains("time") && !datatypes.contains("external-id"));
+        assert!("wikibase-item,externalid".parse::<Datatypes>().is_err());
+        assert!(",".parse::<Datatypes>().is_err());
+    }
+
+    #[test]
+    fn test_keep_datatypes_output() {
+        let datatypes: Datatypes = "wikibase-item".parse().unwrap();
+        let entity = r#"{"id":"Q42","claims":{
+            "P31":[{"mainsnak":{"property":"P31","datatype":"wikibase-item"}}],
+            "P214":[{"mainsnak":{"property":"P214","datatype":"external-id"}}],
+            "P18":[{"mainsnak":{"property":"P18","datatype":"commonsMedia"}},{"mainsnak":{"property":"P18"}}]
+        }}"#;
+        assert_eq!(
+            keep_datatypes_output(entity.to_string(), &datatypes),
+            r#"{"claims":{"P18":[{"mainsnak":{"property":"P18"}}],"P31":[{"mainsnak":{"datatype":"wikibase-item","property":"P31"}}]},"id":"Q42"}"#
+        );
+
+        let kept = r#"{"id":"Q1","claims":{"P31":[{"mainsnak":{"property":"P31","datatype":"wikibase-item"}}]}}"#;
+        assert_eq!(keep_datatypes_output(kept.to_string(), &datatypes), kept);
+        assert_eq!(keep_datatypes_output(String::from("\"Q42\""), &datatypes), "\"Q42\"");
+    }
+}
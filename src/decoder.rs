@@ -0,0 +1,280 @@
+/*!
+ * Decompression of bzip2 encoded dumps. The dumps published by Wikimedia are
+ * made of many concatenated bzip2 streams, so a multi-stream decoder is needed
+ * to read past the first one.
+ */
+
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use bzip2::bufread::BzDecoder;
+use bzip2::read::MultiBzDecoder;
+use log::debug;
+use serde::{Deserialize, Serialize};
+use crate::error::{ProcessError, Result};
+use crate::splitter::{DUMP_END, DUMP_START};
+
+/// Size of the chunks read from the decoder at once
+pub const BUFFER_LENGTH: usize = 500000;
+
+/// Wraps a reader over compressed dump bytes in a decoder yielding the decompressed JSON
+pub fn decoder<R: Read>(reader: R) -> MultiBzDecoder<BufReader<R>> {
+    MultiBzDecoder::new(BufReader::new(reader))
+}
+
+/// Opens the compressed dump at `path`, returning the file along with its (compressed) size in bytes
+pub fn open(path: &Path) -> Result<(File, u64)> {
+    let open_error = |source| ProcessError::OpenInput { path: path.to_path_buf(), source };
+    let file = File::open(path).map_err(open_error)?;
+    let size = file.metadata().map_err(open_error)?.len();
+    debug!("Opening {:?}, size: {}", path, size);
+    Ok((file, size))
+}
+
+/// Where a bzip2 stream starts, in compressed bytes of the input and decompressed bytes of the dump
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamStart {
+    pub compressed_offset: u64,
+    pub decompressed_offset: u64,
+}
+
+/// A multi-stream decoder which keeps track of where the current bzip2 stream started, so that
+/// decoding can later be restarted from there without going through the streams before it
+pub struct StreamDecoder<R: BufRead> {
+    decoder: Option<BzDecoder<R>>,
+    stream_start: StreamStart,
+    decompressed_offset: u64,
+}
+
+impl<R: BufRead> StreamDecoder<R> {
+    /// Decodes `reader`, which is positioned at the start of the stream `start`
+    pub fn new(reader: R, start: StreamStart) -> Self {
+        StreamDecoder { decoder: Some(BzDecoder::new(reader)), stream_start: start, decompressed_offset: start.decompressed_offset }
+    }
+
+    /// The start of the stream the last bytes read came from
+    pub fn stream_start(&self) -> StreamStart {
+        self.stream_start
+    }
+}
+
+impl<R: BufRead> Read for StreamDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let decoder = match self.decoder.as_mut() {
+                Some(decoder) => decoder,
+                None => return Ok(0),
+            };
+            let n = decoder.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.decompressed_offset += n as u64;
+                return Ok(n);
+            }
+
+            // the stream has ended, carry on with the next one if there is one
+            let decoder = self.decoder.take().expect("Decoder was just used");
+            let compressed_offset = self.stream_start.compressed_offset + decoder.total_in();
+            let mut reader = decoder.into_inner();
+            if reader.fill_buf()?.is_empty() {
+                return Ok(0);
+            }
+            self.stream_start = StreamStart { compressed_offset, decompressed_offset: self.decompressed_offset };
+            debug!("bzip2 stream starting at {:?}", self.stream_start);
+            self.decoder = Some(BzDecoder::new(reader));
+        }
+    }
+}
+
+/// Decompressed bytes of a dump, along with the bzip2 stream they came from
+pub trait StreamRead: Read {
+    /// The start of the stream the last bytes read came from
+    fn stream_start(&self) -> StreamStart;
+}
+
+impl<R: BufRead> StreamRead for StreamDecoder<R> {
+    fn stream_start(&self) -> StreamStart {
+        self.stream_start
+    }
+}
+
+/// A range of whole bzip2 streams of a dump, from the compressed offset of the first one to that of the
+/// stream after the last one
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StreamRange {
+    pub start: u64,
+    pub end: u64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RangeState {
+    // looking for the newline the range's first entity follows
+    Start,
+    // within the range
+    Body,
+    // past the end of the range, finishing the last entity
+    Tail,
+    Done,
+}
+
+/// Decodes the entities of one range of a dump's bzip2 streams as if they were a dump of their own, so the
+/// ranges of a dump can be processed separately, e.g. on different machines (see `plan`).
+///
+/// Streams are cut wherever the compressor's blocks end, mid-entity, so a range has the entities whose
+/// preceding newline is in one of its streams: from the first one after a newline in the range, to the one
+/// straddling its end, which is decoded to its end from the streams after the range. Every entity of the
+/// dump is in exactly one range.
+pub struct RangeDecoder<R: BufRead> {
+    decoder: StreamDecoder<R>,
+    end: u64,
+    state: RangeState,
+    // bytes to return before decoding any more
+    pending: Vec<u8>,
+    position: usize,
+    // whether any entity bytes have been returned, to tell a range without entities
+    empty: bool,
+    // a comma at the end of the last bytes decoded, only returned if it doesn't end the range's last entity
+    held_comma: bool,
+    chunk: Vec<u8>,
+}
+
+impl<R: BufRead> RangeDecoder<R> {
+    /// Decodes `reader`, which is positioned at the start of `range`
+    pub fn new(reader: R, range: StreamRange) -> Self {
+        RangeDecoder {
+            decoder: StreamDecoder::new(reader, StreamStart { compressed_offset: range.start, decompressed_offset: 0 }),
+            end: range.end,
+            state: RangeState::Start,
+            pending: Vec::new(),
+            position: 0,
+            empty: true,
+            held_comma: false,
+            chunk: vec![0; BUFFER_LENGTH],
+        }
+    }
+
+    fn past_end(&self) -> bool {
+        self.decoder.stream_start().compressed_offset >= self.end
+    }
+
+    fn finish(&mut self) {
+        self.pending.extend_from_slice(DUMP_END.as_bytes());
+        self.state = RangeState::Done;
+    }
+
+    fn push_body(&mut self, bytes: &[u8]) {
+        // a range without entities, whose first newline is the one before the end of the dump
+        if self.empty && bytes.first() == Some(&b']') {
+            return self.finish();
+        }
+        if !bytes.is_empty() {
+            self.empty = false;
+            self.pending.extend_from_slice(bytes);
+        }
+    }
+
+    // pushes entity bytes, holding back a comma at their end until it's known not to end the range's last entity
+    fn push_entities(&mut self, bytes: &[u8]) {
+        if bytes.is_empty() {
+            return;
+        }
+        if self.held_comma {
+            self.push_body(b",");
+        }
+        self.held_comma = bytes.ends_with(b",");
+        self.push_body(bytes.strip_suffix(b",").unwrap_or(bytes));
+    }
+
+    fn push_tail(&mut self, bytes: &[u8]) {
+        match bytes.iter().position(|&byte| byte == b'\n') {
+            Some(newline) => {
+                self.push_entities(&bytes[..newline]);
+                if self.state != RangeState::Done {
+                    self.finish();
+                }
+            }
+            None => self.push_entities(bytes),
+        }
+    }
+}
+
+impl<R: BufRead> Read for RangeDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.pending.len() {
+                let n = buf.len().min(self.pending.len() - self.position);
+                buf[..n].copy_from_slice(&self.pending[self.position..self.position + n]);
+                self.position += n;
+                return Ok(n);
+            }
+            self.pending.clear();
+            self.position = 0;
+            if self.state == RangeState::Done {
+                return Ok(0);
+            }
+
+            let mut chunk = std::mem::take(&mut self.chunk);
+            let n = self.decoder.read(&mut chunk)?;
+            let bytes = &chunk[..n];
+            match self.state {
+                RangeState::Start if n == 0 || self.past_end() => {
+                    self.pending.extend_from_slice(DUMP_START.as_bytes());
+                    self.finish();
+                }
+                RangeState::Start => {
+                    if let Some(newline) = bytes.iter().position(|&byte| byte == b'\n') {
+                        self.pending.extend_from_slice(DUMP_START.as_bytes());
+                        self.state = RangeState::Body;
+                        self.push_entities(&bytes[newline + 1..]);
+                    }
+                }
+                // the dump itself ended within the range
+                RangeState::Body if n == 0 => self.state = RangeState::Done,
+                RangeState::Body if self.past_end() => {
+                    self.state = RangeState::Tail;
+                    self.push_tail(bytes);
+                }
+                RangeState::Body => self.push_entities(bytes),
+                RangeState::Tail if n == 0 => self.finish(),
+                RangeState::Tail => self.push_tail(bytes),
+                RangeState::Done => {}
+            }
+            self.chunk = chunk;
+        }
+    }
+}
+
+impl<R: BufRead> StreamRead for RangeDecoder<R> {
+    fn stream_start(&self) -> StreamStart {
+        self.decoder.stream_start()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Seek, SeekFrom};
+
+    #[test]
+    fn test_stream_decoder() {
+        // two dumps back to back make for a multi-stream input with a known boundary
+        let dump = std::fs::read("./tests/test-data.json.bz2").unwrap();
+        let input = [dump.clone(), dump.clone()].concat();
+        let mut expected = Vec::new();
+        decoder(&dump[..]).read_to_end(&mut expected).unwrap();
+
+        let mut stream_decoder = StreamDecoder::new(&input[..], StreamStart::default());
+        let mut decoded = Vec::new();
+        stream_decoder.read_to_end(&mut decoded).unwrap();
+        assert_eq!(decoded, [expected.clone(), expected.clone()].concat());
+        let start = stream_decoder.stream_start();
+        assert_eq!(start, StreamStart { compressed_offset: dump.len() as u64, decompressed_offset: expected.len() as u64 });
+
+        // restarting from the recorded boundary only decodes what follows it
+        let mut file = tempfile::tempfile().unwrap();
+        std::io::Write::write_all(&mut file, &input).unwrap();
+        file.seek(SeekFrom::Start(start.compressed_offset)).unwrap();
+        let mut restarted = Vec::new();
+        StreamDecoder::new(BufReader::new(file), start).read_to_end(&mut restarted).unwrap();
+        assert_eq!(restarted, expected);
+    }
+}
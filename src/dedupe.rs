@@ -0,0 +1,168 @@
+/*!
+ * Dropping entities found more than once, e.g. once a full dump has been
+ * concatenated with incremental ones, or an append was run twice.
+ *
+ * Entities are told apart by their id (the first "id" key of their JSON), or
+ * by their whole text if they have none, e.g. outputs of the `.id` filter.
+ * Every id seen is held in memory.
+ */
+
+use std::collections::{HashMap, HashSet};
+use std::io::Write;
+use std::path::Path;
+use std::str::FromStr;
+use serde::Serialize;
+use crate::decoder::BUFFER_LENGTH;
+use crate::error::{ProcessError, Result};
+use crate::progress::{Progress, Reporter};
+use crate::reader::EntityFile;
+use crate::sink::{Sink, WriteSink};
+use crate::splitter;
+
+/// Which of the entities with the same id is kept
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keep {
+    First,
+    /// The last one, which takes a second pass over the input
+    Last,
+}
+
+impl FromStr for Keep {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "first" => Ok(Keep::First),
+            "last" => Ok(Keep::Last),
+            _ => Err(format!("Invalid occurrence to keep '{}', expected first or last", value)),
+        }
+    }
+}
+
+// what tells an entity apart from the others
+fn key(entity: &str) -> &str {
+    splitter::entity_id(entity).unwrap_or(entity)
+}
+
+/// Passes only the first output with each id on to another sink
+pub struct DedupeSink<S: Sink> {
+    sink: S,
+    seen: HashSet<String>,
+    duplicates: u64,
+}
+
+impl<S: Sink> DedupeSink<S> {
+    pub fn new(sink: S) -> Self {
+        DedupeSink { sink, seen: HashSet::new(), duplicates: 0 }
+    }
+
+    /// Number of outputs dropped so far
+    pub fn duplicates(&self) -> u64 {
+        self.duplicates
+    }
+}
+
+impl<S: Sink> Sink for DedupeSink<S> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let key = key(output);
+        if self.seen.contains(key) {
+            self.duplicates += 1;
+            return Ok(());
+        }
+        self.seen.insert(key.to_string());
+        self.sink.write_entity(output)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sink.flush()
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.sink.finalize()
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DedupeStats {
+    pub entities: u64,
+    pub duplicates: u64,
+}
+
+// calls `f` with each entity of the file at `path` and its position in the file, counting from 0
+fn scan(path: &Path, progress: Progress, mut f: impl FnMut(u64, String) -> Result<()>) -> Result<u64> {
+    let mut entities = EntityFile::open(path)?;
+    let progress = Reporter::new(progress, Some(entities.size()), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+    progress.set_draw_rate(1);
+    let mut read = 0;
+    while let Some(entity) = entities.next() {
+        f(read, entity.map_err(ProcessError::Read)?)?;
+        read += 1;
+        progress.set_position(entities.position());
+        progress.set_entities(read as usize, read as usize);
+    }
+    progress.finish(format!("Read {} entities", read));
+    Ok(read)
+}
+
+/// Writes the entities of the dump or `filter` output at `input` to `output` one per line, keeping only one
+/// entity with each id
+pub fn dedupe(input: &Path, keep: Keep, progress: Progress, output: impl Write) -> Result<DedupeStats> {
+    let mut output = WriteSink::new(output, BUFFER_LENGTH);
+    let (entities, written) = match keep {
+        Keep::First => {
+            let mut output = DedupeSink::new(&mut output);
+            let entities = scan(input, progress, |_, entity| output.write_entity(&entity))?;
+            (entities, entities - output.duplicates())
+        }
+        Keep::Last => {
+            let mut last = HashMap::new();
+            scan(input, progress, |i, entity| {
+                last.insert(key(&entity).to_string(), i);
+                Ok(())
+            })?;
+            let entities = scan(input, progress, |i, entity| match last.get(key(&entity)) {
+                Some(&last) if last == i => output.write_entity(&entity),
+                _ => Ok(()),
+            })?;
+            (entities, last.len() as u64)
+        }
+    };
+    output.finalize()?;
+    Ok(DedupeStats { entities, duplicates: entities - written })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_dedupe_sink() {
+        let mut output = Vec::new();
+        let mut sink = DedupeSink::new(WriteSink::new(&mut output, 64));
+        for entity in ["{\"id\": \"Q1\", \"v\": 1}", "\"Q2\"", "{\"id\": \"Q1\", \"v\": 2}", "\"Q2\""] {
+            sink.write_entity(entity).unwrap();
+        }
+        sink.finalize().unwrap();
+        assert_eq!(sink.duplicates(), 2);
+        drop(sink);
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"id\": \"Q1\", \"v\": 1}\n\"Q2\"\n");
+    }
+
+    #[test]
+    fn test_dedupe() {
+        let directory = tempfile::tempdir().unwrap();
+        let input = directory.path().join("input.ndjson");
+        fs::write(&input, "{\"id\":\"Q1\",\"v\":1}\n{\"id\":\"Q2\"}\n{\"id\":\"Q1\",\"v\":2}\n").unwrap();
+
+        let mut output = Vec::new();
+        let stats = dedupe(&input, Keep::First, Progress::Hidden, &mut output).unwrap();
+        assert_eq!(stats, DedupeStats { entities: 3, duplicates: 1 });
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"id\":\"Q1\",\"v\":1}\n{\"id\":\"Q2\"}\n");
+
+        let mut output = Vec::new();
+        dedupe(&input, Keep::Last, Progress::Hidden, &mut output).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"id\":\"Q2\"}\n{\"id\":\"Q1\",\"v\":2}\n");
+        assert!("middle".parse::<Keep>().is_err());
+    }
+}
@@ -0,0 +1,262 @@
+/*!
+ * Patches between two versions of an output sorted by id, so mirrors of a
+ * filtered dataset can download what changed and apply it to their copy
+ * rather than the whole output again.
+ *
+ * A delta is a line of JSON for each entity which differs, in id order:
+ * `{"op":"add","entity":{...}}` for new entities, `{"op":"update","id":"Q42","patch":[...]}`
+ * with a JSON Patch (see `diff::json_patch`) of only what changed, and
+ * `{"op":"delete","id":"Q1"}`. It's compressed with bzip2 or gzip when its
+ * file name ends with `.bz2` or `.gz`.
+ *
+ * Both versions have to be sorted as `sort` does, which lets them be streamed
+ * side by side without holding anything in memory. Entities are compared as
+ * JSON values, so applying a delta gives the new version as JSON, not byte
+ * for byte.
+ */
+
+use std::cmp::Ordering;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::diff::{apply_patch, json_patch};
+use crate::error::{ProcessError, Result};
+use crate::progress::{Progress, Reporter};
+use crate::reader::{entity_json, EntityFile};
+use crate::sink::Sink;
+use crate::sort::SortKey;
+use crate::splitter;
+
+/// A line of a delta, see the module documentation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "lowercase")]
+pub enum Operation {
+    Add { entity: Value },
+    Update { id: String, patch: Vec<Value> },
+    Delete { id: String },
+}
+
+impl Operation {
+    pub fn id(&self) -> Option<&str> {
+        match self {
+            Operation::Add { entity } => entity["id"].as_str(),
+            Operation::Update { id, .. } | Operation::Delete { id } => Some(id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DeltaStats {
+    pub added: u64,
+    pub updated: u64,
+    pub deleted: u64,
+    pub unchanged: u64,
+}
+
+fn invalid(message: String) -> ProcessError {
+    ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, message))
+}
+
+fn parse(id: &str, entity: &str) -> Result<Value> {
+    serde_json::from_str(entity).map_err(|error| invalid(format!("entity {} is not valid JSON: {}", id, error)))
+}
+
+// the entities of a file sorted by id, read one ahead so they can be compared with those of another
+struct SortedEntities {
+    path: PathBuf,
+    entities: EntityFile,
+    current: Option<String>,
+}
+
+impl SortedEntities {
+    fn open(path: &Path) -> Result<Self> {
+        let mut entities = EntityFile::open(path)?;
+        let current = entities.next().transpose().map_err(ProcessError::Read)?;
+        let sorted = SortedEntities { path: path.to_path_buf(), entities, current };
+        if let Some(entity) = &sorted.current {
+            sorted.id(entity)?;
+        }
+        Ok(sorted)
+    }
+
+    fn id<'a>(&self, entity: &'a str) -> Result<&'a str> {
+        splitter::entity_id(entity).ok_or_else(|| invalid(format!("{:?} has an entity without an id", self.path)))
+    }
+
+    fn current_id(&self) -> Result<Option<&str>> {
+        self.current.as_deref().map(|entity| self.id(entity)).transpose()
+    }
+
+    // moves on to the next entity, returning the current one
+    fn advance(&mut self) -> Result<Option<String>> {
+        let next = self.entities.next().transpose().map_err(ProcessError::Read)?;
+        if let (Some(next), Some(current)) = (&next, &self.current) {
+            let (next_id, current_id) = (self.id(next)?, self.id(current)?);
+            if SortKey::of_id(next_id) < SortKey::of_id(current_id) {
+                return Err(invalid(format!("{:?} is not sorted by id, {} comes after {}", self.path, next_id, current_id)));
+            }
+        }
+        Ok(std::mem::replace(&mut self.current, next))
+    }
+}
+
+fn reporter(progress: Progress) -> Reporter {
+    let progress = Reporter::new(progress, None, "{msg}\n{spinner:.green} [{elapsed_precise}]");
+    progress.set_draw_rate(1);
+    progress
+}
+
+/// Writes the delta turning `old` into `new`, both sorted by id, to `output`
+pub fn delta(old: &Path, new: &Path, progress: Progress, mut output: impl Write) -> Result<DeltaStats> {
+    let (mut old, mut new) = (SortedEntities::open(old)?, SortedEntities::open(new)?);
+    let progress = reporter(progress);
+    let mut stats = DeltaStats::default();
+    let mut write = |operation: &Operation| {
+        serde_json::to_writer(&mut output, operation).map_err(|error| ProcessError::Write(error.into()))?;
+        writeln!(output).map_err(ProcessError::Write)
+    };
+    loop {
+        let order = match (old.current_id()?, new.current_id()?) {
+            (None, None) => break,
+            (Some(_), None) => Ordering::Less,
+            (None, Some(_)) => Ordering::Greater,
+            (Some(old_id), Some(new_id)) => SortKey::of_id(old_id).cmp(&SortKey::of_id(new_id)),
+        };
+        match order {
+            Ordering::Less => {
+                let entity = old.advance()?.expect("There is an old entity");
+                stats.deleted += 1;
+                write(&Operation::Delete { id: old.id(&entity)?.to_string() })?;
+            }
+            Ordering::Greater => {
+                let entity = new.advance()?.expect("There is a new entity");
+                stats.added += 1;
+                write(&Operation::Add { entity: parse(new.id(&entity)?, &entity)? })?;
+            }
+            Ordering::Equal => {
+                let (old_entity, new_entity) = (old.advance()?.expect("There is an old entity"), new.advance()?.expect("There is a new entity"));
+                let id = new.id(&new_entity)?;
+                let patch = json_patch(&parse(id, &old_entity)?, &parse(id, &new_entity)?);
+                if patch.is_empty() {
+                    stats.unchanged += 1;
+                } else {
+                    stats.updated += 1;
+                    write(&Operation::Update { id: id.to_string(), patch })?;
+                }
+            }
+        }
+        progress.set_entities((stats.added + stats.updated + stats.unchanged) as usize, (stats.added + stats.updated + stats.deleted) as usize);
+    }
+    output.flush().map_err(ProcessError::Write)?;
+    progress.finish(format!("{} added, {} updated, {} deleted", stats.added, stats.updated, stats.deleted));
+    Ok(stats)
+}
+
+/// Applies the delta at `delta` to `input`, sorted by id, writing the patched entities to `sink` in id order.
+///
+/// Fails if the delta doesn't fit the input, e.g. updating an entity it doesn't have, which usually means it
+/// was made from another version.
+pub fn apply_delta(input: &Path, delta: &Path, progress: Progress, sink: &mut dyn Sink) -> Result<DeltaStats> {
+    let mut input = SortedEntities::open(input)?;
+    let mut operations = EntityFile::open(delta)?;
+    let progress = reporter(progress);
+    let mut stats = DeltaStats::default();
+    let serialize = |entity: &Value| entity_json(entity).map_err(|error| ProcessError::Write(error.into()));
+    let mut previous: Option<String> = None;
+    while let Some(line) = operations.next() {
+        let line = line.map_err(ProcessError::Read)?;
+        let operation: Operation = serde_json::from_str(&line)
+            .map_err(|error| invalid(format!("line {} of {:?} is not a delta operation: {}", operations.line(), delta, error)))?;
+        let id = operation.id().map(String::from)
+            .ok_or_else(|| invalid(format!("line {} of {:?} adds an entity without an id", operations.line(), delta)))?;
+        let id = id.as_str();
+        if previous.as_deref().is_some_and(|previous| SortKey::of_id(id) <= SortKey::of_id(previous)) {
+            return Err(invalid(format!("{:?} is not sorted by id, {} comes after {}", delta, id, previous.unwrap_or_default())));
+        }
+
+        // the entities before this one are unchanged
+        while let Some(current) = input.current_id()? {
+            if SortKey::of_id(current) >= SortKey::of_id(id) {
+                break;
+            }
+            let entity = input.advance()?.expect("There is a current entity");
+            stats.unchanged += 1;
+            sink.write_entity(&entity)?;
+        }
+        let exists = input.current_id()? == Some(id);
+        match operation {
+            Operation::Add { entity } if !exists => {
+                stats.added += 1;
+                sink.write_entity(&serialize(&entity)?)?;
+            }
+            Operation::Update { patch, .. } if exists => {
+                let entity = input.advance()?.expect("There is a current entity");
+                let mut entity = parse(id, &entity)?;
+                apply_patch(&mut entity, &patch).map_err(|error| invalid(format!("could not update {}: {}", id, error)))?;
+                stats.updated += 1;
+                sink.write_entity(&serialize(&entity)?)?;
+            }
+            Operation::Delete { .. } if exists => {
+                input.advance()?;
+                stats.deleted += 1;
+            }
+            Operation::Add { .. } => return Err(invalid(format!("could not add {}, which is already in {:?}", id, input.path))),
+            _ => return Err(invalid(format!("could not apply the delta to {}, which isn't in {:?}", id, input.path))),
+        }
+        previous = Some(id.to_string());
+        progress.set_entities((stats.unchanged + stats.updated + stats.deleted) as usize, (stats.unchanged + stats.added + stats.updated) as usize);
+    }
+    while let Some(entity) = input.advance()? {
+        stats.unchanged += 1;
+        sink.write_entity(&entity)?;
+    }
+    sink.finalize()?;
+    progress.finish(format!("{} added, {} updated, {} deleted, {} unchanged", stats.added, stats.updated, stats.deleted, stats.unchanged));
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::sink::{CompressedWriter, Compression, WriteSink};
+
+    #[test]
+    fn test_delta() {
+        let directory = tempfile::tempdir().unwrap();
+        let (old, new, delta_path) = (directory.path().join("old.ndjson"), directory.path().join("new.ndjson"), directory.path().join("delta.ndjson.gz"));
+        fs::write(&old, "{\"id\":\"Q1\",\"a\":1}\n{\"id\":\"Q2\",\"a\":2}\n{\"id\":\"Q3\"}\n{\"id\":\"Q10\"}\n").unwrap();
+        fs::write(&new, "{\"id\":\"Q2\",\"a\":3}\n{ \"id\": \"Q3\" }\n{\"id\":\"Q4\",\"a\":4}\n{\"id\":\"Q10\"}\n{\"id\":\"Q11\"}\n").unwrap();
+
+        let mut output = Vec::new();
+        let stats = delta(&old, &new, Progress::Hidden, &mut output).unwrap();
+        assert_eq!(stats, DeltaStats { added: 2, updated: 1, deleted: 1, unchanged: 2 });
+        assert_eq!(String::from_utf8(output.clone()).unwrap(), concat!(
+            "{\"op\":\"delete\",\"id\":\"Q1\"}\n",
+            "{\"op\":\"update\",\"id\":\"Q2\",\"patch\":[{\"op\":\"replace\",\"path\":\"/a\",\"value\":3}]}\n",
+            "{\"op\":\"add\",\"entity\":{\"a\":4,\"id\":\"Q4\"}}\n",
+            "{\"op\":\"add\",\"entity\":{\"id\":\"Q11\"}}\n",
+        ));
+
+        let mut compressed = CompressedWriter::new(Vec::new(), Compression::Gzip);
+        compressed.write_all(&output).unwrap();
+        fs::write(&delta_path, compressed.finish().unwrap()).unwrap();
+        let mut patched = Vec::new();
+        let stats = apply_delta(&old, &delta_path, Progress::Hidden, &mut WriteSink::new(&mut patched, 64)).unwrap();
+        assert_eq!(stats, DeltaStats { added: 2, updated: 1, deleted: 1, unchanged: 2 });
+        assert_eq!(String::from_utf8(patched).unwrap(), "{\"id\":\"Q2\",\"a\":3}\n{\"id\":\"Q3\"}\n{\"id\":\"Q4\",\"a\":4}\n{\"id\":\"Q10\"}\n{\"id\":\"Q11\"}\n");
+
+        // made from another version
+        assert!(apply_delta(&new, &delta_path, Progress::Hidden, &mut WriteSink::new(Vec::new(), 64)).is_err());
+    }
+
+    #[test]
+    fn test_unsorted() {
+        let directory = tempfile::tempdir().unwrap();
+        let (old, new) = (directory.path().join("old.ndjson"), directory.path().join("new.ndjson"));
+        fs::write(&old, "{\"id\":\"Q2\"}\n{\"id\":\"Q1\"}\n").unwrap();
+        fs::write(&new, "{\"id\":\"Q1\"}\n").unwrap();
+        assert!(delta(&old, &new, Progress::Hidden, Vec::new()).is_err());
+    }
+}
@@ -0,0 +1,197 @@
+/*!
+ * Compressing outputs with a zstd dictionary trained on a sample of their
+ * entities (with the `zstd` feature). Entities share most of their structure
+ * (keys, datatypes, common property ids), which small compressed shards can't
+ * make use of on their own, so a shared dictionary makes them much smaller.
+ *
+ * The first entities written are held back until there are enough to train
+ * the dictionary on, then written along with everything after them. The
+ * dictionary is needed to decompress the outputs again, e.g.
+ * `zstd -D out.dict -d out.3.ndjson.zst`.
+ */
+
+use std::io::{self, Write};
+use log::{debug, warn};
+use zstd::stream::write::Encoder;
+use crate::error::{ProcessError, Result};
+use crate::sink::Sink;
+
+/// How many entities are sampled to train the dictionary by default
+pub const DEFAULT_SAMPLE_SIZE: usize = 10_000;
+
+/// The largest dictionary trained, zstd's own default of 110 KiB
+pub const DICTIONARY_SIZE: usize = 112_640;
+
+/// Trains a dictionary of at most `max_size` bytes on `samples`
+pub fn train<S: AsRef<[u8]>>(samples: &[S], max_size: usize) -> io::Result<Vec<u8>> {
+    zstd::dict::from_samples(samples, max_size)
+}
+
+/// Writes each entity's output as a line of zstd compressed ndjson, using a dictionary if there is one
+pub struct ZstdSink<W: Write> {
+    encoder: Option<Encoder<'static, W>>,
+}
+
+impl<W: Write> ZstdSink<W> {
+    /// Compresses to `output` with `dictionary`, which can be empty to compress without one
+    pub fn new(output: W, dictionary: &[u8]) -> Result<Self> {
+        let encoder = Encoder::with_dictionary(output, zstd::DEFAULT_COMPRESSION_LEVEL, dictionary).map_err(ProcessError::Write)?;
+        Ok(ZstdSink { encoder: Some(encoder) })
+    }
+
+    fn encoder(&mut self) -> Result<&mut Encoder<'static, W>> {
+        self.encoder.as_mut().ok_or_else(|| ProcessError::Write(io::Error::other("Output already finished")))
+    }
+}
+
+impl<W: Write> Sink for ZstdSink<W> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let encoder = self.encoder()?;
+        encoder.write_all(output.as_bytes()).map_err(ProcessError::Write)?;
+        encoder.write_all(b"\n").map_err(ProcessError::Write)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self.encoder.as_mut() {
+            Some(encoder) => encoder.flush().map_err(ProcessError::Write),
+            None => Ok(()),
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        match self.encoder.take() {
+            Some(encoder) => encoder.finish().and_then(|mut output| output.flush()).map_err(ProcessError::Write),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Holds back the first entities until a dictionary has been trained on them, then hands them and every
+/// entity after them to the sink `open` makes with the dictionary
+pub struct DictionarySink<S: Sink, F: FnOnce(&[u8]) -> Result<S>> {
+    open: Option<F>,
+    sink: Option<S>,
+    samples: Vec<String>,
+    sample_size: usize,
+}
+
+impl<S: Sink, F: FnOnce(&[u8]) -> Result<S>> DictionarySink<S, F> {
+    /// Trains on the first `sample_size` entities, or all of them if there are fewer. If there are too few to
+    /// train a dictionary on, `open` gets an empty one
+    pub fn new(sample_size: usize, open: F) -> Self {
+        DictionarySink { open: Some(open), sink: None, samples: Vec::new(), sample_size: sample_size.max(1) }
+    }
+
+    // trains the dictionary and writes the samples
+    fn start(&mut self) -> Result<&mut S> {
+        if self.sink.is_none() {
+            let dictionary = match train(&self.samples, DICTIONARY_SIZE) {
+                Ok(dictionary) => {
+                    debug!("Trained a {} byte dictionary on {} entities", dictionary.len(), self.samples.len());
+                    dictionary
+                }
+                Err(error) => {
+                    warn!("Could not train a dictionary on {} entities, compressing without one: {}", self.samples.len(), error);
+                    Vec::new()
+                }
+            };
+            let open = self.open.take().expect("The sink is only opened once");
+            let mut sink = open(&dictionary)?;
+            for sample in self.samples.drain(..) {
+                sink.write_entity(&sample)?;
+            }
+            self.sink = Some(sink);
+        }
+        Ok(self.sink.as_mut().expect("The sink was just opened"))
+    }
+}
+
+impl<S: Sink, F: FnOnce(&[u8]) -> Result<S>> Sink for DictionarySink<S, F> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        match self.sink.as_mut() {
+            Some(sink) => sink.write_entity(output),
+            None => {
+                self.samples.push(output.to_string());
+                if self.samples.len() >= self.sample_size {
+                    self.start()?;
+                }
+                Ok(())
+            }
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match self.sink.as_mut() {
+            Some(sink) => sink.flush(),
+            None => Ok(()),
+        }
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.start()?.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use crate::shard::ShardedSink;
+
+    fn entity(n: usize) -> String {
+        format!(r#"{{"type":"item","id":"Q{}","labels":{{"en":{{"language":"en","value":"Item {}"}}}},"claims":{{"P31":[{{"mainsnak":{{"snaktype":"value","property":"P31","datavalue":{{"value":{{"entity-type":"item","numeric-id":5,"id":"Q5"}},"type":"wikibase-entityid"}},"datatype":"wikibase-item"}},"type":"statement","rank":"normal"}}]}}}}"#, n, n)
+    }
+
+    fn decompress(compressed: &[u8], dictionary: &[u8]) -> String {
+        let mut decompressed = String::new();
+        zstd::stream::read::Decoder::with_dictionary(compressed, dictionary).unwrap().read_to_string(&mut decompressed).unwrap();
+        decompressed
+    }
+
+    #[test]
+    fn test_dictionary_sink() {
+        let mut shards = vec![Vec::new(); 100];
+        let mut dictionary = Vec::new();
+        {
+            let mut sink = DictionarySink::new(500, |trained: &[u8]| {
+                dictionary = trained.to_vec();
+                let sinks = shards.iter_mut().map(|shard| ZstdSink::new(shard, trained)).collect::<Result<Vec<_>>>()?;
+                Ok(ShardedSink::new(sinks))
+            });
+            for n in 1..=2000 {
+                sink.write_entity(&entity(n)).unwrap();
+            }
+            sink.finalize().unwrap();
+        }
+        assert!(!dictionary.is_empty());
+
+        let mut ids: Vec<String> = shards.iter()
+            .flat_map(|shard| decompress(shard, &dictionary).lines().map(String::from).collect::<Vec<_>>())
+            .collect();
+        ids.sort_by_key(|line| crate::splitter::entity_id(line).unwrap()[1..].parse::<usize>().unwrap());
+        assert_eq!(ids, (1..=2000).map(entity).collect::<Vec<_>>());
+
+        // small shards are smaller with the dictionary than compressed on their own
+        let mut plain = Vec::new();
+        let mut sink = ZstdSink::new(&mut plain, &[]).unwrap();
+        for n in (1..=2000).filter(|&n| crate::shard::shard_of(&entity(n), 100) == 0) {
+            sink.write_entity(&entity(n)).unwrap();
+        }
+        sink.finalize().unwrap();
+        assert!(shards[0].len() < plain.len());
+    }
+
+    #[test]
+    fn test_too_few_samples() {
+        let mut output = Vec::new();
+        {
+            let mut sink = DictionarySink::new(500, |trained: &[u8]| {
+                assert!(trained.is_empty());
+                ZstdSink::new(&mut output, trained)
+            });
+            sink.write_entity(&entity(1)).unwrap();
+            sink.finalize().unwrap();
+        }
+        assert_eq!(decompress(&output, &[]), format!("{}\n", entity(1)));
+    }
+}
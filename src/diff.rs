@@ -0,0 +1,305 @@
+/*!
+ * Comparing two versions of a dump, or two outputs of `filter`, entity by
+ * entity, so that downstream systems can apply what changed instead of
+ * reloading everything.
+ *
+ * Neither input has to be sorted: the ids of the old one are kept in memory
+ * along with a hash of each entity, and the new one is streamed against them.
+ * Entities are compared as JSON values, so the same entity written with
+ * different whitespace or key order, e.g. once passed through jq, is unchanged.
+ */
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::io::{self, Write};
+use std::path::Path;
+use log::warn;
+use serde::Serialize;
+use serde_json::{json, Value};
+use crate::error::{ProcessError, Result};
+use crate::index::parse_id;
+use crate::progress::{Progress, Reporter};
+use crate::reader::EntityFile;
+use crate::splitter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ChangeKind {
+    Added,
+    Removed,
+    Changed,
+}
+
+/// An entity which differs between the two inputs, serialized as e.g. `{"id":"Q42","change":"added"}`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Change {
+    pub id: String,
+    pub change: ChangeKind,
+    /// JSON Patch (RFC 6902) turning the old entity into the new one, for changed entities when asked for
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub patch: Option<Vec<Value>>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct DiffStats {
+    pub added: u64,
+    pub removed: u64,
+    pub changed: u64,
+    pub unchanged: u64,
+    /// Entities of either input without an id, which can't be compared
+    pub skipped: u64,
+}
+
+// feeds whatever is written to it to a hasher
+struct HashWriter(DefaultHasher);
+
+impl Write for HashWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// hashes the entity as its compact JSON with sorted keys, or as it is if it isn't valid JSON
+fn hash(entity: &str) -> u64 {
+    let mut hasher = HashWriter(DefaultHasher::new());
+    match serde_json::from_str::<Value>(entity) {
+        Ok(value) => serde_json::to_writer(&mut hasher, &value).expect("Hashing can't fail"),
+        Err(_) => entity.hash(&mut hasher.0),
+    }
+    hasher.0.finish()
+}
+
+// calls `f` with the id and text of each entity in the file at `path`, returning how many had no id
+fn scan(path: &Path, progress: Progress, name: &str, mut f: impl FnMut(&str, &str) -> Result<()>) -> Result<u64> {
+    let mut entities = EntityFile::open(path)?;
+    let progress = Reporter::new(progress, Some(entities.size()), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+    progress.set_draw_rate(1);
+    let (mut read, mut skipped) = (0, 0);
+    while let Some(entity) = entities.next() {
+        let entity = entity.map_err(ProcessError::Read)?;
+        read += 1;
+        match splitter::entity_id(&entity) {
+            Some(id) => f(id, &entity)?,
+            None => {
+                warn!("Could not find the id of entity {} of {:?}", read, path);
+                skipped += 1;
+            }
+        }
+        progress.set_position(entities.position());
+        progress.set_entities(read, read - skipped);
+    }
+    progress.finish(format!("Read {} {} entities", read, name));
+    Ok(skipped as u64)
+}
+
+/// Compares the entities of `old` and `new`, which may be dumps or `filter` outputs, passing each one which
+/// differs to `output`.
+///
+/// Added and changed entities come in the order of `new`, followed by the removed ones sorted by id. With
+/// `patches`, changed entities come last instead, in the order of `old`, as finding their patches takes
+/// another pass over it, holding the new version of every changed entity in memory until then.
+pub fn diff(old: &Path, new: &Path, patches: bool, progress: Progress, mut output: impl FnMut(Change) -> Result<()>) -> Result<DiffStats> {
+    let mut stats = DiffStats::default();
+    let mut old_entities = HashMap::new();
+    stats.skipped += scan(old, progress, "old", |id, entity| {
+        old_entities.insert(id.to_string(), hash(entity));
+        Ok(())
+    })?;
+
+    let mut changed = HashMap::new();
+    let skipped = scan(new, progress, "new", |id, entity| {
+        match old_entities.remove(id) {
+            None => {
+                stats.added += 1;
+                output(Change { id: id.to_string(), change: ChangeKind::Added, patch: None })?;
+            }
+            Some(old_hash) if old_hash == hash(entity) => stats.unchanged += 1,
+            Some(_) => {
+                stats.changed += 1;
+                if patches {
+                    changed.insert(id.to_string(), entity.to_string());
+                } else {
+                    output(Change { id: id.to_string(), change: ChangeKind::Changed, patch: None })?;
+                }
+            }
+        }
+        Ok(())
+    })?;
+    stats.skipped += skipped;
+
+    let mut removed = old_entities.into_keys().collect::<Vec<_>>();
+    removed.sort_by(|a, b| (parse_id(a), a).cmp(&(parse_id(b), b)));
+    stats.removed = removed.len() as u64;
+    for id in removed {
+        output(Change { id, change: ChangeKind::Removed, patch: None })?;
+    }
+
+    if !changed.is_empty() {
+        scan(old, progress, "old", |id, entity| {
+            if let Some(new_entity) = changed.remove(id) {
+                let patch = json_patch(&parse(id, entity)?, &parse(id, &new_entity)?);
+                output(Change { id: id.to_string(), change: ChangeKind::Changed, patch: Some(patch) })?;
+            }
+            Ok(())
+        })?;
+    }
+    Ok(stats)
+}
+
+fn parse(id: &str, entity: &str) -> Result<Value> {
+    serde_json::from_str(entity).map_err(|error| {
+        ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, format!("entity {} is not valid JSON: {}", id, error)))
+    })
+}
+
+/// The JSON Patch (RFC 6902) operations turning `old` into `new`.
+///
+/// Objects are compared key by key and arrays of the same length item by item, anything else which
+/// differs is replaced as a whole.
+pub fn json_patch(old: &Value, new: &Value) -> Vec<Value> {
+    let mut patch = Vec::new();
+    add_operations(old, new, &mut String::new(), &mut patch);
+    patch
+}
+
+fn add_operations(old: &Value, new: &Value, path: &mut String, patch: &mut Vec<Value>) {
+    let length = path.len();
+    match (old, new) {
+        (Value::Object(old), Value::Object(new)) => {
+            for (key, old_value) in old {
+                push_token(path, key);
+                match new.get(key) {
+                    Some(new_value) => add_operations(old_value, new_value, path, patch),
+                    None => patch.push(json!({ "op": "remove", "path": path })),
+                }
+                path.truncate(length);
+            }
+            for (key, new_value) in new.iter().filter(|(key, _)| !old.contains_key(*key)) {
+                push_token(path, key);
+                patch.push(json!({ "op": "add", "path": path, "value": new_value }));
+                path.truncate(length);
+            }
+        }
+        (Value::Array(old), Value::Array(new)) if old.len() == new.len() => {
+            for (i, (old_value, new_value)) in old.iter().zip(new).enumerate() {
+                push_token(path, &i.to_string());
+                add_operations(old_value, new_value, path, patch);
+                path.truncate(length);
+            }
+        }
+        _ if old == new => {}
+        _ => patch.push(json!({ "op": "replace", "path": path, "value": new })),
+    }
+}
+
+/// Applies the JSON Patch (RFC 6902) `patch` to `value`, as made by `json_patch`: add, remove and replace
+/// operations on object keys and array items, or replacing the whole value
+pub fn apply_patch(value: &mut Value, patch: &[Value]) -> std::result::Result<(), String> {
+    for operation in patch {
+        let (op, path) = match (operation["op"].as_str(), operation["path"].as_str()) {
+            (Some(op), Some(path)) => (op, path),
+            _ => return Err(format!("invalid operation {}", operation)),
+        };
+        let new_value = || operation.get("value").cloned().ok_or_else(|| format!("no value to {} at {}", op, path));
+        let (parent, token) = match path.rsplit_once('/') {
+            Some((parent, token)) => (parent, token.replace("~1", "/").replace("~0", "~")),
+            None if path.is_empty() && matches!(op, "add" | "replace") => {
+                *value = new_value()?;
+                continue;
+            }
+            None => return Err(format!("invalid path {:?}", path)),
+        };
+        let missing = || format!("nothing to {} at {}", op, path);
+        match (op, value.pointer_mut(parent).ok_or_else(missing)?) {
+            ("add", Value::Object(object)) => {
+                object.insert(token, new_value()?);
+            }
+            ("replace", Value::Object(object)) => {
+                *object.get_mut(&token).ok_or_else(missing)? = new_value()?;
+            }
+            ("remove", Value::Object(object)) => {
+                object.remove(&token).ok_or_else(missing)?;
+            }
+            ("add", Value::Array(array)) if token == "-" => array.push(new_value()?),
+            (_, Value::Array(array)) => {
+                let index = token.parse::<usize>().map_err(|_| missing())?;
+                match op {
+                    "add" if index <= array.len() => array.insert(index, new_value()?),
+                    "replace" if index < array.len() => array[index] = new_value()?,
+                    "remove" if index < array.len() => {
+                        array.remove(index);
+                    }
+                    _ => return Err(missing()),
+                }
+            }
+            _ => return Err(format!("unsupported operation {} at {}", op, path)),
+        }
+    }
+    Ok(())
+}
+
+// appends a JSON Pointer (RFC 6901) reference token
+fn push_token(path: &mut String, token: &str) {
+    path.push('/');
+    path.push_str(&token.replace('~', "~0").replace('/', "~1"));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_diff() {
+        let directory = tempfile::tempdir().unwrap();
+        let (old, new) = (directory.path().join("old.ndjson"), directory.path().join("new.ndjson"));
+        fs::write(&old, "{\"id\":\"Q1\",\"a\":1}\n{\"id\":\"Q2\",\"a\":2}\n{\"id\":\"Q3\"}\n").unwrap();
+        fs::write(&new, "{ \"id\": \"Q3\" }\n{\"id\":\"Q2\",\"a\":3}\n{\"id\":\"Q4\"}\n").unwrap();
+
+        let mut changes = Vec::new();
+        let stats = diff(&old, &new, false, Progress::Hidden, |change| {
+            changes.push((change.id, change.change));
+            Ok(())
+        }).unwrap();
+        assert_eq!(changes, vec![
+            ("Q2".to_string(), ChangeKind::Changed),
+            ("Q4".to_string(), ChangeKind::Added),
+            ("Q1".to_string(), ChangeKind::Removed),
+        ]);
+        assert_eq!(stats, DiffStats { added: 1, removed: 1, changed: 1, unchanged: 1, skipped: 0 });
+
+        let mut changes = Vec::new();
+        diff(&old, &new, true, Progress::Hidden, |change| {
+            changes.push(change);
+            Ok(())
+        }).unwrap();
+        assert_eq!(serde_json::to_string(&changes[2]).unwrap(), r#"{"id":"Q2","change":"changed","patch":[{"op":"replace","path":"/a","value":3}]}"#);
+    }
+
+    #[test]
+    fn test_json_patch() {
+        let old = json!({"a/b": 1, "list": [1, 2], "gone": true, "same": {"x": 1}});
+        let new = json!({"a/b": 2, "list": [1, 3], "same": {"x": 1}, "new~": null});
+        assert_eq!(json_patch(&old, &new), vec![
+            json!({"op": "replace", "path": "/a~1b", "value": 2}),
+            json!({"op": "remove", "path": "/gone"}),
+            json!({"op": "replace", "path": "/list/1", "value": 3}),
+            json!({"op": "add", "path": "/new~0", "value": null}),
+        ]);
+        assert_eq!(json_patch(&json!([1]), &json!([1, 2])), vec![json!({"op": "replace", "path": "", "value": [1, 2]})]);
+
+        let mut patched = old.clone();
+        apply_patch(&mut patched, &json_patch(&old, &new)).unwrap();
+        assert_eq!(patched, new);
+        let mut list = json!({"list": [1, 2]});
+        apply_patch(&mut list, &[json!({"op": "add", "path": "/list/-", "value": 3}), json!({"op": "remove", "path": "/list/0"})]).unwrap();
+        assert_eq!(list, json!({"list": [2, 3]}));
+        assert!(apply_patch(&mut list, &[json!({"op": "remove", "path": "/missing"})]).is_err());
+    }
+}
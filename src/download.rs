@@ -0,0 +1,80 @@
+/*!
+ * Downloading dumps from dumps.wikimedia.org
+ */
+
+use std::cmp::min;
+use std::fs::File;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::time::Instant;
+use futures_util::StreamExt;
+use indicatif::HumanDuration;
+use log::{debug, info};
+use crate::progress::{Progress, Reporter};
+
+/// URL of the json dump for `version`, e.g. "latest" or "20220404"
+pub fn dump_url(version: &str) -> String {
+    format!("https://dumps.wikimedia.org/wikidatawiki/entities/{}-all.json.bz2", version)
+}
+
+/// Where the json dump for `version` is downloaded to within `directory`
+pub fn dump_path(version: &str, directory: &Path) -> PathBuf {
+    directory.join(format!("{}-all.json.bz2", version))
+}
+
+/// Downloads the json dump for `version` into `directory`, returning the path of the downloaded file.
+///
+/// Fails if the file already exists, unless `overwrite` is set.
+pub async fn download(version: &str, directory: &Path, progress: Progress, overwrite: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    download_url(&dump_url(version), directory, progress, overwrite).await
+}
+
+/// Downloads the file at `url` into `directory`, named as in the URL, returning the path of the downloaded file.
+///
+/// Fails if the file already exists, unless `overwrite` is set.
+pub async fn download_url(url: &str, directory: &Path, progress: Progress, overwrite: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    debug!("URL: {}", url);
+    let res = reqwest::Client::new()
+        .get(url)
+        .send()
+        .await
+        .or(Err(format!("Failed to GET from '{}'", &url)))?;
+
+    let total_size = res
+        .content_length()
+        .ok_or(format!("Failed to get content length from '{}'", &url))?;
+
+    let filename = {
+        let filename = res
+            .url()
+            .path_segments()
+            .and_then(|mut segments| segments.next_back())
+            .and_then(|name| if name.is_empty() { None } else { Some(name) })
+            .ok_or(format!("Failed to get a file name from '{}'", &url))?;
+
+        directory.join(filename)
+    };
+    if filename.exists() && !overwrite {
+        return Err(format!("{:?} already exists, use --force-redownload to download it again", filename).into());
+    }
+    info!("Downloading to {:?}", filename.as_os_str());
+    let mut file = File::create(&filename)?;
+
+    let pb = Reporter::new(progress, Some(total_size), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+
+    let mut downloaded: u64 = 0;
+    let mut stream = res.bytes_stream();
+
+    while let Some(item) = stream.next().await {
+        let chunk = item.or(Err("Error while downloading file".to_string()))?;
+        file.write_all(&chunk)
+            .or(Err("Error while writing to file".to_string()))?;
+        let new = min(downloaded + (chunk.len() as u64), total_size);
+        downloaded = new;
+        pb.set_position(new);
+    }
+
+    pb.finish(format!("Downloaded {} to {:?} in {}", &url, filename, HumanDuration(start.elapsed())));
+    Ok(filename)
+}
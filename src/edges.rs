@@ -0,0 +1,224 @@
+/*!
+ * Graph edge lists: a `(source, property, target)` row for every statement
+ * whose value is an item, the input graph libraries and knowledge graph
+ * embedding training expect. Only the statements of each entity are parsed,
+ * and everything else is skipped over.
+ *
+ * Edge lists are written as TSV, or handed to any other sink as a row per
+ * line, e.g. `parquet_sink::EdgeParquetSink`. With qualifiers, a qualifier
+ * whose value is an item becomes an edge from the entity too, with a fourth
+ * column holding the property of the statement it qualifies (empty for the
+ * statements themselves). Deprecated statements are left out.
+ *
+ * `edge_output` makes the same rows out of the outputs of a jq filter, for
+ * `filter --format edges`.
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Write;
+use log::debug;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::sink::{Sink, WriteSink};
+use crate::source::Source;
+
+// the parts of an entity which end up in edge lists
+#[derive(Deserialize)]
+struct Outline<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(default, borrow)]
+    claims: BTreeMap<Cow<'a, str>, Vec<Statement<'a>>>,
+}
+
+// the parts of a statement which end up in edge lists (and quality reports)
+#[derive(Deserialize)]
+pub(crate) struct Statement<'a> {
+    pub(crate) mainsnak: Snak,
+    #[serde(default, borrow)]
+    rank: Option<Cow<'a, str>>,
+    #[serde(default, borrow)]
+    qualifiers: BTreeMap<Cow<'a, str>, Vec<Snak>>,
+}
+
+impl Statement<'_> {
+    pub(crate) fn is_deprecated(&self) -> bool {
+        self.rank.as_deref() == Some("deprecated")
+    }
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Snak {
+    #[serde(default)]
+    datavalue: Option<DataValue>,
+}
+
+#[derive(Deserialize)]
+struct DataValue {
+    value: Target,
+}
+
+// a datavalue's value, of which only entity ids matter
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Target {
+    Entity {
+        #[serde(rename = "entity-type")]
+        entity_type: String,
+        #[serde(default)]
+        id: Option<String>,
+        #[serde(default, rename = "numeric-id")]
+        numeric_id: Option<u64>,
+    },
+    Other(IgnoredAny),
+}
+
+impl Snak {
+    // the id of the item this snak's value is, if it is one
+    pub(crate) fn item(&self) -> Option<Cow<'_, str>> {
+        match &self.datavalue.as_ref()?.value {
+            Target::Entity { entity_type, id, numeric_id } if entity_type == "item" => match (id, numeric_id) {
+                (Some(id), _) => Some(Cow::Borrowed(id.as_str())),
+                // older items only have their number
+                (None, Some(number)) => Some(Cow::Owned(format!("Q{}", number))),
+                (None, None) => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+// the rows of the edge list of an entity, one per line
+fn rows(outline: &Outline, qualifiers: bool) -> String {
+    let mut rows = String::new();
+    let mut row = |property: &str, target: &str, qualified: Option<&str>| {
+        if !rows.is_empty() {
+            rows.push('\n');
+        }
+        rows.push_str(&outline.id);
+        rows.push('\t');
+        rows.push_str(property);
+        rows.push('\t');
+        rows.push_str(target);
+        if qualifiers {
+            rows.push('\t');
+            rows.push_str(qualified.unwrap_or(""));
+        }
+    };
+    for (property, statements) in &outline.claims {
+        for statement in statements.iter().filter(|statement| !statement.is_deprecated()) {
+            if let Some(target) = statement.mainsnak.item() {
+                row(property, &target, None);
+            }
+            if !qualifiers {
+                continue;
+            }
+            for (qualifier, snaks) in &statement.qualifiers {
+                for target in snaks.iter().filter_map(Snak::item) {
+                    row(qualifier, &target, Some(property));
+                }
+            }
+        }
+    }
+    rows
+}
+
+// turns each entity into its rows of the edge list
+struct EdgeExtractor {
+    qualifiers: bool,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl EntityFilter for EdgeExtractor {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let rows = rows(&outline, self.qualifiers);
+        Ok(Some(rows).filter(|rows| !rows.is_empty()).map(Cow::Owned))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+/// Replaces an output which is a whole entity with its rows of the edge list, or drops it if it has none or isn't
+/// an entity
+pub fn edge_output(output: String, qualifiers: bool) -> Option<String> {
+    let outline: Outline = match serde_json::from_str(&output) {
+        Ok(outline) => outline,
+        Err(error) => {
+            debug!("Not an entity, so no edges ({}): {:.100}", error, output);
+            return None;
+        }
+    };
+    Some(rows(&outline, qualifiers)).filter(|rows| !rows.is_empty())
+}
+
+/// Writes the edge list of every entity of `source` to `output` as TSV, see the module documentation. Rows come
+/// in dump order, and by property within an entity.
+pub fn write_edges(source: impl Source, qualifiers: bool, options: ProcessOptions, output: impl Write) -> Result<ProcessStats> {
+    let sink = WriteSink::new(output, options.write_buffer_size);
+    write_edges_to(source, qualifiers, options, sink)
+}
+
+/// Hands the edge list of every entity of `source` to `sink`, the rows of each entity as the lines of its output
+pub fn write_edges_to(source: impl Source, qualifiers: bool, options: ProcessOptions, sink: impl Sink) -> Result<ProcessStats> {
+    let continue_on_error = options.continue_on_error;
+    Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(EdgeExtractor { qualifiers, continue_on_error, failures: 0 }))
+        .entity_sink(sink)
+        .options(options)
+        .build()?
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item","claims":{
+        "P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":5,"id":"Q5"}}},"rank":"normal",
+            "qualifiers":{"P642":[{"snaktype":"value","property":"P642","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":1}}}],"P580":[{"snaktype":"value","property":"P580","datavalue":{"type":"time","value":{"time":"+2001-00-00T00:00:00Z"}}}]}}],
+        "P1559":[{"mainsnak":{"snaktype":"value","property":"P1559","datavalue":{"type":"monolingualtext","value":{"text":"Douglas Adams","language":"en"}}},"rank":"normal"}],
+        "P1687":[{"mainsnak":{"snaktype":"value","property":"P1687","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"property","id":"P50"}}},"rank":"normal"}],
+        "P735":[{"mainsnak":{"snaktype":"somevalue","property":"P735"},"rank":"normal"},{"mainsnak":{"snaktype":"value","property":"P735","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q463035"}}},"rank":"deprecated"}]
+    }}"#;
+
+    #[test]
+    fn test_edge_rows() {
+        let outline = serde_json::from_str(ENTITY).unwrap();
+        assert_eq!(rows(&outline, false), "Q42\tP31\tQ5");
+        assert_eq!(rows(&outline, true), "Q42\tP31\tQ5\t\nQ42\tP642\tQ1\tP31");
+    }
+
+    #[test]
+    fn test_edge_output() {
+        assert_eq!(edge_output(ENTITY.to_string(), false).as_deref(), Some("Q42\tP31\tQ5"));
+        assert_eq!(edge_output(String::from(r#"{"id":"Q1","claims":{}}"#), false), None);
+        assert_eq!(edge_output(String::from(r#""Q42""#), false), None);
+    }
+
+    #[test]
+    fn test_write_edges() {
+        let mut output = Vec::new();
+        let stats = write_edges(FileSource::new("./tests/test-data.json.bz2"), false, ProcessOptions::default(), &mut output).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.lines().all(|row| row.split('\t').count() == 3), "{}", output);
+    }
+}
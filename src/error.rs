@@ -0,0 +1,114 @@
+/*!
+ * Errors which can stop a run, each carrying enough context to be shown to the user as-is
+ */
+
+use std::io;
+use std::path::PathBuf;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum ProcessError {
+    #[error("No input file given, use --input to pass a dump")]
+    MissingInput,
+
+    #[error("Could not open input {path:?}: {source}")]
+    OpenInput { path: PathBuf, source: io::Error },
+
+    #[error("Could not read input: {0}")]
+    Read(#[source] io::Error),
+
+    #[error("Input ended unexpectedly at compressed byte {compressed_offset} ({reason}), after {entities} complete entities, the last of them {last_id}. The dump is truncated or corrupt, so the output is incomplete")]
+    Truncated { compressed_offset: u64, last_id: String, entities: usize, reason: String },
+
+    #[error("Input is not valid UTF-8 at decompressed byte {offset}")]
+    InvalidUtf8 { offset: u64 },
+
+    #[error("Could not compile jq filter '{filter}': {message}")]
+    FilterCompile { filter: String, message: String },
+
+    #[error("Could not filter entity {id}: {message}. Use --continue-on-error to skip entities which can't be filtered")]
+    Filter { id: String, message: String },
+
+    #[error("Entity {id} is {size} bytes, over the --max-entity-size of {limit}. Use --oversize-policy skip or truncate-claims to get past it")]
+    OversizedEntity { id: String, size: usize, limit: usize },
+
+    #[error("Gave up after {failed} of {read} entities could not be filtered, more than the --max-errors of {budget}. The filter is likely broken")]
+    TooManyErrors { failed: usize, read: usize, budget: String },
+
+    #[error("Output file {0:?} already exists, use --force-overwrite-output to overwrite it")]
+    OutputExists(PathBuf),
+
+    #[error("Could not create output {path:?}: {source}")]
+    CreateOutput { path: PathBuf, source: io::Error },
+
+    #[error("Could not write output: {0}")]
+    Write(#[source] io::Error),
+
+    #[error("Could not access checkpoint {path:?}: {source}")]
+    Checkpoint { path: PathBuf, source: io::Error },
+
+    #[error("Invalid checkpoint {path:?}: {message}")]
+    InvalidCheckpoint { path: PathBuf, message: String },
+
+    #[error("Could not write error report {path:?}: {source}")]
+    ErrorReport { path: PathBuf, source: io::Error },
+
+    #[error("Could not access index {path:?}: {source}")]
+    Index { path: PathBuf, source: io::Error },
+
+    #[error("Invalid index {path:?}: {message}")]
+    InvalidIndex { path: PathBuf, message: String },
+
+    #[error("Could not access manifest {path:?}: {source}")]
+    Manifest { path: PathBuf, source: io::Error },
+
+    #[error("Invalid manifest {path:?}: {message}")]
+    InvalidManifest { path: PathBuf, message: String },
+
+    #[error("Could not build text index {path:?}: {message}")]
+    TextIndex { path: PathBuf, message: String },
+
+    #[error("Could not run SQL query: {0}")]
+    Sql(String),
+
+    #[error("Could not write ClickHouse rows: {0}")]
+    ClickHouse(String),
+
+    #[error("Could not write Parquet: {0}")]
+    Parquet(String),
+
+    #[error("Could not write Turtle: {0}")]
+    Turtle(String),
+
+    #[error("Could not write to Redis: {0}")]
+    Redis(String),
+
+    #[error("Could not write to MongoDB: {0}")]
+    MongoDb(String),
+
+    #[error("Could not write to Kafka: {0}")]
+    Kafka(String),
+
+    #[error("Could not write RocksDB database: {0}")]
+    RocksDb(String),
+
+    #[error("Could not access LMDB database: {0}")]
+    Lmdb(String),
+
+    #[error("Could not write SQLite database: {0}")]
+    Sqlite(String),
+
+    #[error("Could not serve Arrow Flight: {0}")]
+    Flight(String),
+
+    #[error("Could not build DataFrame: {0}")]
+    DataFrame(String),
+
+    #[error("Invalid search query: {0}")]
+    InvalidQuery(String),
+
+    #[error("Invalid pipeline: {0}")]
+    InvalidPipeline(&'static str),
+}
+
+pub type Result<T> = std::result::Result<T, ProcessError>;
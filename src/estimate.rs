@@ -0,0 +1,196 @@
+/*!
+ * Estimating what a run over a whole dump will produce before making it.
+ *
+ * A few samples spread evenly over the dump, each some whole bzip2 streams
+ * (see `plan`), are filtered as a full run would filter them. How many
+ * entities each compressed byte holds, how many of those the filter keeps,
+ * how much output they make and how long that takes, are then scaled up to
+ * the compressed size of the dump. Dumps are sorted by id rather than
+ * shuffled, so the further apart the samples are the better: entities of a
+ * kind clustering in some part of a dump, e.g. lexemes at its end, are missed
+ * by samples taken anywhere else.
+ */
+
+use std::io;
+use std::path::Path;
+use std::time::Duration;
+use log::debug;
+use serde::Serialize;
+use crate::decoder::{self, StreamRange};
+use crate::error::{ProcessError, Result};
+use crate::pipeline::Pipeline;
+use crate::plan;
+use crate::process::{ProcessOptions, ProcessStats};
+
+/// Samples taken by default
+pub const DEFAULT_SAMPLES: usize = 8;
+
+/// Compressed bytes in each sample by default
+pub const DEFAULT_SAMPLE_SIZE: u64 = 16 * 1024 * 1024;
+
+/// What a run over a whole dump is expected to produce, extrapolated from samples of it
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Estimate {
+    /// Compressed size of the dump
+    pub dump_size: u64,
+    /// Compressed bytes sampled, and what the samples had
+    pub sampled_bytes: u64,
+    pub sampled_entities: usize,
+    pub sampled_matches: usize,
+    pub entities: u64,
+    /// Entities with an output
+    pub matches: u64,
+    /// Bytes of outputs, with the newline after each
+    pub output_size: u64,
+    /// Serialized as (fractional) seconds
+    #[serde(serialize_with = "serialize_seconds")]
+    pub runtime: Duration,
+}
+
+fn serialize_seconds<S: serde::Serializer>(duration: &Duration, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+impl Estimate {
+    /// Entities in each compressed MiB of the dump
+    pub fn entities_per_mib(&self) -> f64 {
+        self.entities as f64 / self.dump_size.max(1) as f64 * (1 << 20) as f64
+    }
+
+    /// Share of entities with an output, from 0 to 1
+    pub fn match_rate(&self) -> f64 {
+        self.sampled_matches as f64 / self.sampled_entities.max(1) as f64
+    }
+}
+
+/// Picks `samples` ranges of whole streams spread evenly over the dump at `path`, each starting at the first stream
+/// at or after its share of the dump, and ending at the first one after `sample_size` bytes. Samples which would
+/// overlap the one before are left out, so a small dump can have fewer. Returns them along with the size of the dump.
+pub fn sample_ranges(path: &Path, samples: usize, sample_size: u64) -> Result<(Vec<StreamRange>, u64)> {
+    let (mut file, size) = decoder::open(path)?;
+    let mut ranges: Vec<StreamRange> = Vec::new();
+    for i in 0..samples.max(1) as u64 {
+        let offset = (size as u128 * i as u128 / samples.max(1) as u128) as u64;
+        let offset = offset.max(ranges.last().map_or(0, |range| range.end));
+        let start = match plan::next_stream(&mut file, offset).map_err(ProcessError::Read)? {
+            Some(start) if start < size => start,
+            _ => break,
+        };
+        let end = plan::next_stream(&mut file, start.saturating_add(sample_size.max(1))).map_err(ProcessError::Read)?.unwrap_or(size);
+        ranges.push(StreamRange { start, end });
+    }
+    if ranges.is_empty() {
+        return Err(ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is not a bzip2 compressed dump", path))));
+    }
+    Ok((ranges, size))
+}
+
+/// Scales what the runs over `samples` produced up to a dump of `dump_size` compressed bytes
+pub fn extrapolate(dump_size: u64, samples: &[(StreamRange, ProcessStats)]) -> Estimate {
+    let sampled_bytes: u64 = samples.iter().map(|(range, _)| range.end - range.start).sum();
+    let sampled_entities = samples.iter().map(|(_, stats)| stats.entities_read).sum();
+    let sampled_matches = samples.iter().map(|(_, stats)| stats.entities_written).sum();
+    let sampled_output: u64 = samples.iter().map(|(_, stats)| stats.bytes_out).sum();
+    let sampled_duration: Duration = samples.iter().map(|(_, stats)| stats.duration).sum();
+    let scale = if sampled_bytes > 0 { dump_size as f64 / sampled_bytes as f64 } else { 0.0 };
+    let scaled = |sampled: f64| (sampled * scale).round() as u64;
+    Estimate {
+        dump_size,
+        sampled_bytes,
+        sampled_entities,
+        sampled_matches,
+        entities: scaled(sampled_entities as f64),
+        matches: scaled(sampled_matches as f64),
+        output_size: scaled(sampled_output as f64),
+        runtime: sampled_duration.mul_f64(scale),
+    }
+}
+
+/// Estimates what filtering the dump at `path` with `jq_filter` and `options` would produce, from `samples` samples of
+/// `sample_size` compressed bytes each, see the module documentation. The samples aren't checkpointed or reported on.
+pub fn estimate(path: &Path, jq_filter: &str, options: &ProcessOptions, samples: usize, sample_size: u64) -> Result<Estimate> {
+    estimate_with(path, samples, sample_size, |range| {
+        Pipeline::builder()
+            .source(path)
+            .filter(jq_filter)
+            .sink(io::sink())
+            .options(ProcessOptions { range: Some(range), checkpoint: None, error_report: None, ..options.clone() })
+            .build()?
+            .run()
+    })
+}
+
+/// Estimates what a run over the dump at `path` would produce as `estimate` does, with `run` filtering each sample,
+/// for runs made of more than a jq filter. `run` should write nothing, and process only the range it's given.
+pub fn estimate_with(path: &Path, samples: usize, sample_size: u64, mut run: impl FnMut(StreamRange) -> Result<ProcessStats>) -> Result<Estimate> {
+    let (ranges, size) = sample_ranges(path, samples, sample_size)?;
+    let mut sampled = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        let stats = run(range)?;
+        debug!("Sampled compressed bytes {} to {}: {} entities, {} with an output", range.start, range.end, stats.entities_read, stats.entities_written);
+        let cancelled = stats.cancelled;
+        sampled.push((range, stats));
+        if cancelled {
+            break;
+        }
+    }
+    Ok(extrapolate(size, &sampled))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::plan::tests::multistream_dump;
+    use crate::progress::Progress;
+
+    #[test]
+    fn test_sample_ranges() {
+        let directory = tempfile::tempdir().unwrap();
+        let dump = directory.path().join("dump.json.bz2");
+        multistream_dump(&dump, 200);
+        let (streams, size) = plan::find_streams(&dump, Progress::Hidden).unwrap();
+
+        let (ranges, sampled_size) = sample_ranges(&dump, 4, 1).unwrap();
+        assert_eq!(sampled_size, size);
+        assert_eq!(ranges.len(), 4);
+        assert_eq!(ranges[0], StreamRange { start: 0, end: streams[1] });
+        assert!(ranges.iter().all(|range| streams.contains(&range.start) && (streams.contains(&range.end) || range.end == size)));
+        assert!(ranges.windows(2).all(|pair| pair[0].end <= pair[1].start));
+
+        // samples bigger than the dump leave a single one, of all of it
+        assert_eq!(sample_ranges(&dump, 4, size).unwrap().0, [StreamRange { start: 0, end: size }]);
+        assert!(sample_ranges(Path::new("./Cargo.toml"), 4, 1).is_err());
+    }
+
+    #[test]
+    fn test_estimate() {
+        let directory = tempfile::tempdir().unwrap();
+        let dump = directory.path().join("dump.json.bz2");
+        multistream_dump(&dump, 200);
+        let options = ProcessOptions { progress: Progress::Hidden, ..ProcessOptions::default() };
+
+        // sampling all of the dump counts exactly
+        let whole = estimate(&dump, r#"select(.type == "item") | .id"#, &options, 1, u64::MAX).unwrap();
+        assert_eq!(whole.sampled_bytes, whole.dump_size);
+        assert_eq!((whole.entities, whole.matches), (8, 7));
+        // "Q1" to "Q6" and "Q60", each on a line
+        assert_eq!(whole.output_size, 6 * 5 + 6);
+
+        let sampled = estimate(&dump, r#"select(.type == "item") | .id"#, &options, 3, 1).unwrap();
+        assert!(sampled.sampled_bytes < sampled.dump_size);
+        assert!(sampled.sampled_entities > 0);
+        assert!((2..=30).contains(&sampled.entities), "{} entities", sampled.entities);
+        assert!(sampled.matches <= sampled.entities);
+    }
+
+    #[test]
+    fn test_extrapolate() {
+        let stats = ProcessStats { entities_read: 100, entities_written: 10, bytes_out: 1000, duration: Duration::from_secs(2), ..ProcessStats::default() };
+        let estimate = extrapolate(4000, &[(StreamRange { start: 0, end: 500 }, stats.clone()), (StreamRange { start: 2000, end: 2500 }, stats)]);
+        assert_eq!((estimate.sampled_bytes, estimate.sampled_entities, estimate.sampled_matches), (1000, 200, 20));
+        assert_eq!((estimate.entities, estimate.matches, estimate.output_size), (800, 80, 8000));
+        assert_eq!(estimate.runtime, Duration::from_secs(16));
+        assert_eq!(estimate.match_rate(), 0.1);
+        assert_eq!(extrapolate(4000, &[]), Estimate { dump_size: 4000, ..Estimate::default() });
+    }
+}
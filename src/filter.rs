@@ -0,0 +1,303 @@
+/*!
+ * Applying filters to individual entities and writing out the results. jq is
+ * the built-in engine, other engines can be plugged in by implementing
+ * `EntityFilter`.
+ */
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use jq_rs::JqProgram;
+use log::{debug, info};
+use crate::error::{ProcessError, Result};
+use crate::splitter;
+
+/// A filter engine, applied to each entity in turn on one of the filtering threads
+pub trait EntityFilter {
+    /// Returns the output for a single raw entity, or `None` if there is nothing to write.
+    ///
+    /// Borrowed output is written followed by a newline, owned output may span several lines.
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>>;
+
+    /// The number of entities which couldn't be filtered and were skipped so far
+    fn failures(&self) -> usize {
+        0
+    }
+
+    /// Why the last entity skipped couldn't be filtered, if it hasn't been taken yet
+    fn take_error(&mut self) -> Option<String> {
+        None
+    }
+}
+
+impl<F: EntityFilter + ?Sized> EntityFilter for Box<F> {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        (**self).apply(raw)
+    }
+
+    fn failures(&self) -> usize {
+        (**self).failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        (**self).take_error()
+    }
+}
+
+/// Creates a filter for each filtering thread, as filters (jq included) generally can't be shared between threads
+pub type FilterFactory = Arc<dyn Fn() -> Result<Box<dyn EntityFilter>> + Send + Sync>;
+
+/// A factory for `JqFilter`s, see `JqFilter::new`
+pub fn jq_filter_factory(jq_filter: &str, continue_on_error: bool, pass_through: bool) -> FilterFactory {
+    let jq_filter = jq_filter.to_string();
+    Arc::new(move || Ok(Box::new(JqFilter::new(&jq_filter, continue_on_error, pass_through)?) as Box<dyn EntityFilter>))
+}
+
+/// Whether `jq_filter` leaves entities unchanged, in which case jq can be skipped entirely
+pub fn is_identity_filter(jq_filter: &str) -> bool {
+    jq_filter.trim() == "."
+}
+
+/// Compiles `jq_filter`, returning `None` for the identity filter
+pub fn compile(jq_filter: &str) -> Result<Option<JqProgram>> {
+    if is_identity_filter(jq_filter) {
+        return Ok(None);
+    }
+    jq_rs::compile(jq_filter)
+        .map(Some)
+        .map_err(|error| ProcessError::FilterCompile { filter: jq_filter.to_string(), message: error.to_string() })
+}
+
+// in pass-through mode an entity is kept if the filter produced anything other than `false` or `null`
+fn is_kept(filtered_entity: &str) -> bool {
+    filtered_entity.lines().any(|line| !matches!(line.trim(), "" | "false" | "null"))
+}
+
+/// The output for a single entity
+pub enum Output<'a> {
+    /// The entity exactly as it appears in the dump
+    Raw(&'a str),
+    /// jq's output, ending in a newline
+    Filtered(String),
+}
+
+impl<'a> From<Cow<'a, str>> for Output<'a> {
+    fn from(output: Cow<'a, str>) -> Self {
+        match output {
+            Cow::Borrowed(entity) => Output::Raw(entity),
+            Cow::Owned(mut filtered_entity) => {
+                if !filtered_entity.ends_with('\n') {
+                    filtered_entity.push('\n');
+                }
+                Output::Filtered(filtered_entity)
+            }
+        }
+    }
+}
+
+impl<'a> Output<'a> {
+    /// Writes the output as one or more lines
+    pub fn write_to(&self, stream: &mut impl Write) -> std::io::Result<()> {
+        match self {
+            // entities in the dump are already on a single line, so they can be copied as-is to make ndjson
+            Output::Raw(entity) => {
+                stream.write_all(entity.as_bytes())?;
+                stream.write_all(b"\n")
+            }
+            Output::Filtered(filtered_entity) => stream.write_all(filtered_entity.as_bytes()),
+        }
+    }
+
+    /// Appends the output to `buffer`, ending in a newline
+    pub fn push_to(&self, buffer: &mut String) {
+        match self {
+            Output::Raw(entity) => {
+                buffer.push_str(entity);
+                buffer.push('\n');
+            }
+            Output::Filtered(filtered_entity) => {
+                buffer.push_str(filtered_entity);
+                if !filtered_entity.ends_with('\n') {
+                    buffer.push('\n');
+                }
+            }
+        }
+    }
+
+    /// The output without its trailing newline
+    pub fn into_string(self) -> String {
+        match self {
+            Output::Raw(entity) => entity.to_string(),
+            Output::Filtered(mut filtered_entity) => {
+                if filtered_entity.ends_with('\n') {
+                    filtered_entity.pop();
+                }
+                filtered_entity
+            }
+        }
+    }
+}
+
+/// A compiled jq filter along with how its results are turned into output
+pub struct JqFilter {
+    // `None` for the identity filter, which can't change an entity, so jq is skipped entirely
+    program: Option<JqProgram>,
+    continue_on_error: bool,
+    pass_through: bool,
+    failures: usize,
+    error: Option<String>,
+}
+
+impl JqFilter {
+    /// Compiles `jq_filter`. With `continue_on_error`, entities jq can't handle are logged, counted
+    /// and come out as `null` rather than failing. In `pass_through` mode the filter only decides
+    /// whether an entity is kept, and kept entities are output as-is.
+    pub fn new(jq_filter: &str, continue_on_error: bool, pass_through: bool) -> Result<Self> {
+        Ok(JqFilter { program: compile(jq_filter)?, continue_on_error, pass_through, failures: 0, error: None })
+    }
+
+    /// Writes the output for a single entity, returning whether anything was written, see `EntityFilter::apply`
+    pub fn write_entity(&mut self, entity: &str, stream: &mut impl Write) -> Result<bool> {
+        match self.apply(entity)? {
+            Some(output) => Output::from(output).write_to(stream).map(|_| true).map_err(ProcessError::Write),
+            None => Ok(false),
+        }
+    }
+}
+
+impl EntityFilter for JqFilter {
+    fn apply<'a>(&mut self, entity: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let filtered_entity = match self.program.as_mut() {
+            Some(program) => match run(entity, program) {
+                Ok(filtered_entity) => filtered_entity,
+                Err(error) => {
+                    skip_entity(entity, &error, self.continue_on_error)?;
+                    self.failures += 1;
+                    self.error = Some(error);
+                    String::from("null\n")
+                }
+            },
+            None => return Ok(Some(Cow::Borrowed(entity))),
+        };
+
+        if self.pass_through {
+            Ok(is_kept(&filtered_entity).then_some(Cow::Borrowed(entity)))
+        }
+        else {
+            Ok((!filtered_entity.is_empty()).then_some(Cow::Owned(filtered_entity)))
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take()
+    }
+}
+
+/// How many entities the filters sharing it were applied to, and how many they output something for
+#[derive(Debug, Default)]
+pub struct FilterCounts {
+    applied: AtomicUsize,
+    output: AtomicUsize,
+}
+
+impl FilterCounts {
+    pub fn applied(&self) -> usize {
+        self.applied.load(Ordering::Relaxed)
+    }
+
+    pub fn output(&self) -> usize {
+        self.output.load(Ordering::Relaxed)
+    }
+}
+
+/// Counts the entities another filter is applied to and outputs something for, adding them to the shared counts
+/// once dropped, i.e. once the run is over
+pub struct CountingFilter<F: EntityFilter> {
+    filter: F,
+    applied: usize,
+    output: usize,
+    total: Arc<FilterCounts>,
+}
+
+impl<F: EntityFilter> CountingFilter<F> {
+    pub fn new(filter: F, total: Arc<FilterCounts>) -> Self {
+        CountingFilter { filter, applied: 0, output: 0, total }
+    }
+}
+
+impl<F: EntityFilter> EntityFilter for CountingFilter<F> {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        self.applied += 1;
+        let output = self.filter.apply(raw)?;
+        self.output += output.is_some() as usize;
+        Ok(output)
+    }
+
+    fn failures(&self) -> usize {
+        self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.filter.take_error()
+    }
+}
+
+impl<F: EntityFilter> Drop for CountingFilter<F> {
+    fn drop(&mut self) {
+        self.total.applied.fetch_add(self.applied, Ordering::Relaxed);
+        self.total.output.fetch_add(self.output, Ordering::Relaxed);
+    }
+}
+
+// jq's output for a single entity, or why it couldn't be filtered
+fn run(entity: &str, program: &mut JqProgram) -> std::result::Result<String, String> {
+    debug!("{}", entity);
+    let filtered_entity = program.run(entity).map_err(|error| error.to_string())?;
+    debug!("{}", filtered_entity);
+    debug!("---");
+    Ok(filtered_entity)
+}
+
+/// Fails on an entity which couldn't be filtered, or with `continue_on_error` logs its id and the error so it can be
+/// skipped, rather than the whole entity
+pub(crate) fn skip_entity(raw: &str, error: &dyn fmt::Display, continue_on_error: bool) -> Result<()> {
+    let id = splitter::entity_id(raw).unwrap_or("(unknown id)");
+    let error = error.to_string();
+    if !continue_on_error {
+        return Err(ProcessError::Filter { id: id.to_string(), message: error });
+    }
+    info!(target: module_path!(), stage = "filter", qid = id, error = error.as_str(); "Could not parse {}: {}", id, error);
+    Ok(())
+}
+
+/// Runs `program` over a single entity, returning jq's output.
+///
+/// With `continue_on_error`, entities jq can't handle are logged and `None` is returned.
+pub fn filter_entity(entity: &str, program: &mut JqProgram, continue_on_error: bool) -> Result<Option<String>> {
+    match run(entity, program) {
+        Ok(filtered_entity) => Ok(Some(filtered_entity)),
+        Err(error) => skip_entity(entity, &error, continue_on_error).map(|_| None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_counting_filter() {
+        let counts = Arc::new(FilterCounts::default());
+        let mut filter = CountingFilter::new(JqFilter::new("select(.type == \"item\")", false, true).unwrap(), Arc::clone(&counts));
+        assert!(filter.apply(r#"{"id":"Q1","type":"item"}"#).unwrap().is_some());
+        assert!(filter.apply(r#"{"id":"P1","type":"property"}"#).unwrap().is_none());
+        assert_eq!(counts.applied(), 0);
+        drop(filter);
+        assert_eq!((counts.applied(), counts.output()), (2, 1));
+    }
+}
@@ -0,0 +1,302 @@
+/*!
+ * Serving the entities of a run over Arrow Flight while it goes on (with the
+ * `flight` feature), so a Spark or Python job can consume them live rather
+ * than wait for the whole output to be written.
+ *
+ * Entities are simplified and gathered into record batches of the same schema
+ * as the `entities` table of SQL queries (see `sql`). Every `DoGet`, whatever
+ * its ticket, streams batches from one shared queue, so several consumers
+ * split the entities between them rather than each getting all of them. The
+ * run waits for consumers to take batches once a few are queued, and ends
+ * once the last batch has been taken. `GetFlightInfo` and `GetSchema` describe
+ * the single stream, e.g. for `pyarrow.flight.connect(...).do_get(flight.Ticket(b"entities"))`.
+ */
+
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use arrow_flight::encode::FlightDataEncoderBuilder;
+use arrow_flight::error::FlightError;
+use arrow_flight::flight_service_server::{FlightService, FlightServiceServer};
+use arrow_flight::{
+    Action, ActionType, Criteria, Empty, FlightData, FlightDescriptor, FlightEndpoint, FlightInfo, HandshakeRequest,
+    HandshakeResponse, PollInfo, PutResult, SchemaAsIpc, SchemaResult, Ticket,
+};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::ipc::writer::IpcWriteOptions;
+use datafusion::arrow::record_batch::RecordBatch;
+use futures_util::stream::{self, StreamExt};
+use log::{debug, error, info};
+use tokio::sync::oneshot;
+use tonic::transport::server::TcpIncoming;
+use tonic::{Request, Response, Status, Streaming};
+use crate::error::{ProcessError, Result};
+use crate::model::Entity;
+use crate::sink::Sink;
+use crate::sql::{self, EntityBatchBuilder};
+
+/// Ticket of the stream of entities, though any ticket gets it
+pub const TICKET: &str = "entities";
+
+// record batches waiting for a consumer, before the run waits for one to take them
+const QUEUED_BATCHES: usize = 4;
+
+// the queue of batches shared by every DoGet, and who to tell once every batch has been taken
+struct BatchQueue {
+    batches: Mutex<Receiver<RecordBatch>>,
+    // told once a DoGet found the queue closed and empty
+    drained: Mutex<Option<SyncSender<()>>>,
+}
+
+impl BatchQueue {
+    // the next batch, waited for off the runtime, or none once the run is over and every batch has been taken
+    async fn next(self: Arc<Self>) -> Option<RecordBatch> {
+        let queue = Arc::clone(&self);
+        let batch = tokio::task::spawn_blocking(move || queue.batches.lock().expect("Batch queue poisoned").recv().ok()).await.ok().flatten();
+        if batch.is_none() {
+            if let Some(drained) = self.drained.lock().expect("Batch queue poisoned").take() {
+                let _ = drained.send(());
+            }
+        }
+        batch
+    }
+}
+
+struct EntityFlightService {
+    schema: SchemaRef,
+    queue: Arc<BatchQueue>,
+}
+
+impl EntityFlightService {
+    fn flight_info(&self) -> std::result::Result<FlightInfo, Status> {
+        let endpoint = FlightEndpoint::new().with_ticket(Ticket::new(TICKET));
+        Ok(FlightInfo::new()
+            .try_with_schema(&self.schema)
+            .map_err(|error| Status::internal(error.to_string()))?
+            .with_descriptor(FlightDescriptor::new_path(vec![String::from(TICKET)]))
+            .with_endpoint(endpoint))
+    }
+}
+
+type FlightStream<T> = Pin<Box<dyn futures_util::Stream<Item = std::result::Result<T, Status>> + Send + 'static>>;
+
+fn unsupported<T>() -> std::result::Result<T, Status> {
+    Err(Status::unimplemented("Only DoGet, GetFlightInfo and GetSchema of the entities stream are served"))
+}
+
+#[tonic::async_trait]
+impl FlightService for EntityFlightService {
+    type HandshakeStream = FlightStream<HandshakeResponse>;
+    type ListFlightsStream = FlightStream<FlightInfo>;
+    type DoGetStream = FlightStream<FlightData>;
+    type DoPutStream = FlightStream<PutResult>;
+    type DoActionStream = FlightStream<arrow_flight::Result>;
+    type ListActionsStream = FlightStream<ActionType>;
+    type DoExchangeStream = FlightStream<FlightData>;
+
+    async fn handshake(&self, _request: Request<Streaming<HandshakeRequest>>) -> std::result::Result<Response<Self::HandshakeStream>, Status> {
+        unsupported()
+    }
+
+    async fn list_flights(&self, _request: Request<Criteria>) -> std::result::Result<Response<Self::ListFlightsStream>, Status> {
+        let info = self.flight_info()?;
+        Ok(Response::new(Box::pin(stream::once(async move { Ok(info) }))))
+    }
+
+    async fn get_flight_info(&self, _request: Request<FlightDescriptor>) -> std::result::Result<Response<FlightInfo>, Status> {
+        Ok(Response::new(self.flight_info()?))
+    }
+
+    async fn poll_flight_info(&self, _request: Request<FlightDescriptor>) -> std::result::Result<Response<PollInfo>, Status> {
+        unsupported()
+    }
+
+    async fn get_schema(&self, _request: Request<FlightDescriptor>) -> std::result::Result<Response<SchemaResult>, Status> {
+        let schema = SchemaAsIpc::new(&self.schema, &IpcWriteOptions::default())
+            .try_into()
+            .map_err(|error: datafusion::arrow::error::ArrowError| Status::internal(error.to_string()))?;
+        Ok(Response::new(schema))
+    }
+
+    async fn do_get(&self, request: Request<Ticket>) -> std::result::Result<Response<Self::DoGetStream>, Status> {
+        debug!("Streaming entities to {:?}", request.remote_addr());
+        let batches = stream::unfold(Arc::clone(&self.queue), |queue| async move {
+            let batch = Arc::clone(&queue).next().await?;
+            Some((Ok::<_, FlightError>(batch), queue))
+        });
+        let data = FlightDataEncoderBuilder::new()
+            .with_schema(Arc::clone(&self.schema))
+            .build(batches)
+            .map(|data| data.map_err(Status::from));
+        Ok(Response::new(Box::pin(data)))
+    }
+
+    async fn do_put(&self, _request: Request<Streaming<FlightData>>) -> std::result::Result<Response<Self::DoPutStream>, Status> {
+        unsupported()
+    }
+
+    async fn do_exchange(&self, _request: Request<Streaming<FlightData>>) -> std::result::Result<Response<Self::DoExchangeStream>, Status> {
+        unsupported()
+    }
+
+    async fn do_action(&self, _request: Request<Action>) -> std::result::Result<Response<Self::DoActionStream>, Status> {
+        unsupported()
+    }
+
+    async fn list_actions(&self, _request: Request<Empty>) -> std::result::Result<Response<Self::ListActionsStream>, Status> {
+        Ok(Response::new(Box::pin(stream::empty())))
+    }
+}
+
+// serves `service` on `listener` until told to `stop`, or the sink telling it to is dropped
+async fn serve(listener: TcpListener, service: EntityFlightService, stop: oneshot::Receiver<()>) -> std::result::Result<(), Box<dyn std::error::Error + Send + Sync>> {
+    let incoming = TcpIncoming::from_listener(tokio::net::TcpListener::from_std(listener)?, true, None)?;
+    tonic::transport::Server::builder()
+        .add_service(FlightServiceServer::new(service))
+        .serve_with_incoming_shutdown(incoming, async move {
+            let _ = stop.await;
+        })
+        .await?;
+    Ok(())
+}
+
+/// Serves the entities it's given over Arrow Flight, see the module documentation
+pub struct FlightSink {
+    builder: EntityBatchBuilder,
+    batch_size: usize,
+    address: SocketAddr,
+    batches: Option<SyncSender<RecordBatch>>,
+    drained: Receiver<()>,
+    shutdown: Option<oneshot::Sender<()>>,
+    server: Option<JoinHandle<()>>,
+}
+
+impl FlightSink {
+    /// Starts serving on `address`, failing if it can't be listened on. Entities are gathered `batch_size` at a time
+    pub fn new(address: impl ToSocketAddrs, batch_size: usize) -> Result<Self> {
+        let listen_error = |source| ProcessError::Flight(format!("Could not listen: {}", source));
+        let listener = TcpListener::bind(address).map_err(listen_error)?;
+        listener.set_nonblocking(true).map_err(listen_error)?;
+        let address = listener.local_addr().map_err(listen_error)?;
+
+        let (batches, received) = mpsc::sync_channel(QUEUED_BATCHES);
+        let (drained_sender, drained) = mpsc::sync_channel(1);
+        let queue = Arc::new(BatchQueue { batches: Mutex::new(received), drained: Mutex::new(Some(drained_sender)) });
+        let service = EntityFlightService { schema: sql::schema(), queue };
+        let (shutdown, stop) = oneshot::channel::<()>();
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .enable_all()
+            .build()
+            .map_err(|error| ProcessError::Flight(error.to_string()))?;
+        let server = thread::Builder::new()
+            .name(String::from("flight"))
+            .spawn(move || {
+                if let Err(error) = runtime.block_on(serve(listener, service, stop)) {
+                    error!("Could not serve Arrow Flight: {}", error);
+                }
+            })
+            .map_err(|error| ProcessError::Flight(error.to_string()))?;
+        info!("Serving entities over Arrow Flight on grpc://{}", address);
+        Ok(FlightSink {
+            builder: EntityBatchBuilder::default(),
+            batch_size: batch_size.max(1),
+            address,
+            batches: Some(batches),
+            drained,
+            shutdown: Some(shutdown),
+            server: Some(server),
+        })
+    }
+
+    /// The address served on, e.g. to find the port picked for port 0
+    pub fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+
+    // queues the entities gathered so far, waiting for a consumer if the queue is full
+    fn send_batch(&mut self) -> Result<()> {
+        let batch = self.builder.finish();
+        match &self.batches {
+            Some(batches) => batches.send(batch).map_err(|_| ProcessError::Flight(String::from("The server stopped"))),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Sink for FlightSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let entity = Entity::parse(output)
+            .map_err(|error| ProcessError::Flight(format!("Could not read an output as an entity, --flight-listen needs a jq filter keeping whole entities: {}", error)))?;
+        self.builder.append(&entity.simplify());
+        if self.builder.len() >= self.batch_size {
+            self.send_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Queues the last entities, and waits for consumers to take every batch before stopping the server
+    fn finalize(&mut self) -> Result<()> {
+        if !self.builder.is_empty() {
+            self.send_batch()?;
+        }
+        if self.batches.take().is_some() {
+            info!("Waiting for Arrow Flight consumers to take the last entities on grpc://{}", self.address);
+            let _ = self.drained.recv();
+        }
+        if let Some(shutdown) = self.shutdown.take() {
+            let _ = shutdown.send(());
+        }
+        if let Some(server) = self.server.take() {
+            server.join().map_err(|_| ProcessError::Flight(String::from("The server panicked")))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrow_flight::decode::FlightRecordBatchStream;
+    use arrow_flight::flight_service_client::FlightServiceClient;
+    use futures_util::TryStreamExt;
+    use crate::Pipeline;
+
+    #[test]
+    fn test_serve_entities() {
+        let sink = FlightSink::new("127.0.0.1:0", 3).unwrap();
+        let address = sink.local_addr();
+        let consumer = thread::spawn(move || {
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap();
+            runtime.block_on(async move {
+                let mut client = FlightServiceClient::connect(format!("http://{}", address)).await.unwrap();
+                let info = client.get_flight_info(FlightDescriptor::new_path(vec![String::from(TICKET)])).await.unwrap().into_inner();
+                let ticket = info.endpoint[0].ticket.clone().unwrap();
+                let data = client.do_get(ticket).await.unwrap().into_inner().map_err(FlightError::from);
+                FlightRecordBatchStream::new_from_flight_data(data).try_collect::<Vec<_>>().await.unwrap()
+            })
+        });
+        Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .entity_sink(sink)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let batches = consumer.join().unwrap();
+        assert_eq!(batches.iter().map(RecordBatch::num_rows).collect::<Vec<_>>(), vec![3, 3, 2]);
+        assert_eq!(batches[0].schema().fields(), sql::schema().fields());
+    }
+
+    #[test]
+    fn test_address_in_use() {
+        let sink = FlightSink::new("127.0.0.1:0", 3).unwrap();
+        assert!(matches!(FlightSink::new(sink.local_addr(), 3), Err(ProcessError::Flight(_))));
+    }
+}
@@ -0,0 +1,153 @@
+/*!
+ * Gazetteers: the surface forms of entities (every label and alias, in each
+ * language) mapped to their ids and types, the dictionaries dictionary-based
+ * entity recognizers match text against. Only the labels, aliases and
+ * "instance of" (P31) statements of each entity are parsed.
+ *
+ * Gazetteers are written as `form<TAB>language<TAB>id<TAB>types` TSV, with
+ * the types comma separated (empty for entities without any, and deprecated
+ * statements left out) and forms escaped like in label tables (see
+ * `labels::escape_tsv`). A form which is both the label and an alias of an
+ * entity, or several of its aliases, is only written once.
+ */
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::io::Write;
+use serde::Deserialize;
+use crate::edges::Statement;
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+use crate::labels::{escape_tsv, Term};
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+// the parts of an entity which end up in gazetteers
+#[derive(Deserialize)]
+struct Outline<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(default, borrow)]
+    labels: BTreeMap<Cow<'a, str>, Term<'a>>,
+    #[serde(default, borrow)]
+    aliases: BTreeMap<Cow<'a, str>, Vec<Term<'a>>>,
+    #[serde(default, borrow)]
+    claims: Claims<'a>,
+}
+
+#[derive(Deserialize, Default)]
+struct Claims<'a> {
+    #[serde(default, rename = "P31", borrow)]
+    instance_of: Vec<Statement<'a>>,
+}
+
+// turns each entity into its rows of the gazetteer
+struct GazetteerExtractor {
+    // all of them when empty
+    languages: Vec<String>,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl GazetteerExtractor {
+    fn rows(&self, outline: &Outline) -> String {
+        let types = outline.claims.instance_of.iter()
+            .filter(|statement| !statement.is_deprecated())
+            .filter_map(|statement| statement.mainsnak.item())
+            .collect::<Vec<_>>()
+            .join(",");
+        let mut rows = String::new();
+        let mut languages = outline.labels.keys().chain(outline.aliases.keys()).map(Cow::as_ref).collect::<Vec<_>>();
+        languages.sort_unstable();
+        languages.dedup();
+        if !self.languages.is_empty() {
+            languages.retain(|language| self.languages.iter().any(|wanted| wanted == language));
+        }
+        for language in languages {
+            let label = outline.labels.get(language).into_iter();
+            let aliases = outline.aliases.get(language).into_iter().flatten();
+            let mut seen = HashSet::new();
+            for form in label.chain(aliases).map(|term| term.value.as_ref()).filter(|form| seen.insert(*form)) {
+                if !rows.is_empty() {
+                    rows.push('\n');
+                }
+                rows.push_str(&escape_tsv(form));
+                rows.push('\t');
+                rows.push_str(language);
+                rows.push('\t');
+                rows.push_str(&outline.id);
+                rows.push('\t');
+                rows.push_str(&types);
+            }
+        }
+        rows
+    }
+}
+
+impl EntityFilter for GazetteerExtractor {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let rows = self.rows(&outline);
+        Ok(Some(rows).filter(|rows| !rows.is_empty()).map(Cow::Owned))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+/// Writes the gazetteer of every entity of `source` to `output` as TSV, with forms in `languages` (all of them if
+/// empty), see the module documentation. Rows come in dump order, and by language within an entity.
+pub fn write_gazetteer(source: impl Source, languages: Vec<String>, options: ProcessOptions, output: impl Write) -> Result<ProcessStats> {
+    let continue_on_error = options.continue_on_error;
+    Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(GazetteerExtractor { languages: languages.clone(), continue_on_error, failures: 0 }))
+        .sink(output)
+        .options(options)
+        .build()?
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    const ENTITY: &str = r#"{"id":"Q60","type":"item",
+        "labels":{"en":{"language":"en","value":"New York City"},"fr":{"language":"fr","value":"New York"}},
+        "aliases":{"en":[{"language":"en","value":"NYC"},{"language":"en","value":"New York City"},{"language":"en","value":"Big\tApple"}],"de":[{"language":"de","value":"NYC"}]},
+        "claims":{"P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q1093829"}}},"rank":"normal"},
+                         {"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":515}}},"rank":"preferred"},
+                         {"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q5"}}},"rank":"deprecated"}]}}"#;
+
+    fn rows(languages: &[&str]) -> String {
+        let extractor = GazetteerExtractor { languages: languages.iter().map(|language| language.to_string()).collect(), continue_on_error: false, failures: 0 };
+        extractor.rows(&serde_json::from_str(ENTITY).unwrap())
+    }
+
+    #[test]
+    fn test_gazetteer_rows() {
+        assert_eq!(rows(&["en"]), "New York City\ten\tQ60\tQ1093829,Q515\nNYC\ten\tQ60\tQ1093829,Q515\nBig\\tApple\ten\tQ60\tQ1093829,Q515");
+        assert_eq!(rows(&[]).lines().map(|row| row.split('\t').nth(1).unwrap()).collect::<Vec<_>>(), vec!["de", "en", "en", "en", "fr"]);
+        assert_eq!(rows(&["ja"]), "");
+    }
+
+    #[test]
+    fn test_write_gazetteer() {
+        let mut output = Vec::new();
+        let stats = write_gazetteer(FileSource::new("./tests/test-data.json.bz2"), vec![String::from("en")], ProcessOptions::default(), &mut output).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.lines().any(|row| row == "NYC\ten\tQ60\t"), "{}", output);
+        assert!(output.lines().all(|row| row.split('\t').count() == 4));
+    }
+}
@@ -0,0 +1,164 @@
+/*!
+ * GeoJSON feature collections of geolocated entities, so filtered places can
+ * be loaded straight into QGIS, Leaflet and other GIS tools.
+ * See https://datatracker.ietf.org/doc/html/rfc7946
+ *
+ * Each entity with a coordinate location (P625) on Earth becomes a point
+ * feature with the entity's id, from its preferred coordinates if it has any,
+ * otherwise its first non-deprecated ones. Its properties are picked from the
+ * simplified entity (see `model::SimpleEntity`) by dotted paths, e.g.
+ * `labels.en` or `claims.P31`, and are `null` when the entity has nothing at
+ * that path so every feature has the same properties. Entities without
+ * coordinates, and outputs which aren't whole entities, are left out.
+ */
+
+use serde_json::{json, Map, Value};
+use log::debug;
+use crate::error::Result;
+use crate::model::{DataValue, Entity, Rank, SimpleEntity};
+use crate::quickstatements::EARTH;
+use crate::sink::Sink;
+
+/// Coordinate location, the property features take their geometry from
+pub const COORDINATE_LOCATION: &str = "P625";
+
+const COLLECTION_START: &str = "{\"type\":\"FeatureCollection\",\"features\":[";
+const COLLECTION_END: &str = "]}";
+
+// longitude and latitude of the coordinates of `entity` which stand for it, if it has any on Earth
+fn coordinates(entity: &Entity) -> Option<(f64, f64)> {
+    let claims = entity.claims.get(COORDINATE_LOCATION)?;
+    let ranked = claims.iter().filter(|claim| claim.rank == Rank::Preferred)
+        .chain(claims.iter().filter(|claim| claim.rank == Rank::Normal));
+    ranked
+        .filter_map(|claim| match &claim.mainsnak.datavalue {
+            Some(DataValue::GlobeCoordinate(value)) if value.globe == EARTH => Some((value.longitude, value.latitude)),
+            _ => None,
+        })
+        .next()
+}
+
+// the value at the dotted `path` of `value`, if any
+fn pick<'a>(value: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.').try_fold(value, |value, key| value.get(key))
+}
+
+/// The feature of `entity`, with `properties` (dotted paths, see the module documentation), or `None` if it has no
+/// coordinates on Earth
+pub fn feature(entity: &Entity, properties: &[String]) -> Option<Value> {
+    let (longitude, latitude) = coordinates(entity)?;
+    let simple = serde_json::to_value(SimpleEntity::from(entity)).ok()?;
+    let properties = properties.iter()
+        .map(|path| (path.clone(), pick(&simple, path).cloned().unwrap_or(Value::Null)))
+        .collect::<Map<_, _>>();
+    Some(json!({
+        "type": "Feature",
+        "id": entity.id,
+        "geometry": { "type": "Point", "coordinates": [longitude, latitude] },
+        "properties": properties,
+    }))
+}
+
+/// Replaces an output which is a whole entity with its feature, or drops it if it has no coordinates or isn't an
+/// entity. Features go in a collection with `GeoJsonSink`.
+pub fn feature_output(output: String, properties: &[String]) -> Option<String> {
+    let entity = match Entity::parse(&output) {
+        Ok(entity) => entity,
+        Err(error) => {
+            debug!("Not an entity, so no feature ({}): {}", error, output);
+            return None;
+        }
+    };
+    feature(&entity, properties).map(|feature| feature.to_string())
+}
+
+/// Wraps the features written to another sink in a feature collection, one feature per line
+pub struct GeoJsonSink<S: Sink> {
+    sink: S,
+    // the last feature, held back until it's known whether a comma follows it
+    pending: Option<String>,
+    started: bool,
+}
+
+impl<S: Sink> GeoJsonSink<S> {
+    pub fn new(sink: S) -> Self {
+        GeoJsonSink { sink, pending: None, started: false }
+    }
+
+    fn start(&mut self) -> Result<()> {
+        if !self.started {
+            self.started = true;
+            self.sink.write_entity(COLLECTION_START)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: Sink> Sink for GeoJsonSink<S> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        self.start()?;
+        for feature in output.lines() {
+            if let Some(previous) = self.pending.replace(feature.to_string()) {
+                self.sink.write_entity(&format!("{},", previous))?;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sink.flush()
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.start()?;
+        if let Some(last) = self.pending.take() {
+            self.sink.write_entity(&last)?;
+        }
+        self.sink.write_entity(COLLECTION_END)?;
+        self.sink.finalize()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::WriteSink;
+
+    const ENTITY: &str = r#"{"id":"Q60","type":"item","labels":{"en":{"language":"en","value":"New York City"}},"claims":{"P625":[
+        {"mainsnak":{"snaktype":"value","property":"P625","datavalue":{"type":"globecoordinate","value":{"latitude":40.7,"longitude":-74.0,"globe":"http://www.wikidata.org/entity/Q2"}}},"type":"statement","rank":"normal"},
+        {"mainsnak":{"snaktype":"value","property":"P625","datavalue":{"type":"globecoordinate","value":{"latitude":40.71,"longitude":-74.01,"globe":"http://www.wikidata.org/entity/Q2"}}},"type":"statement","rank":"preferred"}]}}"#;
+
+    #[test]
+    fn test_feature() {
+        let entity = Entity::parse(ENTITY).unwrap();
+        let feature = feature(&entity, &[String::from("labels.en"), String::from("descriptions.en")]).unwrap();
+        assert_eq!(feature, json!({
+            "type": "Feature",
+            "id": "Q60",
+            "geometry": { "type": "Point", "coordinates": [-74.01, 40.71] },
+            "properties": { "labels.en": "New York City", "descriptions.en": null },
+        }));
+        assert_eq!(feature_output(String::from(r#"{"id":"Q1","type":"item"}"#), &[]), None);
+        assert_eq!(feature_output(String::from("\"Q60\""), &[]), None);
+    }
+
+    #[test]
+    fn test_geojson_sink() {
+        let mut output = Vec::new();
+        let mut sink = GeoJsonSink::new(WriteSink::new(&mut output, 1024));
+        sink.finalize().unwrap();
+        drop(sink);
+        assert_eq!(serde_json::from_slice::<Value>(&output).unwrap(), json!({"type": "FeatureCollection", "features": []}));
+
+        let mut output = Vec::new();
+        let mut sink = GeoJsonSink::new(WriteSink::new(&mut output, 1024));
+        let feature = feature_output(ENTITY.to_string(), &[]).unwrap();
+        sink.write_entity(&feature).unwrap();
+        sink.write_entity(&feature).unwrap();
+        sink.finalize().unwrap();
+        drop(sink);
+        let collection: Value = serde_json::from_slice(&output).unwrap();
+        assert_eq!(collection["features"].as_array().unwrap().len(), 2);
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 4);
+    }
+}
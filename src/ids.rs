@@ -0,0 +1,126 @@
+/*!
+ * Allowlists of entity ids, in formats which stay small and quick to load
+ * however many ids there are, unlike plain-text lists of tens of millions of
+ * them:
+ *
+ * - a bloom filter written by `filter --bloom-output`, see `bloom`, which lets
+ *   through a few entities not on the list, at the rate it was made for
+ * - a roaring bitmap of the numbers of Q ids, e.g. 42 for Q42, in the portable
+ *   format shared by the roaring libraries of most languages, which is exact
+ *   but only holds items
+ *
+ * Entities are kept or dropped by the id found at the start of their JSON,
+ * before anything else is done with them.
+ */
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use roaring::RoaringBitmap;
+use crate::bloom::BloomFilter;
+use crate::error::{ProcessError, Result};
+use crate::filter::EntityFilter;
+use crate::index::parse_id;
+use crate::splitter;
+
+/// The ids of an allowlist
+#[derive(Debug, Clone, PartialEq)]
+pub enum IdSet {
+    Bloom(BloomFilter),
+    /// The numbers of Q ids
+    Bitmap(RoaringBitmap),
+}
+
+impl IdSet {
+    /// Loads a bloom filter written by `filter --bloom-output`
+    pub fn load_bloom(path: &Path) -> Result<Self> {
+        Ok(IdSet::Bloom(BloomFilter::load(path)?))
+    }
+
+    /// Loads a roaring bitmap of the numbers of Q ids, in the portable serialization format
+    pub fn load_bitmap(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(ProcessError::Read)?;
+        let bitmap = RoaringBitmap::deserialize_from(BufReader::new(file))
+            .map_err(|error| ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is not a roaring bitmap: {}", path, error))))?;
+        Ok(IdSet::Bitmap(bitmap))
+    }
+
+    /// Whether `id` is on the list, or may be for a bloom filter
+    pub fn contains(&self, id: &str) -> bool {
+        match self {
+            IdSet::Bloom(filter) => filter.contains(id),
+            IdSet::Bitmap(bitmap) => matches!(parse_id(id), Some((b'Q', number)) if bitmap.contains(number)),
+        }
+    }
+
+    /// How many ids are on the list
+    pub fn len(&self) -> u64 {
+        match self {
+            IdSet::Bloom(filter) => filter.len(),
+            IdSet::Bitmap(bitmap) => bitmap.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+/// Drops the entities not on an allowlist, passing the others on to another filter
+pub struct IdFilter {
+    ids: Arc<IdSet>,
+    filter: Box<dyn EntityFilter>,
+}
+
+impl IdFilter {
+    pub fn new(ids: Arc<IdSet>, filter: Box<dyn EntityFilter>) -> Self {
+        IdFilter { ids, filter }
+    }
+}
+
+impl EntityFilter for IdFilter {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        match splitter::entity_id(raw) {
+            Some(id) if self.ids.contains(id) => self.filter.apply(raw),
+            _ => Ok(None),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.filter.take_error()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::jq_filter_factory;
+
+    #[test]
+    fn test_id_filter() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("ids.roaring");
+        let bitmap: RoaringBitmap = [1, 42].into_iter().collect();
+        bitmap.serialize_into(File::create(&path).unwrap()).unwrap();
+        let ids = IdSet::load_bitmap(&path).unwrap();
+        assert_eq!(ids.len(), 2);
+        assert!(ids.contains("Q42") && !ids.contains("P42") && !ids.contains("Q2"));
+        assert!(IdSet::load_bitmap(Path::new("./tests/test-data.json.bz2")).is_err());
+
+        let mut filter = IdFilter::new(Arc::new(ids), jq_filter_factory(".id", false, false)().unwrap());
+        assert_eq!(filter.apply("{\"id\":\"Q42\"}").unwrap().as_deref(), Some("\"Q42\"\n"));
+        assert_eq!(filter.apply("{\"id\":\"Q2\"}").unwrap(), None);
+        assert_eq!(filter.apply("[1]").unwrap(), None);
+
+        let mut bloom = BloomFilter::new(2, 0.001);
+        bloom.insert("P31");
+        let ids = IdSet::Bloom(bloom);
+        assert!(ids.contains("P31") && !ids.contains("Q42"));
+    }
+}
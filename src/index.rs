@@ -0,0 +1,310 @@
+/*!
+ * An index from entity ids to where they are in a dump, so single entities
+ * can be read without decompressing everything before them.
+ *
+ * Dumps are made of many bzip2 streams concatenated together, and decoding
+ * can start at any of them. Each entity is located by the compressed offset of
+ * the stream it starts in, along with its offset and length within the
+ * decompressed stream (carrying on into the following streams if need be).
+ *
+ * The index file is an 8 byte header (`INDEX_MAGIC`), the compressed size of
+ * the dump it was built from, and then one fixed size little-endian record per
+ * entity sorted by id, so it can be searched without loading it into memory.
+ */
+
+use std::collections::VecDeque;
+use std::fs::File;
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use log::warn;
+use crate::decoder::{self, StreamDecoder, StreamStart, BUFFER_LENGTH};
+use crate::error::{ProcessError, Result};
+use crate::progress::{Progress, Reporter};
+use crate::source::CountingReader;
+use crate::splitter::{self, EntityBuffer};
+
+/// Start of every index file, ending with the version of the format
+pub const INDEX_MAGIC: &[u8; 8] = b"WDIDX\0\0\x01";
+
+const HEADER_LENGTH: u64 = INDEX_MAGIC.len() as u64 + 8;
+const RECORD_LENGTH: u64 = 1 + 4 + 8 + 4 + 4;
+
+/// Where an entity is in a dump. Ordered by id, e.g. P31 before Q1 before Q42
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct IndexEntry {
+    /// The entity's id, see `parse_id`
+    pub id: (u8, u32),
+    /// Compressed offset of the bzip2 stream the entity starts in
+    pub stream: u64,
+    /// Offset of the entity within its decompressed stream
+    pub offset: u32,
+    /// Length of the entity's JSON in bytes
+    pub length: u32,
+}
+
+impl IndexEntry {
+    fn write_to(&self, output: &mut impl Write) -> io::Result<()> {
+        output.write_all(&[self.id.0])?;
+        output.write_all(&self.id.1.to_le_bytes())?;
+        output.write_all(&self.stream.to_le_bytes())?;
+        output.write_all(&self.offset.to_le_bytes())?;
+        output.write_all(&self.length.to_le_bytes())
+    }
+
+    fn read_from(input: &mut impl Read) -> io::Result<Self> {
+        let mut record = [0; RECORD_LENGTH as usize];
+        input.read_exact(&mut record)?;
+        let u32_at = |i: usize| u32::from_le_bytes(record[i..i + 4].try_into().expect("4 bytes"));
+        Ok(IndexEntry {
+            id: (record[0], u32_at(1)),
+            stream: u64::from_le_bytes(record[5..13].try_into().expect("8 bytes")),
+            offset: u32_at(13),
+            length: u32_at(17),
+        })
+    }
+}
+
+/// Splits an entity id such as `Q42` into its letter and number, as stored in the index
+pub fn parse_id(id: &str) -> Option<(u8, u32)> {
+    let (&letter, number) = id.as_bytes().split_first()?;
+    if !letter.is_ascii_uppercase() || number.is_empty() || !number.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    Some((letter, id[1..].parse().ok()?))
+}
+
+/// Scans the dump at `path`, returning where each of its entities is, in dump order, along with the dump's compressed size
+pub fn build_index(path: &Path, progress: Progress) -> Result<(Vec<IndexEntry>, u64)> {
+    let (file, size) = decoder::open(path)?;
+    let progress = Reporter::new(progress, Some(size), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+    progress.set_draw_rate(1);
+    let file = CountingReader::new(file);
+    let consumed = file.count();
+    let mut decoder = StreamDecoder::new(BufReader::new(file), StreamStart::default());
+
+    let mut index = Vec::new();
+    let mut skipped = 0;
+    let mut entities = EntityBuffer::new();
+    // the streams entities still to be found may have started in, oldest first
+    let mut streams = VecDeque::from([StreamStart::default()]);
+    let mut chunk = vec![0; BUFFER_LENGTH];
+    let mut finished = false;
+    while !finished {
+        let n = decoder.read(&mut chunk).map_err(ProcessError::Read)?;
+        progress.set_position(consumed.load(Ordering::Relaxed));
+        if streams.back() != Some(&decoder.stream_start()) {
+            streams.push_back(decoder.stream_start());
+        }
+        finished = n == 0;
+        entities.extend(&chunk[..n]);
+
+        loop {
+            // the start of the dump has to be skipped for the offset to be the next entity's
+            entities.check_start().map_err(ProcessError::Read)?;
+            let start = entities.offset();
+            let entity = match entities.next_entity().map_err(ProcessError::Read)? {
+                Some(entity) => entity,
+                // once the dump has ended, whatever is left is the last entity
+                None if finished => match entities.finish().map_err(ProcessError::Read)? {
+                    Some(entity) => entity,
+                    None => break,
+                },
+                None => break,
+            };
+            while streams.len() > 1 && streams[1].decompressed_offset <= start {
+                streams.pop_front();
+            }
+            let id = splitter::entity_id(&entity).and_then(parse_id);
+            let offset = u32::try_from(start - streams[0].decompressed_offset).ok();
+            match (id, offset, u32::try_from(entity.len()).ok()) {
+                (Some(id), Some(offset), Some(length)) => index.push(IndexEntry { id, stream: streams[0].compressed_offset, offset, length }),
+                _ => {
+                    warn!("Could not index entity {}", splitter::entity_id(&entity).unwrap_or("(unknown id)"));
+                    skipped += 1;
+                }
+            }
+            progress.set_entities(index.len() + skipped, index.len());
+        }
+    }
+    progress.finish(format!("Indexed {} entities, skipped {}", index.len(), skipped));
+    Ok((index, size))
+}
+
+/// Writes `entries`, sorted by id, as an index of a dump of `dump_size` compressed bytes
+pub fn write_index(entries: &mut [IndexEntry], dump_size: u64, output: impl Write) -> io::Result<()> {
+    entries.sort_unstable();
+    let mut output = io::BufWriter::new(output);
+    output.write_all(INDEX_MAGIC)?;
+    output.write_all(&dump_size.to_le_bytes())?;
+    for entry in entries.iter() {
+        entry.write_to(&mut output)?;
+    }
+    output.flush()
+}
+
+/// Reads a single entity out of `dump`, only decompressing from the start of its stream up to its end
+pub fn read_entity(dump: &mut (impl Read + Seek), entry: &IndexEntry) -> Result<String> {
+    dump.seek(SeekFrom::Start(entry.stream)).map_err(ProcessError::Read)?;
+    let start = StreamStart { compressed_offset: entry.stream, decompressed_offset: 0 };
+    let mut decoder = StreamDecoder::new(BufReader::new(dump), start);
+    io::copy(&mut (&mut decoder).take(entry.offset as u64), &mut io::sink()).map_err(ProcessError::Read)?;
+    let mut entity = vec![0; entry.length as usize];
+    decoder.read_exact(&mut entity).map_err(ProcessError::Read)?;
+    String::from_utf8(entity).map_err(|error| ProcessError::InvalidUtf8 { offset: entry.offset as u64 + error.utf8_error().valid_up_to() as u64 })
+}
+
+/// Where the index of the dump at `dump` goes unless told otherwise: the dump's path with `.idx` appended
+pub fn index_path(dump: &Path) -> PathBuf {
+    let mut path = dump.as_os_str().to_owned();
+    path.push(".idx");
+    path.into()
+}
+
+/// An index file written by `write_index`, searched on disk
+pub struct Index {
+    path: PathBuf,
+    file: File,
+    dump_size: u64,
+    len: u64,
+}
+
+impl Index {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let index_error = |source| ProcessError::Index { path: path.clone(), source };
+        let mut file = File::open(&path).map_err(index_error)?;
+        let length = file.metadata().map_err(index_error)?.len();
+        let mut header = [0; HEADER_LENGTH as usize];
+        if length < HEADER_LENGTH || !(length - HEADER_LENGTH).is_multiple_of(RECORD_LENGTH) {
+            return Err(ProcessError::InvalidIndex { path, message: String::from("unexpected length") });
+        }
+        file.read_exact(&mut header).map_err(index_error)?;
+        if &header[..INDEX_MAGIC.len()] != INDEX_MAGIC {
+            return Err(ProcessError::InvalidIndex { path, message: String::from("not an index, or one from another version") });
+        }
+        let dump_size = u64::from_le_bytes(header[INDEX_MAGIC.len()..].try_into().expect("8 bytes"));
+        Ok(Index { path, file, dump_size, len: (length - HEADER_LENGTH) / RECORD_LENGTH })
+    }
+
+    /// Compressed size of the dump the index was built from
+    pub fn dump_size(&self) -> u64 {
+        self.dump_size
+    }
+
+    /// Opens the dump at `dump`, which must be the one the index was built from (or at least the same size)
+    pub fn open_dump(&self, dump: &Path) -> Result<File> {
+        let file = File::open(dump).map_err(|source| ProcessError::OpenInput { path: dump.to_path_buf(), source })?;
+        let dump_size = file.metadata().map_err(ProcessError::Read)?.len();
+        if dump_size != self.dump_size {
+            let message = format!("built from a dump of {} bytes, not {:?}", self.dump_size, dump);
+            return Err(ProcessError::InvalidIndex { path: self.path.clone(), message });
+        }
+        Ok(file)
+    }
+
+    /// Number of entities in the index
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Finds where the entity `id` (e.g. `Q42`) is, by binary search
+    pub fn lookup(&mut self, id: &str) -> Result<Option<IndexEntry>> {
+        let id = match parse_id(id) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let entry = self.entry(middle)?;
+            match entry.id.cmp(&id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return Ok(Some(entry)),
+            }
+        }
+        Ok(None)
+    }
+
+    fn entry(&mut self, i: u64) -> Result<IndexEntry> {
+        let index_error = |source| ProcessError::Index { path: self.path.clone(), source };
+        self.file.seek(SeekFrom::Start(HEADER_LENGTH + i * RECORD_LENGTH)).map_err(index_error)?;
+        IndexEntry::read_from(&mut self.file).map_err(index_error)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_multi_stream_index() {
+        // split the dump into two bzip2 streams part way through an entity, like parallel compressors do
+        let mut decompressed = Vec::new();
+        decoder::decoder(File::open("./tests/test-data.json.bz2").unwrap()).read_to_end(&mut decompressed).unwrap();
+        let mut dump = tempfile::NamedTempFile::new().unwrap();
+        for part in [&decompressed[..1400], &decompressed[1400..]] {
+            let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+            encoder.write_all(part).unwrap();
+            dump.write_all(&encoder.finish().unwrap()).unwrap();
+        }
+
+        let (entries, _) = build_index(dump.path(), Progress::Hidden).unwrap();
+        assert_eq!(entries.len(), 8);
+        assert!(entries.iter().any(|entry| entry.stream > 0));
+        let expected = crate::reader::EntityReader::new(&decompressed[..]).unwrap().map(|entity| entity.unwrap());
+        let mut file = File::open(dump.path()).unwrap();
+        for (entry, expected) in entries.iter().zip(expected) {
+            assert_eq!(read_entity(&mut file, entry).unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn test_parse_id() {
+        assert_eq!(parse_id("Q42"), Some((b'Q', 42)));
+        assert_eq!(parse_id("P31"), Some((b'P', 31)));
+        assert_eq!(parse_id("L1-F1"), None);
+        assert_eq!(parse_id("Q"), None);
+        assert_eq!(parse_id("q42"), None);
+    }
+
+    #[test]
+    fn test_index() {
+        let dump = Path::new("./tests/test-data.json.bz2");
+        let (mut entries, size) = build_index(dump, Progress::Hidden).unwrap();
+        assert_eq!(entries.len(), 8);
+        assert_eq!(size, std::fs::metadata(dump).unwrap().len());
+
+        // the offsets point at the entities in the decompressed dump
+        let mut decompressed = String::new();
+        decoder::decoder(File::open(dump).unwrap()).read_to_string(&mut decompressed).unwrap();
+        for entry in &entries {
+            let start = entry.offset as usize;
+            let entity = &decompressed[start..start + entry.length as usize];
+            assert!(entity.starts_with('{') && entity.ends_with('}'));
+            assert_eq!(splitter::entity_id(entity).and_then(parse_id), Some(entry.id));
+        }
+        let q60 = *entries.iter().find(|entry| entry.id == (b'Q', 60)).unwrap();
+
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        write_index(&mut entries, size, &mut file).unwrap();
+        let mut index = Index::open(file.path()).unwrap();
+        assert_eq!(index.len(), 8);
+        assert_eq!(index.dump_size(), size);
+        assert_eq!(index.lookup("Q60").unwrap(), Some(q60));
+        assert_eq!(index.lookup("P1").unwrap().map(|entry| entry.id), Some((b'P', 1)));
+        assert_eq!(index.lookup("Q7").unwrap(), None);
+
+        let mut dump_file = File::open(dump).unwrap();
+        let entity = read_entity(&mut dump_file, &q60).unwrap();
+        assert_eq!(splitter::entity_id(&entity), Some("Q60"));
+
+        std::fs::write(file.path(), b"nonsense").unwrap();
+        assert!(matches!(Index::open(file.path()), Err(ProcessError::InvalidIndex { .. })));
+    }
+}
@@ -0,0 +1,156 @@
+/*!
+ * Producing outputs to a Kafka topic (with the `kafka` feature), keyed by the
+ * id of their entity, for streaming a dump into consumers which already read
+ * from Kafka. With a compacted topic, the latest output of each entity is
+ * kept.
+ *
+ * Topics are given as `kafka://broker:9092/topic`, with any number of
+ * comma separated brokers. Outputs are produced as they come, librdkafka
+ * batching and compressing them, and the run waits for every one of them to
+ * be acknowledged once it's done. Each output is sent as-is, so the jq filter
+ * decides what consumers get, but has to keep the id of the entity.
+ */
+
+use std::sync::Mutex;
+use std::time::Duration;
+use log::debug;
+use rdkafka::config::ClientConfig;
+use rdkafka::error::{KafkaError, RDKafkaErrorCode};
+use rdkafka::producer::{BaseProducer, BaseRecord, DeliveryResult, Producer, ProducerContext};
+use rdkafka::ClientContext;
+use crate::error::{ProcessError, Result};
+use crate::sink::{Sink, KAFKA_PREFIX};
+use crate::splitter;
+
+// how long to wait for the brokers to answer when connecting, and for every output to be acknowledged when flushing
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(60);
+
+// how long to let librdkafka send what it has when its queue is full
+const QUEUE_FULL_WAIT: Duration = Duration::from_millis(100);
+
+fn kafka_error(error: KafkaError) -> ProcessError {
+    ProcessError::Kafka(error.to_string())
+}
+
+/// The brokers and topic of a `kafka://broker:9092/topic` output
+pub fn parse_url(url: &str) -> Result<(&str, &str)> {
+    let invalid = || ProcessError::Kafka(format!("{} isn't a Kafka topic, e.g. kafka://localhost:9092/entities", url));
+    let (brokers, topic) = url.strip_prefix(KAFKA_PREFIX).and_then(|rest| rest.split_once('/')).ok_or_else(invalid)?;
+    if brokers.is_empty() || topic.is_empty() || topic.contains('/') {
+        return Err(invalid());
+    }
+    Ok((brokers, topic))
+}
+
+// counts the outputs librdkafka couldn't deliver, which it reports from polls rather than sends, keeping the error of
+// the first
+#[derive(Default)]
+struct DeliveryContext {
+    failures: Mutex<(u64, Option<String>)>,
+}
+
+impl ClientContext for DeliveryContext {}
+
+impl ProducerContext for DeliveryContext {
+    type DeliveryOpaque = ();
+
+    fn delivery(&self, result: &DeliveryResult<'_>, _opaque: ()) {
+        if let (Err((error, _)), Ok(mut failures)) = (result, self.failures.lock()) {
+            failures.0 += 1;
+            failures.1.get_or_insert_with(|| error.to_string());
+        }
+    }
+}
+
+/// Produces the output of each entity to a Kafka topic keyed by its id, see the module documentation
+pub struct KafkaSink {
+    producer: BaseProducer<DeliveryContext>,
+    topic: String,
+    written: u64,
+}
+
+impl KafkaSink {
+    /// Connects to the brokers of `url`, e.g. `kafka://localhost:9092/entities`, failing if they can't be reached
+    /// within a few seconds
+    pub fn connect(url: &str) -> Result<Self> {
+        let (brokers, topic) = parse_url(url)?;
+        let producer: BaseProducer<DeliveryContext> = ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("compression.type", "lz4")
+            .set("linger.ms", "50")
+            .create_with_context(DeliveryContext::default())
+            .map_err(kafka_error)?;
+        producer.client().fetch_metadata(Some(topic), CONNECT_TIMEOUT).map_err(kafka_error)?;
+        debug!("Connected to {}", brokers);
+        Ok(KafkaSink { producer, topic: topic.to_string(), written: 0 })
+    }
+
+    /// Number of outputs produced so far, acknowledged or not
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // fails once an output couldn't be delivered
+    fn check_deliveries(&self) -> Result<()> {
+        match &*self.producer.context().failures.lock().expect("Delivery context poisoned") {
+            (failed, Some(failure)) => Err(ProcessError::Kafka(format!("{} outputs couldn't be delivered, the first because {}", failed, failure))),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Sink for KafkaSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let id = splitter::entity_id(output)
+            .ok_or_else(|| ProcessError::Kafka(format!("Output has no id to key it by, the jq filter has to keep it: {:.100}", output)))?;
+        let mut record = BaseRecord::to(&self.topic).key(id).payload(output);
+        loop {
+            match self.producer.send(record) {
+                Ok(()) => break,
+                Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), returned)) => {
+                    record = returned;
+                    self.producer.poll(QUEUE_FULL_WAIT);
+                }
+                Err((error, _)) => return Err(kafka_error(error)),
+            }
+        }
+        self.written += 1;
+        // serves the delivery reports of what was sent before
+        self.producer.poll(Duration::ZERO);
+        self.check_deliveries()
+    }
+
+    /// Waits for every output produced so far to be acknowledged
+    fn flush(&mut self) -> Result<()> {
+        self.producer.flush(FLUSH_TIMEOUT).map_err(kafka_error)?;
+        self.check_deliveries()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_url() {
+        assert_eq!(parse_url("kafka://localhost:9092/entities").unwrap(), ("localhost:9092", "entities"));
+        assert_eq!(parse_url("kafka://a:9092,b:9092/wikidata.entities").unwrap(), ("a:9092,b:9092", "wikidata.entities"));
+        for url in ["kafka://localhost:9092", "kafka://localhost:9092/", "kafka:///entities", "redis://localhost/entities"] {
+            assert!(matches!(parse_url(url), Err(ProcessError::Kafka(_))), "{}", url);
+        }
+    }
+
+    // needs a broker, given as KAFKA_URL, e.g. kafka://localhost:9092/wikidump-test
+    #[test]
+    #[ignore]
+    fn test_kafka_sink() {
+        let url = std::env::var("KAFKA_URL").expect("KAFKA_URL names a topic to test with");
+        let mut sink = KafkaSink::connect(&url).unwrap();
+        sink.write_entity(r#"{"id":"Q1","label":"universe"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"Earth"}"#).unwrap();
+        assert!(matches!(sink.write_entity(r#"["Q3"]"#), Err(ProcessError::Kafka(_))));
+        sink.finalize().unwrap();
+        assert_eq!(sink.written(), 2);
+    }
+}
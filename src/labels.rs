@@ -0,0 +1,351 @@
+/*!
+ * Label lookup tables, the artifact most often built from a dump for entity
+ * linking, written without going through jq: only the labels, descriptions
+ * and aliases of each entity are parsed, and everything else is skipped over.
+ *
+ * Tables are written as TSV, with tabs, newlines and backslashes in labels
+ * escaped as `\t`, `\n`, `\r` and `\\`, or as a label map: an 8 byte header
+ * (`LABEL_MAP_MAGIC`), the number of entities, one fixed size little-endian
+ * record per entity sorted by id giving where its label is, and then the
+ * labels themselves, each preceded by its length. Like an index, the map is
+ * searched on disk without loading it into memory.
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use log::info;
+use serde::Deserialize;
+use crate::error::{ProcessError, Result};
+use crate::filter::{self, EntityFilter};
+use crate::index::parse_id;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::sink::Sink;
+use crate::source::Source;
+
+/// Start of every label map file, ending with the version of the format
+pub const LABEL_MAP_MAGIC: &[u8; 8] = b"WDLBL\0\0\x01";
+
+const HEADER_LENGTH: u64 = LABEL_MAP_MAGIC.len() as u64 + 8;
+const RECORD_LENGTH: u64 = 1 + 4 + 8;
+
+/// What goes into a label table
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LabelOptions {
+    /// Languages to take labels in, e.g. `en`. For label maps, the first of them an entity has a label in
+    pub languages: Vec<String>,
+    /// Also write a row for each alias, as if it was another label
+    pub aliases: bool,
+    /// Add a column with the description in the row's language, empty if there is none
+    pub descriptions: bool,
+}
+
+// the parts of an entity which end up in label tables (and text indexes), skipping over the rest
+#[derive(Deserialize)]
+pub(crate) struct Terms<'a> {
+    #[serde(borrow)]
+    pub(crate) id: Cow<'a, str>,
+    #[serde(default, borrow)]
+    pub(crate) labels: BTreeMap<Cow<'a, str>, Term<'a>>,
+    #[serde(default, borrow)]
+    pub(crate) descriptions: BTreeMap<Cow<'a, str>, Term<'a>>,
+    #[serde(default, borrow)]
+    pub(crate) aliases: BTreeMap<Cow<'a, str>, Vec<Term<'a>>>,
+}
+
+#[derive(Deserialize)]
+pub(crate) struct Term<'a> {
+    #[serde(borrow)]
+    pub(crate) value: Cow<'a, str>,
+}
+
+/// Escapes `value` for a TSV field, see the module documentation
+pub fn escape_tsv(value: &str) -> Cow<'_, str> {
+    if !value.contains(['\t', '\n', '\r', '\\']) {
+        return Cow::Borrowed(value);
+    }
+    let mut escaped = String::with_capacity(value.len() + 2);
+    for c in value.chars() {
+        match c {
+            '\t' => escaped.push_str("\\t"),
+            '\n' => escaped.push_str("\\n"),
+            '\r' => escaped.push_str("\\r"),
+            '\\' => escaped.push_str("\\\\"),
+            c => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+// turns each entity into its rows of a label table, or its `id<TAB>label` entry of a label map
+struct LabelExtractor {
+    options: LabelOptions,
+    map: bool,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl LabelExtractor {
+    fn rows(&self, terms: &Terms) -> String {
+        let mut rows = String::new();
+        let mut row = |language: &str, label: &str| {
+            if !rows.is_empty() {
+                rows.push('\n');
+            }
+            rows.push_str(&escape_tsv(&terms.id));
+            // the language column is only needed to tell rows apart when there are several
+            if self.options.languages.len() > 1 {
+                rows.push('\t');
+                rows.push_str(&escape_tsv(language));
+            }
+            rows.push('\t');
+            rows.push_str(&escape_tsv(label));
+            if self.options.descriptions {
+                rows.push('\t');
+                if let Some(description) = terms.descriptions.get(language) {
+                    rows.push_str(&escape_tsv(&description.value));
+                }
+            }
+        };
+        for language in &self.options.languages {
+            if let Some(label) = terms.labels.get(language.as_str()) {
+                row(language, &label.value);
+            }
+            if self.options.aliases {
+                for alias in terms.aliases.get(language.as_str()).into_iter().flatten() {
+                    row(language, &alias.value);
+                }
+            }
+        }
+        rows
+    }
+
+    fn entry(&self, terms: &Terms) -> Option<String> {
+        let label = self.options.languages.iter().find_map(|language| terms.labels.get(language.as_str()))?;
+        Some(format!("{}\t{}", terms.id, label.value))
+    }
+}
+
+impl EntityFilter for LabelExtractor {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let terms: Terms = match serde_json::from_str(raw) {
+            Ok(terms) => terms,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let output = match self.map {
+            true => self.entry(&terms),
+            false => Some(self.rows(&terms)).filter(|rows| !rows.is_empty()),
+        };
+        Ok(output.map(Cow::Owned))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+fn extractor(label_options: LabelOptions, map: bool, continue_on_error: bool) -> impl Fn() -> Result<LabelExtractor> + Send + Sync + 'static {
+    move || Ok(LabelExtractor { options: label_options.clone(), map, continue_on_error, failures: 0 })
+}
+
+/// Writes the label table of every entity of `source` to `output` as TSV, one row per label (and alias with
+/// `aliases`): the entity's id, the language if more than one was asked for, the label, and the description
+/// with `descriptions`. Rows come in dump order, and in the order of `languages` within an entity.
+pub fn write_labels(source: impl Source, label_options: LabelOptions, options: ProcessOptions, output: impl Write) -> Result<ProcessStats> {
+    Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(extractor(label_options, false, options.continue_on_error))
+        .sink(output)
+        .options(options)
+        .build()?
+        .run()
+}
+
+// collects label map entries, which only make up a map once sorted
+#[derive(Default)]
+struct MapEntries {
+    ids: Vec<((u8, u32), u64)>,
+    labels: Vec<u8>,
+    skipped: u64,
+}
+
+impl Sink for MapEntries {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let (id, label) = output.split_once('\t').expect("id and label");
+        match parse_id(id) {
+            Some(id) => {
+                self.ids.push((id, self.labels.len() as u64));
+                self.labels.extend_from_slice(&(label.len() as u32).to_le_bytes());
+                self.labels.extend_from_slice(label.as_bytes());
+            }
+            None => self.skipped += 1,
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// Writes a label map of every entity of `source` with a label in one of `label_options.languages` to
+/// `output`, returning how many entities it has. Entities without an id like Q42 are left out.
+///
+/// The map is built in memory, so allow about 16 bytes of RAM per entity on top of its labels.
+pub fn write_label_map(source: impl Source, label_options: LabelOptions, options: ProcessOptions, output: impl Write) -> Result<(u64, ProcessStats)> {
+    let mut entries = MapEntries::default();
+    let stats = Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(extractor(label_options, true, options.continue_on_error))
+        .entity_sink(&mut entries)
+        .options(options)
+        .build()?
+        .run()?;
+    if entries.skipped > 0 {
+        info!("Left {} entities without a numeric id out of the map", entries.skipped);
+    }
+    entries.ids.sort_unstable();
+    let mut output = BufWriter::new(output);
+    let labels_start = HEADER_LENGTH + entries.ids.len() as u64 * RECORD_LENGTH;
+    output.write_all(LABEL_MAP_MAGIC).map_err(ProcessError::Write)?;
+    output.write_all(&(entries.ids.len() as u64).to_le_bytes()).map_err(ProcessError::Write)?;
+    for ((letter, number), offset) in &entries.ids {
+        output.write_all(&[*letter]).map_err(ProcessError::Write)?;
+        output.write_all(&number.to_le_bytes()).map_err(ProcessError::Write)?;
+        output.write_all(&(labels_start + offset).to_le_bytes()).map_err(ProcessError::Write)?;
+    }
+    output.write_all(&entries.labels).map_err(ProcessError::Write)?;
+    output.flush().map_err(ProcessError::Write)?;
+    Ok((entries.ids.len() as u64, stats))
+}
+
+/// A label map written by `write_label_map`, searched on disk
+pub struct LabelMap {
+    path: PathBuf,
+    file: BufReader<File>,
+    len: u64,
+}
+
+impl LabelMap {
+    pub fn open(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let map_error = |source| ProcessError::Index { path: path.clone(), source };
+        let mut file = File::open(&path).map_err(map_error)?;
+        let length = file.metadata().map_err(map_error)?.len();
+        let mut header = [0; HEADER_LENGTH as usize];
+        if length < HEADER_LENGTH {
+            return Err(ProcessError::InvalidIndex { path, message: String::from("unexpected length") });
+        }
+        file.read_exact(&mut header).map_err(map_error)?;
+        if &header[..LABEL_MAP_MAGIC.len()] != LABEL_MAP_MAGIC {
+            return Err(ProcessError::InvalidIndex { path, message: String::from("not a label map, or one from another version") });
+        }
+        let len = u64::from_le_bytes(header[LABEL_MAP_MAGIC.len()..].try_into().expect("8 bytes"));
+        if length < HEADER_LENGTH + len * RECORD_LENGTH {
+            return Err(ProcessError::InvalidIndex { path, message: String::from("unexpected length") });
+        }
+        Ok(LabelMap { path, file: BufReader::new(file), len })
+    }
+
+    /// Number of entities in the map
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// The label of the entity `id` (e.g. `Q42`), by binary search
+    pub fn get(&mut self, id: &str) -> Result<Option<String>> {
+        let id = match parse_id(id) {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let (entry, offset) = self.record(middle)?;
+            match entry.cmp(&id) {
+                std::cmp::Ordering::Less => low = middle + 1,
+                std::cmp::Ordering::Greater => high = middle,
+                std::cmp::Ordering::Equal => return self.label(offset).map(Some),
+            }
+        }
+        Ok(None)
+    }
+
+    fn record(&mut self, i: u64) -> Result<((u8, u32), u64)> {
+        let map_error = |source| ProcessError::Index { path: self.path.clone(), source };
+        self.file.seek(SeekFrom::Start(HEADER_LENGTH + i * RECORD_LENGTH)).map_err(map_error)?;
+        let mut record = [0; RECORD_LENGTH as usize];
+        self.file.read_exact(&mut record).map_err(map_error)?;
+        let number = u32::from_le_bytes(record[1..5].try_into().expect("4 bytes"));
+        let offset = u64::from_le_bytes(record[5..].try_into().expect("8 bytes"));
+        Ok(((record[0], number), offset))
+    }
+
+    fn label(&mut self, offset: u64) -> Result<String> {
+        let map_error = |source| ProcessError::Index { path: self.path.clone(), source };
+        self.file.seek(SeekFrom::Start(offset)).map_err(map_error)?;
+        let mut length = [0; 4];
+        self.file.read_exact(&mut length).map_err(map_error)?;
+        let mut label = vec![0; u32::from_le_bytes(length) as usize];
+        self.file.read_exact(&mut label).map_err(map_error)?;
+        String::from_utf8(label).map_err(|_| map_error(io::Error::new(io::ErrorKind::InvalidData, "label is not valid UTF-8")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item","labels":{"en":{"language":"en","value":"Douglas Adams"},"fr":{"language":"fr","value":"Douglas\tAdams"}},"descriptions":{"en":{"language":"en","value":"English writer"}},"aliases":{"en":[{"language":"en","value":"DNA"}]},"claims":{"P31":[]}}"#;
+
+    fn extract(languages: &[&str], aliases: bool, descriptions: bool) -> Option<String> {
+        let options = LabelOptions { languages: languages.iter().map(|language| language.to_string()).collect(), aliases, descriptions };
+        let mut extractor = extractor(options, false, false)().unwrap();
+        extractor.apply(ENTITY).unwrap().map(Cow::into_owned)
+    }
+
+    #[test]
+    fn test_label_rows() {
+        assert_eq!(extract(&["en"], false, false).unwrap(), "Q42\tDouglas Adams");
+        assert_eq!(extract(&["de"], false, false), None);
+        assert_eq!(extract(&["fr", "en"], false, false).unwrap(), "Q42\tfr\tDouglas\\tAdams\nQ42\ten\tDouglas Adams");
+        assert_eq!(extract(&["en"], true, true).unwrap(), "Q42\tDouglas Adams\tEnglish writer\nQ42\tDNA\tEnglish writer");
+        assert_eq!(extract(&["fr"], false, true).unwrap(), "Q42\tDouglas\\tAdams\t");
+        assert_eq!(escape_tsv("a\\b\nc"), "a\\\\b\\nc");
+    }
+
+    #[test]
+    fn test_label_map() {
+        let options = LabelOptions { languages: vec![String::from("xx"), String::from("en")], ..LabelOptions::default() };
+        let mut table = Vec::new();
+        write_labels(FileSource::new("./tests/test-data.json.bz2"), options.clone(), ProcessOptions::default(), &mut table).unwrap();
+        let table = String::from_utf8(table).unwrap();
+
+        let mut map = tempfile::NamedTempFile::new().unwrap();
+        let (entities, stats) = write_label_map(FileSource::new("./tests/test-data.json.bz2"), options, ProcessOptions::default(), map.as_file_mut()).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        let mut map = LabelMap::open(map.path()).unwrap();
+        assert_eq!(map.len(), entities);
+        assert!(entities > 0);
+        for row in table.lines() {
+            let (id, rest) = row.split_once('\t').unwrap();
+            let (_, label) = rest.split_once('\t').unwrap();
+            // the map holds labels as they are, the table escaped
+            assert_eq!(escape_tsv(&map.get(id).unwrap().unwrap()), label);
+        }
+        assert_eq!(map.get("Q999999").unwrap(), None);
+        assert_eq!(map.get("not an id").unwrap(), None);
+    }
+}
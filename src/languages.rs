@@ -0,0 +1,145 @@
+/*!
+ * Trimming the terms of entities (labels, descriptions and aliases) to some
+ * languages, and splitting outputs into one file per language, the artifacts
+ * multilingual NLP work is usually done with.
+ *
+ * Only outputs which are JSON objects with `labels`, `descriptions` or
+ * `aliases` are touched, and they're re-serialized when they are. Anything
+ * else, e.g. the ids a filter picked out, goes through as-is.
+ */
+
+use std::path::{Path, PathBuf};
+use serde_json::{Map, Value};
+use crate::error::Result;
+use crate::shard;
+use crate::sink::Sink;
+
+/// The members of entities holding terms keyed by language
+pub const TERMS: [&str; 3] = ["labels", "descriptions", "aliases"];
+
+/// Drops the terms of `entity` in languages other than `languages`, returning whether it has any terms at all
+/// (i.e. whether it's an entity rather than some other output)
+pub fn trim_terms(entity: &mut Map<String, Value>, languages: &[String]) -> bool {
+    let mut found = false;
+    for member in TERMS {
+        if let Some(Value::Object(terms)) = entity.get_mut(member) {
+            terms.retain(|language, _| languages.contains(language));
+            found = true;
+        }
+    }
+    found
+}
+
+/// `output` with its terms trimmed to `languages`, see `trim_terms`
+pub fn trim_output(output: String, languages: &[String]) -> String {
+    let mut entity = match serde_json::from_str::<Value>(&output) {
+        Ok(Value::Object(entity)) => entity,
+        _ => return output,
+    };
+    match trim_terms(&mut entity, languages) {
+        true => Value::Object(entity).to_string(),
+        false => output,
+    }
+}
+
+/// Where the output in `language` of the output `path` goes: `out.ndjson` becomes `out.en.ndjson`
+pub fn language_path(path: &Path, language: &str) -> PathBuf {
+    shard::part_path(path, language)
+}
+
+/// Hands each output to a sink per language, with its terms trimmed to that language. Entities without any term
+/// in a language are left out of its output, while outputs which aren't entities go to all of them.
+pub struct LanguageSplitSink<S: Sink> {
+    sinks: Vec<(String, S)>,
+}
+
+impl<S: Sink> LanguageSplitSink<S> {
+    /// Splits over `sinks`, each with the language it's for
+    pub fn new(sinks: Vec<(String, S)>) -> Self {
+        LanguageSplitSink { sinks }
+    }
+
+    fn write_line(&mut self, line: &str) -> Result<()> {
+        let entity = match serde_json::from_str::<Value>(line) {
+            Ok(Value::Object(entity)) if TERMS.iter().any(|member| entity.contains_key(*member)) => entity,
+            _ => return self.sinks.iter_mut().try_for_each(|(_, sink)| sink.write_entity(line)),
+        };
+        for (language, sink) in &mut self.sinks {
+            let mut trimmed = entity.clone();
+            trim_terms(&mut trimmed, std::slice::from_ref(language));
+            let has_terms = TERMS.iter().any(|member| trimmed.get(*member).and_then(Value::as_object).is_some_and(|terms| !terms.is_empty()));
+            if has_terms {
+                sink.write_entity(&Value::Object(trimmed).to_string())?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<S: Sink> Sink for LanguageSplitSink<S> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        // the records of a flattened lexeme, say, are split one by one
+        output.lines().try_for_each(|line| self.write_line(line))
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sinks.iter_mut().try_for_each(|(_, sink)| sink.flush())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.sinks.iter_mut().try_for_each(|(_, sink)| sink.finalize())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::WriteSink;
+
+    const ENTITY: &str = r#"{"id":"Q60","labels":{"en":{"language":"en","value":"New York City"},"fr":{"language":"fr","value":"New York"},"ar":{"language":"ar","value":"نيويورك"}},"descriptions":{"en":{"language":"en","value":"largest city in New York"}},"aliases":{"en":[{"language":"en","value":"NYC"}]}}"#;
+
+    fn languages(languages: &[&str]) -> Vec<String> {
+        languages.iter().map(|language| language.to_string()).collect()
+    }
+
+    #[test]
+    fn test_trim_output() {
+        let trimmed: Value = serde_json::from_str(&trim_output(ENTITY.to_string(), &languages(&["fr", "ar"]))).unwrap();
+        assert_eq!(trimmed["labels"].as_object().unwrap().len(), 2);
+        assert!(trimmed["descriptions"].as_object().unwrap().is_empty());
+        assert!(trimmed["aliases"].as_object().unwrap().is_empty());
+        assert_eq!(trim_output(String::from("\"Q60\""), &languages(&["fr"])), "\"Q60\"");
+        assert_eq!(trim_output(String::from(r#"{"id":"Q60"}"#), &languages(&["fr"])), r#"{"id":"Q60"}"#);
+    }
+
+    #[test]
+    fn test_language_path() {
+        assert_eq!(language_path(Path::new("/data/out.ndjson"), "en"), Path::new("/data/out.en.ndjson"));
+        assert_eq!(language_path(Path::new("out"), "zh-hans"), Path::new("out.zh-hans"));
+    }
+
+    #[test]
+    fn test_language_split_sink() {
+        let (mut en, mut fr, mut de) = (Vec::new(), Vec::new(), Vec::new());
+        {
+            let sinks = vec![
+                (String::from("en"), WriteSink::new(&mut en, 64)),
+                (String::from("fr"), WriteSink::new(&mut fr, 64)),
+                (String::from("de"), WriteSink::new(&mut de, 64)),
+            ];
+            let mut sink = LanguageSplitSink::new(sinks);
+            sink.write_entity(ENTITY).unwrap();
+            sink.write_entity("\"Q1\"").unwrap();
+            sink.finalize().unwrap();
+        }
+        let lines = |output: Vec<u8>| String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect::<Vec<Value>>();
+        let (en, fr) = (lines(en), lines(fr));
+        assert_eq!(en.len(), 2);
+        assert_eq!(en[0]["labels"].as_object().unwrap().len(), 1);
+        assert_eq!(en[0]["aliases"]["en"][0]["value"], "NYC");
+        assert_eq!(fr[0]["labels"]["fr"]["value"], "New York");
+        assert!(fr[0]["descriptions"].as_object().unwrap().is_empty());
+        assert_eq!(fr[1], "Q1");
+        assert_eq!(lines(de), vec![Value::from("Q1")]);
+    }
+}
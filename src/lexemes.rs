@@ -0,0 +1,119 @@
+/*!
+ * Flattening lexemes into one record per form and per sense, the shape
+ * lexicographic data is usually consumed in, rather than the deeply nested
+ * structure of the dumps.
+ *
+ * Each record carries what it needs from its lexeme to stand on its own: the
+ * lexeme's id, language, lexical category and lemmas, along with the form's
+ * representations and grammatical features or the sense's glosses. Terms are
+ * plain strings keyed by language, e.g. `"lemmas": {"en": "cat"}`, and
+ * statements are left out.
+ */
+
+use std::collections::BTreeMap;
+use serde::Serialize;
+use crate::model::{Entity, LabelMap};
+
+/// A form or sense of a lexeme
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct LexemeRecord {
+    /// Id of the form or sense, e.g. `L7-F1`
+    pub id: String,
+    /// "form" or "sense"
+    #[serde(rename = "type")]
+    pub record_type: &'static str,
+    pub lexeme: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(rename = "lexicalCategory", skip_serializing_if = "Option::is_none")]
+    pub lexical_category: Option<String>,
+    pub lemmas: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub representations: BTreeMap<String, String>,
+    #[serde(rename = "grammaticalFeatures", skip_serializing_if = "Vec::is_empty")]
+    pub grammatical_features: Vec<String>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub glosses: BTreeMap<String, String>,
+}
+
+fn values(terms: &LabelMap) -> BTreeMap<String, String> {
+    terms.iter().map(|(language, term)| (language.clone(), term.value.clone())).collect()
+}
+
+/// The records of the forms and then the senses of `lexeme`, none if it has neither (or isn't a lexeme)
+pub fn flatten(lexeme: &Entity) -> Vec<LexemeRecord> {
+    if lexeme.entity_type != "lexeme" {
+        return Vec::new();
+    }
+    let record = |id: &str, record_type| LexemeRecord {
+        id: id.to_string(),
+        record_type,
+        lexeme: lexeme.id.clone(),
+        language: lexeme.language.clone(),
+        lexical_category: lexeme.lexical_category.clone(),
+        lemmas: values(&lexeme.lemmas),
+        representations: BTreeMap::new(),
+        grammatical_features: Vec::new(),
+        glosses: BTreeMap::new(),
+    };
+    let forms = lexeme.forms.iter().map(|form| LexemeRecord {
+        representations: values(&form.representations),
+        grammatical_features: form.grammatical_features.clone(),
+        ..record(&form.id, "form")
+    });
+    let senses = lexeme.senses.iter().map(|sense| LexemeRecord {
+        glosses: values(&sense.glosses),
+        ..record(&sense.id, "sense")
+    });
+    forms.chain(senses).collect()
+}
+
+/// Replaces an output which is a whole lexeme with its records, one per line, or drops it if it has no forms or
+/// senses. Any other output is kept as-is, so this can follow filters which also output other entities.
+pub fn flatten_output(output: String) -> Option<String> {
+    // most outputs aren't lexemes, and can be told apart without parsing
+    if !output.contains("\"lexeme\"") {
+        return Some(output);
+    }
+    let lexeme = match Entity::parse(&output) {
+        Ok(entity) if entity.entity_type == "lexeme" => entity,
+        _ => return Some(output),
+    };
+    let records = flatten(&lexeme).iter()
+        .map(|record| serde_json::to_string(record).expect("records serialize"))
+        .collect::<Vec<_>>();
+    Some(records.join("\n")).filter(|records| !records.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LEXEME: &str = r#"{"type":"lexeme","id":"L7","lemmas":{"en":{"language":"en","value":"cat"}},"lexicalCategory":"Q1084","language":"Q1860","claims":{},
+        "forms":[{"id":"L7-F1","representations":{"en":{"language":"en","value":"cat"}},"grammaticalFeatures":["Q110786"],"claims":{}},
+                 {"id":"L7-F2","representations":{"en":{"language":"en","value":"cats"}},"grammaticalFeatures":["Q146786"],"claims":{}}],
+        "senses":[{"id":"L7-S1","glosses":{"en":{"language":"en","value":"domesticated feline"},"fr":{"language":"fr","value":"félin domestique"}},"claims":{}}]}"#;
+
+    #[test]
+    fn test_flatten() {
+        let records = flatten(&Entity::parse(LEXEME).unwrap());
+        assert_eq!(records.len(), 3);
+        assert_eq!(records[1].id, "L7-F2");
+        assert_eq!(records[1].representations["en"], "cats");
+        assert_eq!(records[2].record_type, "sense");
+        assert_eq!(records[2].lemmas["en"], "cat");
+        assert_eq!(records[2].glosses["fr"], "félin domestique");
+    }
+
+    #[test]
+    fn test_flatten_output() {
+        let output = flatten_output(LEXEME.to_string()).unwrap();
+        let lines = output.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], r#"{"id":"L7-F1","type":"form","lexeme":"L7","language":"Q1860","lexicalCategory":"Q1084","lemmas":{"en":"cat"},"representations":{"en":"cat"},"grammaticalFeatures":["Q110786"]}"#);
+        assert_eq!(lines[2], r#"{"id":"L7-S1","type":"sense","lexeme":"L7","language":"Q1860","lexicalCategory":"Q1084","lemmas":{"en":"cat"},"glosses":{"en":"domesticated feline","fr":"félin domestique"}}"#);
+        // other outputs go through untouched, and lexemes with nothing to flatten are dropped
+        assert_eq!(flatten_output(String::from(r#"{"id":"Q1","type":"item"}"#)).as_deref(), Some(r#"{"id":"Q1","type":"item"}"#));
+        assert_eq!(flatten_output(String::from("\"lexeme\"")).as_deref(), Some("\"lexeme\""));
+        assert_eq!(flatten_output(String::from(r#"{"id":"L1","type":"lexeme"}"#)), None);
+    }
+}
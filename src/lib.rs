@@ -0,0 +1,188 @@
+/*!
+ * Streaming, filtering and writing of bzip2 encoded JSON Wikidata dumps.
+ *
+ * The `wikidump-process` binary is a thin CLI over this library; embedding
+ * applications can use the same stages directly:
+ *
+ * - `download` fetches dumps from dumps.wikimedia.org
+ * - `watch` polls for new dumps, filtering each one once it's complete
+ * - `source` reads them from a file, stdin or HTTP, or anything else implementing `Source`
+ * - `decoder` decompresses them
+ * - `splitter` finds the entities in the decompressed JSON array
+ * - `reader` iterates over those entities one at a time, and `stream` does the same asynchronously
+ * - `filter` applies jq filters to each entity, or any other engine implementing `EntityFilter`
+ * - `sink` opens destinations for the results, and the `Sink` trait lets them go anywhere else
+ * - `index` locates entities in a dump, so they can be read without a full scan
+ * - `model` has typed serde structs for entities
+ * - `cancel` stops a run early from another thread
+ * - `metrics` serves live counters of a run to Prometheus
+ * - `serve` answers HTTP requests for entities, labels and searches from the artifacts built from a dump
+ * - `checkpoint` saves where a run got to, so it can be resumed
+ * - `plan` splits a dump into ranges of bzip2 streams, for filtering it on several machines at once
+ * - `estimate` extrapolates what filtering a whole dump will produce, and how long it will take, from samples of it
+ * - `report` records the entities which couldn't be filtered, by id and position, rather than logging them whole
+ * - `oversize` skips, or drops the statements of, entities too large to filter safely
+ * - `classes` finds the subclass of hierarchy, and every subclass of a class however indirect
+ * - `presets` turns entities into simpler records natively, e.g. their labels or truthy statements as plain values
+ * - `edges` writes the item-valued statements of entities as a graph edge list
+ * - `geojson` writes geolocated entities as GeoJSON features, for GIS tools
+ * - `gazetteer` writes the labels and aliases of entities with their ids and types, for dictionary-based recognizers
+ * - `labels` writes label lookup tables, as TSV or a map searched on disk
+ * - `languages` trims labels, descriptions and aliases to some languages, and splits outputs by language
+ * - `lexemes` flattens lexemes into one record per form and sense
+ * - `properties` writes the datatype, labels and constraints of every property
+ * - `metadata` keeps or drops the page metadata of entities, whatever the jq filter keeps
+ * - `quality` reports entities with missing labels, deprecated-only statements and other gaps to fix
+ * - `coverage` counts the entities with a label, description and aliases in each language
+ * - `bloom` writes bloom filters of the ids of entities, for membership checks elsewhere
+ * - `ids` keeps the entities on an allowlist of ids, read from a bloom filter or a roaring bitmap
+ * - `sitelinks` maps wiki pages to the entities they're about
+ * - `text_index` builds full-text indexes of labels, aliases and descriptions (with the `tantivy` feature)
+ * - `quickstatements` turns statements into QuickStatements commands, for importing them into a Wikibase
+ * - `crosswalk` writes tables mapping entities to their identifiers in other authority files, e.g. GND or VIAF
+ * - `times` normalizes time values to plain ISO 8601 with the name of their precision
+ * - `units` normalizes quantities to a canonical unit, e.g. miles and kilometres to metres
+ * - `revisions` keeps the entities modified since a date or revision, for syncing from an older dump
+ * - `redirects` finds redirects left by merged entities, and points statements at their targets instead
+ * - `datatypes` keeps only the statements of some datatypes, e.g. dropping external ids for graph-only uses
+ * - `references` keeps only the statements, or the entities, with references to sources, and harvests the URLs they cite
+ * - `sql` runs SQL queries over the entities as they stream in, with DataFusion (with the `datafusion` feature)
+ * - `flight` serves entities over Arrow Flight while a run goes on (with the `flight` feature)
+ * - `rdf` writes entities as Turtle, in the shape the Wikidata Query Service has them
+ * - `blazegraph` writes them as gzipped Turtle chunks, named for the query service's bulk loader
+ * - `qlever` writes them as Turtle with the settings and `Qleverfile` QLever's index builder needs
+ * - `clickhouse` writes simplified entities as ClickHouse RowBinary rows, with the statement creating their table
+ * - `parquet_sink` writes simplified entities and edge lists as Parquet files (with the `parquet` feature)
+ * - `redis_sink` writes outputs into Redis keyed by entity id (with the `redis` feature)
+ * - `mongodb_sink` writes outputs into a MongoDB collection, inserted or replaced by entity id (with the `mongodb` feature)
+ * - `kafka_sink` produces outputs to a Kafka topic keyed by entity id (with the `kafka` feature)
+ * - `rocksdb_store` builds RocksDB stores of outputs keyed by entity id, by ingesting sorted SST files (with the `rocksdb` feature)
+ * - `lmdb_store` builds LMDB stores of outputs keyed by entity id, for memory-mapped lookups (with the `lmdb` feature)
+ * - `sqlite_sink` writes outputs into a new SQLite database keyed by entity id (with the `sqlite` feature)
+ * - `profile` counts what a dump is made of, without writing anything out
+ * - `canonical` writes entities as canonical JSON, with sorted keys and statements, for diffing outputs
+ * - `style` writes JSON outputs compact or pretty-printed, whatever produced them
+ * - `convert` re-encodes dumps and outputs, e.g. to MessagePack or gzip compressed ndjson
+ * - `diff` compares two versions of a dump, or of an output, entity by entity
+ * - `delta` writes patches between two sorted versions of an output, and applies them
+ * - `dedupe` drops entities found more than once
+ * - `sort` sorts entities by id, using temporary files for inputs bigger than memory
+ * - `merge` recombines outputs written in parts, and `shard` splits them by id
+ * - `dictionary` compresses shards with a zstd dictionary trained on their entities (with the `zstd` feature)
+ * - `validate` checks a dump or an output for truncation, invalid entities and duplicates
+ * - `dataframe` collects the outputs of a pipeline into a polars DataFrame (with the `polars` feature)
+ * - `process` ties all of the above together, and `pipeline` offers a builder over it
+ */
+
+pub mod blazegraph;
+pub mod bloom;
+pub mod cancel;
+pub mod canonical;
+pub mod checkpoint;
+pub mod classes;
+pub mod clickhouse;
+pub mod convert;
+pub mod coverage;
+pub mod crosswalk;
+pub mod datatypes;
+pub mod decoder;
+pub mod dedupe;
+pub mod delta;
+pub mod diff;
+pub mod download;
+pub mod edges;
+pub mod error;
+pub mod estimate;
+pub mod filter;
+pub mod gazetteer;
+pub mod geojson;
+pub mod ids;
+pub mod index;
+pub mod labels;
+pub mod languages;
+pub mod lexemes;
+pub mod merge;
+pub mod metadata;
+pub mod metrics;
+pub mod model;
+pub mod oversize;
+pub mod pipeline;
+pub mod plan;
+pub mod presets;
+pub mod process;
+pub mod profile;
+pub mod progress;
+pub mod properties;
+pub mod quality;
+pub mod qlever;
+pub mod quickstatements;
+pub mod rdf;
+pub mod reader;
+pub mod redirects;
+pub mod references;
+pub mod report;
+pub mod revisions;
+pub mod serve;
+pub mod shard;
+pub mod sink;
+pub mod sitelinks;
+pub mod sort;
+pub mod source;
+pub mod splitter;
+pub mod stream;
+pub mod style;
+pub mod times;
+pub mod units;
+pub mod util;
+pub mod validate;
+pub mod watch;
+
+#[cfg(feature = "tantivy")]
+pub mod text_index;
+
+#[cfg(feature = "zstd")]
+pub mod dictionary;
+
+#[cfg(feature = "datafusion")]
+pub mod sql;
+
+#[cfg(feature = "polars")]
+pub mod dataframe;
+
+#[cfg(feature = "parquet")]
+pub mod parquet_sink;
+
+#[cfg(feature = "flight")]
+pub mod flight;
+
+#[cfg(feature = "redis")]
+pub mod redis_sink;
+
+#[cfg(feature = "mongodb")]
+pub mod mongodb_sink;
+
+#[cfg(feature = "kafka")]
+pub mod kafka_sink;
+
+#[cfg(feature = "rocksdb")]
+pub mod rocksdb_store;
+
+#[cfg(feature = "lmdb")]
+pub mod lmdb_store;
+
+#[cfg(feature = "sqlite")]
+pub mod sqlite_sink;
+
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub mod uring;
+
+pub use cancel::CancellationToken;
+pub use error::ProcessError;
+pub use filter::EntityFilter;
+pub use pipeline::Pipeline;
+pub use process::{default_threads, process, process_with, ErrorBudget, ProcessOptions, ProcessStats};
+pub use progress::Progress;
+pub use reader::EntityReader;
+pub use sink::Sink;
+pub use source::Source;
+pub use util::{parse_duration, parse_size};
@@ -0,0 +1,183 @@
+/*!
+ * LMDB stores of outputs keyed by the id of their entity (with the `lmdb`
+ * feature), for read-heavy uses like entity linkers looking up millions of ids
+ * an hour: lookups read outputs straight from the memory-mapped database,
+ * without copying or decompressing them, from as many threads or processes as
+ * there are readers.
+ *
+ * `LmdbSink` gathers outputs until `batch_size` bytes are waiting, and puts
+ * them in one write transaction sorted by id, so each transaction touches
+ * fewer pages. The database isn't synced until the run is done. Outputs are
+ * stored as-is, and an id seen more than once is stored with its last output.
+ * RocksDB (see `crate::rocksdb_store`) makes smaller stores, LMDB faster
+ * lookups.
+ */
+
+use std::fs;
+use std::path::Path;
+use heed::types::Str;
+use heed::{Database, Env, EnvFlags, EnvOpenOptions};
+use log::debug;
+use crate::error::{ProcessError, Result};
+use crate::sink::Sink;
+use crate::splitter;
+
+/// Largest a store can grow to by default. The map is only reserved, not allocated, so this can be well over the
+/// memory and disk space there is.
+pub const DEFAULT_MAP_SIZE: usize = 1 << 40;
+
+// the file of an environment holding its data
+const DATA_FILE: &str = "data.mdb";
+
+fn lmdb_error(error: heed::Error) -> ProcessError {
+    ProcessError::Lmdb(error.to_string())
+}
+
+/// Writes outputs into a new LMDB database keyed by entity id, see the module documentation
+pub struct LmdbSink {
+    env: Env,
+    db: Database<Str, Str>,
+    batch_size: usize,
+    // ids and outputs waiting to be put, and their size
+    pending: Vec<(String, String)>,
+    pending_bytes: usize,
+    written: u64,
+}
+
+impl LmdbSink {
+    /// Creates the database in the directory `path`, which can grow to `map_size` bytes. If there is one already, it's
+    /// removed first when `overwrite` is set, otherwise this fails, as it does if `path` is anything but an LMDB
+    /// database.
+    pub fn create(path: &Path, overwrite: bool, map_size: usize, batch_size: usize) -> Result<Self> {
+        if path.exists() {
+            if !overwrite {
+                return Err(ProcessError::OutputExists(path.to_path_buf()));
+            }
+            if !path.join(DATA_FILE).is_file() {
+                return Err(ProcessError::Lmdb(format!("{:?} isn't an LMDB database, so it isn't overwritten", path)));
+            }
+            fs::remove_dir_all(path).map_err(|source| ProcessError::CreateOutput { path: path.to_path_buf(), source })?;
+        }
+        fs::create_dir_all(path).map_err(|source| ProcessError::CreateOutput { path: path.to_path_buf(), source })?;
+        // nothing else has the new environment open, and it's synced once everything is written
+        let env = unsafe { EnvOpenOptions::new().map_size(map_size).flags(EnvFlags::NO_SYNC).open(path) }.map_err(lmdb_error)?;
+        let mut transaction = env.write_txn().map_err(lmdb_error)?;
+        let db = env.create_database(&mut transaction, None).map_err(lmdb_error)?;
+        transaction.commit().map_err(lmdb_error)?;
+        Ok(LmdbSink { env, db, batch_size, pending: Vec::new(), pending_bytes: 0, written: 0 })
+    }
+
+    /// Number of outputs written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // puts the waiting outputs in a single transaction
+    fn put(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        // a stable sort keeps the outputs of an id in order, so the last one put is the last one written
+        self.pending.sort_by(|(id, _), (other, _)| id.cmp(other));
+        let mut transaction = self.env.write_txn().map_err(lmdb_error)?;
+        for (id, output) in &self.pending {
+            self.db.put(&mut transaction, id, output).map_err(lmdb_error)?;
+        }
+        transaction.commit().map_err(lmdb_error)?;
+        self.written += self.pending.len() as u64;
+        debug!("Put {} entities, {} so far", self.pending.len(), self.written);
+        self.pending.clear();
+        self.pending_bytes = 0;
+        Ok(())
+    }
+}
+
+impl Sink for LmdbSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let id = splitter::entity_id(output)
+            .ok_or_else(|| ProcessError::Lmdb(format!("Output has no id to key it by, the jq filter has to keep it: {:.100}", output)))?;
+        self.pending_bytes += id.len() + output.len();
+        self.pending.push((id.to_string(), output.to_string()));
+        if self.pending_bytes >= self.batch_size {
+            self.put()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.put()
+    }
+
+    /// Puts the last outputs and syncs the database to disk
+    fn finalize(&mut self) -> Result<()> {
+        self.put()?;
+        self.env.force_sync().map_err(lmdb_error)
+    }
+}
+
+/// A database written by `LmdbSink`, opened read-only for lookups
+pub struct LmdbStore {
+    env: Env,
+    db: Database<Str, Str>,
+}
+
+impl LmdbStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if !path.join(DATA_FILE).is_file() {
+            return Err(ProcessError::Lmdb(format!("{:?} isn't an LMDB database", path)));
+        }
+        // the environment is only read, so it doesn't matter what else has it open
+        let env = unsafe { EnvOpenOptions::new().flags(EnvFlags::READ_ONLY).open(path) }.map_err(lmdb_error)?;
+        let transaction = env.read_txn().map_err(lmdb_error)?;
+        let db = env.open_database(&transaction, None).map_err(lmdb_error)?
+            .ok_or_else(|| ProcessError::Lmdb(format!("{:?} has no database", path)))?;
+        transaction.commit().map_err(lmdb_error)?;
+        Ok(LmdbStore { env, db })
+    }
+
+    /// The output stored for the entity `id`, if any
+    pub fn get(&self, id: &str) -> Result<Option<String>> {
+        let transaction = self.env.read_txn().map_err(lmdb_error)?;
+        let output = self.db.get(&transaction, id).map_err(lmdb_error)?;
+        Ok(output.map(str::to_string))
+    }
+
+    /// Number of entities in the store
+    pub fn len(&self) -> Result<u64> {
+        let transaction = self.env.read_txn().map_err(lmdb_error)?;
+        self.db.len(&transaction).map_err(lmdb_error)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lmdb_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.lmdb");
+        let mut sink = LmdbSink::create(&path, false, 1 << 20, 64).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"Earth"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q1","label":"universe"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"the Earth"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q3","label":"life"}"#).unwrap();
+        assert!(matches!(sink.write_entity(r#"["Q4"]"#), Err(ProcessError::Lmdb(_))));
+        sink.finalize().unwrap();
+        drop(sink);
+
+        let store = LmdbStore::open(&path).unwrap();
+        assert_eq!(store.len().unwrap(), 3);
+        assert_eq!(store.get("Q2").unwrap().as_deref(), Some(r#"{"id":"Q2","label":"the Earth"}"#));
+        assert_eq!(store.get("Q4").unwrap(), None);
+        drop(store);
+
+        assert!(matches!(LmdbSink::create(&path, false, 1 << 20, 64), Err(ProcessError::OutputExists(_))));
+        assert!(matches!(LmdbSink::create(directory.path(), true, 1 << 20, 64), Err(ProcessError::Lmdb(_))));
+        assert!(matches!(LmdbStore::open(directory.path()), Err(ProcessError::Lmdb(_))));
+    }
+}
@@ -0,0 +1,235 @@
+/*!
+ * Logging to stderr and, optionally, to a file which is rotated once it gets
+ * too big, so the messages of a long run outlive the terminal's scrollback.
+ *
+ * Either can be logged as JSON, one event per line for Loki, ELK and the like,
+ * with the `stage` of the run it comes from (the module logging it, unless the
+ * event says otherwise) and whatever fields the event carries, e.g. the `qid`
+ * and `error` of an entity which couldn't be filtered or the `duration` of a
+ * run in seconds.
+ */
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::SystemTime;
+use log::kv::{self, Key, Value, Visitor};
+use log::{LevelFilter, Log, Metadata, Record};
+use serde_json::Map;
+
+// the log file gets at least this much, whatever goes to stderr, so e.g. skipped entities are always kept
+const MIN_FILE_LEVEL: LevelFilter = LevelFilter::Info;
+
+// how many rotated files are kept besides the one being written to, as log.1 (the newest) to log.N
+const ROTATED_FILES: usize = 5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LogFormat {
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+impl FromStr for LogFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "text" => Ok(LogFormat::Text),
+            "json" => Ok(LogFormat::Json),
+            _ => Err(format!("Invalid log format '{}', expected text or json", value)),
+        }
+    }
+}
+
+// gathers the key-value pairs of an event as JSON, keeping numbers and booleans as such
+struct Fields(Map<String, serde_json::Value>);
+
+impl<'kvs> Visitor<'kvs> for Fields {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let value = if let Some(value) = value.to_u64() {
+            serde_json::Value::from(value)
+        } else if let Some(value) = value.to_i64() {
+            serde_json::Value::from(value)
+        } else if let Some(value) = value.to_f64() {
+            serde_json::Value::from(value)
+        } else if let Some(value) = value.to_bool() {
+            serde_json::Value::from(value)
+        } else {
+            serde_json::Value::from(value.to_string())
+        };
+        self.0.insert(key.to_string(), value);
+        Ok(())
+    }
+}
+
+// the event as a line of JSON, see the module documentation
+fn json_line(record: &Record) -> String {
+    let mut event = Map::new();
+    event.insert(String::from("timestamp"), humantime::format_rfc3339_seconds(SystemTime::now()).to_string().into());
+    event.insert(String::from("level"), record.level().as_str().into());
+    event.insert(String::from("target"), record.target().into());
+    let stage = record.target().rsplit("::").next().unwrap_or_default();
+    event.insert(String::from("stage"), stage.into());
+    event.insert(String::from("message"), record.args().to_string().into());
+    let mut fields = Fields(Map::new());
+    let _ = record.key_values().visit(&mut fields);
+    event.extend(fields.0);
+    serde_json::Value::Object(event).to_string() + "\n"
+}
+
+/// A file which is moved aside to `<path>.1` once it reaches `max_size` bytes, shifting older ones along
+pub struct RotatingFile {
+    path: PathBuf,
+    max_size: u64,
+    file: File,
+    size: u64,
+}
+
+impl RotatingFile {
+    /// Appends to `path` if it already exists
+    pub fn open(path: PathBuf, max_size: u64) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let size = file.metadata()?.len();
+        Ok(RotatingFile { path, max_size, file, size })
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(format!(".{}", n));
+        path.into()
+    }
+
+    fn rotate(&mut self) -> io::Result<()> {
+        for n in (1..ROTATED_FILES).rev() {
+            let from = self.rotated(n);
+            if from.exists() {
+                fs::rename(&from, self.rotated(n + 1))?;
+            }
+        }
+        fs::rename(&self.path, self.rotated(1))?;
+        self.file = File::create(&self.path)?;
+        self.size = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.size > 0 && self.size + buf.len() as u64 > self.max_size {
+            self.rotate()?;
+        }
+        let n = self.file.write(buf)?;
+        self.size += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Logs to stderr as configured, and to a file at the info level or whatever stderr gets if that's more
+pub struct Logger {
+    stderr: env_logger::Logger,
+    stderr_format: LogFormat,
+    file: Option<(Mutex<RotatingFile>, LogFormat)>,
+    file_level: LevelFilter,
+}
+
+impl Logger {
+    pub fn new(stderr: env_logger::Logger, stderr_format: LogFormat, file: Option<(RotatingFile, LogFormat)>) -> Self {
+        let file_level = match file {
+            Some(_) => stderr.filter().max(MIN_FILE_LEVEL),
+            None => LevelFilter::Off,
+        };
+        Logger { stderr, stderr_format, file: file.map(|(file, format)| (Mutex::new(file), format)), file_level }
+    }
+
+    pub fn init(self) -> Result<(), log::SetLoggerError> {
+        let level = self.stderr.filter().max(self.file_level);
+        log::set_boxed_logger(Box::new(self))?;
+        log::set_max_level(level);
+        Ok(())
+    }
+}
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.stderr.enabled(metadata) || metadata.level() <= self.file_level
+    }
+
+    fn log(&self, record: &Record) {
+        if self.stderr.matches(record) {
+            match self.stderr_format {
+                LogFormat::Text => self.stderr.log(record),
+                LogFormat::Json => {
+                    let _ = io::stderr().lock().write_all(json_line(record).as_bytes());
+                }
+            }
+        }
+        if record.level() > self.file_level {
+            return;
+        }
+        if let Some((file, format)) = &self.file {
+            let line = match format {
+                LogFormat::Text => {
+                    let timestamp = humantime::format_rfc3339_seconds(SystemTime::now());
+                    format!("[{} {:<5} {}] {}\n", timestamp, record.level(), record.target(), record.args())
+                }
+                LogFormat::Json => json_line(record),
+            };
+            // logging has nowhere to report its own failures
+            if let Ok(mut file) = file.lock() {
+                let _ = file.write_all(line.as_bytes());
+            }
+        }
+    }
+
+    fn flush(&self) {
+        self.stderr.flush();
+        if let Some((file, _)) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                let _ = file.flush();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rotating_file() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("run.log");
+        let mut file = RotatingFile::open(path.clone(), 10).unwrap();
+        for line in ["first\n", "second\n", "third\n"] {
+            file.write_all(line.as_bytes()).unwrap();
+        }
+        assert_eq!(fs::read_to_string(&path).unwrap(), "third\n");
+        assert_eq!(fs::read_to_string(directory.path().join("run.log.1")).unwrap(), "second\n");
+        assert_eq!(fs::read_to_string(directory.path().join("run.log.2")).unwrap(), "first\n");
+    }
+
+    #[test]
+    fn test_json_line() {
+        let fields: &[(&str, Value)] = &[("stage", Value::from("filter")), ("qid", Value::from("Q42")), ("duration", Value::from(1.5))];
+        let args = format_args!("Could not parse {}", "Q42");
+        let record = Record::builder()
+            .args(args)
+            .level(log::Level::Info)
+            .target("wikidump_process::process")
+            .key_values(&fields)
+            .build();
+        let event: serde_json::Value = serde_json::from_str(&json_line(&record)).unwrap();
+        assert_eq!(event["message"], "Could not parse Q42");
+        assert_eq!(event["level"], "INFO");
+        assert_eq!(event["stage"], "filter");
+        assert_eq!(event["qid"], "Q42");
+        assert_eq!(event["duration"], 1.5);
+    }
+}
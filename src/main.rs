@@ -1,244 +1,94 @@
-/**
+/*!
  * This is an ETL app that takes as input a bzip2 encoded JSON Wikidata dump,
  * streams it through a decoder, extracts the desirable fields, and outputs
  * the result
- *
- * THINGS TO NOTE: in Rust, strings are UTF8 encoded (meaning a given character
- * can be anywhere from 1 to 4 bytes).
  */
 
-use std::cmp::min;
-use std::env;
-use std::fs::File;
-use std::io::{BufReader, Read, Write, BufWriter};
-use std::path::{PathBuf};
-use std::time::{Instant};
-use bzip2::read::{MultiBzDecoder};
-use clap::{Parser};
-use futures_util::StreamExt;
-use indicatif::{HumanDuration, ProgressBar, ProgressStyle, HumanBytes};
-use jq_rs::JqProgram;
-use log::{debug, info};
-use simdutf8::basic::from_utf8;
-use reqwest;
+mod commands;
+mod logging;
 
-// must be large enough to hold the largest entry
-const BUFFER_LENGTH: usize = 500000;
+use std::path::PathBuf;
+use clap::Parser;
+use log::{debug, LevelFilter};
+use wikidump_process::{parse_size, Progress};
+use commands::{Command, Context, Exit};
+use logging::{LogFormat, Logger, RotatingFile};
 
 #[derive(Parser, Debug)]
 #[clap(author="alexgagnon", version, about="Download and filter wikidata dumps")]
 struct Cli {
-    #[clap(short = 'c', long = "continue-on-error", help = "Don't bail on error while filtering")]
-    continue_on_error: bool,
+    #[clap(short = 'v', long = "verbose", parse(from_occurrences), global = true, help = "Log more, -v for info, -vv for debug and -vvv for trace. RUST_LOG takes precedence when set")]
+    verbose: u64,
 
-    #[clap(short = 'd', long = "download", help = "Download wikidata dump json file (default is to '.')")]
-    download: bool,
+    #[clap(short = 'q', long = "quiet", global = true, conflicts_with = "verbose", help = "Only log errors and don't show progress")]
+    quiet: bool,
 
-    #[clap(parse(from_os_str), short = 'i', long = "input", required = false, takes_value = true, required = false, help = "Source wikidata dump source")]
-    input_file_path: Option<PathBuf>,
+    #[clap(long = "progress", default_value = "bar", possible_values = &["bar", "json", "none"], global = true, help = "How to report progress, none with --quiet. json prints a single-line JSON record to stderr every second, for orchestrators and web UIs")]
+    progress: Progress,
 
-    #[clap(parse(from_os_str), short = 'o', long = "output", help = "Filename to output filtered entities (default is stdout)")]
-    output_file_path: Option<PathBuf>,
+    #[clap(long = "log-format", default_value = "text", possible_values = &["text", "json"], global = true, help = "Format of the log on stderr, json writes one JSON object per line with the stage, message and fields like the qid and error of an entity, for Loki, ELK and the like")]
+    log_format: LogFormat,
 
-    #[clap(short = 'f', long = "force", help = "Force overwriting files")]
-    force_overwrite: bool,   
+    #[clap(parse(from_os_str), long = "log-file", global = true, help = "Also log to this file, at the info level or more with -vv and -vvv, e.g. to keep the entities skipped during a long run")]
+    log_file: Option<PathBuf>,
 
-    #[clap(short = 'j', long = "jq-filter", default_value = "", help = "jq filter, see https://stedolan.github.io/jq/ for usage. NOTE: The filter is applied to EACH ENTITY!")]
-    jq_filter: String,
-}
-
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    debug!("Starting...");
-
-    let args = Cli::parse();
-    debug!("{:?}", args);
-    
-    if args.download {
-        let start = Instant::now();
-        let version = "latest".to_string();
-        let url = &format!("https://dumps.wikimedia.org/wikidatawiki/entities/{}-all.json.bz2", version).to_owned();
-        debug!("URL: {}", url);
-        let res = reqwest::Client::new()
-            .get(url)
-            .send()
-            .await
-            .or(Err(format!("Failed to GET from '{}'", &url)))?;
-
-        let total_size = res
-            .content_length()
-            .ok_or(format!("Failed to get content length from '{}'", &url))?;
-        
-        let mut file = {
-            let filename = res
-                .url()
-                .path_segments()
-                .and_then(|segments| segments.last())
-                .and_then(|name| if name.is_empty() { None } else { Some(name) })
-                .unwrap();
-    
-            let filename = env::current_dir()?.join(filename);
-            info!("Downloading to {:?}", filename.as_os_str());
-            File::create(filename)?
-        };
-
-        let pb = ProgressBar::new(total_size);
-        pb.set_style(ProgressStyle::default_bar()
-            .template("{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})")
-            .progress_chars("#>-"));
-            
-        let mut downloaded: u64 = 0;
-        let mut stream = res.bytes_stream();
-
-        while let Some(item) = stream.next().await {
-            let chunk = item.or(Err(format!("Error while downloading file")))?;
-            file.write_all(&chunk)
-                .or(Err(format!("Error while writing to file")))?;
-            let new = min(downloaded + (chunk.len() as u64), total_size);
-            downloaded = new;
-            pb.set_position(new);
-        }
+    #[clap(long = "log-file-format", default_value = "text", possible_values = &["text", "json"], global = true, help = "Format of --log-file, json writes one JSON object per line")]
+    log_file_format: LogFormat,
 
-        pb.finish_with_message(format!("Downloaded {} to {:?} in {}", &url, file, HumanDuration(start.elapsed())));
-    }
+    #[clap(long = "log-file-size", default_value = "100M", parse(try_from_str = parse_size), global = true, help = "Size at which --log-file is moved aside to <log-file>.1, keeping up to 5 older files")]
+    log_file_size: usize,
 
-    if !args.jq_filter.is_empty() {
-        let mut output: Box<dyn Write>;
-        if args.output_file_path.is_none() {
-            let stdout = std::io::stdout(); // get the global stdout entity
-            output = Box::new(stdout.lock()) as Box<dyn Write>; // acquire a lock on it
-        }
-        else {
-            if args.output_file_path.clone().unwrap().exists() && !args.force_overwrite {
-                panic!("Output file already exists, must use `force-overwrite` flag to continue");
-            }
-            // TODO: handle gracefully
-            let output_file = File::create(args.output_file_path.unwrap());
-            output = Box::new(output_file?) as Box<dyn Write>;
-        }
+    #[clap(short = 'y', long = "yes", global = true, help = "Answer yes to any question, e.g. whether to overwrite an existing file")]
+    yes: bool,
 
-        process(args.input_file_path, &mut output, &args.jq_filter, args.continue_on_error)?;
-    }
-    else {
-        info!("No filter provided");
-    }
-    
-    Ok(())
+    #[clap(subcommand)]
+    command: Command,
 }
 
-pub fn process(input: Option<PathBuf>, output: &mut impl Write, jq_filter: &String, continue_on_error: bool) -> Result<(), std::io::Error> {
-    let mut stream = BufWriter::new(output);
-    let input = input.expect("Could not get path");
-    let file = File::open(&input)?;
-    let mut filter = jq_rs::compile(jq_filter).expect("Could not compile jq filter");
-    
-    let size = file.metadata()?.len();
-    debug!("Opening {:?}, size: {}", input.as_path(), size);
-
-    let mut total_bytes: u64 = 0;
-
-    debug!("Initializing buffer to size {}", BUFFER_LENGTH);
-    let reader = BufReader::new(file);
-    let mut md = MultiBzDecoder::new(reader);
-
-    let mut buffer = [0; BUFFER_LENGTH];
-    let mut str_buffer = String::new();
-
-    let bar = ProgressBar::new(size);
-
-    bar.set_draw_rate(1);
-    bar.set_style(ProgressStyle::default_bar()
-    .template("{msg}\n{spinner:.green} [{elapsed_precise}] ({bytes_per_sec})")
-    .progress_chars("#>-"));
-
-    // discard the first two bytes representing "[\n"
-    // NOTE both of these are ASCII characters, so one byte each
-    md.read(&mut [0u8; 2])?;
-
-    let mut num_entities = 0;
-    let mut num_entities_output = 0;
-    let mut n = md.read(&mut buffer)?;
-    
-    let start = Instant::now();
-
-    while n > 0 {
-        total_bytes += n as u64;
-        bar.inc(n as u64);
-
-        // convert to utf8 string and split on newlines
-        str_buffer.push_str(&from_utf8(&buffer[..n]).expect("Could not convert to string"));
-
-        // a vector of string slices
-        let mut entities: Vec<&str> = str_buffer.split(",\n").collect();
-        let length = entities.len();
+#[tokio::main]
+async fn main() {
+    let args = Cli::parse();
 
-        // iterate over the "complete" entities
-        // &mut so we can mutably borrow each item in the vector
-        for entity in &mut entities[..(length - 1)] {
-            let filtered_entity = filter_entity(entity, &mut filter, continue_on_error);
-            num_entities += 1;
-            if !filtered_entity.eq("") {
-                stream.write(filtered_entity.as_bytes()).expect("Could not write");
-                num_entities_output += 1;
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::Error,
+        (false, 0) => LevelFilter::Warn,
+        (false, 1) => LevelFilter::Info,
+        (false, 2) => LevelFilter::Debug,
+        (false, _) => LevelFilter::Trace,
+    };
+    let stderr = env_logger::Builder::new()
+        .filter_level(level)
+        .parse_env("RUST_LOG")
+        .build();
+    let log_file = match &args.log_file {
+        Some(path) => match RotatingFile::open(path.clone(), args.log_file_size as u64) {
+            Ok(file) => Some((file, args.log_file_format)),
+            Err(error) => {
+                eprintln!("Error: Could not open log file {:?}: {}", path, error);
+                std::process::exit(commands::EXIT_FAILED);
             }
-            bar.set_message(format!("Processed {} entities, {} outputted", num_entities, num_entities_output));
-        }
-
-        // mutable ref to entities done here
-        let last = entities.last_mut().expect("Could not get last item");
+        },
+        None => None,
+    };
+    Logger::new(stderr, args.log_format, log_file).init().expect("Logger is only set up once");
+    debug!("Starting...");
+    debug!("{:?}", args);
 
-        // the very end of the file will contain a '\n]', remove the two 1 byte ascii chars and allow it to be processed
-        if last.ends_with("\n]") {
-            debug!("Last entity");
-            *last = &last[..last.len() - 2];
-            debug!("{}", last);
-            let filtered_entity = filter_entity(last, &mut filter, continue_on_error);
-            num_entities += 1;
-            if !filtered_entity.eq("") {
-                stream.write(filtered_entity.as_bytes()).expect("Could not write");
-                num_entities_output += 1;
-            }
-            bar.set_message(format!("Processed {} entities, {} outputted", num_entities, num_entities_output));
-            break;
+    let context = Context {
+        progress: if args.quiet { Progress::Hidden } else { args.progress },
+        yes: args.yes,
+    };
+    let log_format = args.log_format;
+    if let Err(error) = commands::run(args.command, &context).await {
+        let message = match error.downcast_ref::<Exit>() {
+            Some(exit) => exit.to_string(),
+            None => format!("Error: {}", error),
+        };
+        match log_format {
+            LogFormat::Text => eprintln!("{}", message),
+            // so collectors get the reason a run failed as an event like any other
+            LogFormat::Json => log::error!(target: module_path!(), stage = "run"; "{}", message),
         }
-
-        // reset the string buffer with the incomplete last entity
-        str_buffer = last.to_string();
-
-        // clear the buffer by creating a new one
-        buffer = [0; BUFFER_LENGTH];
-        n = md.read(&mut buffer)?;
+        std::process::exit(commands::exit_code(error.as_ref()));
     }
-    stream.flush().expect("Could not flush");
-    bar.finish_with_message(format!("Finished! Processed {} entities and outputted {} in {}", HumanBytes(total_bytes), num_entities, HumanDuration(start.elapsed())));
-    Ok(())
 }
-
-fn filter_entity(entity: &str, filter: &mut JqProgram, continue_on_error: bool) -> String {
-    debug!("{}", entity);
-    let result = filter.run(&entity);
-    let filtered_entity = match result {
-        Ok(e) => e,
-        Err(error) => if !continue_on_error {
-            panic!("Could not parse: {}. {}", entity, error)
-        } else {
-            info!("Could not parse: {}", entity);
-            String::from("null")
-        }
-    };
-    debug!("{}", filtered_entity);
-    debug!("---");
-    filtered_entity
-} 
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    fn test_process() {
-        let input = std::path::Path::new("./tests/invalid-json.json.bz2").to_path_buf();
-        process(Some(input), &mut std::io::stdout(), &".id".to_string(), true);
-    }
-}
\ No newline at end of file
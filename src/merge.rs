@@ -0,0 +1,66 @@
+/*!
+ * Recombining outputs written in several parts, e.g. shards filtered on
+ * different machines, into one output or a different number of shards.
+ */
+
+use std::io;
+use std::path::PathBuf;
+use crate::error::{ProcessError, Result};
+use crate::progress::{Progress, Reporter};
+use crate::reader::EntityFile;
+use crate::sink::Sink;
+use crate::sort;
+
+/// Writes the entities of all `inputs` (dumps or `filter` outputs) to `sink`, returning how many there were.
+///
+/// Inputs are written one after the other, or with `sorted`, merged into one sorted whole, which requires each
+/// input to be sorted as `sort` does.
+pub fn merge(inputs: &[PathBuf], sorted: bool, progress: Progress, sink: &mut dyn Sink) -> Result<u64> {
+    let progress = Reporter::new(progress, None, "{msg}\n{spinner:.green} [{elapsed_precise}]");
+    progress.set_draw_rate(1);
+    let mut entities = 0;
+    let mut write = |entity: String| {
+        entities += 1;
+        progress.set_entities(entities, entities);
+        sink.write_entity(&entity)
+    };
+    if sorted {
+        let inputs = inputs.iter()
+            .map(|input| Ok(Box::new(EntityFile::open(input)?) as Box<dyn Iterator<Item = io::Result<String>>>))
+            .collect::<Result<Vec<_>>>()?;
+        sort::merge(inputs, &mut write)?;
+    } else {
+        for input in inputs {
+            for entity in EntityFile::open(input)? {
+                write(entity.map_err(ProcessError::Read)?)?;
+            }
+        }
+    }
+    sink.finalize()?;
+    progress.finish(format!("Merged {} entities from {} inputs", entities, inputs.len()));
+    Ok(entities as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use crate::dedupe::DedupeSink;
+    use crate::sink::WriteSink;
+
+    #[test]
+    fn test_merge() {
+        let directory = tempfile::tempdir().unwrap();
+        let inputs = vec![directory.path().join("0.ndjson"), directory.path().join("1.ndjson")];
+        fs::write(&inputs[0], "{\"id\":\"Q1\"}\n{\"id\":\"Q3\"}\n").unwrap();
+        fs::write(&inputs[1], "{\"id\":\"Q2\"}\n{\"id\":\"Q3\",\"v\":2}\n").unwrap();
+
+        let mut output = Vec::new();
+        assert_eq!(merge(&inputs, false, Progress::Hidden, &mut WriteSink::new(&mut output, 64)).unwrap(), 4);
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"id\":\"Q1\"}\n{\"id\":\"Q3\"}\n{\"id\":\"Q2\"}\n{\"id\":\"Q3\",\"v\":2}\n");
+
+        let mut output = Vec::new();
+        merge(&inputs, true, Progress::Hidden, &mut DedupeSink::new(WriteSink::new(&mut output, 64))).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "{\"id\":\"Q1\"}\n{\"id\":\"Q2\"}\n{\"id\":\"Q3\"}\n");
+    }
+}
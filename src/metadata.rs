@@ -0,0 +1,197 @@
+/*!
+ * Keeping or dropping the page metadata entities of a dump carry next to their
+ * data: `pageid`, `ns`, `title`, `lastrevid` and `modified`. Some pipelines need
+ * them for provenance whatever their jq filter keeps, others want them gone
+ * for size whatever it keeps.
+ *
+ * Kept fields are copied from the entity into outputs which are JSON objects
+ * lacking them, and dropped fields are removed from outputs which are JSON
+ * objects. Other outputs, e.g. just an id, are written as they are.
+ */
+
+use std::borrow::Cow;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+
+/// The page metadata fields of entities
+pub const FIELDS: [&str; 5] = ["pageid", "ns", "title", "lastrevid", "modified"];
+
+#[derive(Deserialize)]
+struct Outline {
+    #[serde(default)]
+    pageid: Option<Value>,
+    #[serde(default)]
+    ns: Option<Value>,
+    #[serde(default)]
+    title: Option<Value>,
+    #[serde(default)]
+    lastrevid: Option<Value>,
+    #[serde(default)]
+    modified: Option<Value>,
+}
+
+impl Outline {
+    fn field(&self, field: &str) -> Option<&Value> {
+        match field {
+            "pageid" => self.pageid.as_ref(),
+            "ns" => self.ns.as_ref(),
+            "title" => self.title.as_ref(),
+            "lastrevid" => self.lastrevid.as_ref(),
+            "modified" => self.modified.as_ref(),
+            _ => None,
+        }
+    }
+}
+
+/// Comma separated metadata fields, or `all` of them
+pub fn parse_fields(value: &str) -> std::result::Result<Vec<String>, String> {
+    if value == "all" {
+        return Ok(FIELDS.iter().map(|field| field.to_string()).collect());
+    }
+    value.split(',')
+        .map(str::trim)
+        .filter(|field| !field.is_empty())
+        .map(|field| match FIELDS.contains(&field) {
+            true => Ok(field.to_string()),
+            false => Err(format!("Invalid metadata field '{}', expected all or some of {}", field, FIELDS.join(","))),
+        })
+        .collect()
+}
+
+/// Applies another filter, then adds the `keep` metadata fields of the entity to its output and removes the `drop`
+/// ones, see the module documentation
+pub struct MetadataFilter {
+    filter: Box<dyn EntityFilter>,
+    keep: Vec<String>,
+    drop: Vec<String>,
+    continue_on_error: bool,
+    failures: usize,
+    error: Option<String>,
+}
+
+impl MetadataFilter {
+    pub fn new(filter: Box<dyn EntityFilter>, keep: Vec<String>, drop: Vec<String>, continue_on_error: bool) -> Self {
+        MetadataFilter { filter, keep, drop, continue_on_error, failures: 0, error: None }
+    }
+
+    // `output` with the fields kept and dropped, or `None` if it's left as it is
+    fn retain(&mut self, raw: &str, output: &str) -> Result<Option<String>> {
+        let mut object = match serde_json::from_str::<Value>(output) {
+            Ok(Value::Object(object)) => object,
+            _ => return Ok(None),
+        };
+        let missing = self.keep.iter().any(|field| !object.contains_key(field));
+        let dropped = self.drop.iter().any(|field| object.contains_key(field));
+        if !missing && !dropped {
+            return Ok(None);
+        }
+        if missing {
+            let outline: Outline = match serde_json::from_str(raw) {
+                Ok(outline) => outline,
+                Err(error) => {
+                    filter::skip_entity(raw, &error, self.continue_on_error)?;
+                    self.failures += 1;
+                    self.error = Some(error.to_string());
+                    return Ok(None);
+                }
+            };
+            keep_fields(&mut object, &outline, &self.keep);
+        }
+        for field in &self.drop {
+            object.remove(field);
+        }
+        Ok(Some(Value::Object(object).to_string()))
+    }
+}
+
+fn keep_fields(object: &mut Map<String, Value>, outline: &Outline, fields: &[String]) {
+    for field in fields {
+        if let (false, Some(value)) = (object.contains_key(field), outline.field(field)) {
+            object.insert(field.clone(), value.clone());
+        }
+    }
+}
+
+impl EntityFilter for MetadataFilter {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let output = match self.filter.apply(raw)? {
+            Some(output) => output,
+            None => return Ok(None),
+        };
+        // each line of an output spanning several is an output of its own
+        let mut changed = false;
+        let mut lines = Vec::new();
+        for line in output.lines() {
+            match self.retain(raw, line)? {
+                Some(retained) => {
+                    changed = true;
+                    lines.push(Cow::Owned(retained));
+                }
+                None => lines.push(Cow::Borrowed(line)),
+            }
+        }
+        match changed {
+            true => Ok(Some(Cow::Owned(lines.join("\n")))),
+            false => Ok(Some(output)),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.failures + self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take().or_else(|| self.filter.take_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // keeps the id and labels, like a jq filter of `{id, labels}` would
+    struct Labels;
+
+    impl EntityFilter for Labels {
+        fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+            let entity: Value = serde_json::from_str(raw).unwrap();
+            Ok(Some(Cow::Owned(serde_json::json!({"id": entity["id"], "labels": entity["labels"]}).to_string())))
+        }
+    }
+
+    // writes entities out as they are, like --pass-through does
+    struct Through;
+
+    impl EntityFilter for Through {
+        fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+            Ok(Some(Cow::Borrowed(raw)))
+        }
+    }
+
+    const ENTITY: &str = r#"{"pageid":186,"ns":0,"title":"Q60","lastrevid":2050000000,"modified":"2024-01-01T00:00:00Z","id":"Q60","labels":{}}"#;
+
+    fn retained(filter: impl EntityFilter + 'static, keep: &str, drop: &str) -> Value {
+        let mut filter = MetadataFilter::new(Box::new(filter), parse_fields(keep).unwrap(), parse_fields(drop).unwrap(), false);
+        serde_json::from_str(&filter.apply(ENTITY).unwrap().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_parse_fields() {
+        assert_eq!(parse_fields("all").unwrap().len(), FIELDS.len());
+        assert_eq!(parse_fields("title, modified").unwrap(), vec!["title", "modified"]);
+        assert!(parse_fields("claims").is_err());
+    }
+
+    #[test]
+    fn test_metadata_filter() {
+        assert_eq!(retained(Labels, "lastrevid,modified", ""), serde_json::json!({"id": "Q60", "labels": {}, "lastrevid": 2050000000, "modified": "2024-01-01T00:00:00Z"}));
+        assert_eq!(retained(Labels, "", "all"), serde_json::json!({"id": "Q60", "labels": {}}));
+        assert_eq!(retained(Through, "", "pageid,ns"), serde_json::json!({"title": "Q60", "lastrevid": 2050000000, "modified": "2024-01-01T00:00:00Z", "id": "Q60", "labels": {}}));
+
+        // outputs needing no change aren't re-serialized
+        let mut filter = MetadataFilter::new(Box::new(Through), parse_fields("all").unwrap(), Vec::new(), false);
+        assert!(matches!(filter.apply(ENTITY).unwrap(), Some(Cow::Borrowed(ENTITY))));
+    }
+}
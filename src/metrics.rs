@@ -0,0 +1,171 @@
+/*!
+ * Live metrics of a run in the Prometheus text format, so long running jobs
+ * can be monitored and alerted on, e.g. in Kubernetes.
+ *
+ * A run updates the `Metrics` in `ProcessOptions::metrics` as it goes, and
+ * `serve` answers every HTTP request on a listening socket with them. Counters
+ * only ever go up, so rates like entities per second are best taken with
+ * Prometheus' `rate()`; `wikidump_entities_per_second` is the average over the
+ * whole run, for dashboards without one.
+ */
+
+use std::fmt::Write as _;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::thread;
+use std::time::{Duration, Instant};
+use log::{debug, info};
+
+// how long a scrape may take to send its request before it's dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters and gauges of a run, updated from all of its threads
+#[derive(Debug)]
+pub struct Metrics {
+    start: Instant,
+    /// Compressed bytes of the dump consumed
+    pub bytes_read: AtomicU64,
+    /// Decompressed bytes of the dump split into entities
+    pub bytes_decompressed: AtomicU64,
+    pub bytes_written: AtomicU64,
+    pub entities_read: AtomicU64,
+    pub entities_written: AtomicU64,
+    /// Entities which couldn't be filtered and were skipped
+    pub entities_failed: AtomicU64,
+    /// Batches read from the dump and waiting for a filtering thread
+    pub batches_waiting_filter: AtomicU64,
+    /// Batches filtered and waiting for their turn to be written
+    pub batches_waiting_write: AtomicU64,
+    /// Bytes held by entities read but not yet written, as limited by `ProcessOptions::max_memory`
+    pub memory_in_flight: AtomicU64,
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Metrics {
+            start: Instant::now(),
+            bytes_read: AtomicU64::new(0),
+            bytes_decompressed: AtomicU64::new(0),
+            bytes_written: AtomicU64::new(0),
+            entities_read: AtomicU64::new(0),
+            entities_written: AtomicU64::new(0),
+            entities_failed: AtomicU64::new(0),
+            batches_waiting_filter: AtomicU64::new(0),
+            batches_waiting_write: AtomicU64::new(0),
+            memory_in_flight: AtomicU64::new(0),
+        }
+    }
+}
+
+impl Metrics {
+    /// The metrics in the Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let entities_read = self.entities_read.load(Ordering::Relaxed);
+        let metrics: [(&str, &str, &str, f64); 12] = [
+            ("wikidump_bytes_read_total", "counter", "Compressed bytes of the dump consumed", self.bytes_read.load(Ordering::Relaxed) as f64),
+            ("wikidump_bytes_decompressed_total", "counter", "Decompressed bytes of the dump split into entities", self.bytes_decompressed.load(Ordering::Relaxed) as f64),
+            ("wikidump_bytes_written_total", "counter", "Bytes of output written", self.bytes_written.load(Ordering::Relaxed) as f64),
+            ("wikidump_entities_read_total", "counter", "Entities read from the dump", entities_read as f64),
+            ("wikidump_entities_written_total", "counter", "Entities with an output written", self.entities_written.load(Ordering::Relaxed) as f64),
+            ("wikidump_entities_failed_total", "counter", "Entities which could not be filtered and were skipped", self.entities_failed.load(Ordering::Relaxed) as f64),
+            ("wikidump_entities_per_second", "gauge", "Entities read per second, averaged over the whole run", entities_read as f64 / elapsed.max(f64::EPSILON)),
+            ("wikidump_batches_waiting_filter", "gauge", "Batches read and waiting for a filtering thread", self.batches_waiting_filter.load(Ordering::Relaxed) as f64),
+            ("wikidump_batches_waiting_write", "gauge", "Batches filtered and waiting to be written", self.batches_waiting_write.load(Ordering::Relaxed) as f64),
+            ("wikidump_memory_in_flight_bytes", "gauge", "Bytes held by entities read but not yet written", self.memory_in_flight.load(Ordering::Relaxed) as f64),
+            ("wikidump_elapsed_seconds", "gauge", "Seconds since the run started", elapsed),
+            ("wikidump_start_time_seconds", "gauge", "Unix time the run started at", start_time(self.start)),
+        ];
+        let mut output = String::new();
+        for (name, kind, help, value) in metrics {
+            // writing to a String can't fail
+            let _ = write!(output, "# HELP {} {}\n# TYPE {} {}\n{} {}\n", name, help, name, kind, name, value);
+        }
+        output
+    }
+
+    pub(crate) fn add(counter: &AtomicU64, n: u64) {
+        counter.fetch_add(n, Ordering::Relaxed);
+    }
+
+    pub(crate) fn sub(gauge: &AtomicU64, n: u64) {
+        // never wraps around, whatever order the threads get to it in
+        let _ = gauge.fetch_update(Ordering::Relaxed, Ordering::Relaxed, |value| Some(value.saturating_sub(n)));
+    }
+}
+
+fn start_time(start: Instant) -> f64 {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap_or_default();
+    (now.saturating_sub(start.elapsed())).as_secs_f64()
+}
+
+/// Listens on `address` (e.g. `0.0.0.0:9100`) and answers every HTTP request with `metrics` on a background
+/// thread, for as long as the process runs. Returns the address listened on, which tells the port picked for port 0
+pub fn serve(address: impl ToSocketAddrs, metrics: Arc<Metrics>) -> io::Result<SocketAddr> {
+    let listener = TcpListener::bind(address)?;
+    let address = listener.local_addr()?;
+    info!("Serving metrics on http://{}/metrics", address);
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let result = stream.and_then(|stream| respond(stream, &metrics));
+            if let Err(error) = result {
+                debug!("Could not serve metrics: {}", error);
+            }
+        }
+    });
+    Ok(address)
+}
+
+// answers a single scrape, whatever it asked for
+fn respond(mut stream: TcpStream, metrics: &Metrics) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    // only the end of the request's headers matters
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    let body = metrics.render();
+    write!(stream, "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}", body.len(), body)?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_serve() {
+        let metrics = Arc::new(Metrics::default());
+        Metrics::add(&metrics.entities_read, 42);
+        Metrics::sub(&metrics.batches_waiting_write, 1);
+        let address = serve("127.0.0.1:0", Arc::clone(&metrics)).unwrap();
+
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream.write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(response.contains("\n# TYPE wikidump_entities_read_total counter\nwikidump_entities_read_total 42\n"));
+        assert!(response.contains("\nwikidump_batches_waiting_write 0\n"));
+    }
+
+    #[test]
+    fn test_run_metrics() {
+        let metrics = Arc::new(Metrics::default());
+        let options = crate::ProcessOptions { metrics: Some(Arc::clone(&metrics)), ..crate::ProcessOptions::default() };
+        let stats = crate::process(Some("./tests/test-data.json.bz2".into()), &mut io::sink(), ".id", &options).unwrap();
+        assert_eq!(metrics.entities_read.load(Ordering::Relaxed), 8);
+        assert_eq!(metrics.bytes_written.load(Ordering::Relaxed), stats.bytes_out);
+        assert_eq!(metrics.bytes_decompressed.load(Ordering::Relaxed), stats.bytes_in);
+        assert_eq!(metrics.batches_waiting_filter.load(Ordering::Relaxed), 0);
+        assert_eq!(metrics.batches_waiting_write.load(Ordering::Relaxed), 0);
+        assert_eq!(metrics.memory_in_flight.load(Ordering::Relaxed), 0);
+    }
+}
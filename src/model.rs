@@ -0,0 +1,441 @@
+/*!
+ * Typed serde model of Wikidata entities, as found in the JSON dumps, see
+ * https://doc.wikimedia.org/Wikibase/master/php/docs_topics_json.html
+ *
+ * `Entity` mirrors the dump format, while `SimpleEntity` is a lossy, flattened
+ * variant (labels as plain strings, claims as plain values) that is much more
+ * convenient when ranks, qualifiers and references aren't needed.
+ */
+
+use std::collections::BTreeMap;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// Language code to term, e.g. `labels.en`
+pub type LabelMap = BTreeMap<String, Term>;
+
+/// Language code to terms, e.g. `aliases.en`
+pub type AliasMap = BTreeMap<String, Vec<Term>>;
+
+/// Property id to statements, e.g. `claims.P31`
+pub type ClaimMap = BTreeMap<String, Vec<Claim>>;
+
+/// Property id to snaks, as used by qualifiers and references
+pub type SnakMap = BTreeMap<String, Vec<Snak>>;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Entity {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub entity_type: String,
+    /// Only present on properties
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datatype: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: LabelMap,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub descriptions: LabelMap,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: AliasMap,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub claims: ClaimMap,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sitelinks: BTreeMap<String, Sitelink>,
+
+    // page metadata
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pageid: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ns: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lastrevid: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub modified: Option<String>,
+
+    // only present on lexemes
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub lemmas: LabelMap,
+    #[serde(default, rename = "lexicalCategory", skip_serializing_if = "Option::is_none")]
+    pub lexical_category: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub forms: Vec<Form>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub senses: Vec<Sense>,
+}
+
+/// A label, description, alias, lemma, representation or gloss in a given language
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Term {
+    pub language: String,
+    pub value: String,
+}
+
+/// A statement, which dumps (and the Wikibase API) call claims
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Claim {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    pub mainsnak: Snak,
+    #[serde(rename = "type", default = "statement")]
+    pub claim_type: String,
+    #[serde(default = "normal_rank")]
+    pub rank: Rank,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub qualifiers: SnakMap,
+    #[serde(default, rename = "qualifiers-order", skip_serializing_if = "Vec::is_empty")]
+    pub qualifiers_order: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub references: Vec<Reference>,
+}
+
+fn statement() -> String {
+    "statement".to_string()
+}
+
+fn normal_rank() -> Rank {
+    Rank::Normal
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Rank {
+    Preferred,
+    Normal,
+    Deprecated,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Reference {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default)]
+    pub snaks: SnakMap,
+    #[serde(default, rename = "snaks-order", skip_serializing_if = "Vec::is_empty")]
+    pub snaks_order: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Snak {
+    /// "value", "somevalue" or "novalue"
+    pub snaktype: String,
+    pub property: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datatype: Option<String>,
+    /// Only present when `snaktype` is "value"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datavalue: Option<DataValue>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(from = "RawDataValue", into = "RawDataValue")]
+pub enum DataValue {
+    String(String),
+    EntityId(EntityIdValue),
+    Time(TimeValue),
+    Quantity(QuantityValue),
+    GlobeCoordinate(GlobeCoordinateValue),
+    MonolingualText(MonolingualTextValue),
+    /// A type not known to this model (or a value not matching its type), kept as-is
+    Unknown { value_type: String, value: Value },
+}
+
+// the datavalue as it appears in the dump, so unknown types can be kept rather than failing the whole entity
+#[derive(Serialize, Deserialize)]
+struct RawDataValue {
+    #[serde(rename = "type")]
+    value_type: String,
+    value: Value,
+}
+
+impl From<RawDataValue> for DataValue {
+    fn from(raw: RawDataValue) -> Self {
+        let value = raw.value.clone();
+        let parsed = match raw.value_type.as_str() {
+            "string" => serde_json::from_value(value).map(DataValue::String),
+            "wikibase-entityid" => serde_json::from_value(value).map(DataValue::EntityId),
+            "time" => serde_json::from_value(value).map(DataValue::Time),
+            "quantity" => serde_json::from_value(value).map(DataValue::Quantity),
+            "globecoordinate" => serde_json::from_value(value).map(DataValue::GlobeCoordinate),
+            "monolingualtext" => serde_json::from_value(value).map(DataValue::MonolingualText),
+            _ => return DataValue::Unknown { value_type: raw.value_type, value: raw.value },
+        };
+        parsed.unwrap_or(DataValue::Unknown { value_type: raw.value_type, value: raw.value })
+    }
+}
+
+impl From<DataValue> for RawDataValue {
+    fn from(datavalue: DataValue) -> Self {
+        let (value_type, value) = match datavalue {
+            DataValue::String(value) => ("string", serde_json::to_value(value)),
+            DataValue::EntityId(value) => ("wikibase-entityid", serde_json::to_value(value)),
+            DataValue::Time(value) => ("time", serde_json::to_value(value)),
+            DataValue::Quantity(value) => ("quantity", serde_json::to_value(value)),
+            DataValue::GlobeCoordinate(value) => ("globecoordinate", serde_json::to_value(value)),
+            DataValue::MonolingualText(value) => ("monolingualtext", serde_json::to_value(value)),
+            DataValue::Unknown { value_type, value } => return RawDataValue { value_type, value },
+        };
+        RawDataValue { value_type: value_type.to_string(), value: value.unwrap_or(Value::Null) }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EntityIdValue {
+    /// Missing on some older items, in which case it can be built from `entity_type` and `numeric_id`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(rename = "entity-type")]
+    pub entity_type: String,
+    #[serde(default, rename = "numeric-id", skip_serializing_if = "Option::is_none")]
+    pub numeric_id: Option<u64>,
+}
+
+impl EntityIdValue {
+    /// The referenced entity's id, e.g. "Q42"
+    pub fn id(&self) -> Option<String> {
+        if let Some(id) = &self.id {
+            return Some(id.clone());
+        }
+        let prefix = match self.entity_type.as_str() {
+            "item" => "Q",
+            "property" => "P",
+            "lexeme" => "L",
+            _ => return None,
+        };
+        self.numeric_id.map(|numeric_id| format!("{}{}", prefix, numeric_id))
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TimeValue {
+    /// e.g. "+2001-12-31T00:00:00Z"
+    pub time: String,
+    #[serde(default)]
+    pub timezone: i64,
+    #[serde(default)]
+    pub before: i64,
+    #[serde(default)]
+    pub after: i64,
+    /// 0 (billion years) to 14 (seconds), 11 is a day
+    pub precision: u8,
+    pub calendarmodel: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct QuantityValue {
+    /// Decimal string with an explicit sign, e.g. "+1.5"
+    pub amount: String,
+    /// "1" for unitless quantities, otherwise an entity URI
+    pub unit: String,
+    #[serde(default, rename = "upperBound", skip_serializing_if = "Option::is_none")]
+    pub upper_bound: Option<String>,
+    #[serde(default, rename = "lowerBound", skip_serializing_if = "Option::is_none")]
+    pub lower_bound: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct GlobeCoordinateValue {
+    pub latitude: f64,
+    pub longitude: f64,
+    #[serde(default)]
+    pub altitude: Option<f64>,
+    #[serde(default)]
+    pub precision: Option<f64>,
+    pub globe: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct MonolingualTextValue {
+    pub text: String,
+    pub language: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sitelink {
+    pub site: String,
+    pub title: String,
+    #[serde(default)]
+    pub badges: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Form {
+    pub id: String,
+    #[serde(default)]
+    pub representations: LabelMap,
+    #[serde(default, rename = "grammaticalFeatures")]
+    pub grammatical_features: Vec<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub claims: ClaimMap,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Sense {
+    pub id: String,
+    #[serde(default)]
+    pub glosses: LabelMap,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub claims: ClaimMap,
+}
+
+impl Entity {
+    /// Parses a single raw entity, as found on one line of a dump
+    pub fn parse(raw: &str) -> serde_json::Result<Entity> {
+        serde_json::from_str(raw)
+    }
+
+    /// The numeric part of the id, e.g. 42 for "Q42"
+    pub fn numeric_id(&self) -> Option<u64> {
+        self.id.get(1..).and_then(|digits| digits.parse().ok())
+    }
+
+    /// Converts to the lossy simplified form, see `SimpleEntity`
+    pub fn simplify(&self) -> SimpleEntity {
+        SimpleEntity::from(self)
+    }
+}
+
+/// An entity exactly as it appears in the dump, only parsed on demand
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RawEntity<'a> {
+    raw: &'a str,
+}
+
+impl<'a> RawEntity<'a> {
+    pub fn new(raw: &'a str) -> Self {
+        RawEntity { raw }
+    }
+
+    /// The entity's JSON, on a single line
+    pub fn as_str(&self) -> &'a str {
+        self.raw
+    }
+
+    /// The entity's id, found without parsing the whole entity
+    pub fn id(&self) -> Option<&'a str> {
+        crate::splitter::entity_id(self.raw)
+    }
+
+    pub fn parse(&self) -> serde_json::Result<Entity> {
+        Entity::parse(self.raw)
+    }
+}
+
+/// A flattened entity: terms become plain strings and claims become the plain values of their
+/// main snaks, dropping ids, ranks (except deprecated statements, which are dropped), qualifiers
+/// and references
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+pub struct SimpleEntity {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub entity_type: String,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub descriptions: BTreeMap<String, String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub aliases: BTreeMap<String, Vec<String>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub claims: BTreeMap<String, Vec<Value>>,
+    /// Site to page title
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub sitelinks: BTreeMap<String, String>,
+}
+
+fn simplify_terms(terms: &LabelMap) -> BTreeMap<String, String> {
+    terms.iter().map(|(language, term)| (language.clone(), term.value.clone())).collect()
+}
+
+impl DataValue {
+    /// The value as plain JSON: ids for entities, strings for time and quantity amounts,
+    /// `{"latitude", "longitude"}` for coordinates, the text for monolingual text, and unknown values as-is
+    pub fn simplify(&self) -> Value {
+        match self {
+            DataValue::String(value) => Value::from(value.as_str()),
+            DataValue::EntityId(value) => value.id().map(Value::from).unwrap_or(Value::Null),
+            DataValue::Time(value) => Value::from(value.time.as_str()),
+            DataValue::Quantity(value) => Value::from(value.amount.as_str()),
+            DataValue::GlobeCoordinate(value) => serde_json::json!({ "latitude": value.latitude, "longitude": value.longitude }),
+            DataValue::MonolingualText(value) => Value::from(value.text.as_str()),
+            DataValue::Unknown { value, .. } => value.clone(),
+        }
+    }
+}
+
+impl From<&Entity> for SimpleEntity {
+    fn from(entity: &Entity) -> Self {
+        let claims = entity.claims.iter()
+            .map(|(property, claims)| {
+                let values = claims.iter()
+                    .filter(|claim| claim.rank != Rank::Deprecated)
+                    .filter_map(|claim| claim.mainsnak.datavalue.as_ref())
+                    .map(DataValue::simplify)
+                    .collect();
+                (property.clone(), values)
+            })
+            .filter(|(_, values): &(String, Vec<Value>)| !values.is_empty())
+            .collect();
+
+        SimpleEntity {
+            id: entity.id.clone(),
+            entity_type: entity.entity_type.clone(),
+            labels: simplify_terms(&entity.labels),
+            descriptions: simplify_terms(&entity.descriptions),
+            aliases: entity.aliases.iter()
+                .map(|(language, aliases)| (language.clone(), aliases.iter().map(|alias| alias.value.clone()).collect()))
+                .collect(),
+            claims,
+            sitelinks: entity.sitelinks.iter().map(|(site, sitelink)| (site.clone(), sitelink.title.clone())).collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use crate::{decoder, EntityReader};
+
+    #[test]
+    fn test_parse_entities() {
+        let file = File::open("./tests/test-data.json.bz2").unwrap();
+        let entities: Vec<Entity> = EntityReader::new(decoder::decoder(file)).unwrap()
+            .map(|raw| Entity::parse(&raw.unwrap()).unwrap())
+            .collect();
+        assert_eq!(entities.len(), 8);
+
+        let new_york = &entities[7];
+        assert_eq!(new_york.numeric_id(), Some(60));
+        assert_eq!(new_york.labels["en"].value, "New York City");
+        assert_eq!(new_york.sitelinks["dewiki"].badges, vec!["Q17437798"]);
+        match &new_york.claims["P625"][0].mainsnak.datavalue {
+            Some(DataValue::GlobeCoordinate(coordinate)) => assert_eq!(coordinate.latitude, 40.67),
+            other => panic!("Unexpected datavalue {:?}", other),
+        }
+
+        // everything known to the model survives a round trip
+        let reparsed = Entity::parse(&serde_json::to_string(new_york).unwrap()).unwrap();
+        assert_eq!(&reparsed, new_york);
+    }
+
+    #[test]
+    fn test_simplify() {
+        let raw = r#"{"id": "Q1", "type": "item", "labels": {"en": {"language": "en", "value": "universe"}},
+            "claims": {"P31": [
+                {"mainsnak": {"snaktype": "value", "property": "P31", "datavalue": {"value": {"entity-type": "item", "numeric-id": 36906466}, "type": "wikibase-entityid"}}, "type": "statement", "rank": "normal"},
+                {"mainsnak": {"snaktype": "value", "property": "P31", "datavalue": {"value": {"entity-type": "item", "id": "Q1"}, "type": "wikibase-entityid"}}, "type": "statement", "rank": "deprecated"},
+                {"mainsnak": {"snaktype": "novalue", "property": "P31"}, "type": "statement", "rank": "normal"},
+                {"mainsnak": {"snaktype": "value", "property": "P31", "datavalue": {"value": [1, 2], "type": "some-new-type"}}, "rank": "normal"}
+            ]}}"#;
+        let simple = Entity::parse(raw).unwrap().simplify();
+        assert_eq!(simple.labels["en"], "universe");
+        assert_eq!(simple.claims["P31"], vec![Value::from("Q36906466"), serde_json::json!([1, 2])]);
+    }
+}
@@ -0,0 +1,221 @@
+/*!
+ * Writing outputs straight into a MongoDB collection (with the `mongodb`
+ * feature), without a separate `mongoimport` step parsing them again.
+ *
+ * Each output has to be a JSON object with the id of its entity, which
+ * becomes its `_id`. Outputs are written in batches, either inserted (the
+ * fastest, into a new collection) or replacing the document with the same
+ * `_id`, if there is one (for refreshing a collection from a newer dump).
+ *
+ * The client is synchronous, and blocks on a runtime of its own, so batches are
+ * written from a thread of their own while the next one is gathered.
+ */
+
+use std::str::FromStr;
+use std::sync::mpsc::{self, SyncSender};
+use std::thread::{self, JoinHandle};
+use log::debug;
+use mongodb::bson::{doc, Bson, Document};
+use mongodb::options::InsertManyOptions;
+use mongodb::sync::{Client, Collection, Database};
+use crate::error::{ProcessError, Result};
+use crate::sink::Sink;
+
+/// Most documents written at once
+pub const BATCH_SIZE: usize = 1000;
+
+// most bytes of outputs written at once, well under the 16MiB MongoDB allows a command
+const BATCH_BYTES: usize = 8 * 1024 * 1024;
+
+// batches waiting for the writer, before the sink waits for it to catch up
+const QUEUED_BATCHES: usize = 2;
+
+fn mongodb_error(error: mongodb::error::Error) -> ProcessError {
+    ProcessError::MongoDb(error.to_string())
+}
+
+/// How a `MongoDbSink` writes documents
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WriteMode {
+    /// insertMany, failing on documents whose `_id` is already in the collection
+    Insert,
+    /// replaceOne by `_id` for each document, inserting those which aren't there yet
+    Replace,
+}
+
+impl FromStr for WriteMode {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "insert" => Ok(WriteMode::Insert),
+            "replace" => Ok(WriteMode::Replace),
+            _ => Err(format!("Invalid MongoDB write mode '{}', expected insert or replace", value)),
+        }
+    }
+}
+
+// writes `documents` in one command, returning how many were
+fn write_batch(database: &Database, collection: &Collection<Document>, documents: Vec<Document>, mode: WriteMode) -> Result<usize> {
+    let count = documents.len();
+    match mode {
+        WriteMode::Insert => {
+            let options = InsertManyOptions::builder().ordered(false).build();
+            collection.insert_many(documents, options).map_err(mongodb_error)?;
+        }
+        WriteMode::Replace => {
+            let updates = documents.into_iter()
+                .map(|document| doc! { "q": { "_id": document.get("_id").cloned().unwrap_or(Bson::Null) }, "u": document, "upsert": true })
+                .collect::<Vec<_>>();
+            let reply = database.run_command(doc! { "update": collection.name(), "updates": updates, "ordered": false }, None).map_err(mongodb_error)?;
+            if let Ok(errors) = reply.get_array("writeErrors") {
+                // the reply's own error, should a server send the field without any
+                let first = match errors.first() {
+                    Some(error) => error.to_string(),
+                    None => reply.get_str("errmsg").map_or_else(|_| reply.to_string(), str::to_string),
+                };
+                return Err(ProcessError::MongoDb(format!("{} documents couldn't be replaced, the first because {}", errors.len(), first)));
+            }
+        }
+    }
+    Ok(count)
+}
+
+/// Writes the outputs it's given as documents of a MongoDB collection, see the module documentation
+pub struct MongoDbSink {
+    mode: WriteMode,
+    pending: Vec<Document>,
+    pending_bytes: usize,
+    batches: Option<SyncSender<Vec<Document>>>,
+    writer: Option<JoinHandle<Result<u64>>>,
+}
+
+impl MongoDbSink {
+    /// Connects to the server at `url`, e.g. `mongodb://127.0.0.1:27017/wikidata`, to write to `collection` of the
+    /// database given in it. Fails if there's no database, or the server can't be reached.
+    pub fn connect(url: &str, collection: &str, mode: WriteMode) -> Result<Self> {
+        let (batches, received) = mpsc::sync_channel::<Vec<Document>>(QUEUED_BATCHES);
+        let (connected, connection) = mpsc::sync_channel(1);
+        let url = url.to_string();
+        let collection = collection.to_string();
+        let writer = thread::Builder::new()
+            .name(String::from("mongodb"))
+            .spawn(move || {
+                let connect = || -> Result<Database> {
+                    let database = Client::with_uri_str(&url).map_err(mongodb_error)?
+                        .default_database()
+                        .ok_or_else(|| ProcessError::MongoDb(format!("{} doesn't name a database, e.g. mongodb://host/wikidata", url)))?;
+                    database.run_command(doc! { "ping": 1 }, None).map_err(mongodb_error)?;
+                    Ok(database)
+                };
+                let database = match connect() {
+                    Ok(database) => database,
+                    Err(error) => {
+                        let _ = connected.send(Err(error));
+                        return Ok(0);
+                    }
+                };
+                let _ = connected.send(Ok(()));
+                let collection = database.collection::<Document>(&collection);
+                let mut written = 0;
+                for batch in received {
+                    written += write_batch(&database, &collection, batch, mode)? as u64;
+                    debug!("Wrote {} documents to {}", written, collection.namespace());
+                }
+                Ok(written)
+            })
+            .map_err(|error| ProcessError::MongoDb(error.to_string()))?;
+        connection.recv().map_err(|_| ProcessError::MongoDb(String::from("The writer stopped before connecting")))??;
+        Ok(MongoDbSink { mode, pending: Vec::new(), pending_bytes: 0, batches: Some(batches), writer: Some(writer) })
+    }
+
+    // the result of the writer, once it has stopped
+    fn join(&mut self) -> Result<u64> {
+        match self.writer.take() {
+            Some(writer) => writer.join().map_err(|_| ProcessError::MongoDb(String::from("The writer panicked")))?,
+            None => Ok(0),
+        }
+    }
+
+    // hands the documents gathered so far to the writer
+    fn send(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let batch = std::mem::take(&mut self.pending);
+        self.pending_bytes = 0;
+        let sent = self.batches.as_ref().map(|batches| batches.send(batch).is_ok()).unwrap_or(false);
+        match sent {
+            true => Ok(()),
+            // the writer only stops taking batches once it failed
+            false => {
+                self.batches = None;
+                self.join()?;
+                Err(ProcessError::MongoDb(String::from("The writer stopped")))
+            }
+        }
+    }
+}
+
+impl Sink for MongoDbSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let not_a_document = |message: String| ProcessError::MongoDb(format!("Could not write an output as a document, the jq filter has to keep objects with their id: {}", message));
+        let mut document: Document = serde_json::from_str(output).map_err(|error| not_a_document(error.to_string()))?;
+        if !document.contains_key("_id") {
+            let id = document.get_str("id").map_err(|error| not_a_document(error.to_string()))?.to_string();
+            document.insert("_id", id);
+        }
+        self.pending_bytes += output.len();
+        self.pending.push(document);
+        if self.pending.len() >= BATCH_SIZE || self.pending_bytes >= BATCH_BYTES {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.send()
+    }
+
+    /// Hands the last documents to the writer, and waits for it to write them
+    fn finalize(&mut self) -> Result<()> {
+        self.send()?;
+        self.batches = None;
+        let written = self.join()?;
+        debug!("Wrote {} documents ({:?})", written, self.mode);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // needs a server, given as MONGODB_URL, e.g. mongodb://127.0.0.1:27017/test
+    #[test]
+    #[ignore]
+    fn test_mongodb_sink() {
+        let url = std::env::var("MONGODB_URL").expect("MONGODB_URL names a database to test with");
+        let name = format!("wikidump_test_{}", std::process::id());
+        let mut sink = MongoDbSink::connect(&url, &name, WriteMode::Insert).unwrap();
+        sink.write_entity(r#"{"id":"Q1","label":"universe"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"Earth"}"#).unwrap();
+        sink.finalize().unwrap();
+        let mut sink = MongoDbSink::connect(&url, &name, WriteMode::Replace).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"the Earth"}"#).unwrap();
+        sink.finalize().unwrap();
+        assert!(matches!(sink.write_entity(r#"["Q3"]"#), Err(ProcessError::MongoDb(_))));
+
+        let collection = Client::with_uri_str(&url).unwrap().default_database().unwrap().collection::<Document>(&name);
+        assert_eq!(collection.count_documents(None, None).unwrap(), 2);
+        let earth = collection.find_one(doc! { "_id": "Q2" }, None).unwrap().unwrap();
+        assert_eq!(earth.get_str("label").unwrap(), "the Earth");
+        collection.drop(None).unwrap();
+    }
+
+    #[test]
+    fn test_write_mode() {
+        assert_eq!("replace".parse::<WriteMode>(), Ok(WriteMode::Replace));
+        assert!("upsert".parse::<WriteMode>().is_err());
+    }
+}
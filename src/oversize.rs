@@ -0,0 +1,132 @@
+/*!
+ * A guard against oversized entities, so a single pathological entity of
+ * hundreds of megabytes can't blow up the memory jq needs for it or stall a
+ * filtering thread for minutes.
+ *
+ * Entities over the size limit, in bytes of their JSON, are either:
+ *
+ * - skipped, counting as entities which couldn't be filtered, so their ids
+ *   end up in the error report (see `report`)
+ * - passed on with their statements dropped, which is where the size of
+ *   pathological entities almost always is, keeping their terms and sitelinks
+ * - turned into an error stopping the run
+ */
+
+use std::borrow::Cow;
+use std::str::FromStr;
+use log::{info, warn};
+use serde_json::Value;
+use crate::error::{ProcessError, Result};
+use crate::filter::EntityFilter;
+use crate::splitter;
+
+/// What to do with entities over the size limit, see the module documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizePolicy {
+    Skip,
+    TruncateClaims,
+    Error,
+}
+
+impl FromStr for OversizePolicy {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "skip" => Ok(OversizePolicy::Skip),
+            "truncate-claims" => Ok(OversizePolicy::TruncateClaims),
+            "error" => Ok(OversizePolicy::Error),
+            _ => Err(format!("Invalid oversize policy '{}', expected skip, truncate-claims or error", value)),
+        }
+    }
+}
+
+/// `raw` without its statements, or `None` if it isn't a JSON object
+pub fn truncate_claims(raw: &str) -> Option<String> {
+    let mut entity = match serde_json::from_str::<Value>(raw) {
+        Ok(Value::Object(entity)) => entity,
+        _ => return None,
+    };
+    entity.remove("claims");
+    Some(Value::Object(entity).to_string())
+}
+
+/// Applies `policy` to the entities over `limit` bytes, passing the others on to another filter
+pub struct OversizeFilter {
+    limit: usize,
+    policy: OversizePolicy,
+    filter: Box<dyn EntityFilter>,
+    failures: usize,
+    error: Option<String>,
+}
+
+impl OversizeFilter {
+    pub fn new(limit: usize, policy: OversizePolicy, filter: Box<dyn EntityFilter>) -> Self {
+        OversizeFilter { limit, policy, filter, failures: 0, error: None }
+    }
+
+    fn skip(&mut self, message: String) -> Result<Option<Cow<'static, str>>> {
+        warn!("Skipping {}", message);
+        self.failures += 1;
+        self.error = Some(message);
+        Ok(None)
+    }
+}
+
+impl EntityFilter for OversizeFilter {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        if raw.len() <= self.limit {
+            return self.filter.apply(raw);
+        }
+        let id = splitter::entity_id(raw).unwrap_or("(unknown)").to_string();
+        let message = format!("entity {} of {} bytes, over the maximum entity size of {}", id, raw.len(), self.limit);
+        match self.policy {
+            OversizePolicy::Error => Err(ProcessError::OversizedEntity { id, size: raw.len(), limit: self.limit }),
+            OversizePolicy::Skip => self.skip(message),
+            OversizePolicy::TruncateClaims => match truncate_claims(raw) {
+                Some(truncated) => {
+                    info!("Dropping the statements of {}", message);
+                    let output = self.filter.apply(&truncated)?;
+                    Ok(output.map(|output| Cow::Owned(output.into_owned())))
+                }
+                None => self.skip(message),
+            },
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.failures + self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take().or_else(|| self.filter.take_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::jq_filter_factory;
+
+    const ENTITY: &str = r#"{"id":"Q42","labels":{"en":{"language":"en","value":"Douglas Adams"}},"claims":{"P31":[{"mainsnak":{"property":"P31"}}]}}"#;
+
+    #[test]
+    fn test_oversize_filter() {
+        let mut filter = OversizeFilter::new(ENTITY.len(), OversizePolicy::Error, jq_filter_factory(".id", false, false)().unwrap());
+        assert_eq!(filter.apply(ENTITY).unwrap().as_deref(), Some("\"Q42\"\n"));
+
+        let mut filter = OversizeFilter::new(64, OversizePolicy::Error, jq_filter_factory(".id", false, false)().unwrap());
+        assert!(matches!(filter.apply(ENTITY), Err(ProcessError::OversizedEntity { size, limit: 64, .. }) if size == ENTITY.len()));
+
+        let mut filter = OversizeFilter::new(64, OversizePolicy::Skip, jq_filter_factory(".id", false, false)().unwrap());
+        assert_eq!(filter.apply(ENTITY).unwrap(), None);
+        assert_eq!(filter.apply("{\"id\":\"Q1\"}").unwrap().as_deref(), Some("\"Q1\"\n"));
+        assert_eq!(filter.failures(), 1);
+        assert!(filter.take_error().unwrap().starts_with("entity Q42 of"));
+
+        let mut filter = OversizeFilter::new(64, OversizePolicy::TruncateClaims, jq_filter_factory("[.id, .claims]", false, false)().unwrap());
+        assert_eq!(filter.apply(ENTITY).unwrap().as_deref(), Some("[\"Q42\",null]\n"));
+        assert_eq!(filter.failures(), 0);
+        assert!("truncate".parse::<OversizePolicy>().is_err());
+    }
+}
@@ -0,0 +1,256 @@
+/*!
+ * Simplified entities as a Parquet file, for loading a snapshot into
+ * DuckDB, Spark, pandas and the like without them parsing JSON.
+ *
+ * Outputs are parsed as entities and simplified (see `model::SimpleEntity`),
+ * and written as rows of the `sql` module's `entities` table: `id` and `type`
+ * as strings, labels, descriptions and sitelinks as maps of strings, aliases
+ * and claims as maps of lists of strings. Row groups are compressed with
+ * Snappy.
+ *
+ * `EdgeParquetSink` writes edge lists the same way, taking the TSV rows made
+ * by the `edges` module: `source`, `property` and `target` columns, and a
+ * nullable `qualified` one with qualifiers.
+ *
+ * Parquet has its metadata in a footer, so the file is only readable once the
+ * sink is finalized. Each row group is kept in memory until it's complete,
+ * then written out, so outputs can be a pipe or stdout as well as a file.
+ */
+
+use std::io::Write;
+use std::sync::Arc;
+use datafusion::arrow::array::{ArrayRef, StringBuilder};
+use datafusion::arrow::datatypes::{DataType, Field, Schema, SchemaRef};
+use datafusion::arrow::record_batch::RecordBatch;
+use parquet::arrow::ArrowWriter;
+use parquet::basic::Compression;
+use parquet::errors::ParquetError;
+use parquet::file::properties::WriterProperties;
+use crate::error::{ProcessError, Result};
+use crate::model::Entity;
+use crate::sink::Sink;
+use crate::sql::{self, EntityBatchBuilder};
+
+/// Entities in each row group by default, which is what the sink holds in memory
+pub const DEFAULT_ROW_GROUP_SIZE: usize = 65536;
+
+fn parquet_error(error: ParquetError) -> ProcessError {
+    ProcessError::Parquet(error.to_string())
+}
+
+// writes record batches to `output` as they're encoded, a row group at a time
+struct ParquetWriter<W: Write> {
+    // gone once the footer is written
+    writer: Option<ArrowWriter<Vec<u8>>>,
+    output: W,
+}
+
+impl<W: Write> ParquetWriter<W> {
+    fn new(output: W, schema: SchemaRef, row_group_size: usize) -> Result<Self> {
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::SNAPPY)
+            .set_max_row_group_size(row_group_size.max(1))
+            .build();
+        let writer = ArrowWriter::try_new(Vec::new(), schema, Some(properties)).map_err(parquet_error)?;
+        Ok(ParquetWriter { writer: Some(writer), output })
+    }
+
+    // hands `batch` to the writer, writing out whatever it has encoded since
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        let writer = match &mut self.writer {
+            Some(writer) => writer,
+            None => return Err(ProcessError::Parquet(String::from("The file is already finalized"))),
+        };
+        writer.write(batch).map_err(parquet_error)?;
+        let encoded = std::mem::take(writer.inner_mut());
+        self.output.write_all(&encoded).map_err(ProcessError::Write)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.output.flush().map_err(ProcessError::Write)
+    }
+
+    // writes the last row group and the footer
+    fn finish(&mut self) -> Result<()> {
+        if let Some(writer) = self.writer.take() {
+            let rest = writer.into_inner().map_err(parquet_error)?;
+            self.output.write_all(&rest).map_err(ProcessError::Write)?;
+        }
+        self.flush()
+    }
+}
+
+/// Writes the entities it's given, which must be whole entities, to `output` as a Parquet file of the `entities`
+/// table, see the module documentation
+pub struct ParquetSink<W: Write> {
+    builder: EntityBatchBuilder,
+    writer: ParquetWriter<W>,
+}
+
+impl<W: Write> ParquetSink<W> {
+    /// Writes `row_group_size` entities at a time to `output`
+    pub fn new(output: W, row_group_size: usize) -> Result<Self> {
+        Ok(ParquetSink { builder: EntityBatchBuilder::default(), writer: ParquetWriter::new(output, sql::schema(), row_group_size)? })
+    }
+}
+
+impl<W: Write> Sink for ParquetSink<W> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let entity = Entity::parse(output)
+            .map_err(|error| ProcessError::Parquet(format!("Output isn't an entity ({}), the jq filter has to keep whole entities: {:.100}", error, output)))?;
+        self.builder.append(&entity.simplify());
+        if self.builder.len() >= sql::DEFAULT_BATCH_SIZE {
+            self.writer.write(&self.builder.finish())?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    /// Writes the last entities and the footer
+    fn finalize(&mut self) -> Result<()> {
+        if !self.builder.is_empty() {
+            self.writer.write(&self.builder.finish())?;
+        }
+        self.writer.finish()
+    }
+}
+
+/// Schema of the edge lists written by `EdgeParquetSink`, with a `qualified` column if `qualifiers`
+pub fn edge_schema(qualifiers: bool) -> SchemaRef {
+    let mut fields = vec![
+        Field::new("source", DataType::Utf8, false),
+        Field::new("property", DataType::Utf8, false),
+        Field::new("target", DataType::Utf8, false),
+    ];
+    if qualifiers {
+        fields.push(Field::new("qualified", DataType::Utf8, true));
+    }
+    Arc::new(Schema::new(fields))
+}
+
+/// Writes the rows of edge lists it's given, tab separated with one per line as `edges` makes them, to `output` as
+/// a Parquet file, see the module documentation
+pub struct EdgeParquetSink<W: Write> {
+    schema: SchemaRef,
+    columns: Vec<StringBuilder>,
+    rows: usize,
+    writer: ParquetWriter<W>,
+}
+
+impl<W: Write> EdgeParquetSink<W> {
+    /// Writes `row_group_size` edges at a time to `output`, with the property each qualifies if `qualifiers`
+    pub fn new(output: W, qualifiers: bool, row_group_size: usize) -> Result<Self> {
+        let schema = edge_schema(qualifiers);
+        let columns = schema.fields().iter().map(|_| StringBuilder::new()).collect();
+        let writer = ParquetWriter::new(output, Arc::clone(&schema), row_group_size)?;
+        Ok(EdgeParquetSink { schema, columns, rows: 0, writer })
+    }
+
+    fn write_batch(&mut self) -> Result<()> {
+        self.rows = 0;
+        let columns = self.columns.iter_mut().map(|column| Arc::new(column.finish()) as ArrayRef).collect();
+        let batch = RecordBatch::try_new(Arc::clone(&self.schema), columns).expect("a column for each field");
+        self.writer.write(&batch)
+    }
+}
+
+impl<W: Write> Sink for EdgeParquetSink<W> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        for row in output.lines() {
+            let values = row.split('\t').collect::<Vec<_>>();
+            if values.len() != self.columns.len() {
+                return Err(ProcessError::Parquet(format!("Output isn't a row of an edge list with {} columns: {:.100}", self.columns.len(), row)));
+            }
+            for (column, value) in self.columns.iter_mut().zip(values) {
+                // only the qualified property is ever empty, for the statements themselves
+                match value {
+                    "" => column.append_null(),
+                    value => column.append_value(value),
+                }
+            }
+            self.rows += 1;
+        }
+        if self.rows >= sql::DEFAULT_BATCH_SIZE {
+            self.write_batch()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+
+    /// Writes the last edges and the footer
+    fn finalize(&mut self) -> Result<()> {
+        if self.rows > 0 {
+            self.write_batch()?;
+        }
+        self.writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use datafusion::arrow::array::{Array, StringArray};
+    use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+    use crate::Pipeline;
+
+    // the values of the string column `name` of `batch`
+    fn strings<'a>(batch: &'a RecordBatch, name: &str) -> Vec<Option<&'a str>> {
+        batch.column_by_name(name).unwrap().as_any().downcast_ref::<StringArray>().unwrap().iter().collect()
+    }
+
+    #[test]
+    fn test_parquet_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.parquet");
+        Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .entity_sink(ParquetSink::new(File::create(&path).unwrap(), 3).unwrap())
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap();
+        let columns = reader.schema().fields().iter().map(|field| field.name().as_str()).collect::<Vec<_>>();
+        assert_eq!(columns, ["id", "type", "labels", "descriptions", "aliases", "claims", "sitelinks"]);
+        assert_eq!(reader.metadata().num_row_groups(), 3);
+        let batches = reader.build().unwrap().collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        let ids = batches.iter().flat_map(|batch| strings(batch, "id")).collect::<Vec<_>>();
+        assert_eq!(ids.len(), 8);
+        assert!(ids.contains(&Some("Q60")) && ids.contains(&Some("P1")));
+    }
+
+    #[test]
+    fn test_not_an_entity() {
+        let mut sink = ParquetSink::new(Vec::new(), 64).unwrap();
+        assert!(matches!(sink.write_entity(r#"{"label":"universe"}"#), Err(ProcessError::Parquet(_))));
+    }
+
+    #[test]
+    fn test_edge_parquet_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("edges.parquet");
+        let mut sink = EdgeParquetSink::new(File::create(&path).unwrap(), true, 64).unwrap();
+        sink.write_entity("Q42\tP31\tQ5\t\nQ42\tP642\tQ1\tP31").unwrap();
+        sink.write_entity("Q1\tP31\tQ36906466\t").unwrap();
+        sink.finalize().unwrap();
+
+        let reader = ParquetRecordBatchReaderBuilder::try_new(File::open(&path).unwrap()).unwrap().build().unwrap();
+        let batches = reader.collect::<std::result::Result<Vec<_>, _>>().unwrap();
+        assert_eq!(batches.len(), 1);
+        let column = |name| strings(&batches[0], name);
+        assert_eq!(column("source"), [Some("Q42"), Some("Q42"), Some("Q1")]);
+        assert_eq!(column("target"), [Some("Q5"), Some("Q1"), Some("Q36906466")]);
+        assert_eq!(column("qualified"), [None, Some("P31"), None]);
+
+        let mut sink = EdgeParquetSink::new(Vec::new(), false, 64).unwrap();
+        assert!(matches!(sink.write_entity("Q42\tP31"), Err(ProcessError::Parquet(_))));
+    }
+}
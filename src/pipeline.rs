@@ -0,0 +1,243 @@
+/*!
+ * A builder for composing the same stages the CLI uses:
+ *
+ * ```no_run
+ * use wikidump_process::{Pipeline, ProcessOptions};
+ *
+ * let mut output = Vec::new();
+ * Pipeline::builder()
+ *     .source("./example.json.bz2")
+ *     .filter(r#"select(.type == "item") | .labels.en.value"#)
+ *     .transform(|label| Some(label.to_uppercase()))
+ *     .sink(&mut output)
+ *     .options(ProcessOptions { threads: 4, ..ProcessOptions::default() })
+ *     .build()?
+ *     .run()?;
+ * # Ok::<(), wikidump_process::error::ProcessError>(())
+ * ```
+ */
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Arc;
+use crate::error::{ProcessError, Result};
+use crate::filter::{self, EntityFilter, FilterFactory};
+use crate::process::{self, ProcessOptions, ProcessStats};
+use crate::sink::{Sink, WriteSink};
+use crate::source::{FileSource, Source};
+
+/// A function applied to the output of each entity after filtering, in the same thread.
+///
+/// It receives the output without its trailing newline, and can drop it by returning `None`.
+pub type Transform = Arc<dyn Fn(String) -> Option<String> + Send + Sync>;
+
+pub struct Pipeline<'a> {
+    source: Box<dyn Source + 'a>,
+    filter: FilterFactory,
+    transforms: Vec<Transform>,
+    sink: Box<dyn Sink + 'a>,
+    options: ProcessOptions,
+}
+
+#[derive(Default)]
+pub struct PipelineBuilder<'a> {
+    source: Option<Box<dyn Source + 'a>>,
+    filter: Option<String>,
+    entity_filter: Option<FilterFactory>,
+    transforms: Vec<Transform>,
+    sink: Option<Box<dyn Write + 'a>>,
+    entity_sink: Option<Box<dyn Sink + 'a>>,
+    options: ProcessOptions,
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn builder() -> PipelineBuilder<'a> {
+        PipelineBuilder::default()
+    }
+
+    /// Processes the whole source, see `process::process`
+    pub fn run(mut self) -> Result<ProcessStats> {
+        process::run(self.source.as_mut(), self.sink.as_mut(), &self.filter, &self.transforms, &self.options)
+    }
+}
+
+impl<'a> PipelineBuilder<'a> {
+    /// The bzip2 compressed dump to read
+    pub fn source(self, path: impl Into<PathBuf>) -> Self {
+        self.dump_source(FileSource::new(path))
+    }
+
+    /// Reads the bzip2 compressed dump from somewhere other than a local file, e.g. stdin or HTTP
+    pub fn dump_source(mut self, source: impl Source + 'a) -> Self {
+        self.source = Some(Box::new(source));
+        self
+    }
+
+    /// The jq filter applied to each entity, by default the identity filter "."
+    pub fn filter(mut self, jq_filter: impl Into<String>) -> Self {
+        self.filter = Some(jq_filter.into());
+        self
+    }
+
+    /// Filters entities with another engine in place of jq. `new_filter` is called once per filtering thread
+    pub fn entity_filter<F: EntityFilter + 'static>(mut self, new_filter: impl Fn() -> Result<F> + Send + Sync + 'static) -> Self {
+        self.entity_filter = Some(Arc::new(move || Ok(Box::new(new_filter()?) as Box<dyn EntityFilter>)));
+        self
+    }
+
+    /// Adds a transform, run after the filter and any previously added transforms
+    pub fn transform(mut self, transform: impl Fn(String) -> Option<String> + Send + Sync + 'static) -> Self {
+        self.transforms.push(Arc::new(transform));
+        self
+    }
+
+    /// Where the output is written, as ndjson
+    pub fn sink(mut self, output: impl Write + 'a) -> Self {
+        self.sink = Some(Box::new(output));
+        self
+    }
+
+    /// Hands the output for each entity to `sink` rather than writing it out
+    pub fn entity_sink(mut self, sink: impl Sink + 'a) -> Self {
+        self.entity_sink = Some(Box::new(sink));
+        self
+    }
+
+    pub fn options(mut self, options: ProcessOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build(self) -> Result<Pipeline<'a>> {
+        let source = self.source.ok_or(ProcessError::MissingInput)?;
+        let sink: Box<dyn Sink + 'a> = match (self.sink, self.entity_sink) {
+            (Some(_), Some(_)) => return Err(ProcessError::InvalidPipeline("both a sink and an entity sink given")),
+            (Some(output), None) => Box::new(WriteSink::new(output, self.options.write_buffer_size)),
+            (None, Some(entity_sink)) => entity_sink,
+            (None, None) => return Err(ProcessError::InvalidPipeline("no sink given")),
+        };
+        let filter = match (self.filter, self.entity_filter) {
+            (Some(_), Some(_)) => return Err(ProcessError::InvalidPipeline("both a jq filter and an entity filter given")),
+            (None, Some(entity_filter)) => entity_filter,
+            (jq_filter, None) => {
+                let jq_filter = jq_filter.unwrap_or_else(|| ".".to_string());
+                filter::jq_filter_factory(&jq_filter, self.options.continue_on_error, self.options.pass_through)
+            }
+        };
+        Ok(Pipeline {
+            source,
+            filter,
+            transforms: self.transforms,
+            sink,
+            options: self.options,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pipeline() {
+        let mut output = Vec::new();
+        Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .filter(".id")
+            .transform(|id| id.contains('Q').then_some(id))
+            .transform(|id| Some(id.to_lowercase()))
+            .sink(&mut output)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\"q1\"\n\"q2\"\n\"q3\"\n\"q4\"\n\"q5\"\n\"q6\"\n\"q60\"\n");
+    }
+
+    struct IdFilter;
+
+    impl EntityFilter for IdFilter {
+        fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<std::borrow::Cow<'a, str>>> {
+            Ok(crate::splitter::entity_id(raw).map(std::borrow::Cow::Borrowed))
+        }
+    }
+
+    #[test]
+    fn test_pipeline_with_entity_filter() {
+        let mut output = Vec::new();
+        Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .entity_filter(|| Ok(IdFilter))
+            .sink(&mut output)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "Q1\nQ2\nQ3\nQ4\nQ5\nQ6\nP1\nQ60\n");
+    }
+
+    #[derive(Default)]
+    struct IdSink {
+        ids: Vec<String>,
+        finalized: bool,
+    }
+
+    impl Sink for IdSink {
+        fn write_entity(&mut self, output: &str) -> Result<()> {
+            self.ids.push(output.to_string());
+            Ok(())
+        }
+
+        fn flush(&mut self) -> Result<()> {
+            Ok(())
+        }
+
+        fn finalize(&mut self) -> Result<()> {
+            self.finalized = true;
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_pipeline_with_entity_sink() {
+        let mut sink = IdSink::default();
+        Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .filter(".id")
+            .entity_sink(&mut sink)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(sink.ids, vec!["\"Q1\"", "\"Q2\"", "\"Q3\"", "\"Q4\"", "\"Q5\"", "\"Q6\"", "\"P1\"", "\"Q60\""]);
+        assert!(sink.finalized);
+    }
+
+    struct BytesSource(Vec<u8>);
+
+    impl Source for BytesSource {
+        fn open(&mut self) -> Result<(crate::source::DumpReader, Option<u64>)> {
+            Ok((Box::new(std::io::Cursor::new(self.0.clone())), None))
+        }
+    }
+
+    #[test]
+    fn test_pipeline_with_dump_source() {
+        let dump = std::fs::read("./tests/test-data.json.bz2").unwrap();
+        let mut output = Vec::new();
+        Pipeline::builder()
+            .dump_source(BytesSource(dump))
+            .filter(".id")
+            .sink(&mut output)
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 8);
+    }
+
+    #[test]
+    fn test_pipeline_requires_sink() {
+        assert!(matches!(Pipeline::builder().source("./tests/test-data.json.bz2").build(), Err(ProcessError::InvalidPipeline(_))));
+    }
+}
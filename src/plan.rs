@@ -0,0 +1,306 @@
+/*!
+ * Splitting the work of filtering a dump between several machines. The dumps
+ * published by Wikimedia are made of many bzip2 streams, and decoding can
+ * start at any of them, so a dump can be cut into ranges of whole streams to
+ * be filtered separately (see `decoder::RangeDecoder`).
+ *
+ * `plan` finds where the streams start by looking for their headers in the
+ * compressed bytes, without decompressing anything but a few sample streams
+ * to estimate how many entities each range has, and groups them into ranges
+ * of about the same size. The resulting manifest is JSON, e.g.
+ *
+ * ```json
+ * {"dump": "latest-all.json.bz2", "size": 3000000, "streams": 1200, "estimated_entities": 90000,
+ *  "ranges": [{"index": 0, "start": 0, "end": 1001234, "streams": 401, "estimated_entities": 30041}, ...]}
+ * ```
+ *
+ * Workers sharing a filesystem claim ranges by creating a file named after
+ * the range's index in the `<manifest>.claims` directory next to it, which
+ * only one of them can do. The claim of a range whose worker failed can be
+ * deleted for another worker to take it over.
+ */
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use bzip2::bufread::BzDecoder;
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use crate::decoder::{self, StreamRange, BUFFER_LENGTH};
+use crate::error::{ProcessError, Result};
+use crate::progress::{Progress, Reporter};
+
+/// The compressed size ranges are planned to have by default
+pub const DEFAULT_RANGE_SIZE: u64 = 1 << 30;
+
+// how many streams are decompressed to estimate the number of entities per compressed byte
+const SAMPLE_STREAMS: usize = 8;
+
+// `BZh`, the block size from 1 to 9, then the magic of either a block or the end of an empty stream
+const HEADER_LENGTH: usize = 10;
+const BLOCK_MAGIC: [u8; 6] = [0x31, 0x41, 0x59, 0x26, 0x53, 0x59];
+const END_MAGIC: [u8; 6] = [0x17, 0x72, 0x45, 0x38, 0x50, 0x90];
+
+/// A range of whole bzip2 streams of a dump, for one worker to process
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlannedRange {
+    pub index: usize,
+    /// Compressed offset of the range's first stream
+    pub start: u64,
+    /// Compressed offset of the stream after the range's last one, or the size of the dump
+    pub end: u64,
+    pub streams: usize,
+    pub estimated_entities: u64,
+}
+
+impl PlannedRange {
+    pub fn stream_range(&self) -> StreamRange {
+        StreamRange { start: self.start, end: self.end }
+    }
+}
+
+/// The ranges a dump is split into, see the module documentation
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Manifest {
+    pub dump: PathBuf,
+    /// Compressed size of the dump, to tell whether a manifest is for the dump at hand
+    pub size: u64,
+    pub streams: usize,
+    pub estimated_entities: u64,
+    pub ranges: Vec<PlannedRange>,
+}
+
+impl Manifest {
+    pub fn load(path: &Path) -> Result<Self> {
+        let file = File::open(path).map_err(|source| ProcessError::Manifest { path: path.to_path_buf(), source })?;
+        serde_json::from_reader(BufReader::new(file))
+            .map_err(|error| ProcessError::InvalidManifest { path: path.to_path_buf(), message: error.to_string() })
+    }
+
+    pub fn write(&self, mut output: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut output, self)?;
+        output.write_all(b"\n")?;
+        output.flush()
+    }
+
+    pub fn range(&self, index: usize) -> Option<&PlannedRange> {
+        self.ranges.iter().find(|range| range.index == index)
+    }
+
+    /// Claims the first range no other worker has claimed yet, for the manifest at `path`. Returns `None` once
+    /// every range has been claimed
+    pub fn claim(&self, path: &Path) -> Result<Option<&PlannedRange>> {
+        let directory = claims_directory(path);
+        let claim_error = |source| ProcessError::Manifest { path: directory.clone(), source };
+        fs::create_dir_all(&directory).map_err(claim_error)?;
+        for range in &self.ranges {
+            match OpenOptions::new().write(true).create_new(true).open(directory.join(range.index.to_string())) {
+                Ok(mut claim) => {
+                    writeln!(claim, "{}", std::process::id()).map_err(claim_error)?;
+                    debug!("Claimed range {} of {:?}", range.index, path);
+                    return Ok(Some(range));
+                }
+                Err(error) if error.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(error) => return Err(claim_error(error)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// Where the claims of the ranges of the manifest at `path` are kept
+pub fn claims_directory(path: &Path) -> PathBuf {
+    let mut directory = path.as_os_str().to_os_string();
+    directory.push(".claims");
+    PathBuf::from(directory)
+}
+
+fn is_stream_header(bytes: &[u8]) -> bool {
+    bytes.starts_with(b"BZh")
+        && (b'1'..=b'9').contains(&bytes[3])
+        && (bytes[4..HEADER_LENGTH] == BLOCK_MAGIC || bytes[4..HEADER_LENGTH] == END_MAGIC)
+}
+
+/// Finds the compressed offsets of the bzip2 streams of the dump at `path`, returning them along with its size
+pub fn find_streams(path: &Path, progress: Progress) -> Result<(Vec<u64>, u64)> {
+    let (mut file, size) = decoder::open(path)?;
+    let progress = Reporter::new(progress, Some(size), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+    progress.set_draw_rate(1);
+
+    let mut streams = Vec::new();
+    // the bytes not searched yet, kept from one read to the next so headers cut by a read are found too
+    let mut window = Vec::with_capacity(BUFFER_LENGTH + HEADER_LENGTH);
+    let mut window_offset = 0;
+    loop {
+        let kept = window.len();
+        window.resize(kept + BUFFER_LENGTH, 0);
+        let n = file.read(&mut window[kept..]).map_err(ProcessError::Read)?;
+        window.truncate(kept + n);
+        let searched = window.len().saturating_sub(HEADER_LENGTH - 1);
+        streams.extend((0..searched)
+            .filter(|&i| window[i] == b'B' && is_stream_header(&window[i..i + HEADER_LENGTH]))
+            .map(|i| window_offset + i as u64));
+        if n == 0 {
+            break;
+        }
+        window.drain(..searched);
+        window_offset += searched as u64;
+        progress.set_position(window_offset);
+    }
+    if streams.first() != Some(&0) {
+        return Err(ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, format!("{:?} is not a bzip2 compressed dump", path))));
+    }
+    progress.finish(format!("Found {} bzip2 streams", streams.len()));
+    Ok((streams, size))
+}
+
+/// The compressed offset of the first bzip2 stream of `file` starting at or after `offset`, if there is one
+pub fn next_stream(file: &mut File, offset: u64) -> io::Result<Option<u64>> {
+    file.seek(SeekFrom::Start(offset))?;
+    let mut window = Vec::with_capacity(BUFFER_LENGTH + HEADER_LENGTH);
+    let mut window_offset = offset;
+    loop {
+        let kept = window.len();
+        window.resize(kept + BUFFER_LENGTH, 0);
+        let n = file.read(&mut window[kept..])?;
+        window.truncate(kept + n);
+        let searched = window.len().saturating_sub(HEADER_LENGTH - 1);
+        if let Some(i) = (0..searched).find(|&i| window[i] == b'B' && is_stream_header(&window[i..i + HEADER_LENGTH])) {
+            return Ok(Some(window_offset + i as u64));
+        }
+        if n == 0 {
+            return Ok(None);
+        }
+        window.drain(..searched);
+        window_offset += searched as u64;
+    }
+}
+
+// counts the lines of the stream from `start` to `end`, which are about as many as its entities
+fn count_lines(file: &mut File, start: u64, end: u64) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(start))?;
+    let mut decoder = BzDecoder::new(BufReader::new(file.take(end - start)));
+    let mut chunk = vec![0; BUFFER_LENGTH];
+    let mut lines = 0;
+    loop {
+        let n = decoder.read(&mut chunk)?;
+        if n == 0 {
+            return Ok(lines);
+        }
+        lines += chunk[..n].iter().filter(|&&byte| byte == b'\n').count() as u64;
+    }
+}
+
+/// Plans how to split the dump at `path` into ranges of whole streams of about `range_size` compressed bytes each
+pub fn plan(path: &Path, range_size: u64, progress: Progress) -> Result<Manifest> {
+    let (streams, size) = find_streams(path, progress)?;
+    if streams.len() == 1 {
+        warn!("{:?} is a single bzip2 stream, so it can't be split", path);
+    }
+    let stream_end = |i: usize| streams.get(i + 1).copied().unwrap_or(size);
+
+    // entities per compressed byte, from streams spread evenly over the dump
+    let (mut file, _) = decoder::open(path)?;
+    let step = streams.len().div_ceil(SAMPLE_STREAMS);
+    let (mut lines, mut sampled) = (0, 0);
+    for i in (0..streams.len()).step_by(step) {
+        lines += count_lines(&mut file, streams[i], stream_end(i)).map_err(ProcessError::Read)?;
+        sampled += stream_end(i) - streams[i];
+    }
+    let per_byte = if sampled > 0 { lines as f64 / sampled as f64 } else { 0.0 };
+    debug!("{} lines in {} sampled compressed bytes", lines, sampled);
+
+    let mut ranges: Vec<PlannedRange> = Vec::new();
+    for (i, &start) in streams.iter().enumerate() {
+        match ranges.last_mut() {
+            Some(range) if range.end - range.start < range_size => {
+                range.end = stream_end(i);
+                range.streams += 1;
+            }
+            _ => ranges.push(PlannedRange { index: ranges.len(), start, end: stream_end(i), streams: 1, estimated_entities: 0 }),
+        }
+    }
+    for range in &mut ranges {
+        range.estimated_entities = ((range.end - range.start) as f64 * per_byte).round() as u64;
+    }
+    Ok(Manifest {
+        dump: path.to_path_buf(),
+        size,
+        streams: streams.len(),
+        estimated_entities: ranges.iter().map(|range| range.estimated_entities).sum(),
+        ranges,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use bzip2::write::BzEncoder;
+    use bzip2::Compression;
+    use crate::process::{process, ProcessOptions};
+
+    // the test dump recompressed as a stream for every `chunk_size` decompressed bytes, as Wikimedia's dumps are
+    pub(crate) fn multistream_dump(path: &Path, chunk_size: usize) {
+        let mut dump = Vec::new();
+        decoder::decoder(File::open("./tests/test-data.json.bz2").unwrap()).read_to_end(&mut dump).unwrap();
+        let mut output = File::create(path).unwrap();
+        for chunk in dump.chunks(chunk_size) {
+            let mut encoder = BzEncoder::new(Vec::new(), Compression::best());
+            encoder.write_all(chunk).unwrap();
+            output.write_all(&encoder.finish().unwrap()).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let directory = tempfile::tempdir().unwrap();
+        let dump = directory.path().join("dump.json.bz2");
+        multistream_dump(&dump, 200);
+
+        let manifest = plan(&dump, 500, Progress::Hidden).unwrap();
+        assert_eq!(manifest.streams, 37);
+        assert!(manifest.ranges.len() > 2);
+        assert_eq!(manifest.ranges[0].start, 0);
+        assert_eq!(manifest.ranges.last().unwrap().end, manifest.size);
+        assert!(manifest.ranges.windows(2).all(|pair| pair[0].end == pair[1].start));
+        assert_eq!(manifest.ranges.iter().map(|range| range.streams).sum::<usize>(), manifest.streams);
+        // a rough estimate, from a few of the streams
+        assert!((4..=20).contains(&manifest.estimated_entities), "{} entities", manifest.estimated_entities);
+
+        // every entity is in exactly one range, also with ranges of single streams, some without any entities of their own
+        let process_range = |range: StreamRange| {
+            let mut output = Vec::new();
+            let options = ProcessOptions { range: Some(range), ..ProcessOptions::default() };
+            process(Some(dump.clone()), &mut output, ".id", &options).unwrap();
+            String::from_utf8(output).unwrap().lines().map(String::from).collect::<Vec<_>>()
+        };
+        let expected = ["Q1", "Q2", "Q3", "Q4", "Q5", "Q6", "P1", "Q60"].map(|id| format!("\"{}\"", id));
+        let per_range: Vec<Vec<String>> = manifest.ranges.iter().map(|range| process_range(range.stream_range())).collect();
+        assert!(per_range.iter().filter(|ids| !ids.is_empty()).count() > 1);
+        assert_eq!(per_range.concat(), expected);
+        let (streams, size) = find_streams(&dump, Progress::Hidden).unwrap();
+        let ends = streams.iter().skip(1).copied().chain([size]);
+        let per_stream: Vec<Vec<String>> = streams.iter().zip(ends).map(|(&start, end)| process_range(StreamRange { start, end })).collect();
+        assert!(per_stream.iter().any(Vec::is_empty));
+        assert!(per_stream.iter().filter(|ids| !ids.is_empty()).count() > 4);
+        assert_eq!(per_stream.concat(), expected);
+
+        assert!(plan(Path::new("./tests/invalid-json.json.bz2"), DEFAULT_RANGE_SIZE, Progress::Hidden).unwrap().ranges.len() == 1);
+        assert!(plan(Path::new("./Cargo.toml"), DEFAULT_RANGE_SIZE, Progress::Hidden).is_err());
+    }
+
+    #[test]
+    fn test_claim() {
+        let directory = tempfile::tempdir().unwrap();
+        let dump = directory.path().join("dump.json.bz2");
+        multistream_dump(&dump, 500);
+        let path = directory.path().join("manifest.json");
+        plan(&dump, 3000, Progress::Hidden).unwrap().write(File::create(&path).unwrap()).unwrap();
+
+        let manifest = Manifest::load(&path).unwrap();
+        let claimed: Vec<usize> = std::iter::from_fn(|| manifest.claim(&path).unwrap().map(|range| range.index)).collect();
+        assert_eq!(claimed, (0..manifest.ranges.len()).collect::<Vec<_>>());
+        assert!(claims_directory(&path).join("0").exists());
+        assert!(matches!(Manifest::load(&dump), Err(ProcessError::InvalidManifest { .. })));
+    }
+}
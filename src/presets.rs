@@ -0,0 +1,175 @@
+/*!
+ * Built-in presets, turning whole entities into simpler records natively,
+ * without jq, for the most common needs of those new to Wikidata's JSON:
+ *
+ * - `labels-only`: `{"id":"Q42","labels":{"en":"Douglas Adams"}}`
+ * - `minimal`: the id, type and terms, with labels and descriptions as plain
+ *   strings and aliases as lists of them, and sitelinks as page titles by site
+ * - `truthy-simple`: `minimal` with the truthy statements, those of the best
+ *   rank of each property as the query service's `wdt:` has them, as plain
+ *   values by property, e.g. `"claims":{"P31":["Q5"]}`
+ * - `graph-edges`: the id and the items each non-deprecated statement points
+ *   to by property, e.g. `{"id":"Q42","edges":{"P31":["Q5"]}}`, for graph tools
+ *
+ * Plain values are simplified as wikibase-sdk does: entity ids, strings and
+ * times as strings (e.g. `+1952-03-11T00:00:00Z`), quantities as numbers,
+ * monolingual texts as their text and coordinates as `[latitude, longitude]`.
+ * Statements with an unknown or no value are left out.
+ */
+
+use std::str::FromStr;
+use log::debug;
+use serde_json::{json, Map, Value};
+use crate::model::{Claim, DataValue, Entity, Rank};
+
+/// A preset, see the module documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Preset {
+    LabelsOnly,
+    Minimal,
+    TruthySimple,
+    GraphEdges,
+}
+
+impl FromStr for Preset {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "labels-only" => Ok(Preset::LabelsOnly),
+            "minimal" => Ok(Preset::Minimal),
+            "truthy-simple" => Ok(Preset::TruthySimple),
+            "graph-edges" => Ok(Preset::GraphEdges),
+            _ => Err(format!("Invalid preset '{}', expected labels-only, minimal, truthy-simple or graph-edges", value)),
+        }
+    }
+}
+
+/// The value of `datavalue` simplified, see the module documentation, or `None` if it can't be
+pub fn simple_value(datavalue: &DataValue) -> Option<Value> {
+    match datavalue {
+        DataValue::String(value) => Some(Value::String(value.clone())),
+        DataValue::EntityId(value) => value.id().map(Value::String),
+        DataValue::Time(value) => Some(Value::String(value.time.clone())),
+        DataValue::Quantity(value) => value.amount.trim_start_matches('+').parse::<f64>().ok().map(|amount| json!(amount)),
+        DataValue::MonolingualText(value) => Some(Value::String(value.text.clone())),
+        DataValue::GlobeCoordinate(value) => Some(json!([value.latitude, value.longitude])),
+        DataValue::Unknown { .. } => None,
+    }
+}
+
+// the statements of a property with the best rank among them: preferred ones if any, otherwise normal ones
+fn truthy(claims: &[Claim]) -> impl Iterator<Item = &Claim> {
+    let best = match claims.iter().any(|claim| claim.rank == Rank::Preferred) {
+        true => Rank::Preferred,
+        false => Rank::Normal,
+    };
+    claims.iter().filter(move |claim| claim.rank == best)
+}
+
+fn not_deprecated(claims: &[Claim]) -> impl Iterator<Item = &Claim> {
+    claims.iter().filter(|claim| claim.rank != Rank::Deprecated)
+}
+
+fn terms(entity: &Entity, record: &mut Map<String, Value>) {
+    let labels = entity.labels.iter().map(|(language, term)| (language.clone(), json!(term.value))).collect();
+    let descriptions = entity.descriptions.iter().map(|(language, term)| (language.clone(), json!(term.value))).collect();
+    let aliases = entity.aliases.iter()
+        .map(|(language, terms)| (language.clone(), terms.iter().map(|term| json!(term.value)).collect()))
+        .collect();
+    let sitelinks = entity.sitelinks.iter().map(|(site, sitelink)| (site.clone(), json!(sitelink.title))).collect();
+    record.insert(String::from("type"), json!(entity.entity_type));
+    record.insert(String::from("labels"), Value::Object(labels));
+    record.insert(String::from("descriptions"), Value::Object(descriptions));
+    record.insert(String::from("aliases"), Value::Object(aliases));
+    record.insert(String::from("sitelinks"), Value::Object(sitelinks));
+}
+
+// the values of some statements of each property, leaving out properties without any
+fn values_by_property<'a, I: Iterator<Item = &'a Claim>>(entity: &'a Entity, statements: impl Fn(&'a [Claim]) -> I, keep: impl Fn(&DataValue) -> bool) -> Map<String, Value> {
+    let mut values = Map::new();
+    for (property, claims) in &entity.claims {
+        let simple = statements(claims)
+            .filter_map(|claim| claim.mainsnak.datavalue.as_ref())
+            .filter(|datavalue| keep(datavalue))
+            .filter_map(simple_value)
+            .collect::<Vec<_>>();
+        if !simple.is_empty() {
+            values.insert(property.clone(), Value::Array(simple));
+        }
+    }
+    values
+}
+
+/// The record `preset` makes of `entity`
+pub fn apply(entity: &Entity, preset: Preset) -> Value {
+    let mut record = Map::new();
+    record.insert(String::from("id"), json!(entity.id));
+    match preset {
+        Preset::LabelsOnly => {
+            let labels = entity.labels.iter().map(|(language, term)| (language.clone(), json!(term.value))).collect();
+            record.insert(String::from("labels"), Value::Object(labels));
+        }
+        Preset::Minimal => terms(entity, &mut record),
+        Preset::TruthySimple => {
+            terms(entity, &mut record);
+            let claims = values_by_property(entity, truthy, |_| true);
+            record.insert(String::from("claims"), Value::Object(claims));
+        }
+        Preset::GraphEdges => {
+            let edges = values_by_property(entity, not_deprecated, |datavalue| matches!(datavalue, DataValue::EntityId(value) if value.entity_type == "item"));
+            record.insert(String::from("edges"), Value::Object(edges));
+        }
+    }
+    Value::Object(record)
+}
+
+/// Replaces an output which is a whole entity with the record `preset` makes of it. Other outputs are dropped.
+pub fn preset_output(output: String, preset: Preset) -> Option<String> {
+    match Entity::parse(&output) {
+        Ok(entity) => Some(apply(&entity, preset).to_string()),
+        Err(error) => {
+            debug!("Not an entity, so no preset record ({}): {}", error, output);
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item",
+        "labels":{"en":{"language":"en","value":"Douglas Adams"}},
+        "descriptions":{"en":{"language":"en","value":"English writer"}},
+        "aliases":{"en":[{"language":"en","value":"DNA"}]},
+        "sitelinks":{"enwiki":{"site":"enwiki","title":"Douglas Adams","badges":[]}},
+        "claims":{
+            "P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":5}}},"type":"statement","rank":"normal"}],
+            "P569":[{"mainsnak":{"snaktype":"value","property":"P569","datavalue":{"type":"time","value":{"time":"+1952-03-11T00:00:00Z","timezone":0,"before":0,"after":0,"precision":11,"calendarmodel":"http://www.wikidata.org/entity/Q1985727"}}},"type":"statement","rank":"preferred"},
+                    {"mainsnak":{"snaktype":"value","property":"P569","datavalue":{"type":"time","value":{"time":"+1952-02-27T00:00:00Z","timezone":0,"before":0,"after":0,"precision":11,"calendarmodel":"http://www.wikidata.org/entity/Q1985786"}}},"type":"statement","rank":"normal"}],
+            "P2048":[{"mainsnak":{"snaktype":"value","property":"P2048","datavalue":{"type":"quantity","value":{"amount":"+1.96","unit":"http://www.wikidata.org/entity/Q11573"}}},"type":"statement","rank":"normal"}],
+            "P625":[{"mainsnak":{"snaktype":"value","property":"P625","datavalue":{"type":"globecoordinate","value":{"latitude":51.5,"longitude":-0.1,"globe":"http://www.wikidata.org/entity/Q2"}}},"type":"statement","rank":"normal"}],
+            "P106":[{"mainsnak":{"snaktype":"value","property":"P106","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q36180"}}},"type":"statement","rank":"deprecated"}],
+            "P570":[{"mainsnak":{"snaktype":"somevalue","property":"P570"},"type":"statement","rank":"normal"}]
+        }}"#;
+
+    fn preset(preset: &str) -> String {
+        preset_output(ENTITY.to_string(), preset.parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_presets() {
+        assert_eq!(preset("labels-only"), r#"{"id":"Q42","labels":{"en":"Douglas Adams"}}"#);
+        assert_eq!(
+            preset("minimal"),
+            r#"{"aliases":{"en":["DNA"]},"descriptions":{"en":"English writer"},"id":"Q42","labels":{"en":"Douglas Adams"},"sitelinks":{"enwiki":"Douglas Adams"},"type":"item"}"#
+        );
+        let truthy: Value = serde_json::from_str(&preset("truthy-simple")).unwrap();
+        assert_eq!(truthy["claims"], json!({"P31": ["Q5"], "P569": ["+1952-03-11T00:00:00Z"], "P2048": [1.96], "P625": [[51.5, -0.1]]}));
+        assert_eq!(preset("graph-edges"), r#"{"edges":{"P31":["Q5"]},"id":"Q42"}"#);
+
+        assert_eq!(preset_output(String::from("\"Q42\""), Preset::Minimal), None);
+        assert!("simple".parse::<Preset>().is_err());
+    }
+}
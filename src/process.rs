@@ -0,0 +1,1041 @@
+/*!
+ * The processing pipeline: the dump is decompressed and split into batches of
+ * entities on one thread, batches are filtered on a pool of worker threads, and
+ * the results are written out in dump order on the calling thread.
+ *
+ * THINGS TO NOTE: in Rust, strings are UTF8 encoded (meaning a given character
+ * can be anywhere from 1 to 4 bytes).
+ */
+
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::ops::ControlFlow;
+use std::io::{self, BufReader, Read, Write};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Condvar, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender};
+use std::thread;
+use std::time::{Duration, Instant};
+use indicatif::{HumanDuration, HumanBytes};
+use log::{debug, info};
+use serde::{Serialize, Serializer};
+use simdutf8::compat::from_utf8;
+use crate::cancel::CancellationToken;
+use crate::checkpoint::{Checkpoint, Checkpointer};
+use crate::decoder::{self, RangeDecoder, StreamDecoder, StreamRange, StreamRead, StreamStart, BUFFER_LENGTH};
+use crate::error::{ProcessError, Result};
+use crate::filter::{self, EntityFilter, FilterFactory, Output};
+use crate::metrics::Metrics;
+use crate::model::RawEntity;
+use crate::pipeline::Transform;
+use crate::progress::{Progress, Reporter};
+use crate::reader::EntityReader;
+use crate::report::{EntityError, ErrorReport};
+use crate::sink::{Sink, WriteSink};
+use crate::source::{CountingReader, DumpReader, FileSource, Source};
+use crate::splitter::{self, DUMP_END, DUMP_START};
+
+// bounds on how much of the dump is handed to a filtering thread at once
+const MIN_BATCH_SIZE: usize = 16 * 1024;
+const MAX_BATCH_SIZE: usize = 1024 * 1024;
+
+/// Default amount of filtered output accumulated before hitting the underlying writer
+pub const DEFAULT_WRITE_BUFFER_SIZE: usize = 8 * 1024 * 1024;
+
+// how many entities have to be read before a percentage of them failing is taken to mean the filter is broken
+const MIN_ERROR_SAMPLE: usize = 1000;
+
+/// How many entities may fail to be filtered (with `continue_on_error`) before a run gives up
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorBudget {
+    Count(usize),
+    /// A percentage of the entities read, only checked once at least 1000 have been, so the first few failing
+    /// don't end a run
+    Percentage(f64),
+}
+
+impl ErrorBudget {
+    /// Whether `failed` out of `read` entities is over the budget, with `finished` once all of them have been read
+    pub fn is_exceeded(&self, failed: usize, read: usize, finished: bool) -> bool {
+        match *self {
+            ErrorBudget::Count(count) => failed > count,
+            ErrorBudget::Percentage(_) if !finished && read < MIN_ERROR_SAMPLE => false,
+            ErrorBudget::Percentage(percentage) => read > 0 && failed as f64 * 100.0 > percentage * read as f64,
+        }
+    }
+}
+
+impl FromStr for ErrorBudget {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid error budget '{}', expected a number of entities like 100 or a percentage like 5%", value);
+        match value.strip_suffix('%') {
+            Some(percentage) => match percentage.parse::<f64>() {
+                Ok(percentage) if (0.0..=100.0).contains(&percentage) => Ok(ErrorBudget::Percentage(percentage)),
+                _ => Err(invalid()),
+            },
+            None => value.parse().map(ErrorBudget::Count).map_err(|_| invalid()),
+        }
+    }
+}
+
+impl fmt::Display for ErrorBudget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorBudget::Count(count) => write!(f, "{}", count),
+            ErrorBudget::Percentage(percentage) => write!(f, "{}%", percentage),
+        }
+    }
+}
+
+/// Options controlling how entities are filtered and written by `process`
+#[derive(Debug, Clone)]
+pub struct ProcessOptions {
+    pub continue_on_error: bool,
+    pub write_buffer_size: usize,
+    pub pass_through: bool,
+    pub threads: usize,
+    pub pin_cores: bool,
+    pub max_memory: Option<usize>,
+    /// Checked between entities, stops the run early once cancelled
+    pub cancel: CancellationToken,
+    pub progress: Progress,
+    /// Where to save checkpoints every `checkpoint::CHECKPOINT_INTERVAL`, and once the run ends
+    pub checkpoint: Option<PathBuf>,
+    /// A checkpoint of an interrupted run to carry on from, whose output must already have been truncated to `bytes_out`
+    pub resume: Option<Checkpoint>,
+    /// Kept up to date as the run goes, e.g. to be served with `metrics::serve`
+    pub metrics: Option<Arc<Metrics>>,
+    /// How many entities may be skipped with `continue_on_error` before the run fails, rather than any number
+    pub max_errors: Option<ErrorBudget>,
+    /// Where to report the entities skipped with `continue_on_error`, see `report`, added to when resuming
+    pub error_report: Option<PathBuf>,
+    /// How often to print a line of stats to stderr, see `progress`
+    pub stats_interval: Option<Duration>,
+    /// Only the entities of this range of the dump's bzip2 streams, see `decoder::RangeDecoder`. Decompressed
+    /// offsets, e.g. in error reports, are then from the start of the range
+    pub range: Option<StreamRange>,
+}
+
+impl Default for ProcessOptions {
+    fn default() -> Self {
+        ProcessOptions {
+            continue_on_error: false,
+            write_buffer_size: DEFAULT_WRITE_BUFFER_SIZE,
+            pass_through: false,
+            threads: default_threads(),
+            pin_cores: false,
+            max_memory: None,
+            cancel: CancellationToken::default(),
+            progress: Progress::default(),
+            checkpoint: None,
+            resume: None,
+            metrics: None,
+            max_errors: None,
+            error_report: None,
+            stats_interval: None,
+            range: None,
+        }
+    }
+}
+
+/// The number of filtering threads used when none is given, one per available CPU
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+// keeps track of the bytes held in batches that have been read but not yet written
+struct MemoryBudget {
+    limit: Option<usize>,
+    metrics: Option<Arc<Metrics>>,
+    state: Mutex<BudgetState>,
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct BudgetState {
+    used: usize,
+    closed: bool,
+}
+
+impl MemoryBudget {
+    fn new(limit: Option<usize>, metrics: Option<Arc<Metrics>>) -> Self {
+        MemoryBudget { limit, metrics, state: Mutex::new(BudgetState::default()), released: Condvar::new() }
+    }
+
+    fn report(&self, used: usize) {
+        if let Some(metrics) = &self.metrics {
+            metrics.memory_in_flight.store(used as u64, Ordering::Relaxed);
+        }
+    }
+
+    // waits until `bytes` fit within the limit, returning false if the budget was closed while waiting.
+    // a batch is always let through when nothing else is in flight, so one huge entity can't stall the run
+    fn acquire(&self, bytes: usize) -> bool {
+        let mut state = self.state.lock().expect("Memory budget poisoned");
+        if let Some(limit) = self.limit {
+            while !state.closed && state.used > 0 && state.used + bytes > limit {
+                state = self.released.wait(state).expect("Memory budget poisoned");
+            }
+        }
+        state.used += bytes;
+        self.report(state.used);
+        !state.closed
+    }
+
+    // accounts for bytes that have to be held regardless of the limit
+    fn charge(&self, bytes: usize) {
+        let mut state = self.state.lock().expect("Memory budget poisoned");
+        state.used += bytes;
+        self.report(state.used);
+    }
+
+    fn release(&self, bytes: usize) {
+        let mut state = self.state.lock().expect("Memory budget poisoned");
+        state.used = state.used.saturating_sub(bytes);
+        self.report(state.used);
+        self.released.notify_all();
+    }
+
+    // wakes up and stops anyone waiting for memory, used when the run ends early
+    fn close(&self) {
+        self.state.lock().expect("Memory budget poisoned").closed = true;
+        self.released.notify_all();
+    }
+
+    // fraction of the limit currently in use, always 0 without a limit
+    fn pressure(&self) -> f64 {
+        match self.limit {
+            Some(limit) => self.state.lock().expect("Memory budget poisoned").used as f64 / limit.max(1) as f64,
+            None => 0.0,
+        }
+    }
+}
+
+// where decoding can be restarted to pick up right after a batch
+#[derive(Clone, Copy)]
+struct ResumePoint {
+    stream: StreamStart,
+    offset: u64,
+}
+
+// a run of complete entities, still joined by ",\n", in the order they were read
+struct Batch {
+    seq: usize,
+    entities: String,
+    // decompressed offset of the first entity
+    offset: u64,
+    resume: ResumePoint,
+}
+
+struct FilteredBatch {
+    seq: usize,
+    output: String,
+    // where the output for each entity ends within `output`, including its newline
+    ends: Vec<usize>,
+    num_entities: usize,
+    num_entities_output: usize,
+    num_entities_failed: usize,
+    // the entities which couldn't be filtered, by their position within the batch, which only the writer can turn
+    // into a position in the dump
+    errors: Vec<(usize, EntityError)>,
+    resume: ResumePoint,
+    // the run was cancelled part way through this batch, so nothing after it should be written
+    cancelled: bool,
+}
+
+/// Counts describing a finished run
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ProcessStats {
+    pub entities_read: usize,
+    pub entities_written: usize,
+    /// Entities skipped because they couldn't be filtered, only possible with `continue_on_error`
+    pub entities_failed: usize,
+    /// Decompressed bytes read from the dump
+    pub bytes_in: u64,
+    pub bytes_out: u64,
+    /// Serialized as (fractional) seconds
+    #[serde(serialize_with = "serialize_seconds")]
+    pub duration: Duration,
+    /// Whether the run was stopped early through `ProcessOptions::cancel`
+    pub cancelled: bool,
+    /// Where the time went, to tell whether e.g. bzip2 or jq held the run back
+    pub stages: StageTimes,
+}
+
+fn serialize_seconds<S: Serializer>(duration: &Duration, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+    serializer.serialize_f64(duration.as_secs_f64())
+}
+
+/// Time spent in each stage of a run, serialized as (fractional) seconds. Filtering is summed over the filtering
+/// threads, so it can be more than the run took; time spent waiting on another stage isn't counted.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct StageTimes {
+    /// Reading and decompressing the dump
+    #[serde(serialize_with = "serialize_seconds")]
+    pub decompression: Duration,
+    /// Finding the entities in the decompressed dump
+    #[serde(serialize_with = "serialize_seconds")]
+    pub splitting: Duration,
+    /// Applying the filters and transforms
+    #[serde(serialize_with = "serialize_seconds")]
+    pub filtering: Duration,
+    /// Handing the outputs to the sink, which may compress and write them
+    #[serde(serialize_with = "serialize_seconds")]
+    pub writing: Duration,
+}
+
+impl fmt::Display for StageTimes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "decompression {:.1}s, splitting {:.1}s, filtering {:.1}s, writing {:.1}s",
+            self.decompression.as_secs_f64(), self.splitting.as_secs_f64(), self.filtering.as_secs_f64(), self.writing.as_secs_f64())
+    }
+}
+
+// time spent in a stage so far, added to by each of its threads
+#[derive(Default)]
+struct StageClock(AtomicU64);
+
+impl StageClock {
+    fn add(&self, since: Instant) {
+        self.0.fetch_add(since.elapsed().as_nanos() as u64, Ordering::Relaxed);
+    }
+
+    fn elapsed(&self) -> Duration {
+        Duration::from_nanos(self.0.load(Ordering::Relaxed))
+    }
+}
+
+#[derive(Default)]
+struct StageClocks {
+    decompression: StageClock,
+    splitting: StageClock,
+    filtering: StageClock,
+    writing: StageClock,
+}
+
+impl StageClocks {
+    fn times(&self) -> StageTimes {
+        StageTimes {
+            decompression: self.decompression.elapsed(),
+            splitting: self.splitting.elapsed(),
+            filtering: self.filtering.elapsed(),
+            writing: self.writing.elapsed(),
+        }
+    }
+}
+
+/// Decompresses the dump at `input` and writes the result of applying `jq_filter` to each entity to `output` as ndjson.
+///
+/// The dump is read and split on one thread, entities are filtered in batches on `options.threads`
+/// worker threads, and the results are written in their original order on the calling thread.
+pub fn process(input: Option<PathBuf>, output: &mut impl Write, jq_filter: &str, options: &ProcessOptions) -> Result<ProcessStats> {
+    let filters = filter::jq_filter_factory(jq_filter, options.continue_on_error, options.pass_through);
+    let mut source = FileSource::new(input.ok_or(ProcessError::MissingInput)?);
+    run(&mut source, &mut WriteSink::new(output, options.write_buffer_size), &filters, &[], options)
+}
+
+/// Calls `visit` with each entity of the dump at `input`, in order, on the calling thread, stopping
+/// early if it breaks. Returns the value it broke with, if any.
+///
+/// Nothing is filtered or written, which makes this the cheapest way to run arbitrary code over a dump.
+pub fn process_with<B>(input: Option<PathBuf>, mut visit: impl FnMut(&RawEntity) -> ControlFlow<B>) -> Result<Option<B>> {
+    let (dump, _) = FileSource::new(input.ok_or(ProcessError::MissingInput)?).open()?;
+    for entity in EntityReader::new(decoder::decoder(dump)).map_err(ProcessError::Read)? {
+        let entity = entity.map_err(ProcessError::Read)?;
+        if let ControlFlow::Break(value) = visit(&RawEntity::new(&entity)) {
+            return Ok(Some(value));
+        }
+    }
+    Ok(None)
+}
+
+/// Same as `process`, reading from `source`, with a filter from `filters` on each thread in place of jq,
+/// additionally passing the output for each entity through `transforms`, in order, and handing it to `sink`
+pub(crate) fn run(source: &mut dyn Source, sink: &mut dyn Sink, filters: &FilterFactory, transforms: &[Transform], options: &ProcessOptions) -> Result<ProcessStats> {
+    let (dump, size) = match (&options.resume, options.range) {
+        (Some(checkpoint), _) => {
+            info!("Resuming from {:?}", checkpoint);
+            source.open_at(checkpoint.stream.compressed_offset)?
+        }
+        (None, Some(range)) => {
+            info!("Processing compressed bytes {} to {}", range.start, range.end);
+            source.open_at(range.start)?
+        }
+        (None, None) => source.open()?,
+    };
+
+    // each worker creates its own filter, but do it once here so a bad filter fails before any threads start
+    filters()?;
+
+    // progress is counted in compressed bytes, so that with the size of the dump it gives an ETA
+    let template = match size {
+        Some(_) => "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+        None => "{msg}\n{spinner:.green} [{elapsed_precise}] ({bytes_per_sec})",
+    };
+    let mut progress = Reporter::new(options.progress, size, template);
+    progress.set_draw_rate(1);
+    if let Some(interval) = options.stats_interval {
+        progress.set_stats_interval(interval);
+    }
+
+    let threads = options.threads.max(1);
+    let core_ids = if options.pin_cores {
+        core_affinity::get_core_ids().unwrap_or_default()
+    } else {
+        Vec::new()
+    };
+    debug!("Filtering with {} threads, pinned to cores: {:?}", threads, core_ids);
+
+    let budget = MemoryBudget::new(options.max_memory, options.metrics.clone());
+    // leave room for a couple of batches per thread within the limit
+    let max_batch_size = options.max_memory
+        .map(|limit| (limit / (threads * 4)).clamp(MIN_BATCH_SIZE, MAX_BATCH_SIZE))
+        .unwrap_or(MAX_BATCH_SIZE);
+    debug!("Memory limit: {:?}, max batch size: {}", options.max_memory, max_batch_size);
+
+    let start = Instant::now();
+    let mut checkpointer = options.checkpoint.clone().map(|path| {
+        // until a batch is written, a run can only be resumed from wherever it started
+        let started = Checkpoint { offset: DUMP_START.len() as u64, ..Checkpoint::default() };
+        Checkpointer::new(path, options.resume.clone().unwrap_or(started))
+    });
+
+    let clocks = StageClocks::default();
+    let mut stats = thread::scope(|scope| -> Result<ProcessStats> {
+        let (batch_sender, batch_receiver) = mpsc::sync_channel::<Batch>(threads * 2);
+        let (result_sender, result_receiver) = mpsc::channel::<Result<FilteredBatch>>();
+
+        let progress = &progress;
+        let budget = &budget;
+        let clocks = &clocks;
+        let reader = scope.spawn(move || read_batches(dump, batch_sender, progress, budget, clocks, max_batch_size, options));
+
+        let batch_receiver = Arc::new(Mutex::new(batch_receiver));
+        for worker in 0..threads {
+            let batch_receiver = Arc::clone(&batch_receiver);
+            let result_sender = result_sender.clone();
+            let core_id = (!core_ids.is_empty()).then(|| core_ids[worker % core_ids.len()]);
+            scope.spawn(move || filter_batches(filters, transforms, options, batch_receiver, result_sender, budget, clocks, core_id));
+        }
+        // only the workers hold on to these now, so the channels close once they're done
+        drop(batch_receiver);
+        drop(result_sender);
+
+        let written = write_batches(result_receiver, sink, progress, budget, clocks, checkpointer.as_mut(), options);
+        // make sure the reader isn't left waiting for memory that will never be released
+        budget.close();
+        let mut stats = written?;
+
+        let read = reader.join().expect("Reader thread panicked");
+        if let Some(checkpointer) = &mut checkpointer {
+            // a run which stopped early can still be resumed from the last batch written
+            if read.is_ok() && !options.cancel.is_cancelled() {
+                checkpointer.complete(sink)?;
+            } else {
+                checkpointer.save(sink)?;
+            }
+        }
+        stats.bytes_in = read.map_err(|error| match error {
+            ProcessError::Truncated { compressed_offset, last_id, reason, .. } => {
+                ProcessError::Truncated { compressed_offset, last_id, entities: stats.entities_read, reason }
+            }
+            error => error,
+        })?;
+        Ok(stats)
+    })?;
+
+    let finalizing = Instant::now();
+    sink.finalize()?;
+    clocks.writing.add(finalizing);
+    stats.duration = start.elapsed();
+    stats.stages = clocks.times();
+    stats.cancelled = options.cancel.is_cancelled();
+    if stats.cancelled {
+        info!("Cancelled, stopping early");
+    }
+    progress.finish(format!("Finished! Processed {} entities ({}) and outputted {} in {}\nTime spent in {} (filtering over {} threads)",
+        stats.entities_read, HumanBytes(stats.bytes_in), stats.entities_written, HumanDuration(stats.duration), stats.stages, threads));
+    debug!("{:?}", stats);
+    let stages = &stats.stages;
+    info!(target: module_path!(),
+        duration = stats.duration.as_secs_f64(), entities_read = stats.entities_read, entities_written = stats.entities_written, entities_failed = stats.entities_failed,
+        decompression = stages.decompression.as_secs_f64(), splitting = stages.splitting.as_secs_f64(), filtering = stages.filtering.as_secs_f64(), writing = stages.writing.as_secs_f64();
+        "Processed {} entities in {}, time spent in {}", stats.entities_read, HumanDuration(stats.duration), stages);
+    Ok(stats)
+}
+
+// writes filtered batches in the order they were read, returning the entity and output counts
+fn write_batches(results: Receiver<Result<FilteredBatch>>, sink: &mut dyn Sink, progress: &Reporter, budget: &MemoryBudget, clocks: &StageClocks, mut checkpointer: Option<&mut Checkpointer>, options: &ProcessOptions) -> Result<ProcessStats> {
+    // batches can finish out of order, so hold on to them until it's their turn
+    let mut pending = BTreeMap::new();
+    let mut next_seq = 0;
+    let mut stats = ProcessStats::default();
+    if let Some(checkpoint) = &options.resume {
+        stats.entities_read = checkpoint.entities_read;
+        stats.entities_written = checkpoint.entities_written;
+        stats.entities_failed = checkpoint.entities_failed;
+        stats.bytes_out = checkpoint.bytes_out;
+    }
+    let mut report = match &options.error_report {
+        Some(path) => Some(ErrorReport::create(path, options.resume.is_some())?),
+        None => None,
+    };
+    for filtered in results {
+        // stop at the first error, which closes the channels and winds down the other threads
+        let filtered = filtered?;
+        pending.insert(filtered.seq, filtered);
+        while let Some(filtered) = pending.remove(&next_seq) {
+            let writing = Instant::now();
+            let mut start = 0;
+            for &end in &filtered.ends {
+                sink.write_entity(&filtered.output[start..end - 1])?;
+                start = end;
+            }
+            clocks.writing.add(writing);
+            budget.release(filtered.output.len());
+            if let Some(report) = &mut report {
+                for (position, mut error) in filtered.errors {
+                    error.index = stats.entities_read + position;
+                    report.write(&error)?;
+                }
+            }
+            stats.entities_read += filtered.num_entities;
+            stats.entities_written += filtered.num_entities_output;
+            stats.entities_failed += filtered.num_entities_failed;
+            stats.bytes_out += filtered.output.len() as u64;
+            next_seq += 1;
+            progress.set_output(stats.bytes_out, stats.entities_failed);
+            progress.set_entities(stats.entities_read, stats.entities_written);
+            if let Some(metrics) = &options.metrics {
+                Metrics::sub(&metrics.batches_waiting_write, 1);
+                metrics.entities_read.store(stats.entities_read as u64, Ordering::Relaxed);
+                metrics.entities_written.store(stats.entities_written as u64, Ordering::Relaxed);
+                metrics.entities_failed.store(stats.entities_failed as u64, Ordering::Relaxed);
+                metrics.bytes_written.store(stats.bytes_out, Ordering::Relaxed);
+            }
+            if filtered.cancelled {
+                // later batches would leave a gap in the output
+                return Ok(stats);
+            }
+            if let Some(budget) = options.max_errors.filter(|budget| budget.is_exceeded(stats.entities_failed, stats.entities_read, false)) {
+                return Err(ProcessError::TooManyErrors { failed: stats.entities_failed, read: stats.entities_read, budget: budget.to_string() });
+            }
+            // a partly written batch can't be resumed from, so only whole ones are checkpointed
+            if let Some(checkpointer) = checkpointer.as_deref_mut() {
+                // the report has to be as far along as the checkpoint, or errors would be missing from it on resuming
+                if let Some(report) = &mut report {
+                    report.flush()?;
+                }
+                let checkpoint = Checkpoint {
+                    stream: filtered.resume.stream,
+                    offset: filtered.resume.offset,
+                    entities_read: stats.entities_read,
+                    entities_written: stats.entities_written,
+                    entities_failed: stats.entities_failed,
+                    bytes_out: stats.bytes_out,
+                    complete: false,
+                };
+                // saving a checkpoint flushes the sink, which is part of writing
+                let writing = Instant::now();
+                checkpointer.update(checkpoint, sink)?;
+                clocks.writing.add(writing);
+            }
+        }
+    }
+    if let Some(report) = &mut report {
+        report.flush()?;
+    }
+    if let Some(budget) = options.max_errors.filter(|budget| budget.is_exceeded(stats.entities_failed, stats.entities_read, true)) {
+        return Err(ProcessError::TooManyErrors { failed: stats.entities_failed, read: stats.entities_read, budget: budget.to_string() });
+    }
+    Ok(stats)
+}
+
+// shrinks batches while the memory budget is under pressure and grows them back once it eases off
+fn adapt_batch_size(batch_size: usize, max_batch_size: usize, budget: &MemoryBudget) -> usize {
+    let pressure = budget.pressure();
+    if pressure > 0.5 {
+        (batch_size / 2).max(MIN_BATCH_SIZE)
+    } else if pressure < 0.25 {
+        (batch_size * 2).min(max_batch_size)
+    } else {
+        batch_size
+    }
+}
+
+// decompresses the dump and sends it on in batches of complete entities, returning the number of bytes decompressed
+fn read_batches(dump: DumpReader, batches: SyncSender<Batch>, progress: &Reporter, budget: &MemoryBudget, clocks: &StageClocks, max_batch_size: usize, options: &ProcessOptions) -> Result<u64> {
+    debug!("Initializing buffer to size {}", BUFFER_LENGTH);
+    let start = match (&options.resume, options.range) {
+        (Some(checkpoint), _) => checkpoint.stream,
+        (None, Some(range)) => StreamStart { compressed_offset: range.start, decompressed_offset: 0 },
+        (None, None) => StreamStart::default(),
+    };
+    let dump = CountingReader::new(dump);
+    let consumed = dump.count();
+    let compressed_position = || start.compressed_offset + consumed.load(Ordering::Relaxed);
+    let mut md: Box<dyn StreamRead> = match options.range {
+        Some(range) if options.resume.is_none() => Box::new(RangeDecoder::new(BufReader::new(dump), range)),
+        _ => Box::new(StreamDecoder::new(BufReader::new(dump), start)),
+    };
+    // a bzip2 stream cut short before the first entity
+    let start_error = |error: io::Error| match error.kind() {
+        io::ErrorKind::UnexpectedEof => ProcessError::Truncated { compressed_offset: compressed_position(), last_id: String::from("(none)"), entities: 0, reason: error.to_string() },
+        _ => ProcessError::Read(error),
+    };
+    // the streams batches may have started in, oldest first
+    let mut streams = VecDeque::from([start]);
+
+    let mut buffer = vec![0; BUFFER_LENGTH];
+    let mut str_buffer = String::new();
+    // the start of a multi-byte character split across two reads
+    let mut carry: Vec<u8> = Vec::new();
+
+    // decompressed bytes read so far, not counting the leading "[\n"
+    let decompressing = Instant::now();
+    let mut total_bytes = match &options.resume {
+        Some(checkpoint) => {
+            // discard everything up to the first entity not yet processed
+            let skip = checkpoint.offset - checkpoint.stream.decompressed_offset;
+            io::copy(&mut (&mut md).take(skip), &mut io::sink()).map_err(start_error)?;
+            checkpoint.offset - DUMP_START.len() as u64
+        }
+        None => {
+            // discard the first two bytes representing "[\n"
+            md.read_exact(&mut [0u8; DUMP_START.len()]).map_err(start_error)?;
+            0
+        }
+    };
+    clocks.decompression.add(decompressing);
+
+    let mut seq = 0;
+    let mut batch_offset = DUMP_START.len() as u64 + total_bytes;
+    let mut batch_size = max_batch_size;
+    // why the input ended without the end of the array, once it has
+    let mut ended = None;
+    // the id of the last entity sent, to tell where a truncated dump stops
+    let mut last_id = None;
+    let decompressing = Instant::now();
+    let mut read = md.read(&mut buffer[..batch_size.min(BUFFER_LENGTH)]);
+    clocks.decompression.add(decompressing);
+
+    loop {
+        let n = match read {
+            Ok(n) => n,
+            // a bzip2 stream cut short
+            Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => {
+                ended = Some(error.to_string());
+                0
+            }
+            Err(error) => return Err(ProcessError::Read(error)),
+        };
+        if n == 0 {
+            ended.get_or_insert_with(|| String::from("the JSON array was never closed"));
+            // the end of the array may be followed by a newline, as in dumps written by other tools
+            if str_buffer.trim_end().ends_with(DUMP_END) {
+                str_buffer.truncate(str_buffer.trim_end().len());
+            }
+        }
+        total_bytes += n as u64;
+        progress.set_position(compressed_position());
+        if let Some(metrics) = &options.metrics {
+            metrics.bytes_read.store(compressed_position(), Ordering::Relaxed);
+            metrics.bytes_decompressed.store(total_bytes, Ordering::Relaxed);
+        }
+        if streams.back() != Some(&md.stream_start()) {
+            streams.push_back(md.stream_start());
+        }
+
+        let splitting = Instant::now();
+        // convert to utf8 string, holding back a character cut off by the end of the read
+        let bytes = if carry.is_empty() {
+            &buffer[..n]
+        } else {
+            carry.extend_from_slice(&buffer[..n]);
+            &carry[..]
+        };
+        let valid = match from_utf8(bytes) {
+            Ok(valid) => valid.len(),
+            Err(error) if error.error_len().is_none() => error.valid_up_to(),
+            Err(error) => {
+                let offset = DUMP_START.len() as u64 + total_bytes - bytes.len() as u64 + error.valid_up_to() as u64;
+                return Err(ProcessError::InvalidUtf8 { offset });
+            }
+        };
+        // SAFETY: validated just above
+        str_buffer.push_str(unsafe { std::str::from_utf8_unchecked(&bytes[..valid]) });
+        carry = bytes[valid..].to_vec();
+
+        // keep the incomplete last entity in the string buffer and send the rest, all of it once the input has ended
+        let min_length = if ended.is_some() { 0 } else { batch_size };
+        let (entities, last) = splitter::take_complete(&mut str_buffer, min_length);
+        clocks.splitting.add(splitting);
+        if let Some(entities) = entities {
+            last_id = splitter::entities(&entities).last().and_then(splitter::entity_id).map(str::to_string);
+            // the entity left in the buffer is the first one the next batch starts with
+            let offset = DUMP_START.len() as u64 + total_bytes - (carry.len() + str_buffer.len()) as u64;
+            while streams.len() > 1 && streams[1].decompressed_offset <= offset {
+                streams.pop_front();
+            }
+            let resume = ResumePoint { stream: streams[0], offset };
+            if let Some(metrics) = &options.metrics {
+                Metrics::add(&metrics.batches_waiting_filter, 1);
+            }
+            if !budget.acquire(entities.len()) || batches.send(Batch { seq, entities, offset: batch_offset, resume }).is_err() {
+                if let Some(metrics) = &options.metrics {
+                    Metrics::sub(&metrics.batches_waiting_filter, 1);
+                }
+                debug!("Filtering stopped, no longer reading");
+                break;
+            }
+            seq += 1;
+            batch_offset = offset;
+            batch_size = adapt_batch_size(batch_size, max_batch_size, budget);
+        }
+
+        if last {
+            debug!("Last entity");
+            break;
+        }
+
+        if let Some(reason) = ended {
+            // the entities are counted once those sent have been filtered
+            let last_id = last_id.unwrap_or_else(|| String::from("(none)"));
+            return Err(ProcessError::Truncated { compressed_offset: compressed_position(), last_id, entities: 0, reason });
+        }
+
+        if options.cancel.is_cancelled() {
+            debug!("Cancelled, no longer reading");
+            break;
+        }
+
+        let decompressing = Instant::now();
+        read = md.read(&mut buffer[..batch_size.min(BUFFER_LENGTH)]);
+        clocks.decompression.add(decompressing);
+    }
+    Ok(total_bytes)
+}
+
+// runs an entity's output through the transforms, returning `None` if one of them drops it
+fn apply_transforms<'a>(filtered: Option<Output<'a>>, transforms: &[Transform]) -> Option<Output<'a>> {
+    let filtered = filtered?;
+    if transforms.is_empty() {
+        return Some(filtered);
+    }
+    let transformed = transforms.iter().try_fold(filtered.into_string(), |filtered, transform| transform(filtered))?;
+    Some(Output::Filtered(transformed + "\n"))
+}
+
+fn filter_batch(batch: &Batch, filter: &mut dyn EntityFilter, transforms: &[Transform], cancel: &CancellationToken) -> Result<FilteredBatch> {
+    let mut output = String::new();
+    let mut ends = Vec::new();
+    let mut num_entities = 0;
+    let mut num_entities_output = 0;
+    let mut errors = Vec::new();
+    let mut cancelled = false;
+    let failures = filter.failures();
+    for entity in splitter::entities(&batch.entities) {
+        if cancel.is_cancelled() {
+            cancelled = true;
+            break;
+        }
+        num_entities += 1;
+        let failed = filter.failures();
+        let filtered = filter.apply(entity)?.map(Output::from);
+        if filter.failures() > failed {
+            let error = EntityError {
+                id: splitter::entity_id(entity).map(str::to_string),
+                index: 0,
+                offset: batch.offset + (entity.as_ptr() as usize - batch.entities.as_ptr() as usize) as u64,
+                length: entity.len(),
+                error: filter.take_error().unwrap_or_else(|| String::from("could not be filtered")),
+            };
+            errors.push((num_entities - 1, error));
+        }
+        if let Some(filtered) = apply_transforms(filtered, transforms) {
+            filtered.push_to(&mut output);
+            ends.push(output.len());
+            num_entities_output += 1;
+        }
+    }
+    let num_entities_failed = filter.failures() - failures;
+    Ok(FilteredBatch { seq: batch.seq, output, ends, num_entities, num_entities_output, num_entities_failed, errors, resume: batch.resume, cancelled })
+}
+
+// filters batches until there are none left, sending back the output for each
+#[allow(clippy::too_many_arguments)]
+fn filter_batches(filters: &FilterFactory, transforms: &[Transform], options: &ProcessOptions, batches: Arc<Mutex<Receiver<Batch>>>, results: Sender<Result<FilteredBatch>>, budget: &MemoryBudget, clocks: &StageClocks, core_id: Option<core_affinity::CoreId>) {
+    if let Some(core_id) = core_id {
+        if !core_affinity::set_for_current(core_id) {
+            info!("Could not pin filtering thread to core {:?}", core_id);
+        }
+    }
+
+    let mut filter = match filters() {
+        Ok(filter) => filter,
+        Err(error) => {
+            let _ = results.send(Err(error));
+            return;
+        }
+    };
+
+    loop {
+        // the lock is only held while waiting for the next batch
+        let batch = match batches.lock().expect("Batch queue poisoned").recv() {
+            Ok(batch) => batch,
+            Err(_) => break,
+        };
+        if let Some(metrics) = &options.metrics {
+            Metrics::sub(&metrics.batches_waiting_filter, 1);
+        }
+
+        let filtering = Instant::now();
+        let filtered = filter_batch(&batch, filter.as_mut(), transforms, &options.cancel);
+        clocks.filtering.add(filtering);
+
+        // the input is dropped here, only the output is held until it's written
+        if let Ok(filtered) = &filtered {
+            budget.charge(filtered.output.len());
+        }
+        budget.release(batch.entities.len());
+
+        let failed = filtered.is_err();
+        if let Some(metrics) = &options.metrics {
+            Metrics::add(&metrics.batches_waiting_write, 1);
+        }
+        if results.send(filtered).is_err() || failed {
+            break;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process() {
+        let input = std::path::Path::new("./tests/invalid-json.json.bz2").to_path_buf();
+        let options = ProcessOptions { continue_on_error: true, write_buffer_size: 64, ..ProcessOptions::default() };
+        let stats = process(Some(input), &mut std::io::stdout(), ".id", &options).unwrap();
+        assert_eq!(stats.entities_read, 2);
+        assert_eq!(stats.entities_failed, 2);
+    }
+
+    #[test]
+    fn test_process_error_report() {
+        let input = std::path::Path::new("./tests/invalid-json.json.bz2").to_path_buf();
+        let directory = tempfile::tempdir().unwrap();
+        let report = directory.path().join("errors.ndjson");
+        let options = ProcessOptions { continue_on_error: true, error_report: Some(report.clone()), ..ProcessOptions::default() };
+        process(Some(input), &mut Vec::new(), ".id", &options).unwrap();
+
+        let contents = std::fs::read_to_string(&report).unwrap();
+        let errors: Vec<EntityError> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let positions: Vec<_> = errors.iter().map(|error| (error.id.clone(), error.index, error.offset, error.length)).collect();
+        assert_eq!(positions, vec![(None, 0, 2, 13), (None, 1, 17, 18)]);
+        assert!(errors.iter().all(|error| !error.error.is_empty()));
+    }
+
+    #[test]
+    fn test_process_stats() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let mut output = Vec::new();
+        let stats = process(Some(input), &mut output, r#"select(.type == "item") | .id"#, &ProcessOptions::default()).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        assert_eq!(stats.entities_written, 7);
+        assert_eq!(stats.entities_failed, 0);
+        assert_eq!(stats.bytes_in, 7355);
+        assert_eq!(stats.bytes_out, output.len() as u64);
+        // every stage had something to do
+        let stages = &stats.stages;
+        assert!([stages.decompression, stages.splitting, stages.filtering, stages.writing].iter().all(|time| !time.is_zero()));
+    }
+
+    #[test]
+    fn test_process_resume() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let directory = tempfile::tempdir().unwrap();
+        let checkpoint_path = directory.path().join("run.checkpoint");
+        let mut full = Vec::new();
+        let options = ProcessOptions { checkpoint: Some(checkpoint_path.clone()), ..ProcessOptions::default() };
+        let stats = process(Some(input.clone()), &mut full, ".id", &options).unwrap();
+        let completed = Checkpoint::load(&checkpoint_path).unwrap();
+        assert!(completed.complete);
+        assert_eq!(completed.entities_read, stats.entities_read);
+        assert_eq!(completed.bytes_out, full.len() as u64);
+
+        // pretend the run was interrupted right after the first entity
+        let mut dump = String::new();
+        decoder::decoder(std::fs::File::open(&input).unwrap()).read_to_string(&mut dump).unwrap();
+        let first_output = full.iter().position(|&byte| byte == b'\n').unwrap() + 1;
+        let checkpoint = Checkpoint {
+            offset: (dump.find(splitter::ENTITY_SEPARATOR).unwrap() + splitter::ENTITY_SEPARATOR.len()) as u64,
+            entities_read: 1,
+            entities_written: 1,
+            bytes_out: first_output as u64,
+            ..Checkpoint::default()
+        };
+        let mut output = full[..first_output].to_vec();
+        let options = ProcessOptions { resume: Some(checkpoint), ..ProcessOptions::default() };
+        let resumed = process(Some(input), &mut output, ".id", &options).unwrap();
+        assert_eq!(output, full);
+        assert_eq!(resumed.entities_read, stats.entities_read);
+        assert_eq!(resumed.bytes_in, stats.bytes_in);
+        assert_eq!(resumed.bytes_out, stats.bytes_out);
+    }
+
+    #[test]
+    fn test_process_cancelled() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let mut output = Vec::new();
+        let options = ProcessOptions::default();
+        options.cancel.cancel();
+        let stats = process(Some(input), &mut output, ".id", &options).unwrap();
+        assert!(stats.cancelled);
+        assert_eq!(stats.entities_read, 0);
+        assert!(output.is_empty());
+    }
+
+    #[test]
+    fn test_process_cancelled_part_way() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let mut output = Vec::new();
+        let options = ProcessOptions { threads: 1, ..ProcessOptions::default() };
+        let cancel = options.cancel.clone();
+        let transforms: Vec<Transform> = vec![Arc::new(move |id: String| {
+            if id == "\"Q3\"" {
+                cancel.cancel();
+            }
+            Some(id)
+        })];
+        let filters = filter::jq_filter_factory(".id", false, false);
+        let stats = run(&mut FileSource::new(input), &mut WriteSink::new(&mut output, 64), &filters, &transforms, &options).unwrap();
+        assert!(stats.cancelled);
+        assert_eq!(stats.entities_read, 3);
+        assert_eq!(String::from_utf8(output).unwrap(), "\"Q1\"\n\"Q2\"\n\"Q3\"\n");
+    }
+
+    #[test]
+    fn test_process_with() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let mut ids = Vec::new();
+        let found = process_with(Some(input.clone()), |entity| {
+            ids.push(entity.id().unwrap().to_string());
+            ControlFlow::<()>::Continue(())
+        }).unwrap();
+        assert_eq!(found, None);
+        assert_eq!(ids, vec!["Q1", "Q2", "Q3", "Q4", "Q5", "Q6", "P1", "Q60"]);
+
+        let property = process_with(Some(input), |entity| {
+            let entity = entity.parse().unwrap();
+            match entity.entity_type.as_str() {
+                "property" => ControlFlow::Break(entity.id),
+                _ => ControlFlow::Continue(()),
+            }
+        }).unwrap();
+        assert_eq!(property.as_deref(), Some("P1"));
+    }
+
+    #[test]
+    fn test_pass_through() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let mut output = Vec::new();
+        let options = ProcessOptions { pass_through: true, ..ProcessOptions::default() };
+        process(Some(input), &mut output, r#"select(.type == "property")"#, &options).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with(r#"{"id": "P1","type": "property","#));
+        assert_eq!(output.lines().count(), 1);
+    }
+
+    #[test]
+    fn test_process_keeps_order() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let mut output = Vec::new();
+        let options = ProcessOptions { threads: 4, ..ProcessOptions::default() };
+        process(Some(input), &mut output, ".id", &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(), "\"Q1\"\n\"Q2\"\n\"Q3\"\n\"Q4\"\n\"Q5\"\n\"Q6\"\n\"P1\"\n\"Q60\"\n");
+    }
+
+    #[test]
+    fn test_process_with_memory_limit() {
+        let input = std::path::Path::new("./tests/test-data.json.bz2").to_path_buf();
+        let mut output = Vec::new();
+        let options = ProcessOptions { threads: 2, max_memory: Some(1024), ..ProcessOptions::default() };
+        process(Some(input), &mut output, ".id", &options).unwrap();
+        assert_eq!(String::from_utf8(output).unwrap().lines().count(), 8);
+    }
+
+    // a bzip2 compressed dump of `decompressed`
+    fn compressed_dump(decompressed: &str) -> tempfile::NamedTempFile {
+        let mut encoder = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::best());
+        encoder.write_all(decompressed.as_bytes()).unwrap();
+        let mut dump = tempfile::NamedTempFile::new().unwrap();
+        dump.write_all(&encoder.finish().unwrap()).unwrap();
+        dump
+    }
+
+    #[test]
+    fn test_process_truncated() {
+        let dump = compressed_dump("[\n{\"id\":\"Q1\"},\n{\"id\":\"Q2\"},\n{\"id\":");
+        let mut output = Vec::new();
+        let error = process(Some(dump.path().to_path_buf()), &mut output, ".id", &ProcessOptions::default()).unwrap_err();
+        match error {
+            ProcessError::Truncated { compressed_offset, last_id, entities, .. } => {
+                assert_eq!(compressed_offset, std::fs::metadata(dump.path()).unwrap().len());
+                assert_eq!(last_id, "Q2");
+                assert_eq!(entities, 2);
+            }
+            error => panic!("Expected a truncated dump, got {:?}", error),
+        }
+        // the complete entities are still written
+        assert_eq!(String::from_utf8(output).unwrap(), "\"Q1\"\n\"Q2\"\n");
+
+        // a compressed stream cut short
+        let bytes = std::fs::read("./tests/test-data.json.bz2").unwrap();
+        let mut cut = tempfile::NamedTempFile::new().unwrap();
+        cut.write_all(&bytes[..bytes.len() / 2]).unwrap();
+        let error = process(Some(cut.path().to_path_buf()), &mut Vec::new(), ".id", &ProcessOptions::default()).unwrap_err();
+        assert!(matches!(error, ProcessError::Truncated { entities: 0, .. }), "{:?}", error);
+
+        // a newline after the end of the array is fine
+        let dump = compressed_dump("[\n{\"id\":\"Q1\"},\n{\"id\":\"Q2\"}\n]\n");
+        let stats = process(Some(dump.path().to_path_buf()), &mut Vec::new(), ".id", &ProcessOptions::default()).unwrap();
+        assert_eq!(stats.entities_read, 2);
+    }
+
+    #[test]
+    fn test_error_budget() {
+        assert_eq!("100".parse(), Ok(ErrorBudget::Count(100)));
+        assert_eq!("2.5%".parse(), Ok(ErrorBudget::Percentage(2.5)));
+        assert!("150%".parse::<ErrorBudget>().is_err());
+        assert!("lots".parse::<ErrorBudget>().is_err());
+        assert!(!ErrorBudget::Count(2).is_exceeded(2, 10, false));
+        assert!(ErrorBudget::Count(2).is_exceeded(3, 10, false));
+        // too few entities to tell until the end
+        assert!(!ErrorBudget::Percentage(10.0).is_exceeded(5, 10, false));
+        assert!(ErrorBudget::Percentage(10.0).is_exceeded(5, 10, true));
+        assert!(!ErrorBudget::Percentage(10.0).is_exceeded(100, 1000, false));
+        assert!(ErrorBudget::Percentage(10.0).is_exceeded(101, 1000, false));
+
+        let input = std::path::Path::new("./tests/invalid-json.json.bz2").to_path_buf();
+        let options = ProcessOptions { continue_on_error: true, max_errors: Some(ErrorBudget::Count(1)), ..ProcessOptions::default() };
+        assert!(matches!(process(Some(input.clone()), &mut Vec::new(), ".id", &options), Err(ProcessError::TooManyErrors { failed: 2, .. })));
+        let options = ProcessOptions { continue_on_error: true, max_errors: Some(ErrorBudget::Count(2)), ..ProcessOptions::default() };
+        assert!(process(Some(input), &mut Vec::new(), ".id", &options).is_ok());
+    }
+
+    #[test]
+    fn test_process_errors() {
+        let input = std::path::Path::new("./tests/invalid-json.json.bz2").to_path_buf();
+        let options = ProcessOptions::default();
+        assert!(matches!(process(Some(input.clone()), &mut Vec::new(), ".id", &options), Err(ProcessError::Filter { .. })));
+        assert!(matches!(process(Some(input), &mut Vec::new(), ".id |", &options), Err(ProcessError::FilterCompile { .. })));
+        assert!(matches!(process(None, &mut Vec::new(), ".id", &options), Err(ProcessError::MissingInput)));
+    }
+
+}
@@ -0,0 +1,553 @@
+/*!
+ * Profiling a dump without writing anything out: how many entities of each
+ * type it has, which properties, label languages and sites they use, and how
+ * big they are. Entities are profiled on all filtering threads, each thread
+ * keeping its own `DumpProfile` which are merged once the run is done.
+ *
+ * Optionally, it also counts which properties are used together on the same
+ * entities, as a sparse co-occurrence matrix, for schema discovery and mining
+ * constraints, and keeps leaderboards of the entities with the most
+ * statements, sitelinks and references and of the largest ones, to show how
+ * skewed a dump is and help pick buffer sizes which fit its biggest entities.
+ */
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::sync::{Arc, Mutex};
+use serde::de::IgnoredAny;
+use serde::ser::{SerializeStruct, Serializer};
+use serde::{Deserialize, Serialize};
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+use crate::index::parse_id;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+// buckets per doubling of entity sizes, so percentiles are within about 9% of the exact size
+const BUCKETS_PER_DOUBLING: f64 = 8.0;
+
+/// What a dump is made of, see the module documentation
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DumpProfile {
+    pub entities: u64,
+    /// Entities by type, e.g. item, property or lexeme
+    pub types: BTreeMap<String, u64>,
+    /// How often each property is used
+    pub properties: BTreeMap<String, PropertyUsage>,
+    /// Entities with a label in each language
+    pub label_languages: BTreeMap<String, u64>,
+    /// Entities with a sitelink to each site, e.g. enwiki
+    pub sites: BTreeMap<String, u64>,
+    /// Entities by how many sitelinks they have
+    pub sitelink_counts: BTreeMap<usize, u64>,
+    /// Sizes of the entities' JSON in bytes
+    pub sizes: SizeHistogram,
+    /// Only counted when asked for, see `ProfileOptions`
+    #[serde(skip)]
+    pub cooccurrence: Option<Cooccurrence>,
+    /// Only kept when asked for, see `ProfileOptions`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub top: Option<Leaderboards>,
+}
+
+/// What to profile on top of the counts always made
+#[derive(Debug, Clone, Default)]
+pub struct ProfileOptions {
+    /// Count the entities each pair of properties is used together on
+    pub cooccurrence: bool,
+    /// Keep leaderboards of this many entities, see `Leaderboards`
+    pub top: Option<usize>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct PropertyUsage {
+    /// Entities with at least one statement for the property
+    pub entities: u64,
+    pub statements: u64,
+}
+
+// the parts of an entity which are profiled, skipping over the rest without allocating
+#[derive(Deserialize)]
+struct Outline {
+    #[serde(default)]
+    id: String,
+    #[serde(rename = "type")]
+    entity_type: String,
+    #[serde(default)]
+    labels: BTreeMap<String, IgnoredAny>,
+    #[serde(default)]
+    claims: BTreeMap<String, Vec<StatementOutline>>,
+    #[serde(default)]
+    sitelinks: BTreeMap<String, IgnoredAny>,
+}
+
+#[derive(Deserialize)]
+struct StatementOutline {
+    #[serde(default)]
+    references: Vec<IgnoredAny>,
+}
+
+impl DumpProfile {
+    /// An empty profile, counting what `options` asks for
+    pub fn new(options: &ProfileOptions) -> Self {
+        DumpProfile {
+            cooccurrence: options.cooccurrence.then(Cooccurrence::default),
+            top: options.top.map(Leaderboards::new),
+            ..DumpProfile::default()
+        }
+    }
+
+    /// Adds a raw entity to the profile
+    pub fn add(&mut self, raw: &str) -> serde_json::Result<()> {
+        let outline: Outline = serde_json::from_str(raw)?;
+        self.entities += 1;
+        if let Some(cooccurrence) = &mut self.cooccurrence {
+            let mut properties: Vec<u32> = outline.claims.keys()
+                .filter_map(|property| match parse_id(property) {
+                    Some((b'P', number)) => Some(number),
+                    _ => None,
+                })
+                .collect();
+            properties.sort_unstable();
+            cooccurrence.add(&properties);
+        }
+        if let Some(top) = &mut self.top {
+            let statements = outline.claims.values().map(|statements| statements.len() as u64).sum();
+            let references = outline.claims.values().flatten().map(|statement| statement.references.len() as u64).sum();
+            top.statements.add(statements, &outline.id);
+            top.sitelinks.add(outline.sitelinks.len() as u64, &outline.id);
+            top.references.add(references, &outline.id);
+            top.sizes.add(raw.len() as u64, &outline.id);
+        }
+        *self.types.entry(outline.entity_type).or_default() += 1;
+        for (property, statements) in outline.claims {
+            let usage = self.properties.entry(property).or_default();
+            usage.entities += 1;
+            usage.statements += statements.len() as u64;
+        }
+        for language in outline.labels.into_keys() {
+            *self.label_languages.entry(language).or_default() += 1;
+        }
+        *self.sitelink_counts.entry(outline.sitelinks.len()).or_default() += 1;
+        for site in outline.sitelinks.into_keys() {
+            *self.sites.entry(site).or_default() += 1;
+        }
+        self.sizes.add(raw.len() as u64);
+        Ok(())
+    }
+
+    /// Adds everything in `other`, e.g. the profile of another part of the same dump
+    pub fn merge(&mut self, other: DumpProfile) {
+        self.entities += other.entities;
+        merge_counts(&mut self.types, other.types);
+        for (property, usage) in other.properties {
+            let total = self.properties.entry(property).or_default();
+            total.entities += usage.entities;
+            total.statements += usage.statements;
+        }
+        merge_counts(&mut self.label_languages, other.label_languages);
+        merge_counts(&mut self.sites, other.sites);
+        merge_counts(&mut self.sitelink_counts, other.sitelink_counts);
+        self.sizes.merge(other.sizes);
+        match (&mut self.cooccurrence, other.cooccurrence) {
+            (Some(total), Some(cooccurrence)) => total.merge(cooccurrence),
+            (total @ None, cooccurrence) => *total = cooccurrence,
+            (Some(_), None) => {}
+        }
+        match (&mut self.top, other.top) {
+            (Some(total), Some(top)) => total.merge(top),
+            (total @ None, top) => *total = top,
+            (Some(_), None) => {}
+        }
+    }
+
+    /// Writes the profile as CSV, one `section,key,value` row per count
+    pub fn write_csv(&self, output: &mut impl Write) -> io::Result<()> {
+        writeln!(output, "section,key,value")?;
+        writeln!(output, "entities,all,{}", self.entities)?;
+        for (entity_type, count) in &self.types {
+            writeln!(output, "type,{},{}", entity_type, count)?;
+        }
+        for (property, usage) in &self.properties {
+            writeln!(output, "property_entities,{},{}", property, usage.entities)?;
+            writeln!(output, "property_statements,{},{}", property, usage.statements)?;
+        }
+        for (language, count) in &self.label_languages {
+            writeln!(output, "label_language,{},{}", language, count)?;
+        }
+        for (site, count) in &self.sites {
+            writeln!(output, "site,{},{}", site, count)?;
+        }
+        for (sitelinks, count) in &self.sitelink_counts {
+            writeln!(output, "sitelink_count,{},{}", sitelinks, count)?;
+        }
+        for (name, value) in self.sizes.summary() {
+            writeln!(output, "size,{},{}", name, value)?;
+        }
+        if let Some(top) = &self.top {
+            for (section, leaderboard) in top.sections() {
+                for (id, count) in leaderboard.ranking() {
+                    writeln!(output, "{},{},{}", section, id, count)?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// How many entities each pair of properties is used together on. Only pairs used together at least once are kept
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Cooccurrence {
+    // by property number, the lower one first, e.g. (31, 279) for P31 and P279
+    pairs: HashMap<(u32, u32), u64>,
+}
+
+impl Cooccurrence {
+    /// Adds the properties of an entity, by number in ascending order without duplicates
+    pub fn add(&mut self, properties: &[u32]) {
+        for (i, &property) in properties.iter().enumerate() {
+            for &other in &properties[i + 1..] {
+                *self.pairs.entry((property, other)).or_default() += 1;
+            }
+        }
+    }
+
+    pub fn merge(&mut self, other: Cooccurrence) {
+        for (pair, count) in other.pairs {
+            *self.pairs.entry(pair).or_default() += count;
+        }
+    }
+
+    /// How many entities use both properties, e.g. P31 and P279, in either order
+    pub fn get(&self, property: &str, other: &str) -> u64 {
+        match (parse_id(property), parse_id(other)) {
+            (Some((b'P', property)), Some((b'P', other))) => self.pairs.get(&(property.min(other), property.max(other))).copied().unwrap_or(0),
+            _ => 0,
+        }
+    }
+
+    /// How many pairs of properties are used together at least once
+    pub fn len(&self) -> usize {
+        self.pairs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pairs.is_empty()
+    }
+
+    /// Writes the matrix as sparse CSV, one `property,other_property,entities` row per pair used together,
+    /// with the lower property first and the rows in order of property number
+    pub fn write_csv(&self, output: &mut impl Write) -> io::Result<()> {
+        let mut pairs: Vec<_> = self.pairs.iter().collect();
+        pairs.sort_unstable();
+        writeln!(output, "property,other_property,entities")?;
+        for ((property, other), count) in pairs {
+            writeln!(output, "P{},P{},{}", property, other, count)?;
+        }
+        Ok(())
+    }
+}
+
+/// The entities with the highest counts of something, e.g. statements, keeping a fixed number of them
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Leaderboard {
+    size: usize,
+    // lowest count first, ties by id
+    entries: BTreeSet<(u64, String)>,
+}
+
+impl Leaderboard {
+    pub fn new(size: usize) -> Self {
+        Leaderboard { size, entries: BTreeSet::new() }
+    }
+
+    /// Adds an entity's count, unless it's too low to make the leaderboard. Entities with a count of 0 never do
+    pub fn add(&mut self, count: u64, id: &str) {
+        if self.size == 0 || count == 0 {
+            return;
+        }
+        if self.entries.len() >= self.size {
+            match self.entries.first() {
+                Some((lowest, _)) if count > *lowest => self.entries.pop_first(),
+                _ => return,
+            };
+        }
+        self.entries.insert((count, id.to_string()));
+    }
+
+    pub fn merge(&mut self, other: Leaderboard) {
+        for (count, id) in other.entries {
+            self.add(count, &id);
+        }
+    }
+
+    /// The entities' ids and counts, highest count first
+    pub fn ranking(&self) -> Vec<(&str, u64)> {
+        self.entries.iter().rev().map(|(count, id)| (id.as_str(), *count)).collect()
+    }
+}
+
+impl Serialize for Leaderboard {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        #[derive(Serialize)]
+        struct Entry<'a> {
+            id: &'a str,
+            count: u64,
+        }
+        serializer.collect_seq(self.ranking().into_iter().map(|(id, count)| Entry { id, count }))
+    }
+}
+
+/// The entities with the most statements, sitelinks and references (over all their statements), and the
+/// largest ones by the size of their JSON in bytes
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Leaderboards {
+    pub statements: Leaderboard,
+    pub sitelinks: Leaderboard,
+    pub references: Leaderboard,
+    pub sizes: Leaderboard,
+}
+
+impl Leaderboards {
+    /// Empty leaderboards of `size` entities each
+    pub fn new(size: usize) -> Self {
+        Leaderboards {
+            statements: Leaderboard::new(size),
+            sitelinks: Leaderboard::new(size),
+            references: Leaderboard::new(size),
+            sizes: Leaderboard::new(size),
+        }
+    }
+
+    pub fn merge(&mut self, other: Leaderboards) {
+        self.statements.merge(other.statements);
+        self.sitelinks.merge(other.sitelinks);
+        self.references.merge(other.references);
+        self.sizes.merge(other.sizes);
+    }
+
+    // the leaderboards with the sections of their CSV rows
+    fn sections(&self) -> [(&'static str, &Leaderboard); 4] {
+        [
+            ("top_statements", &self.statements),
+            ("top_sitelinks", &self.sitelinks),
+            ("top_references", &self.references),
+            ("top_size", &self.sizes),
+        ]
+    }
+}
+
+fn merge_counts<K: Ord>(total: &mut BTreeMap<K, u64>, counts: BTreeMap<K, u64>) {
+    for (key, count) in counts {
+        *total.entry(key).or_default() += count;
+    }
+}
+
+/// Approximate distribution of sizes, in logarithmic buckets so it stays small however many are added.
+///
+/// Serialized as a summary: count, min, max, mean and the 50th, 90th, 99th and 99.9th percentiles.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SizeHistogram {
+    buckets: BTreeMap<u32, u64>,
+    count: u64,
+    total: u64,
+    min: u64,
+    max: u64,
+}
+
+impl SizeHistogram {
+    pub fn add(&mut self, size: u64) {
+        *self.buckets.entry(bucket(size)).or_default() += 1;
+        self.min = if self.count == 0 { size } else { self.min.min(size) };
+        self.max = self.max.max(size);
+        self.count += 1;
+        self.total += size;
+    }
+
+    pub fn merge(&mut self, other: SizeHistogram) {
+        if other.count == 0 {
+            return;
+        }
+        self.min = if self.count == 0 { other.min } else { self.min.min(other.min) };
+        self.max = self.max.max(other.max);
+        self.count += other.count;
+        self.total += other.total;
+        merge_counts(&mut self.buckets, other.buckets);
+    }
+
+    /// The size which `percentile` percent of sizes are at most, rounded up to the end of its bucket
+    pub fn percentile(&self, percentile: f64) -> u64 {
+        let rank = ((percentile / 100.0) * self.count as f64).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (&bucket, &count) in &self.buckets {
+            seen += count;
+            if seen >= rank {
+                return bucket_end(bucket).clamp(self.min, self.max);
+            }
+        }
+        self.max
+    }
+
+    fn summary(&self) -> [(&'static str, u64); 8] {
+        [
+            ("count", self.count),
+            ("min", self.min),
+            ("max", self.max),
+            ("mean", self.total.checked_div(self.count).unwrap_or(0)),
+            ("p50", self.percentile(50.0)),
+            ("p90", self.percentile(90.0)),
+            ("p99", self.percentile(99.0)),
+            ("p999", self.percentile(99.9)),
+        ]
+    }
+}
+
+fn bucket(size: u64) -> u32 {
+    ((size.max(1) as f64).log2() * BUCKETS_PER_DOUBLING) as u32
+}
+
+// the largest size falling in `bucket`
+fn bucket_end(bucket: u32) -> u64 {
+    (2f64.powf((bucket + 1) as f64 / BUCKETS_PER_DOUBLING)).ceil() as u64 - 1
+}
+
+impl Serialize for SizeHistogram {
+    fn serialize<S: Serializer>(&self, serializer: S) -> std::result::Result<S::Ok, S::Error> {
+        let summary = self.summary();
+        let mut state = serializer.serialize_struct("SizeHistogram", summary.len())?;
+        for (name, value) in summary {
+            state.serialize_field(name, &value)?;
+        }
+        state.end()
+    }
+}
+
+// profiles the entities it's given on one thread, adding its profile to the total once dropped
+struct Profiler {
+    profile: DumpProfile,
+    total: Arc<Mutex<DumpProfile>>,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl EntityFilter for Profiler {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        if let Err(error) = self.profile.add(raw) {
+            filter::skip_entity(raw, &error, self.continue_on_error)?;
+            self.failures += 1;
+        }
+        Ok(None)
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+impl Drop for Profiler {
+    fn drop(&mut self) {
+        if let Ok(mut total) = self.total.lock() {
+            total.merge(std::mem::take(&mut self.profile));
+        }
+    }
+}
+
+/// Profiles every entity of `source` on `options.threads` threads
+pub fn profile(source: impl Source, options: ProcessOptions) -> Result<(DumpProfile, ProcessStats)> {
+    profile_with(source, ProfileOptions::default(), options)
+}
+
+/// Same as `profile`, also counting what `profiling` asks for
+pub fn profile_with(source: impl Source, profiling: ProfileOptions, options: ProcessOptions) -> Result<(DumpProfile, ProcessStats)> {
+    let total = Arc::new(Mutex::new(DumpProfile::new(&profiling)));
+    let continue_on_error = options.continue_on_error;
+    let profilers = Arc::clone(&total);
+    let stats = Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(Profiler { profile: DumpProfile::new(&profiling), total: Arc::clone(&profilers), continue_on_error, failures: 0 }))
+        .sink(io::sink())
+        .options(options)
+        .build()?
+        .run()?;
+    let profile = std::mem::take(&mut *total.lock().expect("Profile poisoned"));
+    Ok((profile, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    #[test]
+    fn test_profile() {
+        let options = ProcessOptions { threads: 2, ..ProcessOptions::default() };
+        let (profile, stats) = profile(FileSource::new("./tests/test-data.json.bz2"), options).unwrap();
+        assert_eq!(profile.entities, 8);
+        assert_eq!(stats.entities_read, 8);
+        assert_eq!(profile.types.get("item"), Some(&7));
+        assert_eq!(profile.types.get("property"), Some(&1));
+        assert_eq!(profile.sizes.summary()[0], ("count", 8));
+        assert_eq!(profile.sitelink_counts.values().sum::<u64>(), 8);
+
+        let mut csv = Vec::new();
+        profile.write_csv(&mut csv).unwrap();
+        assert!(String::from_utf8(csv).unwrap().contains("type,item,7\n"));
+    }
+
+    #[test]
+    fn test_size_histogram() {
+        let mut sizes = SizeHistogram::default();
+        for size in 1..=1000 {
+            sizes.add(size);
+        }
+        let mut other = SizeHistogram::default();
+        other.add(5000);
+        sizes.merge(other);
+        assert_eq!((sizes.min, sizes.max, sizes.count), (1, 5000, 1001));
+        let p50 = sizes.percentile(50.0);
+        assert!((500..=550).contains(&p50), "{}", p50);
+        assert_eq!(sizes.percentile(100.0), 5000);
+    }
+
+    #[test]
+    fn test_leaderboards() {
+        let options = ProcessOptions { threads: 2, ..ProcessOptions::default() };
+        let profiling = ProfileOptions { top: Some(3), ..ProfileOptions::default() };
+        let (profile, _) = profile_with(FileSource::new("./tests/test-data.json.bz2"), profiling, options).unwrap();
+        let top = profile.top.unwrap();
+        let sizes = top.sizes.ranking();
+        assert_eq!(sizes.len(), 3);
+        assert_eq!(sizes[0], ("Q60", profile.sizes.max));
+        assert!(sizes.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+
+        let mut leaderboard = Leaderboard::new(2);
+        for (count, id) in [(5, "Q1"), (1, "Q2"), (9, "Q3"), (5, "Q4"), (0, "Q6")] {
+            leaderboard.add(count, id);
+        }
+        let mut other = Leaderboard::new(2);
+        other.add(7, "Q5");
+        leaderboard.merge(other);
+        assert_eq!(leaderboard.ranking(), vec![("Q3", 9), ("Q5", 7)]);
+        assert_eq!(serde_json::to_string(&leaderboard).unwrap(), r#"[{"id":"Q3","count":9},{"id":"Q5","count":7}]"#);
+    }
+
+    #[test]
+    fn test_cooccurrence() {
+        let options = ProcessOptions { threads: 2, ..ProcessOptions::default() };
+        let profiling = ProfileOptions { cooccurrence: true, ..ProfileOptions::default() };
+        let (profile, _) = profile_with(FileSource::new("./tests/test-data.json.bz2"), profiling, options).unwrap();
+        let cooccurrence = profile.cooccurrence.unwrap();
+        assert!(!cooccurrence.is_empty());
+
+        let mut cooccurrence = Cooccurrence::default();
+        cooccurrence.add(&[17, 31, 279]);
+        cooccurrence.add(&[31, 279]);
+        cooccurrence.add(&[31]);
+        assert_eq!((cooccurrence.get("P279", "P31"), cooccurrence.get("P17", "P31"), cooccurrence.get("P31", "Q5")), (2, 1, 0));
+        let mut csv = Vec::new();
+        cooccurrence.write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "property,other_property,entities\nP17,P31,1\nP17,P279,1\nP31,P279,2\n");
+    }
+}
@@ -0,0 +1,275 @@
+/*!
+ * How progress is reported during long running operations: an interactive
+ * bar, periodic single-line JSON records on stderr for orchestrators and web
+ * UIs, or nothing at all.
+ *
+ * Runs can also print a compact stats line every so often, whichever of those
+ * is used, for batch logs where the bar is useless, e.g.
+ * `[5 minutes] 1234567 entities (4115/s), in 45.2 MB/s, out 12.3 MB/s, 234567 matched, 12 errors, ETA 2 hours`.
+ * Rates are over the time since the previous line, except on the last line,
+ * which is printed once the run is over and averages them over all of it.
+ */
+
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+use indicatif::{HumanDuration, ProgressBar, ProgressStyle};
+use serde::Serialize;
+
+// how often JSON progress records are emitted
+const RECORD_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Progress {
+    /// An interactive progress bar on stderr
+    #[default]
+    Bar,
+    /// One JSON record per line on stderr, see `ProgressRecord`
+    Json,
+    /// Nothing at all, e.g. for cron jobs and CI logs
+    Hidden,
+}
+
+impl FromStr for Progress {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "bar" => Ok(Progress::Bar),
+            "json" => Ok(Progress::Json),
+            "none" => Ok(Progress::Hidden),
+            _ => Err(format!("Invalid progress '{}', expected bar, json or none", value)),
+        }
+    }
+}
+
+/// A snapshot of a run's progress, as emitted with `Progress::Json`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProgressRecord {
+    /// Bytes of input consumed, compressed ones for dumps being filtered
+    pub bytes: u64,
+    /// Only known when the size of the input is
+    pub total_bytes: Option<u64>,
+    pub entities_read: u64,
+    pub entities_written: u64,
+    pub bytes_per_sec: f64,
+    pub elapsed_secs: f64,
+    /// Seconds left at the current rate, only known along with `total_bytes`
+    pub eta_secs: Option<f64>,
+    pub finished: bool,
+}
+
+// what a stats line is worked out from, see the module documentation
+#[derive(Debug, Clone, Copy)]
+struct Snapshot {
+    at: Instant,
+    bytes: u64,
+    bytes_out: u64,
+    entities_read: u64,
+}
+
+// tracks progress and reports it in whichever way was asked for
+pub(crate) struct Reporter {
+    progress: Progress,
+    bar: ProgressBar,
+    total: Option<u64>,
+    bytes: AtomicU64,
+    bytes_out: AtomicU64,
+    entities_read: AtomicU64,
+    entities_written: AtomicU64,
+    entities_failed: AtomicU64,
+    start: Instant,
+    last_record: Mutex<Instant>,
+    stats_interval: Option<Duration>,
+    last_stats: Mutex<Snapshot>,
+}
+
+impl Reporter {
+    // `total` is the number of bytes expected in total, if known. the bar is drawn with `template`
+    pub(crate) fn new(progress: Progress, total: Option<u64>, template: &str) -> Self {
+        let bar = match progress {
+            Progress::Bar => ProgressBar::new(total.unwrap_or(0)),
+            Progress::Json | Progress::Hidden => ProgressBar::hidden(),
+        };
+        bar.set_style(ProgressStyle::default_bar().template(template).progress_chars("#>-"));
+        let start = Instant::now();
+        Reporter {
+            progress,
+            bar,
+            total,
+            bytes: AtomicU64::new(0),
+            bytes_out: AtomicU64::new(0),
+            entities_read: AtomicU64::new(0),
+            entities_written: AtomicU64::new(0),
+            entities_failed: AtomicU64::new(0),
+            start,
+            last_record: Mutex::new(start),
+            stats_interval: None,
+            last_stats: Mutex::new(Snapshot { at: start, bytes: 0, bytes_out: 0, entities_read: 0 }),
+        }
+    }
+
+    pub(crate) fn set_draw_rate(&self, per_sec: u64) {
+        self.bar.set_draw_rate(per_sec);
+    }
+
+    // prints a stats line every `interval`, and once finished
+    pub(crate) fn set_stats_interval(&mut self, interval: Duration) {
+        self.stats_interval = Some(interval);
+    }
+
+    pub(crate) fn set_position(&self, bytes: u64) {
+        self.bytes.store(bytes, Ordering::Relaxed);
+        self.bar.set_position(bytes);
+        self.maybe_emit();
+        self.maybe_print_stats();
+    }
+
+    pub(crate) fn set_entities(&self, read: usize, written: usize) {
+        self.entities_read.store(read as u64, Ordering::Relaxed);
+        self.entities_written.store(written as u64, Ordering::Relaxed);
+        self.bar.set_message(format!("Processed {} entities, {} outputted", read, written));
+        self.maybe_emit();
+        self.maybe_print_stats();
+    }
+
+    // bytes written and entities which couldn't be processed so far, only shown in stats lines
+    pub(crate) fn set_output(&self, bytes_out: u64, failed: usize) {
+        self.bytes_out.store(bytes_out, Ordering::Relaxed);
+        self.entities_failed.store(failed as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn finish(&self, message: String) {
+        // the last line is over the whole run
+        if self.stats_interval.is_some() {
+            let start = Snapshot { at: self.start, bytes: 0, bytes_out: 0, entities_read: 0 };
+            self.print_stats(&self.stats_line(&start, &self.snapshot()));
+        }
+        match self.progress {
+            Progress::Json => self.emit(true),
+            _ => self.bar.finish_with_message(message),
+        }
+    }
+
+    pub(crate) fn record(&self, finished: bool) -> ProgressRecord {
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let elapsed = self.start.elapsed().as_secs_f64();
+        let bytes_per_sec = if elapsed > 0.0 { bytes as f64 / elapsed } else { 0.0 };
+        let eta_secs = self.total
+            .filter(|_| bytes_per_sec > 0.0)
+            .map(|total| total.saturating_sub(bytes) as f64 / bytes_per_sec);
+        ProgressRecord {
+            bytes,
+            total_bytes: self.total,
+            entities_read: self.entities_read.load(Ordering::Relaxed),
+            entities_written: self.entities_written.load(Ordering::Relaxed),
+            bytes_per_sec,
+            elapsed_secs: elapsed,
+            eta_secs,
+            finished,
+        }
+    }
+
+    fn emit(&self, finished: bool) {
+        if let Ok(record) = serde_json::to_string(&self.record(finished)) {
+            eprintln!("{}", record);
+        }
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            at: Instant::now(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+            bytes_out: self.bytes_out.load(Ordering::Relaxed),
+            entities_read: self.entities_read.load(Ordering::Relaxed),
+        }
+    }
+
+    // the stats line for the time since `previous`, see the module documentation
+    fn stats_line(&self, previous: &Snapshot, now: &Snapshot) -> String {
+        let secs = now.at.duration_since(previous.at).as_secs_f64().max(f64::EPSILON);
+        let rate = |to: u64, from: u64| to.saturating_sub(from) as f64 / secs;
+        let eta = self.record(false).eta_secs
+            .map(|eta| HumanDuration(Duration::from_secs_f64(eta)).to_string())
+            .unwrap_or_else(|| String::from("unknown"));
+        format!("[{}] {} entities ({:.0}/s), in {:.1} MB/s, out {:.1} MB/s, {} matched, {} errors, ETA {}",
+            HumanDuration(now.at.duration_since(self.start)),
+            now.entities_read,
+            rate(now.entities_read, previous.entities_read),
+            rate(now.bytes, previous.bytes) / 1e6,
+            rate(now.bytes_out, previous.bytes_out) / 1e6,
+            self.entities_written.load(Ordering::Relaxed),
+            self.entities_failed.load(Ordering::Relaxed),
+            eta)
+    }
+
+    fn print_stats(&self, line: &str) {
+        // above the bar while it's drawn, so it isn't drawn over
+        match self.bar.is_hidden() {
+            true => eprintln!("{}", line),
+            false => self.bar.println(line),
+        }
+    }
+
+    // prints a stats line if it's been long enough since the last one
+    fn maybe_print_stats(&self) {
+        let interval = match self.stats_interval {
+            Some(interval) => interval,
+            None => return,
+        };
+        // whoever holds the lock is about to print anyway
+        if let Ok(mut last_stats) = self.last_stats.try_lock() {
+            if last_stats.at.elapsed() >= interval {
+                let now = self.snapshot();
+                self.print_stats(&self.stats_line(&last_stats, &now));
+                *last_stats = now;
+            }
+        }
+    }
+
+    // emits a JSON record if it's been long enough since the last one
+    fn maybe_emit(&self) {
+        if self.progress != Progress::Json {
+            return;
+        }
+        // whoever holds the lock is about to emit anyway
+        if let Ok(mut last_record) = self.last_record.try_lock() {
+            if last_record.elapsed() >= RECORD_INTERVAL {
+                *last_record = Instant::now();
+                self.emit(false);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_progress_record() {
+        let reporter = Reporter::new(Progress::Hidden, Some(100), "{msg}");
+        reporter.set_position(25);
+        reporter.set_position(50);
+        reporter.set_entities(10, 4);
+        let record = reporter.record(false);
+        assert_eq!(record.bytes, 50);
+        assert_eq!(record.entities_read, 10);
+        assert_eq!(record.entities_written, 4);
+        assert!(record.eta_secs.is_some());
+        assert_eq!("json".parse::<Progress>(), Ok(Progress::Json));
+        assert!("fancy".parse::<Progress>().is_err());
+    }
+
+    #[test]
+    fn test_stats_line() {
+        let reporter = Reporter::new(Progress::Hidden, None, "{msg}");
+        let previous = Snapshot { at: reporter.start, bytes: 0, bytes_out: 0, entities_read: 0 };
+        reporter.set_position(4_000_000);
+        reporter.set_entities(1000, 250);
+        reporter.set_output(2_000_000, 3);
+        let now = Snapshot { at: reporter.start + Duration::from_secs(2), ..reporter.snapshot() };
+        assert_eq!(reporter.stats_line(&previous, &now), "[2 seconds] 1000 entities (500/s), in 2.0 MB/s, out 1.0 MB/s, 250 matched, 3 errors, ETA unknown");
+    }
+}
@@ -0,0 +1,197 @@
+/*!
+ * The property reference table: every property of a dump with its datatype,
+ * labels and constraints, in one pass. Properties are scattered throughout
+ * the dump among a hundred million items, which are skipped by their id
+ * without being parsed.
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+use crate::labels::escape_tsv;
+use crate::model::{Claim, DataValue, Entity, Rank};
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+use crate::splitter;
+
+/// The property "property constraint", whose statements are a property's constraints
+pub const PROPERTY_CONSTRAINT: &str = "P2302";
+
+/// A row of the property table
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PropertyInfo {
+    pub id: String,
+    /// e.g. wikibase-item, external-id or quantity
+    pub datatype: Option<String>,
+    /// Language code to label
+    pub labels: BTreeMap<String, String>,
+    pub constraints: Vec<Constraint>,
+}
+
+/// A property constraint statement, with deprecated ones left out
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Constraint {
+    /// The item for the type of constraint, e.g. Q21502410 for "distinct values"
+    #[serde(rename = "type")]
+    pub constraint_type: String,
+    /// The constraint's parameters, as plain values (see `DataValue::simplify`) by property, with `null` for
+    /// "some value" and "no value"
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub parameters: BTreeMap<String, Vec<Value>>,
+}
+
+/// Output format of the property table
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PropertyFormat {
+    /// A `PropertyInfo` object per line
+    Ndjson,
+    /// `id<TAB>datatype<TAB>label<TAB>constraint types` rows, the label in the first language given with one (or any
+    /// other), and the constraint types comma separated
+    Tsv,
+}
+
+impl FromStr for PropertyFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "ndjson" => Ok(PropertyFormat::Ndjson),
+            "tsv" => Ok(PropertyFormat::Tsv),
+            _ => Err(format!("Invalid property table format '{}', expected ndjson or tsv", value)),
+        }
+    }
+}
+
+impl PropertyInfo {
+    /// The row for `entity` if it's a property, with labels in `languages` (all of them if empty)
+    pub fn from_entity(entity: &Entity, languages: &[String]) -> Option<Self> {
+        if entity.entity_type != "property" {
+            return None;
+        }
+        let labels = entity.labels.iter()
+            .filter(|(language, _)| languages.is_empty() || languages.contains(language))
+            .map(|(language, label)| (language.clone(), label.value.clone()))
+            .collect();
+        let constraints = entity.claims.get(PROPERTY_CONSTRAINT).into_iter().flatten()
+            .filter(|claim| claim.rank != Rank::Deprecated)
+            .filter_map(Constraint::from_claim)
+            .collect();
+        Some(PropertyInfo { id: entity.id.clone(), datatype: entity.datatype.clone(), labels, constraints })
+    }
+
+    fn tsv(&self, languages: &[String]) -> String {
+        let label = languages.iter()
+            .find_map(|language| self.labels.get(language))
+            .or_else(|| self.labels.values().next())
+            .map(String::as_str)
+            .unwrap_or("");
+        let constraint_types = self.constraints.iter().map(|constraint| constraint.constraint_type.as_str()).collect::<Vec<_>>();
+        format!("{}\t{}\t{}\t{}", self.id, self.datatype.as_deref().unwrap_or(""), escape_tsv(label), constraint_types.join(","))
+    }
+}
+
+impl Constraint {
+    fn from_claim(claim: &Claim) -> Option<Self> {
+        let constraint_type = match claim.mainsnak.datavalue.as_ref()?.simplify() {
+            Value::String(constraint_type) => constraint_type,
+            _ => return None,
+        };
+        let parameters = claim.qualifiers.iter()
+            .map(|(property, snaks)| {
+                let values = snaks.iter().map(|snak| snak.datavalue.as_ref().map(DataValue::simplify).unwrap_or(Value::Null)).collect();
+                (property.clone(), values)
+            })
+            .collect();
+        Some(Constraint { constraint_type, parameters })
+    }
+}
+
+// turns each property into its row of the table, skipping anything else
+struct PropertyExtractor {
+    languages: Vec<String>,
+    format: PropertyFormat,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl EntityFilter for PropertyExtractor {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        if !splitter::entity_id(raw).is_some_and(|id| id.starts_with('P')) {
+            return Ok(None);
+        }
+        let entity = match Entity::parse(raw) {
+            Ok(entity) => entity,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let property = match PropertyInfo::from_entity(&entity, &self.languages) {
+            Some(property) => property,
+            None => return Ok(None),
+        };
+        let row = match self.format {
+            PropertyFormat::Ndjson => serde_json::to_string(&property).expect("Property serializes"),
+            PropertyFormat::Tsv => property.tsv(&self.languages),
+        };
+        Ok(Some(Cow::Owned(row)))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+/// Writes the row of every property of `source` to `output` as `format`, in dump order, with labels in
+/// `languages` (all of them if empty)
+pub fn write_properties(source: impl Source, languages: Vec<String>, format: PropertyFormat, options: ProcessOptions, output: impl Write) -> Result<ProcessStats> {
+    let continue_on_error = options.continue_on_error;
+    Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(PropertyExtractor { languages: languages.clone(), format, continue_on_error, failures: 0 }))
+        .sink(output)
+        .options(options)
+        .build()?
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    #[test]
+    fn test_property_info() {
+        let raw = r#"{"id":"P31","type":"property","datatype":"wikibase-item","labels":{"en":{"language":"en","value":"instance of"},"fr":{"language":"fr","value":"nature de l'élément"}},"claims":{"P2302":[
+            {"mainsnak":{"snaktype":"value","property":"P2302","datavalue":{"type":"wikibase-entityid","value":{"id":"Q21510865","entity-type":"item","numeric-id":21510865}}},"rank":"normal","qualifiers":{"P2308":[{"snaktype":"value","property":"P2308","datavalue":{"type":"wikibase-entityid","value":{"id":"Q35120","entity-type":"item","numeric-id":35120}}},{"snaktype":"somevalue","property":"P2308"}]}},
+            {"mainsnak":{"snaktype":"value","property":"P2302","datavalue":{"type":"wikibase-entityid","value":{"id":"Q53869507","entity-type":"item","numeric-id":53869507}}},"rank":"deprecated"}
+        ]}}"#;
+        let property = PropertyInfo::from_entity(&Entity::parse(raw).unwrap(), &[String::from("en")]).unwrap();
+        assert_eq!(property.datatype.as_deref(), Some("wikibase-item"));
+        assert_eq!(property.labels.len(), 1);
+        assert_eq!(property.constraints.len(), 1);
+        assert_eq!(property.constraints[0].constraint_type, "Q21510865");
+        assert_eq!(property.constraints[0].parameters["P2308"], vec![Value::from("Q35120"), Value::Null]);
+        assert_eq!(property.tsv(&[String::from("de")]), "P31\twikibase-item\tinstance of\tQ21510865");
+    }
+
+    #[test]
+    fn test_write_properties() {
+        let mut output = Vec::new();
+        let stats = write_properties(FileSource::new("./tests/test-data.json.bz2"), Vec::new(), PropertyFormat::Ndjson, ProcessOptions::default(), &mut output).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        let output = String::from_utf8(output).unwrap();
+        assert_eq!(output.lines().count(), 1);
+        let property: Value = serde_json::from_str(output.lines().next().unwrap()).unwrap();
+        assert_eq!(property["id"], "P1");
+    }
+}
@@ -0,0 +1,171 @@
+/*!
+ * Inputs for QLever's index builder, for self-hosting SPARQL over a filtered
+ * subset of Wikidata: the entities as gzipped Turtle (see `crate::rdf`), the
+ * settings QLever builds Wikidata indexes with, and a `Qleverfile` tying them
+ * together for the `qlever` command line tool:
+ *
+ * ```text
+ * wikidump-process filter --input latest-all.json.bz2 --qlever ./humans ...
+ * cd humans && qlever index && qlever start
+ * ```
+ *
+ * For a directory `humans`, the files are `humans.ttl.gz`,
+ * `humans.settings.json` and `Qleverfile`. The settings and `Qleverfile` are
+ * only written once the Turtle is complete, so an interrupted run doesn't
+ * leave anything to index. The Turtle has all its prefixes at the start, which
+ * QLever's parallel parser (`ascii-prefixes-only`) needs.
+ */
+
+use std::fs::{self, File};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use flate2::write::GzEncoder;
+use serde_json::json;
+use crate::error::{ProcessError, Result};
+use crate::rdf;
+use crate::sink::Sink;
+
+/// Name of the configuration file of the `qlever` tool
+pub const QLEVERFILE: &str = "Qleverfile";
+
+/// The settings of QLever's index builder, as QLever's own Wikidata configuration has them
+pub fn settings() -> serde_json::Value {
+    json!({
+        "languages-internal": [],
+        "prefixes-external": [""],
+        "locale": { "language": "en", "country": "US", "ignore-punctuation": true },
+        "ascii-prefixes-only": true,
+        "num-triples-per-batch": 5000000,
+    })
+}
+
+/// The `Qleverfile` indexing `<name>.ttl.gz` and serving it, with the Wikidata configuration of QLever's UI
+pub fn qleverfile(name: &str) -> String {
+    format!("# Indexes and serves {name}.ttl.gz, written by wikidump-process: qlever index, then qlever start
+
+[data]
+NAME = {name}
+DESCRIPTION = Wikidata entities filtered by wikidump-process
+
+[index]
+INPUT_FILES = ${{data:NAME}}.ttl.gz
+CAT_INPUT_FILES = zcat ${{INPUT_FILES}}
+SETTINGS_JSON = {settings}
+
+[server]
+PORT = 7001
+ACCESS_TOKEN = ${{data:NAME}}
+MEMORY_FOR_QUERIES = 10G
+TIMEOUT = 300s
+
+[runtime]
+SYSTEM = docker
+IMAGE = docker.io/adfreiburg/qlever:latest
+
+[ui]
+UI_CONFIG = wikidata
+", name = name, settings = settings())
+}
+
+/// Writes each output, which has to be a whole entity, as Turtle for QLever to index, see the module documentation
+pub struct QleverSink {
+    directory: PathBuf,
+    name: String,
+    turtle_file: Option<GzEncoder<BufWriter<File>>>,
+    turtle: String,
+}
+
+impl QleverSink {
+    /// Writes the files for an index called `name` into `directory`, creating it if need be. Files of a previous run
+    /// there are replaced when `overwrite` is set, otherwise this fails.
+    pub fn create(directory: &Path, name: &str, overwrite: bool) -> Result<Self> {
+        let paths = [Self::turtle_path(directory, name), Self::settings_path(directory, name), directory.join(QLEVERFILE)];
+        for path in paths.iter().filter(|path| path.exists()) {
+            if !overwrite {
+                return Err(ProcessError::OutputExists(path.clone()));
+            }
+            fs::remove_file(path).map_err(|source| ProcessError::CreateOutput { path: path.clone(), source })?;
+        }
+        fs::create_dir_all(directory).map_err(|source| ProcessError::CreateOutput { path: directory.to_path_buf(), source })?;
+        let path = &paths[0];
+        let file = File::create(path).map_err(|source| ProcessError::CreateOutput { path: path.clone(), source })?;
+        let mut turtle_file = GzEncoder::new(BufWriter::new(file), flate2::Compression::default());
+        turtle_file.write_all(rdf::PREFIXES.as_bytes()).map_err(ProcessError::Write)?;
+        Ok(QleverSink { directory: directory.to_path_buf(), name: name.to_string(), turtle_file: Some(turtle_file), turtle: String::new() })
+    }
+
+    /// The Turtle of the index `name` in `directory`
+    pub fn turtle_path(directory: &Path, name: &str) -> PathBuf {
+        directory.join(format!("{}.ttl.gz", name))
+    }
+
+    /// The settings of the index `name` in `directory`
+    pub fn settings_path(directory: &Path, name: &str) -> PathBuf {
+        directory.join(format!("{}.settings.json", name))
+    }
+
+    fn write_file(path: PathBuf, contents: &str) -> Result<()> {
+        fs::write(&path, contents).map_err(|source| ProcessError::CreateOutput { path, source })
+    }
+}
+
+impl Sink for QleverSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        self.turtle.clear();
+        rdf::write_output(&mut self.turtle, output)?;
+        match &mut self.turtle_file {
+            Some(turtle_file) => turtle_file.write_all(self.turtle.as_bytes()).map_err(ProcessError::Write),
+            None => Err(ProcessError::Turtle(format!("{}.ttl.gz is already complete", self.name))),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.turtle_file {
+            Some(turtle_file) => turtle_file.flush().map_err(ProcessError::Write),
+            None => Ok(()),
+        }
+    }
+
+    /// Completes the Turtle, then writes the settings and `Qleverfile`
+    fn finalize(&mut self) -> Result<()> {
+        if let Some(turtle_file) = self.turtle_file.take() {
+            turtle_file.finish().and_then(|mut file| file.flush()).map_err(ProcessError::Write)?;
+        }
+        Self::write_file(Self::settings_path(&self.directory, &self.name), &format!("{:#}\n", settings()))?;
+        Self::write_file(self.directory.join(QLEVERFILE), &qleverfile(&self.name))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use flate2::read::GzDecoder;
+    use crate::Pipeline;
+
+    #[test]
+    fn test_qlever_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let sink = QleverSink::create(directory.path(), "cities", false).unwrap();
+        Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .filter(r#"select(.id == "Q60")"#)
+            .entity_sink(sink)
+            .build().unwrap()
+            .run().unwrap();
+
+        let mut turtle = String::new();
+        GzDecoder::new(File::open(directory.path().join("cities.ttl.gz")).unwrap()).read_to_string(&mut turtle).unwrap();
+        assert!(turtle.starts_with(rdf::PREFIXES));
+        assert!(turtle.contains("wd:Q60 a wikibase:Item"));
+        let settings: serde_json::Value = serde_json::from_slice(&fs::read(directory.path().join("cities.settings.json")).unwrap()).unwrap();
+        assert_eq!(settings["ascii-prefixes-only"], true);
+        let qleverfile = fs::read_to_string(directory.path().join(QLEVERFILE)).unwrap();
+        assert!(qleverfile.contains("NAME = cities\n"));
+        assert!(qleverfile.contains("INPUT_FILES = ${data:NAME}.ttl.gz\n"));
+
+        assert!(matches!(QleverSink::create(directory.path(), "cities", false), Err(ProcessError::OutputExists(_))));
+        QleverSink::create(directory.path(), "cities", true).unwrap();
+        assert!(!directory.path().join(QLEVERFILE).exists());
+    }
+}
@@ -0,0 +1,352 @@
+/*!
+ * Data quality reports: the entities of a dump with gaps or suspicious
+ * statements, as CSV files of ids for editors and curators to work through.
+ * Only the labels and statements of each entity are parsed.
+ *
+ * Each check writes `<check>.csv` with a header row:
+ *
+ * - `missing-label`: items and properties without a label in one of the
+ *   languages asked for, as `id,language` rows
+ * - `missing-p31`: items without an "instance of" (P31) statement, deprecated
+ *   ones aside, as `id` rows
+ * - `deprecated-only`: properties of an entity whose statements are all
+ *   deprecated, as `id,property` rows
+ * - `single-value`: properties with a single-value constraint used more than
+ *   once by an entity, as `id,property,count` rows. The constraints come from
+ *   the table written by `properties`
+ * - `self-reference`: statements whose value is the entity itself, as
+ *   `id,property` rows
+ */
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+use crate::classes::INSTANCE_OF;
+use crate::edges::Statement;
+use crate::error::{ProcessError, Result};
+use crate::filter::{self, EntityFilter};
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::properties::PropertyInfo;
+use crate::sink::{self, Sink};
+use crate::source::Source;
+
+/// The item for the "single-value constraint" type
+pub const SINGLE_VALUE_CONSTRAINT: &str = "Q19474404";
+
+/// A check of a quality report, see the module documentation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Check {
+    MissingLabel,
+    MissingP31,
+    DeprecatedOnly,
+    SingleValue,
+    SelfReference,
+}
+
+impl Check {
+    pub const ALL: [Check; 5] = [Check::MissingLabel, Check::MissingP31, Check::DeprecatedOnly, Check::SingleValue, Check::SelfReference];
+
+    /// Name of the check, which is also the stem of its file
+    pub fn name(self) -> &'static str {
+        match self {
+            Check::MissingLabel => "missing-label",
+            Check::MissingP31 => "missing-p31",
+            Check::DeprecatedOnly => "deprecated-only",
+            Check::SingleValue => "single-value",
+            Check::SelfReference => "self-reference",
+        }
+    }
+
+    /// Header row of the check's file
+    pub fn header(self) -> &'static str {
+        match self {
+            Check::MissingLabel => "id,language",
+            Check::MissingP31 => "id",
+            Check::DeprecatedOnly | Check::SelfReference => "id,property",
+            Check::SingleValue => "id,property,count",
+        }
+    }
+
+    /// Path of the check's file in `directory`
+    pub fn path(self, directory: &Path) -> PathBuf {
+        directory.join(format!("{}.csv", self.name()))
+    }
+}
+
+impl FromStr for Check {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        Check::ALL.into_iter().find(|check| check.name() == value).ok_or_else(|| {
+            format!("Invalid check '{}', expected missing-label, missing-p31, deprecated-only, single-value or self-reference", value)
+        })
+    }
+}
+
+/// What a quality report checks for
+#[derive(Debug, Clone, Default)]
+pub struct QualityOptions {
+    /// Languages every item and property should have a label in, for `Check::MissingLabel`
+    pub languages: Vec<String>,
+    pub checks: Vec<Check>,
+    /// Properties with a single-value constraint, for `Check::SingleValue`
+    pub single_value: HashSet<String>,
+}
+
+/// The properties with a single-value constraint in a property table written by `properties` as ndjson
+pub fn single_value_properties(path: &Path) -> Result<HashSet<String>> {
+    let open_error = |source| ProcessError::OpenInput { path: path.to_path_buf(), source };
+    let mut properties = HashSet::new();
+    for (i, line) in BufReader::new(File::open(path).map_err(open_error)?).lines().enumerate() {
+        let line = line.map_err(ProcessError::Read)?;
+        if line.is_empty() {
+            continue;
+        }
+        let property: PropertyInfo = serde_json::from_str(&line).map_err(|error| {
+            let message = format!("line {} of {:?} is not a property: {}", i + 1, path, error);
+            ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, message))
+        })?;
+        if property.constraints.iter().any(|constraint| constraint.constraint_type == SINGLE_VALUE_CONSTRAINT) {
+            properties.insert(property.id);
+        }
+    }
+    Ok(properties)
+}
+
+// the parts of an entity which are checked
+#[derive(Deserialize)]
+struct Outline<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(default, rename = "type", borrow)]
+    entity_type: Cow<'a, str>,
+    #[serde(default, borrow)]
+    labels: BTreeMap<Cow<'a, str>, IgnoredAny>,
+    #[serde(default, borrow)]
+    claims: BTreeMap<Cow<'a, str>, Vec<Statement<'a>>>,
+}
+
+// turns each entity into a `<check>\t<row>` line per problem found
+struct QualityChecker {
+    options: QualityOptions,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl QualityChecker {
+    fn rows(&self, outline: &Outline) -> String {
+        let mut rows = String::new();
+        let mut row = |check: Check, columns: &[&str]| {
+            if !rows.is_empty() {
+                rows.push('\n');
+            }
+            rows.push_str(check.name());
+            rows.push('\t');
+            rows.push_str(&outline.id);
+            for column in columns {
+                rows.push(',');
+                rows.push_str(column);
+            }
+        };
+        let labelled = matches!(outline.entity_type.as_ref(), "item" | "property");
+        for check in &self.options.checks {
+            match check {
+                Check::MissingLabel if labelled => {
+                    for language in self.options.languages.iter().filter(|language| !outline.labels.contains_key(language.as_str())) {
+                        row(Check::MissingLabel, &[language]);
+                    }
+                }
+                Check::MissingP31 if outline.entity_type == "item" => {
+                    let statements = outline.claims.get(INSTANCE_OF).into_iter().flatten();
+                    if statements.filter(|statement| !statement.is_deprecated()).count() == 0 {
+                        row(Check::MissingP31, &[]);
+                    }
+                }
+                Check::DeprecatedOnly => {
+                    for (property, statements) in &outline.claims {
+                        if !statements.is_empty() && statements.iter().all(Statement::is_deprecated) {
+                            row(Check::DeprecatedOnly, &[property]);
+                        }
+                    }
+                }
+                Check::SingleValue => {
+                    for (property, statements) in outline.claims.iter().filter(|(property, _)| self.options.single_value.contains(property.as_ref())) {
+                        let count = statements.iter().filter(|statement| !statement.is_deprecated()).count();
+                        if count > 1 {
+                            row(Check::SingleValue, &[property, &count.to_string()]);
+                        }
+                    }
+                }
+                Check::SelfReference => {
+                    for (property, statements) in &outline.claims {
+                        let referring = statements.iter().any(|statement| statement.mainsnak.item().as_deref() == Some(outline.id.as_ref()));
+                        if referring {
+                            row(Check::SelfReference, &[property]);
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        rows
+    }
+}
+
+impl EntityFilter for QualityChecker {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let rows = self.rows(&outline);
+        Ok(Some(rows).filter(|rows| !rows.is_empty()).map(Cow::Owned))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+/// The files of a quality report, one per check, which rows are routed to
+pub struct QualityReport {
+    files: BTreeMap<Check, (BufWriter<Box<dyn Write>>, usize)>,
+}
+
+impl QualityReport {
+    /// Creates the file of each of `checks` in `directory` (and the directory if needed), failing if one exists
+    /// unless `force_overwrite` is set
+    pub fn create(directory: &Path, checks: &[Check], force_overwrite: bool) -> Result<Self> {
+        fs::create_dir_all(directory).map_err(|source| ProcessError::CreateOutput { path: directory.to_path_buf(), source })?;
+        let mut files = BTreeMap::new();
+        for check in checks {
+            let mut file = BufWriter::new(sink::open_output(Some(&check.path(directory)), force_overwrite)?);
+            writeln!(file, "{}", check.header()).map_err(ProcessError::Write)?;
+            files.insert(*check, (file, 0));
+        }
+        Ok(QualityReport { files })
+    }
+
+    /// Number of rows written for each check
+    pub fn counts(&self) -> BTreeMap<Check, usize> {
+        self.files.iter().map(|(check, (_, count))| (*check, *count)).collect()
+    }
+}
+
+impl Sink for QualityReport {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        for line in output.lines() {
+            let (check, row) = line.split_once('\t').expect("quality rows start with their check");
+            let check = check.parse::<Check>().expect("quality rows start with their check");
+            if let Some((file, count)) = self.files.get_mut(&check) {
+                writeln!(file, "{}", row).map_err(ProcessError::Write)?;
+                *count += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        for (file, _) in self.files.values_mut() {
+            file.flush().map_err(ProcessError::Write)?;
+        }
+        Ok(())
+    }
+}
+
+/// Runs the checks of `quality` over every entity of `source`, writing the problems found to `report`. Rows come
+/// in dump order within each file.
+pub fn write_quality_report(source: impl Source, quality: QualityOptions, options: ProcessOptions, report: &mut QualityReport) -> Result<ProcessStats> {
+    let continue_on_error = options.continue_on_error;
+    Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(QualityChecker { options: quality.clone(), continue_on_error, failures: 0 }))
+        .entity_sink(report)
+        .options(options)
+        .build()?
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item","labels":{"en":{"language":"en","value":"Douglas Adams"}},"claims":{
+        "P19":[{"mainsnak":{"snaktype":"value","property":"P19","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q350"}}},"rank":"normal"},
+               {"mainsnak":{"snaktype":"value","property":"P19","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q84"}}},"rank":"preferred"}],
+        "P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q5"}}},"rank":"deprecated"}],
+        "P735":[{"mainsnak":{"snaktype":"value","property":"P735","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q42"}}},"rank":"normal"}]
+    }}"#;
+
+    fn rows(checks: &[Check]) -> String {
+        let options = QualityOptions {
+            languages: vec![String::from("en"), String::from("fr")],
+            checks: checks.to_vec(),
+            single_value: HashSet::from([String::from("P19"), String::from("P735")]),
+        };
+        let checker = QualityChecker { options, continue_on_error: false, failures: 0 };
+        checker.rows(&serde_json::from_str(ENTITY).unwrap())
+    }
+
+    #[test]
+    fn test_check() {
+        for check in Check::ALL {
+            assert_eq!(check.name().parse::<Check>(), Ok(check));
+        }
+        assert!("missing".parse::<Check>().is_err());
+        assert_eq!(Check::SingleValue.path(Path::new("report")), Path::new("report/single-value.csv"));
+    }
+
+    #[test]
+    fn test_quality_rows() {
+        assert_eq!(rows(&[Check::MissingLabel]), "missing-label\tQ42,fr");
+        assert_eq!(rows(&[Check::MissingP31]), "missing-p31\tQ42");
+        assert_eq!(rows(&[Check::DeprecatedOnly]), "deprecated-only\tQ42,P31");
+        assert_eq!(rows(&[Check::SingleValue]), "single-value\tQ42,P19,2");
+        assert_eq!(rows(&[Check::SelfReference]), "self-reference\tQ42,P735");
+        assert_eq!(rows(&Check::ALL).lines().count(), 5);
+    }
+
+    #[test]
+    fn test_single_value_properties() {
+        let mut table = tempfile::NamedTempFile::new().unwrap();
+        writeln!(table, r#"{{"id":"P19","datatype":"wikibase-item","labels":{{}},"constraints":[{{"type":"{}"}}]}}"#, SINGLE_VALUE_CONSTRAINT).unwrap();
+        writeln!(table, r#"{{"id":"P735","datatype":"wikibase-item","labels":{{}},"constraints":[{{"type":"Q21502410"}}]}}"#).unwrap();
+        assert_eq!(single_value_properties(table.path()).unwrap(), HashSet::from([String::from("P19")]));
+
+        writeln!(table, "P31\twikibase-item").unwrap();
+        assert!(single_value_properties(table.path()).is_err());
+    }
+
+    #[test]
+    fn test_write_quality_report() {
+        let directory = tempfile::tempdir().unwrap();
+        let checks = vec![Check::MissingLabel, Check::MissingP31];
+        let mut report = QualityReport::create(directory.path(), &checks, false).unwrap();
+        let quality = QualityOptions { languages: vec![String::from("en")], checks, ..QualityOptions::default() };
+        let stats = write_quality_report(FileSource::new("./tests/test-data.json.bz2"), quality, ProcessOptions::default(), &mut report).unwrap();
+        assert_eq!(stats.entities_read, 8);
+
+        let missing = fs::read_to_string(Check::MissingLabel.path(directory.path())).unwrap();
+        let mut lines = missing.lines();
+        assert_eq!(lines.next(), Some("id,language"));
+        assert!(lines.any(|row| row == "Q3,en"), "{}", missing);
+        assert_eq!(report.counts()[&Check::MissingLabel], missing.lines().count() - 1);
+        assert!(Check::MissingP31.path(directory.path()).exists());
+        assert!(!Check::SelfReference.path(directory.path()).exists());
+
+        // a report is only overwritten when forced
+        assert!(matches!(QualityReport::create(directory.path(), &[Check::MissingP31], false), Err(ProcessError::OutputExists(_))));
+        assert!(QualityReport::create(directory.path(), &[Check::MissingP31], true).is_ok());
+    }
+}
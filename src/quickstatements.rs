@@ -0,0 +1,206 @@
+/*!
+ * QuickStatements commands recreating the statements of entities, so bots
+ * can import corrected or derived statements into Wikidata, or into another
+ * Wikibase. See https://www.wikidata.org/wiki/Help:QuickStatements
+ *
+ * V1 commands are tab separated, one statement per line, with its qualifiers
+ * and references (as `S` properties) on the same line. CSV has a column per
+ * property under a `qid,P31,...` header and a row per statement, with only
+ * the main value of each. Deprecated statements are left out, as are values
+ * QuickStatements has no syntax for: Julian calendar dates, coordinates on
+ * other globes than Earth's and unknown datatypes.
+ */
+
+use std::str::FromStr;
+use log::debug;
+use crate::model::{Claim, DataValue, Entity, Rank, Snak};
+
+/// Calendar model of proleptic Gregorian dates, the only one QuickStatements writes
+pub const GREGORIAN: &str = "http://www.wikidata.org/entity/Q1985727";
+
+/// Globe of coordinates on Earth, the only one QuickStatements writes
+pub const EARTH: &str = "http://www.wikidata.org/entity/Q2";
+
+/// Flavour of QuickStatements commands
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuickStatementsFormat {
+    V1,
+    Csv,
+}
+
+impl FromStr for QuickStatementsFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "v1" => Ok(QuickStatementsFormat::V1),
+            "csv" => Ok(QuickStatementsFormat::Csv),
+            _ => Err(format!("Invalid QuickStatements format '{}', expected v1 or csv", value)),
+        }
+    }
+}
+
+// fields are tab separated and a string is whatever is between the quotes of its field, so quotes can stay as
+// they are but tabs and line breaks can't
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace(['\t', '\n', '\r'], " "))
+}
+
+/// The value of `snak` in QuickStatements syntax, e.g. `Q5`, `"text"`, `en:"text"`, `+2001-01-15T00:00:00Z/11`,
+/// `1.5U11573` or `@43.26/10.92`, or `None` if it has no syntax for it
+pub fn value(snak: &Snak) -> Option<String> {
+    match snak.snaktype.as_str() {
+        "somevalue" => return Some(String::from("somevalue")),
+        "novalue" => return Some(String::from("novalue")),
+        _ => {}
+    }
+    let value = match snak.datavalue.as_ref()? {
+        DataValue::String(value) => quote(value),
+        DataValue::EntityId(value) => value.id()?,
+        DataValue::Time(value) if value.calendarmodel == GREGORIAN => format!("{}/{}", value.time, value.precision),
+        DataValue::Quantity(value) => {
+            let amount = value.amount.trim_start_matches('+');
+            match value.unit.rsplit_once("/entity/Q") {
+                Some((_, unit)) => format!("{}U{}", amount, unit),
+                None => amount.to_string(),
+            }
+        }
+        DataValue::GlobeCoordinate(value) if value.globe == EARTH => format!("@{}/{}", value.latitude, value.longitude),
+        DataValue::MonolingualText(value) => format!("{}:{}", value.language, quote(&value.text)),
+        _ => return None,
+    };
+    Some(value)
+}
+
+// the statements of `entity` which end up as commands, by property
+fn statements<'a>(entity: &'a Entity, properties: &'a [String]) -> impl Iterator<Item = (&'a String, &'a Claim)> {
+    entity.claims.iter()
+        .filter(move |(property, _)| properties.is_empty() || properties.contains(property))
+        .flat_map(|(property, claims)| claims.iter().map(move |claim| (property, claim)))
+        .filter(|(_, claim)| claim.rank != Rank::Deprecated)
+}
+
+/// A V1 command per statement of `entity` for `properties` (all of them if empty)
+pub fn v1_commands(entity: &Entity, properties: &[String]) -> Vec<String> {
+    let mut commands = Vec::new();
+    for (property, claim) in statements(entity, properties) {
+        let mut command = match value(&claim.mainsnak) {
+            Some(value) => format!("{}\t{}\t{}", entity.id, property, value),
+            None => {
+                debug!("No QuickStatements syntax for a {} value of {}", property, entity.id);
+                continue;
+            }
+        };
+        for (qualifier, snaks) in &claim.qualifiers {
+            for value in snaks.iter().filter_map(value) {
+                command.push_str(&format!("\t{}\t{}", qualifier, value));
+            }
+        }
+        for reference in &claim.references {
+            for (source, snaks) in &reference.snaks {
+                for value in snaks.iter().filter_map(value) {
+                    command.push_str(&format!("\tS{}\t{}", source.trim_start_matches('P'), value));
+                }
+            }
+        }
+        commands.push(command);
+    }
+    commands
+}
+
+fn csv_field(field: &str) -> String {
+    match field.contains(['"', ',', '\n', '\r']) {
+        true => format!("\"{}\"", field.replace('"', "\"\"")),
+        false => field.to_string(),
+    }
+}
+
+/// The header of CSV commands for `properties`
+pub fn csv_header(properties: &[String]) -> String {
+    format!("qid,{}", properties.join(","))
+}
+
+/// A CSV row per statement of `entity` for `properties`, in the columns of `csv_header`
+pub fn csv_rows(entity: &Entity, properties: &[String]) -> Vec<String> {
+    statements(entity, properties)
+        .filter_map(|(property, claim)| {
+            let value = value(&claim.mainsnak)?;
+            let columns = properties.iter()
+                .map(|column| match column == property {
+                    true => csv_field(&value),
+                    false => String::new(),
+                })
+                .collect::<Vec<_>>();
+            Some(format!("{},{}", entity.id, columns.join(",")))
+        })
+        .collect()
+}
+
+/// Replaces an output which is a whole entity with its commands, one per line, or drops it if it has none. Other
+/// outputs (e.g. just an id) can't be turned into commands and are dropped too.
+pub fn quickstatements_output(output: String, format: QuickStatementsFormat, properties: &[String]) -> Option<String> {
+    let entity = match Entity::parse(&output) {
+        Ok(entity) => entity,
+        Err(error) => {
+            debug!("Not an entity, so no QuickStatements ({}): {}", error, output);
+            return None;
+        }
+    };
+    let commands = match format {
+        QuickStatementsFormat::V1 => v1_commands(&entity, properties),
+        QuickStatementsFormat::Csv => csv_rows(&entity, properties),
+    };
+    Some(commands.join("\n")).filter(|commands| !commands.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item","claims":{
+        "P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":5}}},"type":"statement","rank":"normal",
+            "references":[{"snaks":{"P248":[{"snaktype":"value","property":"P248","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q54919"}}}]}}]}],
+        "P569":[{"mainsnak":{"snaktype":"value","property":"P569","datavalue":{"type":"time","value":{"time":"+1952-03-11T00:00:00Z","timezone":0,"before":0,"after":0,"precision":11,"calendarmodel":"http://www.wikidata.org/entity/Q1985727"}}},"type":"statement","rank":"normal"},
+                {"mainsnak":{"snaktype":"value","property":"P569","datavalue":{"type":"time","value":{"time":"+1952-02-27T00:00:00Z","timezone":0,"before":0,"after":0,"precision":11,"calendarmodel":"http://www.wikidata.org/entity/Q1985786"}}},"type":"statement","rank":"normal"}],
+        "P2048":[{"mainsnak":{"snaktype":"value","property":"P2048","datavalue":{"type":"quantity","value":{"amount":"+1.96","unit":"http://www.wikidata.org/entity/Q11573"}}},"type":"statement","rank":"normal",
+            "qualifiers":{"P585":[{"snaktype":"somevalue","property":"P585"}]}}],
+        "P1477":[{"mainsnak":{"snaktype":"value","property":"P1477","datavalue":{"type":"monolingualtext","value":{"text":"Douglas \"Noel\" Adams","language":"en"}}},"type":"statement","rank":"normal"}],
+        "P625":[{"mainsnak":{"snaktype":"value","property":"P625","datavalue":{"type":"globecoordinate","value":{"latitude":51.5,"longitude":-0.1,"globe":"http://www.wikidata.org/entity/Q2"}}},"type":"statement","rank":"deprecated"}]
+    }}"#;
+
+    fn properties(properties: &[&str]) -> Vec<String> {
+        properties.iter().map(|property| property.to_string()).collect()
+    }
+
+    #[test]
+    fn test_v1_commands() {
+        let entity = Entity::parse(ENTITY).unwrap();
+        assert_eq!(v1_commands(&entity, &[]), vec![
+            "Q42\tP1477\ten:\"Douglas \"Noel\" Adams\"",
+            "Q42\tP2048\t1.96U11573\tP585\tsomevalue",
+            "Q42\tP31\tQ5\tS248\tQ54919",
+            "Q42\tP569\t+1952-03-11T00:00:00Z/11",
+        ]);
+        assert_eq!(v1_commands(&entity, &properties(&["P31"])).len(), 1);
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let entity = Entity::parse(ENTITY).unwrap();
+        let properties = properties(&["P31", "P1477"]);
+        assert_eq!(csv_header(&properties), "qid,P31,P1477");
+        assert_eq!(csv_rows(&entity, &properties), vec![
+            "Q42,,\"en:\"\"Douglas \"\"Noel\"\" Adams\"\"\"",
+            "Q42,Q5,",
+        ]);
+    }
+
+    #[test]
+    fn test_quickstatements_output() {
+        let output = quickstatements_output(ENTITY.to_string(), QuickStatementsFormat::V1, &properties(&["P569"]));
+        assert_eq!(output.as_deref(), Some("Q42\tP569\t+1952-03-11T00:00:00Z/11"));
+        assert_eq!(quickstatements_output(ENTITY.to_string(), QuickStatementsFormat::V1, &properties(&["P625"])), None);
+        assert_eq!(quickstatements_output(String::from("\"Q42\""), QuickStatementsFormat::V1, &[]), None);
+        assert_eq!("csv".parse(), Ok(QuickStatementsFormat::Csv));
+    }
+}
@@ -0,0 +1,294 @@
+/*!
+ * Entities as RDF in Turtle, in the shape the Wikidata Query Service has them
+ * after munging, so SPARQL queries written for query.wikidata.org run against
+ * a self-hosted triple store loaded with a filtered subset. See
+ * https://www.mediawiki.org/wiki/Wikibase/Indexing/RDF_Dump_Format
+ *
+ * Each entity gets:
+ *
+ * - its type, `wikibase:Item` or `wikibase:Property`
+ * - its labels, descriptions and aliases, as `rdfs:label`,
+ *   `schema:description` and `skos:altLabel`
+ * - a `wdt:` triple for each value of its best ranked statements (preferred if
+ *   it has any for a property, otherwise normal), as the service's truthy
+ *   triples
+ * - a `p:` statement node for each of its non-deprecated statements, with its
+ *   `ps:` value, `pq:` qualifiers and `wikibase:rank`
+ * - an article for each sitelink with a URL
+ *
+ * References, unknown and no values, and the normalized (e.g. unit converted)
+ * values of the full dumps are left out. Values are items as `wd:` IRIs, URLs
+ * and Commons media as IRIs, times as `xsd:dateTime` with months and days of 0
+ * made 1 as the munger does, quantities as `xsd:decimal`, coordinates as
+ * `geo:wktLiteral` points and anything else as strings. Every chunk of Turtle
+ * written has to start with `PREFIXES`.
+ */
+
+use std::fmt::Write;
+use crate::error::{ProcessError, Result};
+use crate::model::{DataValue, Entity, Rank, Snak};
+use crate::quickstatements::EARTH;
+
+/// Prefixes of the triples written by `write_turtle`
+pub const PREFIXES: &str = "@prefix wikibase: <http://wikiba.se/ontology#> .
+@prefix wd: <http://www.wikidata.org/entity/> .
+@prefix wds: <http://www.wikidata.org/entity/statement/> .
+@prefix wdt: <http://www.wikidata.org/prop/direct/> .
+@prefix p: <http://www.wikidata.org/prop/> .
+@prefix ps: <http://www.wikidata.org/prop/statement/> .
+@prefix pq: <http://www.wikidata.org/prop/qualifier/> .
+@prefix rdfs: <http://www.w3.org/2000/01/rdf-schema#> .
+@prefix schema: <http://schema.org/> .
+@prefix skos: <http://www.w3.org/2004/02/skos/core#> .
+@prefix xsd: <http://www.w3.org/2001/XMLSchema#> .
+@prefix geo: <http://www.opengis.net/ont/geosparql#> .
+
+";
+
+// where Commons media and data values are
+const COMMONS_FILE_PATH: &str = "http://commons.wikimedia.org/wiki/Special:FilePath/";
+const COMMONS_DATA: &str = "http://commons.wikimedia.org/data/main/";
+
+/// `value` as a Turtle string literal
+pub fn literal(value: &str) -> String {
+    let mut literal = String::with_capacity(value.len() + 2);
+    literal.push('"');
+    for character in value.chars() {
+        match character {
+            '\\' => literal.push_str("\\\\"),
+            '"' => literal.push_str("\\\""),
+            '\n' => literal.push_str("\\n"),
+            '\r' => literal.push_str("\\r"),
+            '\t' => literal.push_str("\\t"),
+            character => literal.push(character),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// `value` as a Turtle IRI, percent-encoding the characters IRIs can't have
+pub fn iri(value: &str) -> String {
+    let mut iri = String::with_capacity(value.len() + 2);
+    iri.push('<');
+    for character in value.chars() {
+        match character {
+            '\u{0}'..=' ' | '<' | '>' | '"' | '{' | '}' | '|' | '^' | '`' | '\\' => {
+                let _ = write!(iri, "%{:02X}", character as u32);
+            }
+            character => iri.push(character),
+        }
+    }
+    iri.push('>');
+    iri
+}
+
+// whether `tag` can be a language tag in Turtle, which some Wikimedia language codes can't
+fn is_language_tag(tag: &str) -> bool {
+    let mut parts = tag.split('-');
+    let first = parts.next().unwrap_or_default();
+    !first.is_empty() && first.chars().all(|character| character.is_ascii_alphabetic())
+        && parts.all(|part| !part.is_empty() && part.chars().all(|character| character.is_ascii_alphanumeric()))
+}
+
+// `text` as a literal in `language`, or a plain one if the language can't be a tag
+fn language_literal(text: &str, language: &str) -> String {
+    match is_language_tag(language) {
+        true => format!("{}@{}", literal(text), language),
+        false => literal(text),
+    }
+}
+
+// a Wikibase time, e.g. +1952-03-00T00:00:00Z, as an xsd:dateTime, e.g. 1952-03-01T00:00:00Z
+fn date_time(time: &str) -> String {
+    let time = time.strip_prefix('+').unwrap_or(time);
+    let (date, clock) = time.split_once('T').unwrap_or((time, "00:00:00Z"));
+    let (sign, date) = match date.strip_prefix('-') {
+        Some(date) => ("-", date),
+        None => ("", date),
+    };
+    let date = date.splitn(3, '-')
+        .enumerate()
+        .map(|(index, part)| if index > 0 && part == "00" { "01" } else { part })
+        .collect::<Vec<_>>()
+        .join("-");
+    format!("{}{}T{}", sign, date, clock)
+}
+
+/// The RDF term of the value of `snak`, if it has one, see the module documentation
+pub fn value_term(snak: &Snak) -> Option<String> {
+    let term = match snak.datavalue.as_ref()? {
+        DataValue::EntityId(value) => format!("wd:{}", value.id()?),
+        DataValue::String(value) => match snak.datatype.as_deref() {
+            Some("url") => iri(value),
+            Some("commonsMedia") => iri(&format!("{}{}", COMMONS_FILE_PATH, value.replace(' ', "_"))),
+            Some("geo-shape" | "tabular-data") => iri(&format!("{}{}", COMMONS_DATA, value.replace(' ', "_"))),
+            _ => literal(value),
+        },
+        DataValue::Time(value) => format!("{}^^xsd:dateTime", literal(&date_time(&value.time))),
+        DataValue::Quantity(value) => format!("{}^^xsd:decimal", literal(value.amount.strip_prefix('+').unwrap_or(&value.amount))),
+        DataValue::GlobeCoordinate(value) if value.globe == EARTH => {
+            format!("{}^^geo:wktLiteral", literal(&format!("Point({} {})", value.longitude, value.latitude)))
+        }
+        DataValue::GlobeCoordinate(value) => {
+            format!("{}^^geo:wktLiteral", literal(&format!("<{}> Point({} {})", value.globe, value.longitude, value.latitude)))
+        }
+        DataValue::MonolingualText(value) => language_literal(&value.text, &value.language),
+        DataValue::Unknown { .. } => return None,
+    };
+    Some(term)
+}
+
+// writes `subject` with each of its predicates and objects, if it has any
+fn write_subject(turtle: &mut String, subject: &str, triples: &[(String, String)]) {
+    for (index, (predicate, object)) in triples.iter().enumerate() {
+        match index {
+            0 => {
+                let _ = write!(turtle, "{} {} {}", subject, predicate, object);
+            }
+            _ => {
+                let _ = write!(turtle, " ;\n\t{} {}", predicate, object);
+            }
+        }
+    }
+    if !triples.is_empty() {
+        turtle.push_str(" .\n");
+    }
+}
+
+fn rank_term(rank: Rank) -> &'static str {
+    match rank {
+        Rank::Preferred => "wikibase:PreferredRank",
+        Rank::Normal => "wikibase:NormalRank",
+        Rank::Deprecated => "wikibase:DeprecatedRank",
+    }
+}
+
+/// Appends the triples of `entity` to `turtle`, see the module documentation
+pub fn write_turtle(turtle: &mut String, entity: &Entity) {
+    let subject = format!("wd:{}", entity.id);
+    let mut triples = Vec::new();
+    match entity.entity_type.as_str() {
+        "item" => triples.push((String::from("a"), String::from("wikibase:Item"))),
+        "property" => triples.push((String::from("a"), String::from("wikibase:Property"))),
+        _ => {}
+    }
+    for (predicate, terms) in [("rdfs:label", &entity.labels), ("schema:description", &entity.descriptions)] {
+        for term in terms.values() {
+            triples.push((String::from(predicate), language_literal(&term.value, &term.language)));
+        }
+    }
+    for term in entity.aliases.values().flatten() {
+        triples.push((String::from("skos:altLabel"), language_literal(&term.value, &term.language)));
+    }
+
+    let mut statements = Vec::new();
+    for (property, claims) in &entity.claims {
+        let best = match claims.iter().any(|claim| claim.rank == Rank::Preferred) {
+            true => Rank::Preferred,
+            false => Rank::Normal,
+        };
+        for claim in claims.iter().filter(|claim| claim.rank != Rank::Deprecated) {
+            let value = value_term(&claim.mainsnak);
+            if let (true, Some(value)) = (claim.rank == best, &value) {
+                triples.push((format!("wdt:{}", property), value.clone()));
+            }
+            // statement ids are <entity>$<uuid>, and the service's statement nodes <entity>-<uuid>
+            let node = match claim.id.as_deref().map(|id| id.replace('$', "-")) {
+                Some(node) if node.chars().all(|character| character.is_ascii_alphanumeric() || character == '-') => format!("wds:{}", node),
+                _ => continue,
+            };
+            triples.push((format!("p:{}", property), node.clone()));
+            let types = match claim.rank == best {
+                true => "wikibase:Statement, wikibase:BestRank",
+                false => "wikibase:Statement",
+            };
+            let mut statement = vec![(String::from("a"), String::from(types)), (String::from("wikibase:rank"), String::from(rank_term(claim.rank)))];
+            if let Some(value) = value {
+                statement.push((format!("ps:{}", property), value));
+            }
+            for (qualifier, snaks) in &claim.qualifiers {
+                statement.extend(snaks.iter().filter_map(value_term).map(|value| (format!("pq:{}", qualifier), value)));
+            }
+            statements.push((node, statement));
+        }
+    }
+    write_subject(turtle, &subject, &triples);
+    for (node, statement) in &statements {
+        write_subject(turtle, node, statement);
+    }
+    for sitelink in entity.sitelinks.values() {
+        if let Some(url) = &sitelink.url {
+            let article = [
+                (String::from("a"), String::from("schema:Article")),
+                (String::from("schema:about"), subject.clone()),
+                (String::from("schema:name"), literal(&sitelink.title)),
+            ];
+            write_subject(turtle, &iri(url), &article);
+        }
+    }
+}
+
+/// Appends the triples of `output`, which has to be a whole entity, to `turtle`
+pub fn write_output(turtle: &mut String, output: &str) -> Result<()> {
+    let entity = Entity::parse(output)
+        .map_err(|error| ProcessError::Turtle(format!("Output isn't an entity ({}), the jq filter has to keep whole entities: {:.100}", error, output)))?;
+    write_turtle(turtle, &entity);
+    Ok(())
+}
+
+/// The Turtle of `entity`, without prefixes
+pub fn turtle(entity: &Entity) -> String {
+    let mut turtle = String::new();
+    write_turtle(&mut turtle, entity);
+    turtle
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item","labels":{"en":{"language":"en","value":"Douglas \"Noel\" Adams"}},
+        "aliases":{"en":[{"language":"en","value":"DNA"}]},
+        "claims":{"P31":[{"id":"Q42$F078E5B3","mainsnak":{"snaktype":"value","property":"P31","datatype":"wikibase-item","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":5,"id":"Q5"}}},"type":"statement","rank":"normal"}],
+            "P569":[{"id":"Q42$D8404CDA","mainsnak":{"snaktype":"value","property":"P569","datatype":"time","datavalue":{"type":"time","value":{"time":"+1952-03-11T00:00:00Z","timezone":0,"before":0,"after":0,"precision":11,"calendarmodel":"http://www.wikidata.org/entity/Q1985727"}}},"type":"statement","rank":"normal"},
+                {"id":"Q42$2B2B6E26","mainsnak":{"snaktype":"value","property":"P569","datatype":"time","datavalue":{"type":"time","value":{"time":"+1952-00-00T00:00:00Z","timezone":0,"before":0,"after":0,"precision":9,"calendarmodel":"http://www.wikidata.org/entity/Q1985727"}}},"type":"statement","rank":"preferred",
+                 "qualifiers":{"P1480":[{"snaktype":"value","property":"P1480","datatype":"wikibase-item","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":5727902,"id":"Q5727902"}}}]}}]}}"#;
+
+    #[test]
+    fn test_turtle() {
+        let turtle = turtle(&Entity::parse(ENTITY).unwrap());
+        assert_eq!(turtle, r#"wd:Q42 a wikibase:Item ;
+	rdfs:label "Douglas \"Noel\" Adams"@en ;
+	skos:altLabel "DNA"@en ;
+	wdt:P31 wd:Q5 ;
+	p:P31 wds:Q42-F078E5B3 ;
+	p:P569 wds:Q42-D8404CDA ;
+	wdt:P569 "1952-01-01T00:00:00Z"^^xsd:dateTime ;
+	p:P569 wds:Q42-2B2B6E26 .
+wds:Q42-F078E5B3 a wikibase:Statement, wikibase:BestRank ;
+	wikibase:rank wikibase:NormalRank ;
+	ps:P31 wd:Q5 .
+wds:Q42-D8404CDA a wikibase:Statement ;
+	wikibase:rank wikibase:NormalRank ;
+	ps:P569 "1952-03-11T00:00:00Z"^^xsd:dateTime .
+wds:Q42-2B2B6E26 a wikibase:Statement, wikibase:BestRank ;
+	wikibase:rank wikibase:PreferredRank ;
+	ps:P569 "1952-01-01T00:00:00Z"^^xsd:dateTime ;
+	pq:P1480 wd:Q5727902 .
+"#);
+    }
+
+    #[test]
+    fn test_terms() {
+        assert_eq!(iri("https://example.org/a b"), "<https://example.org/a%20b>");
+        assert_eq!(language_literal("Zürich", "de-CH"), "\"Zürich\"@de-CH");
+        assert_eq!(language_literal("x", "sr-ec_"), "\"x\"");
+        assert_eq!(date_time("-0044-03-15T00:00:00Z"), "-0044-03-15T00:00:00Z");
+    }
+
+    #[test]
+    fn test_not_an_entity() {
+        assert!(matches!(write_output(&mut String::new(), r#"{"label":"universe"}"#), Err(ProcessError::Turtle(_))));
+    }
+}
@@ -0,0 +1,281 @@
+/*!
+ * An iterator over the entities of a decompressed dump, for library users who
+ * want to drive their own loop rather than go through `process`.
+ *
+ * ```no_run
+ * use std::fs::File;
+ * use wikidump_process::{decoder, reader::EntityReader};
+ *
+ * let file = File::open("./example.json.bz2")?;
+ * for entity in EntityReader::new(decoder::decoder(file))? {
+ *     println!("{}", entity?.len());
+ * }
+ * # Ok::<(), std::io::Error>(())
+ * ```
+ *
+ * `EntityFile` reads the entities of a file on disk whether it's a dump, the
+ * one entity per line output of `filter` or any of the formats `convert`
+ * writes.
+ */
+
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use bzip2::bufread::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
+use serde_json::Value;
+use crate::decoder::{self, BUFFER_LENGTH};
+use crate::error::{ProcessError, Result};
+use crate::source::CountingReader;
+use crate::splitter::{EntityBuffer, DUMP_START};
+
+pub struct EntityReader<R: Read> {
+    reader: R,
+    entities: EntityBuffer,
+    chunk: Vec<u8>,
+    finished: bool,
+}
+
+impl<R: Read> EntityReader<R> {
+    /// Creates a reader over decompressed dump text, checking that it starts like a dump
+    pub fn new(reader: R) -> io::Result<Self> {
+        EntityReader::with_chunk_size(reader, BUFFER_LENGTH)
+    }
+
+    /// Same as `new`, reading `chunk_size` bytes at a time from `reader`
+    pub fn with_chunk_size(mut reader: R, chunk_size: usize) -> io::Result<Self> {
+        let mut start = [0u8; DUMP_START.len()];
+        reader.read_exact(&mut start)?;
+        let mut entities = EntityBuffer::new();
+        entities.extend(&start);
+        entities.check_start()?;
+        Ok(EntityReader { reader, entities, chunk: vec![0; chunk_size.max(1)], finished: false })
+    }
+
+    /// Whether the dump ended with the end of its JSON array, once all of its entities have been read
+    pub fn is_complete(&self) -> bool {
+        self.entities.is_complete()
+    }
+
+    fn next_entity(&mut self) -> io::Result<Option<String>> {
+        loop {
+            if let Some(entity) = self.entities.next_entity()? {
+                return Ok(Some(entity));
+            }
+            if self.finished {
+                return Ok(None);
+            }
+            let n = match self.reader.read(&mut self.chunk) {
+                Ok(n) => n,
+                Err(error) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Err(error) => return Err(error),
+            };
+            if n == 0 {
+                self.finished = true;
+                return self.entities.finish();
+            }
+            self.entities.extend(&self.chunk[..n]);
+        }
+    }
+}
+
+impl<R: Read> Iterator for EntityReader<R> {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.next_entity().transpose()
+    }
+}
+
+// bytes every bzip2 stream starts with
+const BZIP2_MAGIC: &[u8] = b"BZh";
+
+// bytes every gzip member starts with
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+
+/// The entities of a file: a dump, one entity per line as `filter` writes them, or MessagePack maps one after
+/// the other, any of which may be compressed with bzip2 or gzip.
+///
+/// Empty lines are skipped, and MessagePack entities are returned as JSON.
+pub struct EntityFile {
+    entities: Entities,
+    consumed: Arc<AtomicU64>,
+    size: u64,
+    line: u64,
+    complete: bool,
+}
+
+enum Entities {
+    Dump(EntityReader<Box<dyn Read + Send>>),
+    Lines(Box<dyn BufRead + Send>),
+    Msgpack(Box<dyn BufRead + Send>),
+}
+
+// whether `byte` starts a MessagePack map, which can't start JSON text
+fn is_msgpack_map(byte: u8) -> bool {
+    matches!(byte, 0x80..=0x8f | 0xde | 0xdf)
+}
+
+// writes `entity` as JSON with its id first, where `splitter::entity_id` looks for it, rather than among the
+// other keys in alphabetical order
+pub(crate) fn entity_json(entity: &Value) -> serde_json::Result<String> {
+    let entity = match entity {
+        Value::Object(entity) if entity.contains_key("id") => entity,
+        _ => return serde_json::to_string(entity),
+    };
+    let mut json = format!("{{\"id\":{}", serde_json::to_string(&entity["id"])?);
+    for (key, value) in entity.iter().filter(|(key, _)| *key != "id") {
+        json.push(',');
+        json.push_str(&serde_json::to_string(key)?);
+        json.push(':');
+        json.push_str(&serde_json::to_string(value)?);
+    }
+    json.push('}');
+    Ok(json)
+}
+
+impl EntityFile {
+    /// Opens `path`, telling which kind of file it is from its first bytes
+    pub fn open(path: &Path) -> Result<Self> {
+        let (file, size) = decoder::open(path)?;
+        let file = CountingReader::new(file);
+        let consumed = file.count();
+        let mut file = BufReader::with_capacity(BUFFER_LENGTH, file);
+        let start = file.fill_buf().map_err(ProcessError::Read)?;
+        let decompressed: Box<dyn Read + Send> = if start.starts_with(BZIP2_MAGIC) {
+            Box::new(MultiBzDecoder::new(file))
+        } else if start.starts_with(GZIP_MAGIC) {
+            Box::new(MultiGzDecoder::new(file))
+        } else {
+            Box::new(file)
+        };
+        let mut decompressed = BufReader::with_capacity(BUFFER_LENGTH, decompressed);
+        let start = decompressed.fill_buf().map_err(ProcessError::Read)?;
+        let (entities, line) = if start.starts_with(DUMP_START.as_bytes()) {
+            (Entities::Dump(EntityReader::new(Box::new(decompressed) as Box<dyn Read + Send>).map_err(ProcessError::Read)?), 1)
+        } else if start.first().is_some_and(|&byte| is_msgpack_map(byte)) {
+            (Entities::Msgpack(Box::new(decompressed)), 0)
+        } else {
+            (Entities::Lines(Box::new(decompressed)), 0)
+        };
+        Ok(EntityFile { entities, consumed, size, line, complete: true })
+    }
+
+    /// Size of the file in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Bytes of the file read so far, which is ahead of the entities returned by up to a buffer's worth
+    pub fn position(&self) -> u64 {
+        self.consumed.load(Ordering::Relaxed)
+    }
+
+    /// Line of the file the entity returned last starts on, counting from 1, or which entity it is of a MessagePack file
+    pub fn line(&self) -> u64 {
+        self.line
+    }
+
+    /// Whether the file ended the way a complete one does, once all of its entities have been read: dumps
+    /// with the end of their JSON array, and other files with a newline
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    fn next_msgpack(reader: &mut dyn BufRead, line: &mut u64) -> io::Result<Option<String>> {
+        if reader.fill_buf()?.is_empty() {
+            return Ok(None);
+        }
+        *line += 1;
+        let invalid = |error: String| io::Error::new(io::ErrorKind::InvalidData, error);
+        let entity: Value = rmp_serde::from_read(reader).map_err(|error| invalid(error.to_string()))?;
+        entity_json(&entity).map(Some).map_err(|error| invalid(error.to_string()))
+    }
+
+    fn next_line(reader: &mut dyn BufRead, line: &mut u64, complete: &mut bool) -> io::Result<Option<String>> {
+        loop {
+            let mut entity = String::new();
+            if reader.read_line(&mut entity)? == 0 {
+                return Ok(None);
+            }
+            *line += 1;
+            *complete = entity.ends_with('\n');
+            if *complete {
+                entity.pop();
+            }
+            if !entity.is_empty() {
+                return Ok(Some(entity));
+            }
+        }
+    }
+}
+
+impl Iterator for EntityFile {
+    type Item = io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.entities {
+            Entities::Dump(reader) => {
+                let entity = reader.next();
+                match entity {
+                    Some(Ok(_)) => self.line += 1,
+                    None => self.complete = reader.is_complete(),
+                    Some(Err(_)) => {}
+                }
+                entity
+            }
+            Entities::Lines(reader) => EntityFile::next_line(reader, &mut self.line, &mut self.complete).transpose(),
+            Entities::Msgpack(reader) => EntityFile::next_msgpack(reader, &mut self.line).transpose(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use crate::decoder;
+
+    #[test]
+    fn test_entity_reader() {
+        let file = File::open("./tests/test-data.json.bz2").unwrap();
+        let entities = EntityReader::new(decoder::decoder(file)).unwrap()
+            .collect::<io::Result<Vec<String>>>()
+            .unwrap();
+        assert_eq!(entities.len(), 8);
+        assert!(entities[0].starts_with(r#"{"id": "Q1","#));
+        assert!(entities[7].ends_with("}}}"));
+    }
+
+    #[test]
+    fn test_entity_reader_splits_characters_across_chunks() {
+        let dump = "[\n{\"id\": \"Q1\", \"label\": \"città\"},\n{\"id\": \"Q2\", \"label\": \"ニューヨーク\"}\n]";
+        let entities = EntityReader::with_chunk_size(dump.as_bytes(), 3).unwrap()
+            .collect::<io::Result<Vec<String>>>()
+            .unwrap();
+        assert_eq!(entities, vec!["{\"id\": \"Q1\", \"label\": \"città\"}", "{\"id\": \"Q2\", \"label\": \"ニューヨーク\"}"]);
+    }
+
+    #[test]
+    fn test_entity_file() {
+        let mut dump = EntityFile::open(Path::new("./tests/test-data.json.bz2")).unwrap();
+        assert_eq!(dump.size(), 1912);
+        let entities = dump.by_ref().collect::<io::Result<Vec<String>>>().unwrap();
+        assert_eq!(entities.len(), 8);
+        assert_eq!(dump.line(), 9);
+        assert!(dump.is_complete());
+
+        let directory = tempfile::tempdir().unwrap();
+        let lines = directory.path().join("entities.ndjson");
+        std::fs::write(&lines, "[1,2]\n\n{\"id\": \"Q2\"}\n").unwrap();
+        let entities = EntityFile::open(&lines).unwrap().collect::<io::Result<Vec<String>>>().unwrap();
+        assert_eq!(entities, vec!["[1,2]", "{\"id\": \"Q2\"}"]);
+
+        std::fs::write(&lines, "{\"id\": \"Q1\"}\n\n{\"id\"").unwrap();
+        let mut entities = EntityFile::open(&lines).unwrap();
+        assert_eq!(entities.by_ref().count(), 2);
+        assert_eq!(entities.line(), 3);
+        assert!(!entities.is_complete());
+    }
+}
@@ -0,0 +1,260 @@
+/*!
+ * Redirects left behind by merged entities, and rewriting the ids of
+ * redirected entities in statements to those of the entities they redirect
+ * to, so graphs built from a dump don't point at entities which no longer
+ * exist.
+ *
+ * Redirects are entities with a `"redirects": {"from": ..., "to": ...}`
+ * object, as Wikibase returns them from `Special:EntityData` and
+ * `wbgetentities`. The full JSON dumps published by Wikimedia leave redirects
+ * out, so they're found in dumps assembled from those, e.g. incremental ones.
+ *
+ * Mappings are written as `from<TAB>to` TSV, sorted by the redirected id.
+ */
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use serde_json::Value;
+use crate::error::{ProcessError, Result};
+use crate::filter::{self, EntityFilter};
+use crate::index::parse_id;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+// how many redirects are followed to resolve one id, which only a cycle needs more of
+const MAX_HOPS: usize = 16;
+
+#[derive(Deserialize)]
+struct Outline {
+    redirects: Option<Redirect>,
+}
+
+#[derive(Deserialize)]
+struct Redirect {
+    from: String,
+    to: String,
+}
+
+/// The redirected id and the id it redirects to, if `entity` is a redirect
+pub fn redirect_of(entity: &str) -> serde_json::Result<Option<(String, String)>> {
+    // most entities aren't, and can be told apart without parsing
+    if !entity.contains("\"redirects\"") {
+        return Ok(None);
+    }
+    let outline: Outline = serde_json::from_str(entity)?;
+    Ok(outline.redirects.map(|redirect| (redirect.from, redirect.to)))
+}
+
+/// Redirected ids and the ids they redirect to
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Redirects {
+    targets: HashMap<String, String>,
+}
+
+impl Redirects {
+    pub fn insert(&mut self, from: impl Into<String>, to: impl Into<String>) {
+        self.targets.insert(from.into(), to.into());
+    }
+
+    /// Number of redirected ids
+    pub fn len(&self) -> usize {
+        self.targets.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.targets.is_empty()
+    }
+
+    /// The id `id` ends up redirecting to, following redirects to redirects, or `None` if it isn't redirected.
+    /// Redirects going round in a cycle resolve to wherever the cycle was noticed.
+    pub fn resolve(&self, id: &str) -> Option<&str> {
+        let mut target = self.targets.get(id)?;
+        for _ in 0..MAX_HOPS {
+            match self.targets.get(target) {
+                Some(next) if next != id => target = next,
+                _ => break,
+            }
+        }
+        Some(target)
+    }
+
+    /// Reads a mapping written by `write_tsv`
+    pub fn load(path: &Path) -> Result<Self> {
+        let open_error = |source| ProcessError::OpenInput { path: path.to_path_buf(), source };
+        let mut redirects = Redirects::default();
+        for (i, line) in BufReader::new(File::open(path).map_err(open_error)?).lines().enumerate() {
+            let line = line.map_err(ProcessError::Read)?;
+            if line.is_empty() {
+                continue;
+            }
+            match line.split_once('\t') {
+                Some((from, to)) => redirects.insert(from, to),
+                None => {
+                    let message = format!("line {} of {:?} is not a from<TAB>to redirect", i + 1, path);
+                    return Err(ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, message)));
+                }
+            }
+        }
+        Ok(redirects)
+    }
+
+    /// Writes every redirected id and the id it resolves to, sorted by the redirected id
+    pub fn write_tsv(&self, output: &mut impl Write) -> io::Result<()> {
+        let mut ids = self.targets.keys().collect::<Vec<_>>();
+        ids.sort_unstable_by_key(|id| (parse_id(id), *id));
+        for id in ids {
+            writeln!(output, "{}\t{}", id, self.resolve(id).expect("redirected"))?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the ids of redirected entities referred to by any `wikibase-entityid` value in `value`, e.g. in
+    /// the main snaks, qualifiers and references of an entity's statements, returning how many were replaced
+    pub fn rewrite_ids(&self, value: &mut Value) -> usize {
+        match value {
+            Value::Object(object) if object.get("type").and_then(Value::as_str) == Some("wikibase-entityid") => {
+                match object.get_mut("value") {
+                    Some(Value::Object(target)) => self.rewrite_entity_id(target) as usize,
+                    _ => 0,
+                }
+            }
+            Value::Object(object) => object.values_mut().map(|value| self.rewrite_ids(value)).sum(),
+            Value::Array(values) => values.iter_mut().map(|value| self.rewrite_ids(value)).sum(),
+            _ => 0,
+        }
+    }
+
+    fn rewrite_entity_id(&self, target: &mut serde_json::Map<String, Value>) -> bool {
+        let resolved = match target.get("id").and_then(Value::as_str).and_then(|id| self.resolve(id)) {
+            Some(resolved) => resolved.to_string(),
+            None => return false,
+        };
+        if let Some((_, number)) = parse_id(&resolved) {
+            if target.contains_key("numeric-id") {
+                target.insert(String::from("numeric-id"), Value::from(number));
+            }
+        }
+        target.insert(String::from("id"), Value::from(resolved));
+        true
+    }
+
+    /// `output` with the ids of redirected entities replaced as `rewrite_ids` does, or as-is if there were none
+    /// or it isn't JSON
+    pub fn rewrite(&self, output: String) -> String {
+        let mut value = match serde_json::from_str::<Value>(&output) {
+            Ok(value) => value,
+            Err(_) => return output,
+        };
+        match self.rewrite_ids(&mut value) {
+            0 => output,
+            _ => value.to_string(),
+        }
+    }
+}
+
+// collects the redirects found on one thread, adding them to the total once dropped
+struct RedirectFinder {
+    redirects: Redirects,
+    total: Arc<Mutex<Redirects>>,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl EntityFilter for RedirectFinder {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        match redirect_of(raw) {
+            Ok(Some((from, to))) => self.redirects.insert(from, to),
+            Ok(None) => {}
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+            }
+        }
+        Ok(None)
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+impl Drop for RedirectFinder {
+    fn drop(&mut self) {
+        if let Ok(mut total) = self.total.lock() {
+            total.targets.extend(std::mem::take(&mut self.redirects.targets));
+        }
+    }
+}
+
+/// Finds the redirects among the entities of `source` on `options.threads` threads
+pub fn find_redirects(source: impl Source, options: ProcessOptions) -> Result<(Redirects, ProcessStats)> {
+    let total = Arc::new(Mutex::new(Redirects::default()));
+    let continue_on_error = options.continue_on_error;
+    let finders = Arc::clone(&total);
+    let stats = Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(RedirectFinder { redirects: Redirects::default(), total: Arc::clone(&finders), continue_on_error, failures: 0 }))
+        .sink(io::sink())
+        .options(options)
+        .build()?
+        .run()?;
+    let redirects = std::mem::take(&mut *total.lock().expect("Redirects poisoned"));
+    Ok((redirects, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    #[test]
+    fn test_redirect_of() {
+        assert_eq!(redirect_of(r#"{"id":"Q2","type":"item","redirects":{"from":"Q1","to":"Q2"}}"#).unwrap(), Some((String::from("Q1"), String::from("Q2"))));
+        assert_eq!(redirect_of(r#"{"id":"Q2","type":"item"}"#).unwrap(), None);
+        let (redirects, stats) = find_redirects(FileSource::new("./tests/test-data.json.bz2"), ProcessOptions::default()).unwrap();
+        assert!(redirects.is_empty());
+        assert_eq!(stats.entities_read, 8);
+    }
+
+    #[test]
+    fn test_resolve() {
+        let mut redirects = Redirects::default();
+        redirects.insert("Q10", "Q1");
+        redirects.insert("Q2", "Q3");
+        redirects.insert("Q3", "Q4");
+        redirects.insert("Q5", "Q6");
+        redirects.insert("Q6", "Q5");
+        assert_eq!(redirects.resolve("Q2"), Some("Q4"));
+        assert_eq!(redirects.resolve("Q4"), None);
+        assert!(redirects.resolve("Q5").is_some());
+
+        let mut tsv = Vec::new();
+        redirects.write_tsv(&mut tsv).unwrap();
+        assert!(String::from_utf8(tsv.clone()).unwrap().starts_with("Q2\tQ4\nQ3\tQ4\nQ5\t"));
+        let path = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(path.path(), &tsv).unwrap();
+        assert_eq!(Redirects::load(path.path()).unwrap().resolve("Q10"), Some("Q1"));
+    }
+
+    #[test]
+    fn test_rewrite() {
+        let mut redirects = Redirects::default();
+        redirects.insert("Q1", "Q2");
+        let entity = r#"{"id":"Q42","claims":{"P31":[{"mainsnak":{"snaktype":"value","property":"P31","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","numeric-id":1,"id":"Q1"}}},"qualifiers":{"P642":[{"snaktype":"value","property":"P642","datavalue":{"type":"wikibase-entityid","value":{"entity-type":"item","id":"Q1"}}}]}}]}}"#;
+        let rewritten: Value = serde_json::from_str(&redirects.rewrite(entity.to_string())).unwrap();
+        let claim = &rewritten["claims"]["P31"][0];
+        assert_eq!(claim["mainsnak"]["datavalue"]["value"]["id"], "Q2");
+        assert_eq!(claim["mainsnak"]["datavalue"]["value"]["numeric-id"], 2);
+        assert_eq!(claim["qualifiers"]["P642"][0]["datavalue"]["value"]["id"], "Q2");
+        assert_eq!(claim["qualifiers"]["P642"][0]["datavalue"]["value"].get("numeric-id"), None);
+        // untouched outputs keep their exact text
+        assert_eq!(redirects.rewrite(String::from("{\"id\": \"Q1\"}")), "{\"id\": \"Q1\"}");
+        assert_eq!(redirects.rewrite(String::from("Q1,label")), "Q1,label");
+    }
+}
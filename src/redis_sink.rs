@@ -0,0 +1,137 @@
+/*!
+ * Writing outputs straight into Redis (with the `redis` feature), keyed by
+ * the id of their entity, for serving entity lookups without a load script.
+ *
+ * Outputs are gathered and sent as pipelined commands once
+ * `buffer_size` bytes of them are waiting: `MSET` of `<prefix><id>` keys, or
+ * with a hash `HSET` of the ids as fields of it. Each output is stored as-is,
+ * so the jq filter (or e.g. `--preset truthy-simple`) decides what a lookup
+ * returns, but has to keep the id of the entity.
+ */
+
+use log::debug;
+use redis::{Client, Connection, Pipeline, RedisError};
+use crate::error::{ProcessError, Result};
+use crate::sink::Sink;
+use crate::splitter;
+
+// entities set by a single command, so one command doesn't hold up the server for long
+const COMMAND_SIZE: usize = 1000;
+
+fn redis_error(error: RedisError) -> ProcessError {
+    ProcessError::Redis(error.to_string())
+}
+
+/// Where a `RedisSink` puts outputs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RedisLayout {
+    /// A key per entity, the id with this prefix, e.g. `entity:Q42` for `entity:`
+    Keys { prefix: String },
+    /// A field per entity, the id, of a single hash
+    Hash { key: String },
+}
+
+/// Sets the output of each entity in Redis under its id, see the module documentation
+pub struct RedisSink {
+    connection: Connection,
+    layout: RedisLayout,
+    buffer_size: usize,
+    // ids and outputs waiting to be sent, and their size
+    pending: Vec<(String, String)>,
+    pending_bytes: usize,
+    written: u64,
+}
+
+impl RedisSink {
+    /// Connects to the server at `url`, e.g. `redis://127.0.0.1:6379/0`, failing if it can't be reached
+    pub fn connect(url: &str, layout: RedisLayout, buffer_size: usize) -> Result<Self> {
+        let mut connection = Client::open(url).and_then(|client| client.get_connection()).map_err(redis_error)?;
+        redis::cmd("PING").query::<()>(&mut connection).map_err(redis_error)?;
+        debug!("Connected to {}", url);
+        Ok(RedisSink { connection, layout, buffer_size, pending: Vec::new(), pending_bytes: 0, written: 0 })
+    }
+
+    /// Number of entities sent so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // sends everything waiting in one round trip
+    fn send(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let mut pipeline = Pipeline::new();
+        for chunk in self.pending.chunks(COMMAND_SIZE) {
+            let command = match &self.layout {
+                RedisLayout::Keys { prefix } => {
+                    let command = pipeline.cmd("MSET");
+                    for (id, output) in chunk {
+                        command.arg(format!("{}{}", prefix, id)).arg(output);
+                    }
+                    command
+                }
+                RedisLayout::Hash { key } => {
+                    let command = pipeline.cmd("HSET").arg(key);
+                    for (id, output) in chunk {
+                        command.arg(id).arg(output);
+                    }
+                    command
+                }
+            };
+            command.ignore();
+        }
+        pipeline.query::<()>(&mut self.connection).map_err(redis_error)?;
+        self.written += self.pending.len() as u64;
+        self.pending.clear();
+        self.pending_bytes = 0;
+        Ok(())
+    }
+}
+
+impl Sink for RedisSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let id = splitter::entity_id(output)
+            .ok_or_else(|| ProcessError::Redis(format!("Output has no id to key it by, the jq filter has to keep it: {:.100}", output)))?;
+        self.pending_bytes += id.len() + output.len();
+        self.pending.push((id.to_string(), output.to_string()));
+        if self.pending_bytes >= self.buffer_size {
+            self.send()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.send()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // needs a server, given as REDIS_URL, e.g. redis://127.0.0.1:6379/15
+    #[test]
+    #[ignore]
+    fn test_redis_sink() {
+        let url = std::env::var("REDIS_URL").expect("REDIS_URL names a database to test with");
+        let prefix = format!("wikidump-test-{}:", std::process::id());
+        let mut sink = RedisSink::connect(&url, RedisLayout::Keys { prefix: prefix.clone() }, 16).unwrap();
+        sink.write_entity(r#"{"id":"Q1","label":"universe"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"Earth"}"#).unwrap();
+        sink.finalize().unwrap();
+        assert_eq!(sink.written(), 2);
+        assert!(matches!(sink.write_entity(r#"["Q3"]"#), Err(ProcessError::Redis(_))));
+
+        let mut connection = Client::open(url.as_str()).unwrap().get_connection().unwrap();
+        let stored: Option<String> = redis::cmd("GET").arg(format!("{}Q2", prefix)).query(&mut connection).unwrap();
+        assert_eq!(stored.as_deref(), Some(r#"{"id":"Q2","label":"Earth"}"#));
+        redis::cmd("DEL").arg(format!("{}Q1", prefix)).arg(format!("{}Q2", prefix)).query::<()>(&mut connection).unwrap();
+    }
+
+    #[test]
+    fn test_unreachable_server() {
+        assert!(matches!(RedisSink::connect("redis://127.0.0.1:1/", RedisLayout::Keys { prefix: String::new() }, 16), Err(ProcessError::Redis(_))));
+        assert!(matches!(RedisSink::connect("not a url", RedisLayout::Keys { prefix: String::new() }, 16), Err(ProcessError::Redis(_))));
+    }
+}
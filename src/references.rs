@@ -0,0 +1,339 @@
+/*!
+ * The references of statements, which say where their values come from, for
+ * pipelines which have to leave out unsourced data: either dropping the
+ * statements without any reference from outputs, or the outputs without any
+ * referenced statement at all. Outputs which aren't entities, i.e. JSON
+ * objects without `claims`, are kept as they are.
+ *
+ * The URLs references cite (their "reference URL", P854) can also be
+ * harvested, for studies of the quality of sources: a TSV of `id<TAB>url` rows
+ * with the distinct URLs each entity cites, and a table of how often each
+ * domain is cited. Only the ids and references of entities are parsed.
+ */
+
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::io::{self, Write};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+use crate::labels::escape_tsv;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+/// What has to have references for an output to be kept whole
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReferenceRequirement {
+    /// Drop the statements without references, keeping the rest of the entity
+    Statements,
+    /// Drop entities without a single referenced statement, keeping the others whole
+    Entities,
+}
+
+impl FromStr for ReferenceRequirement {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "statements" => Ok(ReferenceRequirement::Statements),
+            "entities" => Ok(ReferenceRequirement::Entities),
+            _ => Err(format!("Invalid reference requirement '{}', expected statements or entities", value)),
+        }
+    }
+}
+
+fn is_referenced(statement: &Value) -> bool {
+    matches!(statement.get("references"), Some(Value::Array(references)) if !references.is_empty())
+}
+
+/// Whether any statement of `entity` has a reference
+pub fn has_referenced_statement(entity: &Map<String, Value>) -> bool {
+    match entity.get("claims") {
+        Some(Value::Object(claims)) => claims.values()
+            .filter_map(Value::as_array)
+            .any(|statements| statements.iter().any(is_referenced)),
+        _ => false,
+    }
+}
+
+/// Removes the statements of `entity` without any reference, and the properties left without statements,
+/// returning how many statements were removed
+pub fn drop_unreferenced(entity: &mut Map<String, Value>) -> usize {
+    let claims = match entity.get_mut("claims") {
+        Some(Value::Object(claims)) => claims,
+        _ => return 0,
+    };
+    let mut dropped = 0;
+    claims.retain(|_, statements| match statements {
+        Value::Array(statements) => {
+            let before = statements.len();
+            statements.retain(is_referenced);
+            dropped += before - statements.len();
+            !statements.is_empty()
+        }
+        _ => true,
+    });
+    dropped
+}
+
+/// `output` as `requirement` has it, re-serialized only if statements were dropped, or `None` to leave it out
+pub fn require_references_output(output: String, requirement: ReferenceRequirement) -> Option<String> {
+    let mut entity = match serde_json::from_str::<Value>(&output) {
+        Ok(Value::Object(entity)) if entity.contains_key("claims") => entity,
+        _ => return Some(output),
+    };
+    match requirement {
+        ReferenceRequirement::Entities if has_referenced_statement(&entity) => Some(output),
+        ReferenceRequirement::Entities => None,
+        ReferenceRequirement::Statements => match drop_unreferenced(&mut entity) {
+            0 => Some(output),
+            _ => Some(Value::Object(entity).to_string()),
+        },
+    }
+}
+
+// the parts of an entity reference URLs are found in
+#[derive(Deserialize)]
+struct Outline<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(default, borrow)]
+    claims: BTreeMap<Cow<'a, str>, Vec<StatementOutline<'a>>>,
+}
+
+#[derive(Deserialize)]
+struct StatementOutline<'a> {
+    #[serde(default, borrow)]
+    references: Vec<ReferenceOutline<'a>>,
+}
+
+#[derive(Deserialize)]
+struct ReferenceOutline<'a> {
+    #[serde(default, borrow)]
+    snaks: ReferenceSnaks<'a>,
+}
+
+// other properties of references are skipped over
+#[derive(Deserialize, Default)]
+struct ReferenceSnaks<'a> {
+    #[serde(rename = "P854", default, borrow)]
+    urls: Vec<UrlSnak<'a>>,
+}
+
+#[derive(Deserialize)]
+struct UrlSnak<'a> {
+    #[serde(default, borrow)]
+    datavalue: Option<UrlValue<'a>>,
+}
+
+#[derive(Deserialize)]
+struct UrlValue<'a> {
+    #[serde(borrow)]
+    value: Cow<'a, str>,
+}
+
+impl Outline<'_> {
+    // every reference URL, as often as it's cited
+    fn urls(&self) -> impl Iterator<Item = &str> {
+        self.claims.values()
+            .flatten()
+            .flat_map(|statement| &statement.references)
+            .flat_map(|reference| &reference.snaks.urls)
+            .filter_map(|snak| snak.datavalue.as_ref())
+            .map(|datavalue| datavalue.value.as_ref())
+    }
+}
+
+/// The domain of `url`, lowercased and without a leading `www.`, e.g. `bbc.co.uk` for
+/// `https://www.BBC.co.uk/news`, or `None` if it has no host
+pub fn url_domain(url: &str) -> Option<String> {
+    let (_, rest) = url.split_once("://")?;
+    let authority = rest.split(['/', '?', '#']).next()?;
+    let host = authority.rsplit('@').next()?;
+    // an IPv6 address keeps its colons, within its brackets
+    let host = match host.strip_prefix('[') {
+        Some(address) => address.split(']').next()?,
+        None => host.split(':').next()?,
+    };
+    let host = host.trim_end_matches('.').to_ascii_lowercase();
+    let host = host.strip_prefix("www.").map(str::to_string).unwrap_or(host);
+    Some(host).filter(|host| !host.is_empty())
+}
+
+/// How often each domain is cited by reference URLs
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DomainCount {
+    /// Reference URLs on the domain, as often as they're cited
+    pub references: u64,
+    /// Entities citing the domain at least once
+    pub entities: u64,
+}
+
+/// The domains cited by reference URLs, see `DomainCount`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DomainTable {
+    pub domains: HashMap<String, DomainCount>,
+}
+
+impl DomainTable {
+    pub fn merge(&mut self, other: DomainTable) {
+        for (domain, count) in other.domains {
+            let total = self.domains.entry(domain).or_default();
+            total.references += count.references;
+            total.entities += count.entities;
+        }
+    }
+
+    /// Writes the table as CSV, one `domain,references,entities` row per domain, most cited first
+    pub fn write_csv(&self, output: &mut impl Write) -> io::Result<()> {
+        let mut domains: Vec<_> = self.domains.iter().collect();
+        domains.sort_unstable_by(|a, b| b.1.references.cmp(&a.1.references).then_with(|| a.0.cmp(b.0)));
+        writeln!(output, "domain,references,entities")?;
+        for (domain, count) in domains {
+            writeln!(output, "{},{},{}", domain, count.references, count.entities)?;
+        }
+        Ok(())
+    }
+}
+
+// turns each entity into its rows of reference URLs, counting their domains on one thread and adding them to
+// the total once dropped
+struct UrlExtractor {
+    domains: DomainTable,
+    total: Arc<Mutex<DomainTable>>,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl UrlExtractor {
+    fn rows(&mut self, outline: &Outline) -> String {
+        let mut rows = String::new();
+        let mut urls = BTreeSet::new();
+        let mut domains = BTreeSet::new();
+        for url in outline.urls() {
+            if let Some(domain) = url_domain(url) {
+                self.domains.domains.entry(domain.clone()).or_default().references += 1;
+                domains.insert(domain);
+            }
+            if urls.insert(url) {
+                if !rows.is_empty() {
+                    rows.push('\n');
+                }
+                rows.push_str(&escape_tsv(&outline.id));
+                rows.push('\t');
+                rows.push_str(&escape_tsv(url));
+            }
+        }
+        for domain in domains {
+            self.domains.domains.entry(domain).or_default().entities += 1;
+        }
+        rows
+    }
+}
+
+impl EntityFilter for UrlExtractor {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        // most entities cite no URLs, and can be told apart without parsing
+        if !raw.contains("\"P854\"") {
+            return Ok(None);
+        }
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let rows = self.rows(&outline);
+        Ok(Some(rows).filter(|rows| !rows.is_empty()).map(Cow::Owned))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+impl Drop for UrlExtractor {
+    fn drop(&mut self) {
+        if let Ok(mut total) = self.total.lock() {
+            total.merge(std::mem::take(&mut self.domains));
+        }
+    }
+}
+
+/// Writes the distinct reference URLs of every entity of `source` to `output` as TSV, one `id<TAB>url` row per
+/// URL in dump order, returning the table of the domains cited along with the stats of the run
+pub fn write_reference_urls(source: impl Source, options: ProcessOptions, output: impl Write) -> Result<(DomainTable, ProcessStats)> {
+    let total = Arc::new(Mutex::new(DomainTable::default()));
+    let continue_on_error = options.continue_on_error;
+    let extractors = Arc::clone(&total);
+    let stats = Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(UrlExtractor { domains: DomainTable::default(), total: Arc::clone(&extractors), continue_on_error, failures: 0 }))
+        .sink(output)
+        .options(options)
+        .build()?
+        .run()?;
+    let domains = std::mem::take(&mut *total.lock().expect("Domain table poisoned"));
+    Ok((domains, stats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    #[test]
+    fn test_require_references() {
+        let entity = r#"{"id":"Q1","claims":{"P31":[{"id":"a","references":[{"hash":"h"}]},{"id":"b","references":[]}],"P17":[{"id":"c"}]}}"#;
+        assert_eq!(
+            require_references_output(entity.to_string(), ReferenceRequirement::Statements).as_deref(),
+            Some(r#"{"claims":{"P31":[{"id":"a","references":[{"hash":"h"}]}]},"id":"Q1"}"#),
+        );
+        assert_eq!(require_references_output(entity.to_string(), ReferenceRequirement::Entities).as_deref(), Some(entity));
+
+        let unsourced = r#"{"id":"Q2","claims":{"P17":[{"id":"c","references":[]}]}}"#;
+        assert_eq!(require_references_output(unsourced.to_string(), ReferenceRequirement::Entities), None);
+        assert_eq!(require_references_output(unsourced.to_string(), ReferenceRequirement::Statements).as_deref(), Some(r#"{"claims":{},"id":"Q2"}"#));
+
+        // outputs which aren't entities are kept as they are
+        assert_eq!(require_references_output(String::from("\"Q1\""), ReferenceRequirement::Entities).as_deref(), Some("\"Q1\""));
+        assert!("sources".parse::<ReferenceRequirement>().is_err());
+    }
+
+    #[test]
+    fn test_url_domain() {
+        assert_eq!(url_domain("https://www.BBC.co.uk/news?id=1").as_deref(), Some("bbc.co.uk"));
+        assert_eq!(url_domain("http://user@example.org:8080").as_deref(), Some("example.org"));
+        assert_eq!(url_domain("http://[::1]:80/").as_deref(), Some("::1"));
+        assert_eq!(url_domain("example.org/page"), None);
+    }
+
+    #[test]
+    fn test_reference_urls() {
+        let reference = |url: &str| format!(r#"{{"snaks":{{"P854":[{{"snaktype":"value","property":"P854","datavalue":{{"value":"{}","type":"string"}}}}],"P813":[{{"datavalue":{{"value":{{"time":"+2020-01-01T00:00:00Z"}},"type":"time"}}}}]}}}}"#, url);
+        let entity = format!(
+            r#"{{"id":"Q1","claims":{{"P31":[{{"references":[{},{}]}},{{"references":[{}]}}],"P17":[{{}}]}}}}"#,
+            reference("https://www.example.org/a"), reference("https://example.org/b"), reference("https://www.example.org/a"),
+        );
+        let mut extractor = UrlExtractor { domains: DomainTable::default(), total: Arc::new(Mutex::new(DomainTable::default())), continue_on_error: false, failures: 0 };
+        assert_eq!(extractor.apply(&entity).unwrap().unwrap(), "Q1\thttps://www.example.org/a\nQ1\thttps://example.org/b");
+        assert_eq!(extractor.domains.domains["example.org"], DomainCount { references: 3, entities: 1 });
+        assert_eq!(extractor.apply(r#"{"id":"Q2","claims":{}}"#).unwrap(), None);
+
+        let total = Arc::clone(&extractor.total);
+        drop(extractor);
+        let mut csv = Vec::new();
+        total.lock().unwrap().write_csv(&mut csv).unwrap();
+        assert_eq!(String::from_utf8(csv).unwrap(), "domain,references,entities\nexample.org,3,1\n");
+
+        let (domains, stats) = write_reference_urls(FileSource::new("./tests/test-data.json.bz2"), ProcessOptions::default(), &mut Vec::new()).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        assert!(domains.domains.values().all(|count| count.references >= count.entities));
+    }
+}
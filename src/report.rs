@@ -0,0 +1,78 @@
+/*!
+ * Error reports of the entities which couldn't be filtered, so they can be
+ * found again without logging the entities themselves, which run to
+ * megabytes.
+ *
+ * A report is a line of JSON for each entity skipped with continue on error,
+ * in dump order: its id if one could be found, its position in the dump
+ * (counting from 0), the decompressed byte offset and length of its JSON, and
+ * why it couldn't be filtered, e.g.
+ * `{"id":"Q42","index":41,"offset":1234567,"length":89012,"error":"..."}`.
+ * The offset and length are enough to cut the entity out of the decompressed
+ * dump, e.g. with `tail -c +<offset + 1> | head -c <length>`.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+use crate::error::{ProcessError, Result};
+
+/// An entity which couldn't be filtered
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct EntityError {
+    pub id: Option<String>,
+    /// Position of the entity in the dump, counting from 0
+    pub index: usize,
+    /// Decompressed offset of the entity within the dump
+    pub offset: u64,
+    /// Length of the entity's JSON in bytes
+    pub length: usize,
+    pub error: String,
+}
+
+/// Writes `EntityError`s to a file, one per line
+pub struct ErrorReport {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl ErrorReport {
+    /// Creates the report at `path`, or adds to an existing one with `append`, e.g. when resuming a run
+    pub fn create(path: &Path, append: bool) -> Result<Self> {
+        let file = OpenOptions::new().create(true).write(true).append(append).truncate(!append).open(path)
+            .map_err(|source| ProcessError::ErrorReport { path: path.to_path_buf(), source })?;
+        Ok(ErrorReport { path: path.to_path_buf(), writer: BufWriter::new(file) })
+    }
+
+    pub fn write(&mut self, error: &EntityError) -> Result<()> {
+        let line = serde_json::to_string(error).expect("Entity errors can always be serialized");
+        writeln!(self.writer, "{}", line).map_err(|source| ProcessError::ErrorReport { path: self.path.clone(), source })
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush().map_err(|source| ProcessError::ErrorReport { path: self.path.clone(), source })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_error_report() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("report.ndjson");
+        let error = EntityError { id: Some(String::from("Q42")), index: 41, offset: 1234, length: 56, error: String::from("invalid") };
+        let mut report = ErrorReport::create(&path, false).unwrap();
+        report.write(&error).unwrap();
+        report.flush().unwrap();
+        let mut report = ErrorReport::create(&path, true).unwrap();
+        report.write(&EntityError { id: None, ..error.clone() }).unwrap();
+        report.flush().unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let errors: Vec<EntityError> = contents.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(errors, vec![error.clone(), EntityError { id: None, ..error }]);
+    }
+}
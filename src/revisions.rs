@@ -0,0 +1,154 @@
+/*!
+ * Keeping only the entities edited since some point, by the `modified`
+ * timestamp and `lastrevid` revision id every entity of a dump carries, so
+ * consumers synced from an older dump can extract just what changed since.
+ *
+ * Entities without the field compared are left out, as they can't be told to
+ * be recent.
+ */
+
+use std::borrow::Cow;
+use serde::Deserialize;
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+
+#[derive(Deserialize)]
+struct Outline<'a> {
+    #[serde(default, borrow)]
+    modified: Option<Cow<'a, str>>,
+    #[serde(default)]
+    lastrevid: Option<u64>,
+}
+
+/// A date (`2024-01-01`) or time (`2024-01-01T12:00:00Z`) as the full UTC timestamp entities are `modified` at,
+/// e.g. `2024-01-01T00:00:00Z`, so they can be compared as strings
+pub fn parse_timestamp(value: &str) -> std::result::Result<String, String> {
+    let invalid = || format!("Invalid timestamp '{}', expected a date like 2024-01-01 or a UTC time like 2024-01-01T12:00:00Z", value);
+    let timestamp = match value.len() {
+        10 => format!("{}T00:00:00Z", value),
+        20 => value.to_string(),
+        _ => return Err(invalid()),
+    };
+    let valid = timestamp.bytes().enumerate().all(|(i, byte)| match i {
+        4 | 7 => byte == b'-',
+        10 => byte == b'T',
+        13 | 16 => byte == b':',
+        19 => byte == b'Z',
+        _ => byte.is_ascii_digit(),
+    });
+    match valid {
+        true => Ok(timestamp),
+        false => Err(invalid()),
+    }
+}
+
+/// Which entities are recent enough: those modified after a time, and/or with a last revision after an id
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Since {
+    /// A timestamp as returned by `parse_timestamp`
+    pub modified_after: Option<String>,
+    pub revision_after: Option<u64>,
+}
+
+impl Since {
+    pub fn is_empty(&self) -> bool {
+        self.modified_after.is_none() && self.revision_after.is_none()
+    }
+}
+
+/// Applies another filter only to the entities modified since some point, dropping all others
+pub struct RevisionFilter {
+    since: Since,
+    filter: Box<dyn EntityFilter>,
+    continue_on_error: bool,
+    failures: usize,
+    error: Option<String>,
+}
+
+impl RevisionFilter {
+    pub fn new(since: Since, filter: Box<dyn EntityFilter>, continue_on_error: bool) -> Self {
+        RevisionFilter { since, filter, continue_on_error, failures: 0, error: None }
+    }
+
+    // whether `raw` was modified since
+    fn is_recent(&mut self, raw: &str) -> Result<bool> {
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                self.error = Some(error.to_string());
+                return Ok(false);
+            }
+        };
+        let modified = match &self.since.modified_after {
+            Some(after) => outline.modified.is_some_and(|modified| modified.as_ref() > after.as_str()),
+            None => true,
+        };
+        let revised = match self.since.revision_after {
+            Some(after) => outline.lastrevid.is_some_and(|revision| revision > after),
+            None => true,
+        };
+        Ok(modified && revised)
+    }
+}
+
+impl EntityFilter for RevisionFilter {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        match self.is_recent(raw)? {
+            true => self.filter.apply(raw),
+            false => Ok(None),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.failures + self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.error.take().or_else(|| self.filter.take_error())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::splitter;
+
+    struct Ids;
+
+    impl EntityFilter for Ids {
+        fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+            Ok(splitter::entity_id(raw).map(Cow::Borrowed))
+        }
+    }
+
+    const ENTITIES: [&str; 3] = [
+        r#"{"id":"Q1","lastrevid":100,"modified":"2023-12-31T23:59:59Z"}"#,
+        r#"{"id":"Q2","lastrevid":200,"modified":"2024-01-01T00:00:01Z"}"#,
+        r#"{"id":"Q3"}"#,
+    ];
+
+    fn recent(since: Since) -> Vec<String> {
+        let mut filter = RevisionFilter::new(since, Box::new(Ids), false);
+        ENTITIES.iter().filter_map(|raw| filter.apply(raw).unwrap()).map(Cow::into_owned).collect()
+    }
+
+    #[test]
+    fn test_parse_timestamp() {
+        assert_eq!(parse_timestamp("2024-01-01"), Ok(String::from("2024-01-01T00:00:00Z")));
+        assert_eq!(parse_timestamp("2024-01-01T12:30:00Z"), Ok(String::from("2024-01-01T12:30:00Z")));
+        assert!(parse_timestamp("2024-1-1").is_err());
+        assert!(parse_timestamp("2024-01-01 12:30:00").is_err());
+    }
+
+    #[test]
+    fn test_revision_filter() {
+        assert_eq!(recent(Since { modified_after: Some(parse_timestamp("2024-01-01").unwrap()), revision_after: None }), vec!["Q2"]);
+        assert_eq!(recent(Since { modified_after: None, revision_after: Some(99) }), vec!["Q1", "Q2"]);
+        assert_eq!(recent(Since { modified_after: Some(parse_timestamp("2023-01-01").unwrap()), revision_after: Some(150) }), vec!["Q2"]);
+
+        let mut filter = RevisionFilter::new(Since { modified_after: None, revision_after: Some(1) }, Box::new(Ids), false);
+        assert!(filter.apply("{\"id\":\"Q1\",").is_err());
+    }
+}
@@ -0,0 +1,202 @@
+/*!
+ * RocksDB stores of outputs keyed by the id of their entity (with the
+ * `rocksdb` feature), built in a single pass over a dump and ready to serve
+ * random lookups.
+ *
+ * Rather than putting outputs one at a time, `RocksDbSink` gathers them until
+ * `sst_size` bytes are waiting, sorts them by id and writes them to an SST
+ * file, which is ingested into the database as it is. Once the run is done
+ * the database is compacted, so lookups find an id in a single sorted run.
+ * Outputs are stored as-is and compressed with zstd by RocksDB, a block of
+ * entities at a time. An id seen more than once is stored with its last
+ * output.
+ */
+
+use std::path::{Path, PathBuf};
+use log::debug;
+use rocksdb::{DBCompressionType, IngestExternalFileOptions, Options, SstFileWriter, DB};
+use crate::error::{ProcessError, Result};
+use crate::sink::Sink;
+use crate::splitter;
+
+/// Bytes of outputs written to each SST file by default
+pub const DEFAULT_SST_SIZE: usize = 64 * 1024 * 1024;
+
+fn rocksdb_error(error: rocksdb::Error) -> ProcessError {
+    ProcessError::RocksDb(error.to_string())
+}
+
+// options of the stores, and the SST files ingested into them
+fn options() -> Options {
+    let mut options = Options::default();
+    options.create_if_missing(true);
+    options.set_compression_type(DBCompressionType::Zstd);
+    options
+}
+
+/// Writes outputs into a new RocksDB database keyed by entity id, see the module documentation
+pub struct RocksDbSink {
+    db: DB,
+    directory: PathBuf,
+    sst_size: usize,
+    // ids and outputs waiting to be written to an SST file, and their size
+    pending: Vec<(String, String)>,
+    pending_bytes: usize,
+    files: usize,
+    written: u64,
+}
+
+impl RocksDbSink {
+    /// Creates the database at `path`. If there is one already, it's destroyed first when `overwrite` is set, otherwise
+    /// this fails, as it does if `path` is anything but a RocksDB database.
+    pub fn create(path: &Path, overwrite: bool, sst_size: usize) -> Result<Self> {
+        if path.exists() {
+            if !overwrite {
+                return Err(ProcessError::OutputExists(path.to_path_buf()));
+            }
+            if !path.join("CURRENT").is_file() {
+                return Err(ProcessError::RocksDb(format!("{:?} isn't a RocksDB database, so it isn't overwritten", path)));
+            }
+            DB::destroy(&options(), path).map_err(rocksdb_error)?;
+        }
+        let mut options = options();
+        // the database is compacted once everything is ingested instead
+        options.prepare_for_bulk_load();
+        let db = DB::open(&options, path).map_err(rocksdb_error)?;
+        Ok(RocksDbSink { db, directory: path.to_path_buf(), sst_size, pending: Vec::new(), pending_bytes: 0, files: 0, written: 0 })
+    }
+
+    /// Number of outputs written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // writes the waiting outputs to an SST file, and moves it into the database
+    fn ingest(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        // a stable sort keeps the outputs of an id in order, so the last one is kept
+        self.pending.sort_by(|(id, _), (other, _)| id.cmp(other));
+        self.pending.dedup_by(|next, kept| {
+            let duplicate = next.0 == kept.0;
+            if duplicate {
+                std::mem::swap(next, kept);
+            }
+            duplicate
+        });
+        let path = self.directory.join(format!("ingest-{}.sst", self.files));
+        let mut writer = SstFileWriter::create(&options());
+        writer.open(&path).map_err(rocksdb_error)?;
+        for (id, output) in &self.pending {
+            writer.put(id, output).map_err(rocksdb_error)?;
+        }
+        writer.finish().map_err(rocksdb_error)?;
+        let mut ingest = IngestExternalFileOptions::default();
+        ingest.set_move_files(true);
+        self.db.ingest_external_file_opts(&ingest, vec![&path]).map_err(rocksdb_error)?;
+        debug!("Ingested {} entities from {:?}", self.pending.len(), path);
+        self.written += self.pending.len() as u64;
+        self.files += 1;
+        self.pending.clear();
+        self.pending_bytes = 0;
+        Ok(())
+    }
+}
+
+impl Sink for RocksDbSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let id = splitter::entity_id(output)
+            .ok_or_else(|| ProcessError::RocksDb(format!("Output has no id to key it by, the jq filter has to keep it: {:.100}", output)))?;
+        self.pending_bytes += id.len() + output.len();
+        self.pending.push((id.to_string(), output.to_string()));
+        if self.pending_bytes >= self.sst_size {
+            self.ingest()?;
+        }
+        Ok(())
+    }
+
+    // outputs only go into the database a whole SST file at a time
+    fn flush(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Ingests the last outputs and compacts the database
+    fn finalize(&mut self) -> Result<()> {
+        self.ingest()?;
+        self.db.compact_range(None::<&[u8]>, None::<&[u8]>);
+        self.db.flush().map_err(rocksdb_error)
+    }
+}
+
+/// A database written by `RocksDbSink`, opened read-only for lookups
+pub struct RocksDbStore {
+    db: DB,
+}
+
+impl RocksDbStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        let db = DB::open_for_read_only(&options(), path, false).map_err(rocksdb_error)?;
+        Ok(RocksDbStore { db })
+    }
+
+    /// The output stored for the entity `id`, if any
+    pub fn get(&self, id: &str) -> Result<Option<String>> {
+        let output = self.db.get(id).map_err(rocksdb_error)?;
+        output.map(|output| String::from_utf8(output).map_err(|error| ProcessError::RocksDb(error.to_string()))).transpose()
+    }
+
+    /// Number of entities in the store, as estimated by RocksDB, which is close once the store is compacted
+    pub fn estimated_len(&self) -> Result<u64> {
+        Ok(self.db.property_int_value("rocksdb.estimate-num-keys").map_err(rocksdb_error)?.unwrap_or(0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pipeline;
+
+    #[test]
+    fn test_rocksdb_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.db");
+        let mut sink = RocksDbSink::create(&path, false, 64).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"Earth"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q1","label":"universe"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"the Earth"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q3","label":"life"}"#).unwrap();
+        assert!(matches!(sink.write_entity(r#"["Q4"]"#), Err(ProcessError::RocksDb(_))));
+        sink.finalize().unwrap();
+        assert_eq!(sink.written(), 3);
+        drop(sink);
+
+        let store = RocksDbStore::open(&path).unwrap();
+        assert_eq!(store.get("Q2").unwrap().as_deref(), Some(r#"{"id":"Q2","label":"the Earth"}"#));
+        assert_eq!(store.get("Q3").unwrap().as_deref(), Some(r#"{"id":"Q3","label":"life"}"#));
+        assert_eq!(store.get("Q4").unwrap(), None);
+        assert_eq!(store.estimated_len().unwrap(), 3);
+        drop(store);
+
+        assert!(matches!(RocksDbSink::create(&path, false, 64), Err(ProcessError::OutputExists(_))));
+        assert!(RocksDbSink::create(&path, true, 64).is_ok());
+        assert!(matches!(RocksDbSink::create(directory.path(), true, 64), Err(ProcessError::RocksDb(_))));
+    }
+
+    #[test]
+    fn test_pipeline_into_rocksdb() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.db");
+        let sink = RocksDbSink::create(&path, false, DEFAULT_SST_SIZE).unwrap();
+        let stats = Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .filter("{id, type}")
+            .entity_sink(sink)
+            .build().unwrap()
+            .run().unwrap();
+        let store = RocksDbStore::open(&path).unwrap();
+        let output: serde_json::Value = serde_json::from_str(&store.get("Q60").unwrap().unwrap()).unwrap();
+        assert_eq!(output, serde_json::json!({"id": "Q60", "type": "item"}));
+        assert!(stats.entities_written > 0);
+    }
+}
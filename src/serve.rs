@@ -0,0 +1,511 @@
+/*!
+ * A small read-only HTTP API over the artifacts built from a dump, so a team
+ * can run a private entity service straight from it:
+ *
+ * - `GET /entity/<id>` returns an entity as it is in the dump, read through
+ *   the dump's index (see `index`), or its output as `filter` stored it in a
+ *   RocksDB, LMDB or SQLite database (with the `rocksdb`, `lmdb` or `sqlite`
+ *   feature)
+ * - `GET /label/<id>` returns `{"id": ..., "label": ...}` from a label map
+ *   (see `labels`)
+ * - `GET /search?q=<query>&limit=<n>` returns the best matches of a query in a
+ *   text index (see `text_index`, with the `tantivy` feature)
+ * - `GET /` returns how many entities each of them holds
+ *
+ * Each endpoint is only there when its artifact was given. Responses are
+ * JSON, errors included (`{"error": ...}`), and each connection is answered
+ * and then closed. A fixed number of workers answer connections, so a burst
+ * of clients waits for one to be free rather than each getting a thread.
+ */
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+use log::debug;
+use serde_json::{json, Value};
+use crate::error::{ProcessError, Result};
+use crate::index::{self, Index};
+use crate::labels::LabelMap;
+#[cfg(feature = "lmdb")]
+use crate::lmdb_store::LmdbStore;
+#[cfg(feature = "rocksdb")]
+use crate::rocksdb_store::RocksDbStore;
+#[cfg(feature = "sqlite")]
+use crate::sqlite_sink::SqliteStore;
+#[cfg(feature = "tantivy")]
+use crate::text_index::TextSearch;
+
+/// Number of matches returned by a search without a `limit`
+pub const DEFAULT_LIMIT: usize = 10;
+
+/// Most matches a single search returns, whatever its `limit`
+pub const MAX_LIMIT: usize = 100;
+
+/// Connections answered at the same time by default
+pub const DEFAULT_WORKERS: usize = 16;
+
+// how long a client may take to send its request before it's dropped
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+// longest request head read, which is plenty for the URLs of this API
+const MAX_REQUEST_LENGTH: usize = 16 * 1024;
+
+/// Status and JSON body of an answer to a request
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: &Value) -> Self {
+        Response { status, body: body.to_string() }
+    }
+
+    fn error(status: u16, message: impl std::fmt::Display) -> Self {
+        Response::json(status, &json!({ "error": message.to_string() }))
+    }
+
+    fn failure(error: ProcessError) -> Self {
+        match error {
+            ProcessError::InvalidQuery(_) => Response::error(400, error),
+            _ => Response::error(500, error),
+        }
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+// a dump and its index, read from one request at a time
+struct Dump {
+    index: Index,
+    dump: File,
+}
+
+// where entities are served from
+enum Entities {
+    Dump(Mutex<Dump>),
+    #[cfg(feature = "rocksdb")]
+    RocksDb(RocksDbStore),
+    #[cfg(feature = "lmdb")]
+    Lmdb(LmdbStore),
+    #[cfg(feature = "sqlite")]
+    Sqlite(Mutex<SqliteStore>),
+}
+
+impl Entities {
+    // what the entities are in, for telling which one is missing
+    fn name(&self) -> &'static str {
+        match self {
+            Entities::Dump(_) => "dump",
+            #[cfg(feature = "rocksdb")]
+            Entities::RocksDb(_) => "RocksDB database",
+            #[cfg(feature = "lmdb")]
+            Entities::Lmdb(_) => "LMDB database",
+            #[cfg(feature = "sqlite")]
+            Entities::Sqlite(_) => "SQLite database",
+        }
+    }
+
+    fn get(&self, id: &str) -> Result<Option<String>> {
+        match self {
+            Entities::Dump(dump) => {
+                let mut dump = dump.lock().expect("Dump poisoned");
+                let Dump { index, dump } = &mut *dump;
+                index.lookup(id)?.map(|entry| index::read_entity(dump, &entry)).transpose()
+            }
+            #[cfg(feature = "rocksdb")]
+            Entities::RocksDb(store) => store.get(id),
+            #[cfg(feature = "lmdb")]
+            Entities::Lmdb(store) => store.get(id),
+            #[cfg(feature = "sqlite")]
+            Entities::Sqlite(store) => store.lock().expect("SqliteStore poisoned").get(id),
+        }
+    }
+
+    // how many there are, estimated for RocksDB
+    fn len(&self) -> Result<u64> {
+        match self {
+            Entities::Dump(dump) => Ok(dump.lock().expect("Dump poisoned").index.len()),
+            #[cfg(feature = "rocksdb")]
+            Entities::RocksDb(store) => store.estimated_len(),
+            #[cfg(feature = "lmdb")]
+            Entities::Lmdb(store) => store.len(),
+            #[cfg(feature = "sqlite")]
+            Entities::Sqlite(store) => store.lock().expect("SqliteStore poisoned").len(),
+        }
+    }
+}
+
+/// The artifacts served, and how requests are answered from them
+#[derive(Default)]
+pub struct Service {
+    entities: Option<Entities>,
+    labels: Option<Mutex<LabelMap>>,
+    #[cfg(feature = "tantivy")]
+    search: Option<TextSearch>,
+}
+
+impl Service {
+    /// Serves the entities of the dump at `dump` from its index at `index` (see `index::index_path`)
+    pub fn with_dump(mut self, dump: &Path, index: &Path) -> Result<Self> {
+        let index = Index::open(index)?;
+        let dump = index.open_dump(dump)?;
+        self.entities = Some(Entities::Dump(Mutex::new(Dump { index, dump })));
+        Ok(self)
+    }
+
+    /// Serves entities from the RocksDB database at `path` written by `filter`, instead of a dump
+    #[cfg(feature = "rocksdb")]
+    pub fn with_rocksdb(mut self, path: &Path) -> Result<Self> {
+        self.entities = Some(Entities::RocksDb(RocksDbStore::open(path)?));
+        Ok(self)
+    }
+
+    /// Serves entities from the LMDB database at `path` written by `filter`, instead of a dump
+    #[cfg(feature = "lmdb")]
+    pub fn with_lmdb(mut self, path: &Path) -> Result<Self> {
+        self.entities = Some(Entities::Lmdb(LmdbStore::open(path)?));
+        Ok(self)
+    }
+
+    /// Serves entities from the SQLite database at `path` written by `filter`, instead of a dump
+    #[cfg(feature = "sqlite")]
+    pub fn with_sqlite(mut self, path: &Path) -> Result<Self> {
+        self.entities = Some(Entities::Sqlite(Mutex::new(SqliteStore::open(path)?)));
+        Ok(self)
+    }
+
+    /// Serves the labels of the label map at `path`
+    pub fn with_labels(mut self, path: &Path) -> Result<Self> {
+        self.labels = Some(Mutex::new(LabelMap::open(path)?));
+        Ok(self)
+    }
+
+    /// Serves searches of the text index in `directory`
+    #[cfg(feature = "tantivy")]
+    pub fn with_text_index(mut self, directory: &Path) -> Result<Self> {
+        self.search = Some(TextSearch::open(directory)?);
+        Ok(self)
+    }
+
+    /// Answers a request for `target` (the path and query of the URL) with `method`
+    pub fn respond(&self, method: &str, target: &str) -> Response {
+        if method != "GET" && method != "HEAD" {
+            return Response::error(405, format!("{} is not supported, only GET is", method));
+        }
+        let (path, query) = target.split_once('?').unwrap_or((target, ""));
+        let path = decode(path);
+        if let Some(id) = path.strip_prefix("/entity/") {
+            return self.entity(id);
+        }
+        if let Some(id) = path.strip_prefix("/label/") {
+            return self.label(id);
+        }
+        match path.as_str() {
+            "/" => self.summary(),
+            "/search" => self.search(query),
+            _ => Response::error(404, format!("Nothing at {}", path)),
+        }
+    }
+
+    fn entity(&self, id: &str) -> Response {
+        let entities = match &self.entities {
+            Some(entities) => entities,
+            None => return Response::error(404, "No entities are served, start the server with --input, --rocksdb, --lmdb or --sqlite"),
+        };
+        match entities.get(id) {
+            Ok(Some(entity)) => Response { status: 200, body: entity },
+            Ok(None) => Response::error(404, format!("Entity {} is not in the {}", id, entities.name())),
+            Err(error) => Response::failure(error),
+        }
+    }
+
+    fn label(&self, id: &str) -> Response {
+        let labels = match &self.labels {
+            Some(labels) => labels,
+            None => return Response::error(404, "No label map is served, start the server with --labels"),
+        };
+        match labels.lock().expect("LabelMap poisoned").get(id) {
+            Ok(Some(label)) => Response::json(200, &json!({ "id": id, "label": label })),
+            Ok(None) => Response::error(404, format!("Entity {} has no label in the label map", id)),
+            Err(error) => Response::failure(error),
+        }
+    }
+
+    fn summary(&self) -> Response {
+        let entities = match self.entities.as_ref().map(Entities::len).transpose() {
+            Ok(entities) => entities,
+            Err(error) => return Response::failure(error),
+        };
+        let labels = self.labels.as_ref().map(|labels| labels.lock().expect("LabelMap poisoned").len());
+        Response::json(200, &json!({ "entities": entities, "labels": labels, "search": self.search_len() }))
+    }
+
+    #[cfg(feature = "tantivy")]
+    fn search_len(&self) -> Option<u64> {
+        self.search.as_ref().map(TextSearch::len)
+    }
+
+    #[cfg(not(feature = "tantivy"))]
+    fn search_len(&self) -> Option<u64> {
+        None
+    }
+
+    #[cfg(feature = "tantivy")]
+    fn search(&self, query: &str) -> Response {
+        let search = match &self.search {
+            Some(search) => search,
+            None => return Response::error(404, "No text index is served, start the server with --text-index"),
+        };
+        let (q, limit) = match parse_search(query) {
+            Ok(parameters) => parameters,
+            Err(message) => return Response::error(400, message),
+        };
+        match search.search(&q, limit) {
+            Ok(hits) => Response::json(200, &json!({ "hits": hits })),
+            Err(error) => Response::failure(error),
+        }
+    }
+
+    #[cfg(not(feature = "tantivy"))]
+    fn search(&self, _query: &str) -> Response {
+        Response::error(404, "Searching needs a build with the tantivy feature")
+    }
+}
+
+// the query and number of matches asked for by the query string of a search
+#[cfg_attr(not(feature = "tantivy"), allow(dead_code))]
+fn parse_search(query: &str) -> std::result::Result<(String, usize), String> {
+    let mut q = None;
+    let mut limit = DEFAULT_LIMIT;
+    for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+        let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+        match name {
+            "q" => q = Some(decode(value)),
+            "limit" => limit = decode(value).parse().map_err(|_| format!("Invalid limit '{}'", value))?,
+            _ => {}
+        }
+    }
+    match q {
+        Some(q) if !q.trim().is_empty() => Ok((q, limit.min(MAX_LIMIT))),
+        _ => Err(String::from("Missing the query to search for, e.g. /search?q=douglas+adams")),
+    }
+}
+
+// decodes a percent-encoded URL component, with `+` for spaces. Invalid escapes are kept as they are
+fn decode(component: &str) -> String {
+    let bytes = component.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => match component.get(i + 1..i + 3).and_then(|hex| u8::from_str_radix(hex, 16).ok()) {
+                Some(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                    continue;
+                }
+                None => decoded.push(b'%'),
+            },
+            b'+' => decoded.push(b' '),
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Answers every request to `listener` with `service`, `workers` connections at a time, for as long as the process
+/// runs. Connections past those wait in the listener's backlog until a worker is free.
+pub fn serve(listener: TcpListener, service: Arc<Service>, workers: usize) -> io::Result<()> {
+    let workers = (0..workers.max(1))
+        .map(|_| {
+            let listener = listener.try_clone()?;
+            let service = Arc::clone(&service);
+            Ok(thread::spawn(move || accept(listener, &service)))
+        })
+        .collect::<io::Result<Vec<_>>>()?;
+    for worker in workers {
+        worker.join().expect("Worker panicked");
+    }
+    Ok(())
+}
+
+// answers the connections to `listener`, one at a time
+fn accept(listener: TcpListener, service: &Service) {
+    for stream in listener.incoming() {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(error) => {
+                debug!("Could not accept connection: {}", error);
+                continue;
+            }
+        };
+        if let Err(error) = answer(stream, service) {
+            debug!("Could not answer request: {}", error);
+        }
+    }
+}
+
+// reads a single request and answers it, whatever headers it came with
+fn answer(mut stream: TcpStream, service: &Service) -> io::Result<()> {
+    stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+    let mut request = Vec::new();
+    let mut buffer = [0; 1024];
+    while !request.windows(4).any(|window| window == b"\r\n\r\n") && request.len() < MAX_REQUEST_LENGTH {
+        let n = stream.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        request.extend_from_slice(&buffer[..n]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (line.next().unwrap_or_default(), line.next().unwrap_or("/"));
+    let response = match request.contains("\r\n\r\n") {
+        true => service.respond(method, target),
+        false => Response::error(431, "Request too long"),
+    };
+    debug!("{} {} {}", method, target, response.status);
+
+    write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", response.status, response.reason(), response.body.len())?;
+    if method != "HEAD" {
+        stream.write_all(response.body.as_bytes())?;
+    }
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::labels::{self, LabelOptions};
+    use crate::process::ProcessOptions;
+    use crate::source::FileSource;
+
+    const DUMP: &str = "./tests/test-data.json.bz2";
+
+    fn service(directory: &Path) -> Service {
+        let (mut entries, dump_size) = index::build_index(Path::new(DUMP), crate::Progress::Hidden).unwrap();
+        let index = directory.join("test-data.json.bz2.idx");
+        index::write_index(&mut entries, dump_size, File::create(&index).unwrap()).unwrap();
+        let labels = directory.join("labels.map");
+        let label_options = LabelOptions { languages: vec![String::from("en")], ..LabelOptions::default() };
+        labels::write_label_map(FileSource::new(DUMP), label_options, ProcessOptions::default(), File::create(&labels).unwrap()).unwrap();
+        Service::default().with_dump(Path::new(DUMP), &index).unwrap().with_labels(&labels).unwrap()
+    }
+
+    #[test]
+    fn test_respond() {
+        let directory = tempfile::tempdir().unwrap();
+        let service = service(directory.path());
+
+        let response = service.respond("GET", "/entity/Q60");
+        assert_eq!(response.status, 200);
+        assert_eq!(serde_json::from_str::<Value>(&response.body).unwrap()["id"], "Q60");
+        assert_eq!(service.respond("GET", "/entity/Q61").status, 404);
+        assert_eq!(service.respond("GET", "/label/Q60").body, r#"{"id":"Q60","label":"New York City"}"#);
+        assert_eq!(service.respond("GET", "/label/Q3").status, 404);
+        assert_eq!(service.respond("GET", "/").body, r#"{"entities":8,"labels":7,"search":null}"#);
+        assert_eq!(service.respond("GET", "/search?q=york").status, 404);
+        assert_eq!(service.respond("GET", "/nothing").status, 404);
+        assert_eq!(service.respond("POST", "/entity/Q60").status, 405);
+        assert_eq!(Service::default().respond("GET", "/entity/Q60").status, 404);
+    }
+
+    #[test]
+    fn test_parse_search() {
+        assert_eq!(parse_search("q=new+york%20city&limit=3"), Ok((String::from("new york city"), 3)));
+        assert_eq!(parse_search("limit=1000&q=%22nyc%22"), Ok((String::from("\"nyc\""), MAX_LIMIT)));
+        assert_eq!(parse_search("q=york"), Ok((String::from("york"), DEFAULT_LIMIT)));
+        assert!(parse_search("q=york&limit=ten").is_err());
+        assert!(parse_search("limit=1").is_err());
+        assert_eq!(decode("100%25%zz%e2%82%ac"), "100%%zz€");
+    }
+
+    #[test]
+    fn test_serve() {
+        let directory = tempfile::tempdir().unwrap();
+        let service = Arc::new(service(directory.path()));
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || serve(listener, service, 1));
+
+        // the only worker answers one connection after the other
+        for _ in 0..2 {
+            let mut stream = TcpStream::connect(address).unwrap();
+            stream.write_all(b"GET /label/Q60 HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+            let mut response = String::new();
+            stream.read_to_string(&mut response).unwrap();
+            assert!(response.starts_with("HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n"), "{}", response);
+            assert!(response.ends_with("\r\n\r\n{\"id\":\"Q60\",\"label\":\"New York City\"}"), "{}", response);
+        }
+    }
+
+    // checks entities are served from a database filter wrote with `sink`, as they were stored
+    #[cfg(any(feature = "rocksdb", feature = "lmdb", feature = "sqlite"))]
+    fn check_store(sink: impl crate::Sink, service: impl FnOnce() -> Service) {
+        crate::Pipeline::builder().source(DUMP).filter("{id, type}").entity_sink(sink).build().unwrap().run().unwrap();
+        let service = service();
+        assert_eq!(service.respond("GET", "/entity/Q60").body, r#"{"id":"Q60","type":"item"}"#);
+        assert_eq!(service.respond("GET", "/entity/Q61").status, 404);
+        assert_eq!(service.respond("GET", "/").status, 200);
+    }
+
+    #[cfg(feature = "rocksdb")]
+    #[test]
+    fn test_serve_rocksdb() {
+        use crate::rocksdb_store::{RocksDbSink, DEFAULT_SST_SIZE};
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.db");
+        check_store(RocksDbSink::create(&path, false, DEFAULT_SST_SIZE).unwrap(), || Service::default().with_rocksdb(&path).unwrap());
+    }
+
+    #[cfg(feature = "lmdb")]
+    #[test]
+    fn test_serve_lmdb() {
+        use crate::lmdb_store::LmdbSink;
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.lmdb");
+        check_store(LmdbSink::create(&path, false, 1 << 20, 64).unwrap(), || Service::default().with_lmdb(&path).unwrap());
+        assert_eq!(Service::default().with_lmdb(&path).unwrap().respond("GET", "/").body, r#"{"entities":8,"labels":null,"search":null}"#);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_serve_sqlite() {
+        use crate::sqlite_sink::SqliteSink;
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.db");
+        check_store(SqliteSink::create(&path, false, 64).unwrap(), || Service::default().with_sqlite(&path).unwrap());
+        assert_eq!(Service::default().with_sqlite(&path).unwrap().respond("GET", "/").body, r#"{"entities":8,"labels":null,"search":null}"#);
+    }
+
+    #[cfg(feature = "tantivy")]
+    #[test]
+    fn test_search() {
+        let directory = tempfile::tempdir().unwrap();
+        let text_index = directory.path().join("text");
+        crate::text_index::write_text_index(FileSource::new(DUMP), vec![String::from("en")], 50_000_000, ProcessOptions::default(), &text_index).unwrap();
+        let service = Service::default().with_text_index(&text_index).unwrap();
+        let response = service.respond("GET", "/search?q=label_en%3Ayork&limit=1");
+        assert_eq!(response.status, 200);
+        let hits: Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(hits["hits"][0]["id"], "Q60");
+        assert_eq!(service.respond("GET", "/search?q=label_en%3A(york").status, 400);
+        assert_eq!(service.respond("GET", "/search").status, 400);
+    }
+}
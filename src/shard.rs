@@ -0,0 +1,393 @@
+/*!
+ * Splitting entities into a fixed number of shards by their id, the same way
+ * on every machine and every run, so shards made separately line up, and so
+ * machines can each filter one shard of the same dump without coordinating.
+ */
+
+use std::borrow::Cow;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::thread::{self, JoinHandle};
+use crate::error::{ProcessError, Result};
+use crate::filter::EntityFilter;
+use crate::index::parse_id;
+use crate::sink::Sink;
+use crate::splitter;
+
+// FNV-1a, which unlike std's hashers is specified, so shards don't move between Rust versions
+pub(crate) fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, &byte| (hash ^ byte as u64).wrapping_mul(0x100000001b3))
+}
+
+/// The shard, out of `shards`, that an entity (or the output of one) belongs to, counting from 0.
+///
+/// Ids like Q42 are hashed as their letter and number, any other id as text, and outputs without an id as a
+/// whole.
+pub fn shard_of(entity: &str, shards: usize) -> usize {
+    let hash = match splitter::entity_id(entity) {
+        Some(id) => match parse_id(id) {
+            Some((letter, number)) => fnv1a(&[&[letter][..], &number.to_le_bytes()].concat()),
+            None => fnv1a(id.as_bytes()),
+        },
+        None => fnv1a(entity.as_bytes()),
+    };
+    (hash % shards as u64) as usize
+}
+
+/// Where shard `shard` of the output `path` goes: `out.ndjson` becomes `out.3.ndjson`, or `out.3` without an extension
+pub fn shard_path(path: &Path, shard: usize) -> PathBuf {
+    part_path(path, &shard.to_string())
+}
+
+// `path` with `part` added before its extension, or at the end without one
+pub(crate) fn part_path(path: &Path, part: &str) -> PathBuf {
+    match (path.file_stem(), path.extension()) {
+        (Some(stem), Some(extension)) => {
+            let mut name = stem.to_owned();
+            name.push(format!(".{}.", part));
+            name.push(extension);
+            path.with_file_name(name)
+        }
+        _ => {
+            let mut name = path.as_os_str().to_owned();
+            name.push(format!(".{}", part));
+            name.into()
+        }
+    }
+}
+
+/// One shard out of several, e.g. `3/8` for shard 3 of 8, counting from 0 as `shard_path` does
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Shard {
+    pub index: usize,
+    pub count: usize,
+}
+
+impl Shard {
+    /// Whether `entity` (or the output of one) belongs to this shard, see `shard_of`
+    pub fn contains(&self, entity: &str) -> bool {
+        shard_of(entity, self.count) == self.index
+    }
+}
+
+impl FromStr for Shard {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        let invalid = || format!("Invalid shard '{}', expected <shard>/<shards> counting from 0, e.g. 3/8", value);
+        let (index, count) = value.split_once('/').ok_or_else(invalid)?;
+        match (index.trim().parse(), count.trim().parse()) {
+            (Ok(index), Ok(count)) if index < count => Ok(Shard { index, count }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// Drops the entities of other shards, passing those of its own on to another filter
+pub struct ShardFilter {
+    shard: Shard,
+    filter: Box<dyn EntityFilter>,
+}
+
+impl ShardFilter {
+    pub fn new(shard: Shard, filter: Box<dyn EntityFilter>) -> Self {
+        ShardFilter { shard, filter }
+    }
+}
+
+impl EntityFilter for ShardFilter {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        match self.shard.contains(raw) {
+            true => self.filter.apply(raw),
+            false => Ok(None),
+        }
+    }
+
+    fn failures(&self) -> usize {
+        self.filter.failures()
+    }
+
+    fn take_error(&mut self) -> Option<String> {
+        self.filter.take_error()
+    }
+}
+
+/// Hands each output to one of several sinks, picked with `shard_of`
+pub struct ShardedSink<S: Sink> {
+    sinks: Vec<S>,
+}
+
+impl<S: Sink> ShardedSink<S> {
+    /// Shards over `sinks`, of which there has to be at least one
+    pub fn new(sinks: Vec<S>) -> Self {
+        assert!(!sinks.is_empty(), "No shards to write to");
+        ShardedSink { sinks }
+    }
+}
+
+impl<S: Sink> Sink for ShardedSink<S> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let shard = shard_of(output, self.sinks.len());
+        self.sinks[shard].write_entity(output)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sinks.iter_mut().try_for_each(Sink::flush)
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.sinks.iter_mut().try_for_each(Sink::finalize)
+    }
+}
+
+// how many bytes of outputs are held for a writer before they're handed to it
+const WRITER_BATCH_SIZE: usize = 1 << 20;
+
+// batches handed to each writer and not yet written, so the router only gets ahead of a slow writer so far
+const WRITER_QUEUE: usize = 4;
+
+enum WriterMessage {
+    /// Outputs joined together, with the shard of each and where it ends
+    Outputs(String, Vec<(usize, usize)>),
+    /// Flush the sinks, answering once done
+    Flush(SyncSender<Result<()>>),
+}
+
+// writes the batches of some shards on a thread of its own, finalizing their sinks once there are no more. Shard
+// `shard` is `sinks[shard / writers]`, as writer `w` has shards `w`, `w + writers`...
+fn write_shards<S: Sink>(mut sinks: Vec<S>, writers: usize, messages: Receiver<WriterMessage>) -> Result<()> {
+    for message in messages {
+        match message {
+            WriterMessage::Outputs(outputs, ends) => {
+                let mut start = 0;
+                for (shard, end) in ends {
+                    sinks[shard / writers].write_entity(&outputs[start..end])?;
+                    start = end;
+                }
+            }
+            WriterMessage::Flush(done) => {
+                let flushed = sinks.iter_mut().try_for_each(Sink::flush);
+                let failed = flushed.is_err();
+                let _ = done.send(flushed);
+                if failed {
+                    return Ok(());
+                }
+            }
+        }
+    }
+    sinks.iter_mut().try_for_each(Sink::finalize)
+}
+
+// a writer thread and the outputs waiting to be handed to it
+struct ShardWriter {
+    outputs: String,
+    ends: Vec<(usize, usize)>,
+    messages: Option<SyncSender<WriterMessage>>,
+    thread: Option<JoinHandle<Result<()>>>,
+}
+
+impl ShardWriter {
+    fn send(&mut self, message: WriterMessage) -> Result<()> {
+        let sent = self.messages.as_ref().map(|messages| messages.send(message).is_ok()).unwrap_or(false);
+        match sent {
+            true => Ok(()),
+            // a writer only stops taking messages early when it failed, so its error is the one to report
+            false => Err(self.join().err().unwrap_or_else(|| ProcessError::Write(io::Error::other("Shard writer already finished")))),
+        }
+    }
+
+    fn send_outputs(&mut self) -> Result<()> {
+        if self.ends.is_empty() {
+            return Ok(());
+        }
+        let outputs = mem::take(&mut self.outputs);
+        let ends = mem::take(&mut self.ends);
+        self.send(WriterMessage::Outputs(outputs, ends))
+    }
+
+    // waits for the writer to be done, once its messages are closed
+    fn join(&mut self) -> Result<()> {
+        self.messages = None;
+        match self.thread.take() {
+            Some(thread) => thread.join().expect("Shard writer panicked"),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Hands each output to one of several sinks, picked with `shard_of` as `ShardedSink` does, but writes the shards
+/// on writer threads, so compressing them doesn't hold back the run on a single thread.
+///
+/// There's a writer per shard, up to `threads` of them, past which each writer takes several shards. Outputs are
+/// routed to writers in batches, and the sinks finalized on their writer's thread once `finalize` is called.
+pub struct ParallelShardedSink {
+    shards: usize,
+    writers: Vec<ShardWriter>,
+}
+
+impl ParallelShardedSink {
+    /// Shards over `sinks`, of which there has to be at least one, starting up to `threads` writer threads
+    pub fn new<S: Sink + Send + 'static>(sinks: Vec<S>, threads: usize) -> Self {
+        assert!(!sinks.is_empty(), "No shards to write to");
+        let shards = sinks.len();
+        let count = threads.clamp(1, shards);
+        let mut assigned = (0..count).map(|_| Vec::new()).collect::<Vec<_>>();
+        for (shard, sink) in sinks.into_iter().enumerate() {
+            assigned[shard % count].push(sink);
+        }
+        let writers = assigned.into_iter()
+            .enumerate()
+            .map(|(writer, sinks)| {
+                let (messages, received) = mpsc::sync_channel(WRITER_QUEUE);
+                let thread = thread::Builder::new()
+                    .name(format!("shard-writer-{}", writer))
+                    .spawn(move || write_shards(sinks, count, received))
+                    .expect("Could not start a shard writer thread");
+                ShardWriter { outputs: String::new(), ends: Vec::new(), messages: Some(messages), thread: Some(thread) }
+            })
+            .collect();
+        ParallelShardedSink { shards, writers }
+    }
+}
+
+impl Sink for ParallelShardedSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let shard = shard_of(output, self.shards);
+        let count = self.writers.len();
+        let writer = &mut self.writers[shard % count];
+        writer.outputs.push_str(output);
+        writer.ends.push((shard, writer.outputs.len()));
+        match writer.outputs.len() >= WRITER_BATCH_SIZE {
+            true => writer.send_outputs(),
+            false => Ok(()),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        let mut answers = Vec::with_capacity(self.writers.len());
+        for writer in &mut self.writers {
+            writer.send_outputs()?;
+            let (done, answer) = mpsc::sync_channel(1);
+            writer.send(WriterMessage::Flush(done))?;
+            answers.push(answer);
+        }
+        for (writer, answer) in self.writers.iter_mut().zip(answers) {
+            match answer.recv() {
+                Ok(flushed) => flushed?,
+                Err(_) => writer.join()?,
+            }
+        }
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        let mut result = Ok(());
+        for writer in &mut self.writers {
+            result = result.and(writer.send_outputs());
+        }
+        // every writer is waited for, even after one failed, so none is left writing
+        for writer in &mut self.writers {
+            result = result.and(writer.join());
+        }
+        result
+    }
+}
+
+impl Drop for ParallelShardedSink {
+    fn drop(&mut self) {
+        for writer in &mut self.writers {
+            let _ = writer.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::sink::WriteSink;
+
+    #[test]
+    fn test_shard_of() {
+        let entities = (1..=1000).map(|n| format!("{{\"id\": \"Q{}\"}}", n)).collect::<Vec<_>>();
+        let mut counts = [0; 4];
+        for entity in &entities {
+            counts[shard_of(entity, 4)] += 1;
+        }
+        assert!(counts.iter().all(|&count| count > 200), "{:?}", counts);
+        // the same entity always goes to the same shard, whatever else is in its output
+        assert_eq!(shard_of("{\"id\": \"Q42\"}", 8), shard_of("{\"id\":\"Q42\",\"type\":\"item\"}", 8));
+        assert_eq!(shard_of("\"anything\"", 1), 0);
+    }
+
+    #[test]
+    fn test_shard_filter() {
+        assert_eq!("3/8".parse::<Shard>(), Ok(Shard { index: 3, count: 8 }));
+        assert!("8/8".parse::<Shard>().is_err() && "3".parse::<Shard>().is_err() && "a/8".parse::<Shard>().is_err());
+
+        // every entity is in exactly one of the shards
+        let entities = (1..=100).map(|n| format!("{{\"id\":\"Q{}\"}}", n)).collect::<Vec<_>>();
+        let mut kept = 0;
+        for index in 0..3 {
+            let mut filter = ShardFilter::new(Shard { index, count: 3 }, crate::filter::jq_filter_factory(".", false, true)().unwrap());
+            for entity in &entities {
+                if filter.apply(entity).unwrap().is_some() {
+                    assert_eq!(shard_of(entity, 3), index);
+                    kept += 1;
+                }
+            }
+        }
+        assert_eq!(kept, 100);
+    }
+
+    #[test]
+    fn test_shard_path() {
+        assert_eq!(shard_path(Path::new("/data/out.ndjson"), 3), PathBuf::from("/data/out.3.ndjson"));
+        assert_eq!(shard_path(Path::new("out"), 0), PathBuf::from("out.0"));
+    }
+
+    #[test]
+    fn test_parallel_sharded_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        // more shards than writers, so some writers have several
+        let paths = (0..5).map(|n| shard_path(&directory.path().join("out.ndjson"), n)).collect::<Vec<_>>();
+        let sinks = paths.iter().map(|path| WriteSink::new(std::fs::File::create(path).unwrap(), 64)).collect();
+        let mut sink = ParallelShardedSink::new(sinks, 2);
+        let entities = (1..=1000).map(|n| format!("{{\"id\": \"Q{}\"}}", n)).collect::<Vec<_>>();
+        for entity in &entities[..500] {
+            sink.write_entity(entity).unwrap();
+        }
+        sink.flush().unwrap();
+        for entity in &entities[500..] {
+            sink.write_entity(entity).unwrap();
+        }
+        sink.write_entity("\"two\nlines\"").unwrap();
+        sink.finalize().unwrap();
+        drop(sink);
+
+        let shards = paths.iter().map(|path| std::fs::read_to_string(path).unwrap()).collect::<Vec<_>>();
+        assert_eq!(shards.iter().map(|shard| shard.lines().count()).sum::<usize>(), 1002);
+        for (index, shard) in shards.iter().enumerate() {
+            // in order within each shard, as ShardedSink would have written them
+            let expected = entities.iter().filter(|entity| shard_of(entity, 5) == index).map(|entity| format!("{}\n", entity)).collect::<String>();
+            assert!(shard.starts_with(&expected));
+        }
+    }
+
+    #[test]
+    fn test_sharded_sink() {
+        let (mut first, mut second) = (Vec::new(), Vec::new());
+        let mut sink = ShardedSink::new(vec![WriteSink::new(&mut first, 64), WriteSink::new(&mut second, 64)]);
+        let entities = (1..=20).map(|n| format!("{{\"id\": \"Q{}\"}}", n)).collect::<Vec<_>>();
+        for entity in &entities {
+            sink.write_entity(entity).unwrap();
+        }
+        sink.finalize().unwrap();
+        drop(sink);
+        let (first, second) = (String::from_utf8(first).unwrap(), String::from_utf8(second).unwrap());
+        assert_eq!(first.lines().count() + second.lines().count(), 20);
+        assert!(first.lines().all(|entity| shard_of(entity, 2) == 0));
+    }
+}
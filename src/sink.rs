@@ -0,0 +1,633 @@
+/*!
+ * Destinations for filtered entities. Anything implementing `Write` can be
+ * used through `WriteSink`, other destinations (e.g. an in-memory index) can
+ * implement `Sink` directly.
+ *
+ * Outputs are files, stdout, or Unix domain sockets given as
+ * `unix:///path/to/socket`, which a consumer process is listening on, so local
+ * pipelines can stream entities without temporary files. Files named `.gz` or
+ * `.bz2` are compressed to match, and other compressed extensions refused.
+ * Redis servers given as `redis://host`, MongoDB servers as
+ * `mongodb://host/db`, Kafka topics as `kafka://broker:9092/topic`, and
+ * RocksDB, LMDB and SQLite databases as `rocksdb://path`, `lmdb://path` and
+ * `sqlite://path` aren't written to but have a sink of their own, see
+ * `crate::redis_sink`, `crate::mongodb_sink`, `crate::kafka_sink`,
+ * `crate::rocksdb_store`, `crate::lmdb_store` and `crate::sqlite_sink`.
+ */
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use bzip2::write::BzEncoder;
+use flate2::write::GzEncoder;
+use log::{debug, error, warn};
+use crate::error::{ProcessError, Result};
+
+/// Receives the output for each entity, in dump order, on the thread running the pipeline
+pub trait Sink {
+    /// Takes the output for a single entity, without its trailing newline
+    fn write_entity(&mut self, output: &str) -> Result<()>;
+
+    fn flush(&mut self) -> Result<()>;
+
+    /// Called once after the last entity, including when the run is cancelled
+    fn finalize(&mut self) -> Result<()> {
+        self.flush()
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for &mut S {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        (**self).write_entity(output)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        (**self).finalize()
+    }
+}
+
+impl<S: Sink + ?Sized> Sink for Box<S> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        (**self).write_entity(output)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        (**self).flush()
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        (**self).finalize()
+    }
+}
+
+/// Writes each entity's output as a line (or lines) of ndjson
+pub struct WriteSink<W: Write> {
+    stream: BufWriter<W>,
+}
+
+impl<W: Write> WriteSink<W> {
+    /// Output is accumulated and only handed to `output` once `buffer_size` bytes are
+    /// ready, so there is one large write rather than one per entity
+    pub fn new(output: W, buffer_size: usize) -> Self {
+        debug!("Initializing write buffer to size {}", buffer_size);
+        WriteSink { stream: BufWriter::with_capacity(buffer_size, output) }
+    }
+}
+
+impl<W: Write> Sink for WriteSink<W> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        self.stream.write_all(output.as_bytes()).map_err(ProcessError::Write)?;
+        self.stream.write_all(b"\n").map_err(ProcessError::Write)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.stream.flush().map_err(ProcessError::Write)
+    }
+}
+
+/// Passes outputs on to another sink, counting the records (lines) among them
+pub struct CountingSink<S: Sink> {
+    sink: S,
+    records: Arc<AtomicU64>,
+}
+
+impl<S: Sink> CountingSink<S> {
+    /// Counts into `records`, which can be read once the sink has been handed to a pipeline
+    pub fn new(sink: S, records: Arc<AtomicU64>) -> Self {
+        CountingSink { sink, records }
+    }
+}
+
+impl<S: Sink> Sink for CountingSink<S> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        self.sink.write_entity(output)?;
+        self.records.fetch_add(output.lines().count() as u64, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sink.flush()
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.sink.finalize()
+    }
+}
+
+/// How an output is compressed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    None,
+    Bzip2,
+    Gzip,
+}
+
+impl Compression {
+    /// The compression a file is expected to have from its extension, e.g. bzip2 for `out.json.bz2`
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("bz2") => Compression::Bzip2,
+            Some("gz") => Compression::Gzip,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = String;
+
+    fn from_str(value: &str) -> std::result::Result<Self, Self::Err> {
+        match value {
+            "none" => Ok(Compression::None),
+            "bzip2" => Ok(Compression::Bzip2),
+            "gzip" => Ok(Compression::Gzip),
+            _ => Err(format!("Invalid compression '{}', expected none, bzip2 or gzip", value)),
+        }
+    }
+}
+
+/// Compresses whatever is written to it, which only ends up complete once `finish` is called
+pub enum CompressedWriter<W: Write> {
+    None(W),
+    Bzip2(BzEncoder<W>),
+    Gzip(GzEncoder<W>),
+}
+
+impl<W: Write> CompressedWriter<W> {
+    pub fn new(output: W, compression: Compression) -> Self {
+        match compression {
+            Compression::None => CompressedWriter::None(output),
+            Compression::Bzip2 => CompressedWriter::Bzip2(BzEncoder::new(output, bzip2::Compression::best())),
+            Compression::Gzip => CompressedWriter::Gzip(GzEncoder::new(output, flate2::Compression::default())),
+        }
+    }
+
+    /// Writes the end of the compressed data, returning the output
+    pub fn finish(self) -> io::Result<W> {
+        match self {
+            CompressedWriter::None(mut output) => output.flush().map(|_| output),
+            CompressedWriter::Bzip2(encoder) => encoder.finish(),
+            CompressedWriter::Gzip(encoder) => encoder.finish(),
+        }
+    }
+}
+
+impl<W: Write> Write for CompressedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            CompressedWriter::None(output) => output.write(buf),
+            CompressedWriter::Bzip2(encoder) => encoder.write(buf),
+            CompressedWriter::Gzip(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            CompressedWriter::None(output) => output.flush(),
+            CompressedWriter::Bzip2(encoder) => encoder.flush(),
+            CompressedWriter::Gzip(encoder) => encoder.flush(),
+        }
+    }
+}
+
+/// Prefix of outputs which are a Unix domain socket to connect to, e.g. `unix:///tmp/entities.sock`
+pub const UNIX_SOCKET_PREFIX: &str = "unix://";
+
+/// The socket `path` stands for, if it's a Unix domain socket output like `unix:///tmp/entities.sock`
+pub fn unix_socket(path: &Path) -> Option<&Path> {
+    path.to_str()?.strip_prefix(UNIX_SOCKET_PREFIX).map(Path::new)
+}
+
+/// The URL `path` stands for, if it's a Redis server output like `redis://127.0.0.1:6379/0` (see `crate::redis_sink`)
+pub fn redis_url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|url| url.starts_with("redis://") || url.starts_with("rediss://") || url.starts_with("redis+unix://"))
+}
+
+/// The URL `path` stands for, if it's a MongoDB server output like `mongodb://127.0.0.1:27017/wikidata` (see
+/// `crate::mongodb_sink`)
+pub fn mongodb_url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|url| url.starts_with("mongodb://") || url.starts_with("mongodb+srv://"))
+}
+
+/// Prefix of outputs which are a Kafka topic to produce to, e.g. `kafka://localhost:9092/entities`
+pub const KAFKA_PREFIX: &str = "kafka://";
+
+/// The URL `path` stands for, if it's a Kafka topic output like `kafka://localhost:9092/entities` (see
+/// `crate::kafka_sink`)
+pub fn kafka_url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|url| url.starts_with(KAFKA_PREFIX))
+}
+
+/// Prefix of outputs which are a RocksDB database to create, e.g. `rocksdb://entities.db`
+pub const ROCKSDB_PREFIX: &str = "rocksdb://";
+
+/// The database `path` stands for, if it's a RocksDB output like `rocksdb://entities.db` (see `crate::rocksdb_store`)
+pub fn rocksdb_path(path: &Path) -> Option<&Path> {
+    path.to_str()?.strip_prefix(ROCKSDB_PREFIX).map(Path::new)
+}
+
+/// Prefix of outputs which are an LMDB database to create, e.g. `lmdb://entities.lmdb`
+pub const LMDB_PREFIX: &str = "lmdb://";
+
+/// The database `path` stands for, if it's an LMDB output like `lmdb://entities.lmdb` (see `crate::lmdb_store`)
+pub fn lmdb_path(path: &Path) -> Option<&Path> {
+    path.to_str()?.strip_prefix(LMDB_PREFIX).map(Path::new)
+}
+
+/// Prefix of outputs which are a SQLite database to create, e.g. `sqlite://entities.db`
+pub const SQLITE_PREFIX: &str = "sqlite://";
+
+/// The database `path` stands for, if it's a SQLite output like `sqlite://entities.db` (see `crate::sqlite_sink`)
+pub fn sqlite_path(path: &Path) -> Option<&Path> {
+    path.to_str()?.strip_prefix(SQLITE_PREFIX).map(Path::new)
+}
+
+/// Opens `path` for writing, or stdout when there is no path. A Unix domain socket output is connected to, see
+/// `unix_socket`. Files are compressed as their extension says, see `output_compression`, and the compressed data
+/// ends once the writer is dropped, with any error only logged. Use `open_finishable_output` to be told about it.
+///
+/// Fails if the file already exists, unless `force_overwrite` is set.
+pub fn open_output(path: Option<&Path>, force_overwrite: bool) -> Result<Box<dyn Write>> {
+    open_finishable_output(path, force_overwrite).map(|(output, _)| output)
+}
+
+/// Opens `path` for writing as `open_output` does, along with what writes the end of its compressed data, which
+/// fails if it can't be written. Outputs which aren't compressed have nothing to finish.
+pub fn open_finishable_output(path: Option<&Path>, force_overwrite: bool) -> Result<(Box<dyn Write>, OutputFinish)> {
+    let compression = match path {
+        Some(path) if unix_socket(path).is_none() => output_compression(path)?,
+        _ => Compression::None,
+    };
+    let output = open_uncompressed_output(path, force_overwrite)?;
+    match (compression, path) {
+        (Compression::None, _) | (_, None) => Ok((output, OutputFinish::default())),
+        (compression, Some(path)) => {
+            let compressor = Arc::new(Mutex::new(Some(CompressedWriter::new(output, compression))));
+            let file = CompressedFile { path: path.to_path_buf(), compressor: Arc::clone(&compressor) };
+            Ok((Box::new(file), OutputFinish { compressed: Some((path.to_path_buf(), compressor)) }))
+        }
+    }
+}
+
+/// Opens `path` for writing as `open_output` does, but as it is whatever its extension, for writers compressing
+/// outputs themselves
+pub fn open_uncompressed_output(path: Option<&Path>, force_overwrite: bool) -> Result<Box<dyn Write>> {
+    match path {
+        None => {
+            let stdout = io::stdout(); // get the global stdout entity
+            Ok(Box::new(stdout.lock())) // acquire a lock on it
+        }
+        Some(path) => match unix_socket(path) {
+            Some(socket) => connect_unix(socket),
+            None => Ok(Box::new(create_file(path, force_overwrite)?)),
+        },
+    }
+}
+
+/// How the output written to `path` is compressed, from its extension: with gzip or bzip2 for `.gz` or `.bz2`,
+/// otherwise not at all. Fails for the extensions of other compressions, rather than writing uncompressed data under
+/// their name.
+pub fn output_compression(path: &Path) -> Result<Compression> {
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension @ ("zst" | "xz" | "lz4" | "lzma" | "br")) => Err(ProcessError::CreateOutput {
+            path: path.to_path_buf(),
+            source: io::Error::new(io::ErrorKind::Unsupported, format!(".{} outputs can't be written, only .gz and .bz2 ones are compressed", extension)),
+        }),
+        _ => Ok(Compression::from_path(path)),
+    }
+}
+
+// the compressor of a compressed output, shared by its writer and what finishes it, and gone once finished
+type Compressor = Arc<Mutex<Option<CompressedWriter<Box<dyn Write>>>>>;
+
+// a compressed output, whose compressed data ends when it's finished through its `OutputFinish`, or else once it's
+// dropped
+struct CompressedFile {
+    path: PathBuf,
+    compressor: Compressor,
+}
+
+impl CompressedFile {
+    fn with_writer<T>(&self, write: impl FnOnce(&mut CompressedWriter<Box<dyn Write>>) -> io::Result<T>) -> io::Result<T> {
+        match self.compressor.lock().expect("Compressor poisoned").as_mut() {
+            Some(writer) => write(writer),
+            None => Err(io::Error::other(format!("{:?} is already finished", self.path))),
+        }
+    }
+}
+
+impl Write for CompressedFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.with_writer(|writer| writer.write(buf))
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_writer(|writer| writer.flush())
+    }
+}
+
+impl Drop for CompressedFile {
+    // only for runs which failed before finishing their output, whose errors are already being reported
+    fn drop(&mut self) {
+        if let Ok(mut compressor) = self.compressor.lock() {
+            if let Some(Err(source)) = compressor.take().map(CompressedWriter::finish) {
+                error!("Could not finish compressing {:?}, so it's truncated: {}", self.path, source);
+            }
+        }
+    }
+}
+
+/// Writes the end of the compressed data of an output opened by `open_finishable_output`, see `FinishingSink`
+#[derive(Default)]
+pub struct OutputFinish {
+    compressed: Option<(PathBuf, Compressor)>,
+}
+
+impl OutputFinish {
+    /// Finishes the output, after which nothing more can be written to it. Does nothing when it isn't compressed, or
+    /// is already finished.
+    pub fn finish(&self) -> Result<()> {
+        let (path, compressor) = match &self.compressed {
+            Some(compressed) => compressed,
+            None => return Ok(()),
+        };
+        let writer = compressor.lock().expect("Compressor poisoned").take();
+        if let Some(writer) = writer {
+            let mut output = writer.finish().map_err(ProcessError::Write)?;
+            output.flush().map_err(ProcessError::Write)?;
+            debug!("Finished compressing {:?}", path);
+        }
+        Ok(())
+    }
+}
+
+/// Passes outputs on to a sink writing to an output opened by `open_finishable_output`, finishing the output once
+/// the sink is finalized
+pub struct FinishingSink<S: Sink> {
+    sink: S,
+    finish: OutputFinish,
+}
+
+impl<S: Sink> FinishingSink<S> {
+    pub fn new(sink: S, finish: OutputFinish) -> Self {
+        FinishingSink { sink, finish }
+    }
+}
+
+impl<S: Sink> Sink for FinishingSink<S> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        self.sink.write_entity(output)
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.sink.flush()
+    }
+
+    fn finalize(&mut self) -> Result<()> {
+        self.sink.finalize()?;
+        self.finish.finish()
+    }
+}
+
+#[cfg(unix)]
+fn connect_unix(socket: &Path) -> Result<Box<dyn Write>> {
+    let stream = std::os::unix::net::UnixStream::connect(socket)
+        .map_err(|source| ProcessError::CreateOutput { path: socket.to_path_buf(), source })?;
+    debug!("Connected to {:?}", socket);
+    Ok(Box::new(stream))
+}
+
+#[cfg(not(unix))]
+fn connect_unix(socket: &Path) -> Result<Box<dyn Write>> {
+    let source = io::Error::new(io::ErrorKind::Unsupported, "Unix domain sockets are only supported on Unix");
+    Err(ProcessError::CreateOutput { path: socket.to_path_buf(), source })
+}
+
+/// Opens the output of an interrupted run to carry on writing to it, dropping anything past `length`
+/// (i.e. written after the checkpoint being resumed from)
+pub fn open_resumed_output(path: &Path, length: u64) -> Result<Box<dyn Write>> {
+    let create_error = |source| ProcessError::CreateOutput { path: path.to_path_buf(), source };
+    let file = OpenOptions::new().append(true).open(path).map_err(create_error)?;
+    file.set_len(length).map_err(create_error)?;
+    Ok(Box::new(file))
+}
+
+/// Opens `path` for writing through io_uring, see `crate::uring`
+#[cfg(all(target_os = "linux", feature = "io-uring"))]
+pub fn open_uring_output(path: &Path, force_overwrite: bool, buffer_size: usize) -> Result<Box<dyn Write>> {
+    let file = create_file(path, force_overwrite)?;
+    let writer = crate::uring::UringWriter::new(file, buffer_size)
+        .map_err(|source| ProcessError::CreateOutput { path: path.to_path_buf(), source })?;
+    Ok(Box::new(writer))
+}
+
+/// Reserves `size` bytes of disk for the output file at `path` up front, without changing its length, so that a
+/// large output is laid out in few extents rather than fragmented as it grows (on filesystems supporting it, e.g.
+/// XFS and ext4). Only supported on Linux, elsewhere nothing is reserved. Whatever isn't used is given back by
+/// `Preallocation::release` once the output is written, or when it's dropped, however the run ended.
+pub fn preallocate(path: &Path, size: u64) -> Result<Preallocation> {
+    let create_error = |source| ProcessError::CreateOutput { path: path.to_path_buf(), source };
+    let file = OpenOptions::new().write(true).open(path).map_err(create_error)?;
+    allocate(&file, size).map_err(create_error)?;
+    debug!("Preallocated {} bytes for {:?}", size, path);
+    Ok(Preallocation { path: Some(path.to_path_buf()) })
+}
+
+#[cfg(target_os = "linux")]
+fn allocate(file: &File, size: u64) -> io::Result<()> {
+    use std::os::unix::io::AsRawFd;
+
+    let size = libc::off_t::try_from(size).map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "size too large to preallocate"))?;
+    // SAFETY: the file descriptor stays open for the duration of the call
+    match unsafe { libc::fallocate(file.as_raw_fd(), libc::FALLOC_FL_KEEP_SIZE, 0, size) } {
+        0 => Ok(()),
+        _ => Err(io::Error::last_os_error()),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn allocate(_file: &File, _size: u64) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "preallocating outputs is only supported on Linux"))
+}
+
+/// Disk reserved by `preallocate` for an output
+#[derive(Debug)]
+pub struct Preallocation {
+    // the output, until the disk past its end has been given back
+    path: Option<PathBuf>,
+}
+
+impl Preallocation {
+    /// Gives back the disk reserved past the end of the output, once written, by truncating it to its own length
+    pub fn release(mut self) -> Result<()> {
+        match self.path.take() {
+            Some(path) => release_preallocation(&path),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Preallocation {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            if let Err(error) = release_preallocation(&path) {
+                warn!("Could not give back the disk preallocated for {:?}: {}", path, error);
+            }
+        }
+    }
+}
+
+fn release_preallocation(path: &Path) -> Result<()> {
+    let create_error = |source| ProcessError::CreateOutput { path: path.to_path_buf(), source };
+    let file = OpenOptions::new().write(true).open(path).map_err(create_error)?;
+    let length = file.metadata().map_err(create_error)?.len();
+    file.set_len(length).map_err(create_error)
+}
+
+/// Creates the file `path` for writing, failing if it exists unless `force_overwrite`
+pub fn create_file(path: &Path, force_overwrite: bool) -> Result<File> {
+    if path.exists() && !force_overwrite {
+        return Err(ProcessError::OutputExists(path.to_path_buf()));
+    }
+    File::create(path).map_err(|source| ProcessError::CreateOutput { path: path.to_path_buf(), source })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::reader::EntityFile;
+
+    #[test]
+    fn test_compressed_output() {
+        let directory = tempfile::tempdir().unwrap();
+        for name in ["out.ndjson.gz", "out.ndjson.bz2", "out.ndjson"] {
+            let path = directory.path().join(name);
+            let mut output = open_output(Some(&path), false).unwrap();
+            output.write_all(b"{\"id\":\"Q1\"}\n").unwrap();
+            drop(output);
+            let written = EntityFile::open(&path).unwrap().map(Result::unwrap).collect::<Vec<_>>();
+            assert_eq!(written, [r#"{"id":"Q1"}"#], "{}", name);
+        }
+        assert_eq!(output_compression(Path::new("out.json.gz")).unwrap(), Compression::Gzip);
+        assert!(matches!(open_output(Some(&directory.path().join("out.ndjson.zst")), false), Err(ProcessError::CreateOutput { .. })));
+        assert!(!directory.path().join("out.ndjson.zst").exists());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_compressed_output_full_disk() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("out.ndjson.gz");
+        std::os::unix::fs::symlink("/dev/full", &path).unwrap();
+        let (output, finish) = open_finishable_output(Some(&path), true).unwrap();
+        let mut sink = FinishingSink::new(WriteSink::new(output, 1 << 20), finish);
+        sink.write_entity(r#"{"id":"Q1"}"#).unwrap();
+        assert!(matches!(sink.finalize(), Err(ProcessError::Write(_))));
+    }
+
+    #[test]
+    fn test_finishing_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("out.ndjson.gz");
+        let (output, finish) = open_finishable_output(Some(&path), false).unwrap();
+        let mut sink = FinishingSink::new(WriteSink::new(output, 64), finish);
+        sink.write_entity(r#"{"id":"Q1"}"#).unwrap();
+        sink.finalize().unwrap();
+        // complete before the writer is dropped
+        let written = EntityFile::open(&path).unwrap().map(Result::unwrap).collect::<Vec<_>>();
+        assert_eq!(written, [r#"{"id":"Q1"}"#]);
+        assert!(sink.write_entity(r#"{"id":"Q2"}"#).and_then(|_| sink.flush()).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_preallocate() {
+        use std::os::unix::fs::MetadataExt;
+
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("output.ndjson");
+        let blocks = || std::fs::metadata(&path).unwrap().blocks();
+        assert!(preallocate(&directory.path().join("missing.ndjson"), 1).is_err());
+        let mut output = open_output(Some(&path), false).unwrap();
+        let preallocation = match preallocate(&path, 1 << 20) {
+            Ok(preallocation) => preallocation,
+            // not every filesystem supports it, e.g. tmpfs on older kernels
+            Err(ProcessError::CreateOutput { source, .. }) if source.raw_os_error() == Some(libc::EOPNOTSUPP) => return,
+            Err(error) => panic!("{}", error),
+        };
+        // blocks of 512 bytes, the length staying as it is
+        assert!(blocks() >= 2048, "{} blocks", blocks());
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 0);
+        output.write_all(b"{}\n").unwrap();
+        output.flush().unwrap();
+        drop(output);
+        preallocation.release().unwrap();
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), 3);
+        assert!(blocks() < 2048, "{} blocks", blocks());
+
+        // dropped without being released, e.g. when a run fails
+        drop(preallocate(&path, 1 << 20).unwrap());
+        assert!(blocks() < 2048, "{} blocks", blocks());
+    }
+
+    #[test]
+    fn test_unix_socket() {
+        assert_eq!(unix_socket(Path::new("unix:///tmp/entities.sock")), Some(Path::new("/tmp/entities.sock")));
+        assert_eq!(unix_socket(Path::new("/tmp/entities.ndjson")), None);
+    }
+
+    #[test]
+    fn test_redis_url() {
+        assert_eq!(redis_url(Path::new("redis://127.0.0.1:6379/0")), Some("redis://127.0.0.1:6379/0"));
+        assert_eq!(redis_url(Path::new("./redis/entities.ndjson")), None);
+    }
+
+    #[test]
+    fn test_rocksdb_path() {
+        assert_eq!(rocksdb_path(Path::new("rocksdb://./entities.db")), Some(Path::new("./entities.db")));
+        assert_eq!(rocksdb_path(Path::new("entities.db")), None);
+    }
+
+    #[test]
+    fn test_lmdb_path() {
+        assert_eq!(lmdb_path(Path::new("lmdb:///data/entities.lmdb")), Some(Path::new("/data/entities.lmdb")));
+        assert_eq!(lmdb_path(Path::new("rocksdb://entities.db")), None);
+    }
+
+    #[test]
+    fn test_mongodb_url() {
+        assert_eq!(mongodb_url(Path::new("mongodb+srv://cluster.example.org/wikidata")), Some("mongodb+srv://cluster.example.org/wikidata"));
+        assert_eq!(mongodb_url(Path::new("redis://127.0.0.1:6379/0")), None);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unix_socket_output() {
+        use std::io::Read;
+        let directory = tempfile::tempdir().unwrap();
+        let socket = directory.path().join("entities.sock");
+        let listener = std::os::unix::net::UnixListener::bind(&socket).unwrap();
+        let consumer = std::thread::spawn(move || {
+            let mut received = String::new();
+            listener.accept().unwrap().0.read_to_string(&mut received).unwrap();
+            received
+        });
+
+        let output = open_output(Some(Path::new(&format!("{}{}", UNIX_SOCKET_PREFIX, socket.display()))), false).unwrap();
+        let mut sink = WriteSink::new(output, 1024);
+        sink.write_entity("{\"id\":\"Q1\"}").unwrap();
+        sink.write_entity("{\"id\":\"Q2\"}").unwrap();
+        sink.finalize().unwrap();
+        drop(sink);
+        assert_eq!(consumer.join().unwrap(), "{\"id\":\"Q1\"}\n{\"id\":\"Q2\"}\n");
+
+        assert!(open_output(Some(Path::new("unix:///nonexistent/entities.sock")), false).is_err());
+    }
+}
@@ -0,0 +1,146 @@
+/*!
+ * Mappings from wiki pages to the entities they're about, for joining
+ * Wikipedia (or any other wiki's) text datasets with Wikidata. Only the id and
+ * sitelinks of each entity are parsed, and everything else is skipped over.
+ *
+ * Mappings are written as TSV, escaped like label tables (see `labels`).
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Write;
+use serde::Deserialize;
+use crate::error::Result;
+use crate::filter::{self, EntityFilter};
+use crate::labels::escape_tsv;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+/// What goes into a sitelink mapping
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SitelinkOptions {
+    /// Sites to map the pages of, e.g. `enwiki`, or all of them if empty
+    pub sites: Vec<String>,
+    /// Write titles with underscores instead of spaces, as in page URLs and most Wikipedia dumps
+    pub underscores: bool,
+}
+
+// the parts of an entity which end up in sitelink mappings
+#[derive(Deserialize)]
+struct Outline<'a> {
+    #[serde(borrow)]
+    id: Cow<'a, str>,
+    #[serde(default, borrow)]
+    sitelinks: BTreeMap<Cow<'a, str>, Page<'a>>,
+}
+
+#[derive(Deserialize)]
+struct Page<'a> {
+    #[serde(borrow)]
+    title: Cow<'a, str>,
+}
+
+// turns each entity into its rows of the mapping
+struct SitelinkExtractor {
+    options: SitelinkOptions,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl SitelinkExtractor {
+    fn rows(&self, outline: &Outline) -> String {
+        let mut rows = String::new();
+        let mut row = |site: &str, page: &Page| {
+            if !rows.is_empty() {
+                rows.push('\n');
+            }
+            // the site column is only needed to tell rows apart when there could be several
+            if self.options.sites.len() != 1 {
+                rows.push_str(&escape_tsv(site));
+                rows.push('\t');
+            }
+            match self.options.underscores {
+                true => rows.push_str(&escape_tsv(&page.title.replace(' ', "_"))),
+                false => rows.push_str(&escape_tsv(&page.title)),
+            }
+            rows.push('\t');
+            rows.push_str(&escape_tsv(&outline.id));
+        };
+        if self.options.sites.is_empty() {
+            for (site, page) in &outline.sitelinks {
+                row(site, page);
+            }
+        }
+        for site in &self.options.sites {
+            if let Some(page) = outline.sitelinks.get(site.as_str()) {
+                row(site, page);
+            }
+        }
+        rows
+    }
+}
+
+impl EntityFilter for SitelinkExtractor {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let outline: Outline = match serde_json::from_str(raw) {
+            Ok(outline) => outline,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let rows = self.rows(&outline);
+        Ok(Some(rows).filter(|rows| !rows.is_empty()).map(Cow::Owned))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+/// Writes the sitelink mapping of every entity of `source` to `output` as TSV, one row per page: the site
+/// unless exactly one was asked for, the page title and the entity's id. Rows come in dump order, and within
+/// an entity in the order of `sites`, or by site when mapping all of them.
+pub fn write_sitelinks(source: impl Source, sitelink_options: SitelinkOptions, options: ProcessOptions, output: impl Write) -> Result<ProcessStats> {
+    let continue_on_error = options.continue_on_error;
+    Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(SitelinkExtractor { options: sitelink_options.clone(), continue_on_error, failures: 0 }))
+        .sink(output)
+        .options(options)
+        .build()?
+        .run()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    const ENTITY: &str = r#"{"id":"Q42","type":"item","sitelinks":{"enwiki":{"site":"enwiki","title":"Douglas Adams","badges":[]},"frwiki":{"site":"frwiki","title":"Douglas Adams","badges":[]},"dewikiquote":{"site":"dewikiquote","title":"Douglas\tAdams","badges":[]}}}"#;
+
+    fn rows(sites: &[&str], underscores: bool) -> String {
+        let options = SitelinkOptions { sites: sites.iter().map(|site| site.to_string()).collect(), underscores };
+        let extractor = SitelinkExtractor { options, continue_on_error: false, failures: 0 };
+        extractor.rows(&serde_json::from_str(ENTITY).unwrap())
+    }
+
+    #[test]
+    fn test_sitelink_rows() {
+        assert_eq!(rows(&["enwiki"], true), "Douglas_Adams\tQ42");
+        assert_eq!(rows(&["frwiki", "enwiki", "eswiki"], false), "frwiki\tDouglas Adams\tQ42\nenwiki\tDouglas Adams\tQ42");
+        assert_eq!(rows(&[], false), "dewikiquote\tDouglas\\tAdams\tQ42\nenwiki\tDouglas Adams\tQ42\nfrwiki\tDouglas Adams\tQ42");
+        assert_eq!(rows(&["eswiki"], false), "");
+    }
+
+    #[test]
+    fn test_write_sitelinks() {
+        let mut output = Vec::new();
+        let stats = write_sitelinks(FileSource::new("./tests/test-data.json.bz2"), SitelinkOptions::default(), ProcessOptions::default(), &mut output).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.lines().all(|row| row.split('\t').count() == 3), "{}", output);
+    }
+}
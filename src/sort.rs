@@ -0,0 +1,219 @@
+/*!
+ * Sorting dumps and `filter` outputs by entity id, however big they are, so
+ * that runs can be compared or joined line by line.
+ *
+ * Entities are read in chunks which fit in memory, each chunk is sorted and
+ * written to a temporary file, and the sorted chunks are then merged. Files
+ * which fit in a single chunk are sorted in memory.
+ */
+
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use log::info;
+use serde::Serialize;
+use crate::decoder::BUFFER_LENGTH;
+use crate::error::{ProcessError, Result};
+use crate::index::parse_id;
+use crate::progress::{Progress, Reporter};
+use crate::reader::EntityFile;
+use crate::sink::{Sink, WriteSink};
+use crate::splitter;
+
+/// What entities are sorted by: ids like Q42 by their letter then number, so Q9 comes before Q10, followed
+/// by any other ids as text, and then entities without an id by their whole text
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SortKey<'a> {
+    Numeric(u8, u32),
+    Text(&'a str),
+    NoId(&'a str),
+}
+
+impl<'a> SortKey<'a> {
+    pub fn of(entity: &'a str) -> Self {
+        match splitter::entity_id(entity) {
+            Some(id) => SortKey::of_id(id),
+            None => SortKey::NoId(entity),
+        }
+    }
+
+    /// The key of the entity with the id `id`
+    pub fn of_id(id: &'a str) -> Self {
+        match parse_id(id) {
+            Some((letter, number)) => SortKey::Numeric(letter, number),
+            None => SortKey::Text(id),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct SortStats {
+    pub entities: u64,
+    /// Sorted chunks written to temporary files, none when everything fit in memory
+    pub chunks: usize,
+}
+
+/// Sorts the entities of the dump or `filter` output at `input` by `SortKey`, writing them to `output` one per
+/// line. Entities with the same key keep their order.
+///
+/// At most about `chunk_size` bytes of entities are held in memory at once, with the sorted chunks written to
+/// temporary files in `temp_dir` (or the system's temporary directory), which need as much space as the
+/// uncompressed input.
+pub fn sort(input: &Path, output: impl Write, chunk_size: usize, temp_dir: Option<&Path>, progress: Progress) -> Result<SortStats> {
+    let mut entities = EntityFile::open(input)?;
+    let progress = Reporter::new(progress, Some(entities.size()), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+    progress.set_draw_rate(1);
+    let mut stats = SortStats::default();
+    let mut chunks = Vec::new();
+    let mut chunk = Vec::new();
+    let mut chunk_bytes = 0;
+    while let Some(entity) = entities.next() {
+        let entity = entity.map_err(ProcessError::Read)?;
+        chunk_bytes += entity.len();
+        chunk.push(entity);
+        stats.entities += 1;
+        if chunk_bytes >= chunk_size {
+            chunks.push(write_chunk(&mut chunk, temp_dir)?);
+            chunk_bytes = 0;
+        }
+        progress.set_position(entities.position());
+        progress.set_entities(stats.entities as usize, 0);
+    }
+    progress.finish(format!("Read {} entities", stats.entities));
+
+    let mut output = WriteSink::new(output, BUFFER_LENGTH);
+    if chunks.is_empty() {
+        chunk.sort_by(|a, b| SortKey::of(a).cmp(&SortKey::of(b)));
+        for entity in chunk {
+            output.write_entity(&entity)?;
+        }
+        output.finalize()?;
+        return Ok(stats);
+    }
+    if !chunk.is_empty() {
+        chunks.push(write_chunk(&mut chunk, temp_dir)?);
+    }
+    stats.chunks = chunks.len();
+    info!("Merging {} sorted chunks", chunks.len());
+    let chunks = chunks.into_iter()
+        .map(|chunk| Box::new(BufReader::with_capacity(BUFFER_LENGTH, chunk).lines()) as Box<dyn Iterator<Item = io::Result<String>>>)
+        .collect();
+    merge(chunks, |entity| output.write_entity(&entity))?;
+    output.finalize()?;
+    Ok(stats)
+}
+
+// sorts `chunk` into a temporary file, leaving it empty, and returns the file ready to be read back
+fn write_chunk(chunk: &mut Vec<String>, temp_dir: Option<&Path>) -> Result<File> {
+    chunk.sort_by(|a, b| SortKey::of(a).cmp(&SortKey::of(b)));
+    let file = match temp_dir {
+        Some(temp_dir) => tempfile::tempfile_in(temp_dir),
+        None => tempfile::tempfile(),
+    }.map_err(ProcessError::Write)?;
+    let mut writer = BufWriter::with_capacity(BUFFER_LENGTH, file);
+    for entity in chunk.drain(..) {
+        writer.write_all(entity.as_bytes()).map_err(ProcessError::Write)?;
+        writer.write_all(b"\n").map_err(ProcessError::Write)?;
+    }
+    let mut file = writer.into_inner().map_err(|error| ProcessError::Write(error.into_error()))?;
+    file.seek(SeekFrom::Start(0)).map_err(ProcessError::Write)?;
+    Ok(file)
+}
+
+// the next entity of one of the inputs being merged
+struct Head {
+    entity: String,
+    input: usize,
+}
+
+impl Ord for Head {
+    // reversed, so the max-heap pops the smallest, and the earliest input among equals
+    fn cmp(&self, other: &Self) -> Ordering {
+        (SortKey::of(&other.entity), other.input).cmp(&(SortKey::of(&self.entity), self.input))
+    }
+}
+
+impl PartialOrd for Head {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for Head {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Head {}
+
+/// Merges `inputs`, each already sorted by `SortKey`, passing their entities to `output` in sorted order.
+/// Entities with the same key come in the order of the inputs they're from.
+///
+/// Fails on finding an input which isn't sorted after all.
+pub fn merge(mut inputs: Vec<Box<dyn Iterator<Item = io::Result<String>>>>, mut output: impl FnMut(String) -> Result<()>) -> Result<()> {
+    let mut heads = BinaryHeap::with_capacity(inputs.len());
+    for (i, input) in inputs.iter_mut().enumerate() {
+        if let Some(entity) = input.next() {
+            heads.push(Head { entity: entity.map_err(ProcessError::Read)?, input: i });
+        }
+    }
+    while let Some(Head { entity, input }) = heads.pop() {
+        if let Some(next) = inputs[input].next() {
+            let next = next.map_err(ProcessError::Read)?;
+            if SortKey::of(&next) < SortKey::of(&entity) {
+                let id = |entity| splitter::entity_id(entity).unwrap_or("(no id)");
+                let message = format!("input {} is not sorted by id, {} comes after {}", input + 1, id(&next), id(&entity));
+                return Err(ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, message)));
+            }
+            heads.push(Head { entity: next, input });
+        }
+        output(entity)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn test_sort_key() {
+        let mut entities = vec!["{\"id\":\"Q10\"}", "[1]", "{\"id\":\"P5\"}", "{\"id\":\"Q9\"}", "{\"id\":\"L1-F1\"}"];
+        entities.sort_by_key(|entity| SortKey::of(entity));
+        assert_eq!(entities, vec!["{\"id\":\"P5\"}", "{\"id\":\"Q9\"}", "{\"id\":\"Q10\"}", "{\"id\":\"L1-F1\"}", "[1]"]);
+    }
+
+    #[test]
+    fn test_sort() {
+        let directory = tempfile::tempdir().unwrap();
+        let input = directory.path().join("input.ndjson");
+        fs::write(&input, "{\"id\":\"Q3\"}\n{\"id\":\"Q20\"}\n{\"id\":\"Q1\",\"v\":1}\n{\"id\":\"Q2\"}\n{\"id\":\"Q1\",\"v\":2}\n").unwrap();
+        let sorted = "{\"id\":\"Q1\",\"v\":1}\n{\"id\":\"Q1\",\"v\":2}\n{\"id\":\"Q2\"}\n{\"id\":\"Q3\"}\n{\"id\":\"Q20\"}\n";
+
+        let mut output = Vec::new();
+        let stats = sort(&input, &mut output, 1 << 20, None, Progress::Hidden).unwrap();
+        assert_eq!(stats, SortStats { entities: 5, chunks: 0 });
+        assert_eq!(String::from_utf8(output).unwrap(), sorted);
+
+        // two entities to a chunk
+        let mut output = Vec::new();
+        let stats = sort(&input, &mut output, 20, Some(directory.path()), Progress::Hidden).unwrap();
+        assert_eq!(stats, SortStats { entities: 5, chunks: 3 });
+        assert_eq!(String::from_utf8(output).unwrap(), sorted);
+
+        let unsorted = || Box::new(vec![Ok("{\"id\":\"Q2\"}".to_string()), Ok("{\"id\":\"Q1\"}".to_string())].into_iter()) as Box<dyn Iterator<Item = io::Result<String>>>;
+        assert!(merge(vec![unsorted()], |_| Ok(())).is_err());
+    }
+
+    #[test]
+    fn test_sort_dump() {
+        let mut output = Vec::new();
+        sort(Path::new("./tests/test-data.json.bz2"), &mut output, 1 << 20, None, Progress::Hidden).unwrap();
+        let ids = String::from_utf8(output).unwrap().lines().map(|entity| splitter::entity_id(entity).unwrap().to_string()).collect::<Vec<_>>();
+        assert_eq!(ids, vec!["P1", "Q1", "Q2", "Q3", "Q4", "Q5", "Q6", "Q60"]);
+    }
+}
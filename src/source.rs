@@ -0,0 +1,297 @@
+/*!
+ * Where the compressed dump comes from. Everything downstream only sees a
+ * `Read` over bzip2 bytes, so new sources can be added by implementing
+ * `Source` without touching the rest of the pipeline.
+ *
+ * With the `s3` feature, `S3Source` streams a dump from an S3 bucket (or
+ * anything speaking its API, like MinIO), with credentials and region taken
+ * from the usual `AWS_*` environment variables.
+ */
+
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use futures_util::TryStreamExt;
+use log::debug;
+use tokio::runtime::Handle;
+use tokio_util::io::{StreamReader, SyncIoBridge};
+use crate::decoder;
+use crate::error::{ProcessError, Result};
+#[cfg(feature = "s3")]
+use object_store::{aws::AmazonS3Builder, GetOptions, GetRange, ObjectStore};
+
+/// A reader over compressed dump bytes which can be handed to the reading thread
+pub type DumpReader = Box<dyn Read + Send>;
+
+pub trait Source {
+    /// Opens the compressed dump, returning it along with its compressed size in bytes when known
+    fn open(&mut self) -> Result<(DumpReader, Option<u64>)>;
+
+    /// Same as `open`, positioned `offset` compressed bytes in. By default the bytes before it are read and dropped
+    fn open_at(&mut self, offset: u64) -> Result<(DumpReader, Option<u64>)> {
+        let (mut dump, size) = self.open()?;
+        io::copy(&mut (&mut dump).take(offset), &mut io::sink()).map_err(ProcessError::Read)?;
+        Ok((dump, size))
+    }
+}
+
+impl<S: Source + ?Sized> Source for Box<S> {
+    fn open(&mut self) -> Result<(DumpReader, Option<u64>)> {
+        (**self).open()
+    }
+
+    fn open_at(&mut self, offset: u64) -> Result<(DumpReader, Option<u64>)> {
+        (**self).open_at(offset)
+    }
+}
+
+/// Counts the bytes read through it, e.g. to tell how far into the compressed dump decoding has got
+pub struct CountingReader<R> {
+    inner: R,
+    count: Arc<AtomicU64>,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        CountingReader { inner, count: Arc::default() }
+    }
+
+    /// The number of bytes read so far, which can be checked from other threads
+    pub fn count(&self) -> Arc<AtomicU64> {
+        Arc::clone(&self.count)
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+}
+
+/// A dump on the local filesystem
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        FileSource { path: path.into() }
+    }
+}
+
+impl Source for FileSource {
+    fn open(&mut self) -> Result<(DumpReader, Option<u64>)> {
+        let (file, size) = decoder::open(&self.path)?;
+        Ok((Box::new(file), Some(size)))
+    }
+
+    fn open_at(&mut self, offset: u64) -> Result<(DumpReader, Option<u64>)> {
+        let (mut file, size) = decoder::open(&self.path)?;
+        file.seek(SeekFrom::Start(offset)).map_err(|source| ProcessError::OpenInput { path: self.path.clone(), source })?;
+        Ok((Box::new(file), Some(size)))
+    }
+}
+
+/// A dump piped in through stdin
+#[derive(Default)]
+pub struct StdinSource;
+
+impl Source for StdinSource {
+    fn open(&mut self) -> Result<(DumpReader, Option<u64>)> {
+        debug!("Reading from stdin");
+        Ok((Box::new(io::stdin()), None))
+    }
+}
+
+/// A dump streamed over HTTP as it's being processed, without being saved first
+pub struct HttpSource {
+    url: String,
+    runtime: Handle,
+}
+
+impl HttpSource {
+    /// Must be called from within a tokio runtime, which then drives the download
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let runtime = Handle::try_current().map_err(|_| ProcessError::InvalidPipeline("HTTP sources need a tokio runtime"))?;
+        Ok(HttpSource { url: url.into(), runtime })
+    }
+}
+
+impl Source for HttpSource {
+    fn open(&mut self) -> Result<(DumpReader, Option<u64>)> {
+        debug!("Streaming {}", self.url);
+        // the request is only sent on the first read, which happens on the reading thread,
+        // since blocking on the runtime isn't allowed from the async code which may be running the pipeline
+        Ok((Box::new(HttpReader { url: self.url.clone(), runtime: self.runtime.clone(), body: None }), None))
+    }
+}
+
+struct HttpReader {
+    url: String,
+    runtime: Handle,
+    body: Option<Box<dyn Read + Send>>,
+}
+
+impl HttpReader {
+    fn body(&mut self) -> io::Result<&mut Box<dyn Read + Send>> {
+        if self.body.is_none() {
+            let response = self.runtime.block_on(async { reqwest::get(&self.url).await?.error_for_status() })
+                .map_err(io::Error::other)?;
+            let bytes = response.bytes_stream().map_err(io::Error::other);
+            let reader = SyncIoBridge::new_with_handle(StreamReader::new(bytes), self.runtime.clone());
+            self.body = Some(Box::new(reader));
+        }
+        Ok(self.body.as_mut().expect("HTTP body was just set"))
+    }
+}
+
+impl Read for HttpReader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body()?.read(buf)
+    }
+}
+
+/// Prefix of inputs which are an object in an S3 bucket, e.g. `s3://bucket/latest-all.json.bz2`
+pub const S3_PREFIX: &str = "s3://";
+
+/// The URL `path` stands for, if it's an S3 input like `s3://bucket/latest-all.json.bz2`
+pub fn s3_url(path: &Path) -> Option<&str> {
+    path.to_str().filter(|url| url.starts_with(S3_PREFIX))
+}
+
+/// The bucket and key of an `s3://bucket/key` input
+pub fn parse_s3_url(url: &str) -> Result<(&str, &str)> {
+    let invalid = || ProcessError::OpenInput {
+        path: PathBuf::from(url),
+        source: io::Error::new(io::ErrorKind::InvalidInput, "not an S3 object, e.g. s3://bucket/latest-all.json.bz2"),
+    };
+    let (bucket, key) = url.strip_prefix(S3_PREFIX).and_then(|rest| rest.split_once('/')).ok_or_else(invalid)?;
+    if bucket.is_empty() || key.is_empty() {
+        return Err(invalid());
+    }
+    Ok((bucket, key))
+}
+
+/// A dump streamed from an S3 bucket as it's being processed (with the `s3` feature), see the module documentation.
+/// Opening it at an offset only requests the bytes from there on.
+#[cfg(feature = "s3")]
+pub struct S3Source {
+    url: String,
+    store: Arc<dyn ObjectStore>,
+    key: object_store::path::Path,
+    runtime: Handle,
+}
+
+#[cfg(feature = "s3")]
+impl S3Source {
+    /// Must be called from within a tokio runtime, which then drives the download
+    pub fn new(url: impl Into<String>) -> Result<Self> {
+        let url = url.into();
+        let runtime = Handle::try_current().map_err(|_| ProcessError::InvalidPipeline("S3 sources need a tokio runtime"))?;
+        let (bucket, key) = parse_s3_url(&url)?;
+        let store = AmazonS3Builder::from_env().with_bucket_name(bucket).build()
+            .map_err(|error| ProcessError::OpenInput { path: PathBuf::from(&url), source: io::Error::other(error) })?;
+        let key = object_store::path::Path::from(key);
+        Ok(S3Source { url, store: Arc::new(store), key, runtime })
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Source for S3Source {
+    fn open(&mut self) -> Result<(DumpReader, Option<u64>)> {
+        self.open_at(0)
+    }
+
+    fn open_at(&mut self, offset: u64) -> Result<(DumpReader, Option<u64>)> {
+        debug!("Streaming {} from compressed byte {}", self.url, offset);
+        // requested on the first read, as for HTTP sources
+        let reader = S3Reader { store: Arc::clone(&self.store), key: self.key.clone(), offset, runtime: self.runtime.clone(), body: None };
+        Ok((Box::new(reader), None))
+    }
+}
+
+#[cfg(feature = "s3")]
+struct S3Reader {
+    store: Arc<dyn ObjectStore>,
+    key: object_store::path::Path,
+    offset: u64,
+    runtime: Handle,
+    body: Option<Box<dyn Read + Send>>,
+}
+
+#[cfg(feature = "s3")]
+impl S3Reader {
+    fn body(&mut self) -> io::Result<&mut Box<dyn Read + Send>> {
+        if self.body.is_none() {
+            let options = GetOptions { range: (self.offset > 0).then_some(GetRange::Offset(self.offset as usize)), ..GetOptions::default() };
+            let object = self.runtime.block_on(self.store.get_opts(&self.key, options)).map_err(io::Error::other)?;
+            let bytes = object.into_stream().map_err(io::Error::other);
+            let reader = SyncIoBridge::new_with_handle(StreamReader::new(bytes), self.runtime.clone());
+            self.body = Some(Box::new(reader));
+        }
+        Ok(self.body.as_mut().expect("S3 body was just set"))
+    }
+}
+
+#[cfg(feature = "s3")]
+impl Read for S3Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.body()?.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_source() {
+        let (mut reader, size) = FileSource::new("./tests/test-data.json.bz2").open().unwrap();
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).unwrap();
+        assert_eq!(size, Some(bytes.len() as u64));
+        assert!(matches!(FileSource::new("./tests/missing.json.bz2").open(), Err(ProcessError::OpenInput { .. })));
+    }
+
+    #[test]
+    fn test_counting_reader() {
+        let mut reader = CountingReader::new(&b"some bytes"[..]);
+        let count = reader.count();
+        reader.read_exact(&mut [0; 4]).unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 4);
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(count.load(Ordering::Relaxed), 10);
+    }
+
+    #[test]
+    fn test_parse_s3_url() {
+        assert_eq!(parse_s3_url("s3://dumps/wikidata/latest-all.json.bz2").unwrap(), ("dumps", "wikidata/latest-all.json.bz2"));
+        for url in ["s3://dumps", "s3://dumps/", "s3:///latest-all.json.bz2", "./latest-all.json.bz2"] {
+            assert!(matches!(parse_s3_url(url), Err(ProcessError::OpenInput { .. })), "{}", url);
+        }
+        assert_eq!(s3_url(Path::new("s3://dumps/latest-all.json.bz2")), Some("s3://dumps/latest-all.json.bz2"));
+        assert_eq!(s3_url(Path::new("./latest-all.json.bz2")), None);
+    }
+
+    // needs a bucket holding a copy of tests/test-data.json.bz2, given as S3_URL, e.g. s3://wikidump-test/test-data.json.bz2,
+    // along with the AWS_* variables to reach it
+    #[cfg(feature = "s3")]
+    #[test]
+    #[ignore]
+    fn test_s3_source() {
+        let url = std::env::var("S3_URL").expect("S3_URL names a copy of the test data to test with");
+        let expected = std::fs::read("./tests/test-data.json.bz2").unwrap();
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let mut source = runtime.block_on(async { S3Source::new(url) }).unwrap();
+
+        let mut bytes = Vec::new();
+        source.open().unwrap().0.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, expected);
+        bytes.clear();
+        source.open_at(10).unwrap().0.read_to_end(&mut bytes).unwrap();
+        assert_eq!(bytes, expected[10..]);
+    }
+}
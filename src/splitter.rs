@@ -0,0 +1,222 @@
+/*!
+ * Splitting of decompressed dump text into entities.
+ *
+ * A dump is a single JSON array with one entity per line:
+ *
+ * ```text
+ * [
+ * {"id": "Q1", ...},
+ * {"id": "Q2", ...}
+ * ]
+ * ```
+ *
+ * so entities can be found without parsing any JSON by splitting on ",\n".
+ */
+
+use std::io;
+use simdutf8::basic::from_utf8;
+
+/// Bytes preceding the first entity, "[\n" (both one byte ASCII characters)
+pub const DUMP_START: &str = "[\n";
+
+/// Bytes following the last entity
+pub const DUMP_END: &str = "\n]";
+
+/// Bytes separating two entities
+pub const ENTITY_SEPARATOR: &str = ",\n";
+
+/// Takes the complete entities off the front of `buffer`, leaving the incomplete last entity behind.
+///
+/// Entities are only taken once the buffer holds at least `min_length` bytes, unless the end of the
+/// dump has been reached. Returns the complete entities, still joined by `ENTITY_SEPARATOR`, and
+/// whether the end of the dump was found.
+pub fn take_complete(buffer: &mut String, min_length: usize) -> (Option<String>, bool) {
+    // the very end of the file will contain a '\n]', remove the two 1 byte ascii chars and allow it to be processed,
+    // otherwise everything up to the last ",\n" is a complete entity
+    let last = buffer.ends_with(DUMP_END);
+    let boundary = if last {
+        Some((buffer.len() - DUMP_END.len(), buffer.len()))
+    } else if buffer.len() >= min_length {
+        buffer.rfind(ENTITY_SEPARATOR).map(|i| (i, i + ENTITY_SEPARATOR.len()))
+    } else {
+        None
+    };
+
+    let entities = boundary.map(|(end, rest)| {
+        let remainder = buffer.split_off(rest);
+        buffer.truncate(end);
+        std::mem::replace(buffer, remainder)
+    });
+    (entities.filter(|entities| !entities.is_empty()), last)
+}
+
+/// Finds the id of a raw entity without parsing it, by taking the first "id" key.
+///
+/// Entities in dumps list their id before any claims (whose statements also have ids), so the
+/// first one found is the entity's own.
+pub fn entity_id(entity: &str) -> Option<&str> {
+    let start = entity.find("\"id\"")? + 4;
+    let rest = entity[start..].trim_start().strip_prefix(':')?.trim_start().strip_prefix('"')?;
+    rest.find('"').map(|end| &rest[..end])
+}
+
+/// Iterates over the individual entities of a run of complete entities
+pub fn entities(complete: &str) -> impl Iterator<Item = &str> {
+    complete.split(ENTITY_SEPARATOR)
+}
+
+/// Incrementally splits decompressed dump bytes into entities, however those bytes are chunked.
+///
+/// Unlike `take_complete`, bytes don't have to be valid UTF-8 until a whole entity has been seen,
+/// so multi-byte characters can straddle chunks.
+#[derive(Default)]
+pub struct EntityBuffer {
+    // decompressed bytes which haven't been returned yet
+    buffer: Vec<u8>,
+    // where to resume looking for a separator, so large entities aren't rescanned on every chunk
+    search_from: usize,
+    started: bool,
+    // whether `finish` found the end of the array
+    complete: bool,
+    // decompressed offset of the start of `buffer` within the dump
+    offset: u64,
+}
+
+impl EntityBuffer {
+    pub fn new() -> Self {
+        EntityBuffer::default()
+    }
+
+    /// Decompressed offset within the dump of the entity `next_entity` or `finish` returns next, once `check_start` has passed
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Whether the input ended with the end of the JSON array, once `finish` has been called.
+    ///
+    /// A dump which doesn't was cut short, and its last entity is most likely incomplete.
+    pub fn is_complete(&self) -> bool {
+        self.complete
+    }
+
+    /// Adds the next chunk of decompressed bytes
+    pub fn extend(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    /// Checks and discards the start of the dump, returning false if not enough bytes have been seen yet
+    pub fn check_start(&mut self) -> io::Result<bool> {
+        if self.started {
+            return Ok(true);
+        }
+        if self.buffer.len() < DUMP_START.len() {
+            return Ok(false);
+        }
+        if !self.buffer.starts_with(DUMP_START.as_bytes()) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "Input does not start with a JSON array"));
+        }
+        self.buffer.drain(..DUMP_START.len());
+        self.offset += DUMP_START.len() as u64;
+        self.started = true;
+        Ok(true)
+    }
+
+    /// Takes the next complete entity, or `None` if more bytes are needed
+    pub fn next_entity(&mut self) -> io::Result<Option<String>> {
+        if !self.check_start()? {
+            return Ok(None);
+        }
+        let separator = ENTITY_SEPARATOR.as_bytes();
+        match self.buffer[self.search_from..].windows(separator.len()).position(|window| window == separator) {
+            Some(i) => {
+                let length = self.search_from + i;
+                self.take(length, separator.len()).map(Some)
+            }
+            None => {
+                // the separator may straddle two chunks
+                self.search_from = self.buffer.len().saturating_sub(separator.len() - 1);
+                Ok(None)
+            }
+        }
+    }
+
+    /// Takes whatever is left once the input has ended as the last entity, dropping the end of the array
+    pub fn finish(&mut self) -> io::Result<Option<String>> {
+        if !self.check_start()? {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "Input ended before the start of the JSON array"));
+        }
+        // the end of the array may be followed by a newline, as in dumps written by other tools
+        let trimmed = self.buffer.len() - self.buffer.iter().rev().take_while(|byte| byte.is_ascii_whitespace()).count();
+        self.complete = self.buffer[..trimmed].ends_with(DUMP_END.as_bytes());
+        let (length, skip) = match self.complete {
+            true => (trimmed - DUMP_END.len(), self.buffer.len() - trimmed + DUMP_END.len()),
+            false => (self.buffer.len(), 0),
+        };
+        let entity = self.take(length, skip)?;
+        Ok(Some(entity).filter(|entity| !entity.is_empty()))
+    }
+
+    // takes `length` bytes off the front of the buffer as an entity, dropping the `skip` bytes after it
+    fn take(&mut self, length: usize, skip: usize) -> io::Result<String> {
+        let rest = self.buffer.split_off(length + skip);
+        let mut entity = std::mem::replace(&mut self.buffer, rest);
+        entity.truncate(length);
+        self.search_from = 0;
+        self.offset += (length + skip) as u64;
+        from_utf8(&entity).map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Entity is not valid UTF-8"))?;
+        // SAFETY: validated just above
+        Ok(unsafe { String::from_utf8_unchecked(entity) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_take_complete() {
+        let mut buffer = String::from("{\"id\": \"Q1\"},\n{\"id\": \"Q2\"},\n{\"id\"");
+        assert_eq!(take_complete(&mut buffer, 1000), (None, false));
+
+        let (complete, last) = take_complete(&mut buffer, 0);
+        assert_eq!(entities(&complete.unwrap()).collect::<Vec<_>>(), vec!["{\"id\": \"Q1\"}", "{\"id\": \"Q2\"}"]);
+        assert!(!last);
+        assert_eq!(buffer, "{\"id\"");
+
+        buffer.push_str(": \"Q3\"}\n]");
+        assert_eq!(take_complete(&mut buffer, 1000), (Some("{\"id\": \"Q3\"}".to_string()), true));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn test_entity_buffer_offset() {
+        let mut entities = EntityBuffer::new();
+        entities.extend(b"[\n{\"id\": \"Q1\"},\n{\"id\": \"Q2\"}\n]");
+        assert_eq!(entities.offset(), 0);
+        assert_eq!(entities.next_entity().unwrap().unwrap(), "{\"id\": \"Q1\"}");
+        assert_eq!(entities.offset(), 16);
+        assert_eq!(entities.finish().unwrap().unwrap(), "{\"id\": \"Q2\"}");
+        assert!(entities.is_complete());
+    }
+
+    #[test]
+    fn test_entity_buffer_end() {
+        let mut entities = EntityBuffer::new();
+        entities.extend(b"[\n{\"id\": \"Q1\"}\n]\n");
+        assert_eq!(entities.finish().unwrap().unwrap(), "{\"id\": \"Q1\"}");
+        assert!(entities.is_complete());
+
+        let mut entities = EntityBuffer::new();
+        entities.extend(b"[\n{\"id\": \"Q1\"},\n{\"id\"");
+        assert_eq!(entities.next_entity().unwrap().unwrap(), "{\"id\": \"Q1\"}");
+        assert_eq!(entities.finish().unwrap().unwrap(), "{\"id\"");
+        assert!(!entities.is_complete());
+    }
+
+    #[test]
+    fn test_entity_id() {
+        assert_eq!(entity_id(r#"{"type":"item","id":"Q31","claims":{"P1":[{"id":"Q31$1"}]}}"#), Some("Q31"));
+        assert_eq!(entity_id(r#"{"pageid": 186, "id" : "Q60"}"#), Some("Q60"));
+        assert_eq!(entity_id(r#"{"not valid"}"#), None);
+    }
+}
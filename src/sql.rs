@@ -0,0 +1,359 @@
+/*!
+ * SQL queries over the entities of a dump, executed by DataFusion as they
+ * stream in, e.g. `SELECT id, labels.en FROM entities WHERE type = 'property'`.
+ *
+ * Outputs are parsed as entities and simplified (see `model::SimpleEntity`),
+ * then gathered into Arrow record batches making up the `entities` table:
+ *
+ * - `id` and `type`, as strings
+ * - `labels`, `descriptions` and `sitelinks`, maps of a language (or site) to
+ *   a string, so `labels.en` or `labels['en']` is the English label
+ * - `aliases`, a map of a language to a list of strings
+ * - `claims`, a map of a property to a list of its values as strings: ids,
+ *   times and amounts as they are, anything else (e.g. coordinates) as JSON
+ *
+ * The query runs on its own thread while the dump is read, and its results are
+ * written as ndjson as soon as DataFusion produces them, so queries without
+ * aggregates or sorts don't hold the dump in memory. The table can only be
+ * scanned once, so a query joining it with itself fails.
+ */
+
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, Receiver, Sender, SyncSender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use datafusion::arrow::array::{ArrayRef, ListBuilder, MapBuilder, StringBuilder};
+use datafusion::arrow::datatypes::SchemaRef;
+use datafusion::arrow::json::LineDelimitedWriter;
+use datafusion::arrow::record_batch::RecordBatch;
+use datafusion::dataframe::DataFrame;
+use datafusion::datasource::streaming::StreamingTable;
+use datafusion::error::DataFusionError;
+use datafusion::execution::{SendableRecordBatchStream, TaskContext};
+use datafusion::execution::context::{SQLOptions, SessionContext};
+use datafusion::physical_plan::stream::RecordBatchStreamAdapter;
+use datafusion::physical_plan::streaming::PartitionStream;
+use futures_util::StreamExt;
+use log::debug;
+use serde_json::Value;
+use crate::error::{ProcessError, Result};
+use crate::model::{Entity, SimpleEntity};
+use crate::sink::Sink;
+
+/// Name of the table of entities queries select from
+pub const TABLE: &str = "entities";
+
+/// Rows gathered into each record batch, the same as DataFusion's own batches
+pub const DEFAULT_BATCH_SIZE: usize = 8192;
+
+// record batches waiting for the query to take them, before the sink waits for it to catch up
+const QUEUED_BATCHES: usize = 4;
+
+fn sql_error(error: DataFusionError) -> ProcessError {
+    ProcessError::Sql(error.to_string())
+}
+
+// a simplified claim value as a string: strings as they are, anything else as JSON
+fn value_string(value: &Value) -> String {
+    match value {
+        Value::String(value) => value.clone(),
+        value => value.to_string(),
+    }
+}
+
+/// Gathers simplified entities into record batches of the `entities` table
+pub struct EntityBatchBuilder {
+    ids: StringBuilder,
+    types: StringBuilder,
+    labels: MapBuilder<StringBuilder, StringBuilder>,
+    descriptions: MapBuilder<StringBuilder, StringBuilder>,
+    aliases: MapBuilder<StringBuilder, ListBuilder<StringBuilder>>,
+    claims: MapBuilder<StringBuilder, ListBuilder<StringBuilder>>,
+    sitelinks: MapBuilder<StringBuilder, StringBuilder>,
+    rows: usize,
+}
+
+impl Default for EntityBatchBuilder {
+    fn default() -> Self {
+        let strings = || MapBuilder::new(None, StringBuilder::new(), StringBuilder::new());
+        let lists = || MapBuilder::new(None, StringBuilder::new(), ListBuilder::new(StringBuilder::new()));
+        EntityBatchBuilder {
+            ids: StringBuilder::new(),
+            types: StringBuilder::new(),
+            labels: strings(),
+            descriptions: strings(),
+            aliases: lists(),
+            claims: lists(),
+            sitelinks: strings(),
+            rows: 0,
+        }
+    }
+}
+
+impl EntityBatchBuilder {
+    /// Number of entities gathered since the last batch
+    pub fn len(&self) -> usize {
+        self.rows
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows == 0
+    }
+
+    pub fn append(&mut self, entity: &SimpleEntity) {
+        self.ids.append_value(&entity.id);
+        self.types.append_value(&entity.entity_type);
+        for (map, terms) in [(&mut self.labels, &entity.labels), (&mut self.descriptions, &entity.descriptions), (&mut self.sitelinks, &entity.sitelinks)] {
+            for (key, value) in terms {
+                map.keys().append_value(key);
+                map.values().append_value(value);
+            }
+            map.append(true).expect("as many keys as values");
+        }
+        for (language, aliases) in &entity.aliases {
+            self.aliases.keys().append_value(language);
+            self.aliases.values().append_value(aliases.iter().map(Some));
+        }
+        self.aliases.append(true).expect("as many keys as values");
+        for (property, values) in &entity.claims {
+            self.claims.keys().append_value(property);
+            self.claims.values().append_value(values.iter().map(|value| Some(value_string(value))));
+        }
+        self.claims.append(true).expect("as many keys as values");
+        self.rows += 1;
+    }
+
+    /// The entities gathered so far as a record batch, starting over with none
+    pub fn finish(&mut self) -> RecordBatch {
+        self.rows = 0;
+        let columns: [(&str, ArrayRef); 7] = [
+            ("id", Arc::new(self.ids.finish())),
+            ("type", Arc::new(self.types.finish())),
+            ("labels", Arc::new(self.labels.finish())),
+            ("descriptions", Arc::new(self.descriptions.finish())),
+            ("aliases", Arc::new(self.aliases.finish())),
+            ("claims", Arc::new(self.claims.finish())),
+            ("sitelinks", Arc::new(self.sitelinks.finish())),
+        ];
+        // nullable whether or not this batch has nulls, so every batch has the same schema
+        RecordBatch::try_from_iter_with_nullable(columns.into_iter().map(|(name, column)| (name, column, true)))
+            .expect("columns of the same length")
+    }
+}
+
+/// Schema of the `entities` table, see the module documentation
+pub fn schema() -> SchemaRef {
+    EntityBatchBuilder::default().finish().schema()
+}
+
+// the record batches sent by the sink as the only partition of the table, which can only be scanned once
+#[derive(Debug)]
+struct EntityPartition {
+    schema: SchemaRef,
+    batches: Mutex<Option<Receiver<RecordBatch>>>,
+}
+
+impl PartitionStream for EntityPartition {
+    fn schema(&self) -> &SchemaRef {
+        &self.schema
+    }
+
+    fn execute(&self, _context: Arc<TaskContext>) -> SendableRecordBatchStream {
+        let schema = Arc::clone(&self.schema);
+        let batches = match self.batches.lock().expect("Entity partition poisoned").take() {
+            Some(batches) => Arc::new(Mutex::new(batches)),
+            None => {
+                let error = DataFusionError::Execution(format!("The {} table can only be scanned once per query", TABLE));
+                return Box::pin(RecordBatchStreamAdapter::new(schema, futures_util::stream::once(async move { Err(error) })));
+            }
+        };
+        let stream = futures_util::stream::unfold(batches, |batches| async move {
+            // the sink sends batches from a thread of its own, so they're waited for off the runtime
+            let receiver = Arc::clone(&batches);
+            let batch = tokio::task::spawn_blocking(move || receiver.lock().expect("Entity partition poisoned").recv().ok()).await.ok().flatten()?;
+            Some((Ok(batch), batches))
+        });
+        Box::pin(RecordBatchStreamAdapter::new(schema, stream))
+    }
+}
+
+// plans `sql` over a table of the batches received from `batches`, refusing statements which aren't queries
+async fn plan(sql: &str, batches: Receiver<RecordBatch>) -> std::result::Result<DataFrame, DataFusionError> {
+    let context = SessionContext::new();
+    let partition = EntityPartition { schema: schema(), batches: Mutex::new(Some(batches)) };
+    let table = StreamingTable::try_new(schema(), vec![Arc::new(partition)])?;
+    context.register_table(TABLE, Arc::new(table))?;
+    let options = SQLOptions::new().with_allow_ddl(false).with_allow_dml(false).with_allow_statements(false);
+    context.sql_with_options(sql, options).await
+}
+
+// plans `sql`, reporting whether it could be on `planned`, then runs it, sending each batch of results on
+fn run_query(sql: String, batches: Receiver<RecordBatch>, planned: SyncSender<Result<()>>, results: Sender<Result<RecordBatch>>) {
+    let runtime = match tokio::runtime::Builder::new_current_thread().enable_all().build() {
+        Ok(runtime) => runtime,
+        Err(error) => {
+            let _ = planned.send(Err(ProcessError::Sql(error.to_string())));
+            return;
+        }
+    };
+    runtime.block_on(async move {
+        let frame = match plan(&sql, batches).await {
+            Ok(frame) => frame,
+            Err(error) => {
+                let _ = planned.send(Err(sql_error(error)));
+                return;
+            }
+        };
+        let _ = planned.send(Ok(()));
+        let mut stream = match frame.execute_stream().await {
+            Ok(stream) => stream,
+            Err(error) => {
+                let _ = results.send(Err(sql_error(error)));
+                return;
+            }
+        };
+        while let Some(batch) = stream.next().await {
+            if results.send(batch.map_err(sql_error)).is_err() {
+                return;
+            }
+        }
+    });
+}
+
+/// Runs a SQL query over the outputs it's given, which must be whole entities, and writes its results to `output`
+/// as ndjson. See the module documentation for the table queried.
+pub struct SqlSink<W: Write> {
+    builder: EntityBatchBuilder,
+    batch_size: usize,
+    // gone once the query has all the entities, or stopped taking them
+    batches: Option<SyncSender<RecordBatch>>,
+    results: Receiver<Result<RecordBatch>>,
+    query: Option<JoinHandle<()>>,
+    output: W,
+}
+
+impl<W: Write> SqlSink<W> {
+    /// Plans `sql` over the `entities` table, failing if it isn't a valid query of it, and starts running it.
+    /// Entities are handed to the query `batch_size` at a time.
+    pub fn new(sql: &str, output: W, batch_size: usize) -> Result<Self> {
+        let (batches, received) = mpsc::sync_channel(QUEUED_BATCHES);
+        let (planned, plan_result) = mpsc::sync_channel(1);
+        let (results, results_received) = mpsc::channel();
+        let sql = sql.to_string();
+        let query = thread::Builder::new()
+            .name(String::from("sql"))
+            .spawn(move || run_query(sql, received, planned, results))
+            .map_err(|error| ProcessError::Sql(error.to_string()))?;
+        plan_result.recv().map_err(|_| ProcessError::Sql(String::from("The query stopped before it was planned")))??;
+        debug!("Planned the SQL query");
+        Ok(SqlSink {
+            builder: EntityBatchBuilder::default(),
+            batch_size: batch_size.max(1),
+            batches: Some(batches),
+            results: results_received,
+            query: Some(query),
+            output,
+        })
+    }
+
+    // hands the entities gathered so far to the query
+    fn send_batch(&mut self) {
+        let batch = self.builder.finish();
+        if let Some(batches) = &self.batches {
+            // a query with a LIMIT stops taking entities once it has its results, which are all there is to write
+            if batches.send(batch).is_err() {
+                debug!("The SQL query stopped taking entities");
+                self.batches = None;
+            }
+        }
+    }
+
+    fn write_results(&mut self, batch: Result<RecordBatch>) -> Result<()> {
+        let batch = batch?;
+        let mut writer = LineDelimitedWriter::new(&mut self.output);
+        writer.write(&batch).map_err(|error| ProcessError::Sql(error.to_string()))?;
+        writer.finish().map_err(|error| ProcessError::Sql(error.to_string()))
+    }
+
+    // writes the results ready so far, without waiting for more
+    fn write_ready_results(&mut self) -> Result<()> {
+        loop {
+            match self.results.try_recv() {
+                Ok(batch) => self.write_results(batch)?,
+                Err(TryRecvError::Empty | TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+    }
+}
+
+impl<W: Write> Sink for SqlSink<W> {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let entity = Entity::parse(output)
+            .map_err(|error| ProcessError::Sql(format!("Could not read an output as an entity, --sql needs a jq filter keeping whole entities: {}", error)))?;
+        self.builder.append(&entity.simplify());
+        if self.builder.len() >= self.batch_size {
+            self.send_batch();
+            self.write_ready_results()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_ready_results()?;
+        self.output.flush().map_err(ProcessError::Write)
+    }
+
+    /// Hands the last entities to the query, and writes every result left once it has run
+    fn finalize(&mut self) -> Result<()> {
+        if !self.builder.is_empty() {
+            self.send_batch();
+        }
+        self.batches = None;
+        while let Ok(batch) = self.results.recv() {
+            self.write_results(batch)?;
+        }
+        if let Some(query) = self.query.take() {
+            query.join().map_err(|_| ProcessError::Sql(String::from("The query panicked")))?;
+        }
+        self.output.flush().map_err(ProcessError::Write)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Pipeline;
+
+    // the results of `sql` over the test dump, as lines of JSON
+    fn query(sql: &str) -> Vec<Value> {
+        let mut output = Vec::new();
+        Pipeline::builder()
+            .source("./tests/test-data.json.bz2")
+            .entity_sink(SqlSink::new(sql, &mut output, 3).unwrap())
+            .build()
+            .unwrap()
+            .run()
+            .unwrap();
+        String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_query() {
+        let rows = query("SELECT id, labels.en AS label FROM entities WHERE id = 'Q60'");
+        assert_eq!(rows, vec![serde_json::json!({"id": "Q60", "label": "New York City"})]);
+
+        let rows = query("SELECT type, COUNT(*) AS entities FROM entities GROUP BY type ORDER BY type");
+        assert_eq!(rows, vec![serde_json::json!({"type": "item", "entities": 7}), serde_json::json!({"type": "property", "entities": 1})]);
+
+        let rows = query("SELECT id FROM entities WHERE array_has(claims['P150'], 'Q11299')");
+        assert_eq!(rows, vec![serde_json::json!({"id": "Q60"})]);
+
+        assert_eq!(query("SELECT id FROM entities LIMIT 2").len(), 2);
+    }
+
+    #[test]
+    fn test_invalid_query() {
+        assert!(matches!(SqlSink::new("SELECT nothing FROM entities", Vec::new(), 3), Err(ProcessError::Sql(_))));
+        assert!(matches!(SqlSink::new("DROP TABLE entities", Vec::new(), 3), Err(ProcessError::Sql(_))));
+    }
+}
@@ -0,0 +1,185 @@
+/*!
+ * SQLite databases of outputs keyed by the id of their entity (with the
+ * `sqlite` feature), a single file that anything with SQLite bindings can
+ * query, e.g. with its JSON functions:
+ *
+ * ```text
+ * SELECT json_extract(entity, '$.labels.en.value') FROM entities WHERE id = 'Q42'
+ * ```
+ *
+ * Outputs go in the `entities` table, an `id` primary key and the output as
+ * it is in `entity`. `SqliteSink` gathers them until `batch_size` bytes are
+ * waiting, and inserts them in one transaction. The database is new, and
+ * thrown away if the run fails, so it's written without a journal or syncs.
+ * An id seen more than once is stored with its last output. `SqliteStore`
+ * opens such a database read-only for lookups.
+ */
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use log::debug;
+use rusqlite::{Connection, OpenFlags, OptionalExtension};
+use crate::error::{ProcessError, Result};
+use crate::sink::Sink;
+use crate::splitter;
+
+/// Name of the table outputs are written to
+pub const TABLE: &str = "entities";
+
+// what every SQLite database file starts with
+const HEADER: &[u8] = b"SQLite format 3\0";
+
+fn sqlite_error(error: rusqlite::Error) -> ProcessError {
+    ProcessError::Sqlite(error.to_string())
+}
+
+// whether the file at `path` is a SQLite database, from its header
+fn is_database(path: &Path) -> bool {
+    let mut header = [0; HEADER.len()];
+    fs::File::open(path).and_then(|mut file| file.read_exact(&mut header)).is_ok() && header == HEADER
+}
+
+/// Writes outputs into a new SQLite database keyed by entity id, see the module documentation
+pub struct SqliteSink {
+    connection: Connection,
+    batch_size: usize,
+    // ids and outputs waiting to be inserted, and their size
+    pending: Vec<(String, String)>,
+    pending_bytes: usize,
+    written: u64,
+}
+
+impl SqliteSink {
+    /// Creates the database file `path`. If there is one already, it's removed first when `overwrite` is set,
+    /// otherwise this fails, as it does if `path` is anything but a SQLite database.
+    pub fn create(path: &Path, overwrite: bool, batch_size: usize) -> Result<Self> {
+        if path.exists() {
+            if !overwrite {
+                return Err(ProcessError::OutputExists(path.to_path_buf()));
+            }
+            if !is_database(path) {
+                return Err(ProcessError::Sqlite(format!("{:?} isn't a SQLite database, so it isn't overwritten", path)));
+            }
+            fs::remove_file(path).map_err(|source| ProcessError::CreateOutput { path: path.to_path_buf(), source })?;
+        }
+        let connection = Connection::open(path).map_err(sqlite_error)?;
+        connection.execute_batch(&format!("PRAGMA journal_mode = OFF;
+            PRAGMA synchronous = OFF;
+            CREATE TABLE {} (id TEXT PRIMARY KEY NOT NULL, entity TEXT NOT NULL) WITHOUT ROWID;", TABLE))
+            .map_err(sqlite_error)?;
+        debug!("Created {:?}", path);
+        Ok(SqliteSink { connection, batch_size, pending: Vec::new(), pending_bytes: 0, written: 0 })
+    }
+
+    /// Number of outputs written so far
+    pub fn written(&self) -> u64 {
+        self.written
+    }
+
+    // inserts the waiting outputs in a single transaction
+    fn insert(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let transaction = self.connection.transaction().map_err(sqlite_error)?;
+        {
+            let mut insert = transaction.prepare_cached(&format!("INSERT OR REPLACE INTO {} (id, entity) VALUES (?1, ?2)", TABLE))
+                .map_err(sqlite_error)?;
+            for (id, output) in &self.pending {
+                insert.execute((id, output)).map_err(sqlite_error)?;
+            }
+        }
+        transaction.commit().map_err(sqlite_error)?;
+        self.written += self.pending.len() as u64;
+        self.pending.clear();
+        self.pending_bytes = 0;
+        Ok(())
+    }
+}
+
+impl Sink for SqliteSink {
+    fn write_entity(&mut self, output: &str) -> Result<()> {
+        let id = splitter::entity_id(output)
+            .ok_or_else(|| ProcessError::Sqlite(format!("Output has no id to key it by, the jq filter has to keep it: {:.100}", output)))?;
+        self.pending_bytes += id.len() + output.len();
+        self.pending.push((id.to_string(), output.to_string()));
+        if self.pending_bytes >= self.batch_size {
+            self.insert()?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.insert()
+    }
+}
+
+/// A database written by `SqliteSink`, opened read-only for lookups
+pub struct SqliteStore {
+    connection: Connection,
+}
+
+impl SqliteStore {
+    pub fn open(path: &Path) -> Result<Self> {
+        if !is_database(path) {
+            return Err(ProcessError::Sqlite(format!("{:?} isn't a SQLite database", path)));
+        }
+        let connection = Connection::open_with_flags(path, OpenFlags::SQLITE_OPEN_READ_ONLY | OpenFlags::SQLITE_OPEN_NO_MUTEX).map_err(sqlite_error)?;
+        Ok(SqliteStore { connection })
+    }
+
+    /// The output stored for the entity `id`, if any
+    pub fn get(&self, id: &str) -> Result<Option<String>> {
+        let mut select = self.connection.prepare_cached(&format!("SELECT entity FROM {} WHERE id = ?1", TABLE)).map_err(sqlite_error)?;
+        select.query_row((id,), |row| row.get(0)).optional().map_err(sqlite_error)
+    }
+
+    /// Number of entities in the store
+    pub fn len(&self) -> Result<u64> {
+        let count: i64 = self.connection.query_row(&format!("SELECT COUNT(*) FROM {}", TABLE), (), |row| row.get(0)).map_err(sqlite_error)?;
+        Ok(count as u64)
+    }
+
+    pub fn is_empty(&self) -> Result<bool> {
+        Ok(self.len()? == 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sqlite_sink() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("entities.db");
+        let mut sink = SqliteSink::create(&path, false, 64).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"Earth"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q1","label":"universe"}"#).unwrap();
+        sink.write_entity(r#"{"id":"Q2","label":"the Earth"}"#).unwrap();
+        assert!(matches!(sink.write_entity(r#"["Q4"]"#), Err(ProcessError::Sqlite(_))));
+        sink.finalize().unwrap();
+        assert_eq!(sink.written(), 3);
+        drop(sink);
+
+        let connection = Connection::open(&path).unwrap();
+        let count: i64 = connection.query_row("SELECT COUNT(*) FROM entities", (), |row| row.get(0)).unwrap();
+        assert_eq!(count, 2);
+        let label: String = connection.query_row("SELECT json_extract(entity, '$.label') FROM entities WHERE id = 'Q2'", (), |row| row.get(0)).unwrap();
+        assert_eq!(label, "the Earth");
+        drop(connection);
+
+        let store = SqliteStore::open(&path).unwrap();
+        assert_eq!(store.len().unwrap(), 2);
+        assert_eq!(store.get("Q2").unwrap().as_deref(), Some(r#"{"id":"Q2","label":"the Earth"}"#));
+        assert_eq!(store.get("Q3").unwrap(), None);
+        drop(store);
+
+        assert!(matches!(SqliteSink::create(&path, false, 64), Err(ProcessError::OutputExists(_))));
+        assert!(SqliteSink::create(&path, true, 64).is_ok());
+        let other = directory.path().join("entities.ndjson");
+        fs::write(&other, "{}\n").unwrap();
+        assert!(matches!(SqliteSink::create(&other, true, 64), Err(ProcessError::Sqlite(_))));
+    }
+}
@@ -0,0 +1,84 @@
+/*!
+ * Async streams of entities for tokio based applications. Entities are only
+ * read as fast as the stream is polled, so a slow consumer applies
+ * back-pressure all the way to the source (e.g. an HTTP download).
+ *
+ * ```no_run
+ * use futures_util::StreamExt;
+ * use wikidump_process::{download, stream};
+ *
+ * # async fn run() -> Result<(), Box<dyn std::error::Error>> {
+ * let mut entities = Box::pin(stream::http_entity_stream(&download::dump_url("latest")).await?);
+ * while let Some(entity) = entities.next().await {
+ *     println!("{}", entity?.len());
+ * }
+ * # Ok(())
+ * # }
+ * ```
+ */
+
+use std::io;
+use async_compression::tokio::bufread::BzDecoder;
+use futures_util::{Stream, TryStreamExt};
+use tokio::io::{AsyncBufRead, AsyncRead, AsyncReadExt};
+use tokio_util::io::StreamReader;
+use crate::decoder::BUFFER_LENGTH;
+use crate::splitter::EntityBuffer;
+
+struct State<R> {
+    reader: R,
+    entities: EntityBuffer,
+    chunk: Vec<u8>,
+    finished: bool,
+}
+
+/// Streams the entities of decompressed dump text
+pub fn entity_stream<R: AsyncRead + Unpin>(reader: R) -> impl Stream<Item = io::Result<String>> {
+    let state = State { reader, entities: EntityBuffer::new(), chunk: vec![0; BUFFER_LENGTH], finished: false };
+    futures_util::stream::try_unfold(state, |mut state| async move {
+        loop {
+            if let Some(entity) = state.entities.next_entity()? {
+                return Ok(Some((entity, state)));
+            }
+            if state.finished {
+                return Ok(None);
+            }
+            let n = state.reader.read(&mut state.chunk).await?;
+            if n == 0 {
+                state.finished = true;
+                return Ok(state.entities.finish()?.map(|entity| (entity, state)));
+            }
+            let chunk = &state.chunk[..n];
+            state.entities.extend(chunk);
+        }
+    })
+}
+
+/// Streams the entities of a bzip2 compressed dump
+pub fn bz2_entity_stream<R: AsyncBufRead + Unpin>(reader: R) -> impl Stream<Item = io::Result<String>> {
+    let mut decoder = BzDecoder::new(reader);
+    // dumps are made of many concatenated bzip2 streams
+    decoder.multiple_members(true);
+    entity_stream(decoder)
+}
+
+/// Streams the entities of the bzip2 compressed dump at `url` as it downloads
+pub async fn http_entity_stream(url: &str) -> Result<impl Stream<Item = io::Result<String>>, reqwest::Error> {
+    let response = reqwest::get(url).await?.error_for_status()?;
+    let bytes = response.bytes_stream().map_err(io::Error::other);
+    Ok(bz2_entity_stream(StreamReader::new(bytes)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures_util::TryStreamExt;
+
+    #[tokio::test]
+    async fn test_bz2_entity_stream() {
+        let file = tokio::fs::File::open("./tests/test-data.json.bz2").await.unwrap();
+        let entities: Vec<String> = bz2_entity_stream(tokio::io::BufReader::new(file)).try_collect().await.unwrap();
+        assert_eq!(entities.len(), 8);
+        assert!(entities[6].starts_with(r#"{"id": "P1","#));
+    }
+}
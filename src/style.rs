@@ -0,0 +1,128 @@
+/*!
+ * Consistent formatting of JSON outputs, whatever produced them: compact, on
+ * a single line per value, or pretty-printed with two spaces of indentation
+ * as jq does by default.
+ *
+ * Only whitespace between tokens is changed, keys staying in the order they
+ * were written in. An output may hold several JSON values (e.g. from a jq
+ * filter yielding more than one), which are formatted one after the other.
+ * Outputs which aren't JSON are left as they are.
+ */
+
+use serde::de::IgnoredAny;
+use serde_json::Deserializer;
+
+/// How JSON outputs are formatted
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputStyle {
+    /// Each value on a single line, without any whitespace between tokens
+    Compact,
+    /// Each value indented with two spaces, one member or element per line
+    Pretty,
+}
+
+fn is_json(output: &str) -> bool {
+    let mut values = Deserializer::from_str(output).into_iter::<IgnoredAny>();
+    values.all(|value| value.is_ok()) && !output.trim().is_empty()
+}
+
+fn newline(formatted: &mut String, depth: usize) {
+    formatted.push('\n');
+    for _ in 0..depth {
+        formatted.push_str("  ");
+    }
+}
+
+/// `output` formatted in `style`, see the module documentation
+pub fn format_output(output: String, style: OutputStyle) -> String {
+    if !is_json(&output) {
+        return output;
+    }
+    let mut formatted = String::with_capacity(output.len());
+    let mut chars = output.trim().chars().peekable();
+    let mut depth = 0;
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                formatted.push(c);
+                while let Some(c) = chars.next() {
+                    formatted.push(c);
+                    match c {
+                        '\\' => formatted.extend(chars.next()),
+                        '"' => break,
+                        _ => {}
+                    }
+                }
+            }
+            ' ' | '\t' | '\r' | '\n' => {
+                // whitespace only matters between two values at the top level
+                if depth == 0 && !formatted.is_empty() && !formatted.ends_with('\n') {
+                    while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+                    if chars.peek().is_some() {
+                        formatted.push('\n');
+                    }
+                }
+            }
+            '{' | '[' => {
+                formatted.push(c);
+                while chars.next_if(|c| c.is_ascii_whitespace()).is_some() {}
+                if matches!(chars.peek(), Some('}' | ']')) {
+                    formatted.extend(chars.next());
+                } else {
+                    depth += 1;
+                    if style == OutputStyle::Pretty {
+                        newline(&mut formatted, depth);
+                    }
+                }
+            }
+            '}' | ']' => {
+                depth -= 1;
+                if style == OutputStyle::Pretty {
+                    newline(&mut formatted, depth);
+                }
+                formatted.push(c);
+            }
+            ',' => {
+                formatted.push(c);
+                if style == OutputStyle::Pretty {
+                    newline(&mut formatted, depth);
+                }
+            }
+            ':' => {
+                formatted.push(c);
+                if style == OutputStyle::Pretty {
+                    formatted.push(' ');
+                }
+            }
+            c => {
+                // values directly following each other at the top level, e.g. `1 2`, are told apart by whitespace
+                if depth == 0 && matches!(formatted.chars().last(), Some('}' | ']' | '"')) {
+                    formatted.push('\n');
+                }
+                formatted.push(c);
+            }
+        }
+    }
+    formatted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_output() {
+        let output = String::from("{\"id\": \"Q42\",\n \"labels\": {\"en\": {\"value\": \"a \\\"b\\\", c: [d]\"}}, \"aliases\": {}, \"claims\": [ ]}");
+        assert_eq!(format_output(output.clone(), OutputStyle::Compact), r#"{"id":"Q42","labels":{"en":{"value":"a \"b\", c: [d]"}},"aliases":{},"claims":[]}"#);
+        assert_eq!(
+            format_output(output, OutputStyle::Pretty),
+            "{\n  \"id\": \"Q42\",\n  \"labels\": {\n    \"en\": {\n      \"value\": \"a \\\"b\\\", c: [d]\"\n    }\n  },\n  \"aliases\": {},\n  \"claims\": []\n}"
+        );
+
+        let pretty = format_output(String::from("\"Q1\"\n[1, 2]\n{\"a\" : null}"), OutputStyle::Pretty);
+        assert_eq!(pretty, "\"Q1\"\n[\n  1,\n  2\n]\n{\n  \"a\": null\n}");
+        assert_eq!(format_output(pretty, OutputStyle::Compact), "\"Q1\"\n[1,2]\n{\"a\":null}");
+        assert_eq!(format_output(String::from("1 2"), OutputStyle::Compact), "1\n2");
+        assert_eq!(format_output(String::from("Q42\tP31"), OutputStyle::Pretty), "Q42\tP31");
+    }
+}
@@ -0,0 +1,280 @@
+/*!
+ * Full-text indexes of the labels, aliases and descriptions of entities, built
+ * with tantivy, so a filtered subset of a dump can be searched right away, e.g.
+ * for entity linking experiments. Only the terms of each entity are parsed, as
+ * for label tables (see `labels`).
+ *
+ * Every entity with a term in one of the languages indexed becomes a document
+ * with an `id` field, and for each language `label_<language>`,
+ * `alias_<language>` (one value per alias) and `description_<language>`
+ * fields. All of them are stored, so search results can be shown without
+ * going back to the dump. Terms are split with tantivy's default tokenizer,
+ * which lowercases words without stemming them; it suits languages written
+ * with spaces between words.
+ */
+
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use serde::Serialize;
+use tantivy::collector::TopDocs;
+use tantivy::query::QueryParser;
+use tantivy::schema::{Field, Schema, Value, STORED, STRING, TEXT};
+use tantivy::{Index, IndexReader, IndexWriter, TantivyDocument, TantivyError};
+use crate::error::{ProcessError, Result};
+use crate::filter::{self, EntityFilter};
+use crate::labels::Terms;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::source::Source;
+
+/// Field holding the id of the entity of each document
+pub const ID_FIELD: &str = "id";
+
+/// Field holding the label of entities in `language`
+pub fn label_field(language: &str) -> String {
+    format!("label_{}", language)
+}
+
+/// Field holding the aliases of entities in `language`
+pub fn alias_field(language: &str) -> String {
+    format!("alias_{}", language)
+}
+
+/// Field holding the description of entities in `language`
+pub fn description_field(language: &str) -> String {
+    format!("description_{}", language)
+}
+
+/// Schema of a text index of the terms in `languages`, see the module documentation
+pub fn schema(languages: &[String]) -> Schema {
+    let mut builder = Schema::builder();
+    builder.add_text_field(ID_FIELD, STRING | STORED);
+    for language in languages {
+        builder.add_text_field(&label_field(language), TEXT | STORED);
+        builder.add_text_field(&alias_field(language), TEXT | STORED);
+        builder.add_text_field(&description_field(language), TEXT | STORED);
+    }
+    builder.build()
+}
+
+// the fields of the schema, looked up once rather than for every entity
+struct Fields {
+    id: Field,
+    // language, label, alias and description
+    languages: Vec<(String, Field, Field, Field)>,
+}
+
+impl Fields {
+    fn new(schema: &Schema, languages: &[String]) -> Self {
+        let field = |name: &str| schema.get_field(name).expect("field of the schema");
+        Fields {
+            id: field(ID_FIELD),
+            languages: languages.iter()
+                .map(|language| (language.clone(), field(&label_field(language)), field(&alias_field(language)), field(&description_field(language))))
+                .collect(),
+        }
+    }
+
+    // the document of an entity, if it has any terms in the languages indexed
+    fn document(&self, terms: &Terms) -> Option<TantivyDocument> {
+        let mut document = TantivyDocument::default();
+        let mut empty = true;
+        for (language, label, alias, description) in &self.languages {
+            if let Some(term) = terms.labels.get(language.as_str()) {
+                document.add_text(*label, &term.value);
+                empty = false;
+            }
+            for term in terms.aliases.get(language.as_str()).into_iter().flatten() {
+                document.add_text(*alias, &term.value);
+                empty = false;
+            }
+            if let Some(term) = terms.descriptions.get(language.as_str()) {
+                document.add_text(*description, &term.value);
+                empty = false;
+            }
+        }
+        if empty {
+            return None;
+        }
+        document.add_text(self.id, &terms.id);
+        Some(document)
+    }
+}
+
+fn index_error(path: &Path) -> impl Fn(TantivyError) -> ProcessError + '_ {
+    move |error| ProcessError::TextIndex { path: path.to_path_buf(), message: error.to_string() }
+}
+
+// adds the document of each entity to the index, shared by all threads. The id of each entity indexed is its
+// output, so the run's stats count them as written
+struct TextIndexer {
+    writer: Arc<IndexWriter>,
+    fields: Arc<Fields>,
+    path: PathBuf,
+    continue_on_error: bool,
+    failures: usize,
+}
+
+impl EntityFilter for TextIndexer {
+    fn apply<'a>(&mut self, raw: &'a str) -> Result<Option<Cow<'a, str>>> {
+        let terms: Terms = match serde_json::from_str(raw) {
+            Ok(terms) => terms,
+            Err(error) => {
+                filter::skip_entity(raw, &error, self.continue_on_error)?;
+                self.failures += 1;
+                return Ok(None);
+            }
+        };
+        let document = match self.fields.document(&terms) {
+            Some(document) => document,
+            None => return Ok(None),
+        };
+        self.writer.add_document(document).map_err(index_error(&self.path))?;
+        Ok(Some(terms.id))
+    }
+
+    fn failures(&self) -> usize {
+        self.failures
+    }
+}
+
+/// Builds a text index of the terms in `languages` of every entity of `source` in `directory`, which is created
+/// if needed and mustn't hold an index already. The index is split into at most `writer_memory` bytes worth of
+/// segments at a time (at least 15MB per indexing thread, which tantivy picks the number of), which are then
+/// merged in the background until the index is committed. A cancelled run commits what was indexed so far.
+pub fn write_text_index(source: impl Source, languages: Vec<String>, writer_memory: usize, options: ProcessOptions, directory: &Path) -> Result<ProcessStats> {
+    let error = index_error(directory);
+    fs::create_dir_all(directory).map_err(|source| ProcessError::CreateOutput { path: directory.to_path_buf(), source })?;
+    let index = Index::create_in_dir(directory, schema(&languages)).map_err(&error)?;
+    let writer = Arc::new(index.writer(writer_memory).map_err(&error)?);
+    let fields = Arc::new(Fields::new(&index.schema(), &languages));
+
+    let continue_on_error = options.continue_on_error;
+    let indexers = Arc::clone(&writer);
+    let path = directory.to_path_buf();
+    let stats = Pipeline::builder()
+        .dump_source(source)
+        .entity_filter(move || Ok(TextIndexer { writer: Arc::clone(&indexers), fields: Arc::clone(&fields), path: path.clone(), continue_on_error, failures: 0 }))
+        .sink(io::sink())
+        .options(options)
+        .build()?
+        .run()?;
+
+    // the pipeline, and with it every other reference to the writer, is gone once it has run
+    let mut writer = Arc::try_unwrap(writer).unwrap_or_else(|_| panic!("Text index writer still shared"));
+    writer.commit().map_err(&error)?;
+    writer.wait_merging_threads().map_err(&error)?;
+    Ok(stats)
+}
+
+/// A match of a search of a text index
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f32,
+    /// Every field stored for the entity, e.g. `label_en`, except its id
+    pub fields: BTreeMap<String, Vec<String>>,
+}
+
+/// A text index opened for searching, from any number of threads
+pub struct TextSearch {
+    path: PathBuf,
+    schema: Schema,
+    reader: IndexReader,
+    parser: QueryParser,
+    id: Field,
+}
+
+impl TextSearch {
+    /// Opens the text index built in `directory` by `write_text_index`
+    pub fn open(directory: &Path) -> Result<Self> {
+        let error = index_error(directory);
+        let index = Index::open_in_dir(directory).map_err(&error)?;
+        let schema = index.schema();
+        let id = schema.get_field(ID_FIELD).map_err(&error)?;
+        let terms = schema.fields().map(|(field, _)| field).filter(|field| *field != id).collect();
+        let parser = QueryParser::for_index(&index, terms);
+        let reader = index.reader().map_err(&error)?;
+        Ok(TextSearch { path: directory.to_path_buf(), schema, reader, parser, id })
+    }
+
+    /// Number of entities indexed
+    pub fn len(&self) -> u64 {
+        self.reader.searcher().num_docs()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The `limit` best matches of `query`, best first. Queries use tantivy's syntax, e.g. `new york` matches
+    /// either word in any label, alias or description, and `label_en:"new york"` only the phrase in English labels.
+    pub fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchHit>> {
+        let query = self.parser.parse_query(query).map_err(|error| ProcessError::InvalidQuery(error.to_string()))?;
+        let searcher = self.reader.searcher();
+        let error = index_error(&self.path);
+        let mut hits = Vec::new();
+        for (score, address) in searcher.search(&query, &TopDocs::with_limit(limit).order_by_score()).map_err(&error)? {
+            let document: TantivyDocument = searcher.doc(address).map_err(&error)?;
+            let mut hit = SearchHit { id: String::new(), score, fields: BTreeMap::new() };
+            for (field, value) in document.field_values() {
+                let value = value.as_str().unwrap_or_default().to_string();
+                match field == self.id {
+                    true => hit.id = value,
+                    false => hit.fields.entry(self.schema.get_field_name(field).to_string()).or_default().push(value),
+                }
+            }
+            hits.push(hit);
+        }
+        Ok(hits)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::source::FileSource;
+
+    // ids of the best matches for `query`
+    fn search(index: &TextSearch, query: &str) -> Vec<String> {
+        index.search(query, 10).unwrap().into_iter().map(|hit| hit.id).collect()
+    }
+
+    #[test]
+    fn test_write_text_index() {
+        let directory = tempfile::tempdir().unwrap();
+        let languages = vec![String::from("en"), String::from("fr")];
+        let stats = write_text_index(FileSource::new("./tests/test-data.json.bz2"), languages, 50_000_000, ProcessOptions::default(), directory.path()).unwrap();
+        assert_eq!(stats.entities_read, 8);
+        assert_eq!(stats.entities_written, 8);
+
+        let index = TextSearch::open(directory.path()).unwrap();
+        assert_eq!(index.len(), 8);
+        assert_eq!(search(&index, "label_en:york"), vec!["Q60"]);
+        assert_eq!(search(&index, "alias_en:nyc"), vec!["Q60"]);
+        assert_eq!(search(&index, "description_en:\"largest city\""), vec!["Q60"]);
+        assert!(search(&index, "label_en:universe").is_empty());
+        let hits = index.search("nyc", 1).unwrap();
+        assert_eq!(hits[0].fields["label_en"], vec!["New York City"]);
+        assert_eq!(hits[0].fields["alias_en"], vec!["NYC", "New York"]);
+        assert!(matches!(index.search("label_en:(york", 1), Err(ProcessError::InvalidQuery(_))));
+
+        // an index is never overwritten
+        let error = write_text_index(FileSource::new("./tests/test-data.json.bz2"), vec![String::from("en")], 50_000_000, ProcessOptions::default(), directory.path());
+        assert!(matches!(error, Err(ProcessError::TextIndex { .. })));
+    }
+
+    #[test]
+    fn test_languages() {
+        let directory = tempfile::tempdir().unwrap();
+        let stats = write_text_index(FileSource::new("./tests/test-data.json.bz2"), vec![String::from("ar")], 50_000_000, ProcessOptions::default(), directory.path()).unwrap();
+        assert_eq!(stats.entities_written, 1);
+        let index = Index::open_in_dir(directory.path()).unwrap();
+        assert!(index.schema().get_field("label_ar").is_ok());
+        assert!(index.schema().get_field("label_en").is_err());
+    }
+}
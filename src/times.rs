@@ -0,0 +1,189 @@
+/*!
+ * Normalizing time values to plain ISO 8601, instead of every consumer
+ * reimplementing Wikibase's format: a leading `+`, zeroed months and days
+ * below month or day precision, BCE years without a year 0 and dates in
+ * the Julian calendar.
+ *
+ * Each time datavalue (in main snaks, qualifiers and references alike) gets a
+ * `normalized` member next to its original value, with the time cut down to
+ * its precision and the name of that precision:
+ * `{"time":"+1952-03-00T00:00:00Z","precision":10,...,"normalized":{"time":"1952-03","precision":"month"}}`.
+ * Years are astronomical, as in ISO 8601, so 1 BCE is `0000` and 44 BCE
+ * `-0043` (except below year precision, where they're kept as they are), and
+ * have a sign when outside 0000 to 9999. Julian calendar dates with a day are
+ * converted to the proleptic Gregorian calendar; those only precise to the
+ * month or year are kept as they are, as they can't be converted without
+ * guessing.
+ */
+
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use crate::model::TimeValue;
+
+/// Calendar model of dates in the Julian calendar
+pub const JULIAN: &str = "http://www.wikidata.org/entity/Q1985786";
+
+/// Names of the precisions of time values, from 0 (billion years) to 14 (seconds)
+pub const PRECISIONS: [&str; 15] = [
+    "billion years", "hundred million years", "ten million years", "million years", "hundred thousand years",
+    "ten thousand years", "millennium", "century", "decade", "year", "month", "day", "hour", "minute", "second",
+];
+
+// year (historical, so without a year 0), month, day, hour, minute and second of a Wikibase time string
+fn parse(time: &str) -> Option<(i64, u32, u32, u32, u32, u32)> {
+    let (date, clock) = time.strip_suffix('Z')?.split_once('T')?;
+    let (negative, date) = match date.as_bytes().first()? {
+        b'-' => (true, &date[1..]),
+        b'+' => (false, &date[1..]),
+        _ => (false, date),
+    };
+    let mut date = date.splitn(3, '-');
+    let year: i64 = date.next()?.parse().ok()?;
+    let month = date.next()?.parse().ok()?;
+    let day = date.next()?.parse().ok()?;
+    let mut clock = clock.splitn(3, ':').map(str::parse::<u32>);
+    let (hour, minute, second) = (clock.next()?.ok()?, clock.next()?.ok()?, clock.next()?.ok()?);
+    Some((if negative { -year } else { year }, month, day, hour, minute, second))
+}
+
+// the proleptic Gregorian date of a Julian calendar date, with astronomical years
+fn julian_to_gregorian(year: i64, month: u32, day: u32) -> (i64, u32, u32) {
+    // through the Julian day number, see https://en.wikipedia.org/wiki/Julian_day#Converting_Julian_calendar_date_to_Julian_Day_Number
+    let a = (14 - month as i64) / 12;
+    let y = year + 4800 - a;
+    let m = month as i64 + 12 * a - 3;
+    let jdn = day as i64 + (153 * m + 2) / 5 + 365 * y + y.div_euclid(4) - 32083;
+    let a = jdn + 32044;
+    let b = (4 * a + 3).div_euclid(146097);
+    let c = a - (146097 * b).div_euclid(4);
+    let d = (4 * c + 3) / 1461;
+    let e = c - 1461 * d / 4;
+    let m = (5 * e + 2) / 153;
+    let day = e - (153 * m + 2) / 5 + 1;
+    let month = m + 3 - 12 * (m / 10);
+    (100 * b + d - 4800 + m / 10, month as u32, day as u32)
+}
+
+fn format_year(year: i64) -> String {
+    match year {
+        0..=9999 => format!("{:04}", year),
+        _ => format!("{:+05}", year),
+    }
+}
+
+/// `time` as ISO 8601 cut down to `precision` (see `PRECISIONS`), in the proleptic Gregorian calendar if it has a
+/// day and is in `calendar`, see the module documentation. `None` if `time` isn't a Wikibase time string.
+pub fn iso_time(time: &str, precision: u8, calendar: &str) -> Option<String> {
+    let (year, mut month, mut day, hour, minute, second) = parse(time)?;
+    // zeroed months and days are only expected below their precision, but are found above it too
+    let precision = match (precision, month, day) {
+        (10.., 0, _) => 9,
+        (11.., _, 0) => 10,
+        (precision, _, _) => precision,
+    };
+    // historical years go from 1 BCE to 1 CE, astronomical ones through 0, which is meaningless for decades and
+    // longer, e.g. -13798000000 for the Big Bang
+    let mut year = if year < 0 && precision >= 9 { year + 1 } else { year };
+    if precision >= 11 && calendar == JULIAN && (1..=12).contains(&month) {
+        (year, month, day) = julian_to_gregorian(year, month, day);
+    }
+    let year = format_year(year);
+    let time = match precision {
+        ..=9 => year,
+        10 => format!("{}-{:02}", year, month),
+        11 => format!("{}-{:02}-{:02}", year, month, day),
+        12 => format!("{}-{:02}-{:02}T{:02}Z", year, month, day, hour),
+        13 => format!("{}-{:02}-{:02}T{:02}:{:02}Z", year, month, day, hour, minute),
+        _ => format!("{}-{:02}-{:02}T{:02}:{:02}:{:02}Z", year, month, day, hour, minute, second),
+    };
+    Some(time)
+}
+
+// the normalized form of a time's value
+fn normalized(time: &Map<String, Value>) -> Option<Value> {
+    let time = TimeValue::deserialize(&Value::Object(time.clone())).ok()?;
+    let precision = PRECISIONS.get(time.precision as usize)?;
+    let mut normalized = Map::new();
+    normalized.insert(String::from("time"), Value::from(iso_time(&time.time, time.precision, &time.calendarmodel)?));
+    normalized.insert(String::from("precision"), Value::from(*precision));
+    Some(Value::Object(normalized))
+}
+
+/// Adds the normalized form to every time datavalue in `value`, returning how many
+pub fn normalize(value: &mut Value) -> usize {
+    match value {
+        Value::Object(object) if object.get("type").and_then(Value::as_str) == Some("time") => {
+            let time = match object.get_mut("value") {
+                Some(Value::Object(time)) => time,
+                _ => return 0,
+            };
+            match normalized(time) {
+                Some(normalized) => {
+                    time.insert(String::from("normalized"), normalized);
+                    1
+                }
+                None => 0,
+            }
+        }
+        Value::Object(object) => object.values_mut().map(normalize).sum(),
+        Value::Array(values) => values.iter_mut().map(normalize).sum(),
+        _ => 0,
+    }
+}
+
+/// `output` with its times normalized, re-serialized only if any were, see `normalize`
+pub fn normalize_output(output: String) -> String {
+    // most entities have no times, and can be told apart without parsing
+    if !output.contains("\"time\"") {
+        return output;
+    }
+    let mut value = match serde_json::from_str::<Value>(&output) {
+        Ok(value) => value,
+        Err(_) => return output,
+    };
+    match normalize(&mut value) {
+        0 => output,
+        _ => value.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::quickstatements::GREGORIAN;
+
+    #[test]
+    fn test_iso_time() {
+        assert_eq!(iso_time("+1952-03-11T00:00:00Z", 11, GREGORIAN).as_deref(), Some("1952-03-11"));
+        assert_eq!(iso_time("+1952-03-00T00:00:00Z", 10, GREGORIAN).as_deref(), Some("1952-03"));
+        assert_eq!(iso_time("+1950-00-00T00:00:00Z", 8, GREGORIAN).as_deref(), Some("1950"));
+        assert_eq!(iso_time("+1952-00-00T00:00:00Z", 11, GREGORIAN).as_deref(), Some("1952"));
+        assert_eq!(iso_time("+2001-12-31T13:45:30Z", 14, GREGORIAN).as_deref(), Some("2001-12-31T13:45:30Z"));
+        assert_eq!(iso_time("+2001-12-31T13:45:30Z", 13, GREGORIAN).as_deref(), Some("2001-12-31T13:45Z"));
+        assert_eq!(iso_time("-0001-00-00T00:00:00Z", 9, GREGORIAN).as_deref(), Some("0000"));
+        assert_eq!(iso_time("-13798000000-00-00T00:00:00Z", 3, GREGORIAN).as_deref(), Some("-13798000000"));
+        assert_eq!(iso_time("+10000-00-00T00:00:00Z", 9, GREGORIAN).as_deref(), Some("+10000"));
+        assert_eq!(iso_time("1952", 9, GREGORIAN), None);
+    }
+
+    #[test]
+    fn test_julian() {
+        assert_eq!(iso_time("+1582-10-04T00:00:00Z", 11, JULIAN).as_deref(), Some("1582-10-14"));
+        assert_eq!(iso_time("+1732-02-11T00:00:00Z", 11, JULIAN).as_deref(), Some("1732-02-22"));
+        assert_eq!(iso_time("-0044-03-15T00:00:00Z", 11, JULIAN).as_deref(), Some("-0043-03-13"));
+        assert_eq!(iso_time("+1732-02-00T00:00:00Z", 10, JULIAN).as_deref(), Some("1732-02"));
+    }
+
+    #[test]
+    fn test_normalize_output() {
+        let entity = r#"{"id":"Q42","claims":{"P569":[{"mainsnak":{"snaktype":"value","property":"P569","datavalue":{"type":"time","value":{"time":"+1952-03-11T00:00:00Z","timezone":0,"before":0,"after":0,"precision":11,"calendarmodel":"http://www.wikidata.org/entity/Q1985727"}}},
+            "qualifiers":{"P1480":[{"snaktype":"value","property":"P1480","datavalue":{"type":"string","value":"time"}}]}}]}}"#;
+        let value: Value = serde_json::from_str(&normalize_output(entity.to_string())).unwrap();
+        let time = &value["claims"]["P569"][0]["mainsnak"]["datavalue"]["value"];
+        assert_eq!(time["normalized"], serde_json::json!({"time": "1952-03-11", "precision": "day"}));
+        assert_eq!(time["time"], "+1952-03-11T00:00:00Z");
+
+        let unchanged = r#"{"id":"Q2","claims":{}}"#;
+        assert_eq!(normalize_output(unchanged.to_string()), unchanged);
+    }
+}
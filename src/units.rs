@@ -0,0 +1,219 @@
+/*!
+ * Normalizing quantities to a canonical unit per dimension, e.g. kilometres
+ * and miles to metres, as dumps hold them in whatever unit editors used.
+ *
+ * Each quantity datavalue in a unit the table knows (in main snaks, qualifiers
+ * and references alike) gets a `normalized` member next to its original
+ * amount and unit, holding the amount, unit and bounds converted to the
+ * canonical unit, the same way Wikibase's RDF exports do:
+ * `{"amount":"+5","unit":".../Q828224","normalized":{"amount":"+5000","unit":".../Q11573"}}`.
+ * Amounts are converted as 64-bit floats, so may pick up rounding errors in
+ * their last digits.
+ *
+ * A table is built into the binary for common units of length, area, volume,
+ * mass, time and speed. Tables read from files have a
+ * `unit<TAB>canonical unit<TAB>factor` row per unit, with units as item ids,
+ * e.g. `Q828224\tQ11573\t1000` for kilometres to metres.
+ */
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use serde_json::{Map, Value};
+use crate::error::{ProcessError, Result};
+
+/// Prefix of units in quantity datavalues, followed by the unit's item id
+pub const UNIT_PREFIX: &str = "http://www.wikidata.org/entity/";
+
+// unit, canonical unit and how many canonical units one unit is
+const BUNDLED: &[(&str, &str, f64)] = &[
+    // length, in metres
+    ("Q11573", "Q11573", 1.0),
+    ("Q828224", "Q11573", 1000.0),
+    ("Q174728", "Q11573", 0.01),
+    ("Q174789", "Q11573", 0.001),
+    ("Q253276", "Q11573", 1609.344),
+    ("Q3710", "Q11573", 0.3048),
+    ("Q218593", "Q11573", 0.0254),
+    ("Q482798", "Q11573", 0.9144),
+    ("Q93318", "Q11573", 1852.0),
+    // area, in square metres
+    ("Q25343", "Q25343", 1.0),
+    ("Q712226", "Q25343", 1_000_000.0),
+    ("Q35852", "Q25343", 10_000.0),
+    ("Q81292", "Q25343", 4046.8564224),
+    ("Q232291", "Q25343", 2_589_988.110336),
+    // volume, in cubic metres
+    ("Q25517", "Q25517", 1.0),
+    ("Q11582", "Q25517", 0.001),
+    // mass, in kilograms
+    ("Q11570", "Q11570", 1.0),
+    ("Q41803", "Q11570", 0.001),
+    ("Q191118", "Q11570", 1000.0),
+    ("Q100995", "Q11570", 0.45359237),
+    // time, in seconds
+    ("Q11574", "Q11574", 1.0),
+    ("Q7727", "Q11574", 60.0),
+    ("Q25235", "Q11574", 3600.0),
+    ("Q573", "Q11574", 86_400.0),
+    // speed, in metres per second
+    ("Q182429", "Q182429", 1.0),
+    ("Q180154", "Q182429", 1.0 / 3.6),
+];
+
+/// The canonical unit of each unit, and the factor converting amounts to it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UnitTable {
+    units: HashMap<String, (String, f64)>,
+}
+
+impl UnitTable {
+    /// The table built into the binary, see the module documentation
+    pub fn bundled() -> Self {
+        let mut table = UnitTable::default();
+        for (unit, canonical, factor) in BUNDLED {
+            table.insert(*unit, *canonical, *factor);
+        }
+        table
+    }
+
+    /// Converts amounts in `unit` to `canonical` by multiplying them by `factor`, replacing any earlier conversion
+    pub fn insert(&mut self, unit: impl Into<String>, canonical: impl Into<String>, factor: f64) {
+        self.units.insert(unit.into(), (canonical.into(), factor));
+    }
+
+    /// Number of units known
+    pub fn len(&self) -> usize {
+        self.units.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.units.is_empty()
+    }
+
+    /// The canonical unit of `unit` (an item id) and the factor converting to it
+    pub fn canonical(&self, unit: &str) -> Option<(&str, f64)> {
+        self.units.get(unit).map(|(canonical, factor)| (canonical.as_str(), *factor))
+    }
+
+    /// Adds the rows of the table at `path` (see the module documentation), replacing conversions of the same units
+    pub fn load(&mut self, path: &Path) -> Result<()> {
+        let open_error = |source| ProcessError::OpenInput { path: path.to_path_buf(), source };
+        for (i, line) in BufReader::new(File::open(path).map_err(open_error)?).lines().enumerate() {
+            let line = line.map_err(ProcessError::Read)?;
+            if line.is_empty() {
+                continue;
+            }
+            let columns = line.split('\t').collect::<Vec<_>>();
+            match columns[..] {
+                [unit, canonical, factor] if factor.parse::<f64>().is_ok_and(f64::is_finite) => {
+                    self.insert(unit, canonical, factor.parse().expect("checked"));
+                }
+                _ => {
+                    let message = format!("line {} of {:?} is not a unit<TAB>canonical unit<TAB>factor row", i + 1, path);
+                    return Err(ProcessError::Read(io::Error::new(io::ErrorKind::InvalidData, message)));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // the normalized form of a quantity's value, if its unit is known
+    fn normalized(&self, quantity: &Map<String, Value>) -> Option<Value> {
+        let unit = quantity.get("unit")?.as_str()?.strip_prefix(UNIT_PREFIX)?;
+        let (canonical, factor) = self.canonical(unit)?;
+        let convert = |member: &str| -> Option<Value> {
+            let amount: f64 = quantity.get(member)?.as_str()?.parse().ok()?;
+            Some(Value::from(format!("{:+}", amount * factor)))
+        };
+        let mut normalized = Map::new();
+        normalized.insert(String::from("amount"), convert("amount")?);
+        normalized.insert(String::from("unit"), Value::from(format!("{}{}", UNIT_PREFIX, canonical)));
+        for bound in ["upperBound", "lowerBound"] {
+            if let Some(value) = convert(bound) {
+                normalized.insert(bound.to_string(), value);
+            }
+        }
+        Some(Value::Object(normalized))
+    }
+
+    /// Adds the normalized form to every quantity datavalue in `value` in a known unit, returning how many
+    pub fn normalize(&self, value: &mut Value) -> usize {
+        match value {
+            Value::Object(object) if object.get("type").and_then(Value::as_str) == Some("quantity") => {
+                let quantity = match object.get_mut("value") {
+                    Some(Value::Object(quantity)) => quantity,
+                    _ => return 0,
+                };
+                match self.normalized(quantity) {
+                    Some(normalized) => {
+                        quantity.insert(String::from("normalized"), normalized);
+                        1
+                    }
+                    None => 0,
+                }
+            }
+            Value::Object(object) => object.values_mut().map(|value| self.normalize(value)).sum(),
+            Value::Array(values) => values.iter_mut().map(|value| self.normalize(value)).sum(),
+            _ => 0,
+        }
+    }
+
+    /// `output` with its quantities normalized, re-serialized only if any were, see `normalize`
+    pub fn normalize_output(&self, output: String) -> String {
+        // most entities have no quantities, and can be told apart without parsing
+        if !output.contains("\"quantity\"") {
+            return output;
+        }
+        let mut value = match serde_json::from_str::<Value>(&output) {
+            Ok(value) => value,
+            Err(_) => return output,
+        };
+        match self.normalize(&mut value) {
+            0 => output,
+            _ => value.to_string(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    const ENTITY: &str = r#"{"id":"Q1","claims":{"P2043":[{"mainsnak":{"snaktype":"value","property":"P2043","datavalue":{"type":"quantity","value":{"amount":"+26.2","unit":"http://www.wikidata.org/entity/Q253276","upperBound":"+26.3","lowerBound":"+26.1"}}},
+        "qualifiers":{"P2044":[{"snaktype":"value","property":"P2044","datavalue":{"type":"quantity","value":{"amount":"-3","unit":"http://www.wikidata.org/entity/Q11573"}}}],
+                      "P1114":[{"snaktype":"value","property":"P1114","datavalue":{"type":"quantity","value":{"amount":"+3","unit":"1"}}}]}}]}}"#;
+
+    #[test]
+    fn test_normalize() {
+        let mut entity: Value = serde_json::from_str(ENTITY).unwrap();
+        assert_eq!(UnitTable::bundled().normalize(&mut entity), 2);
+        let statement = &entity["claims"]["P2043"][0];
+        let normalized = &statement["mainsnak"]["datavalue"]["value"]["normalized"];
+        assert_eq!(normalized["unit"], "http://www.wikidata.org/entity/Q11573");
+        assert!(normalized["amount"].as_str().unwrap().starts_with("+42164.81"), "{}", normalized);
+        assert!(normalized["upperBound"].as_str().unwrap().starts_with("+42325.74"), "{}", normalized);
+        assert_eq!(statement["mainsnak"]["datavalue"]["value"]["amount"], "+26.2");
+        assert_eq!(statement["qualifiers"]["P2044"][0]["datavalue"]["value"]["normalized"]["amount"], "-3");
+        assert!(statement["qualifiers"]["P1114"][0]["datavalue"]["value"].get("normalized").is_none());
+
+        let unchanged = r#"{"id":"Q2","claims":{}}"#;
+        assert_eq!(UnitTable::bundled().normalize_output(unchanged.to_string()), unchanged);
+    }
+
+    #[test]
+    fn test_load() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "Q253276\tQ828224\t1.609344").unwrap();
+        let mut table = UnitTable::bundled();
+        let bundled = table.len();
+        table.load(file.path()).unwrap();
+        assert_eq!(table.len(), bundled);
+        assert_eq!(table.canonical("Q253276"), Some(("Q828224", 1.609344)));
+
+        writeln!(file, "Q1\tQ2\tlots").unwrap();
+        assert!(table.load(file.path()).is_err());
+    }
+}
@@ -0,0 +1,202 @@
+/*!
+ * An output backend which hands file writes to the kernel through io_uring,
+ * so that filtering can carry on while previous batches are still being
+ * written out. Only available on Linux with the `io-uring` feature enabled.
+ */
+
+use std::fs::File;
+use std::io::{self, Write};
+use std::os::unix::io::AsRawFd;
+use io_uring::{opcode, types, IoUring};
+use log::{debug, warn};
+
+// number of buffers that can be queued in the kernel at once
+const QUEUE_DEPTH: usize = 4;
+
+struct Buffer {
+    data: Vec<u8>,
+    // position in the file the buffer is being written to
+    offset: u64,
+    // bytes of `data` the kernel has confirmed as written
+    written: usize,
+    in_flight: bool,
+}
+
+impl Buffer {
+    // makes the buffer free to be filled again
+    fn reset(&mut self) {
+        self.data.clear();
+        self.written = 0;
+        self.in_flight = false;
+    }
+}
+
+pub struct UringWriter {
+    file: File,
+    ring: IoUring,
+    buffers: Vec<Buffer>,
+    current: usize,
+    offset: u64,
+    buffer_size: usize,
+}
+
+impl UringWriter {
+    pub fn new(file: File, buffer_size: usize) -> io::Result<UringWriter> {
+        debug!("Initializing io_uring writer with {} buffers of size {}", QUEUE_DEPTH, buffer_size);
+        let ring = IoUring::new(QUEUE_DEPTH as u32)?;
+        let buffers = (0..QUEUE_DEPTH).map(|_| Buffer {
+            data: Vec::with_capacity(buffer_size),
+            offset: 0,
+            written: 0,
+            in_flight: false,
+        }).collect();
+        Ok(UringWriter { file, ring, buffers, current: 0, offset: 0, buffer_size: buffer_size.max(1) })
+    }
+
+    // queue the remaining bytes of the given buffer for writing
+    fn submit(&mut self, index: usize) -> io::Result<()> {
+        let buffer = &mut self.buffers[index];
+        let remaining = &buffer.data[buffer.written..];
+        let entry = opcode::Write::new(types::Fd(self.file.as_raw_fd()), remaining.as_ptr(), remaining.len() as u32)
+            .offset(buffer.offset + buffer.written as u64)
+            .build()
+            .user_data(index as u64);
+        // SAFETY: the buffer is not touched again until its completion has been reaped
+        let pushed = unsafe { self.ring.submission().push(&entry) };
+        if pushed.is_err() {
+            self.buffers[index].reset();
+            return Err(io::Error::other("io_uring submission queue is full"));
+        }
+        self.buffers[index].in_flight = true;
+        // a write pushed but not submitted yet is submitted along with the next one, or while waiting on it
+        self.ring.submit()?;
+        Ok(())
+    }
+
+    // block until at least one queued write completes, resubmitting short writes. Every completion is handled, and the
+    // buffers of failed writes made free again, before the first error is returned.
+    fn reap(&mut self) -> io::Result<()> {
+        if !self.buffers.iter().any(|buffer| buffer.in_flight) {
+            return Ok(());
+        }
+        self.ring.submit_and_wait(1)?;
+        let completed: Vec<(usize, i32)> = self.ring.completion()
+            .map(|cqe| (cqe.user_data() as usize, cqe.result()))
+            .collect();
+        let mut first_error = None;
+        for (index, result) in completed {
+            let buffer = &mut self.buffers[index];
+            buffer.in_flight = false;
+            let error = match result {
+                _ if result < 0 => Some(io::Error::from_raw_os_error(-result)),
+                0 if buffer.written < buffer.data.len() => Some(io::Error::new(io::ErrorKind::WriteZero, "io_uring wrote zero bytes")),
+                _ => {
+                    buffer.written += result as usize;
+                    match buffer.written < buffer.data.len() {
+                        true => self.submit(index).err(),
+                        false => {
+                            buffer.reset();
+                            None
+                        }
+                    }
+                }
+            };
+            if let Some(error) = error {
+                // the bytes are lost either way, and mustn't be written again at another offset, unless they're queued
+                // already and only their submission failed
+                if !self.buffers[index].in_flight {
+                    self.buffers[index].reset();
+                }
+                first_error.get_or_insert(error);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+
+    // hand the buffer currently being filled to the kernel and move on to a free one
+    fn rotate(&mut self) -> io::Result<()> {
+        let index = self.current;
+        let length = self.buffers[index].data.len() as u64;
+        if length == 0 {
+            return Ok(());
+        }
+        self.buffers[index].offset = self.offset;
+        self.offset += length;
+        self.submit(index)?;
+
+        loop {
+            if let Some(free) = self.buffers.iter().position(|buffer| !buffer.in_flight) {
+                self.current = free;
+                return Ok(());
+            }
+            self.reap()?;
+        }
+    }
+}
+
+impl Write for UringWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let buffer = &mut self.buffers[self.current].data;
+        let n = buf.len().min(self.buffer_size - buffer.len());
+        buffer.extend_from_slice(&buf[..n]);
+        if buffer.len() >= self.buffer_size {
+            self.rotate()?;
+        }
+        Ok(n)
+    }
+
+    // waits on every queued write, even once one has failed, as the kernel may still be reading from their buffers
+    fn flush(&mut self) -> io::Result<()> {
+        let mut result = self.rotate();
+        while self.buffers.iter().any(|buffer| buffer.in_flight) {
+            if let (Err(error), true) = (self.reap(), result.is_ok()) {
+                result = Err(error);
+            }
+        }
+        result
+    }
+}
+
+impl Drop for UringWriter {
+    fn drop(&mut self) {
+        // the kernel may still be reading from our buffers, so they must outlive the writes
+        if let Err(error) = self.flush() {
+            warn!("Could not flush io_uring writer, the output is incomplete: {}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+
+    #[test]
+    fn test_uring_writer() {
+        let mut file = tempfile::tempfile().unwrap();
+        {
+            let mut writer = UringWriter::new(file.try_clone().unwrap(), 7).unwrap();
+            for i in 0..100 {
+                writeln!(writer, "entity {}", i).unwrap();
+            }
+        }
+        let mut written = String::new();
+        file.read_to_string(&mut written).unwrap();
+        let expected: String = (0..100).map(|i| format!("entity {}\n", i)).collect();
+        assert_eq!(written, expected);
+    }
+
+    #[test]
+    fn test_uring_writer_error() {
+        let file = std::fs::OpenOptions::new().write(true).open("/dev/full").unwrap();
+        let mut writer = UringWriter::new(file, 7).unwrap();
+        // every write fails, which has to be reported rather than waited on forever
+        let error = (0..100).map(|i| writeln!(writer, "entity {}", i)).find_map(Result::err)
+            .or_else(|| writer.flush().err())
+            .unwrap();
+        assert_eq!(error.raw_os_error(), Some(28), "{}", error);
+        let _ = writer.flush();
+        assert!(writer.buffers.iter().all(|buffer| !buffer.in_flight && buffer.data.is_empty()));
+        writer.flush().unwrap();
+    }
+}
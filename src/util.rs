@@ -0,0 +1,91 @@
+/*!
+ * Small helpers shared by the library and the CLI
+ */
+
+#[cfg(unix)]
+use std::io;
+use std::time::Duration;
+#[cfg(unix)]
+use std::path::Path;
+
+/// Parses a human readable size such as `512`, `64K`, `8M` or `4G` (powers of 1024) into bytes
+pub fn parse_size(value: &str) -> Result<usize, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (digits, suffix) = value.split_at(split);
+    let number: usize = digits.parse().map_err(|_| format!("Invalid size '{}'", value))?;
+    let multiplier: usize = match suffix.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(format!("Invalid size suffix in '{}'", value)),
+    };
+    number.checked_mul(multiplier).ok_or(format!("Size '{}' is too large", value))
+}
+
+/// Parses a human readable duration such as `90`, `90s`, `30m`, `6h` or `2d` (seconds by default)
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let value = value.trim();
+    let split = value.find(|c: char| !c.is_ascii_digit()).unwrap_or(value.len());
+    let (digits, suffix) = value.split_at(split);
+    let number: u64 = digits.parse().map_err(|_| format!("Invalid duration '{}'", value))?;
+    let multiplier: u64 = match suffix.trim().to_ascii_lowercase().as_str() {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("Invalid duration suffix in '{}'", value)),
+    };
+    number.checked_mul(multiplier).map(Duration::from_secs).ok_or(format!("Duration '{}' is too long", value))
+}
+
+/// Bytes available to unprivileged users on the filesystem holding `path`
+#[cfg(unix)]
+pub fn available_space(path: &Path) -> io::Result<u64> {
+    use std::ffi::CString;
+    use std::mem::MaybeUninit;
+    use std::os::unix::ffi::OsStrExt;
+
+    let path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat = MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `path` is nul terminated and `stat` is only read once statvfs has filled it in
+    if unsafe { libc::statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    let stat = unsafe { stat.assume_init() };
+    // the field types differ between platforms
+    #[allow(clippy::unnecessary_cast)]
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("64K").unwrap(), 64 * 1024);
+        assert_eq!(parse_size("8m").unwrap(), 8 * 1024 * 1024);
+        assert!(parse_size("8X").is_err());
+        assert!(parse_size("").is_err());
+    }
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90").unwrap(), Duration::from_secs(90));
+        assert_eq!(parse_duration("30m").unwrap(), Duration::from_secs(30 * 60));
+        assert_eq!(parse_duration("6H").unwrap(), Duration::from_secs(6 * 60 * 60));
+        assert!(parse_duration("6w").is_err());
+        assert!(parse_duration("h").is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_available_space() {
+        assert!(available_space(Path::new(".")).unwrap() > 0);
+        assert!(available_space(Path::new("./does/not/exist")).is_err());
+    }
+}
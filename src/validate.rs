@@ -0,0 +1,206 @@
+/*!
+ * Checking a dump, or an output of `filter`, for problems which would trip up
+ * whatever reads it next: a file cut short, entities which aren't valid JSON
+ * or don't follow the Wikibase JSON format, and entities found more than once.
+ *
+ * Each problem is reported along with the line of the file it's on, and the
+ * whole file is checked rather than stopping at the first one, except when it
+ * can't be read any further.
+ */
+
+use std::collections::HashSet;
+use std::fmt;
+use std::io;
+use std::path::Path;
+use serde::Serialize;
+use serde_json::Value;
+use crate::error::{ProcessError, Result};
+use crate::index::parse_id;
+use crate::model::Entity;
+use crate::progress::{Progress, Reporter};
+use crate::reader::EntityFile;
+use crate::splitter;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    /// The file ends in the middle of an entity or bzip2 stream
+    Truncated,
+    /// The file can't be read any further, e.g. corrupt bzip2 data or invalid UTF-8
+    Unreadable,
+    InvalidJson,
+    /// The entity is valid JSON, but not a Wikibase entity
+    InvalidEntity,
+    DuplicateId,
+    /// The file doesn't have as many entities as were written to it
+    WrongCount,
+}
+
+impl fmt::Display for ProblemKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ProblemKind::Truncated => "truncated",
+            ProblemKind::Unreadable => "unreadable",
+            ProblemKind::InvalidJson => "invalid JSON",
+            ProblemKind::InvalidEntity => "invalid entity",
+            ProblemKind::DuplicateId => "duplicate id",
+            ProblemKind::WrongCount => "wrong count",
+        })
+    }
+}
+
+/// A problem found by `validate`, serialized as e.g. `{"line":12,"id":"Q42","kind":"duplicate_id","message":"..."}`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Problem {
+    /// Line of the file the problem is on, counting from 1
+    pub line: u64,
+    pub id: Option<String>,
+    pub kind: ProblemKind,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct ValidationStats {
+    pub entities: u64,
+    pub problems: u64,
+}
+
+/// Checks every entity of the dump or `filter` output at `path`, passing each problem found to `report`.
+///
+/// With `schema` unset, entities only have to be valid JSON rather than Wikibase entities, for outputs of
+/// filters which don't keep whole entities. Ids are kept in memory to find duplicates.
+pub fn validate(path: &Path, schema: bool, progress: Progress, mut report: impl FnMut(Problem) -> Result<()>) -> Result<ValidationStats> {
+    let mut entities = match EntityFile::open(path) {
+        // the start of a bzip2 compressed file is decompressed straight away to tell what it is
+        Err(ProcessError::Read(error)) => {
+            report(Problem { line: 1, id: None, kind: kind_of(&error), message: error.to_string() })?;
+            return Ok(ValidationStats { entities: 0, problems: 1 });
+        }
+        result => result?,
+    };
+    let progress = Reporter::new(progress, Some(entities.size()), "{msg}\n{spinner:.green} [{elapsed_precise}] [{wide_bar:.cyan/blue}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})");
+    progress.set_draw_rate(1);
+    let mut stats = ValidationStats::default();
+    let mut problem = |stats: &mut ValidationStats, line, id: Option<&str>, kind, message: String| {
+        stats.problems += 1;
+        report(Problem { line, id: id.map(str::to_string), kind, message })
+    };
+
+    let mut ids = HashSet::new();
+    let mut last_id = None;
+    while let Some(entity) = entities.next() {
+        let entity = match entity {
+            Ok(entity) => entity,
+            Err(error) => {
+                let kind = kind_of(&error);
+                let message = format!("{}, after {} entities (the last one {})", error, stats.entities, last_id.as_deref().unwrap_or("without an id"));
+                problem(&mut stats, entities.line() + 1, None, kind, message)?;
+                break;
+            }
+        };
+        stats.entities += 1;
+        let line = entities.line();
+        let id = splitter::entity_id(&entity);
+        last_id = id.map(str::to_string);
+
+        match serde_json::from_str::<Value>(&entity) {
+            Err(error) => problem(&mut stats, line, id, ProblemKind::InvalidJson, error.to_string())?,
+            Ok(value) if schema => {
+                if let Err(error) = serde_json::from_value::<Entity>(value) {
+                    problem(&mut stats, line, id, ProblemKind::InvalidEntity, error.to_string())?;
+                } else if id.and_then(parse_id).is_none() {
+                    problem(&mut stats, line, id, ProblemKind::InvalidEntity, format!("invalid id {:?}", id.unwrap_or_default()))?;
+                }
+            }
+            Ok(_) => {}
+        }
+        if let Some(id) = id {
+            if !ids.insert(id.to_string()) {
+                problem(&mut stats, line, Some(id), ProblemKind::DuplicateId, format!("{} was found before", id))?;
+            }
+        }
+        progress.set_position(entities.position());
+        progress.set_entities(stats.entities as usize, stats.problems as usize);
+    }
+    if !entities.is_complete() {
+        let message = format!("the file ends without the end of the last entity, after {} entities", stats.entities);
+        problem(&mut stats, entities.line(), last_id.as_deref(), ProblemKind::Truncated, message)?;
+    }
+    progress.finish(format!("Checked {} entities, found {} problems", stats.entities, stats.problems));
+    Ok(stats)
+}
+
+/// Checks an output just written at `path` reads to its end, that each of its records is valid JSON, and that
+/// there are `expected` of them (one per line, or per entity of a dump), returning the problems found. Unlike
+/// `validate`, outputs may have the same id more than once, e.g. a record per statement.
+pub fn verify_output(path: &Path, expected: u64, progress: Progress) -> Result<Vec<Problem>> {
+    let mut problems = Vec::new();
+    let stats = validate(path, false, progress, |problem| {
+        if problem.kind != ProblemKind::DuplicateId {
+            problems.push(problem);
+        }
+        Ok(())
+    })?;
+    if stats.entities != expected {
+        let message = format!("{} records were written, but {} were read back", expected, stats.entities);
+        problems.push(Problem { line: stats.entities, id: None, kind: ProblemKind::WrongCount, message });
+    }
+    Ok(problems)
+}
+
+fn kind_of(error: &io::Error) -> ProblemKind {
+    match error.kind() {
+        io::ErrorKind::UnexpectedEof => ProblemKind::Truncated,
+        _ => ProblemKind::Unreadable,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn problems(contents: &[u8], schema: bool) -> Vec<(u64, ProblemKind)> {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("input");
+        fs::write(&path, contents).unwrap();
+        let mut problems = Vec::new();
+        validate(&path, schema, Progress::Hidden, |problem| {
+            problems.push((problem.line, problem.kind));
+            Ok(())
+        }).unwrap();
+        problems
+    }
+
+    #[test]
+    fn test_validate() {
+        let stats = validate(Path::new("./tests/test-data.json.bz2"), true, Progress::Hidden, |problem| panic!("{:?}", problem)).unwrap();
+        assert_eq!(stats, ValidationStats { entities: 8, problems: 0 });
+
+        let lines = b"{\"id\":\"Q1\",\"type\":\"item\"}\n{\"id\":\"Q2\",\n\n{\"id\":\"Q1\",\"type\":\"item\"}\n{\"id\":\"Q3\"}\n";
+        assert_eq!(problems(lines, true), vec![
+            (2, ProblemKind::InvalidJson),
+            (4, ProblemKind::DuplicateId),
+            (5, ProblemKind::InvalidEntity),
+        ]);
+        assert_eq!(problems(b"[1]\n{\"id\":\"Q3\"}", false), vec![(2, ProblemKind::Truncated)]);
+        assert_eq!(problems(b"[\n{\"id\":\"Q1\",\"type\":\"item\"},\n{\"id\":\"Q2\",\"type\":\"item\"}\n]", true), vec![]);
+        assert_eq!(problems(b"[\n{\"id\":\"Q1\",\"type\":\"item\"},\n{\"id\":\"Q2\",\"ty", true), vec![(3, ProblemKind::InvalidJson), (3, ProblemKind::Truncated)]);
+
+        let dump = fs::read("./tests/test-data.json.bz2").unwrap();
+        assert_eq!(problems(&dump[..1000], true), vec![(1, ProblemKind::Truncated)]);
+    }
+
+    #[test]
+    fn test_verify_output() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("output.ndjson");
+        fs::write(&path, "\"Q1\"\n{\"id\":\"Q1\"}\n{\"id\":\"Q1\"}\n").unwrap();
+        assert_eq!(verify_output(&path, 3, Progress::Hidden).unwrap(), vec![]);
+        let kinds = |problems: Vec<Problem>| problems.into_iter().map(|problem| problem.kind).collect::<Vec<_>>();
+        assert_eq!(kinds(verify_output(&path, 4, Progress::Hidden).unwrap()), vec![ProblemKind::WrongCount]);
+
+        fs::write(&path, "\"Q1\"\n{\"id\":").unwrap();
+        assert_eq!(kinds(verify_output(&path, 2, Progress::Hidden).unwrap()), vec![ProblemKind::InvalidJson, ProblemKind::Truncated]);
+    }
+}
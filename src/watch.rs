@@ -0,0 +1,267 @@
+/*!
+ * Watching dumps.wikimedia.org for new dumps, and filtering each one as soon
+ * as it's complete, so a monthly refresh runs by itself.
+ *
+ * Each dump is published in a dated directory of `DUMPS_INDEX`, and is only
+ * complete once the directory's `wikidata-<date>-sha1sums.txt` lists it,
+ * which is also what downloads are verified against. Outputs are written to
+ * `wikidata-<date>.ndjson` in the output directory, keeping only the most
+ * recent ones, and the date of the last dump processed is kept next to them
+ * in `WATCH_STATE_FILE` so a restarted watch carries on where it was.
+ */
+
+use std::error::Error;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use log::{error, info, warn};
+use crate::decoder::BUFFER_LENGTH;
+use crate::download;
+use crate::pipeline::Pipeline;
+use crate::process::{ProcessOptions, ProcessStats};
+use crate::sink;
+
+/// Where the dated directories of the JSON dumps are listed
+pub const DUMPS_INDEX: &str = "https://dumps.wikimedia.org/wikidatawiki/entities/";
+
+/// File in the output directory holding the date of the last dump processed
+pub const WATCH_STATE_FILE: &str = ".wikidump-watch";
+
+// how many of the most recent dumps are checked for being complete on each poll
+const CANDIDATES: usize = 3;
+
+/// What to do with each new dump
+#[derive(Debug, Clone)]
+pub struct WatchOptions {
+    pub jq_filter: String,
+    /// Where outputs (and the state file) are written
+    pub output_dir: PathBuf,
+    /// Where dumps are downloaded to
+    pub download_dir: PathBuf,
+    /// How many outputs to keep, older ones being deleted once a new one is written
+    pub keep: usize,
+    /// Keep dumps once they've been processed, rather than deleting them
+    pub keep_dumps: bool,
+    pub process: ProcessOptions,
+}
+
+/// The dates of the dumps listed in the HTML index of `DUMPS_INDEX`, oldest first
+pub fn parse_versions(index: &str) -> Vec<String> {
+    let mut versions = index.split("href=\"")
+        .skip(1)
+        .filter_map(|link| link.split('"').next())
+        .filter_map(|link| link.strip_suffix('/'))
+        .filter(|link| link.len() == 8 && link.bytes().all(|byte| byte.is_ascii_digit()))
+        .map(str::to_string)
+        .collect::<Vec<_>>();
+    versions.sort();
+    versions.dedup();
+    versions
+}
+
+/// Name of the JSON dump of `version`, e.g. `wikidata-20220404-all.json.bz2`
+pub fn dump_name(version: &str) -> String {
+    format!("wikidata-{}-all.json.bz2", version)
+}
+
+/// URL of the file `name` in the directory of the dump of `version`
+pub fn dated_url(version: &str, name: &str) -> String {
+    format!("{}{}/{}", DUMPS_INDEX, version, name)
+}
+
+/// The checksum listed for the file `name` in a `sha1sums.txt` (or `md5sums.txt`) file
+pub fn parse_checksum(sums: &str, name: &str) -> Option<String> {
+    sums.lines()
+        .filter_map(|line| line.split_once(char::is_whitespace))
+        .find(|(_, file)| file.trim_start_matches([' ', '*']) == name)
+        .map(|(checksum, _)| checksum.to_ascii_lowercase())
+}
+
+/// SHA-1 of the file at `path`, in lowercase hex
+pub fn sha1_file(path: &Path) -> io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = sha1_smol::Sha1::new();
+    let mut buffer = vec![0; BUFFER_LENGTH];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            return Ok(hasher.digest().to_string());
+        }
+        hasher.update(&buffer[..n]);
+    }
+}
+
+/// Output of the dump of `version` within `output_dir`
+pub fn output_path(output_dir: &Path, version: &str) -> PathBuf {
+    output_dir.join(format!("wikidata-{}.ndjson", version))
+}
+
+/// Date of the last dump processed, if any
+pub fn last_version(output_dir: &Path) -> io::Result<Option<String>> {
+    match fs::read_to_string(output_dir.join(WATCH_STATE_FILE)) {
+        Ok(version) => Ok(Some(version.trim().to_string())),
+        Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+        Err(error) => Err(error),
+    }
+}
+
+/// Deletes all but the `keep` most recent outputs in `output_dir`, returning the paths deleted
+pub fn rotate_outputs(output_dir: &Path, keep: usize) -> io::Result<Vec<PathBuf>> {
+    let mut outputs = fs::read_dir(output_dir)?
+        .map(|entry| entry.map(|entry| entry.path()))
+        .collect::<io::Result<Vec<_>>>()?;
+    outputs.retain(|path| {
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or("");
+        name.starts_with("wikidata-") && name.ends_with(".ndjson")
+    });
+    // dates sort like the names they're in
+    outputs.sort();
+    let stale = outputs.len().saturating_sub(keep);
+    let deleted = outputs.drain(..stale).collect::<Vec<_>>();
+    for path in &deleted {
+        fs::remove_file(path)?;
+    }
+    Ok(deleted)
+}
+
+// the most recent complete dump newer than `last`, and its checksum
+async fn newest_complete(client: &reqwest::Client, last: Option<&str>) -> Result<Option<(String, String)>, Box<dyn Error>> {
+    let index = client.get(DUMPS_INDEX).send().await?.error_for_status()?.text().await?;
+    let versions = parse_versions(&index);
+    for version in versions.iter().rev().take(CANDIDATES) {
+        if last.is_some_and(|last| version.as_str() <= last) {
+            break;
+        }
+        let sums = client.get(dated_url(version, &format!("wikidata-{}-sha1sums.txt", version))).send().await?;
+        if !sums.status().is_success() {
+            info!("Dump {} isn't complete yet", version);
+            continue;
+        }
+        match parse_checksum(&sums.text().await?, &dump_name(version)) {
+            Some(checksum) => return Ok(Some((version.clone(), checksum))),
+            None => info!("Dump {} isn't complete yet", version),
+        }
+    }
+    Ok(None)
+}
+
+/// Filters the dump at `dump` into the output for `version`, written to a temporary name first so a partial
+/// output is never mistaken for a finished one
+pub fn process_dump(dump: &Path, version: &str, options: &WatchOptions) -> Result<(PathBuf, ProcessStats), Box<dyn Error>> {
+    let output = output_path(&options.output_dir, version);
+    let mut partial = output.clone().into_os_string();
+    partial.push(".partial");
+    let partial = PathBuf::from(partial);
+    let stats = Pipeline::builder()
+        .source(dump)
+        .filter(options.jq_filter.as_str())
+        .sink(sink::open_output(Some(&partial), true)?)
+        .options(options.process.clone())
+        .build()?
+        .run()?;
+    if stats.cancelled {
+        return Err(format!("Processing dump {} was cancelled", version).into());
+    }
+    fs::rename(&partial, &output)?;
+    Ok((output, stats))
+}
+
+/// Checks once for a dump newer than the last one processed, and if there is a complete one downloads,
+/// verifies and filters it, then rotates the outputs. Returns the output written, if any.
+pub async fn check(options: &WatchOptions) -> Result<Option<PathBuf>, Box<dyn Error>> {
+    let last = last_version(&options.output_dir)?;
+    let client = reqwest::Client::new();
+    let (version, checksum) = match newest_complete(&client, last.as_deref()).await? {
+        Some(newest) => newest,
+        None => return Ok(None),
+    };
+    info!("Found new dump {}", version);
+
+    let dump = download::download_url(&dated_url(&version, &dump_name(&version)), &options.download_dir, options.process.progress, true).await?;
+    let actual = sha1_file(&dump)?;
+    if actual != checksum {
+        fs::remove_file(&dump)?;
+        return Err(format!("Dump {} is corrupt, its SHA-1 is {} rather than {}", version, actual, checksum).into());
+    }
+    info!("Verified dump {}", version);
+
+    let (output, stats) = process_dump(&dump, &version, options)?;
+    info!("Wrote {} entities of dump {} to {:?}", stats.entities_written, version, output);
+    fs::write(options.output_dir.join(WATCH_STATE_FILE), &version)?;
+    for deleted in rotate_outputs(&options.output_dir, options.keep)? {
+        info!("Deleted old output {:?}", deleted);
+    }
+    if !options.keep_dumps {
+        fs::remove_file(&dump)?;
+    }
+    Ok(Some(output))
+}
+
+/// Runs `check` every `interval` until the process is stopped. Failed checks are logged and retried on the next
+/// one, so a network outage or a corrupt download doesn't end the watch.
+pub async fn watch(options: &WatchOptions, interval: Duration) -> Result<(), Box<dyn Error>> {
+    fs::create_dir_all(&options.output_dir)?;
+    fs::create_dir_all(&options.download_dir)?;
+    loop {
+        match check(options).await {
+            Ok(Some(_)) => {}
+            Ok(None) => info!("No new dump, checking again in {}", humantime::format_duration(interval)),
+            Err(failure) => error!("Could not process the newest dump, trying again in {}: {}", humantime::format_duration(interval), failure),
+        }
+        if options.process.cancel.is_cancelled() {
+            warn!("Cancelled, no longer watching");
+            return Ok(());
+        }
+        tokio::time::sleep(interval).await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_versions() {
+        let index = r#"<html><body><h1>Index of /wikidatawiki/entities/</h1><hr><pre><a href="../">../</a>
+<a href="20220404/">20220404/</a>   06-Apr-2022 12:00    -
+<a href="20220328/">20220328/</a>   30-Mar-2022 12:00    -
+<a href="dcatap.rdf">dcatap.rdf</a>
+<a href="latest-all.json.bz2">latest-all.json.bz2</a>
+</pre><hr></body></html>"#;
+        assert_eq!(parse_versions(index), vec!["20220328", "20220404"]);
+    }
+
+    #[test]
+    fn test_parse_checksum() {
+        let sums = "0123456789ABCDEF0123456789abcdef01234567  wikidata-20220404-all.json.gz\nfedcba9876543210fedcba9876543210fedcba98  wikidata-20220404-all.json.bz2\n";
+        assert_eq!(parse_checksum(sums, "wikidata-20220404-all.json.bz2").as_deref(), Some("fedcba9876543210fedcba9876543210fedcba98"));
+        assert_eq!(parse_checksum(sums, "wikidata-20220404-all.json.gz").as_deref(), Some("0123456789abcdef0123456789abcdef01234567"));
+        assert_eq!(parse_checksum(sums, "wikidata-20220328-all.json.bz2"), None);
+    }
+
+    #[test]
+    fn test_process_and_rotate() {
+        let directory = tempfile::tempdir().unwrap();
+        let options = WatchOptions {
+            jq_filter: String::from(".id"),
+            output_dir: directory.path().to_path_buf(),
+            download_dir: directory.path().to_path_buf(),
+            keep: 2,
+            keep_dumps: true,
+            process: ProcessOptions::default(),
+        };
+        for version in ["20220314", "20220321", "20220328"] {
+            let (output, stats) = process_dump(Path::new("./tests/test-data.json.bz2"), version, &options).unwrap();
+            assert_eq!(stats.entities_written, 8);
+            assert_eq!(output, output_path(directory.path(), version));
+        }
+        assert_eq!(rotate_outputs(directory.path(), 2).unwrap(), vec![output_path(directory.path(), "20220314")]);
+        assert!(output_path(directory.path(), "20220328").exists());
+        assert_eq!(last_version(directory.path()).unwrap(), None);
+
+        let abc = directory.path().join("abc");
+        fs::write(&abc, "abc").unwrap();
+        assert_eq!(sha1_file(&abc).unwrap(), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+}